@@ -0,0 +1,118 @@
+//! How long `POST /v1/auth/login` takes for a user in several groups, so the
+//! queries behind it don't quietly grow again.
+//!
+//! The password is hashed with the cheapest Argon2 parameters there are,
+//! which verification reads back out of the hash, so the time is the
+//! handler's own rather than the hashing's.
+//!
+//! ```sh
+//! cargo bench --bench login --features test_support
+//! ```
+//!
+//! Needs a database to make a throwaway one beside, as the tests do.
+
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::test_support::{TestApp, TestDb};
+
+/// Logins timed, after as many again to warm the pool up.
+const ITERATIONS: u32 = 500;
+
+/// Groups the user is in, each with a couple of permissions.
+const GROUPS: i32 = 8;
+
+const PASSWORD: &str = "bench-password";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+
+    let params = Params::new(Params::MIN_M_COST, Params::MIN_T_COST, 1, None)?;
+    let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(PASSWORD.as_bytes(), &SaltString::generate(&mut OsRng))?
+        .to_string();
+
+    let user_id: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description, permissions)
+        VALUES ('bench', $1, 'Bench', '', ARRAY['create_posts']::permission[])
+        RETURNING id
+        ",
+    )
+    .bind(hash)
+    .fetch_one(db.pool())
+    .await?;
+
+    for group in 0..GROUPS {
+        sqlx::query(
+            r"
+            WITH g AS (
+              INSERT INTO groups (group_name, permissions)
+              VALUES ('bench ' || $1, ARRAY['edit_posts', 'manage_pages']::permission[])
+              RETURNING id
+            )
+            INSERT INTO users_groups (user_id, group_id)
+            SELECT $2, id FROM g
+            ",
+        )
+        .bind(group)
+        .bind(user_id)
+        .execute(db.pool())
+        .await?;
+    }
+
+    let app = TestApp::builder(db.pool().clone()).build()?;
+
+    for _ in 0..ITERATIONS {
+        login(&app).await?;
+    }
+
+    let mut times = Vec::with_capacity(ITERATIONS as usize);
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        login(&app).await?;
+        times.push(start.elapsed());
+    }
+    times.sort_unstable();
+
+    let mean = times.iter().sum::<Duration>() / ITERATIONS;
+    let percentile = |p: usize| times[(times.len() - 1) * p / 100];
+    println!(
+        "login ({ITERATIONS} runs, {GROUPS} groups): mean {mean:?}, p50 {:?}, p99 {:?}",
+        percentile(50),
+        percentile(99),
+    );
+
+    db.close().await?;
+
+    Ok(())
+}
+
+async fn login(app: &TestApp) -> Result<(), Box<dyn Error>> {
+    let body = serde_json::json!({ "username": "bench", "password": PASSWORD });
+    let res = app
+        .request(
+            Request::post("/v1/auth/login")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))?,
+        )
+        .await;
+
+    if res.status() == StatusCode::OK {
+        Ok(())
+    } else {
+        Err(format!("Login returned {}", res.status()).into())
+    }
+}
@@ -1,4 +1,68 @@
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Trees built into the binary with the `embedded_assets` feature, relative
+/// to the crate root, as they're looked up at runtime.
+const EMBEDDED_DIRS: &[&str] = &["pages/templates", "pages/dist", "mail"];
+
 fn main() {
     // trigger recompilation when a new migration is added
     println!("cargo:rerun-if-changed=migrations");
+
+    if env::var_os("CARGO_FEATURE_EMBEDDED_ASSETS").is_some() {
+        embed_assets();
+    }
+}
+
+/// Writes `embedded_assets.rs`, a slice of `(key, bytes)` for every file in
+/// [`EMBEDDED_DIRS`], for `src/embedded.rs` to include.
+fn embed_assets() {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+
+    let mut files = Vec::new();
+    for dir in EMBEDDED_DIRS {
+        println!("cargo:rerun-if-changed={dir}");
+        collect_files(&root, &root.join(dir), &mut files);
+    }
+    files.sort();
+
+    let mut out = String::from("&[\n");
+    for (key, path) in files {
+        writeln!(out, "    ({key:?}, include_bytes!({:?})),", path.display()).unwrap();
+    }
+    out.push_str("]\n");
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("embedded_assets.rs");
+    fs::write(out_path, out).unwrap();
+}
+
+/// Skips hidden files such as `.gitkeep`, which are never served.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        if path.is_dir() {
+            collect_files(root, &path, files);
+        } else {
+            let key = path
+                .strip_prefix(root)
+                .unwrap()
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((key, path));
+        }
+    }
 }
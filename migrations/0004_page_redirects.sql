@@ -0,0 +1,14 @@
+create table page_redirects (
+  id serial primary key,
+
+  old_path varchar(1024) not null unique,
+  new_path varchar(1024) not null,
+  page_id integer,
+
+  created_at timestamp not null default now(),
+
+  foreign key (page_id)
+  references pages(id)
+  on update cascade
+  on delete cascade
+);
@@ -0,0 +1,35 @@
+create type navigation_link as enum('page', 'post', 'external');
+
+create table navigation (
+  id serial primary key,
+  parent_id integer,
+
+  label varchar(255) not null,
+  link_type navigation_link not null,
+  page_id integer,
+  post_id integer,
+  url varchar(2048),
+
+  position integer not null default 0,
+
+  check (
+    (link_type = 'page' and page_id is not null) or
+    (link_type = 'post' and post_id is not null) or
+    (link_type = 'external' and url is not null)
+  ),
+
+  foreign key (parent_id)
+  references navigation(id)
+  on update cascade
+  on delete cascade,
+
+  foreign key (page_id)
+  references pages(id)
+  on update cascade
+  on delete cascade,
+
+  foreign key (post_id)
+  references posts(id)
+  on update cascade
+  on delete cascade
+);
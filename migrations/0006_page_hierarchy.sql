@@ -0,0 +1,7 @@
+alter table pages add column parent_id integer;
+
+alter table pages
+add foreign key (parent_id)
+references pages(id)
+on update cascade
+on delete restrict;
@@ -0,0 +1 @@
+ALTER TYPE page_status ADD VALUE 'unpublished';
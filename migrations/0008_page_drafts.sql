@@ -0,0 +1,3 @@
+-- Editor autosaves, kept apart from the spec on disk until the page is saved
+alter table pages add column draft text;
+alter table pages add column draft_saved_at timestamp;
@@ -0,0 +1,18 @@
+alter type permission add value 'manage_media';
+
+create table media (
+  id serial primary key,
+
+  filename varchar(255) not null, -- Sanitised, and the last segment of the file's URL
+  mime varchar(255) not null,
+  size bigint not null,
+  alt_text text not null default '',
+
+  uploader integer,
+  uploaded_at timestamp not null default now(),
+
+  foreign key (uploader)
+  references users(id)
+  on update cascade
+  on delete set null
+);
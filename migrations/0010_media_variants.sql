@@ -0,0 +1,15 @@
+create table media_variants (
+  media_id integer not null,
+  filename varchar(255) not null,
+
+  width integer not null,
+  height integer not null,
+  size bigint not null,
+
+  primary key (media_id, filename),
+
+  foreign key (media_id)
+  references media(id)
+  on update cascade
+  on delete cascade
+);
@@ -0,0 +1,3 @@
+-- Page specs move off disk and onto their row. Existing pages are filled in
+-- from their old spec files at startup, see `import_legacy_specs`
+alter table pages add column data jsonb;
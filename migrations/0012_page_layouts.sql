@@ -0,0 +1,4 @@
+-- Which template from `pages/templates` a page's fragment extends
+create type page_layout as enum('base', 'landing', 'minimal');
+
+alter table pages add column layout page_layout not null default 'base'::page_layout;
@@ -0,0 +1,4 @@
+-- Staff pages are deployed outside `pages/dist` and only served to signed-in staff
+create type page_visibility as enum('public', 'staff');
+
+alter table pages add column visibility page_visibility not null default 'public'::page_visibility;
@@ -0,0 +1,11 @@
+-- Plain text of each page as last deployed, for site-wide search
+alter table pages add column search_text text not null default '';
+alter table pages add column search_vector tsvector generated always as (
+  setweight(to_tsvector('english', name), 'A') || setweight(to_tsvector('english', search_text), 'B')
+) stored;
+create index pages_search_idx on pages using gin (search_vector);
+
+alter table posts add column search_vector tsvector generated always as (
+  setweight(to_tsvector('english', title), 'A') || setweight(to_tsvector('english', content), 'B')
+) stored;
+create index posts_search_idx on posts using gin (search_vector);
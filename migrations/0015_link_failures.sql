@@ -0,0 +1,19 @@
+-- Links found broken by the most recent link check. Rows are replaced on
+-- every run, apart from `first_failed_at`, so long-dead links stand out.
+create type link_source as enum('page', 'post');
+
+create table link_failures (
+  id serial primary key,
+
+  source link_source not null,
+  source_id integer not null,
+  url text not null,
+
+  status smallint, -- Null if no response was received at all
+  error text not null,
+
+  first_failed_at timestamp not null default now(),
+  last_checked_at timestamp not null default now(),
+
+  unique (source, source_id, url)
+);
@@ -0,0 +1,20 @@
+-- Where invitations and password reset links are sent. Optional, as accounts
+-- made before this have none
+alter table users add column email varchar(512);
+
+-- Rendered emails waiting to be sent, or retried after failing
+create table mail_queue (
+  id serial primary key,
+
+  recipient text not null,
+  subject text not null,
+  text_body text not null,
+  html_body text,
+
+  attempts integer not null default 0,
+  last_error text,
+  send_after timestamp not null default now(),
+
+  created_at timestamp not null default now()
+);
+create index mail_queue_send_after_idx on mail_queue (send_after);
@@ -0,0 +1,11 @@
+-- Where each item brought across from a WordPress export ended up, so running
+-- the import again skips what's already been imported
+create table wordpress_posts (
+  wordpress_id integer primary key,
+  post_id integer not null references posts (id) on delete cascade
+);
+
+create table wordpress_media (
+  wordpress_id integer primary key,
+  media_id integer not null references media (id) on delete cascade
+);
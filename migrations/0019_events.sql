@@ -0,0 +1,24 @@
+alter type permission add value 'manage_events';
+
+-- Dates in the school calendar, also published as an iCal feed. All-day
+-- events only use the date part of each time, with `ends_at` on the last day
+create table events (
+  id serial primary key,
+
+  title varchar(255) not null,
+  description text not null default '',
+  location varchar(255) not null default '',
+  starts_at timestamptz not null,
+  ends_at timestamptz not null,
+  all_day boolean not null default false,
+
+  department integer
+  references departments(id)
+  on delete set null,
+
+  updated_at timestamptz not null default now(),
+
+  check (ends_at >= starts_at)
+);
+create index events_starts_at_idx on events (starts_at);
+create index events_ends_at_idx on events (ends_at);
@@ -0,0 +1,19 @@
+-- Optional sign-up for events, such as parents' evening slots. Registration
+-- closes when the event starts unless `registration_closes_at` is set
+alter table events add column registration boolean not null default false;
+alter table events add column capacity integer check (capacity > 0);
+alter table events add column registration_opens_at timestamptz;
+alter table events add column registration_closes_at timestamptz;
+
+create table event_registrations (
+  id serial primary key,
+  event_id integer not null
+  references events(id)
+  on delete cascade,
+
+  name varchar(255) not null,
+  email varchar(512) not null,
+  registered_at timestamptz not null default now(),
+
+  unique (event_id, email)
+);
@@ -0,0 +1,23 @@
+alter type permission add value 'manage_vacancies';
+
+-- Job adverts. The public only sees those still open, until `closes_at`.
+-- `description` holds page elements, like `pages.data`
+create table vacancies (
+  id serial primary key,
+
+  title varchar(255) not null,
+  description jsonb not null default '[]',
+  closes_at timestamptz not null,
+
+  -- The application pack, e.g. a job description and form as a PDF
+  attachment integer
+  references media(id)
+  on delete set null,
+
+  department integer
+  references departments(id)
+  on delete set null,
+
+  created_at timestamptz not null default now()
+);
+create index vacancies_closes_at_idx on vacancies (closes_at);
@@ -0,0 +1,54 @@
+alter type permission add value 'manage_documents';
+
+-- Policies, letters home and the like, listed publicly by `category`. Each
+-- keeps every version uploaded, and the latest is the one downloaded
+create table documents (
+  id serial primary key,
+
+  title varchar(255) not null,
+  description text not null default '',
+  category varchar(255) not null,
+  -- When a policy is next due for review, if it has to be
+  review_by timestamptz,
+
+  updated_at timestamptz not null default now()
+);
+create index documents_category_idx on documents (category);
+
+create table document_versions (
+  id serial primary key,
+
+  document_id integer not null
+  references documents(id)
+  on delete cascade,
+
+  -- The file, kept in the media library
+  media integer not null
+  references media(id)
+  on delete cascade,
+
+  note text not null default '',
+
+  uploaded_by integer
+  references users(id)
+  on delete set null,
+
+  created_at timestamptz not null default now()
+);
+create index document_versions_document_id_idx on document_versions (document_id);
+
+-- Each document with its latest version's file, if it has one yet
+create view current_documents as
+select d.id, d.title, d.description, d.category, d.review_by, d.updated_at,
+  v.id as version,
+  '/media/' || m.id || '/' || m.filename as url,
+  m.mime,
+  m.size
+from documents d
+left join lateral (
+  select id, media from document_versions
+  where document_id = d.id
+  order by created_at desc, id desc
+  limit 1
+) v on true
+left join media m on m.id = v.media;
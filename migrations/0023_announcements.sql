@@ -0,0 +1,21 @@
+alter type permission add value 'manage_announcements';
+
+create type announcement_severity as enum('info', 'warning', 'critical');
+
+-- Site-wide banners, such as snow closures, shown from `starts_at` until
+-- `ends_at`, or until removed if that's null
+create table announcements (
+  id serial primary key,
+
+  message text not null,
+  severity announcement_severity not null default 'info'::announcement_severity,
+  starts_at timestamptz not null default now(),
+  ends_at timestamptz,
+  -- Whether visitors can close the banner
+  dismissible boolean not null default true,
+
+  created_at timestamptz not null default now(),
+
+  check (ends_at is null or ends_at > starts_at)
+);
+create index announcements_ends_at_idx on announcements (ends_at);
@@ -0,0 +1,24 @@
+alter type permission add value 'manage_admissions';
+
+create type enquiry_status as enum('new', 'contacted', 'closed');
+
+-- From prospective parents through the public form. Deleted automatically
+-- once `updated_at` is older than `admissions.retention` days
+create table admissions_enquiries (
+  id serial primary key,
+
+  child_name varchar(255) not null,
+  year_group varchar(64) not null,
+  parent_name varchar(255) not null,
+  email varchar(512) not null,
+  phone varchar(64) not null default '',
+  message text not null default '',
+
+  -- When they agreed to their details being kept to handle the enquiry
+  consented_at timestamptz not null,
+
+  status enquiry_status not null default 'new'::enquiry_status,
+  created_at timestamptz not null default now(),
+  updated_at timestamptz not null default now()
+);
+create index admissions_enquiries_updated_at_idx on admissions_enquiries (updated_at);
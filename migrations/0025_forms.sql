@@ -0,0 +1,32 @@
+alter type permission add value 'manage_forms';
+
+-- Forms staff build themselves, such as consent slips. `fields` is the
+-- schema submissions are checked against
+create table forms (
+  id serial primary key,
+
+  title varchar(255) not null,
+  description text not null default '',
+  fields jsonb not null default '[]',
+
+  -- Sent a copy of each submission
+  notify varchar(512)[] not null default '{}',
+  -- Submissions are only taken while `open`, and until `closes_at` if set
+  open boolean not null default true,
+  closes_at timestamptz,
+
+  created_at timestamptz not null default now(),
+  updated_at timestamptz not null default now()
+);
+
+create table form_submissions (
+  id serial primary key,
+  form_id integer not null
+  references forms(id)
+  on delete cascade,
+
+  -- By field name, as checked against the form's fields when submitted
+  data jsonb not null,
+  submitted_at timestamptz not null default now()
+);
+create index form_submissions_form_id_idx on form_submissions (form_id);
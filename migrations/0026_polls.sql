@@ -0,0 +1,31 @@
+alter type permission add value 'manage_polls';
+
+-- Votes are taken from anyone logged in, from `opens_at` until `closes_at`
+-- if set
+create table polls (
+  id serial primary key,
+
+  question varchar(255) not null,
+  options varchar(255)[] not null,
+
+  opens_at timestamptz not null,
+  closes_at timestamptz,
+  created_at timestamptz not null default now(),
+
+  check (closes_at > opens_at)
+);
+
+-- One per user per poll. `choice` is an index into the poll's `options`
+create table poll_votes (
+  poll_id integer not null
+  references polls(id)
+  on delete cascade,
+  user_id integer not null
+  references users(id)
+  on delete cascade,
+
+  choice smallint not null check (choice >= 0),
+  voted_at timestamptz not null default now(),
+
+  primary key (poll_id, user_id)
+);
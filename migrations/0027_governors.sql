@@ -0,0 +1,31 @@
+alter type permission add value 'manage_governors';
+
+create type governor_category as enum(
+  'headteacher',
+  'parent',
+  'staff',
+  'local_authority',
+  'foundation',
+  'partnership',
+  'co_opted',
+  'associate'
+);
+
+-- The governing board, which the school must publish and keep up to date
+create table governors (
+  id serial primary key,
+
+  name varchar(255) not null,
+  -- Such as chair, or the committees they sit on
+  role varchar(255) not null default '',
+  category governor_category not null,
+  term_ends_on date not null,
+
+  -- Their register of business and pecuniary interests, from the documents
+  -- library
+  interests_document integer
+  references documents(id)
+  on delete set null,
+
+  updated_at timestamptz not null default now()
+);
@@ -0,0 +1,14 @@
+alter type permission add value 'manage_faqs';
+
+-- Questions and answers, listed publicly by `category` and then `position`
+create table faqs (
+  id serial primary key,
+
+  question varchar(255) not null,
+  answer jsonb not null default '[]',
+  category varchar(255) not null,
+  position integer not null default 0,
+
+  updated_at timestamptz not null default now()
+);
+create index faqs_category_position_idx on faqs (category, position);
@@ -0,0 +1,7 @@
+-- Events copied in from external calendar feeds. `source` is the feed's name
+-- from the settings, null for events made here, and `source_uid` the event's
+-- UID in that feed, so each sync updates what it brought in last time
+alter table events add column source varchar(255);
+alter table events add column source_uid text;
+alter table events add constraint events_source_uid_key unique (source, source_uid);
+alter table events add constraint events_source_check check ((source is null) = (source_uid is null));
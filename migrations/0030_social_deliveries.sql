@@ -0,0 +1,23 @@
+create type social_delivery_status as enum('pending', 'sent', 'failed');
+
+-- Sharing each published post on the social channels in the config. Failed
+-- deliveries are retried until `attempts` runs out
+create table social_deliveries (
+  id serial primary key,
+
+  post_id integer not null
+  references posts(id)
+  on delete cascade,
+  -- The channel's name from the config
+  channel varchar(255) not null,
+
+  status social_delivery_status not null default 'pending'::social_delivery_status,
+  attempts integer not null default 0,
+  last_error text,
+
+  created_at timestamptz not null default now(),
+  updated_at timestamptz not null default now(),
+
+  unique (post_id, channel)
+);
+create index social_deliveries_status_idx on social_deliveries (status);
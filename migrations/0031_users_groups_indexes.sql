@@ -0,0 +1,5 @@
+-- Memberships are looked up from both sides: a user's groups when working
+-- out their permissions, and a group's users when it's changed or deleted.
+-- Each covers the other column, so neither needs to touch the table
+create index users_groups_user_id_idx on users_groups (user_id) include (group_id);
+create index users_groups_group_id_idx on users_groups (group_id) include (user_id);
@@ -0,0 +1,13 @@
+-- When each was put in the trash, if it has been. Anything trashed is hidden
+-- everywhere but the trash, and removed for good once `trash.retention` days
+-- have passed. Pages in the trash are also archived, and archived pages
+-- without `deleted_at` are kept indefinitely
+alter table posts add column deleted_at timestamptz;
+alter table pages add column deleted_at timestamptz;
+alter table media add column deleted_at timestamptz;
+alter table users add column deleted_at timestamptz;
+
+create index posts_deleted_at_idx on posts (deleted_at) where deleted_at is not null;
+create index pages_deleted_at_idx on pages (deleted_at) where deleted_at is not null;
+create index media_deleted_at_idx on media (deleted_at) where deleted_at is not null;
+create index users_deleted_at_idx on users (deleted_at) where deleted_at is not null;
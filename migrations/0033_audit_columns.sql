@@ -0,0 +1,25 @@
+-- Who created each record and who last changed it, and when, so editors can
+-- see who touched what. Left null for anything made before these were kept,
+-- or by someone since removed
+alter table posts
+  add column created_by integer references users(id) on update cascade on delete set null,
+  add column updated_by integer references users(id) on update cascade on delete set null,
+  add column updated_at timestamptz not null default now();
+
+-- The author is whoever created the post, unless it's been changed since.
+-- Who last edited it wasn't kept
+update posts set created_by = author, updated_at = date;
+
+alter table pages
+  add column created_by integer references users(id) on update cascade on delete set null,
+  add column updated_by integer references users(id) on update cascade on delete set null;
+
+alter table departments
+  add column created_by integer references users(id) on update cascade on delete set null,
+  add column updated_by integer references users(id) on update cascade on delete set null,
+  add column updated_at timestamptz not null default now();
+
+alter table categories
+  add column created_by integer references users(id) on update cascade on delete set null,
+  add column updated_by integer references users(id) on update cascade on delete set null,
+  add column updated_at timestamptz not null default now();
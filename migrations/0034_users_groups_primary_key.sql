@@ -0,0 +1,8 @@
+-- A user is only ever in a group once, so memberships can be added in bulk
+-- without first checking which already exist. Any duplicates from before are
+-- dropped
+delete from users_groups a
+using users_groups b
+where a.ctid > b.ctid and a.user_id = b.user_id and a.group_id = b.group_id;
+
+alter table users_groups add primary key (user_id, group_id);
@@ -0,0 +1,36 @@
+alter type permission add value 'approve_content';
+
+-- Where a page or post is in review, when `features.approval` is on. Null if
+-- it's never been put forward, or the approved changes have been deployed
+create type review_status as enum('awaiting', 'approved', 'rejected');
+
+alter table pages
+  add column review review_status,
+  add column submitted_by integer references users(id) on update cascade on delete set null,
+  add column reviewed_at timestamptz;
+
+-- Posts go live as soon as they're approved, so are hidden until then. Whether
+-- to share a post on the social channels is kept until it goes live
+alter table posts
+  add column review review_status,
+  add column submitted_by integer references users(id) on update cascade on delete set null,
+  add column reviewed_at timestamptz,
+  add column share_on_approval boolean not null default false,
+  add column published boolean not null generated always as (review is null or review = 'approved') stored;
+
+create index pages_review_idx on pages (review) where review is not null;
+create index posts_review_idx on posts (review) where review is not null;
+
+-- Who did what to which item, and why, kept for good. `actor` is null once
+-- they've been removed
+create table audit_log (
+  id serial primary key,
+  at timestamptz not null default now(),
+  actor integer references users(id) on update cascade on delete set null,
+  action text not null,
+  target_kind text not null,
+  target_id integer not null,
+  comment text
+);
+
+create index audit_log_target_idx on audit_log (target_kind, target_id);
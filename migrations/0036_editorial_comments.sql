@@ -0,0 +1,17 @@
+-- Notes left on a page or post by those editing or reviewing it, never shown
+-- publicly. Each is on exactly one of the two, and goes with it
+create table editorial_comments (
+  id serial primary key,
+  page_id integer references pages(id) on update cascade on delete cascade,
+  post_id integer references posts(id) on update cascade on delete cascade,
+  author integer references users(id) on update cascade on delete set null,
+  body text not null,
+  resolved boolean not null default false,
+  resolved_by integer references users(id) on update cascade on delete set null,
+  created_at timestamptz not null default now(),
+  updated_at timestamptz not null default now(),
+  check (num_nonnulls(page_id, post_id) = 1)
+);
+
+create index editorial_comments_page_id_idx on editorial_comments (page_id) where page_id is not null;
+create index editorial_comments_post_id_idx on editorial_comments (post_id) where post_id is not null;
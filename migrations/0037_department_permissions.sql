@@ -0,0 +1,14 @@
+-- The department a page belongs to, if any, as posts already have
+alter table pages
+  add column department integer references departments(id) on update cascade on delete set null;
+
+create index pages_department_idx on pages (department) where department is not null;
+
+-- Permissions given to a user over only one department's pages or posts, on
+-- top of those they have across the site
+create table department_permissions (
+  user_id integer not null references users(id) on update cascade on delete cascade,
+  department_id integer not null references departments(id) on update cascade on delete cascade,
+  permission permission not null,
+  primary key (user_id, permission, department_id)
+);
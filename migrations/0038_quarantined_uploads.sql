@@ -0,0 +1,12 @@
+-- Uploads the virus scanner flagged, kept apart from `media` and out of reach
+-- of where uploads are served until someone deletes them. Files are under
+-- `quarantine/<id>/` in storage
+create table quarantined_uploads (
+  id serial primary key,
+  filename varchar(255) not null,
+  mime varchar(255) not null,
+  size bigint not null,
+  threat text not null,
+  uploader integer references users(id) on update cascade on delete set null,
+  quarantined_at timestamptz not null default now()
+);
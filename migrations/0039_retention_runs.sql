@@ -0,0 +1,12 @@
+-- What each run of the retention job deleted, as a record that the retention
+-- schedule is being kept to
+create table retention_runs (
+  id serial primary key,
+  ran_at timestamptz not null default now(),
+  audit_log bigint not null,
+  form_submissions bigint not null,
+  admissions_enquiries bigint not null
+);
+
+create index form_submissions_submitted_at_idx on form_submissions (submitted_at);
+create index audit_log_at_idx on audit_log (at);
@@ -0,0 +1,24 @@
+-- Views of each deployed page per day, from the beacon pages send for
+-- visitors who've agreed to analytics. Nothing in here identifies anyone
+create table page_views (
+  day date not null,
+  path varchar(512) not null,
+  views bigint not null default 0,
+  -- Each counted once per page per day
+  visitors bigint not null default 0,
+  primary key (day, path)
+);
+
+-- Visitors are told apart by a hash of their address with the day's salt.
+-- Both are deleted once the day's over, so they can't be followed across days
+create table analytics_salts (
+  day date primary key,
+  salt bytea not null
+);
+
+create table page_view_visitors (
+  day date not null,
+  path varchar(512) not null,
+  visitor bytea not null,
+  primary key (day, path, visitor)
+);
@@ -0,0 +1,4 @@
+-- Pages and posts search engines are asked not to index. They're left out of
+-- the sitemap, and deployed pages get a robots meta tag
+alter table pages add column noindex boolean not null default false;
+alter table posts add column noindex boolean not null default false;
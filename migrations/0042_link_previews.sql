@@ -0,0 +1,12 @@
+-- What search results and link previews on social media show for a page or
+-- post, where it shouldn't just be the title. Images are URLs, usually of
+-- uploaded media
+alter table pages
+  add column meta_description varchar(300),
+  add column og_title varchar(200),
+  add column og_image varchar(2048);
+
+alter table posts
+  add column meta_description varchar(300),
+  add column og_title varchar(200),
+  add column og_image varchar(2048);
@@ -0,0 +1,129 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, LineWriter, Write},
+    sync::Arc,
+    time::Instant,
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use parking_lot::Mutex;
+use serde_json::json;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    auth::AuthUserId,
+    client_ip::ClientInfo,
+    config::{AccessLogConfig, AccessLogFormat},
+    request_id::RequestId,
+};
+
+pub struct AccessLog {
+    format: AccessLogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    /// `None` if access logging is off.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the log file can't be opened.
+    pub fn new(config: &AccessLogConfig) -> io::Result<Option<Arc<Self>>> {
+        if config.format == AccessLogFormat::Off {
+            return Ok(None);
+        }
+
+        let out: Box<dyn Write + Send> = match &config.path {
+            Some(path) => Box::new(LineWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => Box::new(io::stdout()),
+        };
+
+        Ok(Some(Arc::new(Self {
+            format: config.format,
+            out: Mutex::new(out),
+        })))
+    }
+
+    fn write(&self, entry: &Entry) {
+        let timestamp = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+
+        let line = match self.format {
+            AccessLogFormat::Json => json!({
+                "timestamp": timestamp,
+                "request_id": entry.request_id,
+                "client_ip": entry.client_ip,
+                "method": entry.method,
+                "path": entry.path,
+                "status": entry.status,
+                "latency_ms": entry.latency_ms,
+                "user_id": entry.user_id,
+            })
+            .to_string(),
+            AccessLogFormat::Text => format!(
+                "{timestamp} {} {} {} {} {} {:.1}ms user={}",
+                entry.request_id.as_deref().unwrap_or("-"),
+                entry.client_ip.as_deref().unwrap_or("-"),
+                entry.method,
+                entry.path,
+                entry.status,
+                entry.latency_ms,
+                entry
+                    .user_id
+                    .map_or_else(|| "-".into(), |id| id.to_string()),
+            ),
+            AccessLogFormat::Off => return,
+        };
+
+        let written = writeln!(self.out.lock(), "{line}");
+        if let Err(error) = written {
+            tracing::warn!(?error, "Failed to write to the access log");
+        }
+    }
+}
+
+struct Entry {
+    request_id: Option<String>,
+    client_ip: Option<String>,
+    method: String,
+    path: String,
+    status: u16,
+    latency_ms: f64,
+    user_id: Option<i32>,
+}
+
+/// Writes a line to the access log once each response is ready. Streamed
+/// bodies, such as previews, are logged when they start rather than end.
+pub async fn log_request(State(log): State<Arc<AccessLog>>, req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let request_id = req.extensions().get::<RequestId>().map(ToString::to_string);
+    let client_ip = req
+        .extensions()
+        .get::<ClientInfo>()
+        .map(|client| client.ip.to_string());
+    let method = req.method().to_string();
+    let path = req.uri().path().to_owned();
+
+    let res = next.run(req).await;
+
+    let entry = Entry {
+        request_id,
+        client_ip,
+        method,
+        path,
+        status: res.status().as_u16(),
+        latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+        user_id: res.extensions().get::<AuthUserId>().map(|user| user.0),
+    };
+
+    log.write(&entry);
+
+    res
+}
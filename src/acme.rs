@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{extract::Path, http::StatusCode, Extension};
+use axum_server::tls_rustls::RustlsConfig;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, Order, OrderStatus,
+};
+use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+use tokio::fs;
+
+use crate::AcmeConfig;
+
+/// How often the certificate is checked for being due a renewal.
+const CHECK_INTERVAL: Duration = Duration::from_hours(12);
+
+/// How old a certificate gets before it's replaced. Let's Encrypt's last 90
+/// days, so this leaves a month to retry in if the CA is unreachable.
+const RENEW_AFTER: Duration = Duration::from_hours(60 * 24);
+
+/// How many times an order is polled while the CA validates it.
+const MAX_POLLS: u32 = 10;
+
+const ACCOUNT_FILE: &str = "account.json";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AcmeError {
+    #[error("ACME request failed: {0}")]
+    Acme(#[from] instant_acme::Error),
+    #[error("Couldn't make a certificate request: {0}")]
+    Csr(#[from] rcgen::Error),
+    #[error("Couldn't access the ACME cache directory: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid saved ACME account: {0}")]
+    Account(#[from] serde_json::Error),
+    #[error("Certificate order failed: {0}")]
+    Order(String),
+}
+
+/// Key authorizations for the HTTP-01 challenges in flight, by token.
+#[derive(Clone, Default)]
+pub struct Challenges(Arc<parking_lot::RwLock<HashMap<String, String>>>);
+
+/// Answers the CA's requests to `/.well-known/acme-challenge/:token`.
+pub async fn respond_to_challenge(
+    Extension(challenges): Extension<Challenges>,
+    Path(token): Path<String>,
+) -> Result<String, StatusCode> {
+    challenges
+        .0
+        .read()
+        .get(&token)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Gets certificates for [`AcmeConfig::domains`], keeping them and the account
+/// they're issued to in [`AcmeConfig::cache_dir`].
+pub struct Acme {
+    config: AcmeConfig,
+    challenges: Challenges,
+}
+
+impl Acme {
+    #[must_use]
+    pub const fn new(config: AcmeConfig, challenges: Challenges) -> Self {
+        Self { config, challenges }
+    }
+
+    /// Orders a certificate if there isn't one yet or the current one is due
+    /// for renewal, returning whether a new one was written.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the CA can't be reached, a challenge can't be met, or the
+    /// cache directory can't be written to.
+    pub async fn ensure_certificate(&self) -> Result<bool, AcmeError> {
+        let issued = fs::metadata(self.config.cert_path())
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if issued
+            .and_then(|issued| SystemTime::now().duration_since(issued).ok())
+            .is_some_and(|age| age < RENEW_AFTER)
+        {
+            return Ok(false);
+        }
+
+        tracing::info!(domains = ?self.config.domains, "Ordering a certificate");
+
+        let account = self.account().await?;
+        let identifiers = self
+            .config
+            .domains
+            .iter()
+            .map(|domain| Identifier::Dns(domain.clone()))
+            .collect::<Vec<_>>();
+        let mut order = account
+            .new_order(&NewOrder {
+                identifiers: &identifiers,
+            })
+            .await?;
+
+        let mut tokens = Vec::new();
+        let result = async {
+            self.accept_challenges(&mut order, &mut tokens).await?;
+            self.finish_order(&mut order).await
+        }
+        .await;
+
+        let mut pending = self.challenges.0.write();
+        for token in &tokens {
+            pending.remove(token);
+        }
+        drop(pending);
+
+        let (cert, key) = result?;
+        fs::write(self.config.key_path(), key).await?;
+        fs::write(self.config.cert_path(), cert).await?;
+
+        tracing::info!("Got a new certificate");
+        Ok(true)
+    }
+
+    /// The saved account, or a new one if there isn't one yet.
+    async fn account(&self) -> Result<Account, AcmeError> {
+        let path = self.config.cache_dir.join(ACCOUNT_FILE);
+
+        match fs::read(&path).await {
+            Ok(saved) => {
+                let credentials = serde_json::from_slice::<AccountCredentials>(&saved)?;
+                return Ok(Account::from_credentials(credentials).await?);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let contact = self
+            .config
+            .contact
+            .iter()
+            .map(|email| format!("mailto:{email}"))
+            .collect::<Vec<_>>();
+        let contact = contact.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let (account, credentials) = Account::create(
+            &NewAccount {
+                contact: &contact,
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            &self.config.directory,
+            None,
+        )
+        .await?;
+
+        fs::create_dir_all(&self.config.cache_dir).await?;
+        fs::write(&path, serde_json::to_vec(&credentials)?).await?;
+        tracing::info!(directory = self.config.directory, "Created an ACME account");
+
+        Ok(account)
+    }
+
+    /// Publishes the key authorization for each pending HTTP-01 challenge and
+    /// tells the CA to check them, adding their tokens to `tokens`.
+    async fn accept_challenges(
+        &self,
+        order: &mut Order,
+        tokens: &mut Vec<String>,
+    ) -> Result<(), AcmeError> {
+        for authorization in order.authorizations().await? {
+            if authorization.status != AuthorizationStatus::Pending {
+                continue;
+            }
+
+            let challenge = authorization
+                .challenges
+                .iter()
+                .find(|challenge| challenge.r#type == ChallengeType::Http01)
+                .ok_or_else(|| {
+                    AcmeError::Order(format!(
+                        "No HTTP-01 challenge offered for {:?}",
+                        authorization.identifier
+                    ))
+                })?;
+
+            self.challenges.0.write().insert(
+                challenge.token.clone(),
+                order.key_authorization(challenge).as_str().to_owned(),
+            );
+            tokens.push(challenge.token.clone());
+
+            order.set_challenge_ready(&challenge.url).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the CA to validate the order, then has it sign a new key,
+    /// returning the certificate chain and the key as PEM.
+    async fn finish_order(&self, order: &mut Order) -> Result<(String, String), AcmeError> {
+        let mut delay = Duration::from_millis(250);
+        let mut polls = 0;
+
+        loop {
+            tokio::time::sleep(delay).await;
+
+            match order.refresh().await?.status {
+                OrderStatus::Ready => break,
+                OrderStatus::Invalid => {
+                    return Err(AcmeError::Order(
+                        "The CA couldn't validate every domain. Is port 80 reachable?".into(),
+                    ))
+                }
+                _ if polls == MAX_POLLS => {
+                    return Err(AcmeError::Order("Timed out waiting for the CA".into()))
+                }
+                _ => {}
+            }
+
+            delay = (delay * 2).min(Duration::from_secs(10));
+            polls += 1;
+        }
+
+        let mut params = CertificateParams::new(self.config.domains.clone())?;
+        params.distinguished_name = DistinguishedName::new();
+        let key = KeyPair::generate()?;
+        let csr = params.serialize_request(&key)?;
+        order.finalize(csr.der()).await?;
+
+        let cert = loop {
+            if let Some(cert) = order.certificate().await? {
+                break cert;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        };
+
+        Ok((cert, key.serialize_pem()))
+    }
+}
+
+/// Renews the certificate when it's due, swapping it into `tls`, for the
+/// lifetime of the server.
+pub async fn renew_job(acme: Acme, tls: RustlsConfig) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    // The first tick is immediate, and startup has only just checked
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        match acme.ensure_certificate().await {
+            Ok(false) => {}
+            Ok(true) => {
+                if let Err(error) = tls
+                    .reload_from_pem_file(acme.config.cert_path(), acme.config.key_path())
+                    .await
+                {
+                    tracing::error!(?error, "Failed to load the renewed certificate");
+                }
+            }
+            Err(error) => tracing::error!(%error, "Failed to renew the certificate"),
+        }
+    }
+}
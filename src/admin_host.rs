@@ -0,0 +1,70 @@
+//! Keeping the admin API to a hostname of its own, when
+//! [`ServerConfig::admin_host`](crate::ServerConfig::admin_host) is set. Every
+//! other host serves the site, media and the API's reads, along with the few
+//! writes visitors make from the site's pages, and answers any other write as
+//! if it didn't exist.
+//!
+//! [`split_by_host`] marks requests that came in on another host, and
+//! [`admin_only`] turns the marked writes away from the routes it's layered
+//! on. Routes for visitors' writes are added after it, so it never sees them.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, uri::Authority, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+
+use crate::{client_ip::ClientInfo, serve};
+
+/// Marks a request that came in on a host other than the admin host.
+#[derive(Clone, Copy, Debug)]
+struct PublicHost;
+
+/// Marks requests to any host but the admin host, when one is set.
+pub async fn split_by_host(
+    State(admin_host): State<Option<Arc<str>>>,
+    Extension(client): Extension<ClientInfo>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let public = admin_host.is_some_and(|admin_host| {
+        !host(&client, &req).is_some_and(|host| host.eq_ignore_ascii_case(&admin_host))
+    });
+    if public {
+        req.extensions_mut().insert(PublicHost);
+    }
+
+    next.run(req).await
+}
+
+/// Turns away writes that didn't come in on the admin host. Reads are left
+/// alone, as the site's pages are built from them.
+pub async fn admin_only(req: Request, next: Next) -> Response {
+    let is_read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+
+    if is_read || req.extensions().get::<PublicHost>().is_none() {
+        next.run(req).await
+    } else {
+        serve::not_found().await.into_response()
+    }
+}
+
+/// The host the client asked for, without a port. A trusted proxy passes on
+/// the one it was asked for.
+fn host(client: &ClientInfo, req: &Request) -> Option<String> {
+    let host = match &client.forwarded_host {
+        Some(host) => host.as_str(),
+        None => req
+            .headers()
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| req.uri().host())?,
+    };
+
+    let authority = host.parse::<Authority>().ok()?;
+    Some(authority.host().to_owned())
+}
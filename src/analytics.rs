@@ -0,0 +1,306 @@
+//! Counting views of deployed pages without cookies or third-party trackers,
+//! when [`FeatureToggles::analytics`](crate::FeatureToggles::analytics) is on.
+//! Pages send a beacon to `/analytics/hit` for visitors who've agreed to it,
+//! which adds to that page's count for the day.
+//!
+//! Visitors are told apart by a hash of their address and browser with a salt
+//! that's replaced daily. The salts and hashes are deleted with the first view
+//! of the next day, so nobody can be followed from one day to the next.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::Query,
+    http::{header, HeaderMap, StatusCode},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+use time::{Date, OffsetDateTime};
+use tokio::sync::RwLock;
+use tower_cookies::Cookies;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    client_ip::ClientInfo,
+    consent,
+    db::Db,
+    error::{ErrorCode, PhsError},
+    validation::{FieldErrors, Validate, Validated},
+    ServerSettings,
+};
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// Longest path counted, as stored.
+const MAX_PATH_LENGTH: usize = 512;
+
+/// Days summarised if no range is given, up to and including today.
+const DEFAULT_DAYS: i64 = 30;
+
+/// Most days that can be summarised at once.
+const MAX_DAYS: i64 = 366;
+
+/// Most paths listed, most viewed first.
+const MAX_PATHS: i64 = 50;
+
+pub fn router() -> Router {
+    Router::new().route("/analytics", get(get_analytics))
+}
+
+/// Page views, reported from every page of the site.
+pub fn public_router() -> Router {
+    Router::new().route("/analytics/hit", post(hit))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_analytics, hit))]
+struct AnalyticsApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    AnalyticsApi::openapi()
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct Hit {
+    /// The page viewed, e.g. `/about/staff`. Any query or fragment is
+    /// ignored.
+    path: String,
+}
+
+impl Hit {
+    /// The path as it's counted, without a query, fragment or trailing slash.
+    fn page(&self) -> &str {
+        let path = self.path.split(['?', '#']).next().unwrap_or_default();
+        match path.trim_end_matches('/') {
+            "" => "/",
+            path => path,
+        }
+    }
+}
+
+impl Validate for Hit {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.check(self.path.starts_with('/'), "path", "Must start with /");
+        errors.max_chars("path", self.page(), MAX_PATH_LENGTH);
+        errors
+    }
+}
+
+/// Counts a view of a page, unless analytics is switched off or the visitor
+/// hasn't agreed to it, in which case it's quietly ignored.
+#[utoipa::path(
+    post,
+    path = "/analytics/hit",
+    tag = "analytics",
+    request_body = Hit,
+    responses(
+        (status = 204),
+        (status = 422, description = "The path doesn't start with / or is too long"),
+    )
+)]
+#[instrument(skip(pool, settings, client, cookies, headers))]
+async fn hit(
+    Extension(pool): Extension<PgPool>,
+    Extension(settings): Extension<Arc<RwLock<ServerSettings>>>,
+    Extension(client): Extension<ClientInfo>,
+    cookies: Cookies,
+    headers: HeaderMap,
+    Validated(hit): Validated<Hit>,
+) -> Result<StatusCode, PhsError> {
+    if !settings.read().await.features.analytics || !consent::allows_analytics(&cookies) {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    let page = hit.page();
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .map_or(&[][..], |agent| agent.as_bytes());
+
+    let mut tx = pool.begin().await?;
+
+    let visitor = Sha256::new()
+        .chain_update(todays_salt(&mut tx).await?)
+        .chain_update(client.ip.to_string())
+        .chain_update(user_agent)
+        .finalize()
+        .to_vec();
+
+    let new_visitor = sqlx::query!(
+        "INSERT INTO page_view_visitors (day, path, visitor) VALUES (current_date, $1, $2) ON CONFLICT DO NOTHING",
+        page,
+        visitor
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        r"
+        INSERT INTO page_views (day, path, views, visitors) VALUES (current_date, $1, 1, $2)
+        ON CONFLICT (day, path) DO UPDATE
+        SET views = page_views.views + 1, visitors = page_views.visitors + EXCLUDED.visitors
+        ",
+        page,
+        i64::try_from(new_visitor).unwrap_or(i64::MAX)
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Today's salt, made if this is the first view today, in which case earlier
+/// days' salts and hashes are deleted.
+async fn todays_salt(conn: &mut PgConnection) -> Result<Vec<u8>, PhsError> {
+    let mut salt = [0u8; 32];
+    OsRng.fill_bytes(&mut salt);
+
+    let made = sqlx::query!(
+        "INSERT INTO analytics_salts (day, salt) VALUES (current_date, $1) ON CONFLICT (day) DO NOTHING",
+        &salt[..]
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    if made > 0 {
+        sqlx::query!("DELETE FROM analytics_salts WHERE day < current_date")
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query!("DELETE FROM page_view_visitors WHERE day < current_date")
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    sqlx::query_scalar!("SELECT salt FROM analytics_salts WHERE day = current_date")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(Into::into)
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AnalyticsParams {
+    /// The first day, e.g. `2025-09-01`. Defaults to 29 days before `to`.
+    #[serde(default, with = "iso_date::option")]
+    #[param(value_type = Option<String>, format = Date)]
+    from: Option<Date>,
+    /// The last day. Defaults to today.
+    #[serde(default, with = "iso_date::option")]
+    #[param(value_type = Option<String>, format = Date)]
+    to: Option<Date>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct DailyViews {
+    #[serde(with = "iso_date")]
+    #[schema(value_type = String, format = Date)]
+    day: Date,
+    views: i64,
+    /// Added up across pages, so someone who viewed two pages counts twice.
+    visitors: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct PathViews {
+    path: String,
+    views: i64,
+    /// Added up across days, so someone who came back the next day counts
+    /// twice.
+    visitors: i64,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct AnalyticsSummary {
+    /// Every day in the range that had any views, oldest first.
+    days: Vec<DailyViews>,
+    /// The most viewed pages over the range, most viewed first.
+    paths: Vec<PathViews>,
+}
+
+/// Page views over a range of days, both per day and per page.
+#[utoipa::path(
+    get,
+    path = "/analytics",
+    tag = "analytics",
+    params(AnalyticsParams),
+    responses(
+        (status = 200, body = AnalyticsSummary),
+        (status = 400, description = "The range is backwards or longer than 366 days"),
+        (status = 403, description = "Missing the `ManageSettings` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_analytics(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Query(params): Query<AnalyticsParams>,
+) -> Result<Json<AnalyticsSummary>, PhsError> {
+    let to = params
+        .to
+        .unwrap_or_else(|| OffsetDateTime::now_utc().date());
+    let from = params
+        .from
+        .unwrap_or_else(|| to - time::Duration::days(DEFAULT_DAYS - 1));
+
+    if from > to || (to - from).whole_days() >= MAX_DAYS {
+        return Err(PhsError::client(
+            ErrorCode::BadRequest,
+            "The range must run forwards and be at most 366 days",
+        ));
+    }
+
+    let days = db
+        .timed(
+            "analytics_days",
+            sqlx::query_as!(
+                DailyViews,
+                r#"
+                SELECT day, sum(views)::bigint AS "views!", sum(visitors)::bigint AS "visitors!"
+                FROM page_views
+                WHERE day BETWEEN $1 AND $2
+                GROUP BY day
+                ORDER BY day
+                "#,
+                from,
+                to
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    let paths = db
+        .timed(
+            "analytics_paths",
+            sqlx::query_as!(
+                PathViews,
+                r#"
+                SELECT path, sum(views)::bigint AS "views!", sum(visitors)::bigint AS "visitors!"
+                FROM page_views
+                WHERE day BETWEEN $1 AND $2
+                GROUP BY path
+                ORDER BY 2 DESC, path
+                LIMIT $3
+                "#,
+                from,
+                to,
+                MAX_PATHS
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    Ok(Json(AnalyticsSummary { days, paths }))
+}
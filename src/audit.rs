@@ -0,0 +1,62 @@
+//! Who did what to which item, and why, for changes that need answering for
+//! later, such as approving something for publishing. Entries are only ever
+//! added, until they're older than
+//! [`RetentionConfig::audit_log`](crate::RetentionConfig::audit_log).
+
+use serde::Serialize;
+use sqlx::PgConnection;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::{db::Db, error::PhsError};
+
+#[derive(Serialize, Debug, ToSchema)]
+pub(crate) struct AuditEntry {
+    /// Such as `approved`, depending on the kind of item.
+    action: String,
+    /// Who did it, unless they've since been removed.
+    actor: Option<i32>,
+    comment: Option<String>,
+    #[serde(with = "time::serde::iso8601")]
+    at: OffsetDateTime,
+}
+
+/// Records `actor` doing `action` to the item of `kind` with this ID, as part
+/// of whatever transaction `conn` is in so it's only kept if the change is.
+pub(crate) async fn record(
+    conn: &mut PgConnection,
+    actor: i32,
+    action: &str,
+    kind: &str,
+    id: i32,
+    comment: Option<&str>,
+) -> Result<(), PhsError> {
+    sqlx::query!(
+        "INSERT INTO audit_log (actor, action, target_kind, target_id, comment) VALUES ($1, $2, $3, $4, $5)",
+        actor,
+        action,
+        kind,
+        id,
+        comment
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Everything done to the item of `kind` with this ID, oldest first.
+pub(crate) async fn history(db: &Db, kind: &str, id: i32) -> Result<Vec<AuditEntry>, PhsError> {
+    db.timed(
+        "audit_history",
+        sqlx::query_as!(
+            AuditEntry,
+            "SELECT action, actor, comment, at FROM audit_log WHERE target_kind = $1 AND target_id = $2 ORDER BY id",
+            kind,
+            id
+        )
+        .fetch_all(db.read()),
+    )
+    .await
+    .map_err(Into::into)
+}
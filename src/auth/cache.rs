@@ -0,0 +1,157 @@
+use deadpool_redis::Pool as RedisPool;
+use sqlx::PgPool;
+
+use crate::error::PhsError;
+
+use super::Permission;
+
+/// Bumped to invalidate every user's cached permissions at once, as when a
+/// group's permissions change. Old entries can't be found from a new version
+/// and are left to expire.
+const VERSION_KEY: &str = "permissions:version";
+
+/// How long cached permissions live, in seconds, if nothing invalidates them
+/// first.
+const CACHE_TTL: u64 = 60 * 10;
+
+/// Each user's effective permissions, their own together with their groups',
+/// cached in Redis so [`RequirePermission`](super::RequirePermission) sees
+/// changes without the user logging in again.
+///
+/// Entries are keyed by a global version and one per user, which
+/// [`Self::invalidate_all`] and [`Self::invalidate_user`] bump. Without Redis,
+/// or if it's unavailable, permissions are read from the database every time.
+#[derive(Clone)]
+pub struct PermissionCache {
+    redis: Option<RedisPool>,
+}
+
+impl PermissionCache {
+    #[must_use]
+    pub const fn new(redis: Option<RedisPool>) -> Self {
+        Self { redis }
+    }
+
+    /// The user's permissions as they are now, or none if they've been
+    /// deleted.
+    pub(crate) async fn get(
+        &self,
+        pool: &PgPool,
+        user_id: i32,
+    ) -> Result<Vec<Permission>, PhsError> {
+        let Some(redis) = &self.redis else {
+            return effective_permissions(pool, user_id).await;
+        };
+
+        let cached = async {
+            let mut conn = redis.get().await?;
+            let (global, user) = redis::cmd("MGET")
+                .arg(VERSION_KEY)
+                .arg(user_version_key(user_id))
+                .query_async::<(Option<u64>, Option<u64>)>(&mut conn)
+                .await?;
+            let key = format!(
+                "permissions:{}:{}:{user_id}",
+                global.unwrap_or_default(),
+                user.unwrap_or_default()
+            );
+
+            let hit = redis::cmd("GET")
+                .arg(&key)
+                .query_async::<Option<String>>(&mut conn)
+                .await?;
+
+            Ok::<_, PhsError>((conn, key, hit))
+        }
+        .await;
+
+        let (mut conn, key) = match cached {
+            Ok((conn, key, Some(hit))) => match serde_json::from_str(&hit) {
+                Ok(permissions) => return Ok(permissions),
+                // Written by an older version with permissions since removed
+                Err(_) => (conn, key),
+            },
+            Ok((conn, key, None)) => (conn, key),
+            Err(error) => {
+                tracing::warn!(?error, "Permission cache unavailable");
+                return effective_permissions(pool, user_id).await;
+            }
+        };
+
+        // A change made after the versions were read bumps them, so this can
+        // only ever be cached under a key no one will look up again
+        let permissions = effective_permissions(pool, user_id).await?;
+
+        if let Err(error) = redis::cmd("SET")
+            .arg(&key)
+            .arg(serde_json::to_string(&permissions)?)
+            .arg("EX")
+            .arg(CACHE_TTL)
+            .query_async::<()>(&mut conn)
+            .await
+        {
+            tracing::warn!(?error, "Failed to cache permissions");
+        }
+
+        Ok(permissions)
+    }
+
+    /// Drops the user's cached permissions, after a change to their own or
+    /// which groups they're in.
+    pub(crate) async fn invalidate_user(&self, user_id: i32) {
+        self.bump(&user_version_key(user_id)).await;
+    }
+
+    /// Drops every user's cached permissions, after a change to a group's.
+    pub(crate) async fn invalidate_all(&self) {
+        self.bump(VERSION_KEY).await;
+    }
+
+    async fn bump(&self, key: &str) {
+        let Some(redis) = &self.redis else {
+            return;
+        };
+
+        let result = async {
+            let mut conn = redis.get().await?;
+            redis::cmd("INCR")
+                .arg(key)
+                .query_async::<u64>(&mut conn)
+                .await?;
+
+            Ok::<_, PhsError>(())
+        }
+        .await;
+
+        // Left cached until it expires
+        if let Err(error) = result {
+            tracing::error!(?error, key, "Failed to invalidate cached permissions");
+        }
+    }
+}
+
+fn user_version_key(user_id: i32) -> String {
+    format!("permissions:version:{user_id}")
+}
+
+async fn effective_permissions(pool: &PgPool, user_id: i32) -> Result<Vec<Permission>, PhsError> {
+    let permissions = sqlx::query_scalar!(
+        r#"
+        SELECT ARRAY(
+            SELECT unnest(u.permissions)
+            UNION
+            SELECT unnest(g.permissions)
+            FROM users_groups ug
+            JOIN groups g ON g.id = ug.group_id
+            WHERE ug.user_id = u.id
+          ) AS "permissions!: Vec<Permission>"
+        FROM users u
+        WHERE u.id = $1 AND u.deleted_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(permissions.unwrap_or_default())
+}
@@ -1,117 +1,131 @@
+use std::str::FromStr;
+
 use super::Session;
 use argon2::{password_hash, Argon2, PasswordHash, PasswordVerifier};
 use axum::{
     extract::{Path, Query},
-    http::StatusCode,
     routing::{get, post, put},
     Extension, Json, Router,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_with::DeserializeFromStr;
 use sqlx::PgPool;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
 use crate::{
-    auth::{AuthUser, Permission, UserPermissions},
-    error::PhsError,
-    resources::{CursorOptions, CursorResponse, HasSqlxQueryString, Role},
+    auth::{AuthUser, DepartmentPermission, GroupMember, Permission, UserPermissions},
+    db::Db,
+    error::{ErrorCode, PhsError},
+    resources::{CursorOptions, CursorResponse, HasSqlxQueryString},
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    permission::{
+        permission_scope, GroupMemberQueryString, GroupQueryString, UserPermissionsQueryString,
+    },
+    AuthSession, Group, PermissionCache, PermissionScope, RequirePermission,
 };
 
-use super::{AuthSession, Group, RequirePermission};
+/// Longest a group's name can be, as stored.
+const MAX_GROUP_NAME_LENGTH: usize = 128;
+
+/// Most users or groups that can be listed in one bulk membership change.
+const MAX_BULK_IDS: usize = 500;
 
 pub fn router() -> Router {
     Router::new()
-        .route("/v1/auth/login", post(login))
-        .route("/v1/auth/logout", get(logout))
-        .route("/v1/auth/whoami", get(whoami))
-        .route("/v1/auth/groups", get(get_groups).post(create_group))
-        .route("/v1/auth/group/:id", put(put_group).delete(delete_group))
+        .route("/auth/login", post(login))
+        .route("/auth/logout", get(logout))
+        .route("/auth/whoami", get(whoami))
+        .route("/auth/can", get(can))
+        .route("/auth/groups", get(get_groups).post(create_group))
+        .route("/auth/group/:id", put(put_group).delete(delete_group))
+        .route("/auth/group/:id/members", get(get_group_members))
         .route(
-            "/v1/auth/users/groups",
+            "/auth/groups/:id/members",
+            post(add_group_members).delete(delete_group_members),
+        )
+        .route(
+            "/auth/users/groups",
             get(add_to_group).delete(delete_from_group),
         )
-        .route("/v1/auth/users/permissions/:id", get(get_user_permissions))
-        .route("/v1/auth/users/permissions", get(get_users_permissions))
+        .route("/auth/users/:id/groups", put(put_user_groups))
+        .route(
+            "/auth/users/:id/departments",
+            get(get_department_permissions).put(put_department_permissions),
+        )
+        .route("/auth/users/permissions/:id", get(get_user_permissions))
+        .route("/auth/users/permissions", get(get_users_permissions))
 }
 
-#[derive(Deserialize)]
+#[derive(OpenApi)]
+#[openapi(paths(
+    login,
+    logout,
+    whoami,
+    can,
+    get_groups,
+    create_group,
+    put_group,
+    delete_group,
+    get_group_members,
+    add_to_group,
+    delete_from_group,
+    add_group_members,
+    delete_group_members,
+    put_user_groups,
+    get_department_permissions,
+    put_department_permissions,
+    get_user_permissions,
+    get_users_permissions
+))]
+struct AuthApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    AuthApi::openapi()
+}
+
+#[derive(Deserialize, ToSchema)]
 struct PostLoginBody {
     username: String,
     password: String,
 }
 
+/// Starts a session, set in the `id` cookie.
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = PostLoginBody,
+    responses(
+        (status = 200, body = String, content_type = "text/plain"),
+        (status = 401, description = "The username or password is wrong"),
+        (status = 404, description = "No user has this username"),
+    )
+)]
 async fn login(
     session: Session,
     Extension(pool): Extension<PgPool>,
     Json(credentials): Json<PostLoginBody>,
 ) -> Result<String, PhsError> {
-    // TODO: Consolodate queries and remove UserWithHash type
-    struct UserWithHash {
-        id: i32,
-        username: String,
-        role: Role,
-        hash: String,
-        permissions: Vec<Permission>,
-    }
-
-    let user = sqlx::query_as!(
-        UserWithHash,
-        r#"
-        SELECT id, username, role as "role: _", hash, permissions as "permissions: _"
-        FROM users
-        WHERE username = $1
-        "#,
-        credentials.username
-    )
-    .fetch_one(&pool)
-    .await?;
+    let auth_user = auth_user(&pool, &credentials.username).await?;
 
     Argon2::default()
         .verify_password(
             credentials.password.as_bytes(),
-            &PasswordHash::new(user.hash.as_str())?,
+            &PasswordHash::new(auth_user.hash())?,
         )
         .map_err(|e| match e {
-            password_hash::Error::Password => {
-                PhsError(StatusCode::UNAUTHORIZED, Some(Box::new(e)), "Unauthorised")
-            }
+            password_hash::Error::Password => PhsError::client(
+                ErrorCode::WrongCredentials,
+                "Incorrect username or password",
+            ),
             e => e.into(),
         })?;
 
     // Credentials are correct as of here
-
-    // Get the user's groups and permissions
-    let group_data = sqlx::query_as!(
-        Group,
-        r#"
-        SELECT id, group_name, permissions as "permissions: _"
-        FROM users_groups
-        INNER JOIN groups
-        ON groups.id = users_groups.group_id
-        WHERE user_id = $1
-        "#,
-        user.id
-    )
-    .fetch_all(&pool)
-    .await?;
-
-    let mut permissions = group_data
-        .iter()
-        .flat_map(|gd| gd.permissions.iter())
-        .copied()
-        .collect::<Vec<Permission>>();
-
-    // Add the user's override permissions to the vector
-    permissions.extend(&user.permissions);
-
-    let groups = group_data.into_iter().map(|gd| gd.group_name).collect();
-
-    let auth_user = AuthUser {
-        id: user.id,
-        hash: user.hash.clone(),
-        username: user.username.clone(),
-        role: user.role,
-        permissions,
-        groups,
-    };
+    let user_id = auth_user.id();
 
     session.set(auth_user).await?;
 
@@ -121,26 +135,302 @@ async fn login(
     // Then cycle the ID to prevent session fixation
     session.cycle_id().await?;
 
-    let hashed_id = session.get_hashed_id().await.ok_or(PhsError(
-        StatusCode::INTERNAL_SERVER_ERROR,
-        None,
-        "Error getting hashed session ID",
-    ))?;
+    let hashed_id = session
+        .get_hashed_id()
+        .await
+        .ok_or(PhsError::bug("Error getting hashed session ID"))?;
 
-    tracing::info!({ user = ?user.id, hashed_id }, "Successful login");
+    tracing::info!({ user = ?user_id, hashed_id }, "Successful login");
 
     Ok("Logged in".into())
 }
 
+/// What's kept in the session of a logged-in user, with the names of their
+/// groups fetched in the same round-trip.
+async fn auth_user(pool: &PgPool, username: &str) -> Result<AuthUser, PhsError> {
+    sqlx::query_as!(
+        AuthUser,
+        r#"
+        SELECT u.id, u.username, u.hash, u.role AS "role: _",
+          ARRAY(
+            SELECT g.group_name
+            FROM users_groups ug
+            JOIN groups g ON g.id = ug.group_id
+            WHERE ug.user_id = u.id
+            ORDER BY g.group_name
+          ) AS "groups!"
+        FROM users u
+        WHERE u.username = $1 AND u.deleted_at IS NULL
+        "#,
+        username
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(Into::into)
+}
+
+/// The session data [`login`] would store for the user with this ID, so tests
+/// can act as them without knowing their password.
+#[cfg(feature = "test_support")]
+pub(crate) async fn load_auth_user(pool: &PgPool, user_id: i32) -> Result<AuthUser, PhsError> {
+    let username = sqlx::query_scalar!("SELECT username FROM users WHERE id = $1", user_id)
+        .fetch_one(pool)
+        .await?;
+
+    auth_user(pool, &username).await
+}
+
+/// The logged-in user's ID.
+#[utoipa::path(
+    get,
+    path = "/auth/whoami",
+    tag = "auth",
+    responses((status = 200, body = i32), (status = 401, description = "Not logged in")),
+    security(("session" = []))
+)]
 async fn whoami(session: AuthSession) -> Result<Json<i32>, PhsError> {
     Ok(Json(session.auth_user.id))
 }
 
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CanParams {
+    user: i32,
+    permission: Permission,
+    /// A page or post to check it for, as `page:ID` or `post:ID`, going by the
+    /// department it's linked to.
+    #[param(value_type = Option<String>)]
+    resource: Option<Resource>,
+}
+
+/// What a permission can be given over only some departments of.
+#[derive(DeserializeFromStr, Clone, Copy, Debug)]
+enum Resource {
+    Page(i32),
+    Post(i32),
+}
+
+impl FromStr for Resource {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, id) = s.split_once(':').ok_or("Expected `page:ID` or `post:ID`")?;
+        let id = id.parse().map_err(|_| "Expected a number after the `:`")?;
+
+        match kind {
+            "page" => Ok(Self::Page(id)),
+            "post" => Ok(Self::Post(id)),
+            _ => Err("Only pages and posts can be checked"),
+        }
+    }
+}
+
+impl Resource {
+    const fn kind(self) -> &'static str {
+        match self {
+            Self::Page(_) => "page",
+            Self::Post(_) => "post",
+        }
+    }
+
+    /// The department it's linked to, as [`ScopedPermission`] handlers check.
+    ///
+    /// [`ScopedPermission`]: super::ScopedPermission
+    async fn department(self, pool: &PgPool) -> Result<Option<i32>, PhsError> {
+        let department = match self {
+            Self::Page(id) => {
+                sqlx::query_scalar!("SELECT department FROM pages WHERE id = $1", id)
+                    .fetch_one(pool)
+                    .await?
+            }
+            Self::Post(id) => {
+                sqlx::query_scalar!("SELECT department FROM posts WHERE id = $1", id)
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+
+        Ok(department)
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct CanResponse {
+    /// Whether they have it right now, across the site or in some departments,
+    /// or for the `resource` if one was given.
+    allowed: bool,
+    /// Whether it's one of the user's own permissions.
+    own: bool,
+    /// The groups they're in that give it to them, by name.
+    groups: Vec<String>,
+    /// The departments it's given to them over, by ID, if they don't have it
+    /// across the site.
+    departments: Vec<i32>,
+    /// Why, in words, to be shown as is.
+    reason: String,
+}
+
+/// Whether a user has a permission, and where it comes from, for working out
+/// why someone can or can't do something without them trying it.
+///
+/// Permissions don't depend on a user's role, or on who wrote a page or post.
+/// Those given over only some departments let them through
+/// [`ScopedPermission`](super::ScopedPermission) but not
+/// [`RequirePermission`], so are only for those departments' pages and posts,
+/// which `resource` checks for one of.
+#[utoipa::path(
+    get,
+    path = "/auth/can",
+    tag = "auth",
+    params(CanParams),
+    responses(
+        (status = 200, body = CanResponse),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 400, description = "The resource isn't `page:ID` or `post:ID`"),
+        (status = 404, description = "No user, or no page or post, has this ID"),
+    ),
+    security(("session" = []))
+)]
+async fn can(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Query(params): Query<CanParams>,
+) -> Result<Json<CanResponse>, PhsError> {
+    let sources = sqlx::query!(
+        r#"
+        SELECT u.deleted_at IS NOT NULL AS "deleted!",
+          $2 = ANY (u.permissions) AS "own!",
+          ARRAY(
+            SELECT g.group_name
+            FROM users_groups ug
+            JOIN groups g ON g.id = ug.group_id
+            WHERE ug.user_id = u.id AND $2 = ANY (g.permissions)
+            ORDER BY g.group_name
+          ) AS "groups!"
+        FROM users u
+        WHERE u.id = $1
+        "#,
+        params.user,
+        params.permission as Permission
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    // The same lookups as the guards, so the answer can't differ
+    let permissions = cache.get(&pool, params.user).await?.into_iter().collect();
+    let scope = permission_scope(&pool, params.user, permissions, params.permission).await?;
+    let departments = match &scope {
+        Some(PermissionScope::Departments(departments)) => departments.clone(),
+        Some(PermissionScope::Everywhere) | None => Vec::new(),
+    };
+
+    let mut department_names: Vec<String> = sqlx::query!(
+        "SELECT id, department FROM departments WHERE id = ANY($1)",
+        &departments
+    )
+    .fetch_all(&pool)
+    .await?
+    .into_iter()
+    .map(|row| row.department)
+    .collect();
+    department_names.sort();
+
+    // The department of the page or post asked about, if any, checked the
+    // same way its handlers do
+    let resource = match params.resource {
+        Some(resource) => Some((resource, resource.department(&pool).await?)),
+        None => None,
+    };
+    let allowed = match (&scope, resource) {
+        (Some(scope), Some((_, department))) => scope.covers(department),
+        (scope, _) => scope.is_some(),
+    };
+
+    let permission = params.permission;
+    let reason = match (sources.deleted, sources.own, sources.groups.as_slice()) {
+        (true, ..) => "They're in the trash, so have no permissions".to_owned(),
+        (false, false, []) if !department_names.is_empty() => {
+            let departments = format!(
+                "the {} {}",
+                department_names.join(", "),
+                if department_names.len() == 1 {
+                    "department"
+                } else {
+                    "departments"
+                },
+            );
+
+            match resource {
+                Some((resource, _)) if allowed => format!(
+                    "{permission} is only given to them over {departments}, which this {} is \
+                     linked to",
+                    resource.kind(),
+                ),
+                Some((resource, department)) => format!(
+                    "{permission} is only given to them over {departments}, and this {} is \
+                     linked to {}",
+                    resource.kind(),
+                    if department.is_some() {
+                        "another department"
+                    } else {
+                        "none"
+                    },
+                ),
+                None => format!(
+                    "{permission} is only given to them over {departments}, so not for \
+                     anything linked to other departments or none"
+                ),
+            }
+        }
+        (false, false, []) => format!("Neither they nor any of their groups have {permission}"),
+        (false, true, []) => format!("{permission} is one of their own permissions"),
+        (false, own, groups) => format!(
+            "{permission} is given by their {} {}{}",
+            if groups.len() == 1 { "group" } else { "groups" },
+            groups.join(", "),
+            if own {
+                ", as well as being one of their own"
+            } else {
+                ""
+            }
+        ),
+    };
+
+    Ok(Json(CanResponse {
+        allowed,
+        own: sources.own,
+        groups: sources.groups,
+        departments,
+        reason,
+    }))
+}
+
 /// Logout only the current session
+#[utoipa::path(
+    get,
+    path = "/auth/logout",
+    tag = "auth",
+    responses((status = 200), (status = 401, description = "Not logged in")),
+    security(("session" = []))
+)]
 async fn logout(mut auth_session: AuthSession) -> Result<(), PhsError> {
     auth_session.destroy().await
 }
 
+#[utoipa::path(
+    get,
+    path = "/auth/groups",
+    tag = "auth",
+    params(GroupQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<Group>),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+    ),
+    security(("session" = []))
+)]
 async fn get_groups(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
@@ -148,31 +438,53 @@ async fn get_groups(
     Query(cursor_options): Query<CursorOptions>,
     Query(query_string): Query<<Group as HasSqlxQueryString>::QueryString>,
 
-    Extension(pool): Extension<PgPool>,
+    Extension(db): Extension<Db>,
 ) -> Result<Json<CursorResponse<Group>>, PhsError> {
     crate::resources::paginated_query_as::<Group>(
+        "list_groups",
         r"SELECT id, group_name, permissions FROM groups",
         cursor_options,
         query_string,
-        &pool,
+        &db,
     )
     .await
     .map(|groups| Json(CursorResponse::new(groups)))
     .map_err(Into::into)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateGroupBody {
     group_name: String,
     permissions: Vec<Permission>,
 }
 
+impl Validate for CreateGroupBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("group_name", &self.group_name);
+        errors.max_chars("group_name", &self.group_name, MAX_GROUP_NAME_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/groups",
+    tag = "auth",
+    request_body = CreateGroupBody,
+    responses(
+        (status = 200, body = Group),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 422, description = "The name is empty or too long"),
+    ),
+    security(("session" = []))
+)]
 async fn create_group(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
 
     Extension(pool): Extension<PgPool>,
-    Json(body): Json<CreateGroupBody>,
+    Validated(body): Validated<CreateGroupBody>,
 ) -> Result<Json<Group>, PhsError> {
     sqlx::query_as!(
         Group,
@@ -190,21 +502,45 @@ async fn create_group(
     .map_err(Into::into)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct PutGroupBody {
     group_name: String,
     permissions: Vec<Permission>,
 }
 
+impl Validate for PutGroupBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("group_name", &self.group_name);
+        errors.max_chars("group_name", &self.group_name, MAX_GROUP_NAME_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/auth/group/{id}",
+    tag = "auth",
+    params(("id" = i32, Path)),
+    request_body = PutGroupBody,
+    responses(
+        (status = 200, body = Group),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 404, description = "No group has this ID"),
+        (status = 422, description = "The name is empty or too long"),
+    ),
+    security(("session" = []))
+)]
 async fn put_group(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
 
     Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
     Path(id): Path<i32>,
-    Json(body): Json<PutGroupBody>,
+    Validated(body): Validated<PutGroupBody>,
 ) -> Result<Json<Group>, PhsError> {
-    sqlx::query_as!(
+    let group = sqlx::query_as!(
         Group,
         r#"
         update groups
@@ -217,148 +553,629 @@ async fn put_group(
         id
     )
     .fetch_one(&pool)
-    .await
-    .map(Json)
-    .map_err(Into::into)
+    .await?;
+
+    cache.invalidate_all().await;
+
+    Ok(Json(group))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/auth/group/{id}",
+    tag = "auth",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+    ),
+    security(("session" = []))
+)]
 async fn delete_group(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
 
     Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
     Path(id): Path<i32>,
 ) -> Result<(), PhsError> {
     sqlx::query!(r#"delete from groups where id = $1"#, id)
-        .fetch_one(&pool)
+        .execute(&pool)
         .await?;
 
+    cache.invalidate_all().await;
+
     Ok(())
 }
 
-#[derive(Deserialize)]
+/// Everyone in a group.
+#[utoipa::path(
+    get,
+    path = "/auth/group/{id}/members",
+    tag = "auth",
+    params(("id" = i32, Path), GroupMemberQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<GroupMember>),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 404, description = "No group has this ID"),
+    ),
+    security(("session" = []))
+)]
+async fn get_group_members(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+    Query(cursor_options): Query<CursorOptions>,
+    Query(mut query_string): Query<<GroupMember as HasSqlxQueryString>::QueryString>,
+) -> Result<Json<CursorResponse<GroupMember>>, PhsError> {
+    sqlx::query_scalar!("SELECT id FROM groups WHERE id = $1", id)
+        .fetch_one(db.read())
+        .await?;
+
+    query_string.group_id = id;
+
+    crate::resources::paginated_query_as::<GroupMember>(
+        "list_group_members",
+        r"
+        SELECT * FROM (
+            SELECT u.id, u.username, u.name, ug.group_id
+            FROM users_groups ug
+            JOIN users u ON u.id = ug.user_id
+            WHERE u.deleted_at IS NULL
+        ) members
+        ",
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|members| Json(CursorResponse::new(members)))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct ManageGroupParams {
     user: i32,
     group: i32,
 }
 
+#[utoipa::path(
+    get,
+    path = "/auth/users/groups",
+    tag = "auth",
+    params(ManageGroupParams),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+    ),
+    security(("session" = []))
+)]
 async fn add_to_group(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
 
     params: Query<ManageGroupParams>,
     Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
 ) -> Result<(), PhsError> {
     sqlx::query!(
-        r#"INSERT INTO users_groups(user_id, group_id) VALUES($1, $2)"#,
+        r#"INSERT INTO users_groups(user_id, group_id) VALUES($1, $2) ON CONFLICT DO NOTHING"#,
         params.user,
         params.group
     )
-    .fetch_one(&pool)
+    .execute(&pool)
     .await?;
 
+    cache.invalidate_user(params.user).await;
+
     Ok(())
 }
 
+#[utoipa::path(
+    delete,
+    path = "/auth/users/groups",
+    tag = "auth",
+    params(ManageGroupParams),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+    ),
+    security(("session" = []))
+)]
 async fn delete_from_group(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
 
     params: Query<ManageGroupParams>,
     Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
 ) -> Result<(), PhsError> {
     sqlx::query!(
         r#"DELETE FROM users_groups WHERE user_id = $1 AND group_id = $2"#,
         params.user,
         params.group
     )
-    .fetch_one(&pool)
+    .execute(&pool)
     .await?;
 
+    cache.invalidate_user(params.user).await;
+
     Ok(())
 }
 
-async fn get_users_permissions(
+#[derive(Deserialize, ToSchema)]
+struct GroupMembersBody {
+    /// At most 500.
+    users: Vec<i32>,
+}
+
+impl Validate for GroupMembersBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.check(
+            self.users.len() <= MAX_BULK_IDS,
+            "users",
+            format!("Can't list more than {MAX_BULK_IDS} users"),
+        );
+        errors
+    }
+}
+
+/// Adds several users to a group at once. Any already in it are left as they
+/// are.
+#[utoipa::path(
+    post,
+    path = "/auth/groups/{id}/members",
+    tag = "auth",
+    params(("id" = i32, Path)),
+    request_body = GroupMembersBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 404, description = "No group has this ID, or some of the users don't exist"),
+        (status = 422, description = "Too many users are listed"),
+    ),
+    security(("session" = []))
+)]
+async fn add_group_members(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
 
     Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<GroupMembersBody>,
+) -> Result<(), PhsError> {
+    let mut tx = pool.begin().await?;
+
+    // Locked, so neither can be removed before the memberships are added
+    sqlx::query_scalar!("SELECT id FROM groups WHERE id = $1 FOR SHARE", id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    let found = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE id = ANY ($1) AND deleted_at IS NULL FOR SHARE",
+        &body.users
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    ensure_all_found("user", &body.users, &found)?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO users_groups (user_id, group_id)
+        SELECT user_id, $2 FROM unnest($1::int[]) AS user_id
+        ON CONFLICT DO NOTHING
+        "#,
+        &body.users,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    for user in found {
+        cache.invalidate_user(user).await;
+    }
+
+    Ok(())
+}
+
+/// Removes several users from a group at once. Any not in it are ignored.
+#[utoipa::path(
+    delete,
+    path = "/auth/groups/{id}/members",
+    tag = "auth",
+    params(("id" = i32, Path)),
+    request_body = GroupMembersBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 422, description = "Too many users are listed"),
+    ),
+    security(("session" = []))
+)]
+async fn delete_group_members(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<GroupMembersBody>,
+) -> Result<(), PhsError> {
+    let removed = sqlx::query_scalar!(
+        r#"DELETE FROM users_groups WHERE group_id = $1 AND user_id = ANY ($2) RETURNING user_id"#,
+        id,
+        &body.users
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    for user in removed {
+        cache.invalidate_user(user).await;
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, ToSchema)]
+struct UserGroupsBody {
+    /// At most 500.
+    groups: Vec<i32>,
+}
+
+impl Validate for UserGroupsBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.check(
+            self.groups.len() <= MAX_BULK_IDS,
+            "groups",
+            format!("Can't list more than {MAX_BULK_IDS} groups"),
+        );
+        errors
+    }
+}
+
+/// Puts a user in exactly these groups, taking them out of any others.
+#[utoipa::path(
+    put,
+    path = "/auth/users/{id}/groups",
+    tag = "auth",
+    params(("id" = i32, Path)),
+    request_body = UserGroupsBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 404, description = "No user has this ID, or some of the groups don't exist"),
+        (status = 422, description = "Too many groups are listed"),
+    ),
+    security(("session" = []))
+)]
+async fn put_user_groups(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<UserGroupsBody>,
+) -> Result<(), PhsError> {
+    let mut tx = pool.begin().await?;
+
+    // Held until the new memberships are in, as when adding members
+    sqlx::query_scalar!(
+        "SELECT id FROM users WHERE id = $1 AND deleted_at IS NULL FOR SHARE",
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let found = sqlx::query_scalar!(
+        "SELECT id FROM groups WHERE id = ANY ($1) FOR SHARE",
+        &body.groups
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    ensure_all_found("group", &body.groups, &found)?;
+
+    sqlx::query!(
+        "DELETE FROM users_groups WHERE user_id = $1 AND NOT (group_id = ANY ($2))",
+        id,
+        &body.groups
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO users_groups (user_id, group_id)
+        SELECT $1, group_id FROM unnest($2::int[]) AS group_id
+        ON CONFLICT DO NOTHING
+        "#,
+        id,
+        &body.groups
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    cache.invalidate_user(id).await;
+
+    Ok(())
+}
+
+#[derive(Deserialize, ToSchema)]
+struct DepartmentPermissionsBody {
+    /// At most 500, each of a permission that can be limited to departments:
+    /// `ManagePages`, `CreatePosts` or `EditPosts`.
+    permissions: Vec<DepartmentPermission>,
+}
+
+impl Validate for DepartmentPermissionsBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.check(
+            self.permissions.len() <= MAX_BULK_IDS,
+            "permissions",
+            format!("Can't list more than {MAX_BULK_IDS} permissions"),
+        );
+        errors.check(
+            self.permissions.iter().all(|p| p.permission.scopable()),
+            "permissions",
+            "Only ManagePages, CreatePosts and EditPosts can be limited to departments",
+        );
+        errors
+    }
+}
+
+/// The permissions a user has over only some departments' pages and posts,
+/// besides those they have across the site.
+#[utoipa::path(
+    get,
+    path = "/auth/users/{id}/departments",
+    tag = "auth",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Vec<DepartmentPermission>),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 404, description = "No user has this ID"),
+    ),
+    security(("session" = []))
+)]
+async fn get_department_permissions(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<DepartmentPermission>>, PhsError> {
+    sqlx::query_scalar!(
+        "SELECT id FROM users WHERE id = $1 AND deleted_at IS NULL",
+        id
+    )
+    .fetch_one(db.read())
+    .await?;
+
+    db.timed(
+        "get_department_permissions",
+        sqlx::query_as!(
+            DepartmentPermission,
+            r#"
+            SELECT department_id AS department, permission AS "permission: _"
+            FROM department_permissions
+            WHERE user_id = $1
+            ORDER BY department_id, permission
+            "#,
+            id
+        )
+        .fetch_all(db.read()),
+    )
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Gives a user exactly these permissions over departments' pages and posts,
+/// taking away any others they had by department.
+#[utoipa::path(
+    put,
+    path = "/auth/users/{id}/departments",
+    tag = "auth",
+    params(("id" = i32, Path)),
+    request_body = DepartmentPermissionsBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 404, description = "No user has this ID, or some of the departments don't exist"),
+        (status = 422, description = "Too many are listed, or some of the permissions can't be limited to departments"),
+    ),
+    security(("session" = []))
+)]
+async fn put_department_permissions(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<DepartmentPermissionsBody>,
+) -> Result<(), PhsError> {
+    let (departments, permissions): (Vec<i32>, Vec<Permission>) = body
+        .permissions
+        .iter()
+        .map(|p| (p.department, p.permission))
+        .unzip();
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query_scalar!(
+        "SELECT id FROM users WHERE id = $1 AND deleted_at IS NULL FOR SHARE",
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let found = sqlx::query_scalar!(
+        "SELECT id FROM departments WHERE id = ANY ($1) FOR SHARE",
+        &departments
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    ensure_all_found("department", &departments, &found)?;
+
+    sqlx::query!("DELETE FROM department_permissions WHERE user_id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO department_permissions (user_id, department_id, permission)
+        SELECT $1, * FROM unnest($2::int[], $3::permission[])
+        ON CONFLICT DO NOTHING
+        "#,
+        id,
+        &departments,
+        permissions as Vec<Permission>
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Fails with [`ErrorCode::NotFound`], naming every one of `wanted` that
+/// isn't among `found`.
+fn ensure_all_found(kind: &str, wanted: &[i32], found: &[i32]) -> Result<(), PhsError> {
+    let mut missing = wanted
+        .iter()
+        .filter(|id| !found.contains(id))
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    missing.sort_unstable();
+    missing.dedup();
+
+    Err(PhsError::client(
+        ErrorCode::NotFound,
+        format!(
+            "No {kind} exists with these IDs: {}",
+            missing
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    ))
+}
+
+/// Every user's groups and effective permissions. The `permissions` filter
+/// matches users with at least those, from anywhere.
+#[utoipa::path(
+    get,
+    path = "/auth/users/permissions",
+    tag = "auth",
+    params(UserPermissionsQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<UserPermissions>),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+    ),
+    security(("session" = []))
+)]
+async fn get_users_permissions(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
+
+    Extension(db): Extension<Db>,
 
     Query(cursor_options): Query<CursorOptions>,
     Query(query_string): Query<<UserPermissions as HasSqlxQueryString>::QueryString>,
 ) -> Result<Json<CursorResponse<UserPermissions>>, PhsError> {
     crate::resources::paginated_query_as::<UserPermissions>(
+        "list_users_permissions",
         r#"
-        SELECT
-            id, username, name,
-            COALESCE(G.group_ids, array[]::int[]) AS group_ids,
-            COALESCE(G.permissions, array[]::permission[]) AS permissions
-        FROM
-            users
-        LEFT JOIN (
-            SELECT
-    	          UG.user_id AS id,
-    	          ARRAY_AGG(DISTINCT G.id) AS group_ids,
-    	          ARRAY_AGG(element) AS permissions
-            FROM
-                users_groups UG
-            JOIN groups G ON G.id = UG.group_id,
-            (
-                SELECT
-    	              UNNEST(permissions) AS element
-                FROM
-        	          groups
-            )
-            GROUP BY UG.user_id
-        ) G
-        USING (id)
+        SELECT * FROM (
+            SELECT u.id, u.username, u.name,
+              COALESCE(m.group_ids, array[]::int[]) AS group_ids,
+              ARRAY(
+                SELECT DISTINCT p
+                FROM unnest(u.permissions || COALESCE(m.permissions, array[]::permission[])) AS p
+                ORDER BY p
+              ) AS permissions
+            FROM users u
+            LEFT JOIN LATERAL (
+                SELECT ARRAY_AGG(DISTINCT ug.group_id) AS group_ids,
+                  ARRAY_AGG(DISTINCT p) FILTER (WHERE p IS NOT NULL) AS permissions
+                FROM users_groups ug
+                JOIN groups g ON g.id = ug.group_id
+                LEFT JOIN LATERAL unnest(g.permissions) AS p ON true
+                WHERE ug.user_id = u.id
+            ) m ON true
+            WHERE u.deleted_at IS NULL
+        ) users_permissions
         "#,
         cursor_options,
         query_string,
-        &pool,
+        &db,
     )
     .await
     .map(|users_perms| Json(CursorResponse::new(users_perms)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/auth/users/permissions/{id}",
+    tag = "auth",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = UserPermissions),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 404, description = "No user has this ID"),
+    ),
+    security(("session" = []))
+)]
 async fn get_user_permissions(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
 
-    Extension(pool): Extension<PgPool>,
+    Extension(db): Extension<Db>,
     Path(id): Path<i32>,
 ) -> Result<Json<UserPermissions>, PhsError> {
-    sqlx::query_as!(
-        UserPermissions,
-        r#"
-        SELECT
-            id, username, name,
-            COALESCE(G.group_ids, array[]::int[]) AS "group_ids!: _",
-            COALESCE(G.permissions, array[]::permission[]) AS "permissions!: _"
-        FROM
-            users
-        LEFT JOIN (
-            SELECT
-    	          UG.user_id AS id,
-    	          ARRAY_AGG(DISTINCT G.id) AS group_ids,
-    	          ARRAY_AGG(perms_set) AS permissions
-            FROM
-                users_groups UG
-            JOIN groups G ON G.id = UG.group_id,
-            (
-                SELECT
-    	              UNNEST(permissions) AS perms_set
-                FROM
-        	          groups
-            )
-            WHERE UG.user_id = $1
-            GROUP BY UG.user_id
-        ) G
-        USING (id)
-        "#,
-        id
+    db.timed(
+        "get_user_permissions",
+        sqlx::query_as!(
+            UserPermissions,
+            r#"
+            SELECT u.id, u.username, u.name,
+              COALESCE(m.group_ids, array[]::int[]) AS "group_ids!: _",
+              ARRAY(
+                SELECT DISTINCT p
+                FROM unnest(u.permissions || COALESCE(m.permissions, array[]::permission[])) AS p
+                ORDER BY p
+              ) AS "permissions!: _"
+            FROM users u
+            LEFT JOIN LATERAL (
+                SELECT ARRAY_AGG(DISTINCT ug.group_id) AS group_ids,
+                  ARRAY_AGG(DISTINCT p) FILTER (WHERE p IS NOT NULL) AS permissions
+                FROM users_groups ug
+                JOIN groups g ON g.id = ug.group_id
+                LEFT JOIN LATERAL unnest(g.permissions) AS p ON true
+                WHERE ug.user_id = u.id
+            ) m ON true
+            WHERE u.id = $1 AND u.deleted_at IS NULL
+            "#,
+            id
+        )
+        .fetch_one(db.read()),
     )
-    .fetch_one(&pool)
     .await
     .map(Json)
     .map_err(Into::into)
@@ -1,21 +1,29 @@
-use axum::{
-    async_trait,
-    extract::FromRequestParts,
-    http::{request::Parts, StatusCode},
-};
+use std::sync::Arc;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
 
 use serde::{Deserialize, Serialize};
 
 use crate::sessions::Session;
-use crate::{error::PhsError, resources::Role};
+use crate::{
+    error::{ErrorCode, PhsError},
+    resources::Role,
+};
 
+mod cache;
 mod endpoints;
 mod permission;
 mod service;
 
-pub use endpoints::router;
-pub use permission::{Group, Permission, RequirePermission, UserPermissions};
-pub use service::AuthManagerLayer;
+pub use cache::PermissionCache;
+#[cfg(feature = "test_support")]
+pub(crate) use endpoints::load_auth_user;
+pub use endpoints::{openapi, router};
+pub use permission::{
+    CurrentPermissions, DepartmentPermission, Group, GroupMember, Permission, PermissionScope,
+    PermissionSet, RequirePermission, ScopedPermission, UserPermissions,
+};
+pub use service::{AuthManagerLayer, AuthUserId};
 
 #[async_trait]
 impl<S> FromRequestParts<S> for AuthSession {
@@ -25,19 +33,23 @@ impl<S> FromRequestParts<S> for AuthSession {
         parts
             .extensions
             .get::<Self>()
-            .ok_or(PhsError(
-                StatusCode::UNAUTHORIZED,
-                None,
-                "No auth session found in request extensions",
+            .ok_or(PhsError::client(
+                ErrorCode::NotLoggedIn,
+                "You need to log in first",
             ))
             .cloned()
     }
 }
 
+/// The logged-in user, loaded from their session once per request by the
+/// [`AuthManagerLayer`] and cheap to clone from there.
+///
+/// Their permissions aren't kept with the session, as they could change before
+/// it ends; [`CurrentPermissions`] and the guards look them up as they are now.
 #[derive(Clone)]
 pub struct AuthSession {
     session: Session,
-    auth_user: AuthUser,
+    auth_user: Arc<AuthUser>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -47,7 +59,6 @@ pub struct AuthUser {
     username: String,
     hash: String,
 
-    permissions: Vec<Permission>,
     role: Role,
     groups: Vec<String>,
 }
@@ -60,6 +71,11 @@ impl AuthUser {
     pub fn hash(&self) -> &str {
         &self.hash
     }
+
+    /// Teachers and admins, as opposed to students.
+    pub const fn is_staff(&self) -> bool {
+        matches!(self.role, Role::Teacher | Role::Admin)
+    }
 }
 
 impl<'a> AuthSession {
@@ -71,15 +87,15 @@ impl<'a> AuthSession {
         self.session.flush().await.map_err(Into::into)
     }
 
-    pub const fn data(&self) -> &AuthUser {
+    pub fn data(&self) -> &AuthUser {
         &self.auth_user
     }
 
     pub async fn from_session(session: Session) -> Result<Option<Self>, crate::sessions::Error> {
-        let s = session
-            .get()
-            .await?
-            .map(|auth_user| Self { session, auth_user });
+        let s = session.get().await?.map(|auth_user| Self {
+            auth_user: Arc::new(auth_user),
+            session,
+        });
 
         Ok(s)
     }
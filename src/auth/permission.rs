@@ -1,18 +1,17 @@
 use crate::{
-    error::PhsError,
+    db::RowCount,
+    error::{ErrorCode, PhsError},
     resources::{CursorPaginatable, HasSqlxQueryString, SqlxQueryString},
 };
-use axum::{
-    async_trait,
-    extract::FromRequestParts,
-    http::{request::Parts, StatusCode},
-};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, QueryBuilder};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use utoipa::{IntoParams, ToSchema};
 
-use super::AuthSession;
+use super::{AuthSession, PermissionCache};
 
-#[derive(PartialEq, Eq, Clone, Copy, Deserialize, Serialize, Debug, sqlx::Type)]
+#[derive(PartialEq, Eq, Clone, Copy, Deserialize, Serialize, Debug, sqlx::Type, ToSchema)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
 #[sqlx(type_name = "permission", rename_all = "snake_case")]
 pub enum Permission {
     EditDepartments = 0,
@@ -22,6 +21,20 @@ pub enum Permission {
     ManageUsers,
     ManagePermissions,
     ManagePages,
+    ManageMedia,
+    ManageSettings,
+    ManageEvents,
+    ManageVacancies,
+    ManageDocuments,
+    ManageAnnouncements,
+    ManageAdmissions,
+    ManageForms,
+    ManagePolls,
+    ManageGovernors,
+    ManageFaqs,
+    /// Approving pages and posts for publishing, when
+    /// [`FeatureToggles::approval`](crate::FeatureToggles::approval) is on.
+    ApproveContent,
 }
 
 impl std::fmt::Display for Permission {
@@ -37,11 +50,40 @@ impl std::fmt::Display for Permission {
                 Self::ManageUsers => "ManageUsers",
                 Self::ManagePermissions => "ManagePermissions",
                 Self::ManagePages => "ManagePages",
+                Self::ManageMedia => "ManageMedia",
+                Self::ManageSettings => "ManageSettings",
+                Self::ManageEvents => "ManageEvents",
+                Self::ManageVacancies => "ManageVacancies",
+                Self::ManageDocuments => "ManageDocuments",
+                Self::ManageAnnouncements => "ManageAnnouncements",
+                Self::ManageAdmissions => "ManageAdmissions",
+                Self::ManageForms => "ManageForms",
+                Self::ManagePolls => "ManagePolls",
+                Self::ManageGovernors => "ManageGovernors",
+                Self::ManageFaqs => "ManageFaqs",
+                Self::ApproveContent => "ApproveContent",
             }
         )
     }
 }
 
+impl Permission {
+    /// Whether it can be given over only some departments' pages and posts,
+    /// as [`ScopedPermission`] checks for.
+    #[must_use]
+    pub const fn scopable(self) -> bool {
+        matches!(
+            self,
+            Self::ManagePages | Self::CreatePosts | Self::EditPosts
+        )
+    }
+
+    /// Every permission there is.
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..).map_while(|i| Self::try_from(i).ok())
+    }
+}
+
 impl TryFrom<u8> for Permission {
     type Error = ();
     fn try_from(value: u8) -> Result<Self, Self::Error> {
@@ -53,11 +95,106 @@ impl TryFrom<u8> for Permission {
             4 => Ok(Self::ManageUsers),
             5 => Ok(Self::ManagePermissions),
             6 => Ok(Self::ManagePages),
+            7 => Ok(Self::ManageMedia),
+            8 => Ok(Self::ManageSettings),
+            9 => Ok(Self::ManageEvents),
+            10 => Ok(Self::ManageVacancies),
+            11 => Ok(Self::ManageDocuments),
+            12 => Ok(Self::ManageAnnouncements),
+            13 => Ok(Self::ManageAdmissions),
+            14 => Ok(Self::ManageForms),
+            15 => Ok(Self::ManagePolls),
+            16 => Ok(Self::ManageGovernors),
+            17 => Ok(Self::ManageFaqs),
+            18 => Ok(Self::ApproveContent),
             _ => Err(()),
         }
     }
 }
 
+/// A set of permissions as one bit each, so checking one doesn't search a
+/// list.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct PermissionSet(u64);
+
+impl PermissionSet {
+    pub const fn contains(self, permission: Permission) -> bool {
+        self.0 & 1 << permission as u8 != 0
+    }
+}
+
+impl FromIterator<Permission> for PermissionSet {
+    fn from_iter<I: IntoIterator<Item = Permission>>(iter: I) -> Self {
+        Self(
+            iter.into_iter()
+                .fold(0, |bits, permission| bits | 1 << permission as u8),
+        )
+    }
+}
+
+/// The logged-in user's permissions as they are now, going by the
+/// [`PermissionCache`], for handlers that show more to some users rather than
+/// turning others away. Empty for anyone not logged in.
+///
+/// Kept in the request's extensions once looked up, so any other extractor on
+/// the same request doesn't look them up again.
+#[derive(Clone, Copy)]
+pub struct CurrentPermissions(pub PermissionSet);
+
+impl CurrentPermissions {
+    pub const fn contains(self, permission: Permission) -> bool {
+        self.0.contains(permission)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CurrentPermissions {
+    type Rejection = PhsError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if parts.extensions.get::<AuthSession>().is_none() {
+            return Ok(Self(PermissionSet::default()));
+        }
+
+        let (_, permissions) = current_permissions(parts).await?;
+        Ok(Self(permissions))
+    }
+}
+
+/// The logged-in user's ID and their permissions as they are now, looked up
+/// once per request.
+async fn current_permissions(parts: &mut Parts) -> Result<(i32, PermissionSet), PhsError> {
+    let user_id = parts
+        .extensions
+        .get::<AuthSession>()
+        .ok_or(PhsError::client(
+            ErrorCode::NotLoggedIn,
+            "You need to log in first",
+        ))?
+        .data()
+        .id();
+
+    if let Some(CurrentPermissions(permissions)) = parts.extensions.get::<CurrentPermissions>() {
+        return Ok((user_id, *permissions));
+    }
+
+    let cache = parts
+        .extensions
+        .get::<PermissionCache>()
+        .ok_or(PhsError::bug("Permission cache missing from extensions"))?;
+    let pool = parts
+        .extensions
+        .get::<PgPool>()
+        .ok_or(PhsError::bug("Database pool missing from extensions"))?;
+
+    let permissions = cache.get(pool, user_id).await?.into_iter().collect();
+    parts.extensions.insert(CurrentPermissions(permissions));
+
+    Ok((user_id, permissions))
+}
+
+/// Rejects requests from users without the permission, as it is now rather
+/// than as it was when they logged in, going by the [`PermissionCache`].
 pub struct RequirePermission<const PERMISSION: u8>;
 
 #[async_trait]
@@ -65,26 +202,135 @@ impl<S, const PERMISSION: u8> FromRequestParts<S> for RequirePermission<PERMISSI
     type Rejection = PhsError;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let auth_session = parts.extensions.get::<AuthSession>().ok_or(PhsError(
-            StatusCode::UNAUTHORIZED,
-            None,
-            "Could not find AuthSession in request extensions",
-        ))?;
-
         let required_permission: Permission = PERMISSION
             .try_into()
             .expect("Unexpected integer for Permission in RequirePermission");
 
-        auth_session
-            .data()
-            .permissions
-            .contains(&required_permission)
+        let (_, permissions) = current_permissions(parts).await?;
+
+        permissions
+            .contains(required_permission)
             .then_some(Self)
-            .ok_or(PhsError(StatusCode::FORBIDDEN, None, "Missing permission"))
+            .ok_or(PhsError::client(
+                ErrorCode::MissingPermission,
+                "Missing permission",
+            ))
+    }
+}
+
+/// Where the logged-in user has a permission.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PermissionScope {
+    /// Across the whole site, as [`RequirePermission`] checks for.
+    Everywhere,
+    /// Only over pages and posts linked to these departments.
+    Departments(Vec<i32>),
+}
+
+impl PermissionScope {
+    /// Whether this covers an item linked to `department`. Items linked to
+    /// no department are only covered [`Self::Everywhere`].
+    #[must_use]
+    pub fn covers(&self, department: Option<i32>) -> bool {
+        match (self, department) {
+            (Self::Everywhere, _) => true,
+            (Self::Departments(departments), Some(department)) => departments.contains(&department),
+            (Self::Departments(_), None) => false,
+        }
+    }
+
+    /// Fails with [`ErrorCode::MissingPermission`] unless this
+    /// [covers](Self::covers) `department`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if it doesn't.
+    pub fn check(&self, department: Option<i32>) -> Result<(), PhsError> {
+        if self.covers(department) {
+            return Ok(());
+        }
+
+        Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            match department {
+                Some(_) => "Your permission doesn't cover this department",
+                None => "Your permission only covers items linked to your departments",
+            },
+        ))
+    }
+}
+
+/// Lets through users with the permission across the site, as
+/// [`RequirePermission`] does, or only in some departments, leaving the
+/// handler to [check](PermissionScope::check) the item it's changing.
+///
+/// Only the permissions that [`Permission::scopable`] allows are ever given
+/// by department.
+pub struct ScopedPermission<const PERMISSION: u8>(pub PermissionScope);
+
+#[async_trait]
+impl<S, const PERMISSION: u8> FromRequestParts<S> for ScopedPermission<PERMISSION> {
+    type Rejection = PhsError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let required_permission: Permission = PERMISSION
+            .try_into()
+            .expect("Unexpected integer for Permission in ScopedPermission");
+
+        let (user_id, permissions) = current_permissions(parts).await?;
+        let pool = parts
+            .extensions
+            .get::<PgPool>()
+            .ok_or(PhsError::bug("Database pool missing from extensions"))?;
+
+        permission_scope(pool, user_id, permissions, required_permission)
+            .await?
+            .map(Self)
+            .ok_or(PhsError::client(
+                ErrorCode::MissingPermission,
+                "Missing permission",
+            ))
     }
 }
 
-#[derive(Clone, FromRow, Serialize, Deserialize, Debug)]
+/// Where a user has a permission, given their `permissions` across the site,
+/// or `None` if nowhere. Departments are only looked up if it isn't one of
+/// them.
+pub(crate) async fn permission_scope(
+    pool: &PgPool,
+    user_id: i32,
+    permissions: PermissionSet,
+    permission: Permission,
+) -> Result<Option<PermissionScope>, PhsError> {
+    if permissions.contains(permission) {
+        return Ok(Some(PermissionScope::Everywhere));
+    }
+
+    let departments = sqlx::query_scalar!(
+        r#"
+        SELECT dp.department_id
+        FROM department_permissions dp
+        JOIN users u ON u.id = dp.user_id
+        WHERE dp.user_id = $1 AND dp.permission = $2 AND u.deleted_at IS NULL
+        ORDER BY dp.department_id
+        "#,
+        user_id,
+        permission as Permission
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok((!departments.is_empty()).then_some(PermissionScope::Departments(departments)))
+}
+
+/// A permission a user has only over one department's pages or posts.
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug, ToSchema)]
+pub struct DepartmentPermission {
+    pub department: i32,
+    pub permission: Permission,
+}
+
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug, ToSchema)]
 pub struct Group {
     pub id: i32,
     pub group_name: String,
@@ -95,7 +341,8 @@ impl HasSqlxQueryString for Group {
     type QueryString = GroupQueryString;
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GroupQueryString {
     id: Option<i32>,
     group_name: Option<String>,
@@ -136,11 +383,72 @@ impl CursorPaginatable for Group {
     }
 }
 
-#[derive(Clone, FromRow, Serialize, Deserialize, Debug)]
+/// A user in a group, as listed by `GET /auth/group/{id}/members`.
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug, ToSchema)]
+pub struct GroupMember {
+    pub id: i32,
+    pub username: String,
+    pub name: String,
+}
+
+impl HasSqlxQueryString for GroupMember {
+    type QueryString = GroupMemberQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GroupMemberQueryString {
+    /// Taken from the path rather than the query.
+    #[serde(skip)]
+    pub(crate) group_id: i32,
+    username: Option<String>,
+    name: Option<String>,
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for GroupMemberQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        builder.push(" AND group_id = ");
+        builder.push_bind(self.group_id);
+
+        if let Some(ref username) = self.username {
+            builder.push(" AND username LIKE ");
+            builder.push_bind(username);
+        }
+
+        if let Some(ref name) = self.name {
+            builder.push(" AND name LIKE ");
+            builder.push_bind(name);
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "username" | "name") = field.as_str() {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for GroupMember {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+#[derive(Clone, FromRow, Serialize, Deserialize, Debug, ToSchema)]
 pub struct UserPermissions {
     pub id: i32,
     pub username: String,
     pub name: String,
+    /// Their own together with their groups', each once.
     pub permissions: Vec<Permission>,
     pub group_ids: Vec<i32>,
 }
@@ -149,7 +457,8 @@ impl HasSqlxQueryString for UserPermissions {
     type QueryString = UserPermissionsQueryString;
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct UserPermissionsQueryString {
     username: Option<String>,
     name: Option<String>,
@@ -186,7 +495,7 @@ impl SqlxQueryString for UserPermissionsQueryString {
             return false;
         };
 
-        if let s @ ("id" | "group_name") = field.as_str() {
+        if let s @ ("id" | "username" | "name") = field.as_str() {
             builder.push(s);
             order.append_to(builder);
             true
@@ -201,3 +510,5 @@ impl CursorPaginatable for UserPermissions {
         self.id
     }
 }
+
+impl RowCount for UserPermissions {}
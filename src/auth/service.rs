@@ -1,7 +1,6 @@
 use crate::sessions::{CookieController, Session, SessionManager, SessionManagerLayer};
 use axum::{
     extract::Request,
-    http::StatusCode,
     response::{IntoResponse, Response},
 };
 use std::{
@@ -18,6 +17,11 @@ use crate::error::PhsError;
 
 use super::AuthSession;
 
+/// Left in the response extensions for requests made while logged in, so
+/// outer layers such as the access log can tell who made them.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUserId(pub i32);
+
 /// A middleware that provides [`AuthSession`] as a request extension.
 #[derive(Clone)]
 pub struct AuthManager<S> {
@@ -60,27 +64,30 @@ where
         Box::pin(
             async move {
                 let Some(session) = req.extensions().get::<Session>().cloned() else {
-                    return Ok(PhsError(
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        None,
-                        "Session not found in request extensions",
-                    )
-                    .into_response());
+                    return Ok(
+                        PhsError::bug("Session not found in request extensions").into_response()
+                    );
                 };
 
-                match AuthSession::from_session(session).await {
+                let user = match AuthSession::from_session(session).await {
                     Ok(Some(auth_session)) => {
+                        let user = AuthUserId(auth_session.data().id());
                         req.extensions_mut().insert(auth_session);
+                        Some(user)
                     }
                     Err(error) => {
                         tracing::error!(?error, "Error when converting Session to AuthSession");
 
                         return Ok(Into::<PhsError>::into(error).into_response());
                     }
-                    _ => {}
-                }
+                    Ok(None) => None,
+                };
 
-                inner.call(req).await
+                let mut res = inner.call(req).await?;
+                if let Some(user) = user {
+                    res.extensions_mut().insert(user);
+                }
+                Ok(res)
             }, // TODO Span here without wrapping all errors
                //.instrument(span),
         )
@@ -0,0 +1,240 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// Who made a request and how, as seen by the first proxy in front of us that
+/// we don't trust, or by us if there isn't one.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub ip: IpAddr,
+    /// Whether the client connected over HTTPS, even if a proxy then forwarded
+    /// the request over plain HTTP.
+    pub https: bool,
+    /// The `Host` the client asked for, if a proxy passed it on.
+    pub forwarded_host: Option<String>,
+}
+
+/// An address, or a range of them in CIDR notation, e.g. `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl IpRange {
+    pub fn parse(range: &str) -> Option<Self> {
+        let (network, prefix) = match range.split_once('/') {
+            Some((network, prefix)) => (network.parse::<IpAddr>().ok()?, prefix.parse().ok()?),
+            None => {
+                let network = range.parse::<IpAddr>().ok()?;
+                (network, if network.is_ipv4() { 32 } else { 128 })
+            }
+        };
+
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        (prefix <= bits).then_some(Self { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        // So an IPv4 proxy still matches when we're listening on `::`
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The proxies whose forwarding headers are believed, and whether we're
+/// serving HTTPS ourselves.
+pub struct TrustedProxies {
+    ranges: Vec<IpRange>,
+    https: bool,
+}
+
+impl TrustedProxies {
+    /// Ranges that don't parse are skipped; `ServerConfig::validate` has
+    /// already rejected them.
+    pub fn new(ranges: &[String], https: bool) -> Arc<Self> {
+        Arc::new(Self {
+            ranges: ranges
+                .iter()
+                .map(String::as_str)
+                .filter_map(IpRange::parse)
+                .collect(),
+            https,
+        })
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(ip))
+    }
+
+    /// Walks back along the chain of proxies from the one that connected to
+    /// us, stopping at the first address that isn't trusted. Anything further
+    /// along could have been made up by the client.
+    pub fn client_info(&self, peer: IpAddr, headers: &HeaderMap) -> ClientInfo {
+        let mut client = ClientInfo {
+            ip: peer,
+            https: self.https,
+            forwarded_host: None,
+        };
+        if !self.trusts(peer) {
+            return client;
+        }
+
+        for hop in hops(headers).into_iter().rev() {
+            // Obfuscated or unknown, so there's nothing more to go on
+            let Some(ip) = hop.ip else {
+                break;
+            };
+
+            client.ip = ip;
+            if let Some(proto) = hop.proto {
+                client.https = proto.eq_ignore_ascii_case("https");
+            }
+            if hop.host.is_some() {
+                client.forwarded_host = hop.host;
+            }
+
+            if !self.trusts(ip) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+/// One proxy's record of where it got a request from.
+#[derive(Default)]
+struct Hop {
+    ip: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+/// Every hop recorded in `Forwarded`, or failing that in the `X-Forwarded-*`
+/// headers, nearest the client first.
+fn hops(headers: &HeaderMap) -> Vec<Hop> {
+    let forwarded = list(headers, &axum::http::header::FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .map(String::as_str)
+            .map(forwarded_hop)
+            .collect();
+    }
+
+    let ips = list(headers, &X_FORWARDED_FOR);
+    let protos = list(headers, &X_FORWARDED_PROTO);
+    let hosts = list(headers, &X_FORWARDED_HOST);
+
+    // If every proxy appended to every header they line up, but usually only
+    // the first proxy sets the protocol and host, for the client's request
+    let aligned = |values: &[String], i: usize| {
+        if values.len() == ips.len() {
+            values.get(i).cloned()
+        } else {
+            values.first().cloned()
+        }
+    };
+
+    ips.iter()
+        .enumerate()
+        .map(|(i, ip)| Hop {
+            ip: parse_ip(ip),
+            proto: aligned(&protos, i),
+            host: aligned(&hosts, i),
+        })
+        .collect()
+}
+
+/// The comma-separated values of every instance of a header, in order.
+fn list(headers: &HeaderMap, name: &HeaderName) -> Vec<String> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Parses one element of `Forwarded`, e.g. `for="[2001:db8::1]:4711";proto=https`.
+fn forwarded_hop(element: &str) -> Hop {
+    let mut hop = Hop::default();
+
+    for pair in element.split(';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "for" => hop.ip = parse_ip(value),
+            "proto" => hop.proto = Some(value.to_owned()),
+            "host" => hop.host = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+
+    hop
+}
+
+/// Accepts addresses with or without a port, and IPv6 ones in brackets.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            value
+                .strip_prefix('[')
+                .and_then(|v| v.strip_suffix(']'))
+                .and_then(|v| v.parse().ok())
+        })
+}
+
+/// Works out the [`ClientInfo`] for each request and adds it to the request's
+/// extensions.
+pub async fn client_info(
+    State(proxies): State<Arc<TrustedProxies>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    // Only missing if the server wasn't started with connect info
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or(IpAddr::from([0, 0, 0, 0]), |info| info.0.ip());
+
+    let client = proxies.client_info(peer, req.headers());
+    req.extensions_mut().insert(client);
+
+    next.run(req).await
+}
@@ -0,0 +1,319 @@
+//! Notes left on pages and posts by those editing or reviewing them, such as
+//! "this date's wrong", kept inside the CMS and never shown publicly.
+//!
+//! Anyone who can change or approve a kind of item can read, leave and
+//! resolve comments on it. Only a comment's author can reword or delete it.
+
+use axum::{
+    extract::{Path, Query},
+    routing::{get, patch},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, PermissionCache},
+    error::{ErrorCode, PhsError},
+    review::{require_reviewer, ReviewKind},
+    validation::{FieldErrors, Validate, Validated},
+};
+
+/// Longest a comment can be.
+const MAX_BODY_LENGTH: usize = 2000;
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/reviews/:kind/:id/comments",
+            get(get_comments).post(post_comment),
+        )
+        .route("/comments/:id", patch(patch_comment).delete(delete_comment))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_comments, post_comment, patch_comment, delete_comment))]
+struct CommentApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    CommentApi::openapi()
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct Comment {
+    id: i32,
+    /// Unless they've since been removed.
+    author: Option<i32>,
+    body: String,
+    resolved: bool,
+    resolved_by: Option<i32>,
+    #[serde(with = "time::serde::iso8601")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CommentParams {
+    /// Only resolved comments, or only unresolved ones.
+    resolved: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct CommentBody {
+    body: String,
+}
+
+impl Validate for CommentBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("body", &self.body);
+        errors.max_chars("body", &self.body, MAX_BODY_LENGTH);
+        errors
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct CommentPatch {
+    /// Only the author can reword a comment.
+    body: Option<String>,
+    resolved: Option<bool>,
+}
+
+impl Validate for CommentPatch {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        if let Some(body) = &self.body {
+            errors.not_blank("body", body);
+            errors.max_chars("body", body, MAX_BODY_LENGTH);
+        }
+        errors
+    }
+}
+
+/// The comment with this ID's item kind and author, failing with
+/// [`ErrorCode::NotFound`] if there's no such comment.
+async fn find_comment(pool: &PgPool, id: i32) -> Result<(ReviewKind, Option<i32>), PhsError> {
+    let comment = sqlx::query!(
+        "SELECT page_id, author FROM editorial_comments WHERE id = $1",
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let kind = if comment.page_id.is_some() {
+        ReviewKind::Page
+    } else {
+        ReviewKind::Post
+    };
+
+    Ok((kind, comment.author))
+}
+
+/// Comments on the item, oldest first.
+#[utoipa::path(
+    get,
+    path = "/reviews/{kind}/{id}/comments",
+    tag = "reviews",
+    params(("kind" = ReviewKind, Path), ("id" = i32, Path), CommentParams),
+    responses(
+        (status = 200, body = Vec<Comment>),
+        (status = 403, description = "Can't change or approve this kind of item"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, cache, auth_session))]
+async fn get_comments(
+    auth_session: AuthSession,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Path((kind, id)): Path<(ReviewKind, i32)>,
+    Query(params): Query<CommentParams>,
+) -> Result<Json<Vec<Comment>>, PhsError> {
+    require_reviewer(&pool, &cache, &auth_session, kind).await?;
+
+    let comments = match kind {
+        ReviewKind::Page => {
+            sqlx::query_as!(
+                Comment,
+                "SELECT id, author, body, resolved, resolved_by, created_at, updated_at FROM editorial_comments WHERE page_id = $1 AND ($2::bool IS NULL OR resolved = $2) ORDER BY id",
+                id,
+                params.resolved
+            )
+            .fetch_all(&pool)
+            .await?
+        }
+        ReviewKind::Post => {
+            sqlx::query_as!(
+                Comment,
+                "SELECT id, author, body, resolved, resolved_by, created_at, updated_at FROM editorial_comments WHERE post_id = $1 AND ($2::bool IS NULL OR resolved = $2) ORDER BY id",
+                id,
+                params.resolved
+            )
+            .fetch_all(&pool)
+            .await?
+        }
+    };
+
+    Ok(Json(comments))
+}
+
+#[utoipa::path(
+    post,
+    path = "/reviews/{kind}/{id}/comments",
+    tag = "reviews",
+    params(("kind" = ReviewKind, Path), ("id" = i32, Path)),
+    request_body = CommentBody,
+    responses(
+        (status = 200, body = Comment),
+        (status = 403, description = "Can't change or approve this kind of item"),
+        (status = 404, description = "No page or post has this ID, or it's archived or in the trash"),
+        (status = 422, description = "The comment is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, cache, auth_session))]
+async fn post_comment(
+    auth_session: AuthSession,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Path((kind, id)): Path<(ReviewKind, i32)>,
+    Validated(body): Validated<CommentBody>,
+) -> Result<Json<Comment>, PhsError> {
+    require_reviewer(&pool, &cache, &auth_session, kind).await?;
+
+    let (page, post) = match kind {
+        ReviewKind::Page => {
+            sqlx::query_scalar!(
+                "SELECT id FROM pages WHERE id = $1 AND modified <> 'archived'::page_status",
+                id
+            )
+            .fetch_one(&pool)
+            .await?;
+            (Some(id), None)
+        }
+        ReviewKind::Post => {
+            sqlx::query_scalar!(
+                "SELECT id FROM posts WHERE id = $1 AND deleted_at IS NULL",
+                id
+            )
+            .fetch_one(&pool)
+            .await?;
+            (None, Some(id))
+        }
+    };
+
+    sqlx::query_as!(
+        Comment,
+        "INSERT INTO editorial_comments (page_id, post_id, author, body) VALUES ($1, $2, $3, $4) RETURNING id, author, body, resolved, resolved_by, created_at, updated_at",
+        page,
+        post,
+        auth_session.data().id(),
+        body.body
+    )
+    .fetch_one(&pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Rewords a comment, or marks it resolved or not.
+#[utoipa::path(
+    patch,
+    path = "/comments/{id}",
+    tag = "reviews",
+    params(("id" = i32, Path)),
+    request_body = CommentPatch,
+    responses(
+        (status = 200, body = Comment),
+        (status = 403, description = "Can't change or approve the kind of item it's on, or rewording someone else's comment"),
+        (status = 404, description = "No comment has this ID"),
+        (status = 422, description = "The comment is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, cache, auth_session))]
+async fn patch_comment(
+    auth_session: AuthSession,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Path(id): Path<i32>,
+    Validated(patch): Validated<CommentPatch>,
+) -> Result<Json<Comment>, PhsError> {
+    let user = auth_session.data().id();
+
+    let (kind, author) = find_comment(&pool, id).await?;
+    require_reviewer(&pool, &cache, &auth_session, kind).await?;
+
+    if patch.body.is_some() && author != Some(user) {
+        return Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Only whoever left a comment can reword it",
+        ));
+    }
+
+    sqlx::query_as!(
+        Comment,
+        r"
+        UPDATE editorial_comments
+        SET body = COALESCE($2, body),
+          updated_at = CASE WHEN $2::text IS NULL THEN updated_at ELSE now() END,
+          resolved = COALESCE($3, resolved),
+          resolved_by = CASE WHEN $3 THEN $4 WHEN NOT $3 THEN NULL ELSE resolved_by END
+        WHERE id = $1
+        RETURNING id, author, body, resolved, resolved_by, created_at, updated_at
+        ",
+        id,
+        patch.body,
+        patch.resolved,
+        user
+    )
+    .fetch_one(&pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/comments/{id}",
+    tag = "reviews",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Deleting someone else's comment"),
+        (status = 404, description = "No comment has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, cache, auth_session))]
+async fn delete_comment(
+    auth_session: AuthSession,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    let (kind, author) = find_comment(&pool, id).await?;
+    require_reviewer(&pool, &cache, &auth_session, kind).await?;
+
+    if author != Some(auth_session.data().id()) {
+        return Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Only whoever left a comment can delete it",
+        ));
+    }
+
+    sqlx::query!("DELETE FROM editorial_comments WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
@@ -1,30 +1,602 @@
-use std::path::PathBuf;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
 
+use axum::http::{uri::Authority, HeaderName, Method, Uri};
 use serde::{Deserialize, Serialize};
 
+use crate::client_ip::IpRange;
+
+/// Environment variables starting with this override options from the config
+/// file, with `__` between the names of nested options, e.g. `PHS_HTTP_PORT`
+/// or `PHS_CACHE__PAGES`.
+pub const ENV_PREFIX: &str = "PHS_";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("Couldn't read {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("Invalid configuration: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid value for {0}: expected a name like {ENV_PREFIX}CACHE__PAGES")]
+    EnvName(String),
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+/// Options fixed for the life of the server.
+///
+/// Loaded from `config.toml`, then [environment variables](ENV_PREFIX), then
+/// command line flags, each overriding the last. Anything left unset takes its
+/// default; see `config.example.toml` for every option.
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ServerConfig {
+    /// The public origin of the site, e.g. `https://www.example.sch.uk`, used
+    /// wherever absolute URLs are needed.
+    pub site_url: String,
+    /// A template from `pages/templates` to serve in place of unpublished
+    /// pages. Unpublished pages 404 if this isn't set.
+    pub unavailable_template: Option<String>,
+    /// The address to listen on, with `http_port` and `https_port`.
+    pub bind_address: IpAddr,
     pub http_port: u16,
     pub https_port: u16,
+    /// Addresses to listen for HTTP on, in place of `bind_address` and
+    /// `http_port`, e.g. to serve both IPv4 and IPv6.
+    pub http_listen: Vec<SocketAddr>,
+    /// As `http_listen`, but for HTTPS.
+    pub https_listen: Vec<SocketAddr>,
     pub tls_enabled: bool,
+    #[serde(rename = "tls")]
     pub tls_options: Option<TlsOptions>,
+    /// Keep sessions, cached permissions and password reset tokens in Redis,
+    /// at `REDIS_URL`. Without it they're kept in this process, so only
+    /// Postgres is needed, but they're lost on restart and not shared between
+    /// servers; it's meant for development.
+    pub redis: bool,
+    /// Get certificates from an ACME CA, in place of `[tls]`.
+    pub acme: Option<AcmeConfig>,
+    /// Where pages and media are kept.
+    pub storage: StorageConfig,
+    pub cache: CacheConfig,
+    pub response_cache: ResponseCacheConfig,
+    pub database: DatabaseConfig,
+    pub limits: LimitsConfig,
+    pub error_pages: ErrorPages,
+    pub access_log: AccessLogConfig,
+    pub compression: CompressionConfig,
+    /// Send email over SMTP. Nothing is sent if this isn't set.
+    pub mail: Option<MailConfig>,
+    /// Check uploads for viruses before they're stored. Nothing is checked if
+    /// this isn't set.
+    pub scanner: Option<ScannerConfig>,
+    /// Where the settings changed through `/v1/settings` are saved.
+    pub settings_path: PathBuf,
+    /// The key session cookies are signed with, with the `signed_cookies`
+    /// feature. Made on first start if it's missing.
+    pub cookie_key_path: PathBuf,
+    /// The key share links to drafts are signed with. Made on first start if
+    /// it's missing.
+    pub share_key_path: PathBuf,
+    /// What gets logged, in `RUST_LOG` syntax.
+    pub log_filter: String,
+    /// Also log to daily files, for when nothing is collecting stdout.
+    pub log_file: Option<LogFileConfig>,
+    /// Save an export of the content to storage on a schedule. Nothing is
+    /// backed up if this isn't set.
+    pub backup: Option<BackupConfig>,
+    pub admissions: AdmissionsConfig,
+    pub trash: TrashConfig,
+    pub retention: RetentionConfig,
+    /// Where new posts are shared when they're published.
+    pub social: Vec<SocialChannel>,
+    pub cors: CorsConfig,
+    /// A hostname of its own for the admin API, e.g. `admin.example.sch.uk`.
+    /// Any other host only serves the site, media, the API's reads and the
+    /// writes visitors make, such as submitting forms.
+    pub admin_host: Option<String>,
+    /// Proxies in front of the server, as addresses or CIDR ranges, e.g.
+    /// `10.0.0.0/8`. Their `Forwarded` and `X-Forwarded-*` headers are used
+    /// for the client's address and scheme; anyone else's are ignored.
+    pub trusted_proxies: Vec<String>,
     #[cfg(debug_assertions)]
     pub use_tokio_console: bool,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct TlsOptions {
     pub key_path: PathBuf,
     pub cert_path: PathBuf,
 }
 
+/// Certificates issued and renewed automatically, proving control of each
+/// domain by answering HTTP-01 challenges on the HTTP listener. The CA has to
+/// be able to reach it on port 80 at every one of `domains`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AcmeConfig {
+    /// Names to put on the certificate, e.g. `www.example.sch.uk`.
+    pub domains: Vec<String>,
+    /// Email addresses the CA may send expiry warnings to.
+    #[serde(default)]
+    pub contact: Vec<String>,
+    /// The CA's directory URL. Let's Encrypt's production one by default; its
+    /// staging one has much looser rate limits, for trying things out.
+    #[serde(default = "AcmeConfig::default_directory")]
+    pub directory: String,
+    /// Where the account key, certificate and its private key are kept.
+    #[serde(default = "AcmeConfig::default_cache_dir")]
+    pub cache_dir: PathBuf,
+}
+
+impl AcmeConfig {
+    fn default_directory() -> String {
+        "https://acme-v02.api.letsencrypt.org/directory".into()
+    }
+
+    fn default_cache_dir() -> PathBuf {
+        "acme".into()
+    }
+
+    #[must_use]
+    pub fn cert_path(&self) -> PathBuf {
+        self.cache_dir.join("cert.pem")
+    }
+
+    #[must_use]
+    pub fn key_path(&self) -> PathBuf {
+        self.cache_dir.join("key.pem")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Plain files under `root`, which is the working directory by default.
+    Local { root: PathBuf },
+    /// An S3-compatible bucket, so several instances can share one store.
+    S3 {
+        endpoint: String,
+        bucket: String,
+        region: String,
+    },
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self::Local { root: ".".into() }
+    }
+}
+
+/// `max-age`s, in seconds, for each kind of file served from storage.
+///
+/// Stale copies are revalidated with `ETag`s, so an unchanged file costs a 304
+/// and nothing more.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Deployed pages, which change whenever they're redeployed.
+    pub pages: u32,
+    /// Media library uploads, which never change once stored.
+    pub media: u32,
+    /// Anything else in `pages/dist`, such as the sitemap.
+    pub assets: u32,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            pages: 5 * 60,
+            media: 365 * 24 * 60 * 60,
+            assets: 60 * 60,
+        }
+    }
+}
+
+/// Keeping the responses to public API reads that every visitor makes alike,
+/// such as the list of departments, so a burst of traffic doesn't cost a
+/// query per request. Only requests made without logging in are answered from
+/// it, and writes through the API clear whatever they affect.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResponseCacheConfig {
+    pub backend: ResponseCacheBackend,
+    /// Longest a response is kept, in seconds. Changes made through another
+    /// server are only seen once it's up, unless the backend is Redis.
+    pub ttl: u64,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: ResponseCacheBackend::Off,
+            ttl: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseCacheBackend {
+    #[default]
+    Off,
+    /// Kept by each server for itself.
+    Memory,
+    /// Shared between servers, through the Redis sessions are kept in.
+    Redis,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogFileConfig {
+    pub directory: PathBuf,
+    /// How many days of logs to keep, including today's.
+    #[serde(default = "LogFileConfig::default_retain")]
+    pub retain: usize,
+}
+
+impl LogFileConfig {
+    const fn default_retain() -> usize {
+        14
+    }
+}
+
+/// Regular exports of the site's content, saved to storage alongside it. Copy
+/// them somewhere else too, or they go with the storage backend.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackupConfig {
+    /// Hours between backups, at most a year.
+    #[serde(default = "BackupConfig::default_interval")]
+    pub interval: u64,
+    /// How many backups to keep, newest first.
+    #[serde(default = "BackupConfig::default_keep")]
+    pub keep: usize,
+    /// The storage prefix backups are saved under, ending in `/`.
+    #[serde(default = "BackupConfig::default_prefix")]
+    pub prefix: String,
+}
+
+impl BackupConfig {
+    const fn default_interval() -> u64 {
+        24
+    }
+
+    const fn default_keep() -> usize {
+        14
+    }
+
+    fn default_prefix() -> String {
+        "backups/".into()
+    }
+}
+
+/// Enquiries from prospective parents, which hold personal details about
+/// children and so aren't kept forever.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionsConfig {
+    /// Days an enquiry is kept after it last changed, after which it's
+    /// deleted whatever its status.
+    pub retention: u32,
+}
+
+impl Default for AdmissionsConfig {
+    fn default() -> Self {
+        Self { retention: 365 }
+    }
+}
+
+/// Deleted posts, pages, media and users, which can be restored until they're
+/// removed for good.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrashConfig {
+    /// Days something is kept in the trash before it's removed for good.
+    pub retention: u32,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self { retention: 30 }
+    }
+}
+
+/// Days records that aren't needed forever are kept before they're deleted,
+/// checked daily. Anything left unset is kept for good. Admissions enquiries
+/// have their own [`AdmissionsConfig::retention`].
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Entries in the audit log of who approved or rejected what.
+    pub audit_log: Option<u32>,
+    /// Submissions to forms, such as contact forms and consent slips.
+    pub form_submissions: Option<u32>,
+}
+
+/// Somewhere posts are shared, each with a `name` that their delivery status
+/// is recorded under.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SocialChannel {
+    /// Posts to the X account a user access token with `tweet.write` is for.
+    X { name: String, token: String },
+    /// Posts to a Facebook page's feed, with a page access token.
+    Facebook {
+        name: String,
+        page_id: String,
+        token: String,
+    },
+    /// POSTs the post's details as JSON. With `secret`, the body is signed
+    /// with HMAC-SHA256 in an `X-Signature-256` header, as `sha256=<hex>`.
+    Webhook {
+        name: String,
+        url: String,
+        secret: Option<String>,
+    },
+}
+
+impl SocialChannel {
+    #[must_use]
+    pub fn name(&self) -> &str {
+        match self {
+            Self::X { name, .. } | Self::Facebook { name, .. } | Self::Webhook { name, .. } => name,
+        }
+    }
+}
+
+/// Which other sites may call the API from a browser. Sessions are cookies,
+/// so only list origins trusted with a logged-in user's access.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// e.g. `https://admin.example.sch.uk`. If empty, cross-origin requests
+    /// get no CORS headers, so browsers only allow same-origin ones.
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    /// Request headers allowed beyond the ones browsers always allow.
+    pub headers: Vec<String>,
+    /// Seconds browsers may cache a preflight response for.
+    pub max_age: u64,
+    /// Allows any origin, method and header, for local development against a
+    /// frontend on another port.
+    #[cfg(debug_assertions)]
+    pub permissive: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            origins: Vec::new(),
+            methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            headers: ["content-type", "x-request-id"].map(String::from).to_vec(),
+            max_age: 60 * 60,
+            #[cfg(debug_assertions)]
+            permissive: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MailConfig {
+    /// The SMTP server, e.g. `smtp.office365.com`.
+    pub host: String,
+    #[serde(default = "MailConfig::default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Who messages are from, e.g. `Example School <noreply@example.sch.uk>`.
+    pub from: String,
+    /// Where the templates for each kind of message are kept.
+    #[serde(default = "MailConfig::default_templates")]
+    pub templates: PathBuf,
+}
+
+impl MailConfig {
+    const fn default_port() -> u16 {
+        587
+    }
+
+    fn default_templates() -> PathBuf {
+        "mail".into()
+    }
+}
+
+/// What checks uploads for viruses. Flagged files are quarantined rather than
+/// stored, and if the scanner can't be reached uploads are refused.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ScannerConfig {
+    /// A ClamAV daemon, listening on TCP at `address`, e.g. `127.0.0.1:3310`.
+    Clamd { address: String },
+    /// Runs `program` with `args`, giving it the file on stdin. Exiting with
+    /// 0 means it's clean and 1 that it's infected, with the first line of
+    /// output naming the threat, as `clamdscan -` does. Anything else is a
+    /// failure.
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Upgrade a plain connection with `STARTTLS`, usually on port 587.
+    #[default]
+    StartTls,
+    /// TLS from the start, usually on port 465.
+    Tls,
+    /// Unencrypted, for a relay on the same machine.
+    None,
+}
+
+/// One line per request, for feeding into a log collector.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLogConfig {
+    pub format: AccessLogFormat,
+    /// A file to append to, rather than writing to stdout.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    #[default]
+    Off,
+    /// A JSON object per line.
+    Json,
+    /// Space separated fields, for reading by eye.
+    Text,
+}
+
+/// Compression of responses on the fly, for clients that accept brotli or
+/// gzip. Deployed pages are already compressed, and images and event streams
+/// never are.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// Smallest response compressed, in bytes. Below this, the saving isn't
+    /// worth the time. Responses of unknown size are always compressed.
+    pub min_size: u16,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size: 1024,
+        }
+    }
+}
+
+/// The Postgres connection pool. The database itself is `DATABASE_URL`, so
+/// the password can stay out of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Connections open at once, across every request and background task.
+    pub max_connections: u32,
+    /// Connections kept open even when idle.
+    pub min_connections: u32,
+    /// Seconds to wait for a free connection before failing with a 500.
+    pub acquire_timeout: u64,
+    /// Seconds a connection above `min_connections` may sit idle before it's
+    /// closed, or 0 to keep them open.
+    pub idle_timeout: u64,
+    /// Seconds a single statement may run before Postgres cancels it, or 0 for
+    /// no limit.
+    pub statement_timeout: u64,
+    /// Shown against each connection in `pg_stat_activity`.
+    pub application_name: String,
+    /// Milliseconds after which a query is logged as slow, or 0 to log none.
+    pub slow_query_threshold: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 20,
+            min_connections: 0,
+            acquire_timeout: 30,
+            idle_timeout: 10 * 60,
+            statement_timeout: 30,
+            application_name: "phs_backend".into(),
+            slow_query_threshold: 500,
+        }
+    }
+}
+
+/// Caps on what a single request can use, so one misbehaving client can't
+/// starve the rest.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Largest request body accepted, in bytes, other than media uploads.
+    pub body: usize,
+    /// Largest media upload accepted, in bytes. Also caps imports, and each
+    /// file they download.
+    pub upload: usize,
+    /// Seconds a request may take before it's abandoned with a 504.
+    pub timeout: u64,
+    /// As `timeout`, but for media uploads, which can be slow to arrive, and
+    /// imports.
+    pub upload_timeout: u64,
+    /// Requests handled at once. Any more are refused with a 503.
+    pub concurrency: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            body: 2 * 1024 * 1024,
+            upload: 64 * 1024 * 1024,
+            timeout: 30,
+            upload_timeout: 5 * 60,
+            concurrency: 256,
+        }
+    }
+}
+
+/// Deployed dynamic pages to show in place of bare status text, given by their
+/// public paths, e.g. `/not_found`. Either falls back to the status text if
+/// unset or not yet deployed.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorPages {
+    pub not_found: Option<String>,
+    pub server_error: Option<String>,
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
+            site_url: "https://localhost".into(),
+            unavailable_template: None,
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
             https_port: 443,
             http_port: 80,
+            http_listen: Vec::new(),
+            https_listen: Vec::new(),
             tls_enabled: false,
             tls_options: None,
+            redis: true,
+            acme: None,
+            storage: StorageConfig::default(),
+            cache: CacheConfig::default(),
+            response_cache: ResponseCacheConfig::default(),
+            database: DatabaseConfig::default(),
+            limits: LimitsConfig::default(),
+            error_pages: ErrorPages::default(),
+            access_log: AccessLogConfig::default(),
+            compression: CompressionConfig::default(),
+            mail: None,
+            scanner: None,
+            settings_path: "settings.toml".into(),
+            cookie_key_path: "cookie.key".into(),
+            share_key_path: "share.key".into(),
+            log_filter: "trace,sqlx=info,fred=info".into(),
+            log_file: None,
+            backup: None,
+            admissions: AdmissionsConfig::default(),
+            trash: TrashConfig::default(),
+            retention: RetentionConfig::default(),
+            social: Vec::new(),
+            cors: CorsConfig::default(),
+            admin_host: None,
+            trusted_proxies: Vec::new(),
             #[cfg(debug_assertions)]
             use_tokio_console: false,
         }
@@ -32,6 +604,239 @@ impl Default for ServerConfig {
 }
 
 impl ServerConfig {
+    /// Reads `path`, if it exists, and applies overrides from the environment.
+    /// Command line flags are the caller's to apply, followed by
+    /// [`Self::validate`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or parsed, has unknown options, or an
+    /// override has the wrong type.
+    pub fn load(path: &Path, required: bool) -> Result<Self, ConfigError> {
+        let mut table = match std::fs::read_to_string(path) {
+            Ok(contents) => contents.parse::<toml::Table>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => toml::Table::new(),
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.to_owned(),
+                    source,
+                })
+            }
+        };
+
+        for (name, value) in std::env::vars() {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                apply_override(&mut table, key, &value).ok_or(ConfigError::EnvName(name))?;
+            }
+        }
+
+        Ok(toml::Value::Table(table).try_into()?)
+    }
+
+    /// Checks for options that parse but can't work together.
+    ///
+    /// # Errors
+    ///
+    /// Describes the first problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Invalid(message.to_owned()));
+
+        let site_url = self.site_url.parse::<Uri>().ok();
+        if !site_url.as_ref().is_some_and(|url| {
+            matches!(url.scheme_str(), Some("http" | "https")) && url.host().is_some()
+        }) {
+            return invalid("site_url must be an absolute http or https URL");
+        }
+
+        if self.tls_enabled {
+            match (&self.tls_options, &self.acme) {
+                (None, None) => {
+                    return invalid("tls_enabled is set but there's no [tls] or [acme] section");
+                }
+                (Some(_), Some(_)) => return invalid("[tls] and [acme] can't both be set"),
+                (Some(tls), None) => {
+                    if !tls.cert_path.is_file() || !tls.key_path.is_file() {
+                        return invalid(
+                            "tls.cert_path and tls.key_path must both be existing files",
+                        );
+                    }
+                }
+                (None, Some(acme)) => {
+                    if acme.domains.is_empty() {
+                        return invalid("acme.domains must name at least one domain");
+                    }
+                    if acme
+                        .directory
+                        .parse::<Uri>()
+                        .map_or(true, |uri| uri.scheme_str() != Some("https"))
+                    {
+                        return invalid("acme.directory must be an https URL");
+                    }
+                }
+            }
+            let https = self.https_addresses();
+            if self
+                .http_addresses()
+                .iter()
+                .any(|addr| https.contains(addr))
+            {
+                return invalid("HTTP and HTTPS must listen on different ports");
+            }
+        }
+
+        if self.admin_host.as_ref().is_some_and(|host| {
+            host.parse::<Authority>()
+                .map_or(true, |authority| authority.port().is_some())
+        }) {
+            return invalid(
+                "admin_host must be a hostname like admin.example.sch.uk, with no port",
+            );
+        }
+
+        if !self.redis && self.response_cache.backend == ResponseCacheBackend::Redis {
+            return invalid("response_cache.backend can't be \"redis\" when redis is off");
+        }
+
+        let limits = &self.limits;
+        if [limits.body, limits.upload, limits.concurrency].contains(&0)
+            || limits.timeout == 0
+            || limits.upload_timeout == 0
+        {
+            return invalid("Every option in [limits] must be above zero");
+        }
+
+        let database = &self.database;
+        if database.max_connections == 0 || database.acquire_timeout == 0 {
+            return invalid(
+                "database.max_connections and database.acquire_timeout must be above zero",
+            );
+        }
+        if database.min_connections > database.max_connections {
+            return invalid("database.min_connections can't be above database.max_connections");
+        }
+
+        let error_pages = [&self.error_pages.not_found, &self.error_pages.server_error];
+        if error_pages
+            .into_iter()
+            .flatten()
+            .any(|page| !page.starts_with('/'))
+        {
+            return invalid("error_pages must be paths starting with /");
+        }
+
+        if self.cors.origins.iter().any(|origin| {
+            origin.parse::<Uri>().map_or(true, |uri| {
+                uri.scheme().is_none() || uri.host().is_none() || uri.path() != "/"
+            }) || origin.ends_with('/')
+        }) {
+            return invalid("cors.origins must be origins like https://example.com, with no path");
+        }
+        if self
+            .cors
+            .methods
+            .iter()
+            .any(|m| m.parse::<Method>().is_err())
+        {
+            return invalid("cors.methods must be HTTP methods, e.g. PATCH");
+        }
+        if self
+            .cors
+            .headers
+            .iter()
+            .any(|h| h.parse::<HeaderName>().is_err())
+        {
+            return invalid("cors.headers must be header names, e.g. content-type");
+        }
+
+        if let Some(mail) = &self.mail {
+            if mail.from.parse::<lettre::message::Mailbox>().is_err() {
+                return invalid("mail.from must be an address, e.g. School <noreply@example.com>");
+            }
+            if mail.username.is_some() != mail.password.is_some() {
+                return invalid("mail.username and mail.password must be set together");
+            }
+        }
+
+        if self.response_cache.backend != ResponseCacheBackend::Off && self.response_cache.ttl == 0
+        {
+            return invalid("response_cache.ttl must be above zero");
+        }
+
+        if let Some(backup) = &self.backup {
+            if !(1..=24 * 366).contains(&backup.interval) {
+                return invalid("backup.interval must be between 1 and 8784 hours");
+            }
+            if backup.keep == 0 {
+                return invalid("backup.keep must be above zero");
+            }
+            if !backup.prefix.ends_with('/')
+                || ["media/", "pages/"]
+                    .iter()
+                    .any(|reserved| backup.prefix.starts_with(reserved))
+            {
+                return invalid(
+                    "backup.prefix must end in / and be outside media/ and pages/, e.g. backups/",
+                );
+            }
+        }
+
+        if !(1..=10 * 366).contains(&self.admissions.retention) {
+            return invalid("admissions.retention must be between 1 and 3660 days");
+        }
+
+        if !(1..=10 * 366).contains(&self.trash.retention) {
+            return invalid("trash.retention must be between 1 and 3660 days");
+        }
+
+        for (name, days) in [
+            ("audit_log", self.retention.audit_log),
+            ("form_submissions", self.retention.form_submissions),
+        ] {
+            if days.is_some_and(|days| !(1..=10 * 366).contains(&days)) {
+                return Err(ConfigError::Invalid(format!(
+                    "retention.{name} must be between 1 and 3660 days"
+                )));
+            }
+        }
+
+        for (i, channel) in self.social.iter().enumerate() {
+            let name = channel.name();
+            if name.trim().is_empty() || name.chars().count() > 255 {
+                return invalid("social channel names must be between 1 and 255 characters");
+            }
+            if self.social[..i].iter().any(|other| other.name() == name) {
+                return invalid("social channel names must be unique");
+            }
+            if let SocialChannel::Webhook { url, .. } = channel {
+                if !(url.starts_with("http://") || url.starts_with("https://")) {
+                    return invalid("social webhook URLs must start with http:// or https://");
+                }
+            }
+        }
+
+        if self
+            .trusted_proxies
+            .iter()
+            .any(|proxy| IpRange::parse(proxy).is_none())
+        {
+            return invalid("trusted_proxies must be addresses or CIDR ranges, e.g. 10.0.0.0/8");
+        }
+
+        Ok(())
+    }
+
+    /// Where to listen for HTTP, which only redirects to HTTPS if TLS is
+    /// enabled.
+    #[must_use]
+    pub fn http_addresses(&self) -> Vec<SocketAddr> {
+        listen_addresses(&self.http_listen, self.bind_address, self.http_port)
+    }
+
+    #[must_use]
+    pub fn https_addresses(&self) -> Vec<SocketAddr> {
+        listen_addresses(&self.https_listen, self.bind_address, self.https_port)
+    }
+
     pub fn get_cert_filepath(&self) -> Option<&PathBuf> {
         if let (true, Some(TlsOptions { ref cert_path, .. })) =
             (self.tls_enabled, &self.tls_options)
@@ -51,3 +856,43 @@ impl ServerConfig {
         }
     }
 }
+
+fn listen_addresses(listen: &[SocketAddr], address: IpAddr, port: u16) -> Vec<SocketAddr> {
+    if listen.is_empty() {
+        vec![SocketAddr::new(address, port)]
+    } else {
+        listen.to_vec()
+    }
+}
+
+/// Sets the option named by an environment variable (minus the prefix) in a
+/// parsed config file. Values are read as TOML if possible, so numbers and
+/// booleans work, and as plain strings otherwise.
+fn apply_override(table: &mut toml::Table, key: &str, value: &str) -> Option<()> {
+    let mut path = key.split("__").map(str::to_ascii_lowercase).peekable();
+    let mut table = table;
+
+    while let Some(name) = path.next() {
+        if name.is_empty() {
+            return None;
+        }
+
+        if path.peek().is_none() {
+            let value = format!("value = {value}")
+                .parse::<toml::Table>()
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .unwrap_or_else(|| toml::Value::String(value.to_owned()));
+
+            table.insert(name, value);
+            return Some(());
+        }
+
+        table = table
+            .entry(name)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()?;
+    }
+
+    None
+}
@@ -0,0 +1,118 @@
+//! Which optional cookies and scripts a visitor to the site has agreed to,
+//! kept in a cookie of their own rather than a session, since most visitors
+//! never log in.
+//!
+//! Deployed pages are rendered once for everyone, so they can't be rendered
+//! differently per visitor. Instead templates get the cookie's name as
+//! `consent.cookie`, and only load what's in their `analytics` block once it
+//! says the visitor agreed.
+
+use axum::{
+    routing::{get, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use time::Duration;
+use tower_cookies::{cookie::SameSite, Cookie, Cookies};
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+/// What the choices are stored under. Readable by scripts on the page, so it
+/// can't be `HttpOnly`.
+const CONSENT_COOKIE: &str = "phs_consent";
+
+/// How long a choice lasts before the visitor's asked again.
+const CONSENT_LIFETIME: Duration = Duration::days(180);
+
+/// Stored when nothing optional was agreed to, to tell that apart from not
+/// having chosen.
+const NOTHING: &str = "none";
+
+/// Whether the visitor making a request has agreed to analytics.
+pub(crate) fn allows_analytics(cookies: &Cookies) -> bool {
+    cookies
+        .get(CONSENT_COOKIE)
+        .is_some_and(|cookie| Consent::from_cookie(cookie.value()).analytics)
+}
+
+/// Adds what templates need to check the visitor's choices, as `consent`.
+pub(crate) fn add_to_context(context: &mut tera::Context) {
+    context.insert("consent", &serde_json::json!({ "cookie": CONSENT_COOKIE }));
+}
+
+pub fn router() -> Router {
+    Router::new().route("/consent", get(get_consent))
+}
+
+/// The cookie banner's answer, given before the visitor has an account to log
+/// in with, if they ever do.
+pub fn public_router() -> Router {
+    Router::new().route("/consent", put(put_consent))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_consent, put_consent))]
+struct ConsentApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ConsentApi::openapi()
+}
+
+/// What a visitor has agreed to, stored as what's agreed to separated by
+/// dots, e.g. `analytics`, or [`NOTHING`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, ToSchema)]
+struct Consent {
+    /// Counting page views, with `/analytics/hit`.
+    analytics: bool,
+}
+
+impl Consent {
+    fn from_cookie(value: &str) -> Self {
+        Self {
+            analytics: value.split('.').any(|choice| choice == "analytics"),
+        }
+    }
+
+    fn to_cookie(self) -> Cookie<'static> {
+        let value = if self.analytics { "analytics" } else { NOTHING };
+
+        Cookie::build((CONSENT_COOKIE, value))
+            .path("/")
+            .max_age(CONSENT_LIFETIME)
+            .same_site(SameSite::Lax)
+            .secure(true)
+            .build()
+    }
+}
+
+/// The visitor's choices, or null if they haven't made any yet, so a banner
+/// knows to ask.
+#[utoipa::path(
+    get,
+    path = "/consent",
+    tag = "consent",
+    responses((status = 200, body = Option<Consent>))
+)]
+#[instrument(skip(cookies))]
+async fn get_consent(cookies: Cookies) -> Json<Option<Consent>> {
+    Json(
+        cookies
+            .get(CONSENT_COOKIE)
+            .map(|cookie| Consent::from_cookie(cookie.value())),
+    )
+}
+
+/// Records the visitor's choices, replacing any they made before.
+#[utoipa::path(
+    put,
+    path = "/consent",
+    tag = "consent",
+    request_body = Consent,
+    responses((status = 200, body = Consent))
+)]
+#[instrument(skip(cookies))]
+async fn put_consent(cookies: Cookies, Json(consent): Json<Consent>) -> Json<Consent> {
+    cookies.add(consent.to_cookie());
+
+    Json(consent)
+}
@@ -0,0 +1,39 @@
+use std::{fs, io, path::Path};
+
+use tower_cookies::Key;
+
+/// Reads the key session cookies are signed with, making one if there isn't
+/// one yet. Keeping it on disk means sessions survive restarts.
+///
+/// # Errors
+///
+/// Fails if the file can't be read or written, or isn't a key.
+pub fn load_or_create(path: &Path) -> io::Result<Key> {
+    match fs::read(path) {
+        Ok(bytes) => Key::try_from(bytes.as_slice())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => rotate(path),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces the key with a new random one. Every existing session cookie stops
+/// verifying once the server restarts, so everyone is logged out.
+///
+/// # Errors
+///
+/// Fails if there's no randomness to be had or the file can't be written.
+pub fn rotate(path: &Path) -> io::Result<Key> {
+    let key = Key::try_generate().ok_or_else(|| io::Error::other("The OS RNG is unavailable"))?;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // Anyone who can read it can forge a session
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    io::Write::write_all(&mut options.open(path)?, key.master())?;
+    tracing::info!("Wrote a new cookie key to {}", path.display());
+
+    Ok(key)
+}
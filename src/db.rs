@@ -0,0 +1,77 @@
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use sqlx::PgPool;
+
+mod metrics;
+mod tx;
+
+pub use metrics::{QueryMetrics, QueryStats, RowCount};
+pub use tx::{transactions, Tx};
+
+/// The database, plus an optional read-only replica that reads can be sent to,
+/// so heavy public traffic doesn't contend with admin writes.
+///
+/// A replica may lag slightly behind, so anything that reads back what it has
+/// just written, or reads in order to write, uses [`Db::write`].
+#[derive(Debug, Clone)]
+pub struct Db {
+    primary: PgPool,
+    replica: Option<PgPool>,
+    metrics: Arc<QueryMetrics>,
+    /// Queries run through [`Db::timed`] taking longer than this are logged.
+    /// Zero turns that off.
+    slow_threshold: Duration,
+}
+
+impl Db {
+    pub fn new(primary: PgPool, replica: Option<PgPool>, slow_threshold: Duration) -> Self {
+        Self {
+            primary,
+            replica,
+            metrics: Arc::default(),
+            slow_threshold,
+        }
+    }
+
+    /// The replica if there is one, or else the primary.
+    pub fn read(&self) -> &PgPool {
+        self.replica.as_ref().unwrap_or(&self.primary)
+    }
+
+    pub const fn write(&self) -> &PgPool {
+        &self.primary
+    }
+
+    /// Runs `query`, counting its time and rows towards `name` in
+    /// [`Db::metrics`], and logging it if it's slow.
+    ///
+    /// # Errors
+    ///
+    /// Passes on the query's error.
+    pub async fn timed<T: RowCount>(
+        &self,
+        name: &'static str,
+        query: impl Future<Output = Result<T, sqlx::Error>> + Send,
+    ) -> Result<T, sqlx::Error> {
+        let start = Instant::now();
+        let result = query.await;
+        let elapsed = start.elapsed();
+
+        self.metrics
+            .record(name, elapsed, result.as_ref().ok().map(RowCount::row_count));
+
+        if !self.slow_threshold.is_zero() && elapsed > self.slow_threshold {
+            tracing::warn!(query = name, ?elapsed, "Slow query");
+        }
+
+        result
+    }
+
+    pub fn metrics(&self) -> &QueryMetrics {
+        &self.metrics
+    }
+}
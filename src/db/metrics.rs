@@ -0,0 +1,65 @@
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+use sqlx::postgres::PgQueryResult;
+
+/// Totals for one named query since the server started.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct QueryStats {
+    pub calls: u64,
+    pub errors: u64,
+    /// Rows returned, or affected for statements that don't return any.
+    pub rows: u64,
+    pub total_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct QueryMetrics(Mutex<BTreeMap<&'static str, QueryStats>>);
+
+impl QueryMetrics {
+    /// `rows` is `None` if the query failed.
+    pub(super) fn record(&self, name: &'static str, elapsed: Duration, rows: Option<u64>) {
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+
+        let mut queries = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = queries.entry(name).or_default();
+        stats.calls += 1;
+        match rows {
+            Some(rows) => stats.rows += rows,
+            None => stats.errors += 1,
+        }
+        stats.total_ms += elapsed_ms;
+        stats.max_ms = stats.max_ms.max(elapsed_ms);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, QueryStats> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// How many rows a query's result stands for. Single rows from `fetch_one`
+/// keep the default, so their types only need an empty impl.
+pub trait RowCount {
+    fn row_count(&self) -> u64 {
+        1
+    }
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl<T> RowCount for Option<T> {
+    fn row_count(&self) -> u64 {
+        u64::from(self.is_some())
+    }
+}
+
+impl RowCount for PgQueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected()
+    }
+}
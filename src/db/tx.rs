@@ -0,0 +1,107 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request},
+    http::request::Parts,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::error::PhsError;
+
+/// Where [`transactions`] keeps the request's transaction, once a handler's
+/// [`Tx`] has begun one.
+#[derive(Clone, Default)]
+struct Slot(Arc<Mutex<Option<Transaction<'static, Postgres>>>>);
+
+/// A transaction on the primary spanning the whole handler, for handlers
+/// whose steps should all happen or none of them.
+///
+/// It's committed by [`transactions`] once the handler returns anything but
+/// an error, and rolled back otherwise, including when a step after the
+/// queries, such as writing a file, fails. Handlers that need to undo
+/// something outside the database if the commit fails still begin and commit
+/// their own.
+///
+/// Queries run on it as a connection:
+///
+/// ```ignore
+/// sqlx::query!("...").execute(&mut *tx).await?;
+/// ```
+pub struct Tx(OwnedMutexGuard<Option<Transaction<'static, Postgres>>>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Tx {
+    type Rejection = PhsError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let slot = parts
+            .extensions
+            .get::<Slot>()
+            .cloned()
+            .ok_or(PhsError::bug("Transaction layer missing"))?;
+        let pool = parts
+            .extensions
+            .get::<PgPool>()
+            .ok_or(PhsError::bug("Database pool missing from extensions"))?;
+
+        let mut guard = slot
+            .0
+            .try_lock_owned()
+            .map_err(|_| PhsError::bug("Transaction extracted twice in one request"))?;
+        if guard.is_none() {
+            *guard = Some(pool.begin().await?);
+        }
+
+        Ok(Self(guard))
+    }
+}
+
+impl Deref for Tx {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_deref().expect("begun when extracted")
+    }
+}
+
+impl DerefMut for Tx {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_deref_mut().expect("begun when extracted")
+    }
+}
+
+/// Commits whatever a handler did through [`Tx`] if its response is a
+/// success or redirect, replacing it with the error if the commit fails.
+/// Requests that don't use [`Tx`] don't touch the database here.
+pub async fn transactions(mut req: Request, next: Next) -> Response {
+    let slot = Slot::default();
+    req.extensions_mut().insert(slot.clone());
+
+    let res = next.run(req).await;
+
+    // The handler's `Tx` has been dropped by now, unless it was moved into a
+    // task that outlives the request, whose work is then rolled back
+    let Ok(mut guard) = slot.0.try_lock() else {
+        tracing::error!("Transaction still in use after its request finished");
+        return PhsError::bug("Transaction outlived its request").into_response();
+    };
+    let Some(tx) = guard.take() else {
+        return res;
+    };
+
+    if res.status().is_client_error() || res.status().is_server_error() {
+        return res;
+    }
+
+    match tx.commit().await {
+        Ok(()) => res,
+        Err(e) => PhsError::from(e).into_response(),
+    }
+}
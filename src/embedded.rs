@@ -0,0 +1,101 @@
+//! Templates and base assets built into the binary with the `embedded_assets`
+//! feature, so it can be deployed on its own. Anything on disk, or in
+//! storage, takes precedence, so each can still be overridden in place.
+
+use axum::async_trait;
+use tera::Tera;
+
+use crate::{
+    error::PhsError,
+    storage::{SharedStorage, Storage, StoredObject},
+};
+
+/// Every embedded file by its path from the crate root, e.g.
+/// `pages/templates/base.html`, as written by `build.rs`.
+static ASSETS: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+/// Only these are served through [`EmbeddedFallback`]; templates are loaded
+/// by [`add_templates`] instead.
+const DIST_PREFIX: &str = "pages/dist/";
+
+fn get(key: &str) -> Option<&'static [u8]> {
+    ASSETS
+        .binary_search_by_key(&key, |&(key, _)| key)
+        .ok()
+        .map(|i| ASSETS[i].1)
+}
+
+/// Adds the embedded templates under `dir` to `tera`, named relative to it as
+/// if loaded from disk, skipping any it already has.
+///
+/// # Errors
+///
+/// Fails if an embedded template doesn't parse, which `build.rs` can't check.
+pub fn add_templates(tera: &mut Tera, dir: &str) -> Result<(), tera::Error> {
+    let prefix = format!("{}/", dir.trim_end_matches('/'));
+
+    let templates = ASSETS
+        .iter()
+        .filter_map(|(key, data)| {
+            Some((key.strip_prefix(&prefix)?, std::str::from_utf8(data).ok()?))
+        })
+        .filter(|(name, _)| !tera.get_template_names().any(|existing| existing == *name))
+        .collect::<Vec<_>>();
+
+    tera.add_raw_templates(templates)
+}
+
+/// Answers reads under `pages/dist` from the embedded copy when the store
+/// itself has nothing there. Writes only ever go to the store.
+pub struct EmbeddedFallback(pub SharedStorage);
+
+#[async_trait]
+impl Storage for EmbeddedFallback {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PhsError> {
+        Ok(match self.0.get(key).await? {
+            Some(data) => Some(data),
+            None => embedded_dist(key).map(<[u8]>::to_vec),
+        })
+    }
+
+    async fn get_object(&self, key: &str) -> Result<Option<StoredObject>, PhsError> {
+        Ok(match self.0.get_object(key).await? {
+            Some(object) => Some(object),
+            None => embedded_dist(key).map(|data| StoredObject {
+                data: data.to_vec(),
+                last_modified: None,
+            }),
+        })
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), PhsError> {
+        self.0.put(key, data).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), PhsError> {
+        self.0.delete(key).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<bool, PhsError> {
+        self.0.rename(from, to).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, PhsError> {
+        let mut keys = self.0.list(prefix).await?;
+        keys.extend(
+            ASSETS
+                .iter()
+                .map(|(key, _)| *key)
+                .filter(|key| key.starts_with(DIST_PREFIX) && key.starts_with(prefix))
+                .map(str::to_owned),
+        );
+        keys.sort_unstable();
+        keys.dedup();
+
+        Ok(keys)
+    }
+}
+
+fn embedded_dist(key: &str) -> Option<&'static [u8]> {
+    key.starts_with(DIST_PREFIX).then(|| get(key)).flatten()
+}
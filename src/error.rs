@@ -1,71 +1,264 @@
-use std::fmt::Debug;
+use std::{borrow::Cow, fmt::Debug};
 
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
+use serde::Serialize;
 
-use crate::sessions;
+use crate::{request_id::RequestId, sessions, validation::FieldErrors};
+
+/// Why a request failed, as a stable code clients can match on, sent as the
+/// `code` member of the problem body. Each has a fixed status.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The body or query couldn't be read.
+    BadRequest,
+    InvalidLink,
+    /// Something would end up beneath itself, or nested too deeply.
+    InvalidHierarchy,
+    InvalidStorageKey,
+    EmptyQuery,
+    MissingFile,
+    UsernameTaken,
+    InvalidResetToken,
+
+    NotLoggedIn,
+    WrongCredentials,
+    SessionExpired,
+
+    MissingPermission,
+    StaffOnly,
+    /// A share link that's been tampered with or has expired.
+    InvalidShareLink,
+    /// Approving changes one made or put forward oneself.
+    OwnChanges,
+
+    NotFound,
+    DepartmentNotFound,
+    ParentNotFound,
+    SearchDisabled,
+
+    NameTaken,
+    HasChildren,
+    InUse,
+    AlreadyExists,
+    /// Registration for an event isn't open, or has closed.
+    RegistrationClosed,
+    EventFull,
+    /// A form isn't taking submissions.
+    FormClosed,
+    /// A poll isn't taking votes.
+    PollClosed,
+    /// A status can't go straight to the one asked for.
+    InvalidTransition,
+    /// Publishing something that needs approving first.
+    NotApproved,
+
+    PayloadTooLarge,
+    UnsupportedFileType,
+    /// A file the virus scanner flagged, which has been quarantined.
+    Infected,
+
+    /// A body that was read fine, but has problems listed in `errors`.
+    Invalid,
+    InvalidSetting,
+    UnknownParent,
+    /// A template that doesn't parse, so those in use are kept.
+    InvalidTemplate,
+
+    Internal,
+    Overloaded,
+    Timeout,
+    /// The virus scanner can't be reached, so uploads are refused until it
+    /// can.
+    ScannerUnavailable,
+}
+
+impl ErrorCode {
+    pub const fn status(self) -> StatusCode {
+        match self {
+            Self::BadRequest
+            | Self::InvalidLink
+            | Self::InvalidHierarchy
+            | Self::InvalidStorageKey
+            | Self::EmptyQuery
+            | Self::MissingFile
+            | Self::UsernameTaken
+            | Self::InvalidResetToken => StatusCode::BAD_REQUEST,
+            Self::NotLoggedIn | Self::WrongCredentials | Self::SessionExpired => {
+                StatusCode::UNAUTHORIZED
+            }
+            Self::MissingPermission
+            | Self::StaffOnly
+            | Self::InvalidShareLink
+            | Self::OwnChanges => StatusCode::FORBIDDEN,
+            Self::NotFound
+            | Self::DepartmentNotFound
+            | Self::ParentNotFound
+            | Self::SearchDisabled => StatusCode::NOT_FOUND,
+            Self::NameTaken
+            | Self::HasChildren
+            | Self::InUse
+            | Self::AlreadyExists
+            | Self::RegistrationClosed
+            | Self::EventFull
+            | Self::FormClosed
+            | Self::PollClosed
+            | Self::InvalidTransition
+            | Self::NotApproved => StatusCode::CONFLICT,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedFileType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::Invalid
+            | Self::InvalidSetting
+            | Self::UnknownParent
+            | Self::InvalidTemplate
+            | Self::Infected => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Overloaded | Self::ScannerUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct PhsError(
-    pub StatusCode,
-    pub Option<Box<dyn Debug + Send>>,
-    pub &'static str,
-);
+pub enum PhsError {
+    /// The client's mistake, explained to them.
+    Client {
+        code: ErrorCode,
+        detail: Cow<'static, str>,
+    },
+    /// A body with one or more problems, all listed so they can be fixed in
+    /// one go.
+    Invalid {
+        detail: Cow<'static, str>,
+        errors: FieldErrors,
+    },
+    /// Our own failure. The client only gets a 500; `source` and `context`
+    /// are logged, along with the request ID it's sent instead.
+    Internal {
+        source: Option<Box<dyn Debug + Send>>,
+        context: &'static str,
+    },
+}
+
+impl PhsError {
+    pub fn client(code: ErrorCode, detail: impl Into<Cow<'static, str>>) -> Self {
+        Self::Client {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn internal(source: impl Debug + Send + 'static, context: &'static str) -> Self {
+        Self::Internal {
+            source: Some(Box::new(source)),
+            context,
+        }
+    }
+
+    /// For failures with nothing more to log than what was going on.
+    pub const fn bug(context: &'static str) -> Self {
+        Self::Internal {
+            source: None,
+            context,
+        }
+    }
+
+    pub const fn code(&self) -> ErrorCode {
+        match self {
+            Self::Client { code, .. } => *code,
+            Self::Invalid { .. } => ErrorCode::Invalid,
+            Self::Internal { .. } => ErrorCode::Internal,
+        }
+    }
+}
+
+/// An RFC 7807 problem, with the code and any field errors as extension
+/// members.
+#[derive(Serialize)]
+struct Problem<'a> {
+    r#type: &'static str,
+    title: &'static str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    code: ErrorCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<&'a FieldErrors>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
 
 impl IntoResponse for PhsError {
     fn into_response(self) -> Response {
-        tracing::error!(error = ?self.1, "Error {}: {}", self.0, self.2);
+        let code = self.code();
+        let status = code.status();
 
-        // Return the canonical reason to remain ambiguous about system workings
-        (self.0, self.0.canonical_reason().unwrap()).into_response()
+        // Internal errors only get the canonical reason, to remain ambiguous
+        // about system workings, plus the request ID so a report can be
+        // matched up with the logs
+        let (detail, errors) = match &self {
+            Self::Client { detail, .. } => {
+                tracing::debug!(?code, "Error {status}: {detail}");
+                (Some(detail.as_ref()), None)
+            }
+            Self::Invalid { detail, errors } => {
+                tracing::debug!(?errors, "Error {status}: {detail}");
+                (Some(detail.as_ref()), Some(errors))
+            }
+            Self::Internal { source, context } => {
+                tracing::error!(error = ?source, "Error {status}: {context}");
+                (None, None)
+            }
+        };
+
+        let problem = Problem {
+            r#type: "about:blank",
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            detail,
+            code,
+            errors,
+            request_id: RequestId::current().map(|id| id.to_string()),
+        };
+
+        let mut res = (status, Json(problem)).into_response();
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        res
     }
 }
 
 impl From<sqlx::Error> for PhsError {
     fn from(e: sqlx::Error) -> Self {
-        tracing::error!(e = %e, "Sqlx Error");
         match e {
-            sqlx::Error::RowNotFound => Self(
-                StatusCode::NOT_FOUND,
-                Some(Box::new(e)),
-                "The requested resource was not found",
-            ),
-            _ => Self(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Some(Box::new(e)),
-                "SqlX error",
-            ),
+            sqlx::Error::RowNotFound => {
+                Self::client(ErrorCode::NotFound, "The requested resource was not found")
+            }
+            _ => Self::internal(e, "SqlX error"),
         }
     }
 }
 
 impl From<argon2::password_hash::Error> for PhsError {
     fn from(e: argon2::password_hash::Error) -> Self {
-        tracing::error!(e = %e, "Argon2id Error");
         match e {
-            argon2::password_hash::Error::Password => Self(
-                StatusCode::UNAUTHORIZED,
-                Some(Box::new(e)),
-                "Incorrect or invalid password",
-            ),
-            _ => Self(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Some(Box::new(e)),
-                "Argon2 error",
-            ),
+            argon2::password_hash::Error::Password => {
+                Self::client(ErrorCode::WrongCredentials, "Incorrect or invalid password")
+            }
+            _ => Self::internal(e, "Argon2 error"),
         }
     }
 }
 
 impl From<tokio::task::JoinError> for PhsError {
     fn from(e: tokio::task::JoinError) -> Self {
-        Self(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Some(Box::new(e)),
-            "Tokio join error",
-        )
+        Self::internal(e, "Tokio join error")
     }
 }
 
@@ -73,59 +266,51 @@ impl From<sessions::Error> for PhsError {
     fn from(e: sessions::Error) -> Self {
         match e {
             sessions::Error::SessionNotFound => {
-                Self(StatusCode::UNAUTHORIZED, None, "Session not found")
+                Self::client(ErrorCode::NotLoggedIn, "Session not found")
             }
-            _ => Self(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Some(Box::new(e)),
-                "Sessions error",
-            ),
+            _ => Self::internal(e, "Sessions error"),
         }
     }
 }
 
-impl From<(StatusCode, &'static str)> for PhsError {
-    fn from(e: (StatusCode, &'static str)) -> Self {
-        Self(e.0, Some(Box::new(e)), e.1)
-    }
-}
-
 impl From<deadpool_redis::PoolError> for PhsError {
     fn from(e: deadpool_redis::PoolError) -> Self {
-        Self(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Some(Box::new(e)),
-            "Redis pool error",
-        )
+        Self::internal(e, "Redis pool error")
     }
 }
 
 impl From<redis::RedisError> for PhsError {
     fn from(e: redis::RedisError) -> Self {
-        Self(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Some(Box::new(e)),
-            "Redis error",
-        )
+        Self::internal(e, "Redis error")
     }
 }
 
 impl From<tokio::io::Error> for PhsError {
     fn from(e: tokio::io::Error) -> Self {
-        Self(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Some(Box::new(e)),
-            "Tokio IO error",
-        )
+        Self::internal(e, "Tokio IO error")
     }
 }
 
 impl From<serde_json::Error> for PhsError {
     fn from(e: serde_json::Error) -> Self {
-        Self(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Some(Box::new(e)),
-            "Error whilst serialising or deserialising JSON",
-        )
+        Self::internal(e, "Error whilst serialising or deserialising JSON")
+    }
+}
+
+impl From<tera::Error> for PhsError {
+    fn from(e: tera::Error) -> Self {
+        Self::internal(e, "Error whilst rendering a template")
+    }
+}
+
+impl From<axum::extract::multipart::MultipartError> for PhsError {
+    fn from(e: axum::extract::multipart::MultipartError) -> Self {
+        let code = if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            ErrorCode::PayloadTooLarge
+        } else {
+            ErrorCode::BadRequest
+        };
+
+        Self::client(code, e.body_text())
     }
 }
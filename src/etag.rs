@@ -0,0 +1,64 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+use crate::error::PhsError;
+
+/// Whether the request's `If-None-Match` lists `etag`, or `None` if it has
+/// none. Tags are compared weakly, which is all a GET needs.
+pub fn if_none_match(request_headers: &HeaderMap, etag: &str) -> Option<bool> {
+    let etag = etag.strip_prefix("W/").unwrap_or(etag);
+
+    request_headers.get(header::IF_NONE_MATCH).map(|tags| {
+        tags.to_str().is_ok_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+        })
+    })
+}
+
+/// Tags successful GETs with a weak `ETag` hashed from the body, and answers
+/// with an empty 304 when the client already has that version, so tables
+/// polled by the admin UI only come down again when they change.
+///
+/// The handler still runs either way; this saves bandwidth, not queries.
+pub async fn tag_responses(req: Request, next: Next) -> Response {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return next.run(req).await;
+    }
+
+    let request_headers = req.headers().clone();
+    let res = next.run(req).await;
+
+    if res.status() != StatusCode::OK || res.headers().contains_key(header::ETAG) {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return PhsError::internal(e, "Error whilst reading a response to tag").into_response()
+        }
+    };
+
+    // Weak, as the compression layer may still change the bytes sent
+    let etag = format!("W/\"{}\"", hex::encode(&Sha256::digest(&bytes)[..16]));
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        parts.headers.insert(header::ETAG, value);
+    }
+
+    if if_none_match(&request_headers, &etag) == Some(true) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    }
+
+    Response::from_parts(parts, Body::from(bytes))
+}
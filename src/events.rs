@@ -0,0 +1,128 @@
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use axum::{
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Extension, Router,
+};
+use futures_util::{stream, Stream};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::instrument;
+
+use crate::auth::{AuthSession, CurrentPermissions, Permission};
+
+/// Notifications held for slow listeners before they start missing some.
+const CHANNEL_CAPACITY: usize = 64;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+pub fn router() -> Router {
+    Router::new().route("/events/stream", get(get_event_stream))
+}
+
+/// Something the admin UI might want to show straight away, sent as an SSE
+/// event named after the variant, with the fields as JSON data.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Notification {
+    PagesDeployed {
+        pages: Vec<i32>,
+        by: i32,
+    },
+    ImportFinished {
+        created: usize,
+        updated: usize,
+        skipped: usize,
+        by: i32,
+    },
+    LinkCheckFinished {
+        broken: usize,
+    },
+    PostPublished {
+        post: i32,
+        by: i32,
+        /// Whether it's to be shared on the social channels in the config.
+        share: bool,
+    },
+}
+
+impl Notification {
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::PagesDeployed { .. } => "pages_deployed",
+            Self::ImportFinished { .. } => "import_finished",
+            Self::LinkCheckFinished { .. } => "link_check_finished",
+            Self::PostPublished { .. } => "post_published",
+        }
+    }
+
+    /// Who gets to hear about it.
+    const fn permission(&self) -> Permission {
+        match self {
+            Self::PagesDeployed { .. }
+            | Self::ImportFinished { .. }
+            | Self::LinkCheckFinished { .. } => Permission::ManagePages,
+            Self::PostPublished { .. } => Permission::EditPosts,
+        }
+    }
+}
+
+/// Fans notifications out to every open stream. Sending is free when nobody's
+/// listening.
+#[derive(Clone)]
+pub struct Notifier(Arc<broadcast::Sender<Notification>>);
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self(Arc::new(broadcast::channel(CHANNEL_CAPACITY).0))
+    }
+}
+
+impl Notifier {
+    pub fn notify(&self, notification: Notification) {
+        // Only fails if nobody is listening
+        let _ = self.0.send(notification);
+    }
+
+    /// Hears every notification sent from now on, for background jobs that
+    /// act on them.
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.0.subscribe()
+    }
+}
+
+/// Streams notifications the user has the permission to see for as long as
+/// they stay connected. Permissions are those at the time of connecting.
+#[instrument(skip_all)]
+async fn get_event_stream(
+    _auth_session: AuthSession,
+    CurrentPermissions(permissions): CurrentPermissions,
+    Extension(notifier): Extension<Notifier>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = notifier.subscribe();
+
+    let events = stream::unfold(receiver, move |mut receiver| {
+        async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(notification) if permissions.contains(notification.permission()) => {
+                        let event = Event::default()
+                            .event(notification.name())
+                            .json_data(&notification)
+                            .unwrap_or_default();
+                        return Some((Ok(event), receiver));
+                    }
+                    Ok(_) => {}
+                    // Tell the client it missed some, so it can refetch
+                    Err(RecvError::Lagged(_)) => {
+                        return Some((Ok(Event::default().event("lagged").data("")), receiver));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+}
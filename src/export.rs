@@ -0,0 +1,366 @@
+use std::{io::Write, time::Duration};
+
+use axum::{
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Router,
+};
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use sqlx::PgPool;
+use time::{
+    format_description::FormatItem, macros::format_description, OffsetDateTime, PrimitiveDateTime,
+};
+use tracing::instrument;
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    config::BackupConfig,
+    error::PhsError,
+    serve::{PageLayout, PageStatus, PageVisibility},
+    storage::{SharedStorage, Storage},
+};
+
+mod tar;
+
+use tar::TarWriter;
+
+/// Bumped whenever the layout of the archive changes, so a restore can tell
+/// what it's looking at.
+const FORMAT_VERSION: u32 = 1;
+
+/// Storage prefixes copied into the archive as they are.
+const FILE_PREFIXES: &[&str] = &["media/"];
+
+/// How often the backup job checks whether one is due.
+const CHECK_INTERVAL: Duration = Duration::from_hours(1);
+
+/// Sorts the same as the time it stands for, so the newest backup is the last
+/// key.
+const TIMESTAMP: &[FormatItem<'static>] =
+    format_description!("[year][month][day]T[hour][minute][second]Z");
+
+pub fn router() -> Router {
+    Router::new().route("/admin/export", get(get_export))
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    format: u32,
+    #[serde(with = "time::serde::iso8601")]
+    created_at: OffsetDateTime,
+    departments: usize,
+    categories: usize,
+    posts: usize,
+    pages: usize,
+    media: usize,
+    files: usize,
+}
+
+#[derive(Serialize)]
+struct ExportedDepartment {
+    id: i32,
+    department: String,
+}
+
+#[derive(Serialize)]
+struct ExportedCategory {
+    id: i32,
+    category: String,
+}
+
+#[derive(Serialize)]
+struct ExportedPost {
+    id: i32,
+    title: String,
+    content: String,
+    author: Option<i32>,
+    #[serde(with = "time::serde::iso8601")]
+    date: OffsetDateTime,
+    pinned: bool,
+    department: Option<i32>,
+    category: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct ExportedPage {
+    id: i32,
+    name: String,
+    parent_id: Option<i32>,
+    status: PageStatus,
+    layout: PageLayout,
+    visibility: PageVisibility,
+    data: Option<serde_json::Value>,
+    created_at: PrimitiveDateTime,
+    updated_at: PrimitiveDateTime,
+}
+
+#[derive(Serialize)]
+struct ExportedMedia {
+    id: i32,
+    filename: String,
+    mime: String,
+    size: i64,
+    alt_text: String,
+    uploader: Option<i32>,
+    uploaded_at: PrimitiveDateTime,
+}
+
+#[derive(Serialize)]
+struct ExportedVariant {
+    media_id: i32,
+    filename: String,
+    width: i32,
+    height: i32,
+    size: i64,
+}
+
+/// Downloads everything needed to rebuild the site's content: departments,
+/// categories, posts, pages and media, as JSON, plus the media files
+/// themselves, in a `.tar.gz`. Users, settings and anything in the trash
+/// aren't included.
+#[instrument(skip_all)]
+async fn get_export(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+) -> Result<Response, PhsError> {
+    let now = OffsetDateTime::now_utc();
+    let archive = export_archive(&pool, &*storage, now).await?;
+
+    let disposition = format!(
+        r#"attachment; filename="phs-export-{}.tar.gz""#,
+        timestamp(now)
+    );
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/gzip"),
+            ),
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition)
+                    .map_err(|e| PhsError::internal(e, "Invalid export file name"))?,
+            ),
+        ],
+        archive,
+    )
+        .into_response())
+}
+
+/// Builds the archive in memory. Media files are read one at a time, so only
+/// the compressed archive has to fit.
+///
+/// # Errors
+///
+/// Fails if the database or storage can't be read.
+async fn export_archive(
+    pool: &PgPool,
+    storage: &dyn Storage,
+    now: OffsetDateTime,
+) -> Result<Vec<u8>, PhsError> {
+    // One snapshot, so pages don't refer to posts or media that aren't there
+    let mut tx = pool.begin().await?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+
+    let departments = sqlx::query_as!(
+        ExportedDepartment,
+        "SELECT id, department FROM departments ORDER BY id"
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let categories = sqlx::query_as!(
+        ExportedCategory,
+        "SELECT id, category FROM categories ORDER BY id"
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let posts = sqlx::query_as!(
+        ExportedPost,
+        r#"
+        SELECT id, title, content, author, date, pinned, department, category
+        FROM posts
+        WHERE deleted_at IS NULL
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let pages = sqlx::query_as!(
+        ExportedPage,
+        r#"
+        SELECT id, name, parent_id,
+            modified as "status: PageStatus",
+            layout as "layout: PageLayout",
+            visibility as "visibility: PageVisibility",
+            data, created_at, updated_at
+        FROM pages
+        WHERE deleted_at IS NULL
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let media = sqlx::query_as!(
+        ExportedMedia,
+        r#"
+        SELECT id, filename, mime, size, alt_text, uploader, uploaded_at
+        FROM media
+        WHERE deleted_at IS NULL
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let variants = sqlx::query_as!(
+        ExportedVariant,
+        r#"
+        SELECT v.media_id, v.filename, v.width, v.height, v.size
+        FROM media_variants v
+        JOIN media m ON m.id = v.media_id
+        WHERE m.deleted_at IS NULL
+        ORDER BY v.media_id, v.filename
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let mut files = Vec::new();
+    for prefix in FILE_PREFIXES {
+        files.extend(storage.list(prefix).await?);
+    }
+
+    let manifest = Manifest {
+        format: FORMAT_VERSION,
+        created_at: now,
+        departments: departments.len(),
+        categories: categories.len(),
+        posts: posts.len(),
+        pages: pages.len(),
+        media: media.len(),
+        files: files.len(),
+    };
+
+    let mtime = now.unix_timestamp().try_into().unwrap_or_default();
+    let archive_error = |e| PhsError::internal(e, "Error whilst writing an export archive");
+
+    let mut archive = TarWriter::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for (path, json) in [
+        ("manifest.json", serde_json::to_vec_pretty(&manifest)?),
+        ("departments.json", serde_json::to_vec_pretty(&departments)?),
+        ("categories.json", serde_json::to_vec_pretty(&categories)?),
+        ("posts.json", serde_json::to_vec_pretty(&posts)?),
+        ("pages.json", serde_json::to_vec_pretty(&pages)?),
+        ("media.json", serde_json::to_vec_pretty(&media)?),
+        ("media_variants.json", serde_json::to_vec_pretty(&variants)?),
+    ] {
+        archive.append(path, &json, mtime).map_err(archive_error)?;
+    }
+
+    for key in files {
+        // Deleted since it was listed
+        let Some(object) = storage.get_object(&key).await? else {
+            continue;
+        };
+
+        let modified = object
+            .last_modified
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(mtime, |since| since.as_secs());
+
+        archive
+            .append(&key, &object.data, modified)
+            .map_err(archive_error)?;
+    }
+
+    let mut gzip = archive.finish().map_err(archive_error)?;
+    gzip.flush().map_err(archive_error)?;
+    gzip.finish().map_err(archive_error)
+}
+
+fn timestamp(time: OffsetDateTime) -> String {
+    time.format(TIMESTAMP).unwrap_or_default()
+}
+
+/// Saves an export to storage whenever the newest one there is older than
+/// [`BackupConfig::interval`], checking every [`CHECK_INTERVAL`], and keeps
+/// only the newest [`BackupConfig::keep`].
+///
+/// Going by what's in storage rather than a timer means restarts neither skip
+/// a backup nor make extra ones.
+pub async fn backup_job(pool: PgPool, storage: SharedStorage, config: BackupConfig) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match backup_if_due(&pool, &*storage, &config).await {
+            Ok(Some(key)) => tracing::info!(%key, "Saved a backup"),
+            Ok(None) => tracing::debug!("No backup due yet"),
+            Err(error) => tracing::error!(?error, "Failed to save a backup"),
+        }
+    }
+}
+
+async fn backup_if_due(
+    pool: &PgPool,
+    storage: &dyn Storage,
+    config: &BackupConfig,
+) -> Result<Option<String>, PhsError> {
+    let now = OffsetDateTime::now_utc();
+    let backups = list_backups(storage, config).await?;
+
+    // A backup is due if the newest is older than this
+    let due_before = format!(
+        "{}{}.tar.gz",
+        config.prefix,
+        timestamp(now - time::Duration::hours(config.interval.try_into().unwrap_or(i64::MAX)))
+    );
+    if backups.last().is_some_and(|newest| *newest > due_before) {
+        return Ok(None);
+    }
+
+    let key = format!("{}{}.tar.gz", config.prefix, timestamp(now));
+    storage
+        .put(&key, export_archive(pool, storage, now).await?)
+        .await?;
+
+    let backups = list_backups(storage, config).await?;
+    for old in backups.iter().rev().skip(config.keep) {
+        storage.delete(old).await?;
+        tracing::info!(key = %old, "Deleted an old backup");
+    }
+
+    Ok(Some(key))
+}
+
+/// Oldest first.
+async fn list_backups(
+    storage: &dyn Storage,
+    config: &BackupConfig,
+) -> Result<Vec<String>, PhsError> {
+    let mut backups = storage
+        .list(&config.prefix)
+        .await?
+        .into_iter()
+        .filter(|key| key.ends_with(".tar.gz"))
+        .collect::<Vec<_>>();
+    backups.sort_unstable();
+
+    Ok(backups)
+}
@@ -0,0 +1,107 @@
+use std::io::{self, Write};
+
+const BLOCK: usize = 512;
+
+/// Writes a ustar archive, with PAX headers for paths too long for ustar, so
+/// media with long file names survive. Only plain files are supported.
+pub struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub const fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Adds a file at `path`, modified at `mtime` seconds since the epoch.
+    pub fn append(&mut self, path: &str, data: &[u8], mtime: u64) -> io::Result<()> {
+        let (prefix, name) = match split_path(path) {
+            Some(split) => split,
+            None => {
+                let record = pax_record("path", path);
+                self.entry(b'x', "", "PaxHeader", record.as_bytes(), mtime)?;
+                // Readers that don't know PAX get a truncated name instead
+                ("", truncate(path, 100))
+            }
+        };
+
+        self.entry(b'0', prefix, name, data, mtime)
+    }
+
+    /// Writes the two empty blocks that end an archive, returning what it
+    /// was written to.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0; BLOCK * 2])?;
+        Ok(self.inner)
+    }
+
+    fn entry(
+        &mut self,
+        kind: u8,
+        prefix: &str,
+        name: &str,
+        data: &[u8],
+        mtime: u64,
+    ) -> io::Result<()> {
+        let mut header = [0_u8; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], mtime);
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        // Summed with the checksum field itself as spaces
+        header[148..156].fill(b' ');
+        let checksum = header.iter().map(|&b| u64::from(b)).sum();
+        octal(&mut header[148..155], checksum);
+
+        self.inner.write_all(&header)?;
+        self.inner.write_all(data)?;
+
+        let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+        self.inner.write_all(&[0; BLOCK][..padding])
+    }
+}
+
+/// Splits `path` into ustar's 155 byte prefix and 100 byte name, at a `/`.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = max.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// A NUL-terminated, zero-padded octal number filling `field`.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+/// `<length> <key>=<value>\n`, where the length counts itself.
+fn pax_record(key: &str, value: &str) -> String {
+    let body = format!(" {key}={value}\n");
+
+    let mut length = body.len() + 1;
+    while length != body.len() + length.to_string().len() {
+        length = body.len() + length.to_string().len();
+    }
+
+    format!("{length}{body}")
+}
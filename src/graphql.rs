@@ -0,0 +1,728 @@
+//! `/graphql`, with the `graphql` feature, for the admin dashboard's nested
+//! reads. It only reads: changes still go through the REST endpoints.
+//!
+//! Fields check the same permissions as the endpoints they mirror, as they are
+//! now rather than as they were when the user logged in. Objects referenced
+//! from others, such as a post's author, are loaded in batches per request, so
+//! a list doesn't cost a query per item.
+
+use std::collections::HashMap;
+
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    ComplexObject, Context, EmptyMutation, EmptySubscription, ErrorExtensions, Object, Schema,
+    SimpleObject,
+};
+use axum::{routing::post, Extension, Json, Router};
+use sqlx::PgPool;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::sync::OnceCell;
+
+use crate::{
+    auth::{AuthSession, Permission, PermissionCache, PermissionSet},
+    db::Db,
+    error::{ErrorCode, PhsError},
+    resources::Role,
+    serve::{PageLayout, PageStatus, PageVisibility},
+};
+
+/// Deepest a query can nest, so one request can't walk the whole database.
+const MAX_DEPTH: usize = 8;
+
+/// Most fields a query can ask for, counting each list's items once.
+const MAX_COMPLEXITY: usize = 1000;
+
+/// Most items a list field returns, and how many it returns if not asked.
+const MAX_LENGTH: i32 = 200;
+const DEFAULT_LENGTH: i32 = 20;
+
+type PhsSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+type Result<T> = std::result::Result<T, async_graphql::Error>;
+
+pub fn router() -> Router {
+    let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish();
+
+    Router::new()
+        .route("/graphql", post(execute))
+        .layer(Extension(schema))
+}
+
+async fn execute(
+    auth_session: Option<AuthSession>,
+
+    Extension(schema): Extension<PhsSchema>,
+    Extension(db): Extension<Db>,
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    let viewer = Viewer {
+        auth_session,
+        pool,
+        cache,
+        permissions: OnceCell::new(),
+    };
+
+    let request = request
+        .data(viewer)
+        .data(DataLoader::new(Loaders(db.clone()), tokio::spawn))
+        .data(db);
+
+    Json(schema.execute(request).await)
+}
+
+/// Whoever made the request, and what they may see.
+struct Viewer {
+    auth_session: Option<AuthSession>,
+    pool: PgPool,
+    cache: PermissionCache,
+    /// Looked up the first time a field needs them.
+    permissions: OnceCell<PermissionSet>,
+}
+
+impl Viewer {
+    async fn require(&self, permission: Permission) -> std::result::Result<(), PhsError> {
+        let Some(auth_session) = &self.auth_session else {
+            return Err(PhsError::client(
+                ErrorCode::NotLoggedIn,
+                "You need to log in first",
+            ));
+        };
+
+        let permissions = self
+            .permissions
+            .get_or_try_init(|| async {
+                let permissions = self.cache.get(&self.pool, auth_session.data().id()).await?;
+                Ok::<_, PhsError>(permissions.into_iter().collect())
+            })
+            .await?;
+
+        if permissions.contains(permission) {
+            Ok(())
+        } else {
+            Err(PhsError::client(
+                ErrorCode::MissingPermission,
+                "Missing permission",
+            ))
+        }
+    }
+}
+
+/// Errors as GraphQL reports them, with the same `code` as a problem body.
+/// Internal ones are logged and left vague, as they are over REST.
+impl From<PhsError> for async_graphql::Error {
+    fn from(error: PhsError) -> Self {
+        let code = error.code();
+        let message = match error {
+            PhsError::Client { detail, .. } | PhsError::Invalid { detail, .. } => {
+                detail.into_owned()
+            }
+            PhsError::Internal { source, context } => {
+                tracing::error!(error = ?source, "GraphQL error: {context}");
+                "Internal Server Error".to_owned()
+            }
+        };
+
+        Self::new(message).extend_with(|_, extensions| {
+            extensions.set(
+                "code",
+                async_graphql::to_value(code).unwrap_or(async_graphql::Value::Null),
+            );
+        })
+    }
+}
+
+/// How many items a list field should return, as asked for.
+fn length(first: Option<i32>) -> i64 {
+    i64::from(first.unwrap_or(DEFAULT_LENGTH).clamp(1, MAX_LENGTH))
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex)]
+struct Department {
+    id: i32,
+    department: String,
+}
+
+#[ComplexObject]
+impl Department {
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<i32>,
+    ) -> Result<Vec<Post>> {
+        let filter = PostFilter {
+            department: Some(self.id),
+            ..PostFilter::default()
+        };
+        Ok(list_posts(ctx.data()?, filter, first, after).await?)
+    }
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex)]
+struct Category {
+    id: i32,
+    category: String,
+}
+
+#[ComplexObject]
+impl Category {
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<i32>,
+    ) -> Result<Vec<Post>> {
+        let filter = PostFilter {
+            category: Some(self.id),
+            ..PostFilter::default()
+        };
+        Ok(list_posts(ctx.data()?, filter, first, after).await?)
+    }
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex)]
+struct Post {
+    id: i32,
+    title: String,
+    content: String,
+    date: OffsetDateTime,
+    pinned: bool,
+    /// Whether search engines should be asked to leave it out.
+    noindex: bool,
+    /// For search results and link previews.
+    meta_description: Option<String>,
+    og_title: Option<String>,
+    og_image: Option<String>,
+
+    #[graphql(skip)]
+    author_id: Option<i32>,
+    #[graphql(skip)]
+    department_id: Option<i32>,
+    #[graphql(skip)]
+    category_id: Option<i32>,
+}
+
+#[ComplexObject]
+impl Post {
+    async fn author(&self, ctx: &Context<'_>) -> Result<Option<Author>> {
+        let user: Option<User> = load(ctx, self.author_id.map(UserId)).await?;
+        Ok(user.map(Author::from))
+    }
+
+    async fn department(&self, ctx: &Context<'_>) -> Result<Option<Department>> {
+        load(ctx, self.department_id.map(DepartmentId)).await
+    }
+
+    async fn category(&self, ctx: &Context<'_>) -> Result<Option<Category>> {
+        load(ctx, self.category_id.map(CategoryId)).await
+    }
+}
+
+/// Who wrote a post, as anyone reading it can see them.
+#[derive(SimpleObject)]
+struct Author {
+    id: i32,
+    name: String,
+    description: String,
+}
+
+impl From<User> for Author {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            name: user.name,
+            description: user.description,
+        }
+    }
+}
+
+#[derive(Default)]
+struct PostFilter {
+    department: Option<i32>,
+    category: Option<i32>,
+    author: Option<i32>,
+}
+
+/// Posts in order of ID, starting after `after`.
+async fn list_posts(
+    db: &Db,
+    filter: PostFilter,
+    first: Option<i32>,
+    after: Option<i32>,
+) -> std::result::Result<Vec<Post>, PhsError> {
+    db.timed(
+        "graphql_list_posts",
+        sqlx::query_as!(
+            Post,
+            r#"
+            SELECT id, title, content, date, pinned, noindex, meta_description, og_title, og_image,
+              author AS author_id, department AS department_id, category AS category_id
+            FROM posts
+            WHERE id > $1 AND deleted_at IS NULL AND published
+              AND ($2::int IS NULL OR department = $2)
+              AND ($3::int IS NULL OR category = $3)
+              AND ($4::int IS NULL OR author = $4)
+            ORDER BY id
+            LIMIT $5
+            "#,
+            after.unwrap_or_default(),
+            filter.department,
+            filter.category,
+            filter.author,
+            length(first)
+        )
+        .fetch_all(db.read()),
+    )
+    .await
+    .map_err(Into::into)
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex)]
+struct User {
+    id: i32,
+    username: String,
+    name: String,
+    email: Option<String>,
+    description: String,
+    role: Role,
+    permissions: Vec<Permission>,
+
+    #[graphql(skip)]
+    department_id: Option<i32>,
+}
+
+#[ComplexObject]
+impl User {
+    async fn department(&self, ctx: &Context<'_>) -> Result<Option<Department>> {
+        load(ctx, self.department_id.map(DepartmentId)).await
+    }
+
+    /// Posts they wrote.
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<i32>,
+    ) -> Result<Vec<Post>> {
+        let filter = PostFilter {
+            author: Some(self.id),
+            ..PostFilter::default()
+        };
+        Ok(list_posts(ctx.data()?, filter, first, after).await?)
+    }
+
+    /// Needs the `ManagePermissions` permission.
+    async fn groups(&self, ctx: &Context<'_>) -> Result<Vec<Group>> {
+        ctx.data::<Viewer>()?
+            .require(Permission::ManagePermissions)
+            .await?;
+
+        let db = ctx.data::<Db>()?;
+        let groups = db
+            .timed(
+                "graphql_list_user_groups",
+                sqlx::query_as!(
+                    Group,
+                    r#"
+                    SELECT g.id, g.group_name, g.permissions AS "permissions: Vec<Permission>"
+                    FROM users_groups ug
+                    JOIN groups g ON g.id = ug.group_id
+                    WHERE ug.user_id = $1
+                    ORDER BY g.group_name
+                    "#,
+                    self.id
+                )
+                .fetch_all(db.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(groups)
+    }
+}
+
+#[derive(SimpleObject)]
+struct Group {
+    id: i32,
+    group_name: String,
+    permissions: Vec<Permission>,
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex)]
+struct Page {
+    id: i32,
+    name: String,
+    created_at: PrimitiveDateTime,
+    updated_at: PrimitiveDateTime,
+    modified: PageStatus,
+    layout: PageLayout,
+    visibility: PageVisibility,
+
+    #[graphql(skip)]
+    parent_id: Option<i32>,
+}
+
+#[ComplexObject]
+impl Page {
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<Self>> {
+        load(ctx, self.parent_id.map(PageId)).await
+    }
+
+    async fn children(&self, ctx: &Context<'_>) -> Result<Vec<Self>> {
+        let db = ctx.data::<Db>()?;
+        let children = db
+            .timed(
+                "graphql_list_child_pages",
+                sqlx::query_as!(
+                    Page,
+                    r#"
+                    SELECT id, name, created_at, updated_at,
+                      modified AS "modified: PageStatus", layout AS "layout: PageLayout",
+                      visibility AS "visibility: PageVisibility", parent_id
+                    FROM pages
+                    WHERE parent_id = $1 AND deleted_at IS NULL
+                    ORDER BY name
+                    "#,
+                    self.id
+                )
+                .fetch_all(db.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(children)
+    }
+}
+
+struct Query;
+
+#[Object]
+impl Query {
+    async fn departments(&self, ctx: &Context<'_>) -> Result<Vec<Department>> {
+        let db = ctx.data::<Db>()?;
+        let departments = db
+            .timed(
+                "list_departments",
+                sqlx::query_as!(
+                    Department,
+                    r#"SELECT id, department FROM departments LIMIT 100"#
+                )
+                .fetch_all(db.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(departments)
+    }
+
+    async fn department(&self, ctx: &Context<'_>, id: i32) -> Result<Option<Department>> {
+        load(ctx, Some(DepartmentId(id))).await
+    }
+
+    async fn categories(&self, ctx: &Context<'_>) -> Result<Vec<Category>> {
+        let db = ctx.data::<Db>()?;
+        let categories = db
+            .timed(
+                "list_categories",
+                sqlx::query_as!(Category, "SELECT id, category FROM categories LIMIT 100")
+                    .fetch_all(db.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(categories)
+    }
+
+    async fn category(&self, ctx: &Context<'_>, id: i32) -> Result<Option<Category>> {
+        load(ctx, Some(CategoryId(id))).await
+    }
+
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<i32>,
+        department: Option<i32>,
+        category: Option<i32>,
+        author: Option<i32>,
+    ) -> Result<Vec<Post>> {
+        let filter = PostFilter {
+            department,
+            category,
+            author,
+        };
+        Ok(list_posts(ctx.data()?, filter, first, after).await?)
+    }
+
+    async fn post(&self, ctx: &Context<'_>, id: i32) -> Result<Option<Post>> {
+        let db = ctx.data::<Db>()?;
+        let post = db
+            .timed(
+                "graphql_get_post",
+                sqlx::query_as!(
+                    Post,
+                    r#"
+                    SELECT id, title, content, date, pinned, noindex, meta_description, og_title, og_image,
+                      author AS author_id, department AS department_id, category AS category_id
+                    FROM posts
+                    WHERE id = $1 AND deleted_at IS NULL AND published
+                    "#,
+                    id
+                )
+                .fetch_optional(db.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(post)
+    }
+
+    /// Needs the `ManageUsers` permission.
+    async fn users(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<i32>,
+    ) -> Result<Vec<User>> {
+        ctx.data::<Viewer>()?
+            .require(Permission::ManageUsers)
+            .await?;
+
+        let db = ctx.data::<Db>()?;
+        let users = db
+            .timed(
+                "graphql_list_users",
+                sqlx::query_as!(
+                    User,
+                    r#"
+                    SELECT id, username, name, email, description, role AS "role: Role",
+                      permissions AS "permissions: Vec<Permission>", department AS department_id
+                    FROM users
+                    WHERE id > $1 AND deleted_at IS NULL
+                    ORDER BY id
+                    LIMIT $2
+                    "#,
+                    after.unwrap_or_default(),
+                    length(first)
+                )
+                .fetch_all(db.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(users)
+    }
+
+    /// Needs the `ManageUsers` permission.
+    async fn user(&self, ctx: &Context<'_>, id: i32) -> Result<Option<User>> {
+        ctx.data::<Viewer>()?
+            .require(Permission::ManageUsers)
+            .await?;
+
+        load(ctx, Some(UserId(id))).await
+    }
+
+    /// Needs the `ManagePages` permission.
+    async fn pages(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<i32>,
+    ) -> Result<Vec<Page>> {
+        ctx.data::<Viewer>()?
+            .require(Permission::ManagePages)
+            .await?;
+
+        let db = ctx.data::<Db>()?;
+        let pages = db
+            .timed(
+                "graphql_list_pages",
+                sqlx::query_as!(
+                    Page,
+                    r#"
+                    SELECT id, name, created_at, updated_at,
+                      modified AS "modified: PageStatus", layout AS "layout: PageLayout",
+                      visibility AS "visibility: PageVisibility", parent_id
+                    FROM pages
+                    WHERE id > $1 AND deleted_at IS NULL
+                    ORDER BY id
+                    LIMIT $2
+                    "#,
+                    after.unwrap_or_default(),
+                    length(first)
+                )
+                .fetch_all(db.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(pages)
+    }
+
+    /// Needs the `ManagePages` permission.
+    async fn page(&self, ctx: &Context<'_>, id: i32) -> Result<Option<Page>> {
+        ctx.data::<Viewer>()?
+            .require(Permission::ManagePages)
+            .await?;
+
+        load(ctx, Some(PageId(id))).await
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct DepartmentId(i32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct CategoryId(i32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct UserId(i32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct PageId(i32);
+
+/// Loads what's referenced by ID, all at once for each kind.
+struct Loaders(Db);
+
+/// The object with this ID, if there's an ID and it exists.
+async fn load<K, V>(ctx: &Context<'_>, id: Option<K>) -> Result<Option<V>>
+where
+    K: Send + Sync + Copy + Eq + std::hash::Hash + 'static,
+    V: Send + Sync + Clone + 'static,
+    Loaders: Loader<K, Value = V, Error = async_graphql::Error>,
+{
+    let Some(id) = id else {
+        return Ok(None);
+    };
+
+    ctx.data::<DataLoader<Loaders>>()?.load_one(id).await
+}
+
+impl Loader<DepartmentId> for Loaders {
+    type Value = Department;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[DepartmentId]) -> Result<HashMap<DepartmentId, Department>> {
+        let ids: Vec<_> = keys.iter().map(|key| key.0).collect();
+        let departments = self
+            .0
+            .timed(
+                "graphql_load_departments",
+                sqlx::query_as!(
+                    Department,
+                    "SELECT id, department FROM departments WHERE id = ANY($1)",
+                    &ids
+                )
+                .fetch_all(self.0.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(departments
+            .into_iter()
+            .map(|department| (DepartmentId(department.id), department))
+            .collect())
+    }
+}
+
+impl Loader<CategoryId> for Loaders {
+    type Value = Category;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[CategoryId]) -> Result<HashMap<CategoryId, Category>> {
+        let ids: Vec<_> = keys.iter().map(|key| key.0).collect();
+        let categories = self
+            .0
+            .timed(
+                "graphql_load_categories",
+                sqlx::query_as!(
+                    Category,
+                    "SELECT id, category FROM categories WHERE id = ANY($1)",
+                    &ids
+                )
+                .fetch_all(self.0.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(categories
+            .into_iter()
+            .map(|category| (CategoryId(category.id), category))
+            .collect())
+    }
+}
+
+impl Loader<UserId> for Loaders {
+    type Value = User;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[UserId]) -> Result<HashMap<UserId, User>> {
+        let ids: Vec<_> = keys.iter().map(|key| key.0).collect();
+        let users = self
+            .0
+            .timed(
+                "graphql_load_users",
+                sqlx::query_as!(
+                    User,
+                    r#"
+                    SELECT id, username, name, email, description, role AS "role: Role",
+                      permissions AS "permissions: Vec<Permission>", department AS department_id
+                    FROM users
+                    WHERE id = ANY($1) AND deleted_at IS NULL
+                    "#,
+                    &ids
+                )
+                .fetch_all(self.0.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(users
+            .into_iter()
+            .map(|user| (UserId(user.id), user))
+            .collect())
+    }
+}
+
+impl Loader<PageId> for Loaders {
+    type Value = Page;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[PageId]) -> Result<HashMap<PageId, Page>> {
+        let ids: Vec<_> = keys.iter().map(|key| key.0).collect();
+        let pages = self
+            .0
+            .timed(
+                "graphql_load_pages",
+                sqlx::query_as!(
+                    Page,
+                    r#"
+                    SELECT id, name, created_at, updated_at,
+                      modified AS "modified: PageStatus", layout AS "layout: PageLayout",
+                      visibility AS "visibility: PageVisibility", parent_id
+                    FROM pages
+                    WHERE id = ANY($1) AND deleted_at IS NULL
+                    "#,
+                    &ids
+                )
+                .fetch_all(self.0.read()),
+            )
+            .await
+            .map_err(PhsError::from)?;
+
+        Ok(pages
+            .into_iter()
+            .map(|page| (PageId(page.id), page))
+            .collect())
+    }
+}
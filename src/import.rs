@@ -0,0 +1,541 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{body::Bytes, extract::Query, routing::post, Extension, Json, Router};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    error::{ErrorCode, PhsError},
+    media::{create_media, sanitise_filename, Scanner},
+    response_cache::{ResponseCache, Scope},
+    serve::{LinkChecker, TeraPool},
+    storage::SharedStorage,
+    ServerConfig,
+};
+
+mod content;
+mod wxr;
+
+use wxr::{Export, Item};
+
+/// How many attachments are downloaded at once.
+const CONCURRENCY: usize = 4;
+
+/// As stored, for both post titles and category names.
+const MAX_NAME_LENGTH: usize = 255;
+
+pub fn router() -> Router {
+    // Capped by `limits.upload` rather than the usual body limit
+    Router::new().route("/admin/import/wordpress", post(import_wordpress))
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportOptions {
+    /// Report what would be imported without changing anything.
+    #[serde(default)]
+    dry_run: bool,
+    /// Don't download attachments, leaving posts linking to the old site for
+    /// their images.
+    #[serde(default)]
+    skip_media: bool,
+}
+
+#[derive(Serialize, Default)]
+struct ImportReport {
+    dry_run: bool,
+    /// Categories that were, or would be, created. Existing ones are reused.
+    new_categories: Vec<String>,
+    /// Or would be, in a dry run.
+    imported: usize,
+    skipped: usize,
+    failed: usize,
+    items: Vec<ItemReport>,
+}
+
+impl ImportReport {
+    fn add(&mut self, item: ItemReport) {
+        match item.outcome {
+            Outcome::Imported | Outcome::WouldImport => self.imported += 1,
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::Failed => self.failed += 1,
+        }
+        self.items.push(item);
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Imported,
+    WouldImport,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize)]
+struct ItemReport {
+    wordpress_id: Option<i32>,
+    /// WordPress's post type, e.g. `post` or `attachment`.
+    r#type: String,
+    title: String,
+    outcome: Outcome,
+    /// The post or media ID it was imported as, or already had been.
+    id: Option<i32>,
+    /// Why it was skipped or failed, and anything brought across differently.
+    notes: Vec<String>,
+}
+
+impl ItemReport {
+    fn new(item: &Item, outcome: Outcome) -> Self {
+        Self {
+            wordpress_id: item.id,
+            r#type: item.post_type.clone(),
+            title: item.title.clone(),
+            outcome,
+            id: None,
+            notes: Vec::new(),
+        }
+    }
+
+    fn note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+/// Brings news across from a WordPress site, from the WXR file made by its
+/// Tools > Export page, sent as the body.
+///
+/// Published posts are imported with their author, matched by username, their
+/// first category, which is created if it doesn't exist, and whether they were
+/// sticky. Attachments are downloaded from the old site into the media
+/// library, and links to them in posts pointed at the new copies. Anything
+/// already imported is skipped, so an import can be run again after fixing
+/// what failed.
+///
+/// Every item in the export is listed in the report, with what happened to
+/// it.
+#[instrument(skip(
+    pool,
+    storage,
+    scanner,
+    tera,
+    response_cache,
+    config,
+    auth_session,
+    body
+))]
+#[allow(clippy::too_many_arguments)]
+async fn import_wordpress(
+    auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(scanner): Extension<Scanner>,
+    Extension(tera): Extension<Arc<TeraPool>>,
+    Extension(response_cache): Extension<ResponseCache>,
+    Extension(config): Extension<ServerConfig>,
+    Query(options): Query<ImportOptions>,
+    body: Bytes,
+) -> Result<Json<ImportReport>, PhsError> {
+    let xml = std::str::from_utf8(&body)
+        .map_err(|_| PhsError::client(ErrorCode::BadRequest, "The export isn't valid UTF-8"))?;
+    let export = wxr::parse(xml).map_err(|e| PhsError::client(ErrorCode::BadRequest, e))?;
+
+    let mut report = ImportReport {
+        dry_run: options.dry_run,
+        ..ImportReport::default()
+    };
+    let importer = auth_session.data().id();
+
+    let categories =
+        import_categories(&pool, &export, importer, options.dry_run, &mut report).await?;
+
+    let mut media = Vec::new();
+    let attachments = export
+        .items
+        .iter()
+        .filter(|item| item.post_type == "attachment");
+
+    if options.skip_media {
+        for item in attachments {
+            report.add(ItemReport::new(item, Outcome::Skipped).note("Attachments were skipped"));
+        }
+    } else {
+        let checker = LinkChecker::new(&config.site_url)
+            .map_err(|e| PhsError::internal(e, "Couldn't set up a client to download with"))?;
+        let results = stream::iter(attachments)
+            .map(|item| {
+                import_attachment(
+                    &pool,
+                    &storage,
+                    &scanner,
+                    &checker,
+                    item,
+                    importer,
+                    config.limits.upload,
+                    options.dry_run,
+                )
+            })
+            .buffered(CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+
+        for (item, urls) in results {
+            report.add(item);
+            media.extend(urls);
+        }
+    }
+
+    let users = sqlx::query!("SELECT id, username FROM users")
+        .fetch_all(&pool)
+        .await?
+        .into_iter()
+        .map(|user| (user.username, user.id))
+        .collect::<HashMap<_, _>>();
+
+    for item in &export.items {
+        match item.post_type.as_str() {
+            "attachment" => {}
+            "post" => {
+                let item = import_post(
+                    &pool,
+                    item,
+                    &categories,
+                    &users,
+                    &media,
+                    importer,
+                    options.dry_run,
+                )
+                .await;
+                report.add(item);
+            }
+            other => report.add(ItemReport::new(item, Outcome::Skipped).note(format!(
+                "Only posts and attachments are imported, not {other}s"
+            ))),
+        }
+    }
+
+    if !options.dry_run && report.imported > 0 {
+        tera.invalidate_cache().await;
+        // Posts go with it
+        response_cache.invalidate(Scope::Categories).await;
+    }
+
+    tracing::info!(
+        dry_run = options.dry_run,
+        imported = report.imported,
+        skipped = report.skipped,
+        failed = report.failed,
+        "Imported from WordPress"
+    );
+
+    Ok(Json(report))
+}
+
+/// Every category named in the export, by name, with their IDs here. In a dry
+/// run, those that don't exist yet are only reported.
+async fn import_categories(
+    pool: &PgPool,
+    export: &Export,
+    importer: i32,
+    dry_run: bool,
+    report: &mut ImportReport,
+) -> Result<HashMap<String, i32>, PhsError> {
+    let mut names = export.categories.clone();
+    for item in &export.items {
+        names.extend(item.categories.iter().cloned());
+    }
+
+    let mut ids = HashMap::new();
+    for name in names {
+        if ids.contains_key(&name)
+            || report.new_categories.contains(&name)
+            || name.chars().count() > MAX_NAME_LENGTH
+        {
+            continue;
+        }
+
+        let existing = sqlx::query_scalar!("SELECT id FROM categories WHERE category = $1", name)
+            .fetch_optional(pool)
+            .await?;
+
+        let id = match existing {
+            Some(id) => id,
+            None if dry_run => {
+                report.new_categories.push(name);
+                continue;
+            }
+            None => {
+                report.new_categories.push(name.clone());
+                sqlx::query_scalar!(
+                    r#"
+                    INSERT INTO categories (category, created_by, updated_by)
+                    VALUES ($1, $2, $2)
+                    ON CONFLICT (category) DO UPDATE SET category = EXCLUDED.category
+                    RETURNING id
+                    "#,
+                    name,
+                    importer
+                )
+                .fetch_one(pool)
+                .await?
+            }
+        };
+        ids.insert(name, id);
+    }
+
+    Ok(ids)
+}
+
+/// Downloads an attachment into the media library, returning its old and new
+/// URLs for rewriting links in posts. Attachments the scanner flags are
+/// quarantined and reported as failed.
+#[allow(clippy::too_many_arguments)]
+async fn import_attachment(
+    pool: &PgPool,
+    storage: &SharedStorage,
+    scanner: &Scanner,
+    checker: &LinkChecker,
+    item: &Item,
+    uploader: i32,
+    limit: usize,
+    dry_run: bool,
+) -> (ItemReport, Option<(String, String)>) {
+    let report = ItemReport::new(item, Outcome::Failed);
+
+    let Some(wordpress_id) = item.id else {
+        return (report.note("No post ID"), None);
+    };
+    let Some(url) = item.attachment_url.clone() else {
+        return (report.note("No attachment URL"), None);
+    };
+
+    match sqlx::query!(
+        r#"
+        SELECT m.id, m.filename
+        FROM wordpress_media w
+        JOIN media m ON m.id = w.media_id
+        WHERE w.wordpress_id = $1
+        "#,
+        wordpress_id
+    )
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(Some(existing)) => {
+            let report = ItemReport {
+                outcome: Outcome::Skipped,
+                id: Some(existing.id),
+                ..report.note("Already imported")
+            };
+            let ours = format!("/media/{}/{}", existing.id, existing.filename);
+            return (report, Some((url, ours)));
+        }
+        Ok(None) => {}
+        Err(e) => return (report.note(describe(e.into())), None),
+    }
+
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .map(|name| percent_encoding::percent_decode_str(name).decode_utf8_lossy())
+        .unwrap_or_default();
+
+    if let Err(e) = sanitise_filename(&name) {
+        let report = ItemReport {
+            outcome: Outcome::Skipped,
+            ..report
+        };
+        return (report.note(describe(e)), None);
+    }
+
+    if dry_run {
+        let report = ItemReport {
+            outcome: Outcome::WouldImport,
+            ..report
+        };
+        return (report, None);
+    }
+
+    let data = match checker.fetch(&url, limit).await {
+        Ok(data) => data,
+        Err(e) => return (report.note(e), None),
+    };
+
+    let imported = async {
+        let media = create_media(
+            pool,
+            storage.clone(),
+            scanner,
+            &name,
+            data,
+            "",
+            Some(uploader),
+        )
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO wordpress_media (wordpress_id, media_id) VALUES ($1, $2)
+            ON CONFLICT (wordpress_id) DO NOTHING
+            "#,
+            wordpress_id,
+            media.id()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok::<_, PhsError>(media)
+    }
+    .await;
+
+    match imported {
+        Ok(media) => {
+            let report = ItemReport {
+                outcome: Outcome::Imported,
+                id: Some(media.id()),
+                ..report
+            };
+            (report, Some((url, media.url())))
+        }
+        Err(e) => (report.note(describe(e)), None),
+    }
+}
+
+async fn import_post(
+    pool: &PgPool,
+    item: &Item,
+    categories: &HashMap<String, i32>,
+    users: &HashMap<String, i32>,
+    media: &[(String, String)],
+    importer: i32,
+    dry_run: bool,
+) -> ItemReport {
+    let mut report = ItemReport::new(item, Outcome::Failed);
+
+    let Some(wordpress_id) = item.id else {
+        return report.note("No post ID");
+    };
+
+    if item.status != "publish" {
+        report.outcome = Outcome::Skipped;
+        return report.note(format!("Not published ({})", item.status));
+    }
+
+    match sqlx::query_scalar!(
+        "SELECT post_id FROM wordpress_posts WHERE wordpress_id = $1",
+        wordpress_id
+    )
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(Some(id)) => {
+            report.outcome = Outcome::Skipped;
+            report.id = Some(id);
+            return report.note("Already imported");
+        }
+        Ok(None) => {}
+        Err(e) => return report.note(describe(e.into())),
+    }
+
+    if item.title.is_empty() {
+        return report.note("No title");
+    }
+    if item.title.chars().count() > MAX_NAME_LENGTH {
+        return report.note(format!("Title is longer than {MAX_NAME_LENGTH} characters"));
+    }
+    let Some(date) = item.date else {
+        return report.note("No publication date");
+    };
+
+    let author = users.get(&item.creator).copied();
+    if author.is_none() {
+        report.notes.push(format!(
+            "No user is called {}, so it has no author",
+            item.creator
+        ));
+    }
+
+    let category = item.categories.first();
+    if item.categories.len() > 1 {
+        report.notes.push(format!(
+            "Posts only have one category, so only {} was kept",
+            category.map_or("", String::as_str)
+        ));
+    }
+    let category_id = category.and_then(|name| categories.get(name).copied());
+
+    let content = content::convert(&item.content, media);
+    match content::remaining_uploads(&content) {
+        0 => {}
+        n => report
+            .notes
+            .push(format!("Still links to {n} uploads on the old site")),
+    }
+
+    if dry_run {
+        report.outcome = Outcome::WouldImport;
+        return report;
+    }
+
+    let imported = async {
+        let mut tx = pool.begin().await?;
+
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO posts (title, content, author, pinned, category, date, created_by, updated_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $7)
+            RETURNING id
+            "#,
+            item.title,
+            content,
+            author,
+            item.sticky,
+            category_id,
+            date,
+            importer
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO wordpress_posts (wordpress_id, post_id) VALUES ($1, $2)",
+            wordpress_id,
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok::<_, PhsError>(id)
+    }
+    .await;
+
+    match imported {
+        Ok(id) => {
+            report.outcome = Outcome::Imported;
+            report.id = Some(id);
+            report
+        }
+        Err(e) => report.note(describe(e)),
+    }
+}
+
+/// What went wrong, for the report. Our own failures are logged and only
+/// described vaguely, as they would be in a response.
+fn describe(error: PhsError) -> String {
+    match error {
+        PhsError::Client { detail, .. } | PhsError::Invalid { detail, .. } => detail.into_owned(),
+        PhsError::Internal { source, context } => {
+            tracing::error!(error = ?source, "Import item failed: {context}");
+            "Internal error; see the server's logs".into()
+        }
+    }
+}
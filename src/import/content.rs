@@ -0,0 +1,141 @@
+//! Turns WordPress post content into the HTML our posts hold.
+
+/// Where WordPress keeps uploads, for spotting links to any left behind.
+pub const UPLOADS_PATH: &str = "/wp-content/uploads/";
+
+/// Tags that already start a block, so aren't wrapped in a paragraph.
+const BLOCK_TAGS: &[&str] = &[
+    "<h1",
+    "<h2",
+    "<h3",
+    "<h4",
+    "<h5",
+    "<h6",
+    "<ul",
+    "<ol",
+    "<li",
+    "<blockquote",
+    "<figure",
+    "<div",
+    "<table",
+    "<pre",
+    "<hr",
+    "<iframe",
+];
+
+/// Strips block editor comments, points links to uploads at where they were
+/// imported to, and adds the paragraphs WordPress only adds when rendering.
+///
+/// `media` pairs each attachment's URL on the old site with its URL here.
+pub fn convert(content: &str, media: &[(String, String)]) -> String {
+    let mut content = strip_block_comments(&content.replace("\r\n", "\n"));
+
+    for (from, to) in media {
+        content = replace_upload(&content, from, to);
+    }
+
+    if content.contains("<p>") || content.contains("<p ") {
+        content
+    } else {
+        add_paragraphs(&content)
+    }
+}
+
+/// How many links to the old site's uploads are left in converted content.
+pub fn remaining_uploads(content: &str) -> usize {
+    content.matches(UPLOADS_PATH).count()
+}
+
+/// `<!-- wp:paragraph -->` and the like, which only mean something to the
+/// block editor.
+fn strip_block_comments(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find("<!--") {
+        let comment = rest[start + 4..].trim_start();
+        let Some(end) = rest[start..].find("-->").map(|i| start + i + 3) else {
+            break;
+        };
+
+        out.push_str(&rest[..start]);
+        if !(comment.starts_with("wp:") || comment.starts_with("/wp:")) {
+            out.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// Replaces every link to the upload at `from`, over either scheme, and to
+/// the resized copies WordPress names like `photo-300x200.jpg`, with `to`.
+fn replace_upload(content: &str, from: &str, to: &str) -> String {
+    let bare = from.split_once("://").map_or(from, |(_, rest)| rest);
+    let Some((stem, extension)) = bare.rsplit_once('.') else {
+        return content.replace(from, to);
+    };
+    let extension = format!(".{extension}");
+
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(i) = rest.find(stem) {
+        let before = &rest[..i];
+        let after = &rest[i + stem.len()..];
+
+        let scheme = ["https://", "http://", "//"]
+            .into_iter()
+            .find(|scheme| before.ends_with(scheme));
+        let length = resized_suffix(after)
+            .map(|suffix| suffix + extension.len())
+            .filter(|&length| after[length - extension.len()..].starts_with(&extension))
+            .or_else(|| after.starts_with(&extension).then_some(extension.len()));
+
+        match (scheme, length) {
+            (Some(scheme), Some(length)) => {
+                out.push_str(&before[..before.len() - scheme.len()]);
+                out.push_str(to);
+                rest = &after[length..];
+            }
+            _ => {
+                out.push_str(&rest[..i + stem.len()]);
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// The length of a `-300x200` at the start of `s`, if there is one.
+fn resized_suffix(s: &str) -> Option<usize> {
+    let rest = s.strip_prefix('-')?;
+    let width = rest.find(|c: char| !c.is_ascii_digit())?;
+    let height = rest[width..].strip_prefix('x')?;
+    let height_len = height
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(height.len());
+
+    (width > 0 && height_len > 0).then_some(1 + width + 1 + height_len)
+}
+
+/// Wraps each run of text between blank lines in `<p>`, with single line
+/// breaks kept as `<br>`, as WordPress does when it shows a post.
+fn add_paragraphs(content: &str) -> String {
+    content
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|block| !block.is_empty())
+        .map(|block| {
+            if BLOCK_TAGS.iter().any(|tag| block.starts_with(tag)) {
+                block.to_owned()
+            } else {
+                format!("<p>{}</p>", block.replace('\n', "<br>\n"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
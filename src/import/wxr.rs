@@ -0,0 +1,255 @@
+//! Just enough of WordPress's WXR export format, an RSS feed with extra
+//! elements, to read posts, categories and attachments out of it. Content is
+//! in CDATA sections, which are skipped over when looking for tags, so markup
+//! inside a post can't be mistaken for the export's own.
+
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+const CDATA_START: &str = "<![CDATA[";
+const CDATA_END: &str = "]]>";
+
+pub struct Export {
+    pub categories: Vec<String>,
+    pub items: Vec<Item>,
+}
+
+pub struct Item {
+    pub id: Option<i32>,
+    pub title: String,
+    /// The author's username.
+    pub creator: String,
+    pub content: String,
+    /// `post`, `page`, `attachment`, `nav_menu_item` and so on.
+    pub post_type: String,
+    /// `publish`, `draft`, `private` and so on.
+    pub status: String,
+    pub date: Option<OffsetDateTime>,
+    pub sticky: bool,
+    /// Names, in the order given.
+    pub categories: Vec<String>,
+    pub attachment_url: Option<String>,
+}
+
+/// # Errors
+///
+/// Fails if this doesn't look like a WXR file at all. Anything missing from
+/// an item is left empty for the caller to report on.
+pub fn parse(xml: &str) -> Result<Export, &'static str> {
+    if find(xml, 0, "<rss").is_none() || find(xml, 0, "<wp:wxr_version").is_none() {
+        return Err("This isn't a WordPress export (WXR) file");
+    }
+
+    let categories = elements(xml, "wp:category")
+        .into_iter()
+        .filter_map(|category| first_text(category.inner, "wp:cat_name"))
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    let items = elements(xml, "item")
+        .into_iter()
+        .map(|item| parse_item(item.inner))
+        .collect();
+
+    Ok(Export { categories, items })
+}
+
+fn parse_item(xml: &str) -> Item {
+    let text = |tag| first_text(xml, tag).unwrap_or_default();
+
+    let date = first_text(xml, "wp:post_date_gmt")
+        .and_then(|date| parse_date(&date))
+        .or_else(|| first_text(xml, "wp:post_date").and_then(|date| parse_date(&date)));
+
+    Item {
+        id: text("wp:post_id").trim().parse().ok(),
+        title: text("title").trim().to_owned(),
+        creator: text("dc:creator").trim().to_owned(),
+        content: text("content:encoded"),
+        post_type: text("wp:post_type").trim().to_owned(),
+        status: text("wp:status").trim().to_owned(),
+        date,
+        sticky: text("wp:is_sticky").trim() == "1",
+        categories: elements(xml, "category")
+            .into_iter()
+            .filter(|category| {
+                attribute(category.attributes, "domain").as_deref() == Some("category")
+            })
+            .map(|category| text_of(category.inner).trim().to_owned())
+            .filter(|name| !name.is_empty())
+            .collect(),
+        attachment_url: first_text(xml, "wp:attachment_url")
+            .map(|url| url.trim().to_owned())
+            .filter(|url| !url.is_empty()),
+    }
+}
+
+struct Element<'a> {
+    attributes: &'a str,
+    inner: &'a str,
+}
+
+/// Every `<tag>` in `xml`, outside CDATA, with what's between it and its
+/// closing tag. Elements of the same name aren't expected to nest.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<Element<'a>> {
+    let (open, close) = (format!("<{tag}"), format!("</{tag}>"));
+    let mut found = Vec::new();
+    let mut from = 0;
+
+    while let Some(start) = find(xml, from, &open) {
+        let after_name = start + open.len();
+        // Only the whole name, so `<category` doesn't match `<category_x`
+        if !xml[after_name..].starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            from = after_name;
+            continue;
+        }
+
+        let Some(tag_end) = xml[after_name..].find('>').map(|i| after_name + i) else {
+            break;
+        };
+        let attributes = &xml[after_name..tag_end];
+
+        if attributes.ends_with('/') {
+            found.push(Element {
+                attributes: attributes.trim_end_matches('/'),
+                inner: "",
+            });
+            from = tag_end + 1;
+            continue;
+        }
+
+        let Some(end) = find(xml, tag_end + 1, &close) else {
+            break;
+        };
+        found.push(Element {
+            attributes,
+            inner: &xml[tag_end + 1..end],
+        });
+        from = end + close.len();
+    }
+
+    found
+}
+
+fn first_text(xml: &str, tag: &str) -> Option<String> {
+    elements(xml, tag)
+        .first()
+        .map(|element| text_of(element.inner))
+}
+
+/// Finds `pattern` at or after `from`, skipping over CDATA sections.
+fn find(xml: &str, mut from: usize, pattern: &str) -> Option<usize> {
+    loop {
+        let next = xml[from..].find(pattern).map(|i| from + i)?;
+        match xml[from..].find(CDATA_START).map(|i| from + i) {
+            Some(cdata) if cdata < next => {
+                from = xml[cdata..]
+                    .find(CDATA_END)
+                    .map(|i| cdata + i + CDATA_END.len())?;
+            }
+            _ => return Some(next),
+        }
+    }
+}
+
+/// The text content of an element: CDATA sections as they are, and
+/// everything else with entities decoded.
+fn text_of(inner: &str) -> String {
+    let mut text = String::new();
+    let mut rest = inner;
+
+    while let Some(start) = rest.find(CDATA_START) {
+        text.push_str(&unescape(&rest[..start]));
+        let cdata = &rest[start + CDATA_START.len()..];
+        let end = cdata.find(CDATA_END).unwrap_or(cdata.len());
+        text.push_str(&cdata[..end]);
+        rest = cdata.get(end + CDATA_END.len()..).unwrap_or_default();
+    }
+    text.push_str(&unescape(rest));
+
+    text
+}
+
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut rest = attributes;
+
+    while let Some(i) = rest.find(name) {
+        let after = rest[i + name.len()..].trim_start();
+        let preceded = rest[..i].ends_with(char::is_whitespace) || i == 0;
+        if let (true, Some(value)) = (preceded, after.strip_prefix('=')) {
+            let value = value.trim_start();
+            let quote = value.chars().next()?;
+            if quote == '"' || quote == '\'' {
+                let value = &value[1..];
+                return value.find(quote).map(|end| unescape(&value[..end]));
+            }
+        }
+        rest = &rest[i + name.len()..];
+    }
+
+    None
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "lt" => '<',
+                "gt" => '>',
+                "amp" => '&',
+                "quot" => '"',
+                "apos" => '\'',
+                entity => {
+                    let code = if let Some(hex) = entity.strip_prefix("#x") {
+                        u32::from_str_radix(hex, 16).ok()?
+                    } else {
+                        entity.strip_prefix('#')?.parse().ok()?
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// `2019-03-01 10:00:00`, as UTC. Unscheduled drafts have all zeroes, which
+/// isn't a date.
+fn parse_date(s: &str) -> Option<OffsetDateTime> {
+    let (date, time) = s.trim().split_once(' ')?;
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i32>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+    let mut time = time.splitn(3, ':').map(str::parse::<u8>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+
+    let date = Date::from_calendar_date(
+        year,
+        Month::try_from(u8::try_from(month).ok()?).ok()?,
+        u8::try_from(day).ok()?,
+    )
+    .ok()?;
+    let time = Time::from_hms(hour, minute, second).ok()?;
+
+    Some(PrimitiveDateTime::new(date, time).assume_utc())
+}
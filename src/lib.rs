@@ -10,152 +10,487 @@
 #![forbid(unsafe_code)]
 
 use axum::{
-    extract::{Host, Request},
-    handler::HandlerWithoutStateExt,
-    http::{StatusCode, Uri},
+    extract::{DefaultBodyLimit, Request},
+    http::{header, HeaderMap, HeaderName, Method, StatusCode, Uri},
+    middleware,
     response::Redirect,
+    routing::get,
     BoxError, Extension, Router, ServiceExt,
 };
 
 use ::{axum_server::tls_rustls::RustlsConfig, std::net::SocketAddr};
 
-use tokio::sync::{Mutex, RwLock};
-use tower_cookies::Key;
-use tower_http::{cors::CorsLayer, normalize_path::NormalizePathLayer, services::ServeDir};
+use futures_util::future::try_join_all;
+use std::future::IntoFuture;
+use tokio::{net::TcpListener, sync::RwLock};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    cors::{AllowOrigin, CorsLayer},
+    normalize_path::NormalizePathLayer,
+};
 use tower_layer::Layer;
 
 use deadpool_redis::Pool as RedisPool;
-use sqlx::PgPool;
 
 use std::{error::Error, sync::Arc};
 
-use tera::Tera;
 use time::Duration;
 extern crate slugify;
 
+mod access_log;
+mod acme;
+mod admin_host;
+mod analytics;
+mod audit;
 mod auth;
+mod client_ip;
+mod comments;
 mod config;
+mod consent;
+#[cfg(feature = "signed_cookies")]
+mod cookie_key;
+mod db;
+#[cfg(feature = "embedded_assets")]
+mod embedded;
 mod error;
+mod etag;
+mod events;
+mod export;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod import;
+mod limits;
+mod log_file;
+mod mail;
+mod media;
+mod metrics;
+mod openapi;
+mod reload;
+mod request_id;
 mod resources;
+mod response_cache;
+mod retention;
+mod review;
+mod search;
+mod seed;
+mod self_check;
 mod serve;
 mod sessions;
 mod settings;
+mod share;
+mod storage;
+#[cfg(feature = "test_support")]
+pub mod test_support;
+mod validation;
+mod versions;
+
+pub use {
+    config::{
+        AccessLogConfig, AccessLogFormat, AcmeConfig, AdmissionsConfig, BackupConfig, CacheConfig,
+        CompressionConfig, ConfigError, CorsConfig, DatabaseConfig, ErrorPages, LimitsConfig,
+        LogFileConfig, MailConfig, ResponseCacheBackend, ResponseCacheConfig, RetentionConfig,
+        ScannerConfig, ServerConfig, SmtpSecurity, SocialChannel, StorageConfig, TrashConfig,
+    },
+    db::Db,
+    log_file::RollingFile,
+    reload::{watch_config, LiveConfig, LogFilterSetter},
+    resources::create_admin,
+    seed::{seed, SeedSummary, SEED_PASSWORD},
+    self_check::{check_migrations, load_templates, self_check, SelfCheckFailed},
+    serve::{import_legacy_specs, TeraPool},
+    settings::{CalendarFeed, FeatureToggles, ServerSettings},
+    storage::{SharedStorage, Storage},
+};
 
-pub use {config::ServerConfig, settings::ServerSettings};
+#[cfg(feature = "signed_cookies")]
+pub use cookie_key::rotate as rotate_cookie_key;
 
+use access_log::AccessLog;
+use acme::{Acme, Challenges};
 use auth::AuthManagerLayer;
-use sessions::{Expiry, SessionConfig, SessionManagerLayer, SessionStore};
-
-#[allow(clippy::missing_panics_doc)]
+use client_ip::{ClientInfo, TrustedProxies};
+use events::Notifier;
+use limits::Limiter;
+use sessions::{CookieController, Expiry, SessionConfig, SessionManagerLayer, SessionStore};
+use share::ShareKey;
+
+/// Without `redis_pool`, sessions and everything else otherwise kept in Redis
+/// are kept in this process.
+#[allow(clippy::missing_panics_doc, clippy::too_many_arguments)]
 pub fn app(
-    db: PgPool,
-    redis_pool: RedisPool,
-    tera: Arc<Mutex<Tera>>,
+    db: Db,
+    redis_pool: Option<RedisPool>,
+    tera: Arc<TeraPool>,
+    storage: SharedStorage,
     config: &ServerConfig,
     settings: Arc<RwLock<ServerSettings>>,
+    live: LiveConfig,
+    notifier: Notifier,
 ) -> Router {
-    let session_store = SessionStore::new(redis_pool.clone());
+    let session_store = redis_pool
+        .clone()
+        .map_or_else(SessionStore::in_memory, SessionStore::new);
     #[cfg(feature = "signed_cookies")]
     let session_manager_layer = SessionManagerLayer::new_signed(
         session_store,
         SessionConfig::default(),
-        Key::try_generate().expect("OS RNG"),
-    )
-    .with_secure(true)
-    .with_expiry(Expiry::OnInactivity(Duration::hours(2)));
+        cookie_key::load_or_create(&config.cookie_key_path)
+            .expect("cookie key should be readable, as checked at startup"),
+    );
 
     #[cfg(not(feature = "signed_cookies"))]
-    let session_manager_layer = SessionManagerLayer::new(session_store, SessionConfig::default())
-        .with_secure(true)
-        .with_expiry(Expiry::OnInactivity(Duration::hours(2)));
+    let session_manager_layer = SessionManagerLayer::new(session_store, SessionConfig::default());
+
+    let share_key = ShareKey::load_or_create(&config.share_key_path)
+        .expect("share key should be readable, as checked at startup");
+
+    routes(
+        db,
+        redis_pool,
+        tera,
+        storage,
+        config,
+        settings,
+        live,
+        notifier,
+        session_manager_layer,
+        share_key,
+    )
+}
 
-    let auth_layer = AuthManagerLayer::new(session_manager_layer);
+/// Everything [`app`] serves, keeping sessions with whichever store and
+/// cookie handling `session_manager_layer` was made with.
+#[allow(clippy::too_many_arguments)]
+fn routes<C: CookieController>(
+    db: Db,
+    redis_pool: Option<RedisPool>,
+    tera: Arc<TeraPool>,
+    storage: SharedStorage,
+    config: &ServerConfig,
+    settings: Arc<RwLock<ServerSettings>>,
+    live: LiveConfig,
+    notifier: Notifier,
+    session_manager_layer: SessionManagerLayer<C>,
+    share_key: ShareKey,
+) -> Router {
+    let auth_layer = AuthManagerLayer::new(
+        session_manager_layer
+            .with_secure(true)
+            .with_expiry(Expiry::OnInactivity(Duration::hours(2)))
+            .with_settings(settings.clone()),
+    );
 
-    Router::new()
+    let router = Router::new()
         // Routers
-        .merge(resources::router())
-        .merge(auth::router())
-        .merge(serve::router())
-        .route_service("/*page", ServeDir::new("pages/dist/"))
+        .merge(versions::router())
+        .merge(media::files_router())
+        .merge(serve::site_router())
+        .fallback(serve::not_found)
         // Layers
+        // Innermost, so it sees the handler's own status
+        .layer(middleware::from_fn(db::transactions))
+        .layer(DefaultBodyLimit::disable())
+        .layer(middleware::from_fn_with_state(
+            config.limits.clone(),
+            limits::limit_body,
+        ))
+        .layer(middleware::from_fn(serve::error_pages))
         .layer(auth_layer)
-        // TODO WARN: Restrict for prod build
-        .layer(CorsLayer::very_permissive().allow_credentials(true))
+        // Marks the requests the API's routes turn staff writes away from
+        .layer(middleware::from_fn_with_state(
+            config.admin_host.as_deref().map(Arc::from),
+            admin_host::split_by_host,
+        ))
+        // Outside the session layer, so slow session loads count too
+        .layer(middleware::from_fn_with_state(
+            Limiter::new(&config.limits),
+            limits::limit_requests,
+        ))
+        .layer(cors_layer(&config.cors, live))
+        .layer(compression_layer(&config.compression))
+        // Handlers that don't need to pick take the primary as a plain pool
+        .layer(Extension(db.write().clone()))
         .layer(Extension(db))
-        .layer(Extension(redis_pool))
+        .layer(Extension(auth::PermissionCache::new(redis_pool.clone())))
+        .layer(Extension(response_cache::ResponseCache::new(
+            &config.response_cache,
+            redis_pool.clone(),
+        )))
+        .layer(Extension(resources::ResetTokens::new(redis_pool)))
+        .layer(Extension(Arc::new(serve::PreviewChannels::new(
+            tera.clone(),
+            config.site_url.clone(),
+        ))))
         .layer(Extension(tera))
+        .layer(Extension(resources::AnnouncementCache::default()))
+        .layer(Extension(storage))
+        .layer(Extension(mail::Mail::new(config)))
+        .layer(Extension(media::Scanner::new(config)))
+        .layer(Extension(notifier))
+        .layer(Extension(share_key))
         .layer(Extension(config.clone()))
-        // This settings state needs to be saved to TOML on write, or with a timed batch operation
-        .layer(Extension(Arc::new(RwLock::new(settings))))
+        .layer(Extension(settings));
+
+    let router = match AccessLog::new(&config.access_log) {
+        Ok(Some(log)) => router.layer(middleware::from_fn_with_state(log, access_log::log_request)),
+        Ok(None) => router,
+        Err(error) => {
+            tracing::error!(
+                ?error,
+                "Failed to open the access log; requests won't be logged"
+            );
+            router
+        }
+    };
+
+    router
+        .layer(middleware::from_fn(request_id::request_id))
+        .layer(middleware::from_fn_with_state(
+            TrustedProxies::new(&config.trusted_proxies, config.tls_enabled),
+            client_ip::client_info,
+        ))
+}
+
+/// Passes everything through untouched if compression is disabled.
+fn compression_layer(compression: &CompressionConfig) -> CompressionLayer<impl Predicate> {
+    // Already compressed, or streamed and so held up by buffering
+    let predicate = SizeAbove::new(compression.min_size)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::const_new("audio/"))
+        .and(NotForContentType::const_new("video/"))
+        .and(NotForContentType::const_new("application/zip"))
+        .and(NotForContentType::const_new("application/gzip"))
+        .and(NotForContentType::SSE)
+        .and(NotForContentType::GRPC);
+
+    CompressionLayer::new()
+        .br(compression.enabled)
+        .gzip(compression.enabled)
+        .compress_when(predicate)
+}
+
+fn cors_layer(cors: &CorsConfig, live: LiveConfig) -> CorsLayer {
+    #[cfg(debug_assertions)]
+    if cors.permissive {
+        tracing::warn!("Allowing cross-origin requests from anywhere, as cors.permissive is set");
+        return CorsLayer::very_permissive()
+            .allow_credentials(true)
+            .expose_headers([request_id::REQUEST_ID_HEADER]);
+    }
+
+    // Already checked by `ServerConfig::validate`
+    let methods = cors
+        .methods
+        .iter()
+        .filter_map(|method| method.parse::<Method>().ok())
+        .collect::<Vec<_>>();
+    let headers = cors
+        .headers
+        .iter()
+        .filter_map(|header| header.parse::<HeaderName>().ok())
+        .collect::<Vec<_>>();
+
+    CorsLayer::new()
+        .allow_credentials(true)
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            live.allows_origin(origin)
+        }))
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .max_age(std::time::Duration::from_secs(cors.max_age))
+        .expose_headers([request_id::REQUEST_ID_HEADER])
 }
 
 #[allow(clippy::missing_panics_doc)]
 pub async fn serve_http(
-    db: PgPool,
-    redis_pool: RedisPool,
-    tera: Arc<Mutex<Tera>>,
+    db: Db,
+    redis_pool: Option<RedisPool>,
+    tera: Arc<TeraPool>,
+    storage: SharedStorage,
     config: &ServerConfig,
     settings: Arc<RwLock<ServerSettings>>,
+    live: LiveConfig,
 ) -> Result<(), Box<dyn Error>> {
-    let app = ServiceExt::<Request>::into_make_service(
-        NormalizePathLayer::trim_trailing_slash()
-            .layer(app(db, redis_pool, tera, config, settings)),
+    tokio::spawn(serve::sitemap_job(
+        db.write().clone(),
+        storage.clone(),
+        config.clone(),
+    ));
+    let notifier = Notifier::default();
+    tokio::spawn(serve::link_check_job(
+        db.write().clone(),
+        config.clone(),
+        settings.clone(),
+        notifier.clone(),
+    ));
+    tokio::spawn(mail::mail_job(db.write().clone(), config.clone()));
+    if let Some(backup) = &config.backup {
+        tokio::spawn(export::backup_job(
+            db.write().clone(),
+            storage.clone(),
+            backup.clone(),
+        ));
+    }
+    tokio::spawn(retention::retention_job(db.write().clone(), config.clone()));
+    tokio::spawn(resources::trash_purge_job(
+        db.write().clone(),
+        storage.clone(),
+        config.trash.clone(),
+    ));
+    tokio::spawn(resources::calendar_sync_job(
+        db.write().clone(),
+        config.clone(),
+        settings.clone(),
+    ));
+    tokio::spawn(resources::social_job(
+        db.write().clone(),
+        config.clone(),
+        notifier.clone(),
+    ));
+
+    let app = ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(
+        NormalizePathLayer::trim_trailing_slash().layer(app(
+            db, redis_pool, tera, storage, config, settings, live, notifier,
+        )),
     );
 
-    let listener =
-        tokio::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], config.http_port)))
+    let listeners = bind_all(&config.http_addresses()).await?;
+
+    try_join_all(
+        listeners
+            .into_iter()
+            .map(|listener| axum::serve(listener, app.clone()).into_future()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Binds every address up front, so a port that's in use stops startup rather
+/// than leaving the server half-listening.
+async fn bind_all(addrs: &[SocketAddr]) -> Result<Vec<TcpListener>, String> {
+    let mut listeners = Vec::with_capacity(addrs.len());
+
+    for addr in addrs {
+        let listener = TcpListener::bind(addr)
             .await
-            .map_err(|_| {
-                format!(
-                    "Listening on port {} failed. Is this port in use?",
-                    config.http_port
-                )
-            })?;
-
-    axum::serve(listener, app).await.map_err(Into::into)
+            .map_err(|e| format!("Listening on {addr} failed: {e}. Is this port in use?"))?;
+        tracing::info!("Listening on {}", addr);
+        listeners.push(listener);
+    }
+
+    Ok(listeners)
 }
 
 pub async fn serve(
-    db: PgPool,
-    redis_pool: RedisPool,
-    tera: Arc<Mutex<Tera>>,
+    db: Db,
+    redis_pool: Option<RedisPool>,
+    tera: Arc<TeraPool>,
+    storage: SharedStorage,
     config: &ServerConfig,
     settings: Arc<RwLock<ServerSettings>>,
+    live: LiveConfig,
 ) -> Result<(), Box<dyn Error>> {
-    let app = ServiceExt::<Request>::into_make_service(
-        NormalizePathLayer::trim_trailing_slash()
-            .layer(app(db, redis_pool, tera, config, settings)),
+    tokio::spawn(serve::sitemap_job(
+        db.write().clone(),
+        storage.clone(),
+        config.clone(),
+    ));
+    let notifier = Notifier::default();
+    tokio::spawn(serve::link_check_job(
+        db.write().clone(),
+        config.clone(),
+        settings.clone(),
+        notifier.clone(),
+    ));
+    tokio::spawn(mail::mail_job(db.write().clone(), config.clone()));
+    if let Some(backup) = &config.backup {
+        tokio::spawn(export::backup_job(
+            db.write().clone(),
+            storage.clone(),
+            backup.clone(),
+        ));
+    }
+    tokio::spawn(retention::retention_job(db.write().clone(), config.clone()));
+    tokio::spawn(resources::trash_purge_job(
+        db.write().clone(),
+        storage.clone(),
+        config.trash.clone(),
+    ));
+    tokio::spawn(resources::calendar_sync_job(
+        db.write().clone(),
+        config.clone(),
+        settings.clone(),
+    ));
+    tokio::spawn(resources::social_job(
+        db.write().clone(),
+        config.clone(),
+        notifier.clone(),
+    ));
+
+    let app = ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(
+        NormalizePathLayer::trim_trailing_slash().layer(app(
+            db,
+            redis_pool,
+            tera,
+            storage,
+            config,
+            settings,
+            live.clone(),
+            notifier,
+        )),
     );
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], config.https_port));
-
-    tracing::info!("Listening on port {}", addr);
-
-    tokio::spawn(redirect_http_to_https(config.clone()));
+    let challenges = Challenges::default();
+    let redirect_listeners = bind_all(&config.http_addresses()).await?;
+    tokio::spawn(redirect_http_to_https(
+        redirect_listeners,
+        config.clone(),
+        challenges.clone(),
+    ));
 
     assert!(config.tls_enabled, "Serve called with TLS disabled");
 
-    let Some(tls_options) = config.tls_options.as_ref() else {
-        panic!("TLS is enabled but no options have been provided. Check that there is a [tls] section in config.toml")
+    let rustls_config = match (&config.tls_options, &config.acme) {
+        (Some(tls_options), _) => {
+            RustlsConfig::from_pem_file(&tls_options.cert_path, &tls_options.key_path).await?
+        }
+        // The redirect listener is already up to answer the challenges
+        (None, Some(acme_config)) => {
+            let acme = Acme::new(acme_config.clone(), challenges);
+            acme.ensure_certificate().await?;
+            let rustls_config =
+                RustlsConfig::from_pem_file(acme_config.cert_path(), acme_config.key_path())
+                    .await?;
+            tokio::spawn(acme::renew_job(acme, rustls_config.clone()));
+            rustls_config
+        }
+        (None, None) => panic!("TLS is enabled but no options have been provided. Check that there is a [tls] or [acme] section in config.toml"),
     };
+    live.set_tls(rustls_config.clone());
 
-    let rustls_config =
-        RustlsConfig::from_pem_file(&tls_options.cert_path, &tls_options.key_path).await?;
-
-    axum_server::bind_rustls(addr, rustls_config)
-        .serve(app)
-        .await?;
+    // axum_server binds lazily, so a port in use only shows up once serving
+    // starts; it still fails the whole server as with plain HTTP.
+    try_join_all(config.https_addresses().into_iter().map(|addr| {
+        tracing::info!("Listening on {}", addr);
+        axum_server::bind_rustls(addr, rustls_config.clone()).serve(app.clone())
+    }))
+    .await?;
 
     Ok(())
 }
 
-async fn redirect_http_to_https(server_config: ServerConfig) {
-    fn make_https(
-        host: String,
-        uri: Uri,
-        http_port: u16,
-        https_port: u16,
-    ) -> Result<Uri, BoxError> {
+/// Redirects everything to HTTPS, bar answers to ACME challenges, which have to
+/// be served over plain HTTP.
+async fn redirect_http_to_https(
+    listeners: Vec<TcpListener>,
+    server_config: ServerConfig,
+    challenges: Challenges,
+) {
+    fn make_https(host: &str, uri: Uri) -> Result<Uri, BoxError> {
         let mut parts = uri.into_parts();
 
         parts.scheme = Some(axum::http::uri::Scheme::HTTPS);
@@ -164,8 +499,7 @@ async fn redirect_http_to_https(server_config: ServerConfig) {
             parts.path_and_query = Some("/".parse().unwrap());
         }
 
-        let https_host = host.replace(&http_port.to_string(), &https_port.to_string());
-        parts.authority = Some(https_host.parse()?);
+        parts.authority = Some(host.parse()?);
 
         Ok(Uri::from_parts(parts)?)
     }
@@ -173,11 +507,20 @@ async fn redirect_http_to_https(server_config: ServerConfig) {
     let ServerConfig {
         https_port,
         http_port,
+        trusted_proxies,
         ..
     } = server_config;
 
-    let redirect = move |Host(host): Host, uri: Uri| async move {
-        match make_https(host, uri, http_port, https_port) {
+    let redirect = move |Extension(client): Extension<ClientInfo>, headers: HeaderMap, uri: Uri| async move {
+        // A trusted proxy passes on the public host, which has the public port
+        let Some(host) = client.forwarded_host.or_else(|| {
+            let host = headers.get(header::HOST)?.to_str().ok()?;
+            Some(host.replace(&http_port.to_string(), &https_port.to_string()))
+        }) else {
+            return Err(StatusCode::BAD_REQUEST);
+        };
+
+        match make_https(&host, uri) {
             Ok(uri) => Ok(Redirect::permanent(&uri.to_string())),
             Err(error) => {
                 tracing::warn!(%error, "Failed to convert URI to HTTPS");
@@ -186,10 +529,23 @@ async fn redirect_http_to_https(server_config: ServerConfig) {
         }
     };
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], http_port));
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    tracing::info!("Listening on port {}", listener.local_addr().unwrap());
-    axum::serve(listener, redirect.into_make_service())
-        .await
-        .unwrap();
+    let redirect = Router::new()
+        .route(
+            "/.well-known/acme-challenge/:token",
+            get(acme::respond_to_challenge),
+        )
+        .fallback(redirect)
+        .layer(Extension(challenges))
+        .layer(middleware::from_fn_with_state(
+            TrustedProxies::new(&trusted_proxies, false),
+            client_ip::client_info,
+        ))
+        .into_make_service_with_connect_info::<SocketAddr>();
+    try_join_all(
+        listeners
+            .into_iter()
+            .map(|listener| axum::serve(listener, redirect.clone()).into_future()),
+    )
+    .await
+    .unwrap();
 }
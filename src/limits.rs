@@ -0,0 +1,114 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body_util::Limited;
+use tokio::sync::Semaphore;
+
+use crate::{
+    config::LimitsConfig,
+    error::{ErrorCode, PhsError},
+    versions,
+};
+
+/// Caps request bodies by route: media uploads, including documents, and
+/// imports get `limits.upload`, everything else `limits.body`. Bodies that
+/// declare a larger `Content-Length` are turned away before being read, and
+/// any handler that hits the cap while reading is answered with the same 413.
+pub async fn limit_body(State(limits): State<LimitsConfig>, req: Request, next: Next) -> Response {
+    let limit = if is_upload(&req) {
+        limits.upload
+    } else {
+        limits.body
+    };
+
+    let declared = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > limit as u64) {
+        return too_large(limit);
+    }
+
+    let res = next
+        .run(req.map(|body| Body::new(Limited::new(body, limit))))
+        .await;
+
+    if res.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        too_large(limit)
+    } else {
+        res
+    }
+}
+
+/// Bounds how many requests are handled at once and how long each may take.
+/// Past the bound, requests are refused straight away rather than queued, so a
+/// backlog behind a slow database can't grow without limit.
+pub struct Limiter {
+    in_flight: Arc<Semaphore>,
+    timeout: Duration,
+    upload_timeout: Duration,
+}
+
+impl Limiter {
+    pub fn new(limits: &LimitsConfig) -> Arc<Self> {
+        Arc::new(Self {
+            in_flight: Arc::new(Semaphore::new(limits.concurrency)),
+            timeout: Duration::from_secs(limits.timeout),
+            upload_timeout: Duration::from_secs(limits.upload_timeout),
+        })
+    }
+}
+
+/// Streamed responses, such as previews, only count until their headers are
+/// sent, and aren't cut off by the timeout.
+pub async fn limit_requests(
+    State(limiter): State<Arc<Limiter>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, PhsError> {
+    let Ok(_permit) = limiter.in_flight.clone().try_acquire_owned() else {
+        tracing::warn!("Shedding load: too many requests in flight");
+        let mut res =
+            PhsError::client(ErrorCode::Overloaded, "Too many requests in flight").into_response();
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+        return Ok(res);
+    };
+
+    let timeout = if is_upload(&req) {
+        limiter.upload_timeout
+    } else {
+        limiter.timeout
+    };
+
+    tokio::time::timeout(timeout, next.run(req))
+        .await
+        .map_err(|_| PhsError::client(ErrorCode::Timeout, "Request took too long to handle"))
+}
+
+fn is_upload(req: &Request) -> bool {
+    req.method() == Method::POST
+        && match versions::api_path(req.uri().path()) {
+            Some("/media" | "/admin/import/wordpress") => true,
+            // A new version of a document, stored in the media library
+            Some(path) => path
+                .strip_prefix("/documents/")
+                .and_then(|rest| rest.strip_suffix("/versions"))
+                .is_some_and(|id| !id.contains('/')),
+            None => false,
+        }
+}
+
+fn too_large(limit: usize) -> Response {
+    PhsError::client(
+        ErrorCode::PayloadTooLarge,
+        format!("The request body is larger than the {limit} byte limit"),
+    )
+    .into_response()
+}
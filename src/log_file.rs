@@ -0,0 +1,84 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use time::{Date, OffsetDateTime};
+
+use crate::config::LogFileConfig;
+
+const FILE_PREFIX: &str = "phs_backend.log.";
+
+/// Log output that starts a new file each day (UTC), named by date, and
+/// deletes the oldest once there are more than the configured number.
+pub struct RollingFile {
+    directory: PathBuf,
+    retain: usize,
+    current: Option<(Date, File)>,
+}
+
+impl RollingFile {
+    /// # Errors
+    ///
+    /// Fails if the log directory can't be created or today's file opened.
+    pub fn new(config: &LogFileConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+
+        let mut file = Self {
+            directory: config.directory.clone(),
+            retain: config.retain.max(1),
+            current: None,
+        };
+        file.roll(OffsetDateTime::now_utc().date())?;
+
+        Ok(file)
+    }
+
+    fn roll(&mut self, today: Date) -> io::Result<()> {
+        let path = self.directory.join(format!("{FILE_PREFIX}{today}"));
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.current = Some((today, file));
+
+        self.prune()
+    }
+
+    /// Dates sort the same as the names they're in, so the oldest come first.
+    fn prune(&self) -> io::Result<()> {
+        let mut logs = fs::read_dir(&self.directory)?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                name.starts_with(FILE_PREFIX).then_some(name)
+            })
+            .collect::<Vec<_>>();
+        logs.sort_unstable();
+
+        let excess = logs.len().saturating_sub(self.retain);
+        for name in &logs[..excess] {
+            fs::remove_file(self.directory.join(name))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let today = OffsetDateTime::now_utc().date();
+        if self.current.as_ref().is_none_or(|(date, _)| *date != today) {
+            self.roll(today)?;
+        }
+
+        match &mut self.current {
+            Some((_, file)) => file.write(buf),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((_, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::{sync::Arc, time::Duration};
+
+use lettre::{
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Serialize;
+use sqlx::{PgExecutor, PgPool};
+use tera::{Context, Tera};
+
+use crate::{
+    config::{MailConfig, SmtpSecurity},
+    error::PhsError,
+    ServerConfig,
+};
+
+/// How often the queue is checked for mail to send.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many messages are taken from the queue at a time.
+const BATCH_SIZE: i64 = 20;
+
+/// Attempts at sending a message before it's dropped. With the retry delay
+/// doubling from a minute each time, the last is around two hours after the
+/// first.
+const MAX_ATTEMPTS: i32 = 8;
+
+type Transport = AsyncSmtpTransport<Tokio1Executor>;
+
+fn mail_error(e: impl std::fmt::Debug + Send + 'static) -> PhsError {
+    PhsError::internal(e, "Error whilst preparing an email")
+}
+
+/// Loads the templates in [`MailConfig::templates`]. With `embedded_assets`,
+/// the built-in ones fill in any that aren't there.
+///
+/// # Errors
+///
+/// Fails if a template doesn't parse.
+pub fn load_templates(mail: &MailConfig) -> Result<Tera, tera::Error> {
+    let glob = mail.templates.join("**").join("*");
+
+    #[cfg(feature = "embedded_assets")]
+    {
+        let mut tera = if mail.templates.is_dir() {
+            Tera::new(&glob.to_string_lossy())?
+        } else {
+            Tera::default()
+        };
+        crate::embedded::add_templates(&mut tera, "mail")?;
+        Ok(tera)
+    }
+
+    #[cfg(not(feature = "embedded_assets"))]
+    Tera::new(&glob.to_string_lossy())
+}
+
+/// Renders messages from the templates in [`MailConfig::templates`] and queues
+/// them to be sent by [`mail_job`].
+///
+/// Each kind of message is a `<name>.txt` template, whose first line is the
+/// subject and the rest the body, and optionally a `<name>.html` alternative.
+/// Every template gets `site_url`, along with whatever the caller passes.
+#[derive(Clone)]
+pub struct Mail(Option<Arc<Renderer>>);
+
+struct Renderer {
+    tera: Tera,
+    site_url: String,
+}
+
+impl Mail {
+    /// Mail is disabled, and queues nothing, if it isn't configured or the
+    /// templates don't parse.
+    pub fn new(config: &ServerConfig) -> Self {
+        let Some(mail) = &config.mail else {
+            return Self(None);
+        };
+
+        match load_templates(mail) {
+            Ok(tera) => Self(Some(Arc::new(Renderer {
+                tera,
+                site_url: config.site_url.clone(),
+            }))),
+            Err(error) => {
+                tracing::error!(
+                    ?error,
+                    "Failed to load mail templates; no mail will be sent"
+                );
+                Self(None)
+            }
+        }
+    }
+
+    /// Renders the message `template` and queues it for `to`. Queued in a
+    /// transaction, it's only sent if that's committed.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the template doesn't render or the queue can't be written to.
+    pub async fn send(
+        &self,
+        executor: impl PgExecutor<'_>,
+        to: &str,
+        template: &str,
+        context: &impl Serialize,
+    ) -> Result<(), PhsError> {
+        let Some(renderer) = &self.0 else {
+            tracing::debug!(template, "Mail isn't configured, not sending");
+            return Ok(());
+        };
+
+        let mut context = Context::from_serialize(context).map_err(mail_error)?;
+        context.insert("site_url", &renderer.site_url);
+
+        let text = renderer
+            .tera
+            .render(&format!("{template}.txt"), &context)
+            .map_err(mail_error)?;
+        let (subject, text) = text.split_once('\n').unwrap_or((&text, ""));
+
+        let html_name = format!("{template}.html");
+        let html = if renderer.tera.get_template_names().any(|n| n == html_name) {
+            Some(
+                renderer
+                    .tera
+                    .render(&html_name, &context)
+                    .map_err(mail_error)?,
+            )
+        } else {
+            None
+        };
+
+        sqlx::query!(
+            r#"
+            INSERT INTO mail_queue (recipient, subject, text_body, html_body)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            to,
+            subject.trim(),
+            text.trim_start(),
+            html
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
+
+fn transport(mail: &MailConfig) -> Result<Transport, lettre::transport::smtp::Error> {
+    let builder = match mail.security {
+        SmtpSecurity::StartTls => Transport::starttls_relay(&mail.host)?,
+        SmtpSecurity::Tls => Transport::relay(&mail.host)?,
+        SmtpSecurity::None => Transport::builder_dangerous(&mail.host),
+    }
+    .port(mail.port);
+
+    Ok(match (&mail.username, &mail.password) {
+        (Some(username), Some(password)) => builder
+            .credentials(Credentials::new(username.clone(), password.clone()))
+            .build(),
+        _ => builder.build(),
+    })
+}
+
+/// Sends whatever's due in the queue, for the lifetime of the server. Failed
+/// messages are retried with a growing delay, then dropped.
+pub async fn mail_job(pool: PgPool, config: ServerConfig) {
+    let Some(mail) = config.mail else {
+        return;
+    };
+
+    // Already checked by `ServerConfig::validate`
+    let Ok(from) = mail.from.parse::<Mailbox>() else {
+        return;
+    };
+    let transport = match transport(&mail) {
+        Ok(transport) => transport,
+        Err(error) => {
+            tracing::error!(?error, "Invalid SMTP settings; no mail will be sent");
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = send_due(&pool, &transport, &from).await {
+            tracing::error!(?error, "Failed to process the mail queue");
+        }
+    }
+}
+
+async fn send_due(pool: &PgPool, transport: &Transport, from: &Mailbox) -> Result<(), PhsError> {
+    // Rows stay locked while they're sent, so other instances skip them
+    let mut tx = pool.begin().await?;
+
+    let due = sqlx::query!(
+        r#"
+        SELECT id, recipient, subject, text_body, html_body, attempts
+        FROM mail_queue
+        WHERE send_after <= now()
+        ORDER BY id
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED
+        "#,
+        BATCH_SIZE
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for message in due {
+        let built = message
+            .recipient
+            .parse::<Mailbox>()
+            .map_err(|e| e.to_string())
+            .and_then(|to| {
+                let builder = Message::builder()
+                    .from(from.clone())
+                    .to(to)
+                    .subject(message.subject);
+                match message.html_body {
+                    Some(html) => builder
+                        .multipart(MultiPart::alternative_plain_html(message.text_body, html)),
+                    None => builder.body(message.text_body),
+                }
+                .map_err(|e| e.to_string())
+            });
+
+        let result = match built {
+            Ok(email) => transport
+                .send(email)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(()) => {
+                sqlx::query!("DELETE FROM mail_queue WHERE id = $1", message.id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Err(error) if message.attempts + 1 >= MAX_ATTEMPTS => {
+                tracing::error!(
+                    error,
+                    to = message.recipient,
+                    "Giving up on sending an email"
+                );
+                sqlx::query!("DELETE FROM mail_queue WHERE id = $1", message.id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Err(error) => {
+                tracing::warn!(
+                    error,
+                    to = message.recipient,
+                    "Failed to send an email, will retry"
+                );
+                sqlx::query!(
+                    r#"
+                    UPDATE mail_queue
+                    SET attempts = attempts + 1,
+                        last_error = $2,
+                        send_after = now() + interval '1 minute' * power(2, attempts)
+                    WHERE id = $1
+                    "#,
+                    message.id,
+                    error
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
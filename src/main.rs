@@ -8,60 +8,318 @@
 )]
 #![allow(clippy::module_name_repetitions)]
 
-use std::{error::Error, sync::Arc};
+use std::{error::Error, net::IpAddr, path::PathBuf, sync::Arc, time::Duration};
 
+use clap::{Parser, Subcommand};
 use deadpool_redis::{Config as RedisConfig, Pool as RedisPool, Runtime};
-use phs_backend::{ServerConfig, ServerSettings};
-use sqlx::{postgres::PgPoolOptions, Postgres};
+use phs_backend::{
+    ConfigError, DatabaseConfig, Db, LiveConfig, LogFilterSetter, RollingFile, SelfCheckFailed,
+    ServerConfig, ServerSettings, TeraPool,
+};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Postgres,
+};
 use tera::Tera;
-use tokio::sync::Mutex;
 use tokio::{fs, sync::RwLock};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter};
 
 type DbPool = sqlx::Pool<Postgres>;
 
+/// Flags override `config.toml` and `PHS_` environment variables alike.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Config file to load. Unlike the default, it must exist.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[arg(long, global = true)]
+    site_url: Option<String>,
+    #[arg(long, global = true)]
+    bind_address: Option<IpAddr>,
+    #[arg(long, global = true)]
+    http_port: Option<u16>,
+    #[arg(long, global = true)]
+    https_port: Option<u16>,
+    /// Serve over HTTPS, using the certificate in the `[tls]` section.
+    #[arg(long, global = true)]
+    tls: Option<bool>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Serve the site. The default when no command is given.
+    Serve,
+    /// Apply any database migrations that haven't been yet, then exit.
+    Migrate,
+    /// Check the config, database, Redis (if it's on) and templates as `serve`
+    /// would at startup, without serving.
+    CheckConfig,
+    /// Create an admin with every permission, e.g. the first account on a
+    /// fresh install, after `migrate`. The password is read from stdin.
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        email: Option<String>,
+    },
+    /// Fill a freshly migrated database with demo departments, categories,
+    /// users, posts and pages, for local development and integration tests.
+    Seed,
+    /// Replace the key session cookies are signed with, which logs everyone
+    /// out. Takes effect when the server next starts.
+    #[cfg(feature = "signed_cookies")]
+    RotateCookieKey,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let (server_settings_value, server_config) = get_configs().await;
+    let args = Args::parse();
+    let command = args.command.clone().unwrap_or(Command::Serve);
+    let (server_settings_value, server_config) = match get_configs(&args) {
+        Ok(configs) => configs,
+        Err(e) => {
+            // Logging isn't set up yet, and this reads better than the `Debug` form
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
 
     let server_settings = Arc::new(RwLock::new(server_settings_value));
-    init_logging(&server_config, server_settings.clone()).await?;
+    let set_log_filter = init_logging(&server_config, server_settings.clone()).await?;
+
+    match command {
+        Command::Serve => serve(args, server_config, server_settings, set_log_filter).await,
+        Command::Migrate => {
+            let db_pool = init_db(&server_config.database).await?;
+            exit_on_failure(phs_backend::check_migrations(db_pool.write()).await);
+            sqlx::migrate!().run(db_pool.write()).await?;
+            println!("The database is up to date");
+            Ok(())
+        }
+        Command::CheckConfig => {
+            let db_pool = init_db(&server_config.database).await?;
+            let redis_pool = init_redis(&server_config)?;
+            exit_on_failure(check(&db_pool, redis_pool.as_ref(), &server_config).await);
+            println!("Everything checks out");
+            Ok(())
+        }
+        Command::CreateAdmin {
+            username,
+            name,
+            email,
+        } => {
+            let db_pool = init_db(&server_config.database).await?;
+            let password = read_password()?;
+            let id = phs_backend::create_admin(db_pool.write(), username, name, email, password)
+                .await
+                .map_err(|e| format!("Failed to create the admin: {e:?}"))?;
+            println!("Created admin #{id}");
+            Ok(())
+        }
+        Command::Seed => {
+            let db_pool = init_db(&server_config.database).await?;
+            init_file_layout().await?;
+            let storage = server_config.storage.connect()?;
+            let summary = phs_backend::seed(db_pool.write(), &*storage)
+                .await
+                .map_err(|e| format!("Failed to seed the database: {e:?}"))?;
+            println!(
+                "Seeded {} departments, {} categories, {} posts and the pages {}",
+                summary.departments,
+                summary.categories,
+                summary.posts,
+                summary.pages.join(", ")
+            );
+            println!(
+                "Sign in as any of {} with the password `{}`, then deploy to publish the pages",
+                summary.users.join(", "),
+                phs_backend::SEED_PASSWORD
+            );
+            Ok(())
+        }
+        #[cfg(feature = "signed_cookies")]
+        Command::RotateCookieKey => {
+            phs_backend::rotate_cookie_key(&server_config.cookie_key_path)?;
+            println!(
+                "Replaced {}; restart the server to use it",
+                server_config.cookie_key_path.display()
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn serve(
+    args: Args,
+    server_config: ServerConfig,
+    server_settings: Arc<RwLock<ServerSettings>>,
+    set_log_filter: LogFilterSetter,
+) -> Result<(), Box<dyn Error>> {
+    let live = LiveConfig::new(&server_config);
+    let watched = vec![
+        args.config.clone().unwrap_or_else(|| "config.toml".into()),
+        server_config.settings_path.clone(),
+    ];
+    tokio::spawn(phs_backend::watch_config(
+        move || get_configs(&args),
+        watched,
+        server_config.clone(),
+        server_settings.clone(),
+        live.clone(),
+        set_log_filter,
+    ));
 
     init_file_layout().await?;
 
-    let db_pool = init_db().await?;
-    let redis_pool = init_redis()?;
+    let db_pool = init_db(&server_config.database).await?;
+    let redis_pool = init_redis(&server_config)?;
+
+    // Before migrating, so a build older than the database doesn't touch it
+    let templates = exit_on_failure(check(&db_pool, redis_pool.as_ref(), &server_config).await);
+    sqlx::migrate!().run(db_pool.write()).await?;
 
-    let tera = Arc::new(Mutex::new(Tera::new("pages/templates/**/*")?));
+    let mut tera = TeraPool::with_available_parallelism(templates);
+    if let Some(redis_pool) = &redis_pool {
+        tera = tera.with_render_cache(redis_pool.clone());
+    }
+    let tera = Arc::new(tera);
+
+    let storage = server_config.storage.connect()?;
+    phs_backend::import_legacy_specs(db_pool.write(), &*storage)
+        .await
+        .map_err(|e| format!("Failed to import page specs: {e:?}"))?;
 
     if server_config.tls_enabled {
-        phs_backend::serve(db_pool, redis_pool, tera, &server_config, server_settings).await?;
+        phs_backend::serve(
+            db_pool,
+            redis_pool,
+            tera,
+            storage,
+            &server_config,
+            server_settings,
+            live,
+        )
+        .await?;
     } else {
-        phs_backend::serve_http(db_pool, redis_pool, tera, &server_config, server_settings).await?;
+        phs_backend::serve_http(
+            db_pool,
+            redis_pool,
+            tera,
+            storage,
+            &server_config,
+            server_settings,
+            live,
+        )
+        .await?;
     }
 
     Ok(())
 }
 
-fn init_redis() -> Result<RedisPool, Box<dyn Error>> {
+/// Runs the startup checks, passing on the templates they loaded.
+async fn check(
+    db_pool: &Db,
+    redis_pool: Option<&RedisPool>,
+    config: &ServerConfig,
+) -> Result<Tera, SelfCheckFailed> {
+    let templates = phs_backend::load_templates()?;
+    phs_backend::self_check(db_pool.write(), redis_pool, &templates, config).await?;
+    Ok(templates)
+}
+
+fn exit_on_failure<T>(result: Result<T, SelfCheckFailed>) -> T {
+    result.unwrap_or_else(|e| {
+        tracing::error!("{e}");
+        std::process::exit(1);
+    })
+}
+
+/// Reads a line from stdin, so the password stays out of the shell history.
+fn read_password() -> Result<String, Box<dyn Error>> {
+    eprint!("Password: ");
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Connects to `REDIS_URL`, unless `redis` is off.
+fn init_redis(config: &ServerConfig) -> Result<Option<RedisPool>, Box<dyn Error>> {
+    if !config.redis {
+        tracing::warn!(
+            "Redis is off, so sessions and caches are kept in memory; restarting logs everyone out"
+        );
+        return Ok(None);
+    }
+
     let redis_cfg =
         RedisConfig::from_url(dotenv::var("REDIS_URL").map_err(|_| "REDIS_URL not set")?);
 
-    Ok(redis_cfg.create_pool(Some(Runtime::Tokio1))?)
+    Ok(Some(redis_cfg.create_pool(Some(Runtime::Tokio1))?))
 }
 
-async fn init_db() -> Result<DbPool, Box<dyn Error>> {
+/// Connects to `DATABASE_URL`, and to the read-only replica at `DATABASE_REPLICA_URL` if that's set.
+async fn init_db(config: &DatabaseConfig) -> Result<Db, Box<dyn Error>> {
     let database_url = dotenv::var("DATABASE_URL").map_err(|_| "DATABASE_URL not set")?;
 
-    // Create a db connpool and run unapplied migrations
-    let db = PgPoolOptions::new()
-        .max_connections(20)
-        .connect(&database_url)
+    tracing::info!(
+        max_connections = config.max_connections,
+        min_connections = config.min_connections,
+        acquire_timeout = config.acquire_timeout,
+        idle_timeout = config.idle_timeout,
+        statement_timeout = config.statement_timeout,
+        application_name = %config.application_name,
+        "Connecting to the database"
+    );
+
+    let primary = connect(&database_url, config)
         .await
-        .map_err(|_| "Failed to connect to DATABASE_URL")?;
-    sqlx::migrate!().run(&db).await?;
+        .map_err(|e| format!("Failed to connect to DATABASE_URL: {e}"))?;
+
+    let replica = match dotenv::var("DATABASE_REPLICA_URL") {
+        Ok(replica_url) => {
+            tracing::info!("Sending reads to the replica at DATABASE_REPLICA_URL");
+            Some(
+                connect(&replica_url, config)
+                    .await
+                    .map_err(|e| format!("Failed to connect to DATABASE_REPLICA_URL: {e}"))?,
+            )
+        }
+        Err(_) => None,
+    };
 
-    Ok(db)
+    Ok(Db::new(
+        primary,
+        replica,
+        Duration::from_millis(config.slow_query_threshold),
+    ))
+}
+
+/// A pool for the database at `url`, with the same options for the primary
+/// and the replica.
+async fn connect(url: &str, config: &DatabaseConfig) -> Result<DbPool, Box<dyn Error>> {
+    let mut connect_options = url
+        .parse::<PgConnectOptions>()
+        .map_err(|_| "not a valid Postgres URL")?
+        .application_name(&config.application_name);
+    if config.statement_timeout > 0 {
+        connect_options = connect_options.options([(
+            "statement_timeout",
+            format!("{}s", config.statement_timeout),
+        )]);
+    }
+
+    Ok(PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout))
+        .idle_timeout((config.idle_timeout > 0).then(|| Duration::from_secs(config.idle_timeout)))
+        .connect_with(connect_options)
+        .await?)
 }
 
 async fn init_file_layout() -> Result<(), Box<dyn Error>> {
@@ -72,8 +330,16 @@ async fn init_file_layout() -> Result<(), Box<dyn Error>> {
     if !fs::try_exists("./pages/dist").await? {
         fs::create_dir_all("./pages/dist").await?;
     }
-    if !fs::try_exists("./pages/specs").await? {
-        fs::create_dir_all("./pages/specs").await?;
+    if !fs::try_exists("./pages/protected").await? {
+        fs::create_dir_all("./pages/protected").await?;
+    }
+    if !fs::try_exists("./pages/archive").await? {
+        fs::create_dir_all("./pages/archive").await?;
+    }
+
+    // And for uploads to the media library
+    if !fs::try_exists("./media").await? {
+        fs::create_dir_all("./media").await?;
     }
 
     Ok(())
@@ -82,7 +348,7 @@ async fn init_file_layout() -> Result<(), Box<dyn Error>> {
 async fn init_logging(
     config: &ServerConfig,
     settings: Arc<RwLock<ServerSettings>>,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<LogFilterSetter, Box<dyn Error>> {
     #[cfg(debug_assertions)]
     let use_console = config.use_tokio_console;
     #[cfg(not(debug_assertions))]
@@ -94,9 +360,22 @@ async fn init_logging(
             .server_addr(([127, 0, 0, 1], 5555))
             .init();
         tracing::info!("Using Tokio debug console");
+
+        Ok(Box::new(|_| {
+            Err("The log filter is fixed while using the Tokio console".into())
+        }))
     } else {
+        let (filter, handle) = reload::Layer::new(EnvFilter::try_new(&config.log_filter)?);
+
+        let log_file = config
+            .log_file
+            .as_ref()
+            .map(RollingFile::new)
+            .transpose()
+            .map_err(|e| format!("Failed to open log file: {e}"))?;
+
         tracing_subscriber::registry()
-            .with(EnvFilter::new("trace,sqlx=info,fred=info"))
+            .with(filter)
             .with(
                 #[cfg(debug_assertions)]
                 tracing_subscriber::fmt::layer()
@@ -109,23 +388,54 @@ async fn init_logging(
                     .compact()
                     .with_thread_ids(true),
             )
+            .with(log_file.map(|file| {
+                tracing_subscriber::fmt::layer()
+                    .compact()
+                    .with_ansi(false)
+                    .with_thread_ids(true)
+                    .with_writer(std::sync::Mutex::new(file))
+            }))
             .try_init()?;
-        tracing::info!("Logging to stdout");
-    }
+        match &config.log_file {
+            Some(log_file) => {
+                tracing::info!("Logging to stdout and {}", log_file.directory.display());
+            }
+            None => tracing::info!("Logging to stdout"),
+        }
 
-    Ok(())
+        Ok(Box::new(move |filter| {
+            let filter = EnvFilter::try_new(filter).map_err(|e| e.to_string())?;
+            handle.reload(filter).map_err(|e| e.to_string())
+        }))
+    }
 }
 
-async fn get_configs() -> (ServerSettings, ServerConfig) {
-    (
-        ServerSettings {},
-        ServerConfig {
-            http_port: 5000,
-            https_port: 5001,
-            tls_enabled: false,
-            tls_options: None,
-            #[cfg(debug_assertions)]
-            use_tokio_console: false,
-        },
-    )
+fn get_configs(args: &Args) -> Result<(ServerSettings, ServerConfig), ConfigError> {
+    // So `PHS_` overrides can also live in `.env`
+    dotenv::dotenv().ok();
+
+    let mut config = match &args.config {
+        Some(path) => ServerConfig::load(path, true)?,
+        None => ServerConfig::load("config.toml".as_ref(), false)?,
+    };
+
+    if let Some(site_url) = &args.site_url {
+        config.site_url.clone_from(site_url);
+    }
+    if let Some(address) = args.bind_address {
+        config.bind_address = address;
+    }
+    if let Some(port) = args.http_port {
+        config.http_port = port;
+    }
+    if let Some(port) = args.https_port {
+        config.https_port = port;
+    }
+    if let Some(tls) = args.tls {
+        config.tls_enabled = tls;
+    }
+
+    config.validate()?;
+
+    Ok((ServerSettings::load(&config.settings_path)?, config))
 }
@@ -0,0 +1,805 @@
+use axum::{
+    async_trait,
+    extract::{Multipart, Path, Query},
+    http::{header, HeaderMap, HeaderValue},
+    response::Response,
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use slugify::slugify;
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tracing::instrument;
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::{ErrorCode, PhsError},
+    resources::{
+        paginated_query_as, CursorOptions, CursorPaginatable, CursorResponse, Deletable,
+        HasSqlxQueryString, SqlxQueryString, TrashKind,
+    },
+    storage::{stored_response, SharedStorage, Storage},
+    ServerConfig,
+};
+
+mod metadata;
+mod scan;
+mod variants;
+
+use metadata::strip_metadata;
+pub use scan::Scanner;
+use scan::Verdict;
+use variants::{generate_variants, is_processable, variants_of, MediaVariant};
+
+/// File extensions we accept, and the MIME type each is stored and served as.
+/// Anything a browser would execute (HTML, SVG, JS) is deliberately missing,
+/// since uploads are served from the same origin as the site.
+const ALLOWED_TYPES: &[(&str, &str)] = &[
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("pdf", "application/pdf"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("txt", "text/plain"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+];
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/media",
+            // Capped by `limits.upload` rather than the usual body limit
+            get(get_media).post(upload_media),
+        )
+        .route(
+            "/media/:id",
+            get(get_media_item)
+                .put(put_media_item)
+                .delete(delete_media_item),
+        )
+        .route("/media/:id/restore", post(restore_media_item))
+        .route("/quarantine", get(get_quarantine))
+        .route("/quarantine/:id", delete(delete_quarantined))
+}
+
+/// Outside the API, so the URLs of uploads don't change between versions.
+pub fn files_router() -> Router {
+    Router::new().route("/media/*key", get(serve_media))
+}
+
+/// Serves uploads and their variants. Uploads never change once stored, since
+/// a new upload always gets a new ID, so they're marked immutable.
+async fn serve_media(
+    Extension(storage): Extension<SharedStorage>,
+    Extension(config): Extension<ServerConfig>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, PhsError> {
+    let mut res = stored_response(
+        &*storage,
+        &format!("media/{key}"),
+        &headers,
+        &format!("public, max-age={}, immutable", config.cache.media),
+    )
+    .await?
+    .ok_or(PhsError::client(ErrorCode::NotFound, "No such upload"))?;
+
+    res.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    Ok(res)
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+pub struct Media {
+    id: i32,
+
+    /// The sanitised file name, which is also the last segment of its URL.
+    filename: String,
+    mime: String,
+    size: i64,
+    alt_text: String,
+
+    uploader: Option<i32>,
+    uploaded_at: PrimitiveDateTime,
+}
+
+impl Media {
+    pub const fn id(&self) -> i32 {
+        self.id
+    }
+
+    /// The path the file is served from, e.g. `/media/12/prize_giving.jpg`.
+    pub fn url(&self) -> String {
+        format!("/media/{}/{}", self.id, self.filename)
+    }
+}
+
+/// The prefix an upload and its variants are stored under.
+fn media_dir(id: i32) -> String {
+    format!("media/{id}/")
+}
+
+/// Where [`media_dir`] is moved to whilst the upload is in the trash, out of
+/// reach of [`serve_media`].
+fn trashed_media_dir(id: i32) -> String {
+    format!("trash/media/{id}/")
+}
+
+impl HasSqlxQueryString for Media {
+    type QueryString = MediaQueryString;
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MediaQueryString {
+    id: Option<i32>,
+    filename: Option<String>,
+    mime: Option<String>,
+    uploader: Option<i32>,
+
+    #[serde(rename = "uploaded_at[gte]")]
+    uploaded_at_gte: Option<PrimitiveDateTime>,
+    #[serde(rename = "uploaded_at[lte]")]
+    uploaded_at_lte: Option<PrimitiveDateTime>,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for MediaQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        builder.push(" AND deleted_at IS NULL");
+
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        if let Some(ref filename) = self.filename {
+            builder.push(" AND filename LIKE ");
+            builder.push_bind(filename);
+        }
+
+        if let Some(ref mime) = self.mime {
+            builder.push(" AND mime LIKE ");
+            builder.push_bind(mime);
+        }
+
+        if let Some(uploader) = self.uploader {
+            builder.push(" AND uploader = ");
+            builder.push_bind(uploader);
+        }
+
+        if let Some(uploaded_at_gte) = self.uploaded_at_gte {
+            builder.push(" AND uploaded_at >= ");
+            builder.push_bind(uploaded_at_gte);
+        }
+
+        if let Some(uploaded_at_lte) = self.uploaded_at_lte {
+            builder.push(" AND uploaded_at <= ");
+            builder.push_bind(uploaded_at_lte);
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "filename" | "mime" | "size" | "uploader" | "uploaded_at") =
+            field.as_str()
+        {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Media {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Media {}
+
+#[async_trait]
+impl Deletable for Media {
+    const KIND: TrashKind = TrashKind::Media;
+
+    async fn restore(
+        pool: &PgPool,
+        storage: &dyn Storage,
+        id: i32,
+        _by: i32,
+    ) -> Result<(), PhsError> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query_scalar!(
+            "UPDATE media SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING id",
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let (dir, trashed) = (media_dir(id), trashed_media_dir(id));
+        move_dir(storage, &trashed, &dir).await?;
+
+        if let Err(e) = tx.commit().await {
+            if let Err(error) = move_dir(storage, &dir, &trashed).await {
+                tracing::error!(
+                    ?error,
+                    id,
+                    "Failed to move restored media back to the trash"
+                );
+            }
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    async fn purge(
+        pool: &PgPool,
+        storage: &dyn Storage,
+        cutoff: OffsetDateTime,
+    ) -> Result<u64, PhsError> {
+        let ids = sqlx::query_scalar!(
+            "DELETE FROM media WHERE deleted_at < $1 RETURNING id",
+            cutoff
+        )
+        .fetch_all(pool)
+        .await?;
+
+        for &id in &ids {
+            let dir = trashed_media_dir(id);
+            if let Err(error) = storage.delete_all(&dir).await {
+                tracing::warn!(?error, ?dir, "Failed to clean up trashed media directory");
+            }
+        }
+
+        Ok(ids.len() as u64)
+    }
+}
+
+#[instrument(skip(db, _auth_session))]
+async fn get_media(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageMedia as u8 }>,
+
+    Query(cursor_options): Query<CursorOptions>,
+    Query(query_string): Query<<Media as HasSqlxQueryString>::QueryString>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Media>>, PhsError> {
+    paginated_query_as::<Media>(
+        "list_media",
+        r"SELECT id, filename, mime, size, alt_text, uploader, uploaded_at FROM media",
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|media| Json(CursorResponse::new(media)))
+}
+
+#[derive(Serialize, Debug)]
+struct MediaItem {
+    #[serde(flatten)]
+    media: Media,
+    /// Resized WebP copies, for images. These appear a little after upload, as
+    /// they're generated in the background.
+    variants: Vec<MediaVariant>,
+}
+
+#[instrument(skip(db, _auth_session))]
+async fn get_media_item(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageMedia as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<MediaItem>, PhsError> {
+    let media = db
+        .timed(
+            "get_media_item",
+            sqlx::query_as!(
+        Media,
+        "SELECT id, filename, mime, size, alt_text, uploader, uploaded_at FROM media WHERE id = $1 AND deleted_at IS NULL",
+        id
+    )
+            .fetch_one(db.read()),
+        )
+        .await?;
+
+    let variants = variants_of(db.read(), id).await?;
+
+    Ok(Json(MediaItem { media, variants }))
+}
+
+/// Splits an uploaded file name into a safe slug and its MIME type, rejecting
+/// anything not in [`ALLOWED_TYPES`].
+pub(crate) fn sanitise_filename(unsafe_name: &str) -> Result<(String, &'static str), PhsError> {
+    let (stem, extension) = unsafe_name.rsplit_once('.').ok_or(PhsError::client(
+        ErrorCode::UnsupportedFileType,
+        "Uploaded files must have an extension",
+    ))?;
+
+    let extension = extension.to_lowercase();
+
+    let mime = ALLOWED_TYPES
+        .iter()
+        .find_map(|(ext, mime)| (*ext == extension).then_some(*mime))
+        .ok_or(PhsError::client(
+            ErrorCode::UnsupportedFileType,
+            "This type of file can't be uploaded",
+        ))?;
+
+    let stem = slugify::slugify!(stem, separator = "_");
+    let stem = if stem.is_empty() { "file".into() } else { stem };
+
+    Ok((format!("{stem}.{extension}"), mime))
+}
+
+/// Accepts a `multipart/form-data` body with a `file` field and an optional
+/// `alt_text` field.
+#[derive(Serialize, Debug)]
+struct UploadedMedia {
+    #[serde(flatten)]
+    media: Media,
+    /// Whether it was checked for viruses, which it passed. Flagged files are
+    /// quarantined and refused with [`ErrorCode::Infected`] instead.
+    scanned: bool,
+}
+
+#[instrument(skip(pool, storage, scanner, auth_session, multipart))]
+async fn upload_media(
+    auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageMedia as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(scanner): Extension<Scanner>,
+    mut multipart: Multipart,
+) -> Result<Json<UploadedMedia>, PhsError> {
+    let mut alt_text = String::new();
+    let mut file = None;
+
+    while let Some(mut field) = multipart.next_field().await? {
+        match field.name() {
+            Some("alt_text") => alt_text = field.text().await?,
+            Some("file") if file.is_none() => {
+                let name = field.file_name().unwrap_or_default().to_owned();
+                // Before reading it, so unwanted files are turned away early
+                sanitise_filename(&name)?;
+
+                let mut data = Vec::new();
+                while let Some(chunk) = field.chunk().await? {
+                    data.extend_from_slice(&chunk);
+                }
+
+                file = Some((name, data));
+            }
+            _ => {}
+        }
+    }
+
+    let (name, data) = file.ok_or(PhsError::client(
+        ErrorCode::MissingFile,
+        "No file was included in the upload",
+    ))?;
+
+    let media = create_media(
+        &pool,
+        storage,
+        &scanner,
+        &name,
+        data,
+        &alt_text,
+        Some(auth_session.data().id()),
+    )
+    .await?;
+
+    Ok(Json(UploadedMedia {
+        media,
+        scanned: scanner.enabled(),
+    }))
+}
+
+/// Stores a new upload named `unsafe_name`, which is sanitised first, and
+/// starts making its variants, once `scanner` has passed it. Images have
+/// their metadata, such as where a photo was taken, stripped before storing.
+///
+/// # Errors
+///
+/// Fails if the type of file isn't allowed, an image is malformed, or it
+/// can't be stored, in which case nothing is left behind. Fails with
+/// [`ErrorCode::Infected`] if the scanner flags it, after it's been
+/// quarantined, and with [`ErrorCode::ScannerUnavailable`] if the scanner
+/// can't be reached.
+pub(crate) async fn create_media(
+    pool: &PgPool,
+    storage: SharedStorage,
+    scanner: &Scanner,
+    unsafe_name: &str,
+    data: Vec<u8>,
+    alt_text: &str,
+    uploader: Option<i32>,
+) -> Result<Media, PhsError> {
+    let (filename, mime) = sanitise_filename(unsafe_name)?;
+
+    match scanner.scan(&data).await {
+        Ok(Verdict::Clean | Verdict::Unscanned) => {}
+        Ok(Verdict::Infected(threat)) => {
+            quarantine(pool, &*storage, &filename, mime, data, &threat, uploader).await?;
+            return Err(PhsError::client(
+                ErrorCode::Infected,
+                format!("This file was flagged as {threat}, so has been quarantined"),
+            ));
+        }
+        Err(error) => {
+            tracing::error!(%error, "Failed to scan an upload");
+            return Err(PhsError::client(
+                ErrorCode::ScannerUnavailable,
+                "Uploads can't be checked for viruses right now, so can't be accepted",
+            ));
+        }
+    }
+
+    // Only what's published is scrubbed; quarantined files are kept as sent
+    let data = tokio::task::spawn_blocking(move || strip_metadata(mime, data))
+        .await?
+        .ok_or_else(|| {
+            PhsError::client(
+                ErrorCode::UnsupportedFileType,
+                format!("This file isn't a valid {mime} image"),
+            )
+        })?;
+
+    let mut tx = pool.begin().await?;
+
+    let media = sqlx::query_as!(
+        Media,
+        r#"
+        INSERT INTO media (filename, mime, size, alt_text, uploader)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, filename, mime, size, alt_text, uploader, uploaded_at
+        "#,
+        filename,
+        mime,
+        i64::try_from(data.len()).unwrap_or(i64::MAX),
+        alt_text,
+        uploader
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let key = format!("{}{}", media_dir(media.id), media.filename);
+
+    let stored = async {
+        storage.put(&key, data).await?;
+        tx.commit().await?;
+        Ok::<_, PhsError>(())
+    }
+    .await;
+
+    if let Err(e) = stored {
+        remove_media_dir(&*storage, media.id).await;
+        return Err(e);
+    }
+
+    if is_processable(&media.mime) {
+        tokio::spawn(generate_variants(pool.clone(), storage, media.id, key));
+    }
+
+    Ok(media)
+}
+
+/// An upload the scanner flagged, kept under [`quarantine_dir`].
+#[derive(Serialize, Debug)]
+struct QuarantinedUpload {
+    id: i32,
+    filename: String,
+    mime: String,
+    size: i64,
+    /// What the scanner called it.
+    threat: String,
+    uploader: Option<i32>,
+    #[serde(with = "time::serde::iso8601")]
+    quarantined_at: OffsetDateTime,
+}
+
+/// Where a quarantined upload is kept, out of reach of [`serve_media`].
+fn quarantine_dir(id: i32) -> String {
+    format!("quarantine/{id}/")
+}
+
+/// Keeps a flagged upload aside for someone to look at, logging it as a
+/// warning.
+async fn quarantine(
+    pool: &PgPool,
+    storage: &dyn Storage,
+    filename: &str,
+    mime: &str,
+    data: Vec<u8>,
+    threat: &str,
+    uploader: Option<i32>,
+) -> Result<(), PhsError> {
+    let mut tx = pool.begin().await?;
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO quarantined_uploads (filename, mime, size, threat, uploader)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        filename,
+        mime,
+        i64::try_from(data.len()).unwrap_or(i64::MAX),
+        threat,
+        uploader
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let key = format!("{}{filename}", quarantine_dir(id));
+    storage.put(&key, data).await?;
+
+    if let Err(e) = tx.commit().await {
+        if let Err(error) = storage.delete_all(&quarantine_dir(id)).await {
+            tracing::warn!(?error, id, "Failed to clean up quarantined upload");
+        }
+        return Err(e.into());
+    }
+
+    tracing::warn!(id, filename, threat, ?uploader, "Upload quarantined");
+
+    Ok(())
+}
+
+/// Every upload the scanner has flagged, newest first.
+#[instrument(skip(db, _auth_session))]
+async fn get_quarantine(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageMedia as u8 }>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<QuarantinedUpload>>, PhsError> {
+    db.timed(
+        "get_quarantine",
+        sqlx::query_as!(
+            QuarantinedUpload,
+            "SELECT id, filename, mime, size, threat, uploader, quarantined_at FROM quarantined_uploads ORDER BY id DESC"
+        )
+        .fetch_all(db.read()),
+    )
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Deletes a quarantined upload for good. There's no way to release one, as
+/// a file that was wrongly flagged can be uploaded again once the scanner's
+/// been sorted out.
+#[instrument(skip(pool, storage, _auth_session))]
+async fn delete_quarantined(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageMedia as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query_scalar!(
+        "DELETE FROM quarantined_uploads WHERE id = $1 RETURNING id",
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let dir = quarantine_dir(id);
+    if let Err(error) = storage.delete_all(&dir).await {
+        tracing::warn!(?error, ?dir, "Failed to delete quarantined upload");
+    }
+
+    Ok(())
+}
+
+async fn remove_media_dir(storage: &dyn Storage, id: i32) {
+    let dir = media_dir(id);
+    if let Err(error) = storage.delete_all(&dir).await {
+        tracing::warn!(?error, ?dir, "Failed to clean up media directory");
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MediaPatchBody {
+    alt_text: String,
+}
+
+#[instrument(skip(pool, _auth_session))]
+async fn put_media_item(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageMedia as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Json(body): Json<MediaPatchBody>,
+) -> Result<Json<Media>, PhsError> {
+    sqlx::query_as!(
+        Media,
+        r#"
+        UPDATE media SET alt_text = $1 WHERE id = $2 AND deleted_at IS NULL
+        RETURNING id, filename, mime, size, alt_text, uploader, uploaded_at
+        "#,
+        body.alt_text,
+        id
+    )
+    .fetch_one(&pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Whether anything published links to `media` or one of its variants, either
+/// a post or a page's rendered fragment, or it's a vacancy's application pack
+/// or a version of a document.
+async fn is_in_use(pool: &PgPool, storage: &dyn Storage, media: &Media) -> Result<bool, PhsError> {
+    let url = format!("/media/{}/", media.id);
+
+    if sqlx::query_scalar!(
+        r#"
+        SELECT id AS "id!" FROM vacancies WHERE attachment = $1
+        UNION ALL
+        SELECT id FROM document_versions WHERE media = $1
+        LIMIT 1
+        "#,
+        media.id
+    )
+    .fetch_optional(pool)
+    .await?
+    .is_some()
+    {
+        return Ok(true);
+    }
+
+    if sqlx::query_scalar!(
+        "SELECT id FROM posts WHERE strpos(content, $1) > 0 LIMIT 1",
+        url
+    )
+    .fetch_optional(pool)
+    .await?
+    .is_some()
+    {
+        return Ok(true);
+    }
+
+    for key in storage.list("pages/fragments/").await? {
+        let Some(fragment) = storage.get(&key).await? else {
+            continue;
+        };
+
+        if String::from_utf8_lossy(&fragment).contains(&url) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+#[derive(Deserialize, Debug)]
+struct DeleteMediaParams {
+    #[serde(default)]
+    force: bool,
+}
+
+/// Moves an upload and its files to the trash, so its URLs stop working until
+/// it's restored. Refuses if a post or page still links to it, unless
+/// `?force=true` is given.
+#[instrument(skip(pool, storage, _auth_session))]
+async fn delete_media_item(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageMedia as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<i32>,
+    Query(params): Query<DeleteMediaParams>,
+) -> Result<(), PhsError> {
+    let mut tx = pool.begin().await?;
+
+    let media = sqlx::query_as!(
+        Media,
+        r#"
+        UPDATE media SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL
+        RETURNING id, filename, mime, size, alt_text, uploader, uploaded_at
+        "#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if !params.force && is_in_use(&pool, &*storage, &media).await? {
+        return Err(PhsError::client(
+            ErrorCode::InUse,
+            "This file is still used by a post or page",
+        ));
+    }
+
+    let (dir, trashed) = (media_dir(id), trashed_media_dir(id));
+    move_dir(&*storage, &dir, &trashed).await?;
+
+    if let Err(e) = tx.commit().await {
+        if let Err(error) = move_dir(&*storage, &trashed, &dir).await {
+            tracing::error!(?error, id, "Failed to move media back out of the trash");
+        }
+        return Err(e.into());
+    }
+
+    tracing::info!(media = media.url(), "Media moved to the trash");
+
+    Ok(())
+}
+
+/// Takes an upload back out of the trash, files and all.
+#[instrument(skip(pool, storage, auth_session))]
+async fn restore_media_item(
+    auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageMedia as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    Media::restore(&pool, &*storage, id, auth_session.data().id()).await
+}
+
+/// Moves every file under the prefix `from` to the same place under `to`. If
+/// any can't be moved, those already moved are put back first.
+async fn move_dir(storage: &dyn Storage, from: &str, to: &str) -> Result<(), PhsError> {
+    let mut moved: Vec<(String, String)> = Vec::new();
+
+    for key in storage.list(from).await? {
+        let Some(rest) = key.strip_prefix(from) else {
+            continue;
+        };
+        let target = format!("{to}{rest}");
+
+        if let Err(e) = storage.rename(&key, &target).await {
+            for (key, target) in moved.iter().rev() {
+                if let Err(error) = storage.rename(target, key).await {
+                    tracing::error!(?error, ?key, "Failed to move media file back");
+                }
+            }
+            return Err(e);
+        }
+
+        moved.push((key, target));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,482 @@
+//! Scrubbing what cameras and editors embed in images, such as where and when
+//! a photo was taken, out of uploads before anyone else can download them.
+//!
+//! Files are edited in place rather than re-encoded, so the image itself is
+//! untouched. The only thing kept from a JPEG's EXIF is which way up it goes,
+//! since phones rely on it to rotate photos.
+
+/// Which way up a JPEG goes, as a tag in its EXIF.
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// `data` with any metadata in it removed, or `None` if it's an image too
+/// malformed to be sure of. Types that aren't images are left as they are.
+pub fn strip_metadata(mime: &str, data: Vec<u8>) -> Option<Vec<u8>> {
+    match mime {
+        "image/jpeg" => strip_jpeg(&data),
+        "image/png" => strip_png(&data),
+        "image/webp" => strip_webp(&data),
+        "image/gif" => strip_gif(&data),
+        "image/avif" => strip_avif(data),
+        _ => Some(data),
+    }
+}
+
+/// Reads big-endian numbers from the front of a byte slice.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.0.split_at_checked(n)?;
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)?.try_into().ok().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)?.try_into().ok().map(u32::from_be_bytes)
+    }
+
+    /// A number `size` bytes long, where that's 0, 4 or 8.
+    fn uint(&mut self, size: u8) -> Option<u64> {
+        match size {
+            0 => Some(0),
+            4 => self.u32().map(u64::from),
+            8 => self.take(8)?.try_into().ok().map(u64::from_be_bytes),
+            _ => None,
+        }
+    }
+}
+
+/// Drops every `APPn` segment besides JFIF, colour profiles and Adobe's colour
+/// transform, along with comments and anything after the end of the image,
+/// where some phones append extra pictures with their own EXIF.
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Bytes(data);
+    if bytes.take(2)? != [0xFF, 0xD8] {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&[0xFF, 0xD8]);
+    // EXIF goes straight after the start, or after JFIF if there is one
+    let mut exif_at = out.len();
+    let mut leading = true;
+    let mut orientation = None;
+
+    loop {
+        if bytes.u8()? != 0xFF {
+            return None;
+        }
+        let mut marker = bytes.u8()?;
+        while marker == 0xFF {
+            marker = bytes.u8()?;
+        }
+
+        match marker {
+            // End of image
+            0xD9 => {
+                out.extend_from_slice(&[0xFF, 0xD9]);
+                break;
+            }
+            // Restart markers, which stand alone
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&[0xFF, marker]);
+                continue;
+            }
+            _ => {}
+        }
+
+        let length = bytes.u16()?;
+        let body = bytes.take(usize::from(length).checked_sub(2)?)?;
+
+        let keep = match marker {
+            0xE1 => {
+                if let Some(tiff) = body.strip_prefix(b"Exif\0\0") {
+                    orientation = orientation.or_else(|| tiff_orientation(tiff));
+                }
+                false
+            }
+            0xE2 => body.starts_with(b"ICC_PROFILE\0"),
+            0xEE => body.starts_with(b"Adobe"),
+            // Other `APPn` segments, and comments
+            0xE3..=0xEF | 0xFE => false,
+            _ => true,
+        };
+
+        if keep {
+            out.extend_from_slice(&[0xFF, marker]);
+            out.extend_from_slice(&length.to_be_bytes());
+            out.extend_from_slice(body);
+
+            if marker == 0xE0 && leading {
+                exif_at = out.len();
+            } else {
+                leading = false;
+            }
+        }
+
+        // The start of a scan is followed by the compressed image, which runs
+        // until the next marker that isn't a stuffed byte or a restart
+        if marker == 0xDA {
+            let scan = bytes.0;
+            let end = (0..scan.len().saturating_sub(1))
+                .find(|&i| scan[i] == 0xFF && !matches!(scan[i + 1], 0x00 | 0xD0..=0xD7))?;
+            out.extend_from_slice(bytes.take(end)?);
+        }
+    }
+
+    if let Some(orientation) = orientation {
+        let tiff = orientation_tiff(orientation);
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&u16::try_from(tiff.len() + 8).ok()?.to_be_bytes());
+        segment.extend_from_slice(b"Exif\0\0");
+        segment.extend_from_slice(&tiff);
+        out.splice(exif_at..exif_at, segment);
+    }
+
+    Some(out)
+}
+
+/// The orientation in a TIFF-structured EXIF block, unless it's the default.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..4)? {
+        b"MM\0\x2A" => true,
+        b"II\x2A\0" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| {
+        let b = tiff.get(at..at + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    };
+    let u32_at = |at: usize| {
+        let b = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    };
+
+    let ifd = usize::try_from(u32_at(4)?).ok()?;
+    let entries = u16_at(ifd)?;
+
+    (0..usize::from(entries))
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION_TAG))
+        .and_then(|entry| u16_at(entry + 8))
+        .filter(|orientation| (2..=8).contains(orientation))
+}
+
+/// A TIFF block with nothing in it but `orientation`.
+fn orientation_tiff(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0\x2A".to_vec();
+    // Where the only directory starts, and how many entries it has
+    tiff.extend_from_slice(&8u32.to_be_bytes());
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    // One unsigned short, padded to four bytes
+    tiff.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+    tiff.extend_from_slice(&3u16.to_be_bytes());
+    tiff.extend_from_slice(&1u32.to_be_bytes());
+    tiff.extend_from_slice(&orientation.to_be_bytes());
+    tiff.extend_from_slice(&[0, 0]);
+    // No next directory
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+    tiff
+}
+
+/// Drops EXIF, text (where editors put XMP and comments) and timestamp
+/// chunks, and anything after the end of the image.
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Bytes(data);
+    let signature = bytes.take(8)?;
+    if signature != b"\x89PNG\r\n\x1A\n" {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(signature);
+
+    loop {
+        let start = bytes.0;
+        let length = usize::try_from(bytes.u32()?).ok()?;
+        let kind = bytes.take(4)?;
+        // The data, then its checksum
+        bytes.take(length.checked_add(4)?)?;
+
+        if !matches!(kind, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            out.extend_from_slice(&start[..length + 12]);
+        }
+        if kind == b"IEND" {
+            break;
+        }
+    }
+
+    Some(out)
+}
+
+/// Drops EXIF and XMP chunks, and unsets the flags saying they're there.
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Bytes(data);
+    if bytes.take(4)? != b"RIFF" {
+        return None;
+    }
+    let size = u32::from_le_bytes(bytes.take(4)?.try_into().ok()?);
+    if bytes.take(4)? != b"WEBP" {
+        return None;
+    }
+    // Anything past the size given isn't part of the image
+    let mut bytes = Bytes(bytes.take(usize::try_from(size).ok()?.checked_sub(4)?)?);
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(b"RIFF\0\0\0\0WEBP");
+
+    while !bytes.0.is_empty() {
+        let kind = bytes.take(4)?;
+        let length = u32::from_le_bytes(bytes.take(4)?.try_into().ok()?);
+        let chunk = bytes.take(usize::try_from(length).ok()?)?;
+        // Chunks are padded to an even length
+        let padding = if length % 2 == 1 { bytes.take(1)? } else { &[] };
+
+        if matches!(kind, b"EXIF" | b"XMP ") {
+            continue;
+        }
+
+        let at = out.len();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(chunk);
+        out.extend_from_slice(padding);
+
+        if kind == b"VP8X" {
+            *out.get_mut(at + 8)? &= !(0x08 | 0x04);
+        }
+    }
+
+    let size = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&size.to_le_bytes());
+
+    Some(out)
+}
+
+/// Drops comments and application extensions (where editors put XMP) besides
+/// the ones saying how animations loop, and anything after the end.
+fn strip_gif(data: &[u8]) -> Option<Vec<u8>> {
+    /// A run of sub-blocks, each prefixed with its length, ending with an
+    /// empty one.
+    fn sub_blocks<'a>(bytes: &mut Bytes<'a>) -> Option<&'a [u8]> {
+        let start = bytes.0;
+        loop {
+            let length = bytes.u8()?;
+            bytes.take(usize::from(length))?;
+            if length == 0 {
+                return Some(&start[..start.len() - bytes.0.len()]);
+            }
+        }
+    }
+
+    /// Skips the colour table the flags in `packed` say follows, if any.
+    fn colour_table<'a>(bytes: &mut Bytes<'a>, packed: u8) -> Option<&'a [u8]> {
+        if packed & 0x80 == 0 {
+            return Some(&[]);
+        }
+        bytes.take(3 << ((packed & 0x07) + 1))
+    }
+
+    let mut bytes = Bytes(data);
+    let header = bytes.take(6)?;
+    if header != b"GIF87a" && header != b"GIF89a" {
+        return None;
+    }
+    let screen = bytes.take(7)?;
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(header);
+    out.extend_from_slice(screen);
+    out.extend_from_slice(colour_table(&mut bytes, screen[4])?);
+
+    loop {
+        match bytes.u8()? {
+            // Trailer
+            0x3B => {
+                out.push(0x3B);
+                break;
+            }
+            // An image, with its position and flags, then maybe a colour
+            // table, then the compressed data
+            0x2C => {
+                let descriptor = bytes.take(9)?;
+                out.push(0x2C);
+                out.extend_from_slice(descriptor);
+                out.extend_from_slice(colour_table(&mut bytes, descriptor[8])?);
+                out.push(bytes.u8()?);
+                out.extend_from_slice(sub_blocks(&mut bytes)?);
+            }
+            0x21 => {
+                let label = bytes.u8()?;
+                let blocks = sub_blocks(&mut bytes)?;
+
+                let keep = match label {
+                    0xFE => false,
+                    0xFF => {
+                        let identifier = blocks.get(1..12)?;
+                        identifier == b"NETSCAPE2.0" || identifier == b"ANIMEXTS1.0"
+                    }
+                    _ => true,
+                };
+
+                if keep {
+                    out.extend_from_slice(&[0x21, label]);
+                    out.extend_from_slice(blocks);
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    Some(out)
+}
+
+/// An ISO media box, as AVIF files are made of.
+struct IsoBox<'a> {
+    kind: &'a [u8],
+    /// Where `contents` starts in the whole file.
+    at: usize,
+    contents: &'a [u8],
+}
+
+/// The boxes one after another in `data`, which starts `offset` bytes into
+/// the file.
+fn iso_boxes(data: &[u8], offset: usize) -> Option<Vec<IsoBox<'_>>> {
+    let mut bytes = Bytes(data);
+    let mut found = Vec::new();
+
+    while !bytes.0.is_empty() {
+        let start = data.len() - bytes.0.len();
+        let size = bytes.u32()?;
+        let kind = bytes.take(4)?;
+        let header = if size == 1 { 16 } else { 8 };
+        let size = match size {
+            0 => data.len() - start,
+            1 => usize::try_from(bytes.uint(8)?).ok()?,
+            size => usize::try_from(size).ok()?,
+        };
+
+        found.push(IsoBox {
+            kind,
+            at: offset + start + header,
+            contents: bytes.take(size.checked_sub(header)?)?,
+        });
+    }
+
+    Some(found)
+}
+
+/// Zeroes the contents of EXIF and XMP items where they are, since removing
+/// them would mean rewriting where everything else in the file is.
+fn strip_avif(mut data: Vec<u8>) -> Option<Vec<u8>> {
+    let top = iso_boxes(&data, 0)?;
+    let Some(meta) = top.iter().find(|b| b.kind == b"meta") else {
+        return Some(data);
+    };
+    // Past its version and flags
+    let children = iso_boxes(meta.contents.get(4..)?, meta.at + 4)?;
+    let child = |kind: &[u8]| children.iter().find(|b| b.kind == kind);
+
+    let mut metadata = Vec::new();
+    if let Some(iinf) = child(b"iinf") {
+        let mut bytes = Bytes(iinf.contents);
+        let version = bytes.u8()?;
+        bytes.take(3)?;
+        bytes.take(if version == 0 { 2 } else { 4 })?;
+
+        for infe in iso_boxes(bytes.0, 0)? {
+            if infe.kind != b"infe" {
+                continue;
+            }
+            let mut bytes = Bytes(infe.contents);
+            let version = bytes.u8()?;
+            bytes.take(3)?;
+            let id = match version {
+                2 => u32::from(bytes.u16()?),
+                3 => bytes.u32()?,
+                // Older items can't be EXIF or XMP
+                _ => continue,
+            };
+            bytes.take(2)?;
+            if matches!(bytes.take(4)?, b"Exif" | b"mime") {
+                metadata.push(id);
+            }
+        }
+    }
+    if metadata.is_empty() {
+        return Some(data);
+    }
+
+    let iloc = child(b"iloc")?.contents;
+    let idat_at = child(b"idat").map(|idat| idat.at);
+
+    let mut bytes = Bytes(iloc);
+    let version = bytes.u8()?;
+    bytes.take(3)?;
+    let sizes = bytes.u8()?;
+    let (offset_size, length_size) = (sizes >> 4, sizes & 0x0F);
+    let sizes = bytes.u8()?;
+    let (base_offset_size, index_size) = (sizes >> 4, sizes & 0x0F);
+    let items = if version < 2 {
+        u32::from(bytes.u16()?)
+    } else {
+        bytes.u32()?
+    };
+
+    let mut extents = Vec::new();
+    for _ in 0..items {
+        let id = if version < 2 {
+            u32::from(bytes.u16()?)
+        } else {
+            bytes.u32()?
+        };
+        let method = if version == 0 { 0 } else { bytes.u16()? & 0x0F };
+        bytes.u16()?;
+        let base = bytes.uint(base_offset_size)?;
+
+        for _ in 0..bytes.u16()? {
+            if version > 0 {
+                bytes.uint(index_size)?;
+            }
+            let offset = base.checked_add(bytes.uint(offset_size)?)?;
+            let length = bytes.uint(length_size)?;
+
+            if metadata.contains(&id) {
+                let offset = match method {
+                    0 => usize::try_from(offset).ok()?,
+                    1 => idat_at?.checked_add(usize::try_from(offset).ok()?)?,
+                    // Made out of other items, which can't be told apart
+                    _ => return None,
+                };
+                extents.push((offset, usize::try_from(length).ok()?));
+            }
+        }
+    }
+
+    for (offset, length) in extents {
+        // A length of zero runs to the end of the file
+        let end = match length {
+            0 => data.len(),
+            length => offset.checked_add(length)?,
+        };
+        data.get_mut(offset..end)?.fill(0);
+    }
+
+    Some(data)
+}
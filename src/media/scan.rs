@@ -0,0 +1,152 @@
+//! Checking uploads for viruses before they're stored, with whatever
+//! [`ScannerConfig`] sets up, since staff upload from their own machines.
+
+use std::{process::Stdio, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    process::Command,
+};
+
+use crate::{config::ScannerConfig, ServerConfig};
+
+/// Longest a scan can take before it's given up on as failed.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Bytes sent to clamd at a time, well under its default `StreamMaxLength`
+/// for any one chunk.
+const CLAMD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Most of clamd's reply that's read, in bytes.
+const MAX_REPLY_SIZE: u64 = 4096;
+
+/// What a scan found.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// No scanner is set up.
+    Unscanned,
+    Clean,
+    /// Flagged, with the name the scanner gave the threat.
+    Infected(String),
+}
+
+#[derive(Clone)]
+pub struct Scanner(Option<Arc<ScannerConfig>>);
+
+impl Scanner {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self(config.scanner.clone().map(Arc::new))
+    }
+
+    /// Whether uploads are checked at all.
+    pub fn enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Checks `data`, failing with why if the scanner couldn't be reached or
+    /// didn't give an answer in time.
+    pub async fn scan(&self, data: &[u8]) -> Result<Verdict, String> {
+        let Some(config) = &self.0 else {
+            return Ok(Verdict::Unscanned);
+        };
+
+        let scan = async {
+            match &**config {
+                ScannerConfig::Clamd { address } => clamd(address, data).await,
+                ScannerConfig::Command { program, args } => command(program, args, data).await,
+            }
+        };
+
+        tokio::time::timeout(SCAN_TIMEOUT, scan)
+            .await
+            .unwrap_or_else(|_| Err("The scan timed out".into()))
+    }
+}
+
+/// Streams `data` to clamd with `INSTREAM`, as length-prefixed chunks ending
+/// with an empty one.
+async fn clamd(address: &str, data: &[u8]) -> Result<Verdict, String> {
+    let io = |e: std::io::Error| format!("Couldn't talk to clamd at {address}: {e}");
+
+    let mut stream = TcpStream::connect(address).await.map_err(io)?;
+    stream.write_all(b"zINSTREAM\0").await.map_err(io)?;
+
+    for chunk in data.chunks(CLAMD_CHUNK_SIZE) {
+        let length = u32::try_from(chunk.len()).expect("chunks are far smaller than 4 GiB");
+        stream.write_all(&length.to_be_bytes()).await.map_err(io)?;
+        stream.write_all(chunk).await.map_err(io)?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await.map_err(io)?;
+
+    let mut reply = Vec::new();
+    (&mut stream)
+        .take(MAX_REPLY_SIZE)
+        .read_to_end(&mut reply)
+        .await
+        .map_err(io)?;
+
+    // e.g. `stream: OK` or `stream: Eicar-Signature FOUND`
+    let reply = String::from_utf8_lossy(&reply);
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+    if result == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(threat) = result.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(threat.to_owned()))
+    } else {
+        Err(format!("clamd replied {reply:?}"))
+    }
+}
+
+/// Runs the scanning program with `data` on its stdin.
+async fn command(program: &str, args: &[String], data: &[u8]) -> Result<Verdict, String> {
+    let io = |e: std::io::Error| format!("Couldn't run {program}: {e}");
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(io)?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| format!("Couldn't write to {program}"))?;
+    // Written alongside waiting, so a full pipe on either side can't stall
+    let write = async move {
+        let written = stdin.write_all(data).await;
+        drop(stdin);
+        written
+    };
+    let (written, output) = tokio::join!(write, child.wait_with_output());
+    let output = output.map_err(io)?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match output.status.code() {
+        Some(0) => Ok(Verdict::Clean),
+        Some(1) => {
+            let threat = stdout
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .unwrap_or("Unnamed threat");
+            // `clamdscan -` prints `stdin: <name> FOUND`
+            let threat = threat.strip_prefix("stdin: ").unwrap_or(threat);
+            let threat = threat.strip_suffix(" FOUND").unwrap_or(threat);
+            Ok(Verdict::Infected(threat.to_owned()))
+        }
+        status => {
+            written.map_err(io)?;
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(format!(
+                "{program} exited with {status:?}: {}",
+                stderr.trim().chars().take(500).collect::<String>()
+            ))
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::{error::Error, io::Cursor};
+
+use fast_image_resize::{images::Image, PixelType, Resizer};
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, RgbaImage};
+use serde::Serialize;
+use sqlx::{prelude::FromRow, PgPool};
+
+use crate::{error::PhsError, storage::SharedStorage};
+
+/// Widths, in pixels, of the WebP copies made of each uploaded image. Images
+/// narrower than a width get a copy at their own size instead.
+const VARIANT_WIDTHS: &[u32] = &[480, 960, 1920];
+
+/// Upload types we can decode. Anything else is only served as uploaded.
+const PROCESSABLE_TYPES: &[&str] = &["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+#[derive(FromRow, Serialize, Debug)]
+pub struct MediaVariant {
+    width: i32,
+    height: i32,
+    size: i64,
+    url: String,
+}
+
+pub fn is_processable(mime: &str) -> bool {
+    PROCESSABLE_TYPES.contains(&mime)
+}
+
+pub async fn variants_of(pool: &PgPool, media_id: i32) -> Result<Vec<MediaVariant>, PhsError> {
+    sqlx::query_as!(
+        MediaVariant,
+        r#"
+        SELECT width, height, size, '/media/' || media_id || '/' || filename as "url!"
+        FROM media_variants
+        WHERE media_id = $1
+        ORDER BY width
+        "#,
+        media_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Into::into)
+}
+
+/// Resizes an uploaded image into [`VARIANT_WIDTHS`] and records the results.
+/// Meant to be spawned after the upload has been committed, so failures are
+/// only logged; the original is still there to fall back on.
+pub async fn generate_variants(pool: PgPool, storage: SharedStorage, media_id: i32, key: String) {
+    let (dir, filename) = key.rsplit_once('/').unwrap_or(("", &key));
+    let (dir, filename) = (dir.to_owned(), filename.to_owned());
+
+    let original = match storage.get(&key).await {
+        Ok(Some(original)) => original,
+        Ok(None) => return,
+        Err(error) => {
+            tracing::error!(?error, media_id, "Failed to read upload for resizing");
+            return;
+        }
+    };
+
+    let variants =
+        match tokio::task::spawn_blocking(move || resize_image(&filename, original)).await {
+            Ok(Ok(variants)) => variants,
+            Ok(Err(error)) => {
+                tracing::error!(?error, media_id, "Failed to generate image variants");
+                return;
+            }
+            Err(error) => {
+                tracing::error!(?error, media_id, "Image variant task panicked");
+                return;
+            }
+        };
+
+    for variant in variants {
+        let result = async {
+            let size = i64::try_from(variant.data.len()).unwrap_or(i64::MAX);
+            let (width, height) = (
+                i32::try_from(variant.width).unwrap_or(i32::MAX),
+                i32::try_from(variant.height).unwrap_or(i32::MAX),
+            );
+
+            storage
+                .put(&format!("{dir}/{}", variant.filename), variant.data)
+                .await?;
+
+            sqlx::query!(
+                r#"
+                INSERT INTO media_variants (media_id, width, height, filename, size)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (media_id, filename) DO UPDATE
+                SET width = EXCLUDED.width, height = EXCLUDED.height, size = EXCLUDED.size
+                "#,
+                media_id,
+                width,
+                height,
+                variant.filename,
+                size
+            )
+            .execute(&pool)
+            .await?;
+
+            Ok::<_, PhsError>(())
+        }
+        .await;
+
+        // The upload may have been deleted in the meantime, which is fine
+        if let Err(error) = result {
+            tracing::warn!(?error, media_id, "Failed to store image variant");
+        }
+    }
+}
+
+struct EncodedVariant {
+    filename: String,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+fn resize_image(
+    filename: &str,
+    original: Vec<u8>,
+) -> Result<Vec<EncodedVariant>, Box<dyn Error + Send + Sync>> {
+    let stem = filename.rsplit_once('.').map_or(filename, |(stem, _)| stem);
+
+    let mut decoder = ImageReader::new(Cursor::new(original))
+        .with_guessed_format()?
+        .into_decoder()?;
+    // Phone cameras store photos sideways and rely on EXIF to rotate them
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let source = image.into_rgba8();
+    let (source_width, source_height) = source.dimensions();
+    let source = Image::from_vec_u8(
+        source_width,
+        source_height,
+        source.into_raw(),
+        PixelType::U8x4,
+    )?;
+
+    let mut widths = VARIANT_WIDTHS
+        .iter()
+        .map(|width| (*width).min(source_width))
+        .collect::<Vec<_>>();
+    widths.dedup();
+
+    let mut scaler = Resizer::new();
+    let mut variants = Vec::with_capacity(widths.len());
+
+    for width in widths {
+        let height = (u64::from(source_height) * u64::from(width) / u64::from(source_width))
+            .try_into()
+            .unwrap_or(u32::MAX)
+            .max(1);
+
+        let mut resized = Image::new(width, height, PixelType::U8x4);
+        scaler.resize(&source, &mut resized, None)?;
+
+        let resized = RgbaImage::from_raw(width, height, resized.into_vec())
+            .ok_or("Resized buffer didn't match its dimensions")?;
+
+        // Only lossless WebP encoding is supported by `image`, which is still
+        // far smaller than the camera originals
+        let mut data = Vec::new();
+        resized.write_to(&mut Cursor::new(&mut data), ImageFormat::WebP)?;
+
+        variants.push(EncodedVariant {
+            filename: format!("{stem}_{width}.webp"),
+            width,
+            height,
+            data,
+        });
+    }
+
+    Ok(variants)
+}
@@ -0,0 +1,33 @@
+use std::collections::BTreeMap;
+
+use axum::{routing::get, Extension, Json, Router};
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::{Db, QueryStats},
+};
+
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+#[derive(Serialize, Debug)]
+struct Metrics {
+    /// By the name each query is run under.
+    queries: BTreeMap<&'static str, QueryStats>,
+}
+
+/// Counts since the server started, to see what's keeping the database busy.
+#[instrument(skip_all)]
+async fn get_metrics(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(db): Extension<Db>,
+) -> Json<Metrics> {
+    Json(Metrics {
+        queries: db.metrics().snapshot(),
+    })
+}
@@ -0,0 +1,87 @@
+use std::future;
+
+use axum::{http::header, routing::get, Router};
+use utoipa::{
+    openapi::{
+        security::{ApiKey, ApiKeyValue, SecurityScheme},
+        Components, OpenApi as OpenApiDocument,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{analytics, auth, comments, consent, resources, retention, review, serve};
+
+/// The parts of the document that aren't generated from the handlers. Each
+/// module describes its own endpoints, relative to the version prefix, and
+/// [`document`] nests them under it.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "PHS backend", description = "The website's content management API."),
+    modifiers(&SessionCookie),
+    tags(
+        (name = "auth", description = "Logging in and out, and groups of permissions"),
+        (name = "users"),
+        (name = "posts"),
+        (name = "categories"),
+        (name = "departments"),
+        (name = "pages", description = "Editing and deploying dynamic pages"),
+        (name = "reviews", description = "Approving and commenting on pages and posts before they're published"),
+    )
+)]
+struct ApiDoc;
+
+/// Endpoints that need a logged-in user reference this, as `session`.
+struct SessionCookie;
+
+impl Modify for SessionCookie {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        openapi
+            .components
+            .get_or_insert_with(Components::new)
+            .add_security_scheme(
+                "session",
+                SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("id"))),
+            );
+    }
+}
+
+/// A version of the API, as an OpenAPI 3 document. Every version is served
+/// by the same handlers so far, so only the prefix differs.
+pub fn document(prefix: &str) -> OpenApiDocument {
+    let mut api = auth::openapi();
+    api.merge(resources::openapi());
+    api.merge(serve::openapi());
+    api.merge(review::openapi());
+    api.merge(comments::openapi());
+    api.merge(consent::openapi());
+    api.merge(analytics::openapi());
+    api.merge(retention::openapi());
+
+    ApiDoc::openapi().nest(prefix, api)
+}
+
+/// Serves the document for a version at `<prefix>/openapi.json`, and with the
+/// `swagger_ui` feature, Swagger UI for it at `<prefix>/docs`.
+///
+/// This is merged at the root rather than nested, since Swagger UI redirects
+/// to absolute paths.
+pub fn router(prefix: &str) -> Router {
+    // Serialised once up front; it can't change without a rebuild
+    let json = document(prefix)
+        .to_json()
+        .expect("OpenAPI document should serialise");
+    let json_path = format!("{prefix}/openapi.json");
+
+    let router = Router::new().route(
+        &json_path,
+        get(move || future::ready(([(header::CONTENT_TYPE, "application/json")], json.clone()))),
+    );
+
+    #[cfg(feature = "swagger_ui")]
+    let router = router.merge(
+        utoipa_swagger_ui::SwaggerUi::new(format!("{prefix}/docs"))
+            .config(utoipa_swagger_ui::Config::new([json_path])),
+    );
+
+    router
+}
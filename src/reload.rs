@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use axum::http::HeaderValue;
+use axum_server::tls_rustls::RustlsConfig;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use crate::{ConfigError, ServerConfig, ServerSettings};
+
+/// How often watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Config options, or whole sections, applied by a reload. Changes to anything
+/// else are logged, but wait for a restart.
+const RELOADABLE: &[&str] = &["log_filter", "cors.origins", "tls"];
+
+/// Replaces the running log filter, given a filter in `RUST_LOG` syntax.
+pub type LogFilterSetter = Box<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+/// The parts of the running server a reload reaches into.
+#[derive(Clone, Default)]
+pub struct LiveConfig {
+    cors_origins: Arc<parking_lot::RwLock<Vec<HeaderValue>>>,
+    tls: Arc<OnceLock<RustlsConfig>>,
+}
+
+impl LiveConfig {
+    #[must_use]
+    pub fn new(config: &ServerConfig) -> Self {
+        let live = Self::default();
+        live.set_cors_origins(&config.cors.origins);
+        live
+    }
+
+    /// Whether cross-origin requests from `origin` are allowed.
+    pub(crate) fn allows_origin(&self, origin: &HeaderValue) -> bool {
+        self.cors_origins.read().contains(origin)
+    }
+
+    fn set_cors_origins(&self, origins: &[String]) {
+        // Already checked by `ServerConfig::validate`
+        *self.cors_origins.write() = origins
+            .iter()
+            .filter_map(|origin| HeaderValue::from_str(origin).ok())
+            .collect();
+    }
+
+    /// Lets reloads swap in renewed certificates.
+    pub(crate) fn set_tls(&self, tls: RustlsConfig) {
+        let _ = self.tls.set(tls);
+    }
+}
+
+/// Reapplies configuration whenever the process gets `SIGHUP` or one of
+/// `watched` changes on disk, for the lifetime of the server. The certificate
+/// and key in `[tls]` are watched too, and reloaded by themselves when renewed.
+///
+/// `load` should build the settings and config just as at startup. Reloadable
+/// options take effect straight away; changes to the rest are logged along
+/// with a note that they need a restart. If loading fails, everything carries
+/// on as it was.
+pub async fn watch_config<F>(
+    load: F,
+    watched: Vec<PathBuf>,
+    mut current: ServerConfig,
+    settings: Arc<RwLock<ServerSettings>>,
+    live: LiveConfig,
+    set_log_filter: LogFilterSetter,
+) where
+    F: Fn() -> Result<(ServerSettings, ServerConfig), ConfigError> + Send,
+{
+    let mut hangup = Hangup::new();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let mut stamps = modified_times(&watched);
+    let mut cert_stamps = modified_times(&cert_files(&current));
+    let mut cert_seen = cert_stamps.clone();
+
+    loop {
+        tokio::select! {
+            () = hangup.recv() => tracing::info!("Got SIGHUP, reloading configuration"),
+            _ = interval.tick() => {
+                // Renewals write the certificate and key separately, so wait
+                // for both to settle rather than loading a mismatched pair
+                let certs = modified_times(&cert_files(&current));
+                if certs != cert_stamps && certs == cert_seen {
+                    tracing::info!("TLS certificates changed, reloading them");
+                    reload_tls(&live, &current).await;
+                    cert_stamps.clone_from(&certs);
+                }
+                cert_seen = certs;
+
+                let now = modified_times(&watched);
+                if now == stamps {
+                    continue;
+                }
+                tracing::info!("Configuration files changed, reloading");
+            }
+        }
+        stamps = modified_times(&watched);
+
+        let (new_settings, config) = match load() {
+            Ok(loaded) => loaded,
+            Err(error) => {
+                tracing::error!(%error, "Failed to reload configuration, keeping the current one");
+                continue;
+            }
+        };
+
+        for (key, change) in diff(&current, &config) {
+            if RELOADABLE.iter().any(|option| {
+                key.strip_prefix(option)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            }) {
+                tracing::info!(key, change, "Config changed");
+            } else {
+                tracing::warn!(key, change, "Config changed, but needs a restart to apply");
+            }
+        }
+
+        if config.log_filter != current.log_filter {
+            if let Err(error) = set_log_filter(&config.log_filter) {
+                tracing::error!(%error, "Invalid log filter, keeping the current one");
+            }
+        }
+
+        live.set_cors_origins(&config.cors.origins);
+
+        // Certificates are usually renewed in place, so reload them regardless
+        reload_tls(&live, &config).await;
+        cert_stamps = modified_times(&cert_files(&config));
+        cert_seen.clone_from(&cert_stamps);
+
+        let mut settings = settings.write().await;
+        for (key, change) in diff(&*settings, &new_settings) {
+            tracing::info!(key, change, "Setting changed");
+        }
+        *settings = new_settings;
+        drop(settings);
+
+        current = config;
+    }
+}
+
+/// The certificate and key files from `[tls]`, if TLS is enabled. ACME
+/// certificates are reloaded by their own renewal job instead.
+fn cert_files(config: &ServerConfig) -> Vec<PathBuf> {
+    [config.get_cert_filepath(), config.get_key_filepath()]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Swaps in the certificate and key from `[tls]`. The old ones stay in use if
+/// they can't be loaded.
+async fn reload_tls(live: &LiveConfig, config: &ServerConfig) {
+    if let (Some(tls), Some(options)) = (live.tls.get(), &config.tls_options) {
+        if let Err(error) = tls
+            .reload_from_pem_file(&options.cert_path, &options.key_path)
+            .await
+        {
+            tracing::error!(?error, "Failed to reload TLS certificates");
+        }
+    }
+}
+
+fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Each option that differs between `old` and `new`, by its dotted path, with
+/// a description of the change.
+fn diff(old: &impl Serialize, new: &impl Serialize) -> Vec<(String, String)> {
+    let (old, new) = (flatten(old), flatten(new));
+
+    let mut keys = old.keys().chain(new.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let (before, after) = (old.get(key), new.get(key));
+            (before != after).then(|| {
+                let unset = String::from("unset");
+                let (before, after) = (before.unwrap_or(&unset), after.unwrap_or(&unset));
+                (key.clone(), format!("{before} -> {after}"))
+            })
+        })
+        .collect()
+}
+
+fn flatten(value: &impl Serialize) -> BTreeMap<String, String> {
+    fn walk(prefix: &str, value: toml::Value, out: &mut BTreeMap<String, String>) {
+        match value {
+            toml::Value::Table(table) => {
+                for (key, value) in table {
+                    let path = if prefix.is_empty() {
+                        key
+                    } else {
+                        format!("{prefix}.{key}")
+                    };
+                    walk(&path, value, out);
+                }
+            }
+            value => {
+                out.insert(prefix.to_owned(), value.to_string());
+            }
+        }
+    }
+
+    let mut out = BTreeMap::new();
+    if let Ok(value) = toml::Value::try_from(value) {
+        walk("", value, &mut out);
+    }
+    out
+}
+
+/// `SIGHUP`, where there is such a thing.
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        Self {
+            #[cfg(unix)]
+            signal: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .map_err(|error| tracing::warn!(?error, "Can't listen for SIGHUP"))
+                .ok(),
+        }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+
+        std::future::pending::<()>().await;
+    }
+}
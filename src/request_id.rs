@@ -0,0 +1,84 @@
+use std::fmt;
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use rand_core::{OsRng, RngCore};
+use tracing::Instrument;
+
+use crate::client_ip::ClientInfo;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Identifies one request across logs, error responses and bug reports.
+#[derive(Debug, Clone)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    fn generate() -> Self {
+        let id = format!("{:016x}{:016x}", OsRng.next_u64(), OsRng.next_u64());
+        Self(HeaderValue::from_str(&id).expect("hex is a valid header value"))
+    }
+
+    /// Accepts an ID from a proxy or client, as long as it can't be used to
+    /// forge log lines or bloat them.
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let bytes = value.as_bytes();
+        let valid = (1..=64).contains(&bytes.len())
+            && bytes
+                .iter()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+
+        valid.then(|| Self(value.clone()))
+    }
+
+    /// The ID of the request being handled by this task, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only ever built from ASCII
+        f.write_str(self.0.to_str().unwrap_or_default())
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// Takes the request's `X-Request-Id`, or makes one up, and attaches it to the
+/// tracing span, the request's extensions and the response headers.
+pub async fn request_id(mut req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(RequestId::from_header)
+        .unwrap_or_else(RequestId::generate);
+
+    let span = tracing::info_span!(
+        "request",
+        id = %id,
+        client = tracing::field::Empty,
+        method = %req.method(),
+        uri = %req.uri(),
+    );
+    if let Some(client) = req.extensions().get::<ClientInfo>() {
+        span.record("client", tracing::field::display(client.ip));
+    }
+
+    req.extensions_mut().insert(id.clone());
+
+    let mut res = CURRENT
+        .scope(id.clone(), next.run(req))
+        .instrument(span)
+        .await;
+
+    res.headers_mut().insert(REQUEST_ID_HEADER, id.0);
+    res
+}
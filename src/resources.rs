@@ -1,18 +1,40 @@
 use std::fmt::Debug;
 
-use axum::Router;
-
+use axum::{
+    http::{header, HeaderValue},
+    middleware,
+    response::{IntoResponse, Response},
+    Router,
+};
+
+mod admissions;
+mod announcement;
 mod category;
 mod department;
+mod document;
+mod event;
+mod faq;
+mod form;
+mod governor;
+mod poll;
 mod post;
+mod schedule;
+mod trash;
 mod user;
+mod vacancy;
 
+pub use announcement::AnnouncementCache;
 pub use department::Department;
+pub use event::calendar_sync_job;
+pub use post::social_job;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgRow, FromRow, PgPool, QueryBuilder};
-pub use user::Role;
+use sqlx::{postgres::PgRow, FromRow, QueryBuilder};
+pub use trash::trash_purge_job;
+pub(crate) use trash::{Deletable, TrashKind};
+pub use user::{create_admin, ResetTokens, Role};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::error::PhsError;
+use crate::{db::Db, error::PhsError, etag};
 
 pub fn router() -> Router {
     Router::new()
@@ -20,12 +42,55 @@ pub fn router() -> Router {
         .merge(post::router())
         .merge(category::router())
         .merge(department::router())
+        .merge(event::router())
+        .merge(vacancy::router())
+        .merge(document::router())
+        .merge(announcement::router())
+        .merge(admissions::router())
+        .merge(form::router())
+        .merge(poll::router())
+        .merge(governor::router())
+        .merge(faq::router())
+        .merge(schedule::router())
+        .merge(trash::router())
+        .layer(middleware::from_fn(etag::tag_responses))
+}
+
+/// What visitors send from the site's pages, served on every host.
+pub fn public_router() -> Router {
+    Router::new()
+        .merge(event::public_router())
+        .merge(admissions::public_router())
+        .merge(form::public_router())
+        .merge(poll::public_router())
+}
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut openapi = user::openapi();
+    openapi.merge(post::openapi());
+    openapi.merge(category::openapi());
+    openapi.merge(department::openapi());
+    openapi.merge(event::openapi());
+    openapi.merge(vacancy::openapi());
+    openapi.merge(document::openapi());
+    openapi.merge(announcement::openapi());
+    openapi.merge(admissions::openapi());
+    openapi.merge(form::openapi());
+    openapi.merge(poll::openapi());
+    openapi.merge(governor::openapi());
+    openapi.merge(faq::openapi());
+    openapi.merge(schedule::openapi());
+    openapi.merge(trash::openapi());
+    openapi
 }
 
-#[derive(Deserialize, Debug, Serialize)]
+#[derive(Deserialize, Debug, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct CursorOptions {
+    /// The ID to start after, or before if `cursor[previous]` is set.
     #[serde(default)]
     cursor: i32,
+    /// Between 1 and 200.
     #[serde(default = "_default_cursor_length")]
     #[serde(rename = "cursor[length]")]
     length: i32,
@@ -37,7 +102,7 @@ pub struct CursorOptions {
 #[rustfmt::skip]
 const fn _default_cursor_length() -> i32 { 20 }
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
+#[derive(Deserialize, Debug, Serialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CursorResponse<T> {
     next_cursor: Option<i32>,
@@ -109,11 +174,14 @@ pub trait HasSqlxQueryString {
     type QueryString: SqlxQueryString + Deserialize<'static> + Debug + Send;
 }
 
+/// Runs `init` with the cursor and query string's clauses added, against the
+/// replica if there is one, timed as `name`.
 pub async fn paginated_query_as<O>(
+    name: &'static str,
     init: &str,
     mut cursor: CursorOptions,
     query_string: <O as HasSqlxQueryString>::QueryString,
-    pool: &PgPool,
+    db: &Db,
 ) -> Result<Vec<O>, PhsError>
 where
     O: HasSqlxQueryString + Send + Unpin + for<'r> FromRow<'r, PgRow>,
@@ -136,9 +204,45 @@ where
 
     query_builder.push(" LIMIT ").push_bind(cursor.length);
 
-    query_builder
-        .build_query_as()
-        .fetch_all(pool)
+    db.timed(name, query_builder.build_query_as().fetch_all(db.read()))
         .await
         .map_err(Into::into)
 }
+
+/// Quotes a field if it needs it, and defuses anything a spreadsheet would
+/// take for a formula, since much of what's exported comes from the public.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{value}")
+    } else {
+        value.to_owned()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// `csv` as a download named `file_name`.
+fn csv_attachment(file_name: &str, csv: String) -> Result<Response, PhsError> {
+    let disposition = format!(r#"attachment; filename="{file_name}""#);
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/csv; charset=utf-8"),
+            ),
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition)
+                    .map_err(|e| PhsError::internal(e, "Invalid export file name"))?,
+            ),
+        ],
+        csv,
+    )
+        .into_response())
+}
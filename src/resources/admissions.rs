@@ -0,0 +1,439 @@
+use axum::{
+    extract::{Path, Query},
+    routing::{get, post, put},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::{ErrorCode, PhsError},
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+};
+
+/// Longest a name can be, as stored.
+const MAX_NAME_LENGTH: usize = 255;
+const MAX_YEAR_GROUP_LENGTH: usize = 64;
+const MAX_EMAIL_LENGTH: usize = 512;
+const MAX_PHONE_LENGTH: usize = 64;
+const MAX_MESSAGE_LENGTH: usize = 5000;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/admissions/enquiries", get(get_enquiries))
+        .route(
+            "/admissions/enquiries/:id",
+            get(get_enquiry).delete(delete_enquiry),
+        )
+        .route("/admissions/enquiries/:id/status", put(put_enquiry_status))
+}
+
+/// Parents' enquiries, sent from the admissions page.
+pub fn public_router() -> Router {
+    Router::new().route("/admissions/enquiries", post(submit_enquiry))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_enquiries,
+    get_enquiry,
+    submit_enquiry,
+    put_enquiry_status,
+    delete_enquiry
+))]
+struct AdmissionsApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    AdmissionsApi::openapi()
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "enquiry_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum EnquiryStatus {
+    New,
+    Contacted,
+    Closed,
+}
+
+impl EnquiryStatus {
+    /// Enquiries only move forward, except that a closed one can be picked
+    /// up again.
+    const fn can_become(self, next: Self) -> bool {
+        matches!(
+            (self, next),
+            (Self::New, Self::Contacted | Self::Closed)
+                | (Self::Contacted, Self::Closed)
+                | (Self::Closed, Self::Contacted)
+        )
+    }
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Enquiry {
+    id: i32,
+
+    child_name: String,
+    year_group: String,
+    parent_name: String,
+    email: String,
+    phone: String,
+    message: String,
+
+    #[serde(with = "time::serde::iso8601")]
+    consented_at: OffsetDateTime,
+
+    status: EnquiryStatus,
+    #[serde(with = "time::serde::iso8601")]
+    created_at: OffsetDateTime,
+    /// Deleted once this is older than the retention period.
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
+}
+
+impl HasSqlxQueryString for Enquiry {
+    type QueryString = EnquiryQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EnquiryQueryString {
+    id: Option<i32>,
+    status: Option<EnquiryStatus>,
+    year_group: Option<String>,
+    /// Matched against either name or the email address.
+    search: Option<String>,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for EnquiryQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        if let Some(status) = self.status {
+            builder.push(" AND status = ");
+            builder.push_bind(status);
+        }
+
+        if let Some(year_group) = &self.year_group {
+            builder.push(" AND year_group = ");
+            builder.push_bind(year_group);
+        }
+
+        if let Some(search) = &self.search {
+            builder.push(" AND (child_name ILIKE ");
+            builder.push_bind(search);
+            builder.push(" OR parent_name ILIKE ");
+            builder.push_bind(search);
+            builder.push(" OR email ILIKE ");
+            builder.push_bind(search);
+            builder.push(")");
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "child_name" | "year_group" | "status" | "created_at" | "updated_at") =
+            field.as_str()
+        {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Enquiry {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Enquiry {}
+
+#[utoipa::path(
+    get,
+    path = "/admissions/enquiries",
+    tag = "admissions",
+    params(EnquiryQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<Enquiry>),
+        (status = 403, description = "Missing the `ManageAdmissions` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_enquiries(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageAdmissions as u8 }>,
+
+    Query(query_string): Query<<Enquiry as HasSqlxQueryString>::QueryString>,
+    Query(cursor_options): Query<CursorOptions>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Enquiry>>, PhsError> {
+    super::paginated_query_as::<Enquiry>(
+        "list_admissions_enquiries",
+        r#"
+        SELECT id,
+          child_name,
+          year_group,
+          parent_name,
+          email,
+          phone,
+          message,
+          consented_at,
+          status,
+          created_at,
+          updated_at
+        FROM admissions_enquiries
+        "#,
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|enquiries| Json(CursorResponse::new(enquiries)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admissions/enquiries/{id}",
+    tag = "admissions",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Enquiry),
+        (status = 403, description = "Missing the `ManageAdmissions` permission"),
+        (status = 404, description = "No enquiry has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_enquiry(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageAdmissions as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Enquiry>, PhsError> {
+    db.timed(
+        "get_admissions_enquiry",
+        sqlx::query_as!(
+            Enquiry,
+            r#"
+            SELECT id, child_name, year_group, parent_name, email, phone, message,
+              consented_at, status AS "status: EnquiryStatus", created_at, updated_at
+            FROM admissions_enquiries
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(db.read()),
+    )
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+#[derive(Deserialize, ToSchema)]
+struct EnquiryBody {
+    child_name: String,
+    /// As the school names them, e.g. `Reception` or `Year 7`.
+    year_group: String,
+    parent_name: String,
+    email: String,
+    #[serde(default)]
+    phone: String,
+    #[serde(default)]
+    message: String,
+    /// That they agree to their details being kept to handle the enquiry.
+    /// Must be true.
+    consent: bool,
+}
+
+impl Validate for EnquiryBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("child_name", &self.child_name);
+        errors.max_chars("child_name", &self.child_name, MAX_NAME_LENGTH);
+        errors.not_blank("year_group", &self.year_group);
+        errors.max_chars("year_group", &self.year_group, MAX_YEAR_GROUP_LENGTH);
+        errors.not_blank("parent_name", &self.parent_name);
+        errors.max_chars("parent_name", &self.parent_name, MAX_NAME_LENGTH);
+        errors.email("email", &self.email);
+        errors.max_chars("email", &self.email, MAX_EMAIL_LENGTH);
+        errors.max_chars("phone", &self.phone, MAX_PHONE_LENGTH);
+        errors.max_chars("message", &self.message, MAX_MESSAGE_LENGTH);
+        errors.check(
+            self.consent,
+            "consent",
+            "Must be given for the enquiry to be kept",
+        );
+        errors
+    }
+}
+
+/// The public enquiry form. Nothing is sent back, since the details are only
+/// for the admissions team.
+#[utoipa::path(
+    post,
+    path = "/admissions/enquiries",
+    tag = "admissions",
+    request_body = EnquiryBody,
+    responses(
+        (status = 200),
+        (status = 422, description = "A field is missing or too long, the email address isn't valid, or consent wasn't given"),
+    )
+)]
+// Not logging the body, which is a child's personal details
+#[instrument(skip_all)]
+async fn submit_enquiry(
+    Extension(pool): Extension<PgPool>,
+    Validated(body): Validated<EnquiryBody>,
+) -> Result<(), PhsError> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO admissions_enquiries (
+          child_name, year_group, parent_name, email, phone, message, consented_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        RETURNING id
+        "#,
+        body.child_name.trim(),
+        body.year_group.trim(),
+        body.parent_name.trim(),
+        body.email.trim(),
+        body.phone.trim(),
+        body.message.trim()
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    tracing::info!(id, "Received an admissions enquiry");
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct EnquiryStatusBody {
+    status: EnquiryStatus,
+}
+
+/// Moves an enquiry along. New enquiries can be marked contacted or closed,
+/// contacted ones closed, and closed ones contacted again.
+#[utoipa::path(
+    put,
+    path = "/admissions/enquiries/{id}/status",
+    tag = "admissions",
+    params(("id" = i32, Path)),
+    request_body = EnquiryStatusBody,
+    responses(
+        (status = 200, body = Enquiry),
+        (status = 403, description = "Missing the `ManageAdmissions` permission"),
+        (status = 404, description = "No enquiry has this ID"),
+        (status = 409, description = "The enquiry can't go from its status to this one"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn put_enquiry_status(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageAdmissions as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Json(body): Json<EnquiryStatusBody>,
+) -> Result<Json<Enquiry>, PhsError> {
+    let mut tx = pool.begin().await?;
+
+    let current = sqlx::query_scalar!(
+        r#"SELECT status AS "status: EnquiryStatus" FROM admissions_enquiries WHERE id = $1 FOR UPDATE"#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if !current.can_become(body.status) {
+        return Err(PhsError::client(
+            ErrorCode::InvalidTransition,
+            format!(
+                "A {} enquiry can't be marked {}",
+                status_name(current),
+                status_name(body.status)
+            ),
+        ));
+    }
+
+    let enquiry = sqlx::query_as!(
+        Enquiry,
+        r#"
+        UPDATE admissions_enquiries
+        SET status = $1, updated_at = now()
+        WHERE id = $2
+        RETURNING id, child_name, year_group, parent_name, email, phone, message,
+          consented_at, status AS "status: EnquiryStatus", created_at, updated_at
+        "#,
+        body.status as EnquiryStatus,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(enquiry))
+}
+
+const fn status_name(status: EnquiryStatus) -> &'static str {
+    match status {
+        EnquiryStatus::New => "new",
+        EnquiryStatus::Contacted => "contacted",
+        EnquiryStatus::Closed => "closed",
+    }
+}
+
+/// Deletes an enquiry straight away, such as when the family asks for their
+/// details to be erased.
+#[utoipa::path(
+    delete,
+    path = "/admissions/enquiries/{id}",
+    tag = "admissions",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageAdmissions` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_enquiry(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageAdmissions as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM admissions_enquiries WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
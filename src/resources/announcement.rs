@@ -0,0 +1,479 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderValue},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::PhsError,
+    response_cache::{cache_responses, Scope},
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+};
+
+/// Longest a banner's message can be. Anything longer belongs in a post.
+const MAX_MESSAGE_LENGTH: usize = 1000;
+
+/// How long the active announcements are served from memory before they're
+/// loaded again, and how long clients may cache them for.
+const ACTIVE_TTL: Duration = Duration::from_secs(30);
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/announcements",
+            get(get_announcements).post(create_announcement),
+        )
+        .route("/announcements/active", get(get_active_announcements))
+        .route(
+            "/announcements/:id",
+            get(get_announcement)
+                .put(put_announcement)
+                .delete(delete_announcement),
+        )
+        .layer(middleware::from_fn_with_state(
+            Scope::Announcements,
+            cache_responses,
+        ))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_announcements,
+    get_active_announcements,
+    get_announcement,
+    create_announcement,
+    put_announcement,
+    delete_announcement
+))]
+struct AnnouncementApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    AnnouncementApi::openapi()
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "announcement_severity", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Announcement {
+    id: i32,
+
+    message: String,
+    severity: Severity,
+
+    #[serde(with = "time::serde::iso8601")]
+    starts_at: OffsetDateTime,
+    /// Shown until removed if null.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    ends_at: Option<OffsetDateTime>,
+    /// Whether visitors can close the banner.
+    dismissible: bool,
+}
+
+impl Announcement {
+    fn is_active(&self, now: OffsetDateTime) -> bool {
+        self.starts_at <= now && self.ends_at.is_none_or(|ends_at| ends_at > now)
+    }
+}
+
+/// The announcements that haven't ended, kept in memory so the public site
+/// polling for them costs a query at most every [`ACTIVE_TTL`]. Changes made
+/// through this server clear it straight away; other servers catch up when
+/// theirs expires.
+#[derive(Clone, Default)]
+pub struct AnnouncementCache(Arc<Mutex<Option<(Instant, Arc<[Announcement]>)>>>);
+
+impl AnnouncementCache {
+    /// Held locked while loading, so a burst of requests after it expires
+    /// only queries once.
+    async fn unended(&self, db: &Db) -> Result<Arc<[Announcement]>, PhsError> {
+        let mut cached = self.0.lock().await;
+
+        if let Some((loaded_at, announcements)) = &*cached {
+            if loaded_at.elapsed() < ACTIVE_TTL {
+                return Ok(announcements.clone());
+            }
+        }
+
+        // From the primary, so a change isn't missed and then kept for a
+        // whole TTL because a replica was behind
+        let announcements: Arc<[Announcement]> = db
+            .timed(
+                "list_unended_announcements",
+                sqlx::query_as!(
+                    Announcement,
+                    r#"
+                    SELECT id, message, severity AS "severity: Severity", starts_at, ends_at, dismissible
+                    FROM announcements
+                    WHERE ends_at IS NULL OR ends_at > now()
+                    ORDER BY severity DESC, starts_at DESC
+                    "#
+                )
+                .fetch_all(db.write()),
+            )
+            .await?
+            .into();
+
+        *cached = Some((Instant::now(), announcements.clone()));
+        Ok(announcements)
+    }
+
+    async fn clear(&self) {
+        *self.0.lock().await = None;
+    }
+}
+
+impl HasSqlxQueryString for Announcement {
+    type QueryString = AnnouncementQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AnnouncementQueryString {
+    id: Option<i32>,
+    severity: Option<Severity>,
+    dismissible: Option<bool>,
+
+    /// Only announcements that haven't ended.
+    #[serde(default)]
+    unended: bool,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for AnnouncementQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        if let Some(severity) = self.severity {
+            builder.push(" AND severity = ");
+            builder.push_bind(severity);
+        }
+
+        if let Some(dismissible) = self.dismissible {
+            builder.push(" AND dismissible = ");
+            builder.push_bind(dismissible);
+        }
+
+        if self.unended {
+            builder.push(" AND (ends_at IS NULL OR ends_at > now())");
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "severity" | "starts_at" | "ends_at") = field.as_str() {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Announcement {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Announcement {}
+
+/// Every announcement, past and future. The public site uses
+/// `/announcements/active` instead.
+#[utoipa::path(
+    get,
+    path = "/announcements",
+    tag = "announcements",
+    params(AnnouncementQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<Announcement>),
+        (status = 403, description = "Missing the `ManageAnnouncements` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_announcements(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageAnnouncements as u8 }>,
+
+    Query(query_string): Query<<Announcement as HasSqlxQueryString>::QueryString>,
+    Query(cursor_options): Query<CursorOptions>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Announcement>>, PhsError> {
+    super::paginated_query_as::<Announcement>(
+        "list_announcements",
+        r#"
+        SELECT id,
+          message,
+          severity,
+          starts_at,
+          ends_at,
+          dismissible
+        FROM announcements
+        "#,
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|announcements| Json(CursorResponse::new(announcements)))
+}
+
+/// What to show right now, most severe first. Served from memory and cached
+/// briefly by clients, so it's fine to poll.
+#[utoipa::path(
+    get,
+    path = "/announcements/active",
+    tag = "announcements",
+    responses((status = 200, body = Vec<Announcement>))
+)]
+#[instrument(skip(db, cache))]
+async fn get_active_announcements(
+    Extension(db): Extension<Db>,
+    Extension(cache): Extension<AnnouncementCache>,
+) -> Result<Response, PhsError> {
+    let announcements = cache.unended(&db).await?;
+
+    let now = OffsetDateTime::now_utc();
+    let active = announcements
+        .iter()
+        .filter(|announcement| announcement.is_active(now))
+        .collect::<Vec<_>>();
+
+    let cache_control = format!("public, max-age={}", ACTIVE_TTL.as_secs());
+
+    Ok((
+        [(
+            header::CACHE_CONTROL,
+            HeaderValue::from_str(&cache_control)
+                .map_err(|e| PhsError::internal(e, "Invalid Cache-Control"))?,
+        )],
+        Json(active),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/announcements/{id}",
+    tag = "announcements",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Announcement),
+        (status = 403, description = "Missing the `ManageAnnouncements` permission"),
+        (status = 404, description = "No announcement has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_announcement(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageAnnouncements as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Announcement>, PhsError> {
+    db.timed(
+        "get_announcement",
+        sqlx::query_as!(
+            Announcement,
+            r#"
+            SELECT id, message, severity AS "severity: Severity", starts_at, ends_at, dismissible
+            FROM announcements
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(db.read()),
+    )
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct AnnouncementBody {
+    message: String,
+    severity: Severity,
+    #[serde(with = "time::serde::iso8601")]
+    starts_at: OffsetDateTime,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    ends_at: Option<OffsetDateTime>,
+    #[serde(default = "dismissible_default")]
+    dismissible: bool,
+}
+
+const fn dismissible_default() -> bool {
+    true
+}
+
+impl Validate for AnnouncementBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("message", &self.message);
+        errors.max_chars("message", &self.message, MAX_MESSAGE_LENGTH);
+        errors.check(
+            self.ends_at.is_none_or(|ends_at| ends_at > self.starts_at),
+            "ends_at",
+            "Must be after starts_at",
+        );
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/announcements",
+    tag = "announcements",
+    request_body = AnnouncementBody,
+    responses(
+        (status = 200, body = Announcement),
+        (status = 403, description = "Missing the `ManageAnnouncements` permission"),
+        (status = 422, description = "The message is empty or too long, or it ends before it starts"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, cache, _auth_session))]
+async fn create_announcement(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageAnnouncements as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<AnnouncementCache>,
+    Validated(body): Validated<AnnouncementBody>,
+) -> Result<Json<Announcement>, PhsError> {
+    let announcement = sqlx::query_as!(
+        Announcement,
+        r#"
+        INSERT INTO announcements (message, severity, starts_at, ends_at, dismissible)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, message, severity AS "severity: Severity", starts_at, ends_at, dismissible
+        "#,
+        body.message.trim(),
+        body.severity as Severity,
+        body.starts_at,
+        body.ends_at,
+        body.dismissible
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    cache.clear().await;
+
+    Ok(Json(announcement))
+}
+
+#[utoipa::path(
+    put,
+    path = "/announcements/{id}",
+    tag = "announcements",
+    params(("id" = i32, Path)),
+    request_body = AnnouncementBody,
+    responses(
+        (status = 200, body = Announcement),
+        (status = 403, description = "Missing the `ManageAnnouncements` permission"),
+        (status = 404, description = "No announcement has this ID"),
+        (status = 422, description = "The message is empty or too long, or it ends before it starts"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, cache, _auth_session))]
+async fn put_announcement(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageAnnouncements as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<AnnouncementCache>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<AnnouncementBody>,
+) -> Result<Json<Announcement>, PhsError> {
+    let announcement = sqlx::query_as!(
+        Announcement,
+        r#"
+        UPDATE announcements
+        SET message = $1,
+            severity = $2,
+            starts_at = $3,
+            ends_at = $4,
+            dismissible = $5
+        WHERE id = $6
+        RETURNING id, message, severity AS "severity: Severity", starts_at, ends_at, dismissible
+        "#,
+        body.message.trim(),
+        body.severity as Severity,
+        body.starts_at,
+        body.ends_at,
+        body.dismissible,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    cache.clear().await;
+
+    Ok(Json(announcement))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/announcements/{id}",
+    tag = "announcements",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageAnnouncements` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, cache, _auth_session))]
+async fn delete_announcement(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageAnnouncements as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<AnnouncementCache>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM announcements WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    cache.clear().await;
+
+    Ok(())
+}
@@ -1,82 +1,162 @@
 use axum::{
     extract::Path,
+    middleware,
     routing::{delete, post},
     Extension, Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, PgPool};
+use time::OffsetDateTime;
 use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
 
 use crate::{
     auth::{AuthSession, Permission, RequirePermission},
+    db::{Db, RowCount},
     error::PhsError,
+    response_cache::{cache_responses, Scope},
+    validation::{FieldErrors, Validate, Validated},
 };
 
-#[derive(FromRow, Serialize, Deserialize)]
+/// Longest a category's name can be, as stored.
+const MAX_NAME_LENGTH: usize = 255;
+
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Category {
     id: i32,
     category: String,
+
+    created_by: Option<i32>,
+    updated_by: Option<i32>,
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
 }
 
+impl RowCount for Category {}
+
 pub fn router() -> Router {
     Router::new()
         .route(
-            "/v1/categories/:id",
+            "/categories/:id",
             delete(delete_tag).put(put_tag).get(get_tag),
         )
-        .route("/v1/categories", post(create_tag).get(get_tags))
+        .route("/categories", post(create_tag).get(get_tags))
+        .layer(middleware::from_fn_with_state(
+            Scope::Categories,
+            cache_responses,
+        ))
 }
 
-async fn get_tags(Extension(pool): Extension<PgPool>) -> Result<Json<Vec<Category>>, PhsError> {
-    let tags = sqlx::query_as!(Category, "SELECT id, category FROM categories LIMIT 100")
-        .fetch_all(&pool)
+#[derive(OpenApi)]
+#[openapi(paths(get_tags, get_tag, create_tag, put_tag, delete_tag))]
+struct CategoryApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    CategoryApi::openapi()
+}
+
+#[utoipa::path(
+    get,
+    path = "/categories",
+    tag = "categories",
+    responses((status = 200, body = Vec<Category>))
+)]
+async fn get_tags(Extension(db): Extension<Db>) -> Result<Json<Vec<Category>>, PhsError> {
+    let tags = db
+        .timed(
+            "list_categories",
+            sqlx::query_as!(
+                Category,
+                "SELECT id, category, created_by, updated_by, updated_at FROM categories LIMIT 100"
+            )
+            .fetch_all(db.read()),
+        )
         .await?;
 
     Ok(Json(tags))
 }
 
-#[instrument(skip(pool))]
+#[utoipa::path(
+    get,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Category),
+        (status = 404, description = "No category has this ID"),
+    )
+)]
+#[instrument(skip(db))]
 async fn get_tag(
-    Extension(pool): Extension<PgPool>,
+    Extension(db): Extension<Db>,
     Path(id): Path<i32>,
 ) -> Result<Json<Category>, PhsError> {
-    let tag = sqlx::query_as!(
-        Category,
-        r#"
+    let tag = db
+        .timed(
+            "get_category",
+            sqlx::query_as!(
+                Category,
+                r#"
         SELECT id,
-            category
+            category,
+            created_by,
+            updated_by,
+            updated_at
         FROM categories
         WHERE id = $1
         "#,
-        id
-    )
-    .fetch_one(&pool)
-    .await?;
+                id
+            )
+            .fetch_one(db.read()),
+        )
+        .await?;
 
     Ok(Json(tag))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct CreateCategoryBody {
     tag: String,
 }
 
-#[instrument(skip(pool, _auth_session))]
+impl Validate for CreateCategoryBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("tag", &self.tag);
+        errors.max_chars("tag", &self.tag, MAX_NAME_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/categories",
+    tag = "categories",
+    request_body = CreateCategoryBody,
+    responses(
+        (status = 200, body = Category),
+        (status = 403, description = "Missing the `EditCategories` permission"),
+        (status = 422, description = "The name is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, auth_session))]
 async fn create_tag(
-    _auth_session: AuthSession,
+    auth_session: AuthSession,
     _: RequirePermission<{ Permission::EditCategories as u8 }>,
 
     Extension(pool): Extension<PgPool>,
-    Json(req): Json<CreateCategoryBody>,
+    Validated(req): Validated<CreateCategoryBody>,
 ) -> Result<Json<Category>, PhsError> {
     let tag = sqlx::query_as!(
         Category,
         r#"
-        INSERT INTO categories(category)
-        VALUES ($1)
-        RETURNING id, category
+        INSERT INTO categories(category, created_by, updated_by)
+        VALUES ($1, $2, $2)
+        RETURNING id, category, created_by, updated_by, updated_at
         "#,
-        req.tag
+        req.tag,
+        auth_session.data().id()
     )
     .fetch_one(&pool)
     .await?;
@@ -84,30 +164,54 @@ async fn create_tag(
     Ok(Json(tag))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 struct PutTagBody {
     new: String,
 }
 
-#[instrument(skip(pool, _auth_session))]
+impl Validate for PutTagBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("new", &self.new);
+        errors.max_chars("new", &self.new, MAX_NAME_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(("id" = i32, Path)),
+    request_body = PutTagBody,
+    responses(
+        (status = 200, body = Category),
+        (status = 403, description = "Missing the `EditCategories` permission"),
+        (status = 404, description = "No category has this ID"),
+        (status = 422, description = "The name is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, auth_session))]
 async fn put_tag(
-    _auth_session: AuthSession,
+    auth_session: AuthSession,
     _: RequirePermission<{ Permission::EditCategories as u8 }>,
 
     Extension(pool): Extension<PgPool>,
     Path(id): Path<i32>,
-    Json(body): Json<PutTagBody>,
+    Validated(body): Validated<PutTagBody>,
 ) -> Result<Json<Category>, PhsError> {
     let tag = sqlx::query_as!(
         Category,
         r#"
         UPDATE categories
-        SET category = $1
+        SET category = $1, updated_by = $3, updated_at = now()
         WHERE id = $2
-        RETURNING id, category
+        RETURNING id, category, created_by, updated_by, updated_at
         "#,
         body.new,
-        id
+        id,
+        auth_session.data().id()
     )
     .fetch_one(&pool)
     .await?;
@@ -115,6 +219,17 @@ async fn put_tag(
     Ok(Json(tag))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `EditCategories` permission"),
+    ),
+    security(("session" = []))
+)]
 #[instrument(skip(pool, _auth_session))]
 async fn delete_tag(
     _auth_session: AuthSession,
@@ -1,88 +1,162 @@
 use axum::{
     extract::Path,
+    middleware,
     routing::{delete, post},
     Extension, Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, PgPool};
+use time::OffsetDateTime;
 use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
 
 use crate::{
     auth::{AuthSession, Permission, RequirePermission},
+    db::{Db, RowCount},
     error::PhsError,
+    response_cache::{cache_responses, Scope},
+    validation::{FieldErrors, Validate, Validated},
 };
 
-#[derive(FromRow, Serialize, Deserialize)]
+/// Longest a department's name can be, as stored.
+const MAX_NAME_LENGTH: usize = 255;
+
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Department {
     pub id: i32,
     pub department: String,
+
+    pub created_by: Option<i32>,
+    pub updated_by: Option<i32>,
+    #[serde(with = "time::serde::iso8601")]
+    pub updated_at: OffsetDateTime,
 }
 
+impl RowCount for Department {}
+
 pub fn router() -> Router {
     Router::new()
         .route(
-            "/v1/departments/:id",
+            "/departments/:id",
             delete(delete_department)
                 .put(put_department)
                 .get(get_department),
         )
-        .route(
-            "/v1/departments",
-            post(create_department).get(get_departments),
-        )
+        .route("/departments", post(create_department).get(get_departments))
+        .layer(middleware::from_fn_with_state(
+            Scope::Departments,
+            cache_responses,
+        ))
 }
 
-#[instrument(skip(pool))]
-async fn get_departments(
-    Extension(pool): Extension<PgPool>,
-) -> Result<Json<Vec<Department>>, PhsError> {
-    sqlx::query_as!(
-        Department,
-        r#"SELECT id, department FROM departments LIMIT 100"#
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_departments,
+    get_department,
+    create_department,
+    put_department,
+    delete_department
+))]
+struct DepartmentApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    DepartmentApi::openapi()
+}
+
+#[utoipa::path(
+    get,
+    path = "/departments",
+    tag = "departments",
+    responses((status = 200, body = Vec<Department>))
+)]
+#[instrument(skip(db))]
+async fn get_departments(Extension(db): Extension<Db>) -> Result<Json<Vec<Department>>, PhsError> {
+    db.timed(
+        "list_departments",
+        sqlx::query_as!(
+            Department,
+            r#"SELECT id, department, created_by, updated_by, updated_at FROM departments LIMIT 100"#
+        )
+        .fetch_all(db.read()),
     )
-    .fetch_all(&pool)
     .await
     .map(Json)
     .map_err(Into::into)
 }
 
-#[instrument(skip(pool))]
+#[utoipa::path(
+    get,
+    path = "/departments/{id}",
+    tag = "departments",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Department),
+        (status = 404, description = "No department has this ID"),
+    )
+)]
+#[instrument(skip(db))]
 async fn get_department(
-    Extension(pool): Extension<PgPool>,
+    Extension(db): Extension<Db>,
     Path(id): Path<i32>,
 ) -> Result<Json<Department>, PhsError> {
-    let department = sqlx::query_as!(
-        Department,
-        r#"SELECT id, department FROM departments WHERE id = $1"#,
-        id
-    )
-    .fetch_one(&pool)
-    .await?;
+    let department = db
+        .timed(
+            "get_department",
+            sqlx::query_as!(
+                Department,
+                r#"SELECT id, department, created_by, updated_by, updated_at FROM departments WHERE id = $1"#,
+                id
+            )
+            .fetch_one(db.read()),
+        )
+        .await?;
 
     Ok(Json(department))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 struct CreateDepartmentBody {
     department: String,
 }
 
-#[instrument(skip(pool, _auth_session))]
+impl Validate for CreateDepartmentBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("department", &self.department);
+        errors.max_chars("department", &self.department, MAX_NAME_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/departments",
+    tag = "departments",
+    request_body = CreateDepartmentBody,
+    responses(
+        (status = 200, body = Department),
+        (status = 403, description = "Missing the `EditDepartments` permission"),
+        (status = 422, description = "The name is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, auth_session))]
 async fn create_department(
-    _auth_session: AuthSession,
+    auth_session: AuthSession,
     _: RequirePermission<{ Permission::EditDepartments as u8 }>,
 
     Extension(pool): Extension<PgPool>,
-    Json(req): Json<CreateDepartmentBody>,
+    Validated(req): Validated<CreateDepartmentBody>,
 ) -> Result<Json<Department>, PhsError> {
     let department = sqlx::query_as!(
         Department,
         r#"
-        INSERT INTO departments(department)
-        VALUES ($1)
-        RETURNING id, department
+        INSERT INTO departments(department, created_by, updated_by)
+        VALUES ($1, $2, $2)
+        RETURNING id, department, created_by, updated_by, updated_at
         "#,
-        req.department
+        req.department,
+        auth_session.data().id()
     )
     .fetch_one(&pool)
     .await?;
@@ -90,30 +164,54 @@ async fn create_department(
     Ok(Json(department))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 struct PutDepartmentBody {
     new: String,
 }
 
-#[instrument(skip(pool, _auth_session))]
+impl Validate for PutDepartmentBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("new", &self.new);
+        errors.max_chars("new", &self.new, MAX_NAME_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/departments/{id}",
+    tag = "departments",
+    params(("id" = i32, Path)),
+    request_body = PutDepartmentBody,
+    responses(
+        (status = 200, body = Department),
+        (status = 403, description = "Missing the `EditDepartments` permission"),
+        (status = 404, description = "No department has this ID"),
+        (status = 422, description = "The name is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, auth_session))]
 async fn put_department(
-    _auth_session: AuthSession,
+    auth_session: AuthSession,
     _: RequirePermission<{ Permission::EditDepartments as u8 }>,
 
     Extension(pool): Extension<PgPool>,
     Path(id): Path<i32>,
-    Json(body): Json<PutDepartmentBody>,
+    Validated(body): Validated<PutDepartmentBody>,
 ) -> Result<Json<Department>, PhsError> {
     let department = sqlx::query_as!(
         Department,
         r#"
         UPDATE departments
-        SET department = $1
+        SET department = $1, updated_by = $3, updated_at = now()
         WHERE id = $2
-        RETURNING id, department
+        RETURNING id, department, created_by, updated_by, updated_at
         "#,
         body.new,
-        id
+        id,
+        auth_session.data().id()
     )
     .fetch_one(&pool)
     .await?;
@@ -121,6 +219,17 @@ async fn put_department(
     Ok(Json(department))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/departments/{id}",
+    tag = "departments",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `EditDepartments` permission"),
+    ),
+    security(("session" = []))
+)]
 #[instrument(skip(pool, _auth_session))]
 async fn delete_department(
     _auth_session: AuthSession,
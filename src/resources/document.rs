@@ -0,0 +1,627 @@
+use axum::{
+    extract::{Multipart, Path, Query},
+    routing::{delete, get},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, CurrentPermissions, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::{ErrorCode, PhsError},
+    media::{create_media, sanitise_filename, Scanner},
+    storage::SharedStorage,
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+};
+
+/// Longest a document's title or category can be, as stored.
+const MAX_NAME_LENGTH: usize = 255;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/documents", get(get_documents).post(create_document))
+        .route("/documents/by-category", get(get_documents_by_category))
+        .route(
+            "/documents/:id",
+            get(get_document).put(put_document).delete(delete_document),
+        )
+        .route(
+            "/documents/:id/versions",
+            get(get_versions).post(upload_version),
+        )
+        .route(
+            "/documents/:id/versions/:version_id",
+            delete(delete_version),
+        )
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_documents,
+    get_documents_by_category,
+    get_document,
+    create_document,
+    put_document,
+    delete_document,
+    get_versions,
+    upload_version,
+    delete_version
+))]
+struct DocumentApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    DocumentApi::openapi()
+}
+
+/// A document with its latest version, which is the one to download.
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Document {
+    id: i32,
+
+    title: String,
+    description: String,
+    category: String,
+    #[serde(with = "time::serde::iso8601::option")]
+    review_by: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
+
+    /// The latest version's ID. Documents without one yet aren't public.
+    version: Option<i32>,
+    /// Where the latest version is served from.
+    url: Option<String>,
+    mime: Option<String>,
+    size: Option<i64>,
+}
+
+impl HasSqlxQueryString for Document {
+    type QueryString = DocumentQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DocumentQueryString {
+    id: Option<i32>,
+    title: Option<String>,
+    category: Option<String>,
+
+    /// Only documents due for review by now.
+    #[serde(default)]
+    overdue: bool,
+    /// Also list documents that have nothing uploaded yet. Needs the
+    /// `ManageDocuments` permission.
+    #[serde(default)]
+    include_empty: bool,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for DocumentQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        if !self.include_empty {
+            builder.push(" AND version IS NOT NULL");
+        }
+
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        if let Some(title) = &self.title {
+            builder.push(" AND title LIKE ");
+            builder.push_bind(title);
+        }
+
+        if let Some(category) = &self.category {
+            builder.push(" AND category = ");
+            builder.push_bind(category);
+        }
+
+        if self.overdue {
+            builder.push(" AND review_by <= now()");
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "title" | "category" | "review_by" | "updated_at") = field.as_str() {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Document {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Document {}
+
+/// Whether whoever's asking may see documents with nothing uploaded yet.
+fn can_see_empty(permissions: CurrentPermissions) -> Result<(), PhsError> {
+    if permissions.contains(Permission::ManageDocuments) {
+        Ok(())
+    } else {
+        Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Only those who manage documents can see ones with nothing uploaded",
+        ))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/documents",
+    tag = "documents",
+    params(DocumentQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<Document>),
+        (status = 403, description = "Asked for empty documents without the `ManageDocuments` permission"),
+    )
+)]
+#[instrument(skip(db, permissions))]
+async fn get_documents(
+    permissions: CurrentPermissions,
+    Query(query_string): Query<<Document as HasSqlxQueryString>::QueryString>,
+    Query(cursor_options): Query<CursorOptions>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Document>>, PhsError> {
+    if query_string.include_empty {
+        can_see_empty(permissions)?;
+    }
+
+    super::paginated_query_as::<Document>(
+        "list_documents",
+        r#"
+        SELECT id,
+          title,
+          description,
+          category,
+          review_by,
+          updated_at,
+          version,
+          url,
+          mime,
+          size
+        FROM current_documents
+        "#,
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|documents| Json(CursorResponse::new(documents)))
+}
+
+#[derive(Serialize, ToSchema)]
+struct DocumentCategory {
+    category: String,
+    documents: Vec<Document>,
+}
+
+/// Every document with something uploaded, grouped by category, both in
+/// alphabetical order. Made for the public policies page.
+#[utoipa::path(
+    get,
+    path = "/documents/by-category",
+    tag = "documents",
+    responses((status = 200, body = Vec<DocumentCategory>))
+)]
+#[instrument(skip(db))]
+async fn get_documents_by_category(
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<DocumentCategory>>, PhsError> {
+    let documents = db
+        .timed(
+            "list_documents_by_category",
+            sqlx::query_as!(
+                Document,
+                r#"
+                SELECT id AS "id!", title AS "title!", description AS "description!",
+                  category AS "category!", review_by, updated_at AS "updated_at!",
+                  version, url, mime, size
+                FROM current_documents
+                WHERE version IS NOT NULL
+                ORDER BY category, title, id
+                "#
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    let mut categories: Vec<DocumentCategory> = Vec::new();
+    for document in documents {
+        match categories.last_mut() {
+            Some(last) if last.category == document.category => last.documents.push(document),
+            _ => categories.push(DocumentCategory {
+                category: document.category.clone(),
+                documents: vec![document],
+            }),
+        }
+    }
+
+    Ok(Json(categories))
+}
+
+/// A document with nothing uploaded is only found with the `ManageDocuments`
+/// permission.
+#[utoipa::path(
+    get,
+    path = "/documents/{id}",
+    tag = "documents",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Document),
+        (status = 404, description = "No document with anything uploaded has this ID"),
+    )
+)]
+#[instrument(skip(db, permissions))]
+async fn get_document(
+    permissions: CurrentPermissions,
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Document>, PhsError> {
+    let document = db.timed("get_document", fetch(db.read(), id)).await?;
+
+    if document.version.is_none() && can_see_empty(permissions).is_err() {
+        return Err(PhsError::client(
+            ErrorCode::NotFound,
+            "No document with anything uploaded has this ID",
+        ));
+    }
+
+    Ok(Json(document))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct DocumentBody {
+    title: String,
+    #[serde(default)]
+    description: String,
+    category: String,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    review_by: Option<OffsetDateTime>,
+}
+
+impl Validate for DocumentBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("title", &self.title);
+        errors.max_chars("title", &self.title, MAX_NAME_LENGTH);
+        errors.not_blank("category", &self.category);
+        errors.max_chars("category", &self.category, MAX_NAME_LENGTH);
+        errors
+    }
+}
+
+/// Makes a document to upload versions of. It stays out of public listings
+/// until the first one is uploaded.
+#[utoipa::path(
+    post,
+    path = "/documents",
+    tag = "documents",
+    request_body = DocumentBody,
+    responses(
+        (status = 200, body = Document),
+        (status = 403, description = "Missing the `ManageDocuments` permission"),
+        (status = 422, description = "The title or category is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn create_document(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageDocuments as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Validated(body): Validated<DocumentBody>,
+) -> Result<Json<Document>, PhsError> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO documents (title, description, category, review_by)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+        body.title.trim(),
+        body.description,
+        body.category.trim(),
+        body.review_by
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    fetch(&pool, id).await.map(Json).map_err(Into::into)
+}
+
+#[utoipa::path(
+    put,
+    path = "/documents/{id}",
+    tag = "documents",
+    params(("id" = i32, Path)),
+    request_body = DocumentBody,
+    responses(
+        (status = 200, body = Document),
+        (status = 403, description = "Missing the `ManageDocuments` permission"),
+        (status = 404, description = "No document has this ID"),
+        (status = 422, description = "The title or category is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn put_document(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageDocuments as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<DocumentBody>,
+) -> Result<Json<Document>, PhsError> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE documents
+        SET title = $1,
+            description = $2,
+            category = $3,
+            review_by = $4,
+            updated_at = now()
+        WHERE id = $5
+        RETURNING id
+        "#,
+        body.title.trim(),
+        body.description,
+        body.category.trim(),
+        body.review_by,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    fetch(&pool, id).await.map(Json).map_err(Into::into)
+}
+
+/// Deletes a document and its history. The files stay in the media library.
+#[utoipa::path(
+    delete,
+    path = "/documents/{id}",
+    tag = "documents",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageDocuments` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_document(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageDocuments as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM documents WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct DocumentVersion {
+    id: i32,
+    /// The upload in the media library.
+    media: i32,
+    url: String,
+    note: String,
+    uploaded_by: Option<i32>,
+    #[serde(with = "time::serde::iso8601")]
+    created_at: OffsetDateTime,
+}
+
+/// Every version of a document, newest first.
+#[utoipa::path(
+    get,
+    path = "/documents/{id}/versions",
+    tag = "documents",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Vec<DocumentVersion>),
+        (status = 403, description = "Missing the `ManageDocuments` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_versions(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageDocuments as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<DocumentVersion>>, PhsError> {
+    db.timed(
+        "list_document_versions",
+        sqlx::query_as!(
+            DocumentVersion,
+            r#"
+            SELECT v.id, v.media, '/media/' || m.id || '/' || m.filename AS "url!",
+              v.note, v.uploaded_by, v.created_at
+            FROM document_versions v
+            JOIN media m ON m.id = v.media
+            WHERE v.document_id = $1
+            ORDER BY v.created_at DESC, v.id DESC
+            "#,
+            id
+        )
+        .fetch_all(db.read()),
+    )
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Uploads a new version of a document, which replaces the current one in
+/// listings. Accepts a `multipart/form-data` body with a `file` field and an
+/// optional `note` field saying what changed. The file goes into the media
+/// library, so the same types are allowed.
+#[utoipa::path(
+    post,
+    path = "/documents/{id}/versions",
+    tag = "documents",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = DocumentVersion),
+        (status = 400, description = "No file was included"),
+        (status = 403, description = "Missing the `ManageDocuments` permission"),
+        (status = 404, description = "No document has this ID"),
+        (status = 415, description = "This type of file can't be uploaded, or it's a malformed image"),
+        (status = 422, description = "The virus scanner flagged the file, which has been quarantined"),
+        (status = 503, description = "The virus scanner can't be reached"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, scanner, auth_session, multipart))]
+async fn upload_version(
+    auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageDocuments as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(scanner): Extension<Scanner>,
+    Path(id): Path<i32>,
+    mut multipart: Multipart,
+) -> Result<Json<DocumentVersion>, PhsError> {
+    // Before reading the file, so it isn't stored for nothing
+    let title = sqlx::query_scalar!("SELECT title FROM documents WHERE id = $1", id)
+        .fetch_optional(&pool)
+        .await?
+        .ok_or_else(|| PhsError::client(ErrorCode::NotFound, "No document has this ID"))?;
+
+    let mut note = String::new();
+    let mut file = None;
+
+    while let Some(mut field) = multipart.next_field().await? {
+        match field.name() {
+            Some("note") => note = field.text().await?,
+            Some("file") if file.is_none() => {
+                let name = field.file_name().unwrap_or_default().to_owned();
+                sanitise_filename(&name)?;
+
+                let mut data = Vec::new();
+                while let Some(chunk) = field.chunk().await? {
+                    data.extend_from_slice(&chunk);
+                }
+
+                file = Some((name, data));
+            }
+            _ => {}
+        }
+    }
+
+    let (name, data) = file.ok_or(PhsError::client(
+        ErrorCode::MissingFile,
+        "No file was included in the upload",
+    ))?;
+
+    let uploader = auth_session.data().id();
+    let media = create_media(
+        &pool,
+        storage,
+        &scanner,
+        &name,
+        data,
+        &title,
+        Some(uploader),
+    )
+    .await?;
+
+    let mut tx = pool.begin().await?;
+
+    let version = sqlx::query_as!(
+        DocumentVersion,
+        r#"
+        INSERT INTO document_versions (document_id, media, note, uploaded_by)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, media, $5::text AS "url!", note, uploaded_by, created_at
+        "#,
+        id,
+        media.id(),
+        note.trim(),
+        uploader,
+        media.url()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!("UPDATE documents SET updated_at = now() WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(version))
+}
+
+/// Removes a version uploaded by mistake, so the one before is current again.
+/// The file stays in the media library.
+#[utoipa::path(
+    delete,
+    path = "/documents/{id}/versions/{version_id}",
+    tag = "documents",
+    params(("id" = i32, Path), ("version_id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageDocuments` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_version(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageDocuments as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path((id, version_id)): Path<(i32, i32)>,
+) -> Result<(), PhsError> {
+    sqlx::query!(
+        "DELETE FROM document_versions WHERE id = $1 AND document_id = $2",
+        version_id,
+        id
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn fetch(pool: &PgPool, id: i32) -> Result<Document, sqlx::Error> {
+    sqlx::query_as!(
+        Document,
+        r#"
+        SELECT id AS "id!", title AS "title!", description AS "description!",
+          category AS "category!", review_by, updated_at AS "updated_at!",
+          version, url, mime, size
+        FROM current_documents
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
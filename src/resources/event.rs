@@ -0,0 +1,539 @@
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::PhsError,
+    validation::{FieldErrors, Validate, Validated},
+    ServerConfig,
+};
+
+use super::{
+    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+};
+
+mod ical;
+mod registration;
+mod sync;
+
+pub use sync::calendar_sync_job;
+
+/// Longest an event's title or location can be, as stored.
+const MAX_NAME_LENGTH: usize = 255;
+
+/// How far back the calendar feed goes, so subscribers keep recent history
+/// without the feed growing forever.
+const FEED_HISTORY: time::Duration = time::Duration::days(365);
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/events", get(get_events).post(create_event))
+        .route("/events.ics", get(get_calendar))
+        .route(
+            "/events/:id",
+            get(get_event).put(put_event).delete(delete_event),
+        )
+        .merge(registration::router())
+}
+
+pub fn public_router() -> Router {
+    registration::public_router()
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_events,
+    get_event,
+    get_calendar,
+    create_event,
+    put_event,
+    delete_event
+))]
+struct EventApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut openapi = EventApi::openapi();
+    openapi.merge(registration::openapi());
+    openapi
+}
+
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Event {
+    id: i32,
+
+    title: String,
+    description: String,
+    location: String,
+
+    #[serde(with = "time::serde::iso8601")]
+    starts_at: OffsetDateTime,
+    /// For all-day events, on the last day.
+    #[serde(with = "time::serde::iso8601")]
+    ends_at: OffsetDateTime,
+    /// Only the dates of `starts_at` and `ends_at` count, in UTC.
+    all_day: bool,
+
+    department: Option<i32>,
+
+    /// Whether people can sign up through `/events/{id}/register`.
+    registration: bool,
+    /// Most people who can sign up, or unlimited if null.
+    capacity: Option<i32>,
+    /// When sign-up opens, or straight away if null.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    registration_opens_at: Option<OffsetDateTime>,
+    /// When sign-up closes, or when the event starts if null.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    registration_closes_at: Option<OffsetDateTime>,
+    /// How many people have signed up.
+    registered: i64,
+
+    /// The calendar feed this was copied from, or null if it was made here.
+    /// Changes to copied events are overwritten when the feed next syncs.
+    source: Option<String>,
+}
+
+impl HasSqlxQueryString for Event {
+    type QueryString = EventQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventQueryString {
+    id: Option<i32>,
+    title: Option<String>,
+
+    /// Only events that end on or after this.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    from: Option<OffsetDateTime>,
+    /// Only events that start on or before this.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    to: Option<OffsetDateTime>,
+
+    all_day: Option<bool>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    department: Option<Option<i32>>,
+    /// A calendar feed's name, or null for events made here.
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    source: Option<Option<String>>,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for EventQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        if let Some(title) = &self.title {
+            builder.push(" AND title LIKE ");
+            builder.push_bind(title);
+        }
+
+        if let Some(from) = self.from {
+            builder.push(" AND ends_at >= ");
+            builder.push_bind(from);
+        }
+
+        if let Some(to) = self.to {
+            builder.push(" AND starts_at <= ");
+            builder.push_bind(to);
+        }
+
+        if let Some(all_day) = self.all_day {
+            builder.push(" AND all_day = ");
+            builder.push_bind(all_day);
+        }
+
+        match self.department {
+            Some(Some(department)) => {
+                builder.push(" AND department = ");
+                builder.push_bind(department);
+            }
+            Some(None) => {
+                builder.push(" AND department IS NULL");
+            }
+            None => {}
+        }
+
+        match &self.source {
+            Some(Some(source)) => {
+                builder.push(" AND source = ");
+                builder.push_bind(source);
+            }
+            Some(None) => {
+                builder.push(" AND source IS NULL");
+            }
+            None => {}
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "title" | "starts_at" | "ends_at" | "department") = field.as_str() {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Event {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Event {}
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(EventQueryString, CursorOptions),
+    responses((status = 200, body = CursorResponse<Event>))
+)]
+#[instrument(skip(db))]
+async fn get_events(
+    Query(query_string): Query<<Event as HasSqlxQueryString>::QueryString>,
+    Query(cursor_options): Query<CursorOptions>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Event>>, PhsError> {
+    super::paginated_query_as::<Event>(
+        "list_events",
+        r#"
+        SELECT id,
+          title,
+          description,
+          location,
+          starts_at,
+          ends_at,
+          all_day,
+          department,
+          registration,
+          capacity,
+          registration_opens_at,
+          registration_closes_at,
+          (SELECT count(*) FROM event_registrations r WHERE r.event_id = events.id) AS registered,
+          source
+        FROM events
+        "#,
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|events| Json(CursorResponse::new(events)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/events/{id}",
+    tag = "events",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Event),
+        (status = 404, description = "No event has this ID"),
+    )
+)]
+#[instrument(skip(db))]
+async fn get_event(
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Event>, PhsError> {
+    db.timed(
+        "get_event",
+        sqlx::query_as!(
+            Event,
+            r#"
+            SELECT id, title, description, location, starts_at, ends_at, all_day, department,
+              registration, capacity, registration_opens_at, registration_closes_at,
+              (SELECT count(*) FROM event_registrations r WHERE r.event_id = events.id) AS "registered!",
+              source
+            FROM events
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(db.read()),
+    )
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CalendarQuery {
+    /// Only this department's events, plus those for the whole school.
+    department: Option<i32>,
+}
+
+/// The calendar as an iCalendar feed, for subscribing to from a phone or
+/// desktop calendar. Covers the past year and everything to come.
+#[utoipa::path(
+    get,
+    path = "/events.ics",
+    tag = "events",
+    params(CalendarQuery),
+    responses((status = 200, content_type = "text/calendar", body = String))
+)]
+#[instrument(skip(db, config))]
+async fn get_calendar(
+    Extension(db): Extension<Db>,
+    Extension(config): Extension<ServerConfig>,
+    Query(query): Query<CalendarQuery>,
+) -> Result<Response, PhsError> {
+    let since = OffsetDateTime::now_utc() - FEED_HISTORY;
+
+    let events = db
+        .timed(
+            "list_calendar_events",
+            sqlx::query_as!(
+                ical::CalendarEvent,
+                r#"
+                SELECT id, title, description, location, starts_at, ends_at, all_day, updated_at
+                FROM events
+                WHERE ends_at >= $1
+                    AND ($2::int IS NULL OR department IS NULL OR department = $2)
+                ORDER BY starts_at
+                "#,
+                since,
+                query.department
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    let calendar = ical::calendar(&config.site_url, &events);
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/calendar; charset=utf-8"),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_static(r#"inline; filename="events.ics""#),
+            ),
+        ],
+        calendar,
+    )
+        .into_response())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct EventBody {
+    title: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    location: String,
+    #[serde(with = "time::serde::iso8601")]
+    starts_at: OffsetDateTime,
+    /// For all-day events, on the last day.
+    #[serde(with = "time::serde::iso8601")]
+    ends_at: OffsetDateTime,
+    /// Only the dates of `starts_at` and `ends_at` count, in UTC, so send
+    /// midnight UTC on each.
+    #[serde(default)]
+    all_day: bool,
+    department: Option<i32>,
+
+    #[serde(default)]
+    registration: bool,
+    capacity: Option<i32>,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    registration_opens_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    registration_closes_at: Option<OffsetDateTime>,
+}
+
+impl Validate for EventBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("title", &self.title);
+        errors.max_chars("title", &self.title, MAX_NAME_LENGTH);
+        errors.max_chars("location", &self.location, MAX_NAME_LENGTH);
+        errors.check(
+            self.ends_at >= self.starts_at,
+            "ends_at",
+            "Must not be before starts_at",
+        );
+        errors.check(
+            self.capacity.is_none_or(|capacity| capacity > 0),
+            "capacity",
+            "Must be at least 1",
+        );
+        if let (Some(opens_at), Some(closes_at)) =
+            (self.registration_opens_at, self.registration_closes_at)
+        {
+            errors.check(
+                closes_at >= opens_at,
+                "registration_closes_at",
+                "Must not be before registration_opens_at",
+            );
+        }
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/events",
+    tag = "events",
+    request_body = EventBody,
+    responses(
+        (status = 200, body = Event),
+        (status = 403, description = "Missing the `ManageEvents` permission"),
+        (status = 422, description = "The title is empty or too long, it ends before it starts, or registration closes before it opens"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn create_event(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageEvents as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Validated(body): Validated<EventBody>,
+) -> Result<Json<Event>, PhsError> {
+    let event = sqlx::query_as!(
+        Event,
+        r#"
+        INSERT INTO events (
+          title, description, location, starts_at, ends_at, all_day, department,
+          registration, capacity, registration_opens_at, registration_closes_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING id, title, description, location, starts_at, ends_at, all_day, department,
+          registration, capacity, registration_opens_at, registration_closes_at,
+          0::bigint AS "registered!", source
+        "#,
+        body.title,
+        body.description,
+        body.location,
+        body.starts_at,
+        body.ends_at,
+        body.all_day,
+        body.department,
+        body.registration,
+        body.capacity,
+        body.registration_opens_at,
+        body.registration_closes_at
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(event))
+}
+
+#[utoipa::path(
+    put,
+    path = "/events/{id}",
+    tag = "events",
+    params(("id" = i32, Path)),
+    request_body = EventBody,
+    responses(
+        (status = 200, body = Event),
+        (status = 403, description = "Missing the `ManageEvents` permission"),
+        (status = 404, description = "No event has this ID"),
+        (status = 422, description = "The title is empty or too long, it ends before it starts, or registration closes before it opens"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn put_event(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageEvents as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<EventBody>,
+) -> Result<Json<Event>, PhsError> {
+    let event = sqlx::query_as!(
+        Event,
+        r#"
+        UPDATE events
+        SET title = $1,
+            description = $2,
+            location = $3,
+            starts_at = $4,
+            ends_at = $5,
+            all_day = $6,
+            department = $7,
+            registration = $8,
+            capacity = $9,
+            registration_opens_at = $10,
+            registration_closes_at = $11,
+            updated_at = now()
+        WHERE id = $12
+        RETURNING id, title, description, location, starts_at, ends_at, all_day, department,
+          registration, capacity, registration_opens_at, registration_closes_at,
+          (SELECT count(*) FROM event_registrations r WHERE r.event_id = events.id) AS "registered!",
+          source
+        "#,
+        body.title,
+        body.description,
+        body.location,
+        body.starts_at,
+        body.ends_at,
+        body.all_day,
+        body.department,
+        body.registration,
+        body.capacity,
+        body.registration_opens_at,
+        body.registration_closes_at,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(event))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/events/{id}",
+    tag = "events",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageEvents` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_event(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageEvents as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM events WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
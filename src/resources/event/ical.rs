@@ -0,0 +1,331 @@
+//! Writes events as an iCalendar (RFC 5545) feed, and reads other
+//! calendars' feeds.
+
+use sqlx::prelude::FromRow;
+use time::{
+    macros::{format_description, offset, time},
+    Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, UtcOffset, Weekday,
+};
+
+/// Lines are folded to at most this many bytes, not counting the CRLF.
+const MAX_LINE_LENGTH: usize = 75;
+
+#[derive(FromRow)]
+pub struct CalendarEvent {
+    pub id: i32,
+    pub title: String,
+    pub description: String,
+    pub location: String,
+    pub starts_at: OffsetDateTime,
+    pub ends_at: OffsetDateTime,
+    pub all_day: bool,
+    pub updated_at: OffsetDateTime,
+}
+
+/// The whole feed. Event UIDs are made from `site_url`'s host, so they stay
+/// the same between fetches and don't clash with other calendars.
+pub fn calendar(site_url: &str, events: &[CalendarEvent]) -> String {
+    let host = site_url
+        .split_once("://")
+        .map_or(site_url, |(_, rest)| rest)
+        .split(['/', ':'])
+        .next()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    line(&mut out, "BEGIN:VCALENDAR");
+    line(&mut out, "VERSION:2.0");
+    line(
+        &mut out,
+        concat!("PRODID:-//phs_backend//", env!("CARGO_PKG_VERSION"), "//EN"),
+    );
+    line(&mut out, "CALSCALE:GREGORIAN");
+    line(&mut out, "METHOD:PUBLISH");
+
+    for event in events {
+        line(&mut out, "BEGIN:VEVENT");
+        line(&mut out, &format!("UID:event-{}@{host}", event.id));
+        line(&mut out, &format!("DTSTAMP:{}", utc(event.updated_at)));
+        line(
+            &mut out,
+            &format!("LAST-MODIFIED:{}", utc(event.updated_at)),
+        );
+
+        if event.all_day {
+            // The end date is exclusive in iCalendar, but inclusive for us
+            line(
+                &mut out,
+                &format!("DTSTART;VALUE=DATE:{}", date(event.starts_at)),
+            );
+            line(
+                &mut out,
+                &format!("DTEND;VALUE=DATE:{}", date(event.ends_at + Duration::DAY)),
+            );
+        } else {
+            line(&mut out, &format!("DTSTART:{}", utc(event.starts_at)));
+            line(&mut out, &format!("DTEND:{}", utc(event.ends_at)));
+        }
+
+        line(&mut out, &format!("SUMMARY:{}", escape(&event.title)));
+        if !event.description.is_empty() {
+            line(
+                &mut out,
+                &format!("DESCRIPTION:{}", escape(&event.description)),
+            );
+        }
+        if !event.location.is_empty() {
+            line(&mut out, &format!("LOCATION:{}", escape(&event.location)));
+        }
+        line(&mut out, "END:VEVENT");
+    }
+
+    line(&mut out, "END:VCALENDAR");
+    out
+}
+
+/// Appends `content`, folded onto continuation lines that start with a space
+/// wherever it's too long, without splitting a character.
+fn line(out: &mut String, content: &str) {
+    let mut length = 0;
+
+    for c in content.chars() {
+        // Continuation lines lose a byte to the leading space
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            out.push_str("\r\n ");
+            length = 1;
+        }
+        out.push(c);
+        length += c.len_utf8();
+    }
+
+    out.push_str("\r\n");
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn utc(time: OffsetDateTime) -> String {
+    time.to_offset(UtcOffset::UTC)
+        .format(format_description!(
+            "[year][month][day]T[hour][minute][second]Z"
+        ))
+        .unwrap_or_default()
+}
+
+fn date(time: OffsetDateTime) -> String {
+    time.to_offset(UtcOffset::UTC)
+        .format(format_description!("[year][month][day]"))
+        .unwrap_or_default()
+}
+
+/// An event read from another calendar's feed.
+pub struct FeedEvent {
+    pub uid: String,
+    pub title: String,
+    pub description: String,
+    pub location: String,
+    pub starts_at: OffsetDateTime,
+    /// For all-day events, on the last day, as ours are.
+    pub ends_at: OffsetDateTime,
+    pub all_day: bool,
+}
+
+/// The events in a feed. Recurring events only bring their first occurrence,
+/// and cancelled events, changes to single occurrences, and events without a
+/// UID or a start we can read are left out.
+pub fn parse(feed: &str) -> Vec<FeedEvent> {
+    let mut events = Vec::new();
+    let mut current: Option<Vec<Property>> = None;
+    // Depth of components inside the event, such as alarms, whose properties
+    // aren't the event's
+    let mut nested = 0_usize;
+
+    for line in unfold(feed) {
+        let Some(property) = Property::parse(&line) else {
+            continue;
+        };
+        let Some(properties) = &mut current else {
+            if property.name == "BEGIN" && property.value.eq_ignore_ascii_case("VEVENT") {
+                current = Some(Vec::new());
+            }
+            continue;
+        };
+
+        match property.name.as_str() {
+            "BEGIN" => nested += 1,
+            "END" if nested > 0 => nested -= 1,
+            "END" => {
+                events.extend(feed_event(properties));
+                current = None;
+            }
+            _ if nested > 0 => {}
+            _ => properties.push(property),
+        }
+    }
+
+    events
+}
+
+fn feed_event(properties: &[Property]) -> Option<FeedEvent> {
+    let get = |name: &str| properties.iter().find(|p| p.name == name);
+    let text = |name: &str| get(name).map(|p| unescape(&p.value)).unwrap_or_default();
+
+    if get("RECURRENCE-ID").is_some()
+        || get("STATUS").is_some_and(|p| p.value.eq_ignore_ascii_case("CANCELLED"))
+    {
+        return None;
+    }
+
+    let uid = get("UID")?.value.trim().to_owned();
+    if uid.is_empty() {
+        return None;
+    }
+
+    let (starts_at, all_day) = get("DTSTART").and_then(Property::time)?;
+    let ends_at = match get("DTEND").and_then(Property::time) {
+        // The end date is exclusive in iCalendar, but inclusive for us
+        Some((ends_at, true)) if all_day => ends_at - Duration::DAY,
+        Some((ends_at, _)) => ends_at,
+        None => starts_at,
+    };
+
+    Some(FeedEvent {
+        uid,
+        title: text("SUMMARY"),
+        description: text("DESCRIPTION"),
+        location: text("LOCATION"),
+        starts_at,
+        ends_at: ends_at.max(starts_at),
+        all_day,
+    })
+}
+
+/// One content line, such as `DTSTART;TZID=Europe/London:20240101T090000`.
+struct Property {
+    /// Upper case, since names aren't case sensitive.
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Self> {
+        // Parameter values can hold colons if they're quoted
+        let mut quoted = false;
+        let (colon, _) = line.char_indices().find(|&(_, c)| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            c == ':' && !quoted
+        })?;
+
+        let mut head = line[..colon].split(';');
+        let name = head.next()?.trim().to_ascii_uppercase();
+        let params = head
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.to_ascii_uppercase(), value.trim_matches('"').to_owned()))
+            .collect();
+
+        Some(Self {
+            name,
+            params,
+            value: line[colon + 1..].to_owned(),
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// A date or date-time value, and whether it was just a date. Times that
+    /// aren't in UTC are taken to be UK time whatever their `TZID`, since
+    /// that's where the school and the calendars it follows are.
+    fn time(&self) -> Option<(OffsetDateTime, bool)> {
+        let value = self.value.trim();
+
+        if self.param("VALUE") == Some("DATE") || value.len() == 8 {
+            let date = Date::parse(value, format_description!("[year][month][day]")).ok()?;
+            return Some((date.midnight().assume_utc(), true));
+        }
+
+        let format = format_description!("[year][month][day]T[hour][minute][second]");
+        let time = match value.strip_suffix('Z') {
+            Some(utc) => PrimitiveDateTime::parse(utc, format).ok()?.assume_utc(),
+            None => uk_time(PrimitiveDateTime::parse(value, format).ok()?),
+        };
+        Some((time, false))
+    }
+}
+
+/// Splits a feed into lines, joining folded lines back together.
+fn unfold(feed: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in feed.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_owned()),
+        }
+    }
+
+    lines
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+/// A wall clock time in the UK as an instant, in BST from 01:00 UTC on the
+/// last Sunday in March until 01:00 UTC on the last Sunday in October.
+fn uk_time(local: PrimitiveDateTime) -> OffsetDateTime {
+    let last_sunday = |month| {
+        // March and October both have 31 days
+        let last = Date::from_calendar_date(local.year(), month, 31).unwrap_or(local.date());
+        last.next_day()
+            .unwrap_or(last)
+            .prev_occurrence(Weekday::Sunday)
+            .with_time(time!(1:00))
+            .assume_utc()
+    };
+
+    let summer = local.assume_offset(offset!(+1));
+    if (last_sunday(Month::March)..last_sunday(Month::October)).contains(&summer) {
+        summer
+    } else {
+        local.assume_utc()
+    }
+}
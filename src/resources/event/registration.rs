@@ -0,0 +1,309 @@
+//! Signing up for events that take registrations, such as parents' evenings,
+//! and the attendee lists staff see.
+
+use axum::{
+    extract::Path,
+    response::Response,
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{prelude::FromRow, PgPool};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::Db,
+    error::{ErrorCode, PhsError},
+    mail::Mail,
+    resources::{csv_attachment, csv_field},
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::MAX_NAME_LENGTH;
+
+/// Longest an email address can be, as stored.
+const MAX_EMAIL_LENGTH: usize = 512;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/events/:id/registrations", get(get_registrations))
+        .route("/events/:id/registrations.csv", get(get_registrations_csv))
+        .route(
+            "/events/:id/registrations/:registration_id",
+            delete(delete_registration),
+        )
+}
+
+/// Signing up from the event's page.
+pub fn public_router() -> Router {
+    Router::new().route("/events/:id/register", post(register))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    register,
+    get_registrations,
+    get_registrations_csv,
+    delete_registration
+))]
+struct RegistrationApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    RegistrationApi::openapi()
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Registration {
+    id: i32,
+    name: String,
+    email: String,
+    #[serde(with = "time::serde::iso8601")]
+    registered_at: OffsetDateTime,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct RegisterBody {
+    name: String,
+    email: String,
+}
+
+impl Validate for RegisterBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("name", &self.name);
+        errors.max_chars("name", &self.name, MAX_NAME_LENGTH);
+        errors.email("email", &self.email);
+        errors.max_chars("email", &self.email, MAX_EMAIL_LENGTH);
+        errors
+    }
+}
+
+/// Signs someone up for an event, and emails them to confirm it. Each email
+/// address can only sign up once per event.
+#[utoipa::path(
+    post,
+    path = "/events/{id}/register",
+    tag = "events",
+    params(("id" = i32, Path)),
+    request_body = RegisterBody,
+    responses(
+        (status = 200, body = Registration),
+        (status = 404, description = "No event has this ID"),
+        (status = 409, description = "Registration isn't open, the event is full, or this email address is already signed up"),
+        (status = 422, description = "The name is empty or too long, or the email address isn't valid"),
+    )
+)]
+#[instrument(skip(pool, mail))]
+async fn register(
+    Extension(pool): Extension<PgPool>,
+    Extension(mail): Extension<Mail>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<RegisterBody>,
+) -> Result<Json<Registration>, PhsError> {
+    let email = body.email.trim().to_lowercase();
+    let name = body.name.trim();
+
+    let mut tx = pool.begin().await?;
+
+    // Locked, so two people can't both take the last place
+    let event = sqlx::query!(
+        r#"
+        SELECT title, location, starts_at, ends_at, all_day,
+          registration, capacity, registration_opens_at, registration_closes_at
+        FROM events
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| PhsError::client(ErrorCode::NotFound, "No event has this ID"))?;
+
+    let now = OffsetDateTime::now_utc();
+    let opens_at = event.registration_opens_at;
+    let closes_at = event.registration_closes_at.unwrap_or(event.starts_at);
+    if !event.registration || opens_at.is_some_and(|opens_at| now < opens_at) || now > closes_at {
+        return Err(PhsError::client(
+            ErrorCode::RegistrationClosed,
+            "Registration for this event isn't open",
+        ));
+    }
+
+    if let Some(capacity) = event.capacity {
+        let registered = sqlx::query_scalar!(
+            r#"SELECT count(*) AS "count!" FROM event_registrations WHERE event_id = $1"#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if registered >= i64::from(capacity) {
+            return Err(PhsError::client(
+                ErrorCode::EventFull,
+                "This event has no places left",
+            ));
+        }
+    }
+
+    let registration = sqlx::query_as!(
+        Registration,
+        r#"
+        INSERT INTO event_registrations (event_id, name, email)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (event_id, email) DO NOTHING
+        RETURNING id, name, email, registered_at
+        "#,
+        id,
+        name,
+        email
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| {
+        PhsError::client(
+            ErrorCode::AlreadyExists,
+            "This email address is already signed up for this event",
+        )
+    })?;
+
+    tx.commit().await?;
+
+    // All-day events only have dates, and those are in UTC
+    let format = |time: OffsetDateTime| {
+        time.to_offset(UtcOffset::UTC)
+            .format(&Rfc3339)
+            .unwrap_or_default()
+    };
+    mail.send(
+        &pool,
+        &registration.email,
+        "event_registration",
+        &json!({
+            "name": registration.name,
+            "title": event.title,
+            "location": event.location,
+            "starts_at": format(event.starts_at),
+            "ends_at": format(event.ends_at),
+            "all_day": event.all_day,
+        }),
+    )
+    .await?;
+
+    Ok(Json(registration))
+}
+
+#[utoipa::path(
+    get,
+    path = "/events/{id}/registrations",
+    tag = "events",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Vec<Registration>),
+        (status = 403, description = "Missing the `ManageEvents` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_registrations(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageEvents as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<Registration>>, PhsError> {
+    list(&db, id).await.map(Json)
+}
+
+/// The attendee list as a spreadsheet, oldest sign-up first.
+#[utoipa::path(
+    get,
+    path = "/events/{id}/registrations.csv",
+    tag = "events",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, content_type = "text/csv", body = String),
+        (status = 403, description = "Missing the `ManageEvents` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_registrations_csv(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageEvents as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Response, PhsError> {
+    let registrations = list(&db, id).await?;
+
+    let mut csv = String::from("name,email,registered_at\r\n");
+    for registration in &registrations {
+        let registered_at = registration
+            .registered_at
+            .format(&Rfc3339)
+            .unwrap_or_default();
+
+        csv.push_str(&csv_field(&registration.name));
+        csv.push(',');
+        csv.push_str(&csv_field(&registration.email));
+        csv.push(',');
+        csv.push_str(&registered_at);
+        csv.push_str("\r\n");
+    }
+
+    csv_attachment(&format!("event-{id}-registrations.csv"), csv)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/events/{id}/registrations/{registration_id}",
+    tag = "events",
+    params(("id" = i32, Path), ("registration_id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageEvents` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_registration(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageEvents as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path((id, registration_id)): Path<(i32, i32)>,
+) -> Result<(), PhsError> {
+    sqlx::query!(
+        "DELETE FROM event_registrations WHERE id = $1 AND event_id = $2",
+        registration_id,
+        id
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn list(db: &Db, event_id: i32) -> Result<Vec<Registration>, PhsError> {
+    db.timed(
+        "list_event_registrations",
+        sqlx::query_as!(
+            Registration,
+            r#"
+            SELECT id, name, email, registered_at
+            FROM event_registrations
+            WHERE event_id = $1
+            ORDER BY registered_at, id
+            "#,
+            event_id
+        )
+        .fetch_all(db.read()),
+    )
+    .await
+    .map_err(Into::into)
+}
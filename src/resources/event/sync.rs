@@ -0,0 +1,151 @@
+//! Copies events in from other calendars' iCalendar feeds, as set up in
+//! [`ServerSettings::calendar_feeds`].
+
+use std::{sync::Arc, time::Duration};
+
+use sqlx::PgPool;
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::{error::PhsError, serve::LinkChecker, CalendarFeed, ServerConfig, ServerSettings};
+
+use super::{ical, MAX_NAME_LENGTH};
+
+/// How often every feed is synced.
+const SYNC_INTERVAL: Duration = Duration::from_hours(1);
+
+/// How long after startup the first sync runs, so restarts don't each
+/// download every feed straight away.
+const STARTUP_DELAY: Duration = Duration::from_mins(2);
+
+/// Largest feed that's downloaded, in bytes.
+const MAX_FEED_SIZE: usize = 10 * 1024 * 1024;
+
+/// Syncs every feed every [`SYNC_INTERVAL`] for the lifetime of the server.
+/// A feed that can't be fetched keeps the events it had.
+pub async fn calendar_sync_job(
+    pool: PgPool,
+    config: ServerConfig,
+    settings: Arc<RwLock<ServerSettings>>,
+) {
+    let checker = match LinkChecker::new(&config.site_url) {
+        Ok(checker) => checker,
+        Err(error) => {
+            tracing::error!(?error, "Calendar sync couldn't start");
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval_at(Instant::now() + STARTUP_DELAY, SYNC_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let feeds = settings.read().await.calendar_feeds.clone();
+
+        let names: Vec<_> = feeds.iter().map(|feed| feed.name.clone()).collect();
+        match sqlx::query!(
+            "DELETE FROM events WHERE source IS NOT NULL AND NOT (source = ANY($1))",
+            &names
+        )
+        .execute(&pool)
+        .await
+        {
+            Ok(result) if result.rows_affected() > 0 => tracing::info!(
+                count = result.rows_affected(),
+                "Deleted events from calendar feeds that were taken away"
+            ),
+            Ok(_) => {}
+            Err(error) => tracing::error!(?error, "Failed to delete events from old feeds"),
+        }
+
+        for feed in &feeds {
+            match sync(&pool, &checker, feed).await {
+                Ok((synced, removed)) => {
+                    tracing::info!(feed = feed.name, synced, removed, "Synced calendar feed");
+                }
+                Err(error) => {
+                    tracing::error!(?error, feed = feed.name, "Failed to sync calendar feed");
+                }
+            }
+        }
+    }
+}
+
+/// Brings one feed's events up to date, returning how many the feed has and
+/// how many were deleted for having gone from it.
+async fn sync(
+    pool: &PgPool,
+    checker: &LinkChecker,
+    feed: &CalendarFeed,
+) -> Result<(usize, u64), PhsError> {
+    let body = checker
+        .fetch(&feed.url, MAX_FEED_SIZE)
+        .await
+        .map_err(|e| PhsError::internal(e, "Couldn't download the calendar feed"))?;
+    let body = String::from_utf8_lossy(&body);
+
+    // An error page would otherwise look like an empty feed, and delete
+    // everything it brought in
+    if !body.contains("BEGIN:VCALENDAR") {
+        return Err(PhsError::bug("Not an iCalendar feed"));
+    }
+    let events = ical::parse(&body);
+
+    let mut tx = pool.begin().await?;
+
+    for event in &events {
+        // Unchanged events are left alone, so their `updated_at` stays put
+        sqlx::query!(
+            r#"
+            INSERT INTO events (
+              title, description, location, starts_at, ends_at, all_day, department,
+              source, source_uid
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (source, source_uid) DO UPDATE
+            SET title = EXCLUDED.title,
+                description = EXCLUDED.description,
+                location = EXCLUDED.location,
+                starts_at = EXCLUDED.starts_at,
+                ends_at = EXCLUDED.ends_at,
+                all_day = EXCLUDED.all_day,
+                department = EXCLUDED.department,
+                updated_at = now()
+            WHERE (events.title, events.description, events.location, events.starts_at,
+                events.ends_at, events.all_day, events.department)
+              IS DISTINCT FROM (EXCLUDED.title, EXCLUDED.description, EXCLUDED.location,
+                EXCLUDED.starts_at, EXCLUDED.ends_at, EXCLUDED.all_day, EXCLUDED.department)
+            "#,
+            truncate(&event.title),
+            event.description,
+            truncate(&event.location),
+            event.starts_at,
+            event.ends_at,
+            event.all_day,
+            feed.department,
+            feed.name,
+            event.uid
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let uids: Vec<_> = events.iter().map(|event| event.uid.clone()).collect();
+    let removed = sqlx::query!(
+        "DELETE FROM events WHERE source = $1 AND NOT (source_uid = ANY($2))",
+        feed.name,
+        &uids
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    Ok((events.len(), removed))
+}
+
+/// Cuts titles and locations down to what we can store.
+fn truncate(text: &str) -> String {
+    text.chars().take(MAX_NAME_LENGTH).collect()
+}
@@ -0,0 +1,362 @@
+use axum::{
+    extract::{Path, Query},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::PhsError,
+    serve::{element_errors, DynamicPageData, DynamicPageElement},
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+};
+
+/// Longest a question or category can be, as stored.
+const MAX_NAME_LENGTH: usize = 255;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/faqs", get(get_faqs).post(create_faq))
+        .route("/faqs/by-category", get(get_faqs_by_category))
+        .route("/faqs/:id", get(get_faq).put(put_faq).delete(delete_faq))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_faqs,
+    get_faqs_by_category,
+    get_faq,
+    create_faq,
+    put_faq,
+    delete_faq
+))]
+struct FaqApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    FaqApi::openapi()
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Faq {
+    id: i32,
+
+    question: String,
+    /// Page elements, as in a page's `data`.
+    #[schema(value_type = Vec<DynamicPageElement>)]
+    answer: serde_json::Value,
+    category: String,
+    /// Lower comes first within the category.
+    position: i32,
+
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
+}
+
+impl HasSqlxQueryString for Faq {
+    type QueryString = FaqQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FaqQueryString {
+    id: Option<i32>,
+    question: Option<String>,
+    category: Option<String>,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for FaqQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        if let Some(question) = &self.question {
+            builder.push(" AND question LIKE ");
+            builder.push_bind(question);
+        }
+
+        if let Some(category) = &self.category {
+            builder.push(" AND category = ");
+            builder.push_bind(category);
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "question" | "category" | "position" | "updated_at") = field.as_str() {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Faq {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Faq {}
+
+/// For one accordion, filter by `category` and sort by `position`.
+#[utoipa::path(
+    get,
+    path = "/faqs",
+    tag = "faqs",
+    params(FaqQueryString, CursorOptions),
+    responses((status = 200, body = CursorResponse<Faq>))
+)]
+#[instrument(skip(db))]
+async fn get_faqs(
+    Query(query_string): Query<<Faq as HasSqlxQueryString>::QueryString>,
+    Query(cursor_options): Query<CursorOptions>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Faq>>, PhsError> {
+    super::paginated_query_as::<Faq>(
+        "list_faqs",
+        r#"
+        SELECT id,
+          question,
+          answer,
+          category,
+          position,
+          updated_at
+        FROM faqs
+        "#,
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|faqs| Json(CursorResponse::new(faqs)))
+}
+
+#[derive(Serialize, ToSchema)]
+struct FaqCategory {
+    category: String,
+    faqs: Vec<Faq>,
+}
+
+/// Every question, grouped by category in alphabetical order, each in
+/// position order. Made for a page of accordions.
+#[utoipa::path(
+    get,
+    path = "/faqs/by-category",
+    tag = "faqs",
+    responses((status = 200, body = Vec<FaqCategory>))
+)]
+#[instrument(skip(db))]
+async fn get_faqs_by_category(
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<FaqCategory>>, PhsError> {
+    let faqs = db
+        .timed(
+            "list_faqs_by_category",
+            sqlx::query_as!(
+                Faq,
+                r#"
+                SELECT id, question, answer, category, position, updated_at
+                FROM faqs
+                ORDER BY category, position, id
+                "#
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    let mut categories: Vec<FaqCategory> = Vec::new();
+    for faq in faqs {
+        match categories.last_mut() {
+            Some(last) if last.category == faq.category => last.faqs.push(faq),
+            _ => categories.push(FaqCategory {
+                category: faq.category.clone(),
+                faqs: vec![faq],
+            }),
+        }
+    }
+
+    Ok(Json(categories))
+}
+
+#[utoipa::path(
+    get,
+    path = "/faqs/{id}",
+    tag = "faqs",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Faq),
+        (status = 404, description = "No question has this ID"),
+    )
+)]
+#[instrument(skip(db))]
+async fn get_faq(Extension(db): Extension<Db>, Path(id): Path<i32>) -> Result<Json<Faq>, PhsError> {
+    db.timed(
+        "get_faq",
+        sqlx::query_as!(
+            Faq,
+            r#"
+            SELECT id, question, answer, category, position, updated_at
+            FROM faqs
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(db.read()),
+    )
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct FaqBody {
+    question: String,
+    #[serde(default)]
+    #[schema(value_type = Vec<DynamicPageElement>)]
+    answer: DynamicPageData,
+    category: String,
+    #[serde(default)]
+    position: i32,
+}
+
+impl Validate for FaqBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = element_errors("answer", &self.answer);
+        errors.not_blank("question", &self.question);
+        errors.max_chars("question", &self.question, MAX_NAME_LENGTH);
+        errors.not_blank("category", &self.category);
+        errors.max_chars("category", &self.category, MAX_NAME_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/faqs",
+    tag = "faqs",
+    request_body = FaqBody,
+    responses(
+        (status = 200, body = Faq),
+        (status = 403, description = "Missing the `ManageFaqs` permission"),
+        (status = 422, description = "The question or category is empty or too long, or the answer isn't valid"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn create_faq(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageFaqs as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Validated(body): Validated<FaqBody>,
+) -> Result<Json<Faq>, PhsError> {
+    sqlx::query_as!(
+        Faq,
+        r#"
+        INSERT INTO faqs (question, answer, category, position)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, question, answer, category, position, updated_at
+        "#,
+        body.question.trim(),
+        serde_json::to_value(&body.answer)?,
+        body.category.trim(),
+        body.position
+    )
+    .fetch_one(&pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+#[utoipa::path(
+    put,
+    path = "/faqs/{id}",
+    tag = "faqs",
+    params(("id" = i32, Path)),
+    request_body = FaqBody,
+    responses(
+        (status = 200, body = Faq),
+        (status = 403, description = "Missing the `ManageFaqs` permission"),
+        (status = 404, description = "No question has this ID"),
+        (status = 422, description = "The question or category is empty or too long, or the answer isn't valid"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn put_faq(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageFaqs as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<FaqBody>,
+) -> Result<Json<Faq>, PhsError> {
+    sqlx::query_as!(
+        Faq,
+        r#"
+        UPDATE faqs
+        SET question = $1,
+            answer = $2,
+            category = $3,
+            position = $4,
+            updated_at = now()
+        WHERE id = $5
+        RETURNING id, question, answer, category, position, updated_at
+        "#,
+        body.question.trim(),
+        serde_json::to_value(&body.answer)?,
+        body.category.trim(),
+        body.position,
+        id
+    )
+    .fetch_one(&pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/faqs/{id}",
+    tag = "faqs",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageFaqs` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_faq(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageFaqs as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM faqs WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
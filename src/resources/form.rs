@@ -0,0 +1,374 @@
+use axum::{
+    extract::{Path, Query},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, CurrentPermissions, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::PhsError,
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+};
+
+mod field;
+mod submission;
+
+use field::Field;
+
+/// Longest a form's title can be, as stored.
+const MAX_TITLE_LENGTH: usize = 255;
+/// Longest an email address can be, as stored.
+const MAX_EMAIL_LENGTH: usize = 512;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/forms", get(get_forms).post(create_form))
+        .route(
+            "/forms/:id",
+            get(get_form).put(put_form).delete(delete_form),
+        )
+        .merge(submission::router())
+}
+
+pub fn public_router() -> Router {
+    submission::public_router()
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_forms, get_form, create_form, put_form, delete_form))]
+struct FormApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut openapi = FormApi::openapi();
+    openapi.merge(submission::openapi());
+    openapi
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Form {
+    id: i32,
+
+    title: String,
+    description: String,
+    #[schema(value_type = Vec<Field>)]
+    fields: serde_json::Value,
+
+    /// Who's emailed a copy of each submission. Empty unless you have the
+    /// `ManageForms` permission.
+    notify: Vec<String>,
+    /// Submissions are only taken while this is true, and until `closes_at`
+    /// if that's set.
+    open: bool,
+    #[serde(with = "time::serde::iso8601::option")]
+    closes_at: Option<OffsetDateTime>,
+
+    #[serde(with = "time::serde::iso8601")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
+}
+
+impl Form {
+    fn is_accepting(&self) -> bool {
+        self.open
+            && !self
+                .closes_at
+                .is_some_and(|closes_at| OffsetDateTime::now_utc() >= closes_at)
+    }
+}
+
+impl HasSqlxQueryString for Form {
+    type QueryString = FormQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FormQueryString {
+    id: Option<i32>,
+    title: Option<String>,
+    open: Option<bool>,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for FormQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        if let Some(title) = &self.title {
+            builder.push(" AND title LIKE ");
+            builder.push_bind(title);
+        }
+
+        if let Some(open) = self.open {
+            builder.push(" AND open = ");
+            builder.push_bind(open);
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "title" | "closes_at" | "created_at" | "updated_at") = field.as_str() {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Form {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Form {}
+
+#[utoipa::path(
+    get,
+    path = "/forms",
+    tag = "forms",
+    params(FormQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<Form>),
+        (status = 403, description = "Missing the `ManageForms` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_forms(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageForms as u8 }>,
+
+    Query(query_string): Query<<Form as HasSqlxQueryString>::QueryString>,
+    Query(cursor_options): Query<CursorOptions>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Form>>, PhsError> {
+    super::paginated_query_as::<Form>(
+        "list_forms",
+        r#"
+        SELECT id,
+          title,
+          description,
+          fields,
+          notify,
+          open,
+          closes_at,
+          created_at,
+          updated_at
+        FROM forms
+        "#,
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|forms| Json(CursorResponse::new(forms)))
+}
+
+/// A form to fill in. Closed forms are still found, so visitors can be told
+/// they've closed.
+#[utoipa::path(
+    get,
+    path = "/forms/{id}",
+    tag = "forms",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Form),
+        (status = 404, description = "No form has this ID"),
+    )
+)]
+#[instrument(skip(db, permissions))]
+async fn get_form(
+    permissions: CurrentPermissions,
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Form>, PhsError> {
+    let mut form = db.timed("get_form", fetch(db.read(), id)).await?;
+
+    if !permissions.contains(Permission::ManageForms) {
+        form.notify.clear();
+    }
+
+    Ok(Json(form))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct FormBody {
+    title: String,
+    #[serde(default)]
+    description: String,
+    fields: Vec<Field>,
+    #[serde(default)]
+    notify: Vec<String>,
+    open: bool,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    closes_at: Option<OffsetDateTime>,
+}
+
+impl Validate for FormBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = field::schema_errors("fields", &self.fields);
+        errors.not_blank("title", &self.title);
+        errors.max_chars("title", &self.title, MAX_TITLE_LENGTH);
+        for (i, email) in self.notify.iter().enumerate() {
+            let field = format!("notify[{i}]");
+            errors.email(&field, email);
+            errors.max_chars(&field, email, MAX_EMAIL_LENGTH);
+        }
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/forms",
+    tag = "forms",
+    request_body = FormBody,
+    responses(
+        (status = 200, body = Form),
+        (status = 403, description = "Missing the `ManageForms` permission"),
+        (status = 422, description = "The title is empty or too long, a field isn't valid, or an email address isn't"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn create_form(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageForms as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Validated(body): Validated<FormBody>,
+) -> Result<Json<Form>, PhsError> {
+    sqlx::query_as!(
+        Form,
+        r#"
+        INSERT INTO forms (title, description, fields, notify, open, closes_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, title, description, fields, notify, open, closes_at, created_at, updated_at
+        "#,
+        body.title,
+        body.description,
+        serde_json::to_value(&body.fields)?,
+        &body.notify,
+        body.open,
+        body.closes_at
+    )
+    .fetch_one(&pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Submissions already made are kept as they are, so answers to fields that
+/// are taken away stop being exported.
+#[utoipa::path(
+    put,
+    path = "/forms/{id}",
+    tag = "forms",
+    params(("id" = i32, Path)),
+    request_body = FormBody,
+    responses(
+        (status = 200, body = Form),
+        (status = 403, description = "Missing the `ManageForms` permission"),
+        (status = 404, description = "No form has this ID"),
+        (status = 422, description = "The title is empty or too long, a field isn't valid, or an email address isn't"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn put_form(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageForms as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<FormBody>,
+) -> Result<Json<Form>, PhsError> {
+    sqlx::query_as!(
+        Form,
+        r#"
+        UPDATE forms
+        SET title = $1,
+            description = $2,
+            fields = $3,
+            notify = $4,
+            open = $5,
+            closes_at = $6,
+            updated_at = now()
+        WHERE id = $7
+        RETURNING id, title, description, fields, notify, open, closes_at, created_at, updated_at
+        "#,
+        body.title,
+        body.description,
+        serde_json::to_value(&body.fields)?,
+        &body.notify,
+        body.open,
+        body.closes_at,
+        id
+    )
+    .fetch_one(&pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Also deletes every submission to the form.
+#[utoipa::path(
+    delete,
+    path = "/forms/{id}",
+    tag = "forms",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageForms` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_form(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageForms as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM forms WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn fetch(pool: &PgPool, id: i32) -> Result<Form, sqlx::Error> {
+    sqlx::query_as!(
+        Form,
+        r#"
+        SELECT id, title, description, fields, notify, open, closes_at, created_at, updated_at
+        FROM forms
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
@@ -0,0 +1,271 @@
+//! What a form asks for, and checking submissions against it.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use time::{macros::format_description, Date};
+use utoipa::ToSchema;
+
+use crate::validation::{is_email, FieldErrors};
+
+/// Most fields one form can have.
+const MAX_FIELDS: usize = 100;
+/// Longest a field's name, label or option can be.
+const MAX_LABEL_LENGTH: usize = 255;
+/// Longest any text answer can be, whatever the field allows.
+const MAX_ANSWER_LENGTH: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Field {
+    /// What answers are stored under. Letters, digits, `_` and `-` only, and
+    /// unique within the form.
+    pub name: String,
+    pub label: String,
+    /// Shown under the label.
+    #[serde(default)]
+    pub help: String,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(flatten)]
+    pub kind: FieldKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FieldKind {
+    /// One line of text.
+    Text {
+        max_length: Option<u32>,
+    },
+    /// Several lines of text.
+    Paragraph {
+        max_length: Option<u32>,
+    },
+    Email,
+    Number {
+        min: Option<f64>,
+        max: Option<f64>,
+        /// Whether only whole numbers are allowed.
+        #[serde(default)]
+        integer: bool,
+    },
+    /// As `YYYY-MM-DD`.
+    Date,
+    /// Yes or no. Ticking it is what `required` asks for, so it can be used
+    /// for consent.
+    Checkbox,
+    /// Exactly one of `options`.
+    Choice {
+        options: Vec<String>,
+    },
+    /// Any of `options`, as a list.
+    Choices {
+        options: Vec<String>,
+    },
+}
+
+/// Problems with a form's fields, by path under `field`.
+pub fn schema_errors(field: &str, fields: &[Field]) -> FieldErrors {
+    let mut errors = FieldErrors::default();
+    errors.check(
+        fields.len() <= MAX_FIELDS,
+        field,
+        format!("Can't have more than {MAX_FIELDS} fields"),
+    );
+
+    let mut names = HashSet::new();
+    for (i, f) in fields.iter().enumerate() {
+        let path = |key: &str| format!("{field}[{i}].{key}");
+
+        errors.not_blank(&path("name"), &f.name);
+        errors.max_chars(&path("name"), &f.name, MAX_LABEL_LENGTH);
+        errors.check(
+            f.name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+            &path("name"),
+            "Can only have letters, digits, `_` and `-`",
+        );
+        errors.check(
+            names.insert(f.name.as_str()),
+            &path("name"),
+            "Another field has this name",
+        );
+        errors.not_blank(&path("label"), &f.label);
+        errors.max_chars(&path("label"), &f.label, MAX_LABEL_LENGTH);
+
+        match &f.kind {
+            FieldKind::Text { max_length } | FieldKind::Paragraph { max_length } => {
+                errors.check(
+                    *max_length != Some(0),
+                    &path("max_length"),
+                    "Must be at least 1",
+                );
+            }
+            FieldKind::Number { min, max, .. } => {
+                errors.check(
+                    !min.zip(*max).is_some_and(|(min, max)| min > max),
+                    &path("max"),
+                    "Can't be less than `min`",
+                );
+            }
+            FieldKind::Choice { options } | FieldKind::Choices { options } => {
+                errors.check(
+                    !options.is_empty(),
+                    &path("options"),
+                    "Must have at least one option",
+                );
+                let mut seen = HashSet::new();
+                for (j, option) in options.iter().enumerate() {
+                    let path = format!("{field}[{i}].options[{j}]");
+                    errors.not_blank(&path, option);
+                    errors.max_chars(&path, option, MAX_LABEL_LENGTH);
+                    errors.check(seen.insert(option), &path, "Another option is the same");
+                }
+            }
+            FieldKind::Email | FieldKind::Date | FieldKind::Checkbox => {}
+        }
+    }
+
+    errors
+}
+
+/// Checks `answers` against `fields`, returning what should be stored: text
+/// trimmed, blank answers left out, and checkboxes always present.
+pub fn check_answers(
+    fields: &[Field],
+    answers: &Map<String, Value>,
+) -> Result<Map<String, Value>, FieldErrors> {
+    let mut errors: FieldErrors = answers
+        .keys()
+        .filter(|name| !fields.iter().any(|f| &f.name == *name))
+        .map(|name| (name.as_str(), "Isn't a field on this form"))
+        .collect();
+
+    let mut checked = Map::new();
+    for field in fields {
+        match field.check(answers.get(&field.name)) {
+            Ok(Some(value)) => {
+                checked.insert(field.name.clone(), value);
+            }
+            Ok(None) => {}
+            Err(message) => errors.add(&field.name, message),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(checked)
+    } else {
+        Err(errors)
+    }
+}
+
+impl Field {
+    fn check(&self, value: Option<&Value>) -> Result<Option<Value>, String> {
+        match (&self.kind, value.filter(|value| !is_blank(value))) {
+            (FieldKind::Checkbox, value) => {
+                let ticked = match value {
+                    None => false,
+                    Some(Value::Bool(ticked)) => *ticked,
+                    Some(_) => return Err("Must be true or false".into()),
+                };
+                if self.required && !ticked {
+                    return Err("Must be ticked".into());
+                }
+                Ok(Some(ticked.into()))
+            }
+            (_, None) if self.required => Err("Must be filled in".into()),
+            (_, None) => Ok(None),
+            (FieldKind::Text { max_length } | FieldKind::Paragraph { max_length }, Some(value)) => {
+                let text = as_str(value)?;
+                let max = max_length
+                    .and_then(|max| usize::try_from(max).ok())
+                    .map_or(MAX_ANSWER_LENGTH, |max| max.min(MAX_ANSWER_LENGTH));
+                if text.chars().count() > max {
+                    return Err(format!("Can't be longer than {max} characters"));
+                }
+                Ok(Some(text.into()))
+            }
+            (FieldKind::Email, Some(value)) => {
+                let email = as_str(value)?;
+                if !is_email(email) {
+                    return Err("Isn't a valid email address".into());
+                }
+                Ok(Some(email.into()))
+            }
+            (FieldKind::Number { min, max, integer }, Some(value)) => {
+                let Some(number) = value.as_f64() else {
+                    return Err("Must be a number".into());
+                };
+                if *integer && number.fract() != 0.0 {
+                    return Err("Must be a whole number".into());
+                }
+                if let Some(min) = min.filter(|min| number < *min) {
+                    return Err(format!("Can't be less than {min}"));
+                }
+                if let Some(max) = max.filter(|max| number > *max) {
+                    return Err(format!("Can't be more than {max}"));
+                }
+                Ok(Some(value.clone()))
+            }
+            (FieldKind::Date, Some(value)) => {
+                let date = as_str(value)?;
+                Date::parse(date, format_description!("[year]-[month]-[day]"))
+                    .map_err(|_| "Must be a date, as YYYY-MM-DD")?;
+                Ok(Some(date.into()))
+            }
+            (FieldKind::Choice { options }, Some(value)) => {
+                let choice = as_str(value)?;
+                if !options.iter().any(|option| option == choice) {
+                    return Err("Isn't one of the options".into());
+                }
+                Ok(Some(choice.into()))
+            }
+            (FieldKind::Choices { options }, Some(value)) => {
+                let Value::Array(choices) = value else {
+                    return Err("Must be a list of options".into());
+                };
+                let mut checked = Vec::with_capacity(choices.len());
+                for choice in choices {
+                    let choice = as_str(choice)?;
+                    if !options.iter().any(|option| option == choice) {
+                        return Err(format!("`{choice}` isn't one of the options"));
+                    }
+                    if !checked.contains(&choice) {
+                        checked.push(choice);
+                    }
+                }
+                Ok(Some(checked.into()))
+            }
+        }
+    }
+}
+
+fn as_str(value: &Value) -> Result<&str, String> {
+    value
+        .as_str()
+        .map(str::trim)
+        .ok_or_else(|| "Must be text".into())
+}
+
+fn is_blank(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(s) => s.trim().is_empty(),
+        Value::Array(a) => a.is_empty(),
+        _ => false,
+    }
+}
+
+/// An answer as a person would write it, for emails and spreadsheets.
+pub fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(true) => "Yes".into(),
+        Value::Bool(false) => "No".into(),
+        Value::String(s) => s.clone(),
+        Value::Array(values) => values.iter().map(display).collect::<Vec<_>>().join(", "),
+        Value::Number(_) | Value::Object(_) => value.to_string(),
+    }
+}
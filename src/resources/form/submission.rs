@@ -0,0 +1,251 @@
+//! Filling in forms, and the submissions staff see.
+
+use axum::{
+    extract::Path,
+    response::Response,
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use sqlx::{prelude::FromRow, PgPool};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::Db,
+    error::{ErrorCode, PhsError},
+    mail::Mail,
+    resources::{csv_attachment, csv_field},
+};
+
+use super::field::{self, Field};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/forms/:id/submissions", get(get_submissions))
+        .route("/forms/:id/submissions.csv", get(get_submissions_csv))
+        .route(
+            "/forms/:id/submissions/:submission_id",
+            delete(delete_submission),
+        )
+}
+
+/// Filling a form in on the site, logged in or not.
+pub fn public_router() -> Router {
+    Router::new().route("/forms/:id/submissions", post(submit))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(submit, get_submissions, get_submissions_csv, delete_submission))]
+struct SubmissionApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    SubmissionApi::openapi()
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Submission {
+    id: i32,
+    /// Answers by field name. Blank answers are left out.
+    #[schema(value_type = Object)]
+    data: Value,
+    #[serde(with = "time::serde::iso8601")]
+    submitted_at: OffsetDateTime,
+}
+
+/// Fills in a form, and emails a copy to whoever the form notifies. The body
+/// has an answer for each field, by name.
+#[utoipa::path(
+    post,
+    path = "/forms/{id}/submissions",
+    tag = "forms",
+    params(("id" = i32, Path)),
+    request_body(content = Object, description = "Answers by field name"),
+    responses(
+        (status = 200, body = Submission),
+        (status = 404, description = "No form has this ID"),
+        (status = 409, description = "The form isn't taking submissions"),
+        (status = 422, description = "An answer is missing or isn't valid for its field, or isn't for a field on the form"),
+    )
+)]
+// Not logging the body, which can be anything the form asks for
+#[instrument(skip(pool, mail, answers))]
+async fn submit(
+    Extension(pool): Extension<PgPool>,
+    Extension(mail): Extension<Mail>,
+    Path(id): Path<i32>,
+    Json(answers): Json<Map<String, Value>>,
+) -> Result<Json<Submission>, PhsError> {
+    let form = super::fetch(&pool, id).await?;
+    if !form.is_accepting() {
+        return Err(PhsError::client(
+            ErrorCode::FormClosed,
+            "This form isn't taking submissions",
+        ));
+    }
+
+    let fields: Vec<Field> = serde_json::from_value(form.fields)?;
+    let data = field::check_answers(&fields, &answers).map_err(|errors| PhsError::Invalid {
+        detail: "Some answers need fixing first".into(),
+        errors,
+    })?;
+
+    let submission = sqlx::query_as!(
+        Submission,
+        r#"
+        INSERT INTO form_submissions (form_id, data)
+        VALUES ($1, $2)
+        RETURNING id, data, submitted_at
+        "#,
+        id,
+        Value::Object(data)
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    if !form.notify.is_empty() {
+        let answers: Vec<_> = fields
+            .iter()
+            .map(|f| {
+                let value = submission.data.get(&f.name).map(field::display);
+                json!({ "label": f.label, "value": value.unwrap_or_default() })
+            })
+            .collect();
+        let context = json!({
+            "id": submission.id,
+            "title": form.title,
+            "answers": answers,
+        });
+
+        for to in &form.notify {
+            mail.send(&pool, to, "form_submission", &context).await?;
+        }
+    }
+
+    Ok(Json(submission))
+}
+
+#[utoipa::path(
+    get,
+    path = "/forms/{id}/submissions",
+    tag = "forms",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Vec<Submission>),
+        (status = 403, description = "Missing the `ManageForms` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_submissions(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageForms as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<Submission>>, PhsError> {
+    list(&db, id).await.map(Json)
+}
+
+/// Submissions as a spreadsheet, oldest first, with a column for each of the
+/// form's fields as they are now.
+#[utoipa::path(
+    get,
+    path = "/forms/{id}/submissions.csv",
+    tag = "forms",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, content_type = "text/csv", body = String),
+        (status = 403, description = "Missing the `ManageForms` permission"),
+        (status = 404, description = "No form has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_submissions_csv(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageForms as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Response, PhsError> {
+    let form = db.timed("get_form", super::fetch(db.read(), id)).await?;
+    let fields: Vec<Field> = serde_json::from_value(form.fields)?;
+    let submissions = list(&db, id).await?;
+
+    let mut csv = String::from("id,submitted_at");
+    for f in &fields {
+        csv.push(',');
+        csv.push_str(&csv_field(&f.label));
+    }
+    csv.push_str("\r\n");
+
+    for submission in &submissions {
+        let submitted_at = submission.submitted_at.format(&Rfc3339).unwrap_or_default();
+
+        csv.push_str(&submission.id.to_string());
+        csv.push(',');
+        csv.push_str(&submitted_at);
+        for f in &fields {
+            csv.push(',');
+            if let Some(value) = submission.data.get(&f.name) {
+                csv.push_str(&csv_field(&field::display(value)));
+            }
+        }
+        csv.push_str("\r\n");
+    }
+
+    csv_attachment(&format!("form-{id}-submissions.csv"), csv)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/forms/{id}/submissions/{submission_id}",
+    tag = "forms",
+    params(("id" = i32, Path), ("submission_id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageForms` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_submission(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageForms as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path((id, submission_id)): Path<(i32, i32)>,
+) -> Result<(), PhsError> {
+    sqlx::query!(
+        "DELETE FROM form_submissions WHERE id = $1 AND form_id = $2",
+        submission_id,
+        id
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn list(db: &Db, form_id: i32) -> Result<Vec<Submission>, PhsError> {
+    db.timed(
+        "list_form_submissions",
+        sqlx::query_as!(
+            Submission,
+            r#"
+            SELECT id, data, submitted_at
+            FROM form_submissions
+            WHERE form_id = $1
+            ORDER BY submitted_at, id
+            "#,
+            form_id
+        )
+        .fetch_all(db.read()),
+    )
+    .await
+    .map_err(Into::into)
+}
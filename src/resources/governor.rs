@@ -0,0 +1,364 @@
+use axum::{
+    extract::{Path, Query},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::{Date, OffsetDateTime};
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::PhsError,
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+};
+
+/// Longest a governor's name or role can be, as stored.
+const MAX_NAME_LENGTH: usize = 255;
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/governors", get(get_governors).post(create_governor))
+        .route(
+            "/governors/:id",
+            get(get_governor).put(put_governor).delete(delete_governor),
+        )
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_governors,
+    get_governor,
+    create_governor,
+    put_governor,
+    delete_governor
+))]
+struct GovernorApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    GovernorApi::openapi()
+}
+
+/// The types of governor the constitution regulations set out.
+#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "governor_category", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum GovernorCategory {
+    Headteacher,
+    Parent,
+    Staff,
+    LocalAuthority,
+    Foundation,
+    Partnership,
+    CoOpted,
+    Associate,
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Governor {
+    id: i32,
+
+    name: String,
+    /// Such as chair, or the committees they sit on.
+    role: String,
+    category: GovernorCategory,
+    /// The last day of their term of office.
+    #[serde(with = "iso_date")]
+    #[schema(value_type = String, format = Date)]
+    term_ends_on: Date,
+
+    /// Their register of interests, from the documents library.
+    interests_document: Option<i32>,
+    /// Where the register's latest version is served from.
+    interests_url: Option<String>,
+
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
+}
+
+impl HasSqlxQueryString for Governor {
+    type QueryString = GovernorQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GovernorQueryString {
+    id: Option<i32>,
+    name: Option<String>,
+    category: Option<GovernorCategory>,
+    /// Only governors whose term hasn't ended, or only ones whose has.
+    current: Option<bool>,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for GovernorQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        if let Some(name) = &self.name {
+            builder.push(" AND name LIKE ");
+            builder.push_bind(name);
+        }
+
+        if let Some(category) = self.category {
+            builder.push(" AND category = ");
+            builder.push_bind(category);
+        }
+
+        match self.current {
+            Some(true) => {
+                builder.push(" AND term_ends_on >= CURRENT_DATE");
+            }
+            Some(false) => {
+                builder.push(" AND term_ends_on < CURRENT_DATE");
+            }
+            None => {}
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "name" | "category" | "term_ends_on" | "updated_at") = field.as_str() {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Governor {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Governor {}
+
+/// The governing board, including those whose terms have ended unless
+/// `current` is set, since those from the last year must be published too.
+#[utoipa::path(
+    get,
+    path = "/governors",
+    tag = "governors",
+    params(GovernorQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<Governor>),
+    )
+)]
+#[instrument(skip(db))]
+async fn get_governors(
+    Query(query_string): Query<<Governor as HasSqlxQueryString>::QueryString>,
+    Query(cursor_options): Query<CursorOptions>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Governor>>, PhsError> {
+    super::paginated_query_as::<Governor>(
+        "list_governors",
+        r#"
+        SELECT id,
+          name,
+          role,
+          category,
+          term_ends_on,
+          interests_document,
+          (SELECT d.url FROM current_documents d WHERE d.id = governors.interests_document) AS interests_url,
+          updated_at
+        FROM governors
+        "#,
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|governors| Json(CursorResponse::new(governors)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/governors/{id}",
+    tag = "governors",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Governor),
+        (status = 404, description = "No governor has this ID"),
+    )
+)]
+#[instrument(skip(db))]
+async fn get_governor(
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Governor>, PhsError> {
+    db.timed("get_governor", fetch(db.read(), id))
+        .await
+        .map(Json)
+        .map_err(Into::into)
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct GovernorBody {
+    name: String,
+    #[serde(default)]
+    role: String,
+    category: GovernorCategory,
+    #[serde(with = "iso_date")]
+    #[schema(value_type = String, format = Date)]
+    term_ends_on: Date,
+    interests_document: Option<i32>,
+}
+
+impl Validate for GovernorBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("name", &self.name);
+        errors.max_chars("name", &self.name, MAX_NAME_LENGTH);
+        errors.max_chars("role", &self.role, MAX_NAME_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/governors",
+    tag = "governors",
+    request_body = GovernorBody,
+    responses(
+        (status = 200, body = Governor),
+        (status = 403, description = "Missing the `ManageGovernors` permission"),
+        (status = 422, description = "The name is empty, or the name or role is too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn create_governor(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageGovernors as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Validated(body): Validated<GovernorBody>,
+) -> Result<Json<Governor>, PhsError> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO governors (name, role, category, term_ends_on, interests_document)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        body.name.trim(),
+        body.role.trim(),
+        body.category as GovernorCategory,
+        body.term_ends_on,
+        body.interests_document
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    fetch(&pool, id).await.map(Json).map_err(Into::into)
+}
+
+#[utoipa::path(
+    put,
+    path = "/governors/{id}",
+    tag = "governors",
+    params(("id" = i32, Path)),
+    request_body = GovernorBody,
+    responses(
+        (status = 200, body = Governor),
+        (status = 403, description = "Missing the `ManageGovernors` permission"),
+        (status = 404, description = "No governor has this ID"),
+        (status = 422, description = "The name is empty, or the name or role is too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn put_governor(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageGovernors as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<GovernorBody>,
+) -> Result<Json<Governor>, PhsError> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE governors
+        SET name = $1,
+            role = $2,
+            category = $3,
+            term_ends_on = $4,
+            interests_document = $5,
+            updated_at = now()
+        WHERE id = $6
+        RETURNING id
+        "#,
+        body.name.trim(),
+        body.role.trim(),
+        body.category as GovernorCategory,
+        body.term_ends_on,
+        body.interests_document,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    fetch(&pool, id).await.map(Json).map_err(Into::into)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/governors/{id}",
+    tag = "governors",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageGovernors` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_governor(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageGovernors as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM governors WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
+
+/// A governor with their register of interests' URL filled in.
+async fn fetch(pool: &PgPool, id: i32) -> Result<Governor, sqlx::Error> {
+    sqlx::query_as!(
+        Governor,
+        r#"
+        SELECT g.id, g.name, g.role, g.category AS "category: GovernorCategory",
+          g.term_ends_on, g.interests_document, d.url AS interests_url, g.updated_at
+        FROM governors g
+        LEFT JOIN current_documents d ON d.id = g.interests_document
+        WHERE g.id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
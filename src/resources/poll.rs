@@ -0,0 +1,617 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, Query},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, CurrentPermissions, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::{ErrorCode, PhsError},
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+};
+
+/// Longest a question or option can be, as stored.
+const MAX_TEXT_LENGTH: usize = 255;
+/// Most options one poll can have.
+const MAX_OPTIONS: usize = 20;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/polls", get(get_polls).post(create_poll))
+        .route(
+            "/polls/:id",
+            get(get_poll).put(put_poll).delete(delete_poll),
+        )
+        .route("/polls/:id/results", get(get_results))
+}
+
+/// Votes are cast from the poll on the site, by whoever is logged in there.
+pub fn public_router() -> Router {
+    Router::new().route("/polls/:id/vote", post(vote))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_polls,
+    get_poll,
+    get_results,
+    vote,
+    create_poll,
+    put_poll,
+    delete_poll
+))]
+struct PollApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    PollApi::openapi()
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Poll {
+    id: i32,
+
+    question: String,
+    /// Voted for by index.
+    options: Vec<String>,
+
+    #[serde(with = "time::serde::iso8601")]
+    opens_at: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601::option")]
+    closes_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::iso8601")]
+    created_at: OffsetDateTime,
+}
+
+impl Poll {
+    fn has_opened(&self) -> bool {
+        self.opens_at <= OffsetDateTime::now_utc()
+    }
+
+    fn is_open(&self) -> bool {
+        self.has_opened()
+            && self
+                .closes_at
+                .is_none_or(|closes_at| OffsetDateTime::now_utc() < closes_at)
+    }
+}
+
+impl HasSqlxQueryString for Poll {
+    type QueryString = PollQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PollQueryString {
+    id: Option<i32>,
+    /// Only polls taking votes, or only ones that have closed.
+    open: Option<bool>,
+
+    /// Also list polls that haven't opened yet. Needs the `ManagePolls`
+    /// permission.
+    #[serde(default)]
+    include_upcoming: bool,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for PollQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        if !self.include_upcoming {
+            builder.push(" AND opens_at <= now()");
+        }
+
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        match self.open {
+            Some(true) => {
+                builder.push(" AND opens_at <= now() AND (closes_at IS NULL OR closes_at > now())");
+            }
+            Some(false) => {
+                builder.push(" AND closes_at <= now()");
+            }
+            None => {}
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "question" | "opens_at" | "closes_at" | "created_at") = field.as_str() {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Poll {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Poll {}
+
+/// Whether whoever's asking may see polls that haven't opened yet.
+fn can_see_upcoming(permissions: CurrentPermissions) -> Result<(), PhsError> {
+    if permissions.contains(Permission::ManagePolls) {
+        Ok(())
+    } else {
+        Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Only those who manage polls can see ones that haven't opened",
+        ))
+    }
+}
+
+/// Polls that have opened, including closed ones so their results can still
+/// be seen.
+#[utoipa::path(
+    get,
+    path = "/polls",
+    tag = "polls",
+    params(PollQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<Poll>),
+        (status = 403, description = "Asked for upcoming polls without the `ManagePolls` permission"),
+    )
+)]
+#[instrument(skip(db, permissions))]
+async fn get_polls(
+    permissions: CurrentPermissions,
+    Query(query_string): Query<<Poll as HasSqlxQueryString>::QueryString>,
+    Query(cursor_options): Query<CursorOptions>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Poll>>, PhsError> {
+    if query_string.include_upcoming {
+        can_see_upcoming(permissions)?;
+    }
+
+    super::paginated_query_as::<Poll>(
+        "list_polls",
+        r#"
+        SELECT id,
+          question,
+          options,
+          opens_at,
+          closes_at,
+          created_at
+        FROM polls
+        "#,
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|polls| Json(CursorResponse::new(polls)))
+}
+
+/// A poll that hasn't opened is only found with the `ManagePolls` permission.
+#[utoipa::path(
+    get,
+    path = "/polls/{id}",
+    tag = "polls",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Poll),
+        (status = 404, description = "No poll that has opened has this ID"),
+    )
+)]
+#[instrument(skip(db, permissions))]
+async fn get_poll(
+    permissions: CurrentPermissions,
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Poll>, PhsError> {
+    visible(&db, permissions, id).await.map(Json)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PollResults {
+    total: i64,
+    /// How many votes each option has, in the same order as the options.
+    votes: Vec<i64>,
+    /// The index of the option you voted for, if you're logged in and have
+    /// voted.
+    your_vote: Option<i16>,
+}
+
+impl RowCount for PollResults {}
+
+/// The votes so far, counted as they come in.
+#[utoipa::path(
+    get,
+    path = "/polls/{id}/results",
+    tag = "polls",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = PollResults),
+        (status = 404, description = "No poll that has opened has this ID"),
+    )
+)]
+#[instrument(skip(db, auth_session, permissions))]
+async fn get_results(
+    auth_session: Option<AuthSession>,
+    permissions: CurrentPermissions,
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<PollResults>, PhsError> {
+    let poll = visible(&db, permissions, id).await?;
+    let user = auth_session.map(|session| session.data().id());
+
+    db.timed("get_poll_results", results(db.read(), &poll, user))
+        .await
+        .map(Json)
+        .map_err(Into::into)
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct VoteBody {
+    /// The index of the option to vote for.
+    choice: i16,
+}
+
+/// Votes as the logged-in user. Everyone gets one vote per poll, which can't
+/// be changed.
+#[utoipa::path(
+    post,
+    path = "/polls/{id}/vote",
+    tag = "polls",
+    params(("id" = i32, Path)),
+    request_body = VoteBody,
+    responses(
+        (status = 200, body = PollResults),
+        (status = 400, description = "There's no option at `choice`"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "No poll has this ID"),
+        (status = 409, description = "The poll isn't taking votes, or you've already voted"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, auth_session))]
+async fn vote(
+    auth_session: AuthSession,
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Json(body): Json<VoteBody>,
+) -> Result<Json<PollResults>, PhsError> {
+    let user = auth_session.data().id();
+
+    let mut tx = pool.begin().await?;
+
+    // Shared, so the options can't change under the vote
+    let poll = sqlx::query_as!(
+        Poll,
+        r#"
+        SELECT id, question, options, opens_at, closes_at, created_at
+        FROM polls
+        WHERE id = $1
+        FOR SHARE
+        "#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if !poll.is_open() {
+        return Err(PhsError::client(
+            ErrorCode::PollClosed,
+            "This poll isn't taking votes",
+        ));
+    }
+
+    if !usize::try_from(body.choice).is_ok_and(|choice| choice < poll.options.len()) {
+        return Err(PhsError::client(
+            ErrorCode::BadRequest,
+            "This poll has no option at that index",
+        ));
+    }
+
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO poll_votes (poll_id, user_id, choice)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (poll_id, user_id) DO NOTHING
+        RETURNING choice
+        "#,
+        id,
+        user,
+        body.choice
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| {
+        PhsError::client(
+            ErrorCode::AlreadyExists,
+            "You've already voted in this poll",
+        )
+    })?;
+
+    tx.commit().await?;
+
+    // From the primary, so the vote is counted
+    results(&pool, &poll, Some(user))
+        .await
+        .map(Json)
+        .map_err(Into::into)
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct PollBody {
+    question: String,
+    options: Vec<String>,
+    #[serde(with = "time::serde::iso8601")]
+    opens_at: OffsetDateTime,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    closes_at: Option<OffsetDateTime>,
+}
+
+impl Validate for PollBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("question", &self.question);
+        errors.max_chars("question", &self.question, MAX_TEXT_LENGTH);
+        errors.check(
+            (2..=MAX_OPTIONS).contains(&self.options.len()),
+            "options",
+            format!("Must have between 2 and {MAX_OPTIONS} options"),
+        );
+
+        let mut seen = HashSet::new();
+        for (i, option) in self.options.iter().enumerate() {
+            let field = format!("options[{i}]");
+            errors.not_blank(&field, option);
+            errors.max_chars(&field, option, MAX_TEXT_LENGTH);
+            errors.check(
+                seen.insert(option.trim()),
+                &field,
+                "Another option is the same",
+            );
+        }
+
+        errors.check(
+            self.closes_at
+                .is_none_or(|closes_at| closes_at > self.opens_at),
+            "closes_at",
+            "Must be after opens_at",
+        );
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/polls",
+    tag = "polls",
+    request_body = PollBody,
+    responses(
+        (status = 200, body = Poll),
+        (status = 403, description = "Missing the `ManagePolls` permission"),
+        (status = 422, description = "The question or an option is empty or too long, or the times are the wrong way round"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn create_poll(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePolls as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Validated(body): Validated<PollBody>,
+) -> Result<Json<Poll>, PhsError> {
+    let options: Vec<_> = body.options.iter().map(|o| o.trim().to_owned()).collect();
+
+    sqlx::query_as!(
+        Poll,
+        r#"
+        INSERT INTO polls (question, options, opens_at, closes_at)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, question, options, opens_at, closes_at, created_at
+        "#,
+        body.question.trim(),
+        &options,
+        body.opens_at,
+        body.closes_at
+    )
+    .fetch_one(&pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Options can't be changed once anyone has voted, since votes are by index.
+#[utoipa::path(
+    put,
+    path = "/polls/{id}",
+    tag = "polls",
+    params(("id" = i32, Path)),
+    request_body = PollBody,
+    responses(
+        (status = 200, body = Poll),
+        (status = 403, description = "Missing the `ManagePolls` permission"),
+        (status = 404, description = "No poll has this ID"),
+        (status = 409, description = "The options were changed after voting started"),
+        (status = 422, description = "The question or an option is empty or too long, or the times are the wrong way round"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn put_poll(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePolls as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<PollBody>,
+) -> Result<Json<Poll>, PhsError> {
+    let options: Vec<_> = body.options.iter().map(|o| o.trim().to_owned()).collect();
+
+    let mut tx = pool.begin().await?;
+
+    // Locked, so nobody votes for an option as it changes
+    let current = sqlx::query_scalar!("SELECT options FROM polls WHERE id = $1 FOR UPDATE", id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    if current != options {
+        let voted = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM poll_votes WHERE poll_id = $1) AS "voted!""#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if voted {
+            return Err(PhsError::client(
+                ErrorCode::InUse,
+                "Options can't be changed once anyone has voted",
+            ));
+        }
+    }
+
+    let poll = sqlx::query_as!(
+        Poll,
+        r#"
+        UPDATE polls
+        SET question = $1,
+            options = $2,
+            opens_at = $3,
+            closes_at = $4
+        WHERE id = $5
+        RETURNING id, question, options, opens_at, closes_at, created_at
+        "#,
+        body.question.trim(),
+        &options,
+        body.opens_at,
+        body.closes_at,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(poll))
+}
+
+/// Also deletes every vote in the poll.
+#[utoipa::path(
+    delete,
+    path = "/polls/{id}",
+    tag = "polls",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePolls` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_poll(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePolls as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM polls WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn fetch(pool: &PgPool, id: i32) -> Result<Poll, sqlx::Error> {
+    sqlx::query_as!(
+        Poll,
+        r#"
+        SELECT id, question, options, opens_at, closes_at, created_at
+        FROM polls
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// The poll, unless it hasn't opened and whoever's asking can't see it yet.
+async fn visible(db: &Db, permissions: CurrentPermissions, id: i32) -> Result<Poll, PhsError> {
+    let poll = db.timed("get_poll", fetch(db.read(), id)).await?;
+
+    if !poll.has_opened() && can_see_upcoming(permissions).is_err() {
+        return Err(PhsError::client(
+            ErrorCode::NotFound,
+            "No poll that has opened has this ID",
+        ));
+    }
+
+    Ok(poll)
+}
+
+async fn results(
+    pool: &PgPool,
+    poll: &Poll,
+    user: Option<i32>,
+) -> Result<PollResults, sqlx::Error> {
+    let counts = sqlx::query!(
+        r#"
+        SELECT choice, count(*) AS "votes!"
+        FROM poll_votes
+        WHERE poll_id = $1
+        GROUP BY choice
+        "#,
+        poll.id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut votes = vec![0; poll.options.len()];
+    for count in &counts {
+        if let Some(votes) = usize::try_from(count.choice)
+            .ok()
+            .and_then(|choice| votes.get_mut(choice))
+        {
+            *votes = count.votes;
+        }
+    }
+
+    let your_vote = match user {
+        Some(user) => {
+            sqlx::query_scalar!(
+                "SELECT choice FROM poll_votes WHERE poll_id = $1 AND user_id = $2",
+                poll.id,
+                user
+            )
+            .fetch_optional(pool)
+            .await?
+        }
+        None => None,
+    };
+
+    Ok(PollResults {
+        total: votes.iter().sum(),
+        votes,
+        your_vote,
+    })
+}
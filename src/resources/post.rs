@@ -1,32 +1,82 @@
+use std::sync::Arc;
+
 use axum::{
+    async_trait,
     extract::{Path, Query},
-    routing::{delete, get},
+    middleware,
+    routing::{delete, get, post},
     Extension, Json, Router,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use sqlx::{prelude::FromRow, PgExecutor, PgPool, QueryBuilder};
 use time::OffsetDateTime;
+use tokio::sync::RwLock;
 use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
 use crate::{
-    auth::{AuthSession, Permission, RequirePermission},
+    auth::{AuthSession, CurrentPermissions, Permission, PermissionScope, ScopedPermission},
+    db::{Db, RowCount},
     error::PhsError,
+    events::{Notification, Notifier},
+    response_cache::{cache_responses, Scope},
+    review::{self, ReviewKind},
+    serve::{meta_errors, TeraPool},
+    share::{ShareBody, ShareKey, ShareLink, ShareParams, SharedKind},
+    storage::{SharedStorage, Storage},
+    validation::{FieldErrors, Validate, Validated},
+    ServerConfig, ServerSettings,
 };
 
 use super::{
-    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+    CursorOptions, CursorPaginatable, CursorResponse, Deletable, HasSqlxQueryString,
+    SqlxQueryString, TrashKind,
 };
 
+mod social;
+
+pub use social::social_job;
+
+/// Longest a post's title can be, as stored.
+const MAX_TITLE_LENGTH: usize = 255;
+
 pub fn router() -> Router {
     Router::new()
-        .route("/v1/posts", get(get_posts).post(new_post))
+        .route("/posts", get(get_posts).post(new_post))
         .route(
-            "/v1/posts/:id",
+            "/posts/:id",
             delete(delete_post).put(put_post).get(get_post),
         )
+        .route("/posts/:id/restore", post(restore_post))
+        .layer(middleware::from_fn_with_state(
+            Scope::Posts,
+            cache_responses,
+        ))
+        // Left uncached, as a cached copy would outlive its link
+        .route("/posts/:id/share", get(get_shared_post).post(share_post))
+        .merge(social::router())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_posts,
+    get_post,
+    new_post,
+    put_post,
+    delete_post,
+    restore_post,
+    share_post,
+    get_shared_post
+))]
+struct PostApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut openapi = PostApi::openapi();
+    openapi.merge(social::openapi());
+    openapi
 }
 
-#[derive(FromRow, Serialize, Deserialize)]
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Post {
     id: i32,
 
@@ -40,13 +90,27 @@ pub struct Post {
     pinned: bool,
     department: Option<i32>,
     category: Option<i32>,
+    /// Whether search engines are asked to leave it out, with a robots meta
+    /// tag wherever it's shown. It's left out of `sitemap.xml` either way.
+    noindex: bool,
+    /// For search results and link previews, as with pages. Nothing here
+    /// renders posts, so whatever does should put these in its `<meta>` tags.
+    meta_description: Option<String>,
+    og_title: Option<String>,
+    og_image: Option<String>,
+
+    created_by: Option<i32>,
+    updated_by: Option<i32>,
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
 }
 
 impl HasSqlxQueryString for Post {
     type QueryString = PostQueryString;
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct PostQueryString {
     id: Option<i32>,
     title: Option<String>,
@@ -68,6 +132,8 @@ pub struct PostQueryString {
 
 impl SqlxQueryString for PostQueryString {
     fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        builder.push(" AND deleted_at IS NULL AND published");
+
         if let Some(id) = self.id {
             builder.push(" AND id = ");
             builder.push_bind(id);
@@ -116,7 +182,7 @@ impl SqlxQueryString for PostQueryString {
         };
 
         if let s @ ("id" | "title" | "content" | "author" | "date" | "pinned" | "department"
-        | "category") = field.as_str()
+        | "category" | "updated_at") = field.as_str()
         {
             builder.push(s);
             order.append_to(builder);
@@ -133,14 +199,58 @@ impl CursorPaginatable for Post {
     }
 }
 
-#[instrument(skip(pool))]
+impl RowCount for Post {}
+
+#[async_trait]
+impl Deletable for Post {
+    const KIND: TrashKind = TrashKind::Post;
+
+    async fn restore(
+        pool: &PgPool,
+        _storage: &dyn Storage,
+        id: i32,
+        _by: i32,
+    ) -> Result<(), PhsError> {
+        sqlx::query_scalar!(
+            "UPDATE posts SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING id",
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn purge(
+        pool: &PgPool,
+        _storage: &dyn Storage,
+        cutoff: OffsetDateTime,
+    ) -> Result<u64, PhsError> {
+        Ok(
+            sqlx::query!("DELETE FROM posts WHERE deleted_at < $1", cutoff)
+                .execute(pool)
+                .await?
+                .rows_affected(),
+        )
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/posts",
+    tag = "posts",
+    params(PostQueryString, CursorOptions),
+    responses((status = 200, body = CursorResponse<Post>))
+)]
+#[instrument(skip(db))]
 async fn get_posts(
     Query(query_string): Query<<Post as HasSqlxQueryString>::QueryString>,
     Query(cursor_options): Query<CursorOptions>,
 
-    Extension(pool): Extension<PgPool>,
+    Extension(db): Extension<Db>,
 ) -> Result<Json<CursorResponse<Post>>, PhsError> {
     super::paginated_query_as::<Post>(
+        "list_posts",
         r#"
         SELECT id,
           title,
@@ -148,66 +258,153 @@ async fn get_posts(
           pinned,
           department,
           category,
+          noindex,
+          meta_description,
+          og_title,
+          og_image,
           author,
-          date
+          date,
+          created_by,
+          updated_by,
+          updated_at
         FROM posts
         "#,
         cursor_options,
         query_string,
-        &pool,
+        &db,
     )
     .await
     .map(|posts| Json(CursorResponse::new(posts)))
     .map_err(Into::into)
 }
 
-#[instrument(skip(pool))]
+#[utoipa::path(
+    get,
+    path = "/posts/{id}",
+    tag = "posts",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Post),
+        (status = 404, description = "No post has this ID, or it's awaiting approval and you can't change or approve it"),
+    )
+)]
+#[instrument(skip(db, permissions))]
 async fn get_post(
-    Extension(pool): Extension<PgPool>,
+    permissions: CurrentPermissions,
+    Extension(db): Extension<Db>,
     Path(id): Path<i32>,
 ) -> Result<Json<Post>, PhsError> {
-    sqlx::query_as!(
-        Post,
-        r#"
+    let reviewer = permissions.contains(Permission::EditPosts)
+        || permissions.contains(Permission::ApproveContent);
+
+    find_post(&db, id, reviewer).await.map(Json)
+}
+
+/// The post with this ID, unless it's in the trash, or it's yet to go live and
+/// `unpublished` isn't set.
+async fn find_post(db: &Db, id: i32, unpublished: bool) -> Result<Post, PhsError> {
+    db.timed(
+        "get_post",
+        sqlx::query_as!(
+            Post,
+            r#"
         SELECT id,
             title,
             content,
             pinned,
             department,
             category,
+            noindex,
+            meta_description,
+            og_title,
+            og_image,
             author,
-            date as "date: _"
+            date as "date: _",
+            created_by,
+            updated_by,
+            updated_at
         FROM posts
-        WHERE id = $1
+        WHERE id = $1 AND deleted_at IS NULL AND (published OR $2)
         "#,
-        id,
+            id,
+            unpublished,
+        )
+        .fetch_one(db.read()),
     )
-    .fetch_one(&pool)
     .await
-    .map(Json)
     .map_err(Into::into)
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 struct NewPostBody {
     title: String,
     content: String,
     pinned: bool,
     department: Option<i32>,
     category: Option<i32>,
+    #[serde(default)]
+    noindex: bool,
+    meta_description: Option<String>,
+    og_title: Option<String>,
+    /// An `https://` URL or a path on this site, e.g. of uploaded media.
+    og_image: Option<String>,
+    /// Whether to share it on the social channels in the config.
+    #[serde(default = "share_by_default")]
+    share: bool,
+}
+
+const fn share_by_default() -> bool {
+    true
 }
 
-#[instrument(skip(pool, auth_session))]
+impl Validate for NewPostBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("title", &self.title);
+        errors.max_chars("title", &self.title, MAX_TITLE_LENGTH);
+        meta_errors(
+            &mut errors,
+            self.meta_description.as_deref(),
+            self.og_title.as_deref(),
+            self.og_image.as_deref(),
+        );
+        errors
+    }
+}
+
+/// Makes a post, live straight away unless approval is switched on, in which
+/// case it's put forward and hidden until it's approved.
+#[utoipa::path(
+    post,
+    path = "/posts",
+    tag = "posts",
+    request_body = NewPostBody,
+    responses(
+        (status = 200, body = Post),
+        (status = 403, description = "Missing the `CreatePosts` permission, or only having it in other departments"),
+        (status = 422, description = "The title is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, tera, settings, notifier, auth_session))]
 async fn new_post(
     auth_session: AuthSession,
-    _: RequirePermission<{ Permission::CreatePosts as u8 }>,
+    ScopedPermission(scope): ScopedPermission<{ Permission::CreatePosts as u8 }>,
 
     Extension(pool): Extension<PgPool>,
-    Json(body): Json<NewPostBody>,
+    Extension(tera): Extension<Arc<TeraPool>>,
+    Extension(settings): Extension<Arc<RwLock<ServerSettings>>>,
+    Extension(notifier): Extension<Notifier>,
+    Validated(body): Validated<NewPostBody>,
 ) -> Result<Json<Post>, PhsError> {
+    scope.check(body.department)?;
+
     let user = auth_session.data();
+    let approval = settings.read().await.features.approval;
+
+    let mut tx = pool.begin().await?;
 
-    sqlx::query_as!(
+    let post = sqlx::query_as!(
         Post,
         r#"
             INSERT INTO posts (
@@ -216,17 +413,36 @@ async fn new_post(
                 author,
                 pinned,
                 department,
-                category
+                category,
+                noindex,
+                meta_description,
+                og_title,
+                og_image,
+                created_by,
+                updated_by,
+                review,
+                submitted_by,
+                share_on_approval
             ) VALUES (
-                $1, $2, $3, $4, $5, $6
+                $1, $2, $3, $4, $5, $6, $9, $10, $11, $12, $3, $3,
+                CASE WHEN $7 THEN 'awaiting'::review_status END,
+                CASE WHEN $7 THEN $3 END,
+                $7 AND $8
             ) RETURNING id,
                 title,
                 content,
                 pinned,
                 department,
                 category,
+                noindex,
+                meta_description,
+                og_title,
+                og_image,
                 author,
-                date as "date: _"
+                date as "date: _",
+                created_by,
+                updated_by,
+                updated_at
             "#,
         body.title,
         body.content,
@@ -234,29 +450,119 @@ async fn new_post(
         body.pinned,
         body.department,
         body.category,
+        approval,
+        body.share,
+        body.noindex,
+        body.meta_description,
+        body.og_title,
+        body.og_image,
     )
-    .fetch_one(&pool)
-    .await
-    .map(Json)
-    .map_err(Into::into)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if approval {
+        review::submitted(&mut tx, ReviewKind::Post, post.id, user.id()).await?;
+    }
+    tx.commit().await?;
+
+    if !approval {
+        tera.invalidate_cache().await;
+        notifier.notify(Notification::PostPublished {
+            post: post.id,
+            by: user.id(),
+            share: body.share,
+        });
+    }
+
+    Ok(Json(post))
 }
 
-#[instrument(skip(pool, _auth_session))]
+/// Fails unless `scope` covers the post with this ID, in the trash or not,
+/// only looking it up if `scope` is limited to some departments.
+async fn check_scope(
+    executor: impl PgExecutor<'_>,
+    scope: &PermissionScope,
+    id: i32,
+) -> Result<(), PhsError> {
+    if *scope == PermissionScope::Everywhere {
+        return Ok(());
+    }
+
+    let department = sqlx::query_scalar!("SELECT department FROM posts WHERE id = $1", id)
+        .fetch_one(executor)
+        .await?;
+
+    scope.check(department)
+}
+
+/// Moves a post to the trash, from where it can be restored until it expires.
+#[utoipa::path(
+    delete,
+    path = "/posts/{id}",
+    tag = "posts",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `EditPosts` permission, or only having it in other departments"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, tera, _auth_session))]
 async fn delete_post(
     _auth_session: AuthSession,
-    _: RequirePermission<{ Permission::EditPosts as u8 }>,
+    ScopedPermission(scope): ScopedPermission<{ Permission::EditPosts as u8 }>,
 
     Extension(pool): Extension<PgPool>,
+    Extension(tera): Extension<Arc<TeraPool>>,
     Path(id): Path<i32>,
 ) -> Result<(), PhsError> {
-    sqlx::query_as!(Post, r#"DELETE FROM posts WHERE id = $1"#, id,)
-        .execute(&pool)
-        .await?;
+    check_scope(&pool, &scope, id).await?;
+
+    sqlx::query!(
+        "UPDATE posts SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL",
+        id
+    )
+    .execute(&pool)
+    .await?;
+
+    tera.invalidate_cache().await;
+
+    Ok(())
+}
+
+/// Takes a post back out of the trash.
+#[utoipa::path(
+    post,
+    path = "/posts/{id}/restore",
+    tag = "posts",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `EditPosts` permission, or only having it in other departments"),
+        (status = 404, description = "No post in the trash has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, tera, auth_session))]
+async fn restore_post(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::EditPosts as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(tera): Extension<Arc<TeraPool>>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    Post::restore(&pool, &*storage, id, auth_session.data().id()).await?;
+
+    tera.invalidate_cache().await;
 
     Ok(())
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 struct PostPatchBody {
     title: String,
     content: String,
@@ -264,18 +570,66 @@ struct PostPatchBody {
     pinned: bool,
     department: Option<i32>,
     category: Option<i32>,
+    #[serde(default)]
+    noindex: bool,
+    meta_description: Option<String>,
+    og_title: Option<String>,
+    /// An `https://` URL or a path on this site, e.g. of uploaded media.
+    og_image: Option<String>,
 }
 
-#[instrument(skip(pool, _auth_session))]
+impl Validate for PostPatchBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("title", &self.title);
+        errors.max_chars("title", &self.title, MAX_TITLE_LENGTH);
+        meta_errors(
+            &mut errors,
+            self.meta_description.as_deref(),
+            self.og_title.as_deref(),
+            self.og_image.as_deref(),
+        );
+        errors
+    }
+}
+
+/// Replaces a post. With approval switched on it's put forward again, and
+/// taken down until it's approved, as posts have no draft to keep changes in.
+#[utoipa::path(
+    put,
+    path = "/posts/{id}",
+    tag = "posts",
+    params(("id" = i32, Path)),
+    request_body = PostPatchBody,
+    responses(
+        (status = 200, body = Post),
+        (status = 403, description = "Missing the `EditPosts` permission, or only having it in other departments than the post's old or new one"),
+        (status = 404, description = "No post has this ID"),
+        (status = 422, description = "The title is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, tera, settings, auth_session))]
 async fn put_post(
-    _auth_session: AuthSession,
-    _: RequirePermission<{ Permission::EditPosts as u8 }>,
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::EditPosts as u8 }>,
 
     Extension(pool): Extension<PgPool>,
+    Extension(tera): Extension<Arc<TeraPool>>,
+    Extension(settings): Extension<Arc<RwLock<ServerSettings>>>,
     Path(id): Path<i32>,
-    put_body: Json<PostPatchBody>,
+    Validated(put_body): Validated<PostPatchBody>,
 ) -> Result<Json<Post>, PhsError> {
-    sqlx::query_as!(
+    let user = auth_session.data().id();
+    let approval = settings.read().await.features.approval;
+
+    let mut tx = pool.begin().await?;
+
+    // Moving a post between departments needs both
+    check_scope(&mut *tx, &scope, id).await?;
+    scope.check(put_body.department)?;
+
+    let post = sqlx::query_as!(
         Post,
         r#"
             UPDATE posts
@@ -284,16 +638,32 @@ async fn put_post(
                 pinned = $3,
                 department = $4,
                 category = $5,
-                author = $6
-            WHERE id = $7
+                author = $6,
+                noindex = $10,
+                meta_description = $11,
+                og_title = $12,
+                og_image = $13,
+                updated_by = $8,
+                updated_at = now(),
+                review = CASE WHEN $9 THEN 'awaiting'::review_status ELSE review END,
+                submitted_by = CASE WHEN $9 THEN $8 ELSE submitted_by END,
+                reviewed_at = CASE WHEN $9 THEN NULL ELSE reviewed_at END
+            WHERE id = $7 AND deleted_at IS NULL
             RETURNING id,
                 title,
                 content,
                 pinned,
                 department,
                 category,
+                noindex,
+                meta_description,
+                og_title,
+                og_image,
                 author,
-                date as "date: _"
+                date as "date: _",
+                created_by,
+                updated_by,
+                updated_at
             "#,
         put_body.title,
         put_body.content,
@@ -302,9 +672,91 @@ async fn put_post(
         put_body.category,
         put_body.author,
         id,
+        user,
+        approval,
+        put_body.noindex,
+        put_body.meta_description,
+        put_body.og_title,
+        put_body.og_image,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if approval {
+        review::submitted(&mut tx, ReviewKind::Post, id, user).await?;
+    }
+    tx.commit().await?;
+
+    tera.invalidate_cache().await;
+
+    Ok(Json(post))
+}
+
+/// A link to the post for anyone who has it until it expires, logged in or
+/// not. See [`crate::share`].
+#[utoipa::path(
+    post,
+    path = "/posts/{id}/share",
+    tag = "posts",
+    params(("id" = i32, Path)),
+    request_body = ShareBody,
+    responses(
+        (status = 200, body = ShareLink),
+        (status = 403, description = "Missing the `EditPosts` permission, or only having it in other departments"),
+        (status = 404, description = "No post has this ID"),
+        (status = 422, description = "`days` is out of range"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, share_key, config, _auth_session))]
+async fn share_post(
+    _auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::EditPosts as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(share_key): Extension<ShareKey>,
+    Extension(config): Extension<ServerConfig>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<ShareBody>,
+) -> Result<Json<ShareLink>, PhsError> {
+    let department = sqlx::query_scalar!(
+        "SELECT department FROM posts WHERE id = $1 AND deleted_at IS NULL",
+        id
     )
     .fetch_one(&pool)
-    .await
-    .map(Json)
-    .map_err(Into::into)
+    .await?;
+    scope.check(department)?;
+
+    Ok(Json(share_key.link(
+        &config.site_url,
+        &format!("/posts/{id}"),
+        SharedKind::Post,
+        id,
+        body.days(),
+    )))
+}
+
+/// The post, for a link from `POST /posts/{id}/share`, even if it's awaiting
+/// approval. Needs no login.
+#[utoipa::path(
+    get,
+    path = "/posts/{id}/share",
+    tag = "posts",
+    params(("id" = i32, Path), ShareParams),
+    responses(
+        (status = 200, body = Post),
+        (status = 403, description = "The link isn't valid or has expired"),
+        (status = 404, description = "No post has this ID"),
+    )
+)]
+#[instrument(skip(db, share_key))]
+async fn get_shared_post(
+    Extension(db): Extension<Db>,
+    Extension(share_key): Extension<ShareKey>,
+    Path(id): Path<i32>,
+    Query(params): Query<ShareParams>,
+) -> Result<Json<Post>, PhsError> {
+    share_key.verify(SharedKind::Post, id, &params)?;
+
+    find_post(&db, id, true).await.map(Json)
 }
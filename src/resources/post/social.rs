@@ -0,0 +1,358 @@
+//! Sharing posts on the social channels in [`ServerConfig::social`] when
+//! they're published.
+
+use std::time::Duration;
+
+use axum::{
+    extract::Path,
+    http::{header, HeaderName, StatusCode},
+    routing::get,
+    Extension, Json, Router,
+};
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{prelude::FromRow, PgPool};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::Db,
+    error::PhsError,
+    events::{Notification, Notifier},
+    serve::LinkChecker,
+    ServerConfig, SocialChannel,
+};
+
+/// How often failed deliveries are retried.
+const RETRY_INTERVAL: Duration = Duration::from_mins(5);
+
+/// Attempts at a delivery before it's left as failed.
+const MAX_ATTEMPTS: i32 = 3;
+
+/// Most of a channel's response that's read, in bytes.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+const X_ENDPOINT: &str = "https://api.twitter.com/2/tweets";
+
+const FACEBOOK_ENDPOINT: &str = "https://graph.facebook.com/v19.0";
+
+pub fn router() -> Router {
+    Router::new().route("/posts/:id/social", get(get_deliveries))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_deliveries))]
+struct SocialApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    SocialApi::openapi()
+}
+
+#[derive(Serialize, Debug, sqlx::Type, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "social_delivery_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Not tried yet.
+    Pending,
+    Sent,
+    /// Tried and failed, and retried unless it's run out of attempts.
+    Failed,
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Delivery {
+    id: i32,
+    /// The channel's name from the config.
+    channel: String,
+    status: DeliveryStatus,
+    attempts: i32,
+    /// Why the last attempt failed.
+    last_error: Option<String>,
+    #[serde(with = "time::serde::iso8601")]
+    updated_at: OffsetDateTime,
+}
+
+/// Where a post has been shared, or has failed to be. Posts whose author
+/// opted out, or published before any channels were set up, have none.
+#[utoipa::path(
+    get,
+    path = "/posts/{id}/social",
+    tag = "posts",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Vec<Delivery>),
+        (status = 403, description = "Missing the `EditPosts` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_deliveries(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::EditPosts as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<Delivery>>, PhsError> {
+    db.timed(
+        "list_social_deliveries",
+        sqlx::query_as!(
+            Delivery,
+            r#"
+            SELECT id, channel, status AS "status: DeliveryStatus", attempts, last_error,
+              updated_at
+            FROM social_deliveries
+            WHERE post_id = $1
+            ORDER BY channel
+            "#,
+            id
+        )
+        .fetch_all(db.read()),
+    )
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Queues a delivery to every channel for each post published with sharing
+/// on, as heard from the [`Notifier`], and sends them straight away. Failed
+/// ones are retried every [`RETRY_INTERVAL`].
+pub async fn social_job(pool: PgPool, config: ServerConfig, notifier: Notifier) {
+    if config.social.is_empty() {
+        return;
+    }
+
+    let client = match LinkChecker::new(&config.site_url) {
+        Ok(client) => client,
+        Err(error) => {
+            tracing::error!(?error, "Social sharing couldn't start");
+            return;
+        }
+    };
+
+    let channels: Vec<_> = config
+        .social
+        .iter()
+        .map(|channel| channel.name().to_owned())
+        .collect();
+    let mut notifications = notifier.subscribe();
+    let mut interval = tokio::time::interval(RETRY_INTERVAL);
+
+    loop {
+        tokio::select! {
+            notification = notifications.recv() => match notification {
+                Ok(Notification::PostPublished { post, share: true, .. }) => {
+                    let queued = sqlx::query!(
+                        r#"
+                        INSERT INTO social_deliveries (post_id, channel)
+                        SELECT $1, unnest($2::varchar[])
+                        ON CONFLICT (post_id, channel) DO NOTHING
+                        "#,
+                        post,
+                        &channels
+                    )
+                    .execute(&pool)
+                    .await;
+
+                    if let Err(error) = queued {
+                        tracing::error!(?error, post, "Failed to queue a post for sharing");
+                    }
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "Missed notifications, so some posts may not be shared");
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            _ = interval.tick() => {}
+        }
+
+        if let Err(error) = deliver_due(&pool, &client, &config).await {
+            tracing::error!(?error, "Failed to share posts");
+        }
+    }
+}
+
+/// The post as a delivery needs it.
+struct Shared {
+    id: i32,
+    channel: String,
+    attempts: i32,
+    post_id: i32,
+    title: String,
+    date: OffsetDateTime,
+}
+
+async fn deliver_due(
+    pool: &PgPool,
+    client: &LinkChecker,
+    config: &ServerConfig,
+) -> Result<(), PhsError> {
+    // Rows stay locked while they're sent, so other instances skip them
+    let mut tx = pool.begin().await?;
+
+    let due = sqlx::query_as!(
+        Shared,
+        r#"
+        SELECT d.id, d.channel, d.attempts, p.id AS post_id, p.title, p.date
+        FROM social_deliveries d
+        JOIN posts p ON p.id = d.post_id
+        WHERE d.status <> 'sent'::social_delivery_status AND d.attempts < $1
+            AND p.deleted_at IS NULL
+        ORDER BY d.id
+        FOR UPDATE OF d SKIP LOCKED
+        "#,
+        MAX_ATTEMPTS
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for delivery in due {
+        // Channels taken out of the config are left as they were
+        let Some(channel) = config
+            .social
+            .iter()
+            .find(|channel| channel.name() == delivery.channel)
+        else {
+            continue;
+        };
+
+        let result = send(client, channel, &config.site_url, &delivery).await;
+        if let Err(error) = &result {
+            tracing::warn!(
+                %error,
+                channel = delivery.channel,
+                post = delivery.post_id,
+                attempt = delivery.attempts + 1,
+                "Failed to share a post"
+            );
+        }
+
+        let status = if result.is_ok() {
+            DeliveryStatus::Sent
+        } else {
+            DeliveryStatus::Failed
+        };
+        sqlx::query!(
+            r#"
+            UPDATE social_deliveries
+            SET status = $1, attempts = attempts + 1, last_error = $2, updated_at = now()
+            WHERE id = $3
+            "#,
+            status as DeliveryStatus,
+            result.err(),
+            delivery.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn send(
+    client: &LinkChecker,
+    channel: &SocialChannel,
+    site_url: &str,
+    delivery: &Shared,
+) -> Result<(), String> {
+    let url = format!(
+        "{}/posts/{}",
+        site_url.trim_end_matches('/'),
+        delivery.post_id
+    );
+
+    let (status, body) = match channel {
+        SocialChannel::X { token, .. } => {
+            // Titles fit in a tweet, with the link counted as 23 characters
+            let body = json!({ "text": format!("{}\n\n{url}", delivery.title) });
+            let authorization = format!("Bearer {token}");
+            client
+                .post(
+                    X_ENDPOINT,
+                    &[
+                        (header::AUTHORIZATION, authorization.as_str()),
+                        (header::CONTENT_TYPE, "application/json"),
+                    ],
+                    body.to_string().into_bytes(),
+                    MAX_RESPONSE_SIZE,
+                )
+                .await?
+        }
+        SocialChannel::Facebook { page_id, token, .. } => {
+            let form = [
+                ("message", delivery.title.as_str()),
+                ("link", url.as_str()),
+                ("access_token", token.as_str()),
+            ]
+            .map(|(key, value)| format!("{key}={}", utf8_percent_encode(value, NON_ALPHANUMERIC)))
+            .join("&");
+            client
+                .post(
+                    &format!("{FACEBOOK_ENDPOINT}/{page_id}/feed"),
+                    &[(header::CONTENT_TYPE, "application/x-www-form-urlencoded")],
+                    form.into_bytes(),
+                    MAX_RESPONSE_SIZE,
+                )
+                .await?
+        }
+        SocialChannel::Webhook {
+            url: webhook,
+            secret,
+            ..
+        } => {
+            let body = json!({
+                "id": delivery.post_id,
+                "title": delivery.title,
+                "url": url,
+                "date": delivery.date.format(&Rfc3339).ok(),
+            })
+            .to_string();
+
+            let signature = secret.as_ref().map(|secret| {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                    .expect("HMAC accepts keys of any length");
+                mac.update(body.as_bytes());
+                format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+            });
+
+            let mut headers = vec![(header::CONTENT_TYPE, "application/json")];
+            if let Some(signature) = &signature {
+                headers.push((
+                    HeaderName::from_static("x-signature-256"),
+                    signature.as_str(),
+                ));
+            }
+
+            client
+                .post(webhook, &headers, body.into_bytes(), MAX_RESPONSE_SIZE)
+                .await?
+        }
+    };
+
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(describe(status, &body))
+    }
+}
+
+/// A failed response, with the start of what the channel said about it.
+fn describe(status: StatusCode, body: &[u8]) -> String {
+    let body = String::from_utf8_lossy(body);
+    let excerpt: String = body.trim().chars().take(500).collect();
+
+    if excerpt.is_empty() {
+        format!("Returned {status}")
+    } else {
+        format!("Returned {status}: {excerpt}")
+    }
+}
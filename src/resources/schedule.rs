@@ -0,0 +1,182 @@
+//! What's due to go live, across everything with a go-live time of its own,
+//! for editors planning the week ahead.
+//!
+//! Posts and pages go live as soon as they're created or deployed, so only
+//! events, announcements and polls are scheduled.
+
+use axum::{extract::Query, routing::get, Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, PermissionCache, PermissionSet},
+    db::Db,
+    error::{ErrorCode, PhsError},
+};
+
+/// How far ahead the schedule looks if `to` isn't given.
+const DEFAULT_RANGE: Duration = Duration::weeks(1);
+
+/// Longest range that can be asked for at once.
+const MAX_RANGE: Duration = Duration::days(366);
+
+/// Most items listed, earliest first.
+const MAX_ITEMS: i64 = 500;
+
+pub fn router() -> Router {
+    Router::new().route("/schedule", get(get_schedule))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_schedule))]
+struct ScheduleApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ScheduleApi::openapi()
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ScheduleRange {
+    /// Defaults to now.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    from: Option<OffsetDateTime>,
+    /// Exclusive. Defaults to a week after `from`, and can be at most a year
+    /// after it.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    to: Option<OffsetDateTime>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum ScheduledKind {
+    /// Goes live when it starts.
+    Event,
+    /// Goes live when its banner starts showing.
+    Announcement,
+    /// Goes live when it opens for votes.
+    Poll,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct ScheduledItem {
+    kind: ScheduledKind,
+    /// The ID among others of its kind.
+    id: i32,
+    /// An event's title, an announcement's message or a poll's question.
+    title: String,
+    /// When it goes live.
+    #[serde(with = "time::serde::iso8601")]
+    at: OffsetDateTime,
+}
+
+/// Everything going live between `from` and `to`, earliest first. Each kind is
+/// only listed to those with the permission to manage it.
+#[utoipa::path(
+    get,
+    path = "/schedule",
+    tag = "schedule",
+    params(ScheduleRange),
+    responses(
+        (status = 200, body = Vec<ScheduledItem>),
+        (status = 400, description = "`to` isn't after `from`, or is more than a year after it"),
+        (status = 403, description = "Missing the `ManageEvents`, `ManageAnnouncements` and `ManagePolls` permissions"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, pool, cache, auth_session))]
+async fn get_schedule(
+    auth_session: AuthSession,
+
+    Extension(db): Extension<Db>,
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Query(range): Query<ScheduleRange>,
+) -> Result<Json<Vec<ScheduledItem>>, PhsError> {
+    let from = range.from.unwrap_or_else(OffsetDateTime::now_utc);
+    let to = range.to.unwrap_or(from + DEFAULT_RANGE);
+
+    if to <= from {
+        return Err(PhsError::client(
+            ErrorCode::BadRequest,
+            "`to` must be after `from`",
+        ));
+    }
+    if to - from > MAX_RANGE {
+        return Err(PhsError::client(
+            ErrorCode::BadRequest,
+            "`to` can be at most a year after `from`",
+        ));
+    }
+
+    let permissions: PermissionSet = cache
+        .get(&pool, auth_session.data().id())
+        .await?
+        .into_iter()
+        .collect();
+    let events = permissions.contains(Permission::ManageEvents);
+    let announcements = permissions.contains(Permission::ManageAnnouncements);
+    let polls = permissions.contains(Permission::ManagePolls);
+
+    if !(events || announcements || polls) {
+        return Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Missing permission",
+        ));
+    }
+
+    let rows = db
+        .timed(
+            "list_schedule",
+            sqlx::query!(
+                r#"
+                SELECT kind AS "kind!", id AS "id!", title AS "title!", at AS "at!"
+                FROM (
+                  SELECT 'event' AS kind, id, title, starts_at AS at
+                  FROM events
+                  WHERE $3 AND starts_at >= $1 AND starts_at < $2
+                  UNION ALL
+                  SELECT 'announcement', id, message, starts_at
+                  FROM announcements
+                  WHERE $4 AND starts_at >= $1 AND starts_at < $2
+                  UNION ALL
+                  SELECT 'poll', id, question, opens_at
+                  FROM polls
+                  WHERE $5 AND opens_at >= $1 AND opens_at < $2
+                ) scheduled
+                ORDER BY at, kind, id
+                LIMIT $6
+                "#,
+                from,
+                to,
+                events,
+                announcements,
+                polls,
+                MAX_ITEMS
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            let kind = match row.kind.as_str() {
+                "event" => ScheduledKind::Event,
+                "announcement" => ScheduledKind::Announcement,
+                "poll" => ScheduledKind::Poll,
+                _ => return Err(PhsError::bug("Unknown kind of scheduled item")),
+            };
+
+            Ok(ScheduledItem {
+                kind,
+                id: row.id,
+                title: row.title,
+                at: row.at,
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Json)
+}
@@ -0,0 +1,227 @@
+//! Deleted posts, pages, media and users, kept for [`TrashConfig::retention`]
+//! days so they can be restored.
+//!
+//! Each has a `deleted_at` column, set by its usual `DELETE` endpoint and
+//! cleared by its `restore` endpoint, and is left out everywhere else whilst
+//! it's set. Deleting and restoring stay with each resource, since they have
+//! side effects of their own, such as moving files aside or logging a user
+//! out; this lists the trash across all of them and empties it as things
+//! expire.
+
+use std::time::Duration;
+
+use axum::{async_trait, routing::get, Extension, Json, Router};
+use serde::Serialize;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, PermissionCache, PermissionSet},
+    config::TrashConfig,
+    db::Db,
+    error::{ErrorCode, PhsError},
+    media::Media,
+    serve::DynamicPageMetadata,
+    storage::{SharedStorage, Storage},
+    ServerConfig,
+};
+
+use super::{post::Post, user::User};
+
+/// How often the trash is checked for anything past its retention.
+const PURGE_INTERVAL: Duration = Duration::from_hours(1);
+
+/// Most items listed, most recently deleted first.
+const MAX_ITEMS: i64 = 500;
+
+/// Something that's soft-deleted into the trash by setting its `deleted_at`.
+#[async_trait]
+pub(crate) trait Deletable {
+    const KIND: TrashKind;
+
+    /// Takes the item with this ID back out of the trash on behalf of the user
+    /// `by`, failing with [`ErrorCode::NotFound`] if it isn't in there.
+    async fn restore(
+        pool: &PgPool,
+        storage: &dyn Storage,
+        id: i32,
+        by: i32,
+    ) -> Result<(), PhsError>;
+
+    /// Removes everything deleted before `cutoff` for good, along with its
+    /// files, returning how many were removed.
+    async fn purge(
+        pool: &PgPool,
+        storage: &dyn Storage,
+        cutoff: OffsetDateTime,
+    ) -> Result<u64, PhsError>;
+}
+
+pub fn router() -> Router {
+    Router::new().route("/trash", get(get_trash))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_trash))]
+struct TrashApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    TrashApi::openapi()
+}
+
+#[derive(Serialize, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TrashKind {
+    /// Restored with `POST /posts/{id}/restore`.
+    Post,
+    /// Restored with `POST /pages/{id}/restore`.
+    Page,
+    /// Restored with `POST /media/{id}/restore`.
+    Media,
+    /// Restored with `POST /users/{id}/restore`.
+    User,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct TrashedItem {
+    kind: TrashKind,
+    /// The ID among others of its kind.
+    id: i32,
+    /// A post's title, a page's name, an upload's file name or a username.
+    title: String,
+    #[serde(with = "time::serde::iso8601")]
+    deleted_at: OffsetDateTime,
+    /// When it'll be removed for good, if it isn't restored first.
+    #[serde(with = "time::serde::iso8601")]
+    purge_at: OffsetDateTime,
+}
+
+/// Everything in the trash, most recently deleted first. Each kind is only
+/// listed to those with the permission to delete it.
+#[utoipa::path(
+    get,
+    path = "/trash",
+    tag = "trash",
+    responses(
+        (status = 200, body = Vec<TrashedItem>),
+        (status = 403, description = "Missing the `EditPosts`, `ManagePages`, `ManageMedia` and `ManageUsers` permissions"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, pool, cache, config, auth_session))]
+async fn get_trash(
+    auth_session: AuthSession,
+
+    Extension(db): Extension<Db>,
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Extension(config): Extension<ServerConfig>,
+) -> Result<Json<Vec<TrashedItem>>, PhsError> {
+    let permissions: PermissionSet = cache
+        .get(&pool, auth_session.data().id())
+        .await?
+        .into_iter()
+        .collect();
+    let posts = permissions.contains(Permission::EditPosts);
+    let pages = permissions.contains(Permission::ManagePages);
+    let media = permissions.contains(Permission::ManageMedia);
+    let users = permissions.contains(Permission::ManageUsers);
+
+    if !(posts || pages || media || users) {
+        return Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Missing permission",
+        ));
+    }
+
+    let retention = time::Duration::days(config.trash.retention.into());
+
+    let rows = db
+        .timed(
+            "list_trash",
+            sqlx::query!(
+                r#"
+                SELECT kind AS "kind!", id AS "id!", title AS "title!", deleted_at AS "deleted_at!"
+                FROM (
+                  SELECT 'post' AS kind, id, title, deleted_at
+                  FROM posts
+                  WHERE $1 AND deleted_at IS NOT NULL
+                  UNION ALL
+                  SELECT 'page', id, name, deleted_at
+                  FROM pages
+                  WHERE $2 AND deleted_at IS NOT NULL
+                  UNION ALL
+                  SELECT 'media', id, filename, deleted_at
+                  FROM media
+                  WHERE $3 AND deleted_at IS NOT NULL
+                  UNION ALL
+                  SELECT 'user', id, username, deleted_at
+                  FROM users
+                  WHERE $4 AND deleted_at IS NOT NULL
+                ) trashed
+                ORDER BY deleted_at DESC, kind, id
+                LIMIT $5
+                "#,
+                posts,
+                pages,
+                media,
+                users,
+                MAX_ITEMS
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            let kind = match row.kind.as_str() {
+                "post" => TrashKind::Post,
+                "page" => TrashKind::Page,
+                "media" => TrashKind::Media,
+                "user" => TrashKind::User,
+                _ => return Err(PhsError::bug("Unknown kind of trashed item")),
+            };
+
+            Ok(TrashedItem {
+                kind,
+                id: row.id,
+                title: row.title,
+                deleted_at: row.deleted_at,
+                purge_at: row.deleted_at + retention,
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Json)
+}
+
+/// Removes everything that's been in the trash for `config.retention` days,
+/// every [`PURGE_INTERVAL`] for the lifetime of the server.
+pub async fn trash_purge_job(pool: PgPool, storage: SharedStorage, config: TrashConfig) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    let retention = time::Duration::days(config.retention.into());
+
+    loop {
+        interval.tick().await;
+
+        let cutoff = OffsetDateTime::now_utc() - retention;
+
+        purge::<Post>(&pool, &*storage, cutoff).await;
+        purge::<DynamicPageMetadata>(&pool, &*storage, cutoff).await;
+        purge::<Media>(&pool, &*storage, cutoff).await;
+        purge::<User>(&pool, &*storage, cutoff).await;
+    }
+}
+
+async fn purge<T: Deletable>(pool: &PgPool, storage: &dyn Storage, cutoff: OffsetDateTime) {
+    match T::purge(pool, storage, cutoff).await {
+        Ok(0) => tracing::debug!(kind = ?T::KIND, "Nothing in the trash past its retention"),
+        Ok(count) => tracing::info!(
+            kind = ?T::KIND,
+            count,
+            "Removed trashed items past their retention"
+        ),
+        Err(error) => tracing::error!(?error, kind = ?T::KIND, "Failed to empty the trash"),
+    }
+}
@@ -1,44 +1,97 @@
-use std::fmt::Debug;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use argon2::{
     password_hash,
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHasher, SaltString,
+    },
     Argon2, PasswordHash, PasswordVerifier,
 };
 use axum::{
+    async_trait,
     extract::{Path, Query},
-    http::StatusCode,
     routing::{get, post},
     Extension, Json, Router,
 };
 use deadpool_redis::Pool as RedisPool;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sha2::{Digest, Sha256};
-use sqlx::{prelude::FromRow, PgPool};
+use sqlx::{prelude::FromRow, PgConnection, PgPool};
+use time::OffsetDateTime;
 use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
 use crate::{
-    auth::{AuthSession, Permission, RequirePermission},
-    error::PhsError,
+    auth::{AuthSession, Group, Permission, PermissionCache, RequirePermission},
+    db::{Db, RowCount, Tx},
+    error::{ErrorCode, PhsError},
+    mail::Mail,
     resources::Department,
+    sessions::Session,
+    storage::{SharedStorage, Storage},
+    validation::{FieldErrors, Validate, Validated},
 };
 
 use super::{
-    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+    CursorOptions, CursorPaginatable, CursorResponse, Deletable, HasSqlxQueryString,
+    SqlxQueryString, TrashKind,
 };
 
 pub fn router() -> Router {
     Router::new()
-        .route("/v1/users", get(get_users).post(create_user))
+        .route("/users", get(get_users).post(create_user))
         .route(
-            "/v1/users/:id",
+            "/users/:id",
             get(get_user).put(put_user).delete(delete_user),
         )
-        .route("/v1/users/change-password", post(change_password))
-        .route("/v1/users/reset-password", post(reset_password))
+        .route("/users/:id/restore", post(restore_user))
+        .route("/users/:id/groups", get(get_user_groups))
+        .route("/users/change-password", post(change_password))
+        .route("/users/reset-password", post(reset_password))
+        .route("/users/forgot-password", post(forgot_password))
+        .route(
+            "/users/forgot-password/reset",
+            post(reset_forgotten_password),
+        )
 }
 
-#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_users,
+    create_user,
+    get_user,
+    get_user_groups,
+    put_user,
+    delete_user,
+    restore_user,
+    change_password,
+    reset_password,
+    forgot_password,
+    reset_forgotten_password
+))]
+struct UserApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    UserApi::openapi()
+}
+
+/// How long a password reset link works for, in seconds.
+const RESET_TOKEN_LIFETIME: u64 = 60 * 60;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Longest a name, username or email address can be, as stored.
+const MAX_FIELD_LENGTH: usize = 512;
+
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
 #[sqlx(type_name = "role", rename_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
@@ -47,8 +100,24 @@ pub enum Role {
     Student,
 }
 
-#[derive(Serialize, Deserialize, FromRow)]
-struct User {
+#[derive(Serialize, Deserialize, FromRow, ToSchema)]
+pub struct User {
+    id: i32,
+    username: String,
+    name: String,
+    email: Option<String>,
+
+    description: String,
+    department: Option<i32>,
+
+    role: Role,
+    permissions: Vec<Permission>,
+}
+
+/// A user as anyone can look them up. Their email address is only shown to
+/// those who manage users.
+#[derive(Serialize, FromRow, ToSchema)]
+struct PublicUser {
     id: i32,
     username: String,
     name: String,
@@ -64,7 +133,8 @@ impl HasSqlxQueryString for User {
     type QueryString = UserQueryString;
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct UserQueryString {
     id: Option<i32>,
     username: Option<String>,
@@ -79,6 +149,8 @@ struct UserQueryString {
 
 impl SqlxQueryString for UserQueryString {
     fn where_clause<'a>(&'a self, builder: &mut sqlx::QueryBuilder<'a, sqlx::Postgres>) {
+        builder.push(" AND deleted_at IS NULL");
+
         if let Some(id) = self.id {
             builder.push(" AND id = ");
             builder.push_bind(id);
@@ -126,49 +198,116 @@ impl CursorPaginatable for User {
     }
 }
 
-#[derive(Deserialize, Debug)]
+impl RowCount for User {}
+
+#[async_trait]
+impl Deletable for User {
+    const KIND: TrashKind = TrashKind::User;
+
+    async fn restore(
+        pool: &PgPool,
+        _storage: &dyn Storage,
+        id: i32,
+        _by: i32,
+    ) -> Result<(), PhsError> {
+        sqlx::query_scalar!(
+            "UPDATE users SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING id",
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn purge(
+        pool: &PgPool,
+        _storage: &dyn Storage,
+        cutoff: OffsetDateTime,
+    ) -> Result<u64, PhsError> {
+        Ok(
+            sqlx::query!("DELETE FROM users WHERE deleted_at < $1", cutoff)
+                .execute(pool)
+                .await?
+                .rows_affected(),
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
 struct CreateUserRequest {
     name: String,
     username: String,
+    /// Sent an invitation if given.
+    email: Option<String>,
     password: String,
     role: Role,
     description: String,
     department: Option<i32>,
 }
 
-#[instrument(skip(pool, _auth_session, req))]
+impl Validate for CreateUserRequest {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        for (field, value) in [("name", &self.name), ("username", &self.username)] {
+            errors.not_blank(field, value);
+            errors.max_chars(field, value, MAX_FIELD_LENGTH);
+        }
+        if let Some(email) = &self.email {
+            errors.email("email", email);
+            errors.max_chars("email", email, MAX_FIELD_LENGTH);
+        }
+        errors.min_chars("password", &self.password, MIN_PASSWORD_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    request_body = CreateUserRequest,
+    responses(
+        (status = 200, body = User),
+        (status = 400, description = "The username is taken"),
+        (status = 403, description = "Missing the `ManageUsers` permission"),
+        (status = 404, description = "No department has the given ID"),
+        (status = 422, description = "A field is empty, too long or malformed"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(tx, mail, _auth_session, req))]
 async fn create_user(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManageUsers as u8 }>,
 
-    Extension(pool): Extension<PgPool>,
-    Json(req): Json<CreateUserRequest>,
+    mut tx: Tx,
+    Extension(mail): Extension<Mail>,
+    Validated(req): Validated<CreateUserRequest>,
 ) -> Result<Json<User>, PhsError> {
     if req.department.is_some()
         && sqlx::query_as!(
             Department,
-            r#"SELECT id, department FROM departments WHERE id = $1"#,
+            r#"SELECT id, department, created_by, updated_by, updated_at FROM departments WHERE id = $1"#,
             req.department.unwrap()
         )
-        .fetch_optional(&pool)
+        .fetch_optional(&mut *tx)
         .await?
         .is_none()
     {
-        return Err(PhsError(
-            StatusCode::NOT_FOUND,
-            None,
+        return Err(PhsError::client(
+            ErrorCode::DepartmentNotFound,
             "No department exists with this ID",
         ));
     }
 
     if sqlx::query!(r#"SELECT id FROM users WHERE username = $1"#, req.username)
-        .fetch_optional(&pool)
+        .fetch_optional(&mut *tx)
         .await?
         .is_some()
     {
-        return Err(PhsError(
-            StatusCode::BAD_REQUEST,
-            None,
+        return Err(PhsError::client(
+            ErrorCode::UsernameTaken,
             "A user with this username already exists",
         ));
     }
@@ -180,11 +319,12 @@ async fn create_user(
     let user = sqlx::query_as!(
         User,
         r#"
-        INSERT INTO users (name, username, role, description, department, hash)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        INSERT INTO users (name, username, email, role, description, department, hash)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
         RETURNING id,
             name,
             username,
+            email,
             role as "role: _",
             description,
             department,
@@ -192,38 +332,186 @@ async fn create_user(
         "#,
         req.name,
         req.username,
+        req.email,
         req.role as Role,
         req.description,
         req.department,
         hash
     )
-    .fetch_one(&pool)
+    .fetch_one(&mut *tx)
     .await?;
 
+    if let Some(email) = &user.email {
+        mail.send(
+            &mut *tx,
+            email,
+            "invitation",
+            &json!({ "name": user.name, "username": user.username }),
+        )
+        .await?;
+    }
+
     Ok(Json(user))
 }
 
-#[instrument(skip(pool))]
-async fn get_user(
-    Path(id): Path<i32>,
-    Extension(pool): Extension<PgPool>,
-) -> Result<Json<User>, PhsError> {
-    let user = sqlx::query_as!(
-        User,
+/// Creates an admin with every permission, for bootstrapping a fresh install
+/// from the command line. Unlike [`create_user`], no invitation is sent.
+///
+/// # Errors
+///
+/// Fails if a field is invalid or the username is taken.
+pub async fn create_admin(
+    pool: &PgPool,
+    username: String,
+    name: String,
+    email: Option<String>,
+    password: String,
+) -> Result<i32, PhsError> {
+    let req = CreateUserRequest {
+        name,
+        username,
+        email,
+        password,
+        role: Role::Admin,
+        description: String::new(),
+        department: None,
+    };
+
+    let errors = req.validate();
+    if !errors.is_empty() {
+        return Err(PhsError::Invalid {
+            detail: "Some fields need fixing first".into(),
+            errors,
+        });
+    }
+
+    if sqlx::query!(r#"SELECT id FROM users WHERE username = $1"#, req.username)
+        .fetch_optional(pool)
+        .await?
+        .is_some()
+    {
+        return Err(PhsError::client(
+            ErrorCode::UsernameTaken,
+            "A user with this username already exists",
+        ));
+    }
+
+    let hash = Argon2::default()
+        .hash_password(req.password.as_bytes(), &SaltString::generate(&mut OsRng))?
+        .to_string();
+
+    sqlx::query_scalar!(
         r#"
-        SELECT id, name, username, role as "role: Role", description, department, permissions as "permissions: Vec<Permission>"
-        FROM users
-        WHERE id = $1
+        INSERT INTO users (name, username, email, role, description, hash, permissions)
+        VALUES ($1, $2, $3, 'admin', '', $4, $5)
+        RETURNING id
         "#,
+        req.name,
+        req.username,
+        req.email,
+        hash,
+        Permission::all().collect::<Vec<_>>() as Vec<Permission>
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(Into::into)
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = PublicUser),
+        (status = 404, description = "No user has this ID"),
+    )
+)]
+#[instrument(skip(db))]
+async fn get_user(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Db>,
+) -> Result<Json<PublicUser>, PhsError> {
+    let user = db
+        .timed(
+            "get_user",
+            sqlx::query_as!(
+                PublicUser,
+                r#"
+                SELECT id, name, username, role as "role: Role", description, department,
+                  permissions as "permissions: Vec<Permission>"
+                FROM users
+                WHERE id = $1 AND deleted_at IS NULL
+                "#,
+                id
+            )
+            .fetch_one(db.read()),
+        )
+        .await?;
+
+    Ok(Json(user))
+}
+
+/// The groups a user is in, by name.
+#[utoipa::path(
+    get,
+    path = "/users/{id}/groups",
+    tag = "users",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Vec<Group>),
+        (status = 403, description = "Missing the `ManagePermissions` permission"),
+        (status = 404, description = "No user has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
+async fn get_user_groups(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePermissions as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<Group>>, PhsError> {
+    sqlx::query_scalar!(
+        "SELECT id FROM users WHERE id = $1 AND deleted_at IS NULL",
         id
     )
-    .fetch_one(&pool)
+    .fetch_one(db.read())
     .await?;
 
-    Ok(Json(user))
+    db.timed(
+        "list_user_groups",
+        sqlx::query_as!(
+            Group,
+            r#"
+            SELECT g.id, g.group_name, g.permissions as "permissions: _"
+            FROM users_groups ug
+            JOIN groups g ON g.id = ug.group_id
+            WHERE ug.user_id = $1
+            ORDER BY g.group_name
+            "#,
+            id
+        )
+        .fetch_all(db.read()),
+    )
+    .await
+    .map(Json)
+    .map_err(Into::into)
 }
 
-#[instrument(skip(pool, _auth_session))]
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "users",
+    params(UserQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<User>),
+        (status = 403, description = "Missing the `ManageUsers` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, _auth_session))]
 async fn get_users(
     _auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManageUsers as u8 }>,
@@ -231,28 +519,61 @@ async fn get_users(
     Query(cursor_options): Query<CursorOptions>,
     Query(query_string): Query<<User as HasSqlxQueryString>::QueryString>,
 
-    Extension(pool): Extension<PgPool>,
+    Extension(db): Extension<Db>,
 ) -> Result<Json<CursorResponse<User>>, PhsError> {
     let users_no_hash = super::paginated_query_as::<User>(
-        r#"SELECT id, name, username, role, description, department, permissions FROM users"#,
+        "list_users",
+        r#"SELECT id, name, username, email, role, description, department, permissions FROM users"#,
         cursor_options,
         query_string,
-        &pool,
+        &db,
     )
     .await?;
 
     Ok(Json(CursorResponse::new(users_no_hash)))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 struct PutUserBody {
     username: Option<String>,
     name: Option<String>,
+    email: Option<String>,
     description: Option<String>,
     department: Option<i32>,
     role: Option<Role>,
 }
 
+impl Validate for PutUserBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        for (field, value) in [("name", &self.name), ("username", &self.username)] {
+            if let Some(value) = value {
+                errors.not_blank(field, value);
+                errors.max_chars(field, value, MAX_FIELD_LENGTH);
+            }
+        }
+        if let Some(email) = &self.email {
+            errors.email("email", email);
+            errors.max_chars("email", email, MAX_FIELD_LENGTH);
+        }
+        errors
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path)),
+    request_body = PutUserBody,
+    responses(
+        (status = 200, body = User),
+        (status = 403, description = "Missing the `ManageUsers` permission"),
+        (status = 404, description = "No user has this ID"),
+        (status = 422, description = "A field is empty, too long or malformed"),
+    ),
+    security(("session" = []))
+)]
 #[instrument(skip(pool, _auth_session))]
 async fn put_user(
     _auth_session: AuthSession,
@@ -260,7 +581,7 @@ async fn put_user(
 
     Path(id): Path<i32>,
     Extension(pool): Extension<PgPool>,
-    Json(body): Json<PutUserBody>,
+    Validated(body): Validated<PutUserBody>,
 ) -> Result<Json<User>, PhsError> {
     let user_no_hash = sqlx::query_as!(
         User,
@@ -268,13 +589,15 @@ async fn put_user(
         UPDATE users SET
             username = $1,
             name = $2,
-            description = $3,
-            department = $4,
-            role = $5
-        WHERE id = $6
+            email = $3,
+            description = $4,
+            department = $5,
+            role = $6
+        WHERE id = $7 AND deleted_at IS NULL
         RETURNING id,
             username,
             name,
+            email,
             description,
             department,
             role as "role: _",
@@ -282,6 +605,7 @@ async fn put_user(
         "#,
         body.username,
         body.name,
+        body.email,
         body.description,
         body.department,
         body.role as Option<Role>,
@@ -293,18 +617,38 @@ async fn put_user(
     Ok(Json(user_no_hash))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct ChangePasswordBody {
     current_password: String,
     new_password: String,
 }
 
-#[instrument(skip(pool, redis_pool, auth_session, body))]
+impl Validate for ChangePasswordBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.min_chars("new_password", &self.new_password, MIN_PASSWORD_LENGTH);
+        errors
+    }
+}
+
+/// Changes the current user's password, logging out their other sessions.
+#[utoipa::path(
+    post,
+    path = "/users/change-password",
+    tag = "users",
+    request_body = ChangePasswordBody,
+    responses(
+        (status = 200),
+        (status = 401, description = "The current password is wrong"),
+        (status = 422, description = "The new password is too short"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(tx, auth_session, body))]
 async fn change_password(
     auth_session: AuthSession,
-    Extension(pool): Extension<PgPool>,
-    Extension(redis_pool): Extension<RedisPool>,
-    Json(body): Json<ChangePasswordBody>,
+    mut tx: Tx,
+    Validated(body): Validated<ChangePasswordBody>,
 ) -> Result<(), PhsError> {
     let user_data = auth_session.data();
 
@@ -315,7 +659,7 @@ async fn change_password(
         )
         .map_err(|e| match e {
             password_hash::Error::Password => {
-                PhsError(StatusCode::UNAUTHORIZED, Some(Box::new(e)), "Unauthorised")
+                PhsError::client(ErrorCode::WrongCredentials, "Incorrect current password")
             }
             e => e.into(),
         })?;
@@ -338,116 +682,340 @@ async fn change_password(
         new_hash,
         user_data.id()
     )
-    .execute(&pool)
+    .execute(&mut *tx)
     .await?;
 
-    let mut conn = redis_pool.get().await?;
-
-    let mut sessions: Vec<String> = redis::cmd("FT.SEARCH")
-        .arg("idx:sessionsUserId")
-        .arg(format!(r#""@id:[{0} {0}]""#, user_data.id()))
-        .arg("NOCONTENT")
-        .query_async(&mut conn)
-        .await
-        .unwrap();
-
     // Clear all of the user's other sessions
-
-    let current_session_id = hex::encode(Sha256::digest(
-        auth_session
-            .session()
-            .get_hashed_id()
-            .await
-            .ok_or(PhsError(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                None,
-                "Error getting hashed session ID",
-            ))?,
-    ));
-
-    let Some(current_index) = sessions.iter().position(|s| *s == current_session_id) else {
-        return Err(PhsError(
-            StatusCode::UNAUTHORIZED,
-            None,
-            "Current session not found in user's sessions, most likely an expiry",
-        ));
-    };
-
-    // Remove the current session from the list to delete
-    sessions.swap_remove(current_index);
-
-    redis::cmd("DEL")
-        .arg(sessions)
-        .exec_async(&mut conn)
+    auth_session
+        .session()
+        .delete_user_sessions(user_data.id())
         .await?;
 
     Ok(())
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct PostResetPasswordBody {
     user_id: i32,
     new_password: String,
 }
 
+impl Validate for PostResetPasswordBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.min_chars("new_password", &self.new_password, MIN_PASSWORD_LENGTH);
+        errors
+    }
+}
+
+/// Sets another user's password, logging them out everywhere.
+#[utoipa::path(
+    post,
+    path = "/users/reset-password",
+    tag = "users",
+    request_body = PostResetPasswordBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageUsers` permission"),
+        (status = 404, description = "No user has this ID"),
+        (status = 422, description = "The new password is too short"),
+    ),
+    security(("session" = []))
+)]
 #[instrument(skip_all)]
 async fn reset_password(
-    _auth_session: AuthSession,
+    auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManageUsers as u8 }>,
 
+    mut tx: Tx,
+    Extension(mail): Extension<Mail>,
+
+    Validated(body): Validated<PostResetPasswordBody>,
+) -> Result<(), PhsError> {
+    set_password(
+        &mut tx,
+        &auth_session.session(),
+        &mail,
+        body.user_id,
+        &body.new_password,
+    )
+    .await
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PostForgotPasswordBody {
+    username: String,
+}
+
+/// Emails the user a link to choose a new password, if they have an email
+/// address. Succeeds either way, so it can't be used to find out who has an
+/// account.
+#[utoipa::path(
+    post,
+    path = "/users/forgot-password",
+    tag = "users",
+    request_body = PostForgotPasswordBody,
+    responses((status = 200))
+)]
+#[instrument(skip_all)]
+async fn forgot_password(
     Extension(pool): Extension<PgPool>,
-    Extension(redis_pool): Extension<RedisPool>,
+    Extension(reset_tokens): Extension<ResetTokens>,
+    Extension(mail): Extension<Mail>,
+
+    Json(body): Json<PostForgotPasswordBody>,
+) -> Result<(), PhsError> {
+    let user = sqlx::query!(
+        "SELECT id, name, username, email FROM users WHERE username = $1 AND deleted_at IS NULL",
+        body.username
+    )
+    .fetch_optional(&pool)
+    .await?;
+
+    let Some(user) = user else {
+        return Ok(());
+    };
+    let Some(email) = &user.email else {
+        return Ok(());
+    };
+
+    let mut token = [0; 32];
+    OsRng.fill_bytes(&mut token);
+    let token = hex::encode(token);
+
+    reset_tokens.insert(&token, user.id).await?;
+
+    mail.send(
+        &pool,
+        email,
+        "password_reset",
+        &json!({ "name": user.name, "username": user.username, "token": token }),
+    )
+    .await
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PostResetForgottenPasswordBody {
+    token: String,
+    new_password: String,
+}
 
-    Json(body): Json<PostResetPasswordBody>,
+impl Validate for PostResetForgottenPasswordBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.min_chars("new_password", &self.new_password, MIN_PASSWORD_LENGTH);
+        errors
+    }
+}
+
+/// Sets a new password given a token from [`forgot_password`], which only
+/// works once.
+#[utoipa::path(
+    post,
+    path = "/users/forgot-password/reset",
+    tag = "users",
+    request_body = PostResetForgottenPasswordBody,
+    responses(
+        (status = 200),
+        (status = 400, description = "The token is invalid or has expired"),
+        (status = 422, description = "The new password is too short"),
+    )
+)]
+#[instrument(skip_all)]
+async fn reset_forgotten_password(
+    session: Session,
+    mut tx: Tx,
+    Extension(reset_tokens): Extension<ResetTokens>,
+    Extension(mail): Extension<Mail>,
+
+    Validated(body): Validated<PostResetForgottenPasswordBody>,
+) -> Result<(), PhsError> {
+    let Some(user_id) = reset_tokens.take(&body.token).await? else {
+        return Err(PhsError::client(
+            ErrorCode::InvalidResetToken,
+            "This reset link is invalid or has expired",
+        ));
+    };
+
+    set_password(&mut tx, &session, &mail, user_id, &body.new_password).await
+}
+
+/// Password reset tokens that haven't been used or expired, with whose
+/// password each resets.
+#[derive(Clone)]
+pub enum ResetTokens {
+    Redis(RedisPool),
+    /// With when each expires, for running without Redis.
+    Memory(Arc<parking_lot::Mutex<HashMap<String, (i32, Instant)>>>),
+}
+
+impl ResetTokens {
+    #[must_use]
+    pub fn new(redis: Option<RedisPool>) -> Self {
+        redis.map_or_else(|| Self::Memory(Arc::default()), Self::Redis)
+    }
+
+    async fn insert(&self, token: &str, user_id: i32) -> Result<(), PhsError> {
+        let key = reset_token_key(token);
+
+        match self {
+            Self::Redis(redis) => {
+                let mut conn = redis.get().await?;
+                redis::cmd("SET")
+                    .arg(key)
+                    .arg(user_id)
+                    .arg("EX")
+                    .arg(RESET_TOKEN_LIFETIME)
+                    .exec_async(&mut conn)
+                    .await?;
+            }
+            Self::Memory(tokens) => {
+                let now = Instant::now();
+                let mut tokens = tokens.lock();
+                tokens.retain(|_, (_, expires)| *expires > now);
+                tokens.insert(
+                    key,
+                    (user_id, now + Duration::from_secs(RESET_TOKEN_LIFETIME)),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whose password the token resets, if anyone's. It won't work again.
+    async fn take(&self, token: &str) -> Result<Option<i32>, PhsError> {
+        let key = reset_token_key(token);
+
+        match self {
+            Self::Redis(redis) => {
+                let mut conn = redis.get().await?;
+                Ok(redis::cmd("GETDEL")
+                    .arg(key)
+                    .query_async::<Option<i32>>(&mut conn)
+                    .await?)
+            }
+            Self::Memory(tokens) => Ok(tokens
+                .lock()
+                .remove(&key)
+                .filter(|(_, expires)| *expires > Instant::now())
+                .map(|(user_id, _)| user_id)),
+        }
+    }
+}
+
+/// Only the hash is stored, so the tokens can't be read back out of Redis.
+fn reset_token_key(token: &str) -> String {
+    format!("password_reset:{}", hex::encode(Sha256::digest(token)))
+}
+
+/// Replaces a user's password, logs them out everywhere and lets them know by
+/// email. Run in a transaction, the password stays as it was if the sessions
+/// can't be cleared.
+async fn set_password(
+    conn: &mut PgConnection,
+    session: &Session,
+    mail: &Mail,
+    user_id: i32,
+    new_password: &str,
 ) -> Result<(), PhsError> {
     let new_hash = Argon2::default()
-        .hash_password(
-            body.new_password.as_bytes(),
-            &SaltString::generate(&mut OsRng),
-        )?
+        .hash_password(new_password.as_bytes(), &SaltString::generate(&mut OsRng))?
         .to_string();
 
-    sqlx::query!(
+    let user = sqlx::query!(
         r#"
         UPDATE users
         SET hash = $1
         WHERE users.id = $2
+        RETURNING name, username, email
         "#,
         new_hash,
-        body.user_id
+        user_id
     )
-    .execute(&pool)
+    .fetch_one(&mut *conn)
     .await?;
 
-    // Clear all of the user's sessions
-    let mut conn = redis_pool.get().await?;
+    // Clear all of the user's sessions, bar the one making the request if
+    // they're resetting their own
+    session.delete_user_sessions(user_id).await?;
 
-    let sessions: Vec<String> = redis::cmd("FT.SEARCH")
-        .arg("idx:sessionsUserId")
-        .arg(format!(r#""@id:[{0} {0}]""#, body.user_id))
-        .arg("NOCONTENT")
-        .query_async(&mut conn)
-        .await?;
-
-    redis::cmd("DEL")
-        .arg(sessions)
-        .exec_async(&mut conn)
+    if let Some(email) = &user.email {
+        mail.send(
+            conn,
+            email,
+            "password_changed",
+            &json!({ "name": user.name, "username": user.username }),
+        )
         .await?;
+    }
 
     Ok(())
 }
 
-#[instrument(skip(pool, _auth_session))]
+/// Moves a user to the trash and logs them out everywhere. They can't log in
+/// again unless they're restored before the trash expires.
+#[utoipa::path(
+    delete,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageUsers` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, cache, auth_session))]
 async fn delete_user(
-    _auth_session: AuthSession,
+    auth_session: AuthSession,
     _: RequirePermission<{ Permission::ManageUsers as u8 }>,
 
     Path(id): Path<i32>,
     Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
 ) -> Result<(), PhsError> {
-    sqlx::query!("DELETE FROM users WHERE id = $1", id)
-        .execute(&pool)
-        .await?;
+    sqlx::query!(
+        "UPDATE users SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL",
+        id
+    )
+    .execute(&pool)
+    .await?;
+
+    // In case clearing their sessions fails, they mustn't keep their permissions
+    cache.invalidate_user(id).await;
+    auth_session.session().delete_user_sessions(id).await?;
+
+    Ok(())
+}
+
+/// Takes a user back out of the trash, so they can log in again.
+#[utoipa::path(
+    post,
+    path = "/users/{id}/restore",
+    tag = "users",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageUsers` permission"),
+        (status = 404, description = "No user in the trash has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, cache, auth_session))]
+async fn restore_user(
+    auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageUsers as u8 }>,
+
+    Path(id): Path<i32>,
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(cache): Extension<PermissionCache>,
+) -> Result<(), PhsError> {
+    User::restore(&pool, &*storage, id, auth_session.data().id()).await?;
+
+    // They had none whilst they were in the trash
+    cache.invalidate_user(id).await;
 
     Ok(())
 }
@@ -0,0 +1,378 @@
+use axum::{
+    extract::{Path, Query},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, CurrentPermissions, Permission, RequirePermission},
+    db::{Db, RowCount},
+    error::{ErrorCode, PhsError},
+    serve::{element_errors, DynamicPageData, DynamicPageElement},
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    CursorOptions, CursorPaginatable, CursorResponse, HasSqlxQueryString, SqlxQueryString,
+};
+
+/// Longest a vacancy's title can be, as stored.
+const MAX_TITLE_LENGTH: usize = 255;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/vacancies", get(get_vacancies).post(create_vacancy))
+        .route(
+            "/vacancies/:id",
+            get(get_vacancy).put(put_vacancy).delete(delete_vacancy),
+        )
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_vacancies,
+    get_vacancy,
+    create_vacancy,
+    put_vacancy,
+    delete_vacancy
+))]
+struct VacancyApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    VacancyApi::openapi()
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Vacancy {
+    id: i32,
+
+    title: String,
+    /// Page elements, as in a page's `data`.
+    #[schema(value_type = Vec<DynamicPageElement>)]
+    description: serde_json::Value,
+    /// Applications close, and the vacancy stops being listed, at this time.
+    #[serde(with = "time::serde::iso8601")]
+    closes_at: OffsetDateTime,
+
+    /// The application pack, as an upload.
+    attachment: Option<i32>,
+    /// Where the application pack is served from, since uploads can't be
+    /// looked up without logging in.
+    attachment_url: Option<String>,
+    department: Option<i32>,
+
+    #[serde(with = "time::serde::iso8601")]
+    created_at: OffsetDateTime,
+}
+
+impl HasSqlxQueryString for Vacancy {
+    type QueryString = VacancyQueryString;
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VacancyQueryString {
+    id: Option<i32>,
+    title: Option<String>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    department: Option<Option<i32>>,
+
+    /// Also list vacancies that have closed. Needs the `ManageVacancies`
+    /// permission.
+    #[serde(default)]
+    include_closed: bool,
+
+    sort_by: Option<String>,
+}
+
+impl SqlxQueryString for VacancyQueryString {
+    fn where_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) {
+        if !self.include_closed {
+            builder.push(" AND closes_at > now()");
+        }
+
+        if let Some(id) = self.id {
+            builder.push(" AND id = ");
+            builder.push_bind(id);
+        }
+
+        if let Some(title) = &self.title {
+            builder.push(" AND title LIKE ");
+            builder.push_bind(title);
+        }
+
+        match self.department {
+            Some(Some(department)) => {
+                builder.push(" AND department = ");
+                builder.push_bind(department);
+            }
+            Some(None) => {
+                builder.push(" AND department IS NULL");
+            }
+            None => {}
+        }
+    }
+
+    fn order_by_clause<'a>(&'a self, builder: &mut QueryBuilder<'a, sqlx::Postgres>) -> bool {
+        let Some((field, order)) = Self::parse_sort_by(&self.sort_by) else {
+            return false;
+        };
+
+        if let s @ ("id" | "title" | "closes_at" | "department" | "created_at") = field.as_str() {
+            builder.push(s);
+            order.append_to(builder);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl CursorPaginatable for Vacancy {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl RowCount for Vacancy {}
+
+/// Whether whoever's asking may see vacancies that have closed.
+fn can_see_closed(permissions: CurrentPermissions) -> Result<(), PhsError> {
+    if permissions.contains(Permission::ManageVacancies) {
+        Ok(())
+    } else {
+        Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Only those who manage vacancies can see closed ones",
+        ))
+    }
+}
+
+/// Vacancies still taking applications.
+#[utoipa::path(
+    get,
+    path = "/vacancies",
+    tag = "vacancies",
+    params(VacancyQueryString, CursorOptions),
+    responses(
+        (status = 200, body = CursorResponse<Vacancy>),
+        (status = 403, description = "Asked for closed vacancies without the `ManageVacancies` permission"),
+    )
+)]
+#[instrument(skip(db, permissions))]
+async fn get_vacancies(
+    permissions: CurrentPermissions,
+    Query(query_string): Query<<Vacancy as HasSqlxQueryString>::QueryString>,
+    Query(cursor_options): Query<CursorOptions>,
+
+    Extension(db): Extension<Db>,
+) -> Result<Json<CursorResponse<Vacancy>>, PhsError> {
+    if query_string.include_closed {
+        can_see_closed(permissions)?;
+    }
+
+    super::paginated_query_as::<Vacancy>(
+        "list_vacancies",
+        r#"
+        SELECT id,
+          title,
+          description,
+          closes_at,
+          attachment,
+          (SELECT '/media/' || m.id || '/' || m.filename FROM media m WHERE m.id = vacancies.attachment) AS attachment_url,
+          department,
+          created_at
+        FROM vacancies
+        "#,
+        cursor_options,
+        query_string,
+        &db,
+    )
+    .await
+    .map(|vacancies| Json(CursorResponse::new(vacancies)))
+}
+
+/// A vacancy that has closed is only found with the `ManageVacancies`
+/// permission.
+#[utoipa::path(
+    get,
+    path = "/vacancies/{id}",
+    tag = "vacancies",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = Vacancy),
+        (status = 404, description = "No open vacancy has this ID"),
+    )
+)]
+#[instrument(skip(db, permissions))]
+async fn get_vacancy(
+    permissions: CurrentPermissions,
+    Extension(db): Extension<Db>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vacancy>, PhsError> {
+    let vacancy = db.timed("get_vacancy", fetch(db.read(), id)).await?;
+
+    if vacancy.closes_at <= OffsetDateTime::now_utc() && can_see_closed(permissions).is_err() {
+        return Err(PhsError::client(
+            ErrorCode::NotFound,
+            "No open vacancy has this ID",
+        ));
+    }
+
+    Ok(Json(vacancy))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct VacancyBody {
+    title: String,
+    #[serde(default)]
+    #[schema(value_type = Vec<DynamicPageElement>)]
+    description: DynamicPageData,
+    #[serde(with = "time::serde::iso8601")]
+    closes_at: OffsetDateTime,
+    attachment: Option<i32>,
+    department: Option<i32>,
+}
+
+impl Validate for VacancyBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = element_errors("description", &self.description);
+        errors.not_blank("title", &self.title);
+        errors.max_chars("title", &self.title, MAX_TITLE_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/vacancies",
+    tag = "vacancies",
+    request_body = VacancyBody,
+    responses(
+        (status = 200, body = Vacancy),
+        (status = 403, description = "Missing the `ManageVacancies` permission"),
+        (status = 422, description = "The title is empty or too long, or the description isn't valid"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn create_vacancy(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageVacancies as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Validated(body): Validated<VacancyBody>,
+) -> Result<Json<Vacancy>, PhsError> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO vacancies (title, description, closes_at, attachment, department)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        body.title,
+        serde_json::to_value(&body.description)?,
+        body.closes_at,
+        body.attachment,
+        body.department
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    fetch(&pool, id).await.map(Json).map_err(Into::into)
+}
+
+#[utoipa::path(
+    put,
+    path = "/vacancies/{id}",
+    tag = "vacancies",
+    params(("id" = i32, Path)),
+    request_body = VacancyBody,
+    responses(
+        (status = 200, body = Vacancy),
+        (status = 403, description = "Missing the `ManageVacancies` permission"),
+        (status = 404, description = "No vacancy has this ID"),
+        (status = 422, description = "The title is empty or too long, or the description isn't valid"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn put_vacancy(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageVacancies as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<VacancyBody>,
+) -> Result<Json<Vacancy>, PhsError> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE vacancies
+        SET title = $1,
+            description = $2,
+            closes_at = $3,
+            attachment = $4,
+            department = $5
+        WHERE id = $6
+        RETURNING id
+        "#,
+        body.title,
+        serde_json::to_value(&body.description)?,
+        body.closes_at,
+        body.attachment,
+        body.department,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    fetch(&pool, id).await.map(Json).map_err(Into::into)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/vacancies/{id}",
+    tag = "vacancies",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageVacancies` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn delete_vacancy(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageVacancies as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM vacancies WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
+
+/// A vacancy with its attachment's URL filled in.
+async fn fetch(pool: &PgPool, id: i32) -> Result<Vacancy, sqlx::Error> {
+    sqlx::query_as!(
+        Vacancy,
+        r#"
+        SELECT v.id, v.title, v.description, v.closes_at, v.attachment,
+          '/media/' || m.id || '/' || m.filename AS attachment_url,
+          v.department, v.created_at
+        FROM vacancies v
+        LEFT JOIN media m ON m.id = v.attachment
+        WHERE v.id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
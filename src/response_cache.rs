@@ -0,0 +1,366 @@
+//! Public API reads answered from a cache, as set up by
+//! [`ServerConfig::response_cache`](crate::ServerConfig::response_cache).
+//!
+//! Routers opt in with [`cache_responses`], naming the [`Scope`] they belong
+//! to. Their GETs made without logging in are cached under it, and their
+//! successful writes clear it along with any scope that shows what they
+//! change.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use deadpool_redis::Pool as RedisPool;
+use sha2::{Digest, Sha256};
+
+use crate::{auth::AuthSession, error::PhsError, ResponseCacheBackend, ResponseCacheConfig};
+
+/// Largest response kept, in bytes.
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// Most responses kept in memory at once. Expired ones are dropped to make
+/// room, and if that isn't enough, everything is.
+const MAX_MEMORY_ENTRIES: usize = 1000;
+
+/// What a cached response was read from, and so what a write clears.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Departments,
+    Categories,
+    Posts,
+    Announcements,
+}
+
+impl Scope {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Departments => "departments",
+            Self::Categories => "categories",
+            Self::Posts => "posts",
+            Self::Announcements => "announcements",
+        }
+    }
+
+    /// Scopes a write to this one makes stale. Deleting a department or
+    /// category takes it off its posts.
+    const fn invalidates(self) -> &'static [Self] {
+        match self {
+            Self::Departments => &[Self::Departments, Self::Posts],
+            Self::Categories => &[Self::Categories, Self::Posts],
+            Self::Posts => &[Self::Posts],
+            Self::Announcements => &[Self::Announcements],
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseCache(Option<Arc<Inner>>);
+
+struct Inner {
+    backend: Backend,
+    ttl: Duration,
+}
+
+enum Backend {
+    Memory(parking_lot::Mutex<Memory>),
+    Redis(RedisPool),
+}
+
+/// Entries are keyed as in Redis, so bumping a scope's generation leaves its
+/// old ones unreachable.
+#[derive(Default)]
+struct Memory {
+    generations: HashMap<Scope, u64>,
+    entries: HashMap<String, (Instant, Cached)>,
+}
+
+/// A response as it's kept, with the one header besides its type that it's
+/// sent with.
+#[derive(Clone)]
+struct Cached {
+    cache_control: Option<String>,
+    body: Bytes,
+}
+
+impl IntoResponse for Cached {
+    fn into_response(self) -> Response {
+        let mut res = (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )],
+            self.body,
+        )
+            .into_response();
+
+        if let Some(value) = self
+            .cache_control
+            .and_then(|value| HeaderValue::from_str(&value).ok())
+        {
+            res.headers_mut().insert(header::CACHE_CONTROL, value);
+        }
+
+        res
+    }
+}
+
+impl ResponseCache {
+    #[must_use]
+    pub fn new(config: &ResponseCacheConfig, redis: Option<RedisPool>) -> Self {
+        let backend = match (config.backend, redis) {
+            (ResponseCacheBackend::Off, _) => return Self(None),
+            (ResponseCacheBackend::Redis, Some(redis)) => Backend::Redis(redis),
+            // The config can't ask for Redis when it's off, but memory will do
+            (ResponseCacheBackend::Memory | ResponseCacheBackend::Redis, _) => {
+                Backend::Memory(parking_lot::Mutex::default())
+            }
+        };
+
+        Self(Some(Arc::new(Inner {
+            backend,
+            ttl: Duration::from_secs(config.ttl),
+        })))
+    }
+
+    /// Drops everything cached under `scope` and the scopes it affects, for
+    /// changes made other than through its router.
+    pub(crate) async fn invalidate(&self, scope: Scope) {
+        let Some(inner) = &self.0 else {
+            return;
+        };
+
+        match &inner.backend {
+            Backend::Memory(memory) => {
+                let mut memory = memory.lock();
+                for scope in scope.invalidates() {
+                    *memory.generations.entry(*scope).or_default() += 1;
+                }
+            }
+            Backend::Redis(redis) => {
+                let result = async {
+                    let mut conn = redis.get().await?;
+                    let mut pipe = redis::pipe();
+                    for scope in scope.invalidates() {
+                        pipe.cmd("INCR").arg(generation_key(*scope)).ignore();
+                    }
+                    pipe.query_async::<()>(&mut conn).await?;
+
+                    Ok::<_, PhsError>(())
+                }
+                .await;
+
+                // Left cached until it expires
+                if let Err(error) = result {
+                    tracing::error!(
+                        ?error,
+                        scope = scope.name(),
+                        "Failed to invalidate cached responses"
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Inner {
+    /// Where the response to `path_and_query` would be kept, as things are
+    /// now.
+    async fn key(&self, scope: Scope, path_and_query: &str) -> Result<String, PhsError> {
+        let generation = match &self.backend {
+            Backend::Memory(memory) => memory
+                .lock()
+                .generations
+                .get(&scope)
+                .copied()
+                .unwrap_or_default(),
+            Backend::Redis(redis) => {
+                let mut conn = redis.get().await?;
+                redis::cmd("GET")
+                    .arg(generation_key(scope))
+                    .query_async::<Option<u64>>(&mut conn)
+                    .await?
+                    .unwrap_or_default()
+            }
+        };
+
+        let hash = hex::encode(Sha256::digest(path_and_query));
+        Ok(format!("responses:{}:{generation}:{hash}", scope.name()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Cached>, PhsError> {
+        match &self.backend {
+            Backend::Memory(memory) => Ok(memory
+                .lock()
+                .entries
+                .get(key)
+                .filter(|(expires, _)| *expires > Instant::now())
+                .map(|(_, cached)| cached.clone())),
+            Backend::Redis(redis) => {
+                let mut conn = redis.get().await?;
+                let (body, cache_control) = redis::cmd("HMGET")
+                    .arg(key)
+                    .arg("body")
+                    .arg("cache_control")
+                    .query_async::<(Option<Vec<u8>>, Option<String>)>(&mut conn)
+                    .await?;
+
+                Ok(body.map(|body| Cached {
+                    cache_control,
+                    body: Bytes::from(body),
+                }))
+            }
+        }
+    }
+
+    async fn put(&self, key: String, cached: Cached) -> Result<(), PhsError> {
+        match &self.backend {
+            Backend::Memory(memory) => {
+                let mut memory = memory.lock();
+                if memory.entries.len() >= MAX_MEMORY_ENTRIES {
+                    let now = Instant::now();
+                    memory.entries.retain(|_, (expires, _)| *expires > now);
+                    if memory.entries.len() >= MAX_MEMORY_ENTRIES {
+                        memory.entries.clear();
+                    }
+                }
+                memory
+                    .entries
+                    .insert(key, (Instant::now() + self.ttl, cached));
+            }
+            Backend::Redis(redis) => {
+                let mut conn = redis.get().await?;
+                let mut pipe = redis::pipe();
+                pipe.cmd("HSET")
+                    .arg(&key)
+                    .arg("body")
+                    .arg(cached.body.as_ref())
+                    .ignore();
+                if let Some(cache_control) = &cached.cache_control {
+                    pipe.cmd("HSET")
+                        .arg(&key)
+                        .arg("cache_control")
+                        .arg(cache_control)
+                        .ignore();
+                }
+                pipe.cmd("EXPIRE")
+                    .arg(&key)
+                    .arg(self.ttl.as_secs())
+                    .ignore();
+                pipe.query_async::<()>(&mut conn).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn generation_key(scope: Scope) -> String {
+    format!("responses:{}:generation", scope.name())
+}
+
+/// Serves the router's GETs from the cache when they're made without logging
+/// in, caching successful JSON responses, and clears the scope after any
+/// write that succeeds. If the cache is unavailable, requests go through as
+/// normal.
+pub async fn cache_responses(State(scope): State<Scope>, req: Request, next: Next) -> Response {
+    let Some(cache) = req.extensions().get::<ResponseCache>().cloned() else {
+        return next.run(req).await;
+    };
+    let Some(inner) = cache.0.clone() else {
+        return next.run(req).await;
+    };
+
+    if *req.method() != Method::GET {
+        let writes = !matches!(*req.method(), Method::HEAD | Method::OPTIONS);
+        let res = next.run(req).await;
+
+        if writes && res.status().is_success() {
+            cache.invalidate(scope).await;
+        }
+
+        return res;
+    }
+
+    // Others may see more than the public does
+    if req.extensions().get::<AuthSession>().is_some() {
+        return next.run(req).await;
+    }
+
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or_else(|| req.uri().path(), |p| p.as_str())
+        .to_owned();
+
+    // The key is taken before the handler runs, so a write made meanwhile
+    // leaves what it read under a generation no one will look up again
+    let key = match inner.key(scope, &path_and_query).await {
+        Ok(key) => key,
+        Err(error) => {
+            tracing::warn!(?error, "Response cache unavailable");
+            return next.run(req).await;
+        }
+    };
+
+    match inner.get(&key).await {
+        Ok(Some(cached)) => return cached.into_response(),
+        Ok(None) => {}
+        Err(error) => {
+            tracing::warn!(?error, "Response cache unavailable");
+            return next.run(req).await;
+        }
+    }
+
+    let res = next.run(req).await;
+
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if res.status() != StatusCode::OK || !is_json || res.headers().contains_key(header::SET_COOKIE)
+    {
+        return res;
+    }
+
+    // Streamed bodies are left alone, as are those too big to keep
+    let fits = res
+        .body()
+        .size_hint()
+        .exact()
+        .is_some_and(|size| size <= MAX_RESPONSE_SIZE as u64);
+    if !fits {
+        return res;
+    }
+
+    let (parts, body) = res.into_parts();
+    let body = match to_bytes(body, MAX_RESPONSE_SIZE).await {
+        Ok(body) => body,
+        Err(e) => {
+            return PhsError::internal(e, "Error whilst reading a response to cache")
+                .into_response()
+        }
+    };
+
+    let cached = Cached {
+        cache_control: parts
+            .headers
+            .get(header::CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned),
+        body,
+    };
+    if let Err(error) = inner.put(key, cached.clone()).await {
+        tracing::warn!(?error, "Failed to cache response");
+    }
+
+    Response::from_parts(parts, Body::from(cached.body))
+}
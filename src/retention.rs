@@ -0,0 +1,182 @@
+//! Deleting records about people once they're older than the retention
+//! schedule allows, as [`RetentionConfig`] and
+//! [`AdmissionsConfig::retention`](crate::AdmissionsConfig::retention) set,
+//! and keeping a report of what each run deleted.
+
+use std::time::Duration;
+
+use axum::{routing::get, Extension, Json, Router};
+use serde::Serialize;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    config::RetentionConfig,
+    db::Db,
+    error::PhsError,
+    ServerConfig,
+};
+
+/// How often records past their retention are looked for.
+const PURGE_INTERVAL: Duration = Duration::from_hours(24);
+
+/// Most runs listed, most recent first.
+const MAX_RUNS: i64 = 100;
+
+pub fn router() -> Router {
+    Router::new().route("/retention", get(get_retention))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_retention))]
+struct RetentionApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    RetentionApi::openapi()
+}
+
+/// How many of each kind of record a run deleted.
+#[derive(Serialize, Debug, ToSchema)]
+struct RetentionRun {
+    #[serde(with = "time::serde::iso8601")]
+    ran_at: OffsetDateTime,
+    audit_log: i64,
+    form_submissions: i64,
+    admissions_enquiries: i64,
+}
+
+/// Days each kind of record is kept, or null if it's kept for good.
+#[derive(Serialize, Debug, ToSchema)]
+struct RetentionPolicy {
+    audit_log: Option<u32>,
+    form_submissions: Option<u32>,
+    admissions_enquiries: u32,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct RetentionReport {
+    policy: RetentionPolicy,
+    /// Most recent first.
+    runs: Vec<RetentionRun>,
+}
+
+/// How long records are kept, and what's been deleted for being older.
+#[utoipa::path(
+    get,
+    path = "/retention",
+    tag = "retention",
+    responses(
+        (status = 200, body = RetentionReport),
+        (status = 403, description = "Missing the `ManageSettings` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, config, _auth_session))]
+async fn get_retention(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(db): Extension<Db>,
+    Extension(config): Extension<ServerConfig>,
+) -> Result<Json<RetentionReport>, PhsError> {
+    let runs = db
+        .timed(
+            "list_retention_runs",
+            sqlx::query_as!(
+                RetentionRun,
+                "SELECT ran_at, audit_log, form_submissions, admissions_enquiries FROM retention_runs ORDER BY id DESC LIMIT $1",
+                MAX_RUNS
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    let RetentionConfig {
+        audit_log,
+        form_submissions,
+    } = config.retention;
+
+    Ok(Json(RetentionReport {
+        policy: RetentionPolicy {
+            audit_log,
+            form_submissions,
+            admissions_enquiries: config.admissions.retention,
+        },
+        runs,
+    }))
+}
+
+/// Deletes everything past its retention, every [`PURGE_INTERVAL`] for the
+/// lifetime of the server.
+pub async fn retention_job(pool: PgPool, config: ServerConfig) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match purge(&pool, &config).await {
+            Ok(run) => tracing::info!(
+                audit_log = run.audit_log,
+                form_submissions = run.form_submissions,
+                admissions_enquiries = run.admissions_enquiries,
+                "Deleted records past their retention"
+            ),
+            Err(error) => tracing::error!(?error, "Failed to delete records past their retention"),
+        }
+    }
+}
+
+/// Deletes everything past its retention and records how much, all or
+/// nothing.
+async fn purge(pool: &PgPool, config: &ServerConfig) -> Result<RetentionRun, PhsError> {
+    let days = |days: u32| i32::try_from(days).unwrap_or(i32::MAX);
+    let count = |rows: u64| i64::try_from(rows).unwrap_or(i64::MAX);
+
+    let mut tx = pool.begin().await?;
+
+    // A null interval matches nothing, so whatever's kept for good is left
+    let audit_log = sqlx::query!(
+        "DELETE FROM audit_log WHERE at < now() - make_interval(days => $1)",
+        config.retention.audit_log.map(days)
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let form_submissions = sqlx::query!(
+        "DELETE FROM form_submissions WHERE submitted_at < now() - make_interval(days => $1)",
+        config.retention.form_submissions.map(days)
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let admissions_enquiries = sqlx::query!(
+        "DELETE FROM admissions_enquiries WHERE updated_at < now() - make_interval(days => $1)",
+        days(config.admissions.retention)
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let run = sqlx::query_as!(
+        RetentionRun,
+        r"
+        INSERT INTO retention_runs (audit_log, form_submissions, admissions_enquiries)
+        VALUES ($1, $2, $3)
+        RETURNING ran_at, audit_log, form_submissions, admissions_enquiries
+        ",
+        count(audit_log),
+        count(form_submissions),
+        count(admissions_enquiries)
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(run)
+}
@@ -0,0 +1,576 @@
+//! Approving pages and posts before they go out, when
+//! [`FeatureToggles::approval`](crate::FeatureToggles::approval) is on.
+//!
+//! Something is put forward, then approved or rejected by someone with the
+//! `ApproveContent` permission, each step recorded in the [`audit`] log. No
+//! one can approve changes they made or put forward themselves.
+//!
+//! Pages are put forward by hand once they're ready, and can only be deployed
+//! whilst approved. Changing one after it's approved means approving it
+//! again, and deploying it clears its review for the next round. Posts are
+//! put forward whenever they're made or changed, and are hidden until
+//! they're approved.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    audit::{self, AuditEntry},
+    auth::{AuthSession, Permission, PermissionCache, PermissionSet, RequirePermission},
+    db::Db,
+    error::{ErrorCode, PhsError},
+    events::{Notification, Notifier},
+    response_cache::{ResponseCache, Scope},
+    serve::TeraPool,
+    validation::{FieldErrors, Validate, Validated},
+};
+
+/// Longest a reviewer's comment can be.
+const MAX_COMMENT_LENGTH: usize = 2000;
+
+/// Most items listed, most recently reviewed first.
+const MAX_ITEMS: i64 = 500;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/reviews", get(get_reviews))
+        .route("/reviews/:kind/:id", get(get_review_history))
+        .route("/reviews/:kind/:id/submit", post(submit))
+        .route("/reviews/:kind/:id/approve", post(approve))
+        .route("/reviews/:kind/:id/reject", post(reject))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_reviews, get_review_history, submit, approve, reject))]
+struct ReviewApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ReviewApi::openapi()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReviewKind {
+    Page,
+    Post,
+}
+
+impl ReviewKind {
+    /// What the audit log calls this kind of item.
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Page => "page",
+            Self::Post => "post",
+        }
+    }
+
+    /// Needed to change this kind of item, and so to put it forward.
+    const fn edit_permission(self) -> Permission {
+        match self {
+            Self::Page => Permission::ManagePages,
+            Self::Post => Permission::EditPosts,
+        }
+    }
+
+    /// Whether someone with these permissions can change or approve this kind
+    /// of item, and so follow and discuss its reviews.
+    const fn reviewable_by(self, permissions: PermissionSet) -> bool {
+        permissions.contains(self.edit_permission())
+            || permissions.contains(Permission::ApproveContent)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "review_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ReviewStatus {
+    Awaiting,
+    Approved,
+    Rejected,
+}
+
+impl ReviewStatus {
+    /// What the audit log calls moving to this status.
+    const fn action(self) -> &'static str {
+        match self {
+            Self::Awaiting => "submitted",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct Review {
+    kind: ReviewKind,
+    id: i32,
+    /// A page's name or a post's title.
+    title: String,
+    status: ReviewStatus,
+    /// Whether an approved page has been changed since, so needs approving
+    /// again before it can be deployed.
+    changed_since: bool,
+    submitted_by: Option<i32>,
+    #[serde(with = "time::serde::iso8601::option")]
+    reviewed_at: Option<OffsetDateTime>,
+}
+
+#[derive(Deserialize, Debug, Default, ToSchema)]
+#[serde(default)]
+struct ReviewBody {
+    comment: Option<String>,
+}
+
+impl Validate for ReviewBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        if let Some(comment) = &self.comment {
+            errors.max_chars("comment", comment, MAX_COMMENT_LENGTH);
+        }
+        errors
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct RejectBody {
+    /// What needs changing.
+    comment: String,
+}
+
+impl Validate for RejectBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("comment", &self.comment);
+        errors.max_chars("comment", &self.comment, MAX_COMMENT_LENGTH);
+        errors
+    }
+}
+
+/// Where an item is in review, and who last touched it.
+struct ReviewState {
+    review: Option<ReviewStatus>,
+    submitted_by: Option<i32>,
+    updated_by: Option<i32>,
+    /// Whether a page has changes to deploy, or a post should be shared once
+    /// it goes live.
+    pending: bool,
+}
+
+/// Locks the item for the rest of the transaction, failing with
+/// [`ErrorCode::NotFound`] if it's archived or in the trash.
+async fn lock(conn: &mut PgConnection, kind: ReviewKind, id: i32) -> Result<ReviewState, PhsError> {
+    let state = match kind {
+        ReviewKind::Page => {
+            sqlx::query_as!(
+                ReviewState,
+                r#"SELECT review as "review: ReviewStatus", submitted_by, updated_by, modified = ANY (ARRAY['new', 'edited', 'unpublished']::page_status[]) as "pending!" FROM pages WHERE id = $1 AND modified <> 'archived'::page_status FOR UPDATE"#,
+                id
+            )
+            .fetch_one(conn)
+            .await?
+        }
+        ReviewKind::Post => {
+            sqlx::query_as!(
+                ReviewState,
+                r#"SELECT review as "review: ReviewStatus", submitted_by, updated_by, share_on_approval as pending FROM posts WHERE id = $1 AND deleted_at IS NULL FOR UPDATE"#,
+                id
+            )
+            .fetch_one(conn)
+            .await?
+        }
+    };
+
+    Ok(state)
+}
+
+/// Moves the item to `status` and records who did it in the audit log.
+async fn transition(
+    conn: &mut PgConnection,
+    kind: ReviewKind,
+    id: i32,
+    status: ReviewStatus,
+    by: i32,
+    comment: Option<&str>,
+) -> Result<(), PhsError> {
+    match kind {
+        ReviewKind::Page => {
+            sqlx::query!(
+                "UPDATE pages SET review = $2, submitted_by = CASE WHEN $2 = 'awaiting'::review_status THEN $3 ELSE submitted_by END, reviewed_at = CASE WHEN $2 = 'awaiting'::review_status THEN NULL ELSE now() END WHERE id = $1",
+                id,
+                status as ReviewStatus,
+                by
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        ReviewKind::Post => {
+            sqlx::query!(
+                "UPDATE posts SET review = $2, submitted_by = CASE WHEN $2 = 'awaiting'::review_status THEN $3 ELSE submitted_by END, reviewed_at = CASE WHEN $2 = 'awaiting'::review_status THEN NULL ELSE now() END, share_on_approval = share_on_approval AND $2 <> 'approved'::review_status WHERE id = $1",
+                id,
+                status as ReviewStatus,
+                by
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    audit::record(conn, by, status.action(), kind.name(), id, comment).await
+}
+
+/// Records the item being put forward by `by` as part of changing it, for
+/// kinds that are put forward whenever they're changed.
+pub(crate) async fn submitted(
+    conn: &mut PgConnection,
+    kind: ReviewKind,
+    id: i32,
+    by: i32,
+) -> Result<(), PhsError> {
+    audit::record(
+        conn,
+        by,
+        ReviewStatus::Awaiting.action(),
+        kind.name(),
+        id,
+        None,
+    )
+    .await
+}
+
+/// The user's permissions as they are now.
+async fn permissions(
+    pool: &PgPool,
+    cache: &PermissionCache,
+    auth_session: &AuthSession,
+) -> Result<PermissionSet, PhsError> {
+    Ok(cache
+        .get(pool, auth_session.data().id())
+        .await?
+        .into_iter()
+        .collect())
+}
+
+/// Fails unless the user can currently change or approve this kind of item.
+pub(crate) async fn require_reviewer(
+    pool: &PgPool,
+    cache: &PermissionCache,
+    auth_session: &AuthSession,
+    kind: ReviewKind,
+) -> Result<(), PhsError> {
+    if kind.reviewable_by(permissions(pool, cache, auth_session).await?) {
+        Ok(())
+    } else {
+        Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Missing permission",
+        ))
+    }
+}
+
+/// Pages and posts in review, apart from posts that have gone live, most
+/// recently reviewed first. Each kind is only listed to those who can change
+/// or approve it.
+#[utoipa::path(
+    get,
+    path = "/reviews",
+    tag = "reviews",
+    responses(
+        (status = 200, body = Vec<Review>),
+        (status = 403, description = "Missing the `ManagePages`, `EditPosts` and `ApproveContent` permissions"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, pool, cache, auth_session))]
+async fn get_reviews(
+    auth_session: AuthSession,
+
+    Extension(db): Extension<Db>,
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+) -> Result<Json<Vec<Review>>, PhsError> {
+    let permissions = permissions(&pool, &cache, &auth_session).await?;
+    let approver = permissions.contains(Permission::ApproveContent);
+    let pages = approver || permissions.contains(Permission::ManagePages);
+    let posts = approver || permissions.contains(Permission::EditPosts);
+
+    if !(pages || posts) {
+        return Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Missing permission",
+        ));
+    }
+
+    let rows = db
+        .timed(
+            "list_reviews",
+            sqlx::query!(
+                r#"
+                SELECT kind AS "kind!", id AS "id!", title AS "title!", status AS "status!: ReviewStatus",
+                  changed_since AS "changed_since!", submitted_by, reviewed_at
+                FROM (
+                  SELECT 'page' AS kind, id, name AS title, review AS status,
+                    review = 'approved'::review_status AND reviewed_at < updated_at AS changed_since,
+                    submitted_by, reviewed_at
+                  FROM pages
+                  WHERE $1 AND review IS NOT NULL AND modified <> 'archived'::page_status
+                  UNION ALL
+                  SELECT 'post', id, title, review, false, submitted_by, reviewed_at
+                  FROM posts
+                  WHERE $2 AND NOT published AND deleted_at IS NULL
+                ) reviews
+                ORDER BY reviewed_at DESC NULLS FIRST, kind, id
+                LIMIT $3
+                "#,
+                pages,
+                posts,
+                MAX_ITEMS
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            let kind = match row.kind.as_str() {
+                "page" => ReviewKind::Page,
+                "post" => ReviewKind::Post,
+                _ => return Err(PhsError::bug("Unknown kind of item in review")),
+            };
+
+            Ok(Review {
+                kind,
+                id: row.id,
+                title: row.title,
+                status: row.status,
+                changed_since: row.changed_since,
+                submitted_by: row.submitted_by,
+                reviewed_at: row.reviewed_at,
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Json)
+}
+
+/// Everything recorded about the item's reviews, oldest first, with
+/// reviewers' comments.
+#[utoipa::path(
+    get,
+    path = "/reviews/{kind}/{id}",
+    tag = "reviews",
+    params(("kind" = ReviewKind, Path), ("id" = i32, Path)),
+    responses(
+        (status = 200, body = Vec<AuditEntry>),
+        (status = 403, description = "Can't change or approve this kind of item"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(db, pool, cache, auth_session))]
+async fn get_review_history(
+    auth_session: AuthSession,
+
+    Extension(db): Extension<Db>,
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Path((kind, id)): Path<(ReviewKind, i32)>,
+) -> Result<Json<Vec<AuditEntry>>, PhsError> {
+    require_reviewer(&pool, &cache, &auth_session, kind).await?;
+
+    audit::history(&db, kind.name(), id).await.map(Json)
+}
+
+/// Puts the item forward for approval. Pages can be put forward whenever they
+/// have changes to deploy, and posts once they've been rejected, since
+/// they're put forward whenever they're changed.
+#[utoipa::path(
+    post,
+    path = "/reviews/{kind}/{id}/submit",
+    tag = "reviews",
+    params(("kind" = ReviewKind, Path), ("id" = i32, Path)),
+    request_body = ReviewBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` or `EditPosts` permission, as the kind needs"),
+        (status = 404, description = "No page or post has this ID, or it's archived or in the trash"),
+        (status = 409, description = "It can't be put forward as it is"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, cache, auth_session))]
+async fn submit(
+    auth_session: AuthSession,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(cache): Extension<PermissionCache>,
+    Path((kind, id)): Path<(ReviewKind, i32)>,
+    Validated(body): Validated<ReviewBody>,
+) -> Result<(), PhsError> {
+    if !permissions(&pool, &cache, &auth_session)
+        .await?
+        .contains(kind.edit_permission())
+    {
+        return Err(PhsError::client(
+            ErrorCode::MissingPermission,
+            "Missing permission",
+        ));
+    }
+
+    let mut tx = pool.begin().await?;
+    let state = lock(&mut tx, kind, id).await?;
+
+    let ready = match kind {
+        ReviewKind::Page => state.pending && state.review != Some(ReviewStatus::Awaiting),
+        ReviewKind::Post => state.review == Some(ReviewStatus::Rejected),
+    };
+    if !ready {
+        return Err(PhsError::client(
+            ErrorCode::InvalidTransition,
+            match kind {
+                ReviewKind::Page => "Only pages with changes to deploy, not already awaiting approval, can be put forward",
+                ReviewKind::Post => "Only rejected posts can be put forward again",
+            },
+        ));
+    }
+
+    transition(
+        &mut tx,
+        kind,
+        id,
+        ReviewStatus::Awaiting,
+        auth_session.data().id(),
+        body.comment.as_deref(),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Approves the item, so a page can be deployed or a post goes live. Whoever
+/// put it forward or last changed it has to leave this to someone else.
+#[utoipa::path(
+    post,
+    path = "/reviews/{kind}/{id}/approve",
+    tag = "reviews",
+    params(("kind" = ReviewKind, Path), ("id" = i32, Path)),
+    request_body = ReviewBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ApproveContent` permission, or approving one's own changes"),
+        (status = 404, description = "No page or post has this ID, or it's archived or in the trash"),
+        (status = 409, description = "It isn't awaiting approval"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, tera, response_cache, notifier, auth_session))]
+async fn approve(
+    auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ApproveContent as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(tera): Extension<Arc<TeraPool>>,
+    Extension(response_cache): Extension<ResponseCache>,
+    Extension(notifier): Extension<Notifier>,
+    Path((kind, id)): Path<(ReviewKind, i32)>,
+    Validated(body): Validated<ReviewBody>,
+) -> Result<(), PhsError> {
+    let user = auth_session.data().id();
+
+    let mut tx = pool.begin().await?;
+    let state = lock(&mut tx, kind, id).await?;
+
+    if state.review != Some(ReviewStatus::Awaiting) {
+        return Err(PhsError::client(
+            ErrorCode::InvalidTransition,
+            "Only what's awaiting approval can be approved",
+        ));
+    }
+    if state.submitted_by == Some(user) || state.updated_by == Some(user) {
+        return Err(PhsError::client(
+            ErrorCode::OwnChanges,
+            "Someone else has to approve changes you made or put forward",
+        ));
+    }
+
+    transition(
+        &mut tx,
+        kind,
+        id,
+        ReviewStatus::Approved,
+        user,
+        body.comment.as_deref(),
+    )
+    .await?;
+    tx.commit().await?;
+
+    if let ReviewKind::Post = kind {
+        tera.invalidate_cache().await;
+        response_cache.invalidate(Scope::Posts).await;
+        notifier.notify(Notification::PostPublished {
+            post: id,
+            by: user,
+            share: state.pending,
+        });
+    }
+
+    Ok(())
+}
+
+/// Sends the item back with what needs changing. Pages need putting forward
+/// again afterwards, whilst posts are put forward again when they're changed.
+#[utoipa::path(
+    post,
+    path = "/reviews/{kind}/{id}/reject",
+    tag = "reviews",
+    params(("kind" = ReviewKind, Path), ("id" = i32, Path)),
+    request_body = RejectBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ApproveContent` permission"),
+        (status = 404, description = "No page or post has this ID, or it's archived or in the trash"),
+        (status = 409, description = "It isn't awaiting approval"),
+        (status = 422, description = "The comment is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, auth_session))]
+async fn reject(
+    auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ApproveContent as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path((kind, id)): Path<(ReviewKind, i32)>,
+    Validated(body): Validated<RejectBody>,
+) -> Result<(), PhsError> {
+    let mut tx = pool.begin().await?;
+    let state = lock(&mut tx, kind, id).await?;
+
+    if state.review != Some(ReviewStatus::Awaiting) {
+        return Err(PhsError::client(
+            ErrorCode::InvalidTransition,
+            "Only what's awaiting approval can be rejected",
+        ));
+    }
+
+    transition(
+        &mut tx,
+        kind,
+        id,
+        ReviewStatus::Rejected,
+        auth_session.data().id(),
+        Some(&body.comment),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use axum::{extract::Query, routing::get, Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::instrument;
+
+use crate::{
+    db::Db,
+    error::{ErrorCode, PhsError},
+    ServerSettings,
+};
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 50;
+
+pub fn router() -> Router {
+    Router::new().route("/search", get(search))
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchParams {
+    q: String,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum SearchResultKind {
+    Page,
+    Post,
+}
+
+#[derive(Serialize, Debug)]
+struct SearchResult {
+    kind: SearchResultKind,
+    title: String,
+    url: String,
+    /// Plain text around the matches, with each match wrapped in `<b>`.
+    snippet: String,
+}
+
+/// Searches deployed public pages and posts, best matches first. `q` takes
+/// the usual search box syntax: quoted phrases, `or`, and `-` to exclude.
+#[instrument(skip(db, settings))]
+async fn search(
+    Extension(db): Extension<Db>,
+    Extension(settings): Extension<Arc<RwLock<ServerSettings>>>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<SearchResult>>, PhsError> {
+    if !settings.read().await.features.search {
+        return Err(PhsError::client(
+            ErrorCode::SearchDisabled,
+            "Search is switched off",
+        ));
+    }
+
+    if params.q.trim().is_empty() {
+        return Err(PhsError::client(
+            ErrorCode::EmptyQuery,
+            "Search query can't be empty",
+        ));
+    }
+
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    // Post content is HTML, so tags are stripped before making snippets
+    let rows = db
+        .timed(
+            "search",
+            sqlx::query!(
+                r#"
+                WITH RECURSIVE paths AS (
+                    SELECT id, ARRAY[name]::text[] AS path FROM pages WHERE parent_id IS NULL
+                    UNION ALL
+                    SELECT p.id, paths.path || p.name::text FROM pages p
+                    JOIN paths ON p.parent_id = paths.id
+                ),
+                query AS (SELECT websearch_to_tsquery('english', $1) AS query)
+                SELECT kind as "kind!", title as "title!", url as "url!", snippet as "snippet!"
+                FROM (
+                    SELECT 'page' AS kind,
+                        p.name::text AS title,
+                        '/' || array_to_string(paths.path, '/') AS url,
+                        ts_headline('english', p.search_text, query.query, 'MaxFragments=2, MinWords=5, MaxWords=20') AS snippet,
+                        ts_rank(p.search_vector, query.query) AS rank
+                    FROM pages p
+                    JOIN paths USING (id)
+                    CROSS JOIN query
+                    WHERE p.search_vector @@ query.query
+                        AND p.modified IN ('unmodified'::page_status, 'edited'::page_status)
+                        AND p.visibility = 'public'::page_visibility
+                    UNION ALL
+                    SELECT 'post',
+                        posts.title::text,
+                        '/posts/' || posts.id,
+                        ts_headline('english', regexp_replace(posts.content, '<[^>]*>', ' ', 'g'), query.query, 'MaxFragments=2, MinWords=5, MaxWords=20'),
+                        ts_rank(posts.search_vector, query.query)
+                    FROM posts
+                    CROSS JOIN query
+                    WHERE posts.search_vector @@ query.query AND posts.deleted_at IS NULL AND posts.published
+                ) results
+                ORDER BY rank DESC
+                LIMIT $2
+                "#,
+                params.q,
+                limit
+            )
+            .fetch_all(db.read()),
+        )
+        .await?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| SearchResult {
+                kind: if row.kind == "page" {
+                    SearchResultKind::Page
+                } else {
+                    SearchResultKind::Post
+                },
+                title: row.title,
+                url: row.url,
+                snippet: row.snippet,
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,390 @@
+//! Demo data for a fresh instance, so the frontend has something to show and
+//! integration tests have something to query, without clicking it all in.
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Argon2,
+};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    auth::Permission,
+    error::{ErrorCode, PhsError},
+    resources::Role,
+    serve::{create_page, DynamicPageData, PageLayout, PageVisibility},
+    storage::Storage,
+};
+
+/// The password of every seeded user. Demo data only; never seed a database
+/// anyone else can reach.
+pub const SEED_PASSWORD: &str = "demo-password";
+
+const DEPARTMENTS: &[&str] = &[
+    "English",
+    "Mathematics",
+    "Science",
+    "History",
+    "Music",
+    "Physical Education",
+];
+
+const CATEGORIES: &[&str] = &["News", "Events", "Sport", "Trips", "Achievements"];
+
+struct SeedUser {
+    username: &'static str,
+    name: &'static str,
+    role: Role,
+    department: Option<&'static str>,
+    description: &'static str,
+    permissions: &'static [Permission],
+}
+
+/// One of each kind of account, so every permission check can be tried out.
+/// `admin` gets every permission on top of these.
+const USERS: &[SeedUser] = &[
+    SeedUser {
+        username: "admin",
+        name: "Alex Admin",
+        role: Role::Admin,
+        department: None,
+        description: "Looks after the website.",
+        permissions: &[],
+    },
+    SeedUser {
+        username: "editor",
+        name: "Erin Editor",
+        role: Role::Teacher,
+        department: Some("English"),
+        description: "Head of English, and edits the school newsletter.",
+        permissions: &[
+            Permission::CreatePosts,
+            Permission::EditPosts,
+            Permission::ManagePages,
+            Permission::ManageMedia,
+            Permission::EditCategories,
+        ],
+    },
+    SeedUser {
+        username: "teacher",
+        name: "Sam Teacher",
+        role: Role::Teacher,
+        department: Some("Science"),
+        description: "Teaches chemistry and runs the science club.",
+        permissions: &[Permission::CreatePosts],
+    },
+    SeedUser {
+        username: "student",
+        name: "Jo Student",
+        role: Role::Student,
+        department: None,
+        description: "",
+        permissions: &[],
+    },
+];
+
+struct SeedPost {
+    title: &'static str,
+    content: &'static str,
+    author: &'static str,
+    pinned: bool,
+    department: Option<&'static str>,
+    category: Option<&'static str>,
+    days_ago: i32,
+}
+
+const POSTS: &[SeedPost] = &[
+    SeedPost {
+        title: "Welcome back for the autumn term",
+        content: "We're delighted to welcome everyone back, including our new Year 7s. \
+                  Timetables are on the noticeboard outside the library.",
+        author: "admin",
+        pinned: true,
+        department: None,
+        category: Some("News"),
+        days_ago: 40,
+    },
+    SeedPost {
+        title: "Open evening",
+        content: "Families thinking of joining us are invited to our open evening on \
+                  Thursday from 6pm. Tours leave from the main hall every fifteen minutes.",
+        author: "editor",
+        pinned: true,
+        department: None,
+        category: Some("Events"),
+        days_ago: 21,
+    },
+    SeedPost {
+        title: "Science club wins regional rocketry prize",
+        content: "Congratulations to the science club, whose water rocket flew 120 metres \
+                  to take first place at the regional finals.",
+        author: "teacher",
+        pinned: false,
+        department: Some("Science"),
+        category: Some("Achievements"),
+        days_ago: 14,
+    },
+    SeedPost {
+        title: "Year 9 trip to the Globe",
+        content: "Year 9 will see Macbeth at the Globe next month. Consent forms are due \
+                  back to form tutors by Friday.",
+        author: "editor",
+        pinned: false,
+        department: Some("English"),
+        category: Some("Trips"),
+        days_ago: 10,
+    },
+    SeedPost {
+        title: "Maths challenge results",
+        content: "Forty students took part in the junior maths challenge this year, with \
+                  twelve gold certificates.",
+        author: "editor",
+        pinned: false,
+        department: Some("Mathematics"),
+        category: Some("Achievements"),
+        days_ago: 7,
+    },
+    SeedPost {
+        title: "Netball team through to the county semi-finals",
+        content: "A 24-18 win away from home puts the under-14s into the semi-finals. \
+                  Come and support them next Wednesday.",
+        author: "admin",
+        pinned: false,
+        department: Some("Physical Education"),
+        category: Some("Sport"),
+        days_ago: 3,
+    },
+    SeedPost {
+        title: "Winter concert rehearsals",
+        content: "Orchestra and choir rehearsals move to Tuesday lunchtimes until the \
+                  concert.",
+        author: "editor",
+        pinned: false,
+        department: Some("Music"),
+        category: Some("Events"),
+        days_ago: 1,
+    },
+];
+
+/// What [`seed`] added.
+#[derive(Debug, Default)]
+pub struct SeedSummary {
+    pub departments: usize,
+    pub categories: usize,
+    /// Usernames, all with [`SEED_PASSWORD`].
+    pub users: Vec<&'static str>,
+    pub posts: usize,
+    /// Page names. They're left as `new`, so deploying shows them on the site.
+    pub pages: Vec<&'static str>,
+}
+
+/// Fills an empty database with a small, plausible school: departments,
+/// categories, a user for each role, posts spread over the last few weeks and
+/// a handful of pages.
+///
+/// Departments and categories that already exist are reused.
+///
+/// # Errors
+///
+/// Fails if the database already has users, so this can't be run against a
+/// real site by mistake.
+pub async fn seed(pool: &PgPool, storage: &dyn Storage) -> Result<SeedSummary, PhsError> {
+    if sqlx::query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM users) AS "exists!""#)
+        .fetch_one(pool)
+        .await?
+    {
+        return Err(PhsError::client(
+            ErrorCode::AlreadyExists,
+            "The database already has users; only seed a fresh one",
+        ));
+    }
+
+    let mut summary = SeedSummary::default();
+
+    let mut departments = Vec::new();
+    for &department in DEPARTMENTS {
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO departments (department)
+            VALUES ($1)
+            ON CONFLICT (department) DO UPDATE SET department = EXCLUDED.department
+            RETURNING id
+            "#,
+            department
+        )
+        .fetch_one(pool)
+        .await?;
+        departments.push((department, id));
+    }
+    summary.departments = departments.len();
+
+    let mut categories = Vec::new();
+    for &category in CATEGORIES {
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO categories (category)
+            VALUES ($1)
+            ON CONFLICT (category) DO UPDATE SET category = EXCLUDED.category
+            RETURNING id
+            "#,
+            category
+        )
+        .fetch_one(pool)
+        .await?;
+        categories.push((category, id));
+    }
+    summary.categories = categories.len();
+
+    // Hashing is slow enough to be worth doing once
+    let hash = Argon2::default()
+        .hash_password(SEED_PASSWORD.as_bytes(), &SaltString::generate(&mut OsRng))?
+        .to_string();
+
+    let mut users = Vec::new();
+    for user in USERS {
+        let permissions = if user.role == Role::Admin {
+            Permission::all().collect()
+        } else {
+            user.permissions.to_vec()
+        };
+
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO users (name, username, email, role, description, department, hash, permissions)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id
+            "#,
+            user.name,
+            user.username,
+            format!("{}@example.com", user.username),
+            user.role as Role,
+            user.description,
+            user.department.and_then(|name| lookup(&departments, name)),
+            hash,
+            permissions as Vec<Permission>
+        )
+        .fetch_one(pool)
+        .await?;
+        users.push((user.username, id));
+        summary.users.push(user.username);
+    }
+
+    for post in POSTS {
+        sqlx::query!(
+            r#"
+            INSERT INTO posts (title, content, author, pinned, department, category, date)
+            VALUES ($1, $2, $3, $4, $5, $6, now() - make_interval(days => $7))
+            "#,
+            post.title,
+            post.content,
+            lookup(&users, post.author),
+            post.pinned,
+            post.department.and_then(|name| lookup(&departments, name)),
+            post.category.and_then(|name| lookup(&categories, name)),
+            post.days_ago
+        )
+        .execute(pool)
+        .await?;
+        summary.posts += 1;
+    }
+
+    summary.pages = seed_pages(pool, storage).await?;
+
+    tracing::info!(?summary, "Seeded the database");
+
+    Ok(summary)
+}
+
+/// A small public tree under `home`, and a staff-only page.
+async fn seed_pages(pool: &PgPool, storage: &dyn Storage) -> Result<Vec<&'static str>, PhsError> {
+    let mut tx = pool.begin().await?;
+
+    let home = create_page(
+        &mut tx,
+        storage,
+        "home",
+        None,
+        PageLayout::Landing,
+        PageVisibility::Public,
+        page_data(json!([
+            { "type": "header", "size": "h1", "contents": "Welcome to our school" },
+            { "type": "text", "components": [
+                { "modifiers": [], "link": null, "content": "A friendly, ambitious school at the heart of the community. " },
+                { "modifiers": [], "link": "/home/about", "content": "Find out more about us" },
+            ] },
+        ]))?,
+        None,
+    )
+    .await?;
+
+    let about = create_page(
+        &mut tx,
+        storage,
+        "about",
+        Some(home),
+        PageLayout::Base,
+        PageVisibility::Public,
+        page_data(json!([
+            { "type": "header", "size": "h1", "contents": "About us" },
+            { "type": "text", "components": [
+                { "modifiers": [], "link": null, "content": "We teach around 900 students aged 11 to 18, across six departments." },
+            ] },
+            { "type": "header", "size": "h2", "contents": "Our values" },
+            { "type": "list", "list_type": "unordered", "items": [
+                [{ "modifiers": ["Bold"], "link": null, "content": "Curiosity" }],
+                [{ "modifiers": ["Bold"], "link": null, "content": "Kindness" }],
+                [{ "modifiers": ["Bold"], "link": null, "content": "Perseverance" }],
+            ] },
+        ]))?,
+        None,
+    )
+    .await?;
+
+    create_page(
+        &mut tx,
+        storage,
+        "term_dates",
+        Some(about),
+        PageLayout::Minimal,
+        PageVisibility::Public,
+        page_data(json!([
+            { "type": "header", "size": "h1", "contents": "Term dates" },
+            { "type": "list", "list_type": "ordered", "items": [
+                [{ "modifiers": [], "link": null, "content": "Autumn: 4 September to 19 December" }],
+                [{ "modifiers": [], "link": null, "content": "Spring: 6 January to 3 April" }],
+                [{ "modifiers": [], "link": null, "content": "Summer: 22 April to 18 July" }],
+            ] },
+        ]))?,
+        None,
+    )
+    .await?;
+
+    create_page(
+        &mut tx,
+        storage,
+        "staff_handbook",
+        None,
+        PageLayout::Base,
+        PageVisibility::Staff,
+        page_data(json!([
+            { "type": "header", "size": "h1", "contents": "Staff handbook" },
+            { "type": "text", "components": [
+                { "modifiers": ["Italic"], "link": null, "content": "Only visible to signed-in staff." },
+            ] },
+        ]))?,
+        None,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(vec!["home", "about", "term_dates", "staff_handbook"])
+}
+
+fn lookup(ids: &[(&str, i32)], name: &str) -> Option<i32> {
+    ids.iter().find(|(n, _)| *n == name).map(|&(_, id)| id)
+}
+
+fn page_data(value: serde_json::Value) -> Result<DynamicPageData, PhsError> {
+    Ok(serde_json::from_value(value)?)
+}
@@ -0,0 +1,331 @@
+use std::{collections::HashMap, error::Error, fmt, path::Path};
+
+use deadpool_redis::Pool as RedisPool;
+use sqlx::{migrate::Migrate, PgPool};
+use tera::Tera;
+
+#[cfg(feature = "signed_cookies")]
+use crate::cookie_key;
+#[cfg(feature = "embedded_assets")]
+use crate::embedded;
+use crate::{mail, share::ShareKey, ServerConfig};
+
+/// Where the page templates are loaded from.
+const TEMPLATES_DIR: &str = "pages/templates";
+pub const TEMPLATES_GLOB: &str = "pages/templates/**/*";
+
+/// Everything found wrong by [`self_check`], each with what to do about it.
+#[derive(Debug)]
+pub struct SelfCheckFailed(Vec<String>);
+
+impl fmt::Display for SelfCheckFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The server can't start until these are fixed:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for SelfCheckFailed {}
+
+impl SelfCheckFailed {
+    /// What's wrong, without the preamble about starting up.
+    pub(crate) fn problems(&self) -> &[String] {
+        &self.0
+    }
+}
+
+/// Loads the page templates, explaining what's wrong with any that don't
+/// parse. Tera's own message only names the file.
+///
+/// # Errors
+///
+/// Fails if any template doesn't parse.
+pub fn load_templates() -> Result<Tera, SelfCheckFailed> {
+    let failed =
+        |e: tera::Error| SelfCheckFailed(vec![format!("pages/templates: {}", error_chain(&e))]);
+
+    // Those on disk override the embedded ones, which may be all there is
+    #[cfg(feature = "embedded_assets")]
+    {
+        let mut tera = if Path::new(TEMPLATES_DIR).is_dir() {
+            Tera::new(TEMPLATES_GLOB).map_err(failed)?
+        } else {
+            Tera::default()
+        };
+        embedded::add_templates(&mut tera, TEMPLATES_DIR).map_err(failed)?;
+        Ok(tera)
+    }
+
+    #[cfg(not(feature = "embedded_assets"))]
+    Tera::new(TEMPLATES_GLOB).map_err(failed)
+}
+
+/// Each template in `pages/templates` by name, with why it doesn't parse if it
+/// doesn't. Each is parsed with the others standing in as empty templates, so
+/// one that's broken doesn't hide how the rest are doing.
+///
+/// # Errors
+///
+/// Fails if the directory can't be read.
+pub(crate) fn template_statuses() -> std::io::Result<Vec<(String, Option<String>)>> {
+    let mut sources = Vec::new();
+    read_templates(Path::new(TEMPLATES_DIR), "", &mut sources)?;
+    sources.sort();
+
+    let statuses = sources
+        .iter()
+        .map(|(name, source)| {
+            let stand_ins = sources
+                .iter()
+                .filter(|(other, _)| other != name)
+                .map(|(other, _)| (other.as_str(), ""));
+
+            let mut tera = Tera::default();
+            let parsed = tera
+                .add_raw_templates(stand_ins)
+                .and_then(|()| match source {
+                    Some(source) => tera.add_raw_template(name, source),
+                    None => Err(tera::Error::msg("Isn't valid UTF-8")),
+                });
+
+            (name.clone(), parsed.err().map(|e| error_chain(&e)))
+        })
+        .collect();
+
+    Ok(statuses)
+}
+
+/// Adds every file under `dir` to `sources`, named as Tera names them, or
+/// without a source if it isn't text.
+fn read_templates(
+    dir: &Path,
+    prefix: &str,
+    sources: &mut Vec<(String, Option<String>)>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+
+        if entry.file_type()?.is_dir() {
+            read_templates(&entry.path(), &format!("{name}/"), sources)?;
+        } else {
+            let source = String::from_utf8(std::fs::read(entry.path())?).ok();
+            sources.push((name, source));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks what would otherwise only fail on the first request that needs it,
+/// so a broken install stops at startup with a list of what to fix rather
+/// than answering with 500s.
+///
+/// Every check runs, so all the problems are reported at once.
+///
+/// # Errors
+///
+/// Fails with every problem found.
+pub async fn self_check(
+    db: &PgPool,
+    redis_pool: Option<&RedisPool>,
+    tera: &Tera,
+    config: &ServerConfig,
+) -> Result<(), SelfCheckFailed> {
+    let mut problems = Vec::new();
+
+    if let Err(problem) = migrations_problem(db).await {
+        problems.push(problem);
+    }
+    if let Some(redis_pool) = redis_pool {
+        if let Err(problem) = check_redis(redis_pool).await {
+            problems.push(problem);
+        }
+    }
+    problems.extend(check_templates(tera, config));
+    problems.extend(check_files(config));
+
+    #[cfg(feature = "signed_cookies")]
+    if let Err(e) = cookie_key::load_or_create(&config.cookie_key_path) {
+        problems.push(format!(
+            "cookie_key_path ({}) can't be read or created: {e}",
+            config.cookie_key_path.display()
+        ));
+    }
+
+    if let Err(e) = ShareKey::load_or_create(&config.share_key_path) {
+        problems.push(format!(
+            "share_key_path ({}) can't be read or created: {e}",
+            config.share_key_path.display()
+        ));
+    }
+
+    if problems.is_empty() {
+        tracing::info!("Startup checks passed");
+        Ok(())
+    } else {
+        Err(SelfCheckFailed(problems))
+    }
+}
+
+/// Runs before migrating, so only looks for what would stop that: migrations
+/// that were changed after being applied, or ones the build doesn't know
+/// about, which would mean it's older than the schema.
+///
+/// # Errors
+///
+/// Fails if migrating would, or the database can't be reached.
+pub async fn check_migrations(db: &PgPool) -> Result<(), SelfCheckFailed> {
+    migrations_problem(db)
+        .await
+        .map_err(|problem| SelfCheckFailed(vec![problem]))
+}
+
+async fn migrations_problem(db: &PgPool) -> Result<(), String> {
+    let mut conn = db
+        .acquire()
+        .await
+        .map_err(|e| format!("Couldn't connect to DATABASE_URL: {e}"))?;
+    conn.ensure_migrations_table()
+        .await
+        .map_err(|e| format!("Couldn't create the migrations table: {e}"))?;
+    let applied = conn
+        .list_applied_migrations()
+        .await
+        .map_err(|e| format!("Couldn't list applied migrations: {e}"))?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum))
+        .collect::<HashMap<_, _>>();
+
+    let migrator = sqlx::migrate!();
+
+    for migration in migrator.iter() {
+        if applied
+            .get(&migration.version)
+            .is_some_and(|checksum| *checksum != migration.checksum)
+        {
+            return Err(format!(
+                "Migration {} ({}) was changed after it was applied; restore the original \
+                 from version control",
+                migration.version, migration.description
+            ));
+        }
+    }
+
+    if let Some(version) = applied
+        .keys()
+        .find(|version| !migrator.iter().any(|m| m.version == **version))
+    {
+        return Err(format!(
+            "The database has migration {version}, which this build doesn't; is it out of date?"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Sessions are stored with RedisJSON commands, which plain Redis doesn't
+/// have.
+async fn check_redis(redis_pool: &RedisPool) -> Result<(), String> {
+    let mut conn = redis_pool
+        .get()
+        .await
+        .map_err(|e| format!("Couldn't connect to REDIS_URL: {e}"))?;
+
+    let result: Result<Option<String>, _> = redis::cmd("JSON.GET")
+        .arg("self_check")
+        .query_async(&mut conn)
+        .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) if e.to_string().to_lowercase().contains("unknown command") => Err(
+            "Redis doesn't have the RedisJSON module, which sessions need; use Redis Stack or \
+             load the module with `loadmodule`"
+                .into(),
+        ),
+        Err(e) => Err(format!("Redis didn't answer properly: {e}")),
+    }
+}
+
+fn check_templates(tera: &Tera, config: &ServerConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if let Some(template) = &config.unavailable_template {
+        if !tera.get_template_names().any(|name| name == template) {
+            problems.push(format!(
+                "unavailable_template is {template}, but there's no such file in pages/templates"
+            ));
+        }
+    }
+
+    // Otherwise they only fail when the first message is sent
+    if let Some(mail) = &config.mail {
+        if let Err(e) = mail::load_templates(mail) {
+            problems.push(format!("{}: {}", mail.templates.display(), error_chain(&e)));
+        }
+    }
+
+    problems
+}
+
+/// Files and directories the config points at, beyond what
+/// [`ServerConfig::validate`] can check without touching the disk.
+fn check_files(config: &ServerConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if config.tls_enabled {
+        if let Some(tls) = &config.tls_options {
+            for (option, path) in [
+                ("tls.cert_path", &tls.cert_path),
+                ("tls.key_path", &tls.key_path),
+            ] {
+                match std::fs::read_to_string(path) {
+                    Ok(pem) if pem.contains("-----BEGIN ") => {}
+                    Ok(_) => {
+                        problems.push(format!("{option} ({}) isn't a PEM file", path.display()))
+                    }
+                    Err(e) => {
+                        problems.push(format!("{option} ({}) can't be read: {e}", path.display()))
+                    }
+                }
+            }
+        }
+
+        if let Some(acme) = &config.acme {
+            if let Err(e) = std::fs::create_dir_all(&acme.cache_dir) {
+                problems.push(format!(
+                    "acme.cache_dir ({}) can't be created: {e}",
+                    acme.cache_dir.display()
+                ));
+            }
+        }
+    }
+
+    if let Some(parent) = config.settings_path.parent() {
+        if parent != Path::new("") && !parent.is_dir() {
+            problems.push(format!(
+                "settings_path ({}) is in a directory that doesn't exist",
+                config.settings_path.display()
+            ));
+        }
+    }
+
+    problems
+}
+
+/// An error and each of its sources, as Tera puts the useful part in the
+/// source.
+fn error_chain(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
@@ -1,20 +1,70 @@
-use axum::Router;
+use axum::{routing::get, Router};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 use time::PrimitiveDateTime;
+use utoipa::ToSchema;
 
 use crate::resources::{CursorPaginatable, HasSqlxQueryString, SqlxQueryString};
 
+mod accessibility;
+mod bundle;
+mod error_pages;
+mod links;
+mod meta;
+mod navigation;
 mod page;
+mod preview;
 mod render;
+mod robots;
+mod sitemap;
+mod templates;
+mod validation;
+
+pub(crate) use {
+    links::LinkChecker, meta::meta_errors, page::create_page, validation::element_errors,
+};
+
+pub use {
+    error_pages::{error_pages, not_found},
+    links::link_check_job,
+    page::import_legacy_specs,
+    preview::PreviewChannels,
+    sitemap::sitemap_job,
+    templates::TeraPool,
+};
 
 pub fn router() -> Router {
-    Router::new().merge(page::router())
+    Router::new()
+        .merge(page::router())
+        .merge(bundle::router())
+        .merge(navigation::router())
+        .merge(links::router())
+        .merge(preview::router())
+        .merge(accessibility::router())
+        .merge(robots::router())
+        .merge(templates::router())
+}
+
+/// The deployed site itself, outside the API.
+pub fn site_router() -> Router {
+    Router::new()
+        .route("/robots.txt", get(robots::serve_robots))
+        .route("/staff/*page", get(page::serve_protected_page))
+        .route("/*page", get(page::serve_deployed_page))
+}
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut openapi = page::openapi();
+    openapi.merge(accessibility::openapi());
+    openapi.merge(preview::openapi());
+    openapi.merge(robots::openapi());
+    openapi.merge(templates::openapi());
+    openapi
 }
 
 pub type DynamicPageData = Vec<DynamicPageElement>;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum HeaderSize {
     H1,
@@ -24,6 +74,15 @@ pub enum HeaderSize {
 }
 
 impl HeaderSize {
+    const fn level(&self) -> u8 {
+        match self {
+            Self::H1 => 1,
+            Self::H2 => 2,
+            Self::H3 => 3,
+            Self::H4 => 4,
+        }
+    }
+
     fn into_tag(self) -> (String, String) {
         let (open, close) = match self {
             Self::H1 => ("<h1>", "</h1>"),
@@ -36,7 +95,7 @@ impl HeaderSize {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
 pub enum TextModifier {
     Bold,
     Italic,
@@ -55,14 +114,14 @@ impl TextModifier {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TextComponent {
     modifiers: Vec<TextModifier>,
     link: Option<String>,
     content: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum DynamicPageElement {
     Header {
@@ -78,22 +137,34 @@ pub enum DynamicPageElement {
     },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum ListType {
     Ordered,
     Unordered,
 }
 
-#[derive(Serialize, Deserialize, Debug, FromRow)]
+#[derive(Serialize, Deserialize, Debug, FromRow, ToSchema)]
 pub struct DynamicPageMetadata {
     id: i32,
     name: String,
 
     created_at: PrimitiveDateTime,
     updated_at: PrimitiveDateTime,
+    created_by: Option<i32>,
+    updated_by: Option<i32>,
 
     modified: PageStatus,
+    layout: PageLayout,
+    visibility: PageVisibility,
+    /// The department it belongs to, which decides who can change it if
+    /// they only have `ManagePages` in some departments.
+    department: Option<i32>,
+    /// Whether search engines are asked to leave it out.
+    noindex: bool,
+    meta_description: Option<String>,
+    og_title: Option<String>,
+    og_image: Option<String>,
 }
 
 impl HasSqlxQueryString for DynamicPageMetadata {
@@ -105,6 +176,10 @@ pub struct DynamicPageMetadataQueryString {
     id: Option<i32>,
     name: Option<String>,
     modified: Option<PageStatus>,
+    layout: Option<PageLayout>,
+    visibility: Option<PageVisibility>,
+    department: Option<i32>,
+    noindex: Option<bool>,
 
     #[serde(rename = "created_at[gte]")]
     created_at_gte: Option<PrimitiveDateTime>,
@@ -135,6 +210,26 @@ impl SqlxQueryString for DynamicPageMetadataQueryString {
             builder.push_bind(modified);
         }
 
+        if let Some(ref layout) = self.layout {
+            builder.push(" AND layout = ");
+            builder.push_bind(layout);
+        }
+
+        if let Some(ref visibility) = self.visibility {
+            builder.push(" AND visibility = ");
+            builder.push_bind(visibility);
+        }
+
+        if let Some(department) = self.department {
+            builder.push(" AND department = ");
+            builder.push_bind(department);
+        }
+
+        if let Some(noindex) = self.noindex {
+            builder.push(" AND noindex = ");
+            builder.push_bind(noindex);
+        }
+
         if let Some(created_at_gte) = self.created_at_gte {
             builder.push(" AND created_at >= ");
             builder.push_bind(created_at_gte);
@@ -177,10 +272,62 @@ impl CursorPaginatable for DynamicPageMetadata {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
 #[sqlx(type_name = "page_status", rename_all = "lowercase")]
 pub enum PageStatus {
     Unmodified,
     New,
     Edited,
+    Archived,
+    Unpublished,
+}
+
+/// The templates in `pages/templates` a page can be built on.
+#[derive(
+    Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, Default, PartialEq, Eq, ToSchema,
+)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[sqlx(type_name = "page_layout", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PageLayout {
+    #[default]
+    Base,
+    Landing,
+    Minimal,
+}
+
+impl PageLayout {
+    pub const fn template(self) -> &'static str {
+        match self {
+            Self::Base => "base.html",
+            Self::Landing => "landing.html",
+            Self::Minimal => "minimal.html",
+        }
+    }
+}
+
+/// Who a deployed page is served to.
+#[derive(
+    Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, Default, PartialEq, Eq, ToSchema,
+)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[sqlx(type_name = "page_visibility", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PageVisibility {
+    #[default]
+    Public,
+    /// Only signed-in staff, through `/staff/...`.
+    Staff,
+}
+
+impl PageVisibility {
+    /// Where pages with this visibility are deployed. Only `pages/dist` is
+    /// served publicly.
+    pub const fn dist_root(self) -> &'static str {
+        match self {
+            Self::Public => "pages/dist",
+            Self::Staff => "pages/protected",
+        }
+    }
 }
@@ -0,0 +1,428 @@
+//! Basic WCAG checks on rendered page elements. Text can carry its own HTML,
+//! so these look at the markup each element renders to rather than the spec.
+//! They're warnings, not errors: none of them stop a page being saved.
+
+use axum::{routing::get, Extension, Json, Router};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    error::PhsError,
+};
+
+use super::{DynamicPageData, DynamicPageElement};
+
+/// Lowest contrast ratio WCAG AA allows for body text.
+const MIN_CONTRAST: f64 = 4.5;
+
+/// Link text that doesn't say where the link goes, lower case.
+const VAGUE_LINK_TEXT: &[&str] = &[
+    "click here",
+    "click",
+    "here",
+    "link",
+    "more",
+    "more info",
+    "read more",
+    "this link",
+];
+
+pub fn router() -> Router {
+    Router::new().route("/pages/accessibility-report", get(get_accessibility_report))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_accessibility_report))]
+struct AccessibilityApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    AccessibilityApi::openapi()
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    /// An image without an `alt` attribute (WCAG 1.1.1).
+    ImageAlt,
+    /// A link with nothing for a screen reader to read out (WCAG 2.4.4).
+    EmptyLink,
+    /// Link text like "click here" (WCAG 2.4.4).
+    VagueLink,
+    /// Headings that skip a level (WCAG 1.3.1).
+    HeadingOrder,
+    /// Text colour too close to its background (WCAG 1.4.3).
+    Contrast,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AccessibilityWarning {
+    /// Index of the element in the page's data.
+    element: usize,
+    check: Check,
+    message: String,
+}
+
+/// A page's warnings, in reports covering several pages.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PageAccessibility {
+    pub id: i32,
+    pub name: String,
+    pub warnings: Vec<AccessibilityWarning>,
+}
+
+/// Every saved page with something to fix, for the annual accessibility
+/// audit. Archived pages are left out.
+#[utoipa::path(
+    get,
+    path = "/pages/accessibility-report",
+    tag = "pages",
+    responses(
+        (status = 200, body = Vec<PageAccessibility>),
+        (status = 403, description = "Missing the `ManagePages` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn get_accessibility_report(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<Vec<PageAccessibility>>, PhsError> {
+    let pages = sqlx::query!(
+        r#"
+        SELECT id, name, data
+        FROM pages
+        WHERE data IS NOT NULL AND modified <> 'archived'::page_status
+        ORDER BY name, id
+        "#
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let mut report = Vec::new();
+    for page in pages {
+        let Some(data) = page.data else { continue };
+        let data = match serde_json::from_value::<DynamicPageData>(data) {
+            Ok(data) => data,
+            Err(error) => {
+                tracing::warn!(?error, id = page.id, "Skipping unreadable page spec");
+                continue;
+            }
+        };
+
+        let warnings = check_page(&data);
+        if !warnings.is_empty() {
+            report.push(PageAccessibility {
+                id: page.id,
+                name: page.name,
+                warnings,
+            });
+        }
+    }
+
+    Ok(Json(report))
+}
+
+/// Everything the checks find in a page, in element order.
+pub fn check_page(data: &DynamicPageData) -> Vec<AccessibilityWarning> {
+    let mut warnings = Vec::new();
+    // As in validation, the page's title stands in for a level 1 heading
+    let mut heading_level = 1;
+
+    for (i, element) in data.iter().enumerate() {
+        let html = DynamicPageElement::render(element.clone());
+        let mut warn = |check, message: String| {
+            warnings.push(AccessibilityWarning {
+                element: i,
+                check,
+                message,
+            });
+        };
+
+        let element_tags = tags(&html);
+        for (t, tag) in element_tags.iter().enumerate() {
+            if tag.closing {
+                continue;
+            }
+            let tag_attributes = attributes(tag.attributes);
+            let attribute = |name: &str| {
+                tag_attributes
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.as_str())
+            };
+
+            match tag.name.as_str() {
+                "img" if attribute("alt").is_none() => warn(
+                    Check::ImageAlt,
+                    format!(
+                        "Image `{}` has no alt text. Give it alt=\"\" if it's only decoration",
+                        attribute("src").unwrap_or_default()
+                    ),
+                ),
+                "a" => {
+                    let href = attribute("href").unwrap_or_default();
+                    let inner = element_tags[t + 1..]
+                        .iter()
+                        .find(|end| end.closing && end.name == "a")
+                        .map_or(&html[tag.end..], |end| &html[tag.end..end.start]);
+                    let text = text(inner);
+
+                    if text.is_empty() {
+                        let labelled = attribute("aria-label")
+                            .is_some_and(|l| !l.trim().is_empty())
+                            || tags(inner).iter().any(|img| {
+                                img.name == "img"
+                                    && attributes(img.attributes)
+                                        .iter()
+                                        .any(|(key, alt)| key == "alt" && !alt.trim().is_empty())
+                            });
+                        if !labelled {
+                            warn(Check::EmptyLink, format!("Link to `{href}` has no text"));
+                        }
+                    } else if VAGUE_LINK_TEXT.contains(&text.to_lowercase().as_str()) {
+                        warn(
+                            Check::VagueLink,
+                            format!("Link text `{text}` doesn't say where `{href}` goes"),
+                        );
+                    }
+                }
+                name => {
+                    if let Some(level) = heading_level_of(name) {
+                        if level > heading_level + 1 {
+                            warn(
+                                Check::HeadingOrder,
+                                format!("Heading skips from level {heading_level} to {level}"),
+                            );
+                        }
+                        heading_level = level;
+                    }
+                }
+            }
+
+            if let Some(style) = attribute("style") {
+                if let Some(message) = contrast_problem(style) {
+                    warn(Check::Contrast, message);
+                }
+            }
+        }
+    }
+
+    warnings
+}
+
+fn heading_level_of(name: &str) -> Option<u8> {
+    match name.as_bytes() {
+        [b'h', level @ b'1'..=b'6'] => Some(level - b'0'),
+        _ => None,
+    }
+}
+
+struct Tag<'a> {
+    /// Lower case.
+    name: String,
+    closing: bool,
+    /// Everything after the name, up to the `>`.
+    attributes: &'a str,
+    /// Byte offsets of the `<` and just after the `>`.
+    start: usize,
+    end: usize,
+}
+
+/// The tags in some HTML. Not a real parser, but the markup comes from our own
+/// renderer and editor, so it's predictable.
+fn tags(html: &str) -> Vec<Tag<'_>> {
+    let mut tags = Vec::new();
+    let mut from = 0;
+
+    while let Some(start) = html[from..].find('<').map(|i| from + i) {
+        let Some(close) = html[start..].find('>').map(|i| start + i) else {
+            break;
+        };
+        let inner = &html[start + 1..close];
+        let (closing, inner) = inner
+            .strip_prefix('/')
+            .map_or((false, inner), |inner| (true, inner));
+
+        let name_end = inner
+            .find(|c: char| c.is_whitespace() || c == '/')
+            .unwrap_or(inner.len());
+        let name = &inner[..name_end];
+
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            tags.push(Tag {
+                name: name.to_ascii_lowercase(),
+                closing,
+                attributes: &inner[name_end..],
+                start,
+                end: close + 1,
+            });
+        }
+
+        from = close + 1;
+    }
+
+    tags
+}
+
+/// A tag's attributes as lower case names and unquoted values. Attributes
+/// without a value get an empty one.
+fn attributes(source: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = source.trim_start();
+
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '/' | '>'))
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            // A stray `/` or `=`
+            rest = rest[1..].trim_start();
+            continue;
+        }
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let mut value = "";
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            (value, rest) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let quoted = &after[1..];
+                    quoted
+                        .find(quote)
+                        .map_or((quoted, ""), |end| (&quoted[..end], &quoted[end + 1..]))
+                }
+                _ => after.split_at(after.find(char::is_whitespace).unwrap_or(after.len())),
+            };
+            rest = rest.trim_start();
+        }
+
+        attributes.push((name, value.to_owned()));
+    }
+
+    attributes
+}
+
+/// What a screen reader would read out, near enough.
+fn text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    text.replace("&nbsp;", " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Why an inline style's colours are too close, when they can be worked out.
+/// Without a background colour, the text is taken to be on the layouts'
+/// white.
+fn contrast_problem(style: &str) -> Option<String> {
+    let mut foreground = None;
+    let mut background = None;
+
+    for declaration in style.split(';') {
+        let Some((property, value)) = declaration.split_once(':') else {
+            continue;
+        };
+        match property.trim().to_ascii_lowercase().as_str() {
+            "color" => foreground = parse_colour(value),
+            "background" | "background-color" => background = parse_colour(value),
+            _ => {}
+        }
+    }
+
+    let foreground = foreground?;
+    let ratio = contrast_ratio(foreground, background.unwrap_or([255, 255, 255]));
+    if ratio >= MIN_CONTRAST {
+        return None;
+    }
+
+    let on = background.map_or_else(|| "the default white background".to_owned(), hex);
+    Some(format!(
+        "Text colour {} on {on} has a contrast ratio of {ratio:.1}:1, below the {MIN_CONTRAST}:1 needed",
+        hex(foreground)
+    ))
+}
+
+/// `#rgb`, `#rrggbb`, `rgb(r, g, b)`, black or white. Anything else, such as
+/// a colour with transparency, can't be judged on its own.
+fn parse_colour(value: &str) -> Option<[u8; 3]> {
+    let value = value
+        .trim()
+        .trim_end_matches("!important")
+        .trim()
+        .to_ascii_lowercase();
+
+    if let Some(digits) = value.strip_prefix('#') {
+        let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+        return match digits.len() {
+            3 => {
+                let mut rgb = [0; 3];
+                for (i, digit) in digits.chars().enumerate() {
+                    rgb[i] = channel(&digit.to_string())? * 17;
+                }
+                Some(rgb)
+            }
+            6 => Some([
+                channel(digits.get(0..2)?)?,
+                channel(digits.get(2..4)?)?,
+                channel(digits.get(4..6)?)?,
+            ]),
+            _ => None,
+        };
+    }
+
+    if let Some(channels) = value
+        .strip_prefix("rgb(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let channels = channels
+            .split(',')
+            .map(|channel| channel.trim().parse::<u8>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        return <[u8; 3]>::try_from(channels).ok();
+    }
+
+    match value.as_str() {
+        "black" => Some([0, 0, 0]),
+        "white" => Some([255, 255, 255]),
+        _ => None,
+    }
+}
+
+/// As WCAG defines it, from 1:1 up to 21:1.
+fn contrast_ratio(a: [u8; 3], b: [u8; 3]) -> f64 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+fn luminance(rgb: [u8; 3]) -> f64 {
+    let [r, g, b] = rgb.map(|channel| {
+        let channel = f64::from(channel) / 255.0;
+        if channel <= 0.039_28 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    0.2126f64.mul_add(r, 0.7152f64.mul_add(g, 0.0722 * b))
+}
+
+fn hex(rgb: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
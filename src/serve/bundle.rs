@@ -0,0 +1,347 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::Query,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use slugify::slugify;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    error::{ErrorCode, PhsError},
+    events::{Notification, Notifier},
+    storage::SharedStorage,
+    validation::{FieldErrors, Validate, Validated},
+};
+
+use super::{
+    page::{discard_files, fragment_key},
+    render::Renderer,
+    validation::{page_issues, ValidationIssue},
+    DynamicPageData, PageLayout, PageVisibility,
+};
+
+/// Bumped whenever the bundle format changes incompatibly.
+const BUNDLE_VERSION: u32 = 1;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/pages/export", get(get_pages_export))
+        .route("/pages/import", post(post_pages_import))
+}
+
+/// Every page on a site, for moving content between installs. Pages refer to
+/// their parents by name, as IDs differ from one database to the next, and
+/// parents always come before their children.
+#[derive(Serialize, Deserialize, Debug)]
+struct PageBundle {
+    version: u32,
+    pages: Vec<BundledPage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BundledPage {
+    name: String,
+    parent: Option<String>,
+    layout: PageLayout,
+    visibility: PageVisibility,
+    data: DynamicPageData,
+}
+
+impl Validate for PageBundle {
+    fn validate(&self) -> FieldErrors {
+        if self.version != BUNDLE_VERSION {
+            return [ValidationIssue::whole_page(format!(
+                "Unsupported bundle version {}, expected {BUNDLE_VERSION}",
+                self.version
+            ))]
+            .into_iter()
+            .collect();
+        }
+
+        let mut issues = Vec::new();
+        let mut seen = HashSet::new();
+
+        for (i, page) in self.pages.iter().enumerate() {
+            // Names end up in storage keys, so must already be slugs
+            if page.name.is_empty() || slugify!(&page.name, separator = "_") != page.name {
+                issues.push(ValidationIssue::whole_page("Name isn't a valid slug").in_page(i));
+            }
+
+            if let Some(parent) = &page.parent {
+                // A parent from later in the bundle would make a loop possible
+                if self.pages[i..].iter().any(|later| &later.name == parent) {
+                    issues.push(
+                        ValidationIssue::whole_page("Parent must come before its children")
+                            .in_page(i),
+                    );
+                }
+            }
+
+            if !seen.insert(&page.name) {
+                issues.push(ValidationIssue::whole_page("Name appears more than once").in_page(i));
+            }
+
+            issues.extend(
+                page_issues(&page.data)
+                    .into_iter()
+                    .map(|issue| issue.in_page(i)),
+            );
+        }
+
+        issues.into_iter().collect()
+    }
+}
+
+/// Exports every page that isn't archived.
+#[instrument(skip(pool, _auth_session))]
+async fn get_pages_export(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<PageBundle>, PhsError> {
+    let rows = sqlx::query!(
+        r#"
+        WITH RECURSIVE tree AS (
+            SELECT id, 0 AS depth FROM pages WHERE parent_id IS NULL
+            UNION ALL
+            SELECT p.id, tree.depth + 1 FROM pages p
+            JOIN tree ON p.parent_id = tree.id
+        )
+        SELECT p.name, parent.name as "parent?", p.layout as "layout: PageLayout", p.visibility as "visibility: PageVisibility", p.data as "data!"
+        FROM pages p
+        JOIN tree USING (id)
+        LEFT JOIN pages parent ON parent.id = p.parent_id
+        WHERE p.modified <> 'archived'::page_status AND p.data IS NOT NULL
+        ORDER BY tree.depth, p.id
+        "#
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let pages = rows
+        .into_iter()
+        .map(|row| {
+            Ok(BundledPage {
+                name: row.name,
+                parent: row.parent,
+                layout: row.layout,
+                visibility: row.visibility,
+                data: serde_json::from_value(row.data)?,
+            })
+        })
+        .collect::<Result<Vec<_>, PhsError>>()?;
+
+    Ok(Json(PageBundle {
+        version: BUNDLE_VERSION,
+        pages,
+    }))
+}
+
+/// What to do with a bundled page whose name is already taken.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ConflictStrategy {
+    /// Import nothing if any name is taken.
+    #[default]
+    Fail,
+    /// Leave existing pages as they are.
+    Skip,
+    /// Replace the content and layout of existing pages. Where they sit and
+    /// who can see them are left alone, as changing those moves deployed
+    /// files; use the parent and visibility endpoints for that.
+    Overwrite,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportParams {
+    #[serde(default)]
+    on_conflict: ConflictStrategy,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct ImportReport {
+    created: Vec<String>,
+    updated: Vec<String>,
+    skipped: Vec<String>,
+}
+
+/// Recreates the pages in a bundle. Everything imported is left undeployed,
+/// ready to be reviewed and deployed as usual.
+#[instrument(skip(pool, storage, notifier, bundle, auth_session))]
+async fn post_pages_import(
+    auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(notifier): Extension<Notifier>,
+    Query(params): Query<ImportParams>,
+    Validated(bundle): Validated<PageBundle>,
+) -> Result<Json<ImportReport>, PhsError> {
+    let mut tx = pool.begin().await?;
+
+    let names = bundle
+        .pages
+        .iter()
+        .map(|page| page.name.clone())
+        .chain(bundle.pages.iter().filter_map(|page| page.parent.clone()))
+        .collect::<Vec<_>>();
+
+    // Locked, so nothing is renamed or archived out from under the import
+    let mut existing = sqlx::query!(
+        r#"SELECT name, id, modified = 'archived'::page_status as "archived!" FROM pages WHERE name = ANY ($1) FOR UPDATE"#,
+        &names
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.name, (row.id, row.archived)))
+    .collect::<HashMap<_, _>>();
+
+    if params.on_conflict == ConflictStrategy::Fail
+        && bundle
+            .pages
+            .iter()
+            .any(|page| existing.contains_key(&page.name))
+    {
+        return Err(PhsError::client(
+            ErrorCode::AlreadyExists,
+            "Some pages in the bundle already exist",
+        ));
+    }
+
+    let importer = auth_session.data().id();
+    let mut report = ImportReport::default();
+    // Fragments written so far, with what they replaced, to undo on failure
+    let mut written = Vec::<(String, Option<Vec<u8>>)>::new();
+
+    let result = async {
+        for page in bundle.pages {
+            let parent_id = resolve_parent(&existing, page.parent.as_deref())?;
+
+            let key = fragment_key(&page.name);
+            let value = serde_json::to_value(&page.data)?;
+
+            match existing.get(&page.name) {
+                // Archived pages keep their names, but can't be brought back this way
+                Some(&(_, true)) => {
+                    report.skipped.push(page.name);
+                    continue;
+                }
+                Some(_) if params.on_conflict == ConflictStrategy::Skip => {
+                    report.skipped.push(page.name);
+                    continue;
+                }
+                Some(&(id, false)) => {
+                    sqlx::query!(
+                        "UPDATE pages SET data = $2, layout = $3, modified = CASE WHEN modified = 'unpublished'::page_status THEN modified ELSE 'edited'::page_status END, updated_at = now(), updated_by = $4, draft = NULL, draft_saved_at = NULL WHERE id = $1",
+                        id,
+                        value,
+                        page.layout as PageLayout,
+                        importer
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+
+                    let previous = storage.get(&key).await?;
+                    written.push((key.clone(), previous));
+                    report.updated.push(page.name.clone());
+                }
+                None => {
+                    let id = sqlx::query_scalar!(
+                        "INSERT INTO pages (name, parent_id, modified, data, layout, visibility, created_by, updated_by) VALUES ($1, $2, 'new'::page_status, $3, $4, $5, $6, $6) RETURNING id",
+                        page.name,
+                        parent_id,
+                        value,
+                        page.layout as PageLayout,
+                        page.visibility as PageVisibility,
+                        importer
+                    )
+                    .fetch_one(&mut *tx)
+                    .await?;
+
+                    existing.insert(page.name.clone(), (id, false));
+                    written.push((key.clone(), None));
+                    report.created.push(page.name.clone());
+                }
+            }
+
+            storage
+                .put(
+                    &key,
+                    Renderer::render_fragment(page.layout, page.data).into_bytes(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = match result {
+        Ok(()) => tx.commit().await.map_err(PhsError::from),
+        Err(e) => Err(e),
+    } {
+        undo_fragments(&storage, written).await;
+        return Err(e);
+    }
+
+    tracing::info!(
+        created = report.created.len(),
+        updated = report.updated.len(),
+        skipped = report.skipped.len(),
+        "Pages imported"
+    );
+
+    notifier.notify(Notification::ImportFinished {
+        created: report.created.len(),
+        updated: report.updated.len(),
+        skipped: report.skipped.len(),
+        by: importer,
+    });
+
+    Ok(Json(report))
+}
+
+/// The ID of a bundled page's parent, which must exist by the time the page
+/// is imported.
+fn resolve_parent(
+    existing: &HashMap<String, (i32, bool)>,
+    parent: Option<&str>,
+) -> Result<Option<i32>, PhsError> {
+    let Some(parent) = parent else {
+        return Ok(None);
+    };
+
+    match existing.get(parent) {
+        Some(&(id, false)) => Ok(Some(id)),
+        _ => Err(PhsError::client(
+            ErrorCode::UnknownParent,
+            "A page's parent is neither in the bundle nor on this site",
+        )),
+    }
+}
+
+/// Puts back the fragments an import replaced and removes those it created.
+async fn undo_fragments(storage: &SharedStorage, written: Vec<(String, Option<Vec<u8>>)>) {
+    let mut created = Vec::new();
+
+    for (key, previous) in written {
+        match previous {
+            Some(previous) => {
+                if let Err(error) = storage.put(&key, previous).await {
+                    tracing::error!(?error, ?key, "Failed to restore page fragment");
+                }
+            }
+            None => created.push(key),
+        }
+    }
+
+    discard_files(&**storage, created.iter()).await;
+}
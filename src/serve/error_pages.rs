@@ -0,0 +1,56 @@
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+    Extension,
+};
+
+use crate::{
+    error::{ErrorCode, PhsError},
+    storage::SharedStorage,
+    versions, ServerConfig,
+};
+
+/// Catches requests no route matched.
+pub async fn not_found() -> PhsError {
+    PhsError::client(ErrorCode::NotFound, "No route matches this request")
+}
+
+/// Swaps the problem body of failed requests for the matching page from
+/// [`ServerConfig::error_pages`], once it's been deployed. API routes are left
+/// alone, since their clients never show the body to anyone.
+pub async fn error_pages(
+    Extension(storage): Extension<SharedStorage>,
+    Extension(config): Extension<ServerConfig>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let is_api = versions::api_path(req.uri().path()).is_some();
+    let res = next.run(req).await;
+
+    let page = match res.status() {
+        _ if is_api => None,
+        StatusCode::NOT_FOUND => config.error_pages.not_found.as_deref(),
+        status if status.is_server_error() => config.error_pages.server_error.as_deref(),
+        _ => None,
+    };
+
+    let Some(page) = page else {
+        return res;
+    };
+
+    match storage.get(&format!("pages/dist{page}.html")).await {
+        Ok(Some(html)) => {
+            let mut page = (res.status(), Html(html)).into_response();
+            page.headers_mut()
+                .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            page
+        }
+        Ok(None) => res,
+        Err(error) => {
+            tracing::warn!(?error, page, "Failed to load error page");
+            res
+        }
+    }
+}
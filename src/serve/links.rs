@@ -0,0 +1,565 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum::{
+    http::{header, request, HeaderName, Method, Request, Response, StatusCode, Uri},
+    routing::get,
+    Extension, Json, Router,
+};
+use futures_util::{stream, StreamExt};
+use http_body_util::{BodyExt, Empty, Full, Limited};
+use hyper::body::{Body, Bytes, Incoming};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use sqlx::PgPool;
+use time::PrimitiveDateTime;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::RwLock,
+    time::{timeout, Instant},
+};
+use tokio_rustls::{
+    rustls::{self, pki_types::ServerName, RootCertStore},
+    TlsConnector,
+};
+use tracing::instrument;
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    error::PhsError,
+    events::{Notification, Notifier},
+    ServerConfig, ServerSettings,
+};
+
+use super::{DynamicPageData, DynamicPageElement};
+
+/// How often every link is checked.
+const CHECK_INTERVAL: Duration = Duration::from_hours(24);
+
+/// How long after startup the first check runs, so restarts don't each
+/// trigger a crawl straight away.
+const STARTUP_DELAY: Duration = Duration::from_mins(5);
+
+/// How many links are checked at once.
+const CONCURRENCY: usize = 8;
+
+/// How long a single request, including connecting, may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAX_REDIRECTS: usize = 5;
+
+const USER_AGENT: &str = concat!("phs_backend-link-checker/", env!("CARGO_PKG_VERSION"));
+
+pub fn router() -> Router {
+    Router::new().route("/pages/link-report", get(get_link_report))
+}
+
+/// Where a link was found.
+#[derive(Serialize, Debug, sqlx::Type, Clone, Copy, PartialEq, Eq, Hash)]
+#[sqlx(type_name = "link_source", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum LinkSource {
+    Page,
+    Post,
+}
+
+impl LinkSource {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Page => "page",
+            Self::Post => "post",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct LinkFailure {
+    id: i32,
+    source: LinkSource,
+    source_id: i32,
+    /// The page's name or the post's title.
+    source_title: Option<String>,
+    url: String,
+    status: Option<i16>,
+    error: String,
+    first_failed_at: PrimitiveDateTime,
+    last_checked_at: PrimitiveDateTime,
+}
+
+/// Every link that failed the last check, longest broken first.
+#[instrument(skip(pool, _auth_session))]
+async fn get_link_report(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<Vec<LinkFailure>>, PhsError> {
+    let failures = sqlx::query_as!(
+        LinkFailure,
+        r#"
+        SELECT f.id, f.source as "source: LinkSource", f.source_id,
+            COALESCE(p.name::text, posts.title::text) as source_title,
+            f.url, f.status, f.error, f.first_failed_at, f.last_checked_at
+        FROM link_failures f
+        LEFT JOIN pages p ON f.source = 'page'::link_source AND p.id = f.source_id
+        LEFT JOIN posts ON f.source = 'post'::link_source AND posts.id = f.source_id
+        ORDER BY f.first_failed_at, f.id
+        "#
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(failures))
+}
+
+/// Checks every link in page specs and posts, replacing the contents of
+/// `link_failures` with whatever turns out to be broken, and returns how many
+/// are.
+pub async fn check_links(pool: &PgPool, checker: &LinkChecker) -> Result<usize, PhsError> {
+    let mut sources = HashMap::<String, Vec<(LinkSource, i32)>>::new();
+
+    let pages = sqlx::query!(
+        "SELECT id, data FROM pages WHERE data IS NOT NULL AND modified <> 'archived'::page_status"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for page in pages {
+        let Some(data) = page.data else { continue };
+        let data = match serde_json::from_value::<DynamicPageData>(data) {
+            Ok(data) => data,
+            Err(error) => {
+                tracing::warn!(?error, id = page.id, "Skipping unreadable page spec");
+                continue;
+            }
+        };
+
+        for link in page_links(&data) {
+            sources
+                .entry(link.to_owned())
+                .or_default()
+                .push((LinkSource::Page, page.id));
+        }
+    }
+
+    let posts = sqlx::query!("SELECT id, content FROM posts WHERE deleted_at IS NULL")
+        .fetch_all(pool)
+        .await?;
+
+    for post in posts {
+        for link in html_links(&post.content) {
+            sources
+                .entry(link.to_owned())
+                .or_default()
+                .push((LinkSource::Post, post.id));
+        }
+    }
+
+    sources.retain(|url, _| checkable(url));
+
+    let results = stream::iter(sources)
+        .map(|(url, found_in)| async move {
+            let failure = checker.check(&url).await;
+            (url, found_in, failure)
+        })
+        .buffer_unordered(CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut kinds = Vec::new();
+    let mut ids = Vec::new();
+    let mut urls = Vec::new();
+    let mut statuses = Vec::new();
+    let mut errors = Vec::new();
+
+    for (url, found_in, failure) in results {
+        let Some((status, error)) = failure else {
+            continue;
+        };
+
+        for (source, id) in found_in {
+            kinds.push(source.as_str().to_owned());
+            ids.push(id);
+            urls.push(url.clone());
+            statuses.push(status.map(|s| i16::try_from(s.as_u16()).unwrap_or(i16::MAX)));
+            errors.push(error.clone());
+        }
+    }
+
+    tracing::info!(broken = urls.len(), "Finished checking links");
+
+    // `now()` is fixed for the whole transaction, so anything not touched by
+    // this run is older and no longer broken
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO link_failures (source, source_id, url, status, error)
+        SELECT source::link_source, source_id, url, status, error
+        FROM UNNEST($1::text[], $2::int[], $3::text[], $4::smallint[], $5::text[])
+            AS f(source, source_id, url, status, error)
+        ON CONFLICT (source, source_id, url) DO UPDATE
+        SET status = EXCLUDED.status, error = EXCLUDED.error, last_checked_at = now()
+        "#,
+        &kinds,
+        &ids,
+        &urls,
+        &statuses as &[Option<i16>],
+        &errors,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM link_failures WHERE last_checked_at < now()")
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(urls.len())
+}
+
+/// Checks links every [`CHECK_INTERVAL`] for the lifetime of the server,
+/// unless switched off in the settings.
+pub async fn link_check_job(
+    pool: PgPool,
+    config: ServerConfig,
+    settings: Arc<RwLock<ServerSettings>>,
+    notifier: Notifier,
+) {
+    let checker = match LinkChecker::new(&config.site_url) {
+        Ok(checker) => checker,
+        Err(error) => {
+            tracing::error!(?error, "Link checker couldn't start");
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval_at(Instant::now() + STARTUP_DELAY, CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if !settings.read().await.features.link_checker {
+            continue;
+        }
+
+        match check_links(&pool, &checker).await {
+            Ok(broken) => notifier.notify(Notification::LinkCheckFinished { broken }),
+            Err(error) => tracing::error!(?error, "Failed to check links"),
+        }
+    }
+}
+
+fn page_links(data: &DynamicPageData) -> Vec<&str> {
+    data.iter()
+        .flat_map(|element| match element {
+            DynamicPageElement::Header { .. } => Vec::new(),
+            DynamicPageElement::Text { components } => components.iter().collect(),
+            DynamicPageElement::List { items, .. } => items.iter().flatten().collect(),
+        })
+        .filter_map(|component| component.link.as_deref())
+        .collect()
+}
+
+/// The targets of `href` attributes in post HTML. Not a real parser, but
+/// posts come from our own editor so the markup is predictable.
+fn html_links(html: &str) -> Vec<&str> {
+    let mut links = Vec::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find("href=") {
+        rest = &rest[start + "href=".len()..];
+
+        let Some(quote) = rest.chars().next().filter(|c| matches!(c, '"' | '\'')) else {
+            continue;
+        };
+        rest = &rest[1..];
+
+        let Some(end) = rest.find(quote) else { break };
+        links.push(rest[..end].trim());
+        rest = &rest[end + 1..];
+    }
+
+    links
+}
+
+/// Whether a link points somewhere we can request. Anchors, `mailto:` and the
+/// like are left alone.
+fn checkable(url: &str) -> bool {
+    (url.starts_with('/') && !url.starts_with("//"))
+        || url.starts_with("http://")
+        || url.starts_with("https://")
+}
+
+pub struct LinkChecker {
+    site_url: String,
+    tls: TlsConnector,
+}
+
+impl LinkChecker {
+    /// # Errors
+    ///
+    /// Fails if a TLS client can't be configured.
+    pub fn new(site_url: &str) -> Result<Self, rustls::Error> {
+        let roots = webpki_roots::TLS_SERVER_ROOTS
+            .iter()
+            .cloned()
+            .collect::<RootCertStore>();
+        let tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        Ok(Self {
+            site_url: site_url.trim_end_matches('/').to_owned(),
+            tls: TlsConnector::from(Arc::new(tls)),
+        })
+    }
+
+    /// Follows `url` through any redirects, returning the status, if there
+    /// was one, and a description of the problem when the link is broken.
+    async fn check(&self, url: &str) -> Option<(Option<StatusCode>, String)> {
+        // Site-relative links are checked against our own public address
+        let mut target = if url.starts_with('/') {
+            format!("{}{url}", self.site_url)
+        } else {
+            url.to_owned()
+        };
+
+        for _ in 0..=MAX_REDIRECTS {
+            let uri = match target.parse::<Uri>() {
+                Ok(uri) if uri.host().is_some() => uri,
+                _ => return Some((None, format!("Invalid URL `{target}`"))),
+            };
+
+            // Some servers refuse HEAD outright, so fall back to GET
+            let response = match self.request(Method::HEAD, &uri).await {
+                Ok((StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED, _)) => {
+                    self.request(Method::GET, &uri).await
+                }
+                response => response,
+            };
+
+            let (status, location) = match response {
+                Ok(response) => response,
+                Err(error) => return Some((None, error)),
+            };
+
+            if status.is_redirection() {
+                let Some(location) = location else {
+                    return Some((Some(status), "Redirect without a location".into()));
+                };
+                target = resolve(&uri, &location);
+                continue;
+            }
+
+            return if status.is_success() {
+                None
+            } else {
+                Some((
+                    Some(status),
+                    status
+                        .canonical_reason()
+                        .unwrap_or("Unexpected status")
+                        .to_owned(),
+                ))
+            };
+        }
+
+        Some((None, "Too many redirects".into()))
+    }
+
+    /// Downloads `url`, following any redirects, for bringing files across
+    /// from another site. Bodies longer than `limit` bytes are refused.
+    pub async fn fetch(&self, url: &str, limit: usize) -> Result<Vec<u8>, String> {
+        let mut target = url.to_owned();
+
+        for _ in 0..=MAX_REDIRECTS {
+            let uri = match target.parse::<Uri>() {
+                Ok(uri) if uri.host().is_some() => uri,
+                _ => return Err(format!("Invalid URL `{target}`")),
+            };
+
+            let res = self.send(Method::GET, &uri).await?;
+            let status = res.status();
+
+            if status.is_redirection() {
+                let location = location(&res).ok_or("Redirect without a location")?;
+                target = resolve(&uri, &location);
+                continue;
+            }
+            if !status.is_success() {
+                return Err(format!("{url} returned {status}"));
+            }
+
+            let body = timeout(
+                REQUEST_TIMEOUT,
+                Limited::new(res.into_body(), limit).collect(),
+            )
+            .await
+            .map_err(|_| "Timed out".to_owned())?
+            .map_err(|e| format!("Couldn't download {url}: {e}"))?;
+
+            return Ok(body.to_bytes().to_vec());
+        }
+
+        Err("Too many redirects".into())
+    }
+
+    /// Sends `body` to `url`, for calling other services' APIs. Redirects
+    /// aren't followed. Returns the status and the response body, which is
+    /// refused if it's longer than `limit` bytes.
+    pub async fn post(
+        &self,
+        url: &str,
+        headers: &[(HeaderName, &str)],
+        body: Vec<u8>,
+        limit: usize,
+    ) -> Result<(StatusCode, Vec<u8>), String> {
+        let uri = match url.parse::<Uri>() {
+            Ok(uri) if uri.host().is_some() => uri,
+            _ => return Err(format!("Invalid URL `{url}`")),
+        };
+
+        let req = headers
+            .iter()
+            .fold(builder(Method::POST, &uri), |req, (name, value)| {
+                req.header(name, *value)
+            })
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| e.to_string())?;
+
+        let res = self.dispatch(&uri, req).await?;
+        let status = res.status();
+
+        let body = timeout(
+            REQUEST_TIMEOUT,
+            Limited::new(res.into_body(), limit).collect(),
+        )
+        .await
+        .map_err(|_| "Timed out".to_owned())?
+        .map_err(|e| format!("Couldn't read the response from {url}: {e}"))?;
+
+        Ok((status, body.to_bytes().to_vec()))
+    }
+
+    /// Sends one request, returning the status and any `Location` header.
+    async fn request(
+        &self,
+        method: Method,
+        uri: &Uri,
+    ) -> Result<(StatusCode, Option<String>), String> {
+        let res = self.send(method, uri).await?;
+        Ok((res.status(), location(&res)))
+    }
+
+    /// Sends one request without a body, returning once the response's
+    /// headers arrive.
+    async fn send(&self, method: Method, uri: &Uri) -> Result<Response<Incoming>, String> {
+        let req = builder(method, uri)
+            .body(Empty::<Bytes>::new())
+            .map_err(|e| e.to_string())?;
+
+        self.dispatch(uri, req).await
+    }
+
+    /// Connects to `uri`'s host and sends `req`, returning once the
+    /// response's headers arrive.
+    async fn dispatch<B>(&self, uri: &Uri, req: Request<B>) -> Result<Response<Incoming>, String>
+    where
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let https = uri.scheme_str() == Some("https");
+        let host = uri.host().unwrap_or_default();
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+        let send = async {
+            let tcp = TcpStream::connect((host, port))
+                .await
+                .map_err(|e| format!("Couldn't connect: {e}"))?;
+
+            if https {
+                let server_name = ServerName::try_from(host.to_owned())
+                    .map_err(|e| format!("Invalid host: {e}"))?;
+                let tls = self
+                    .tls
+                    .connect(server_name, tcp)
+                    .await
+                    .map_err(|e| format!("TLS error: {e}"))?;
+                exchange(tls, req).await
+            } else {
+                exchange(tcp, req).await
+            }
+        };
+
+        timeout(REQUEST_TIMEOUT, send)
+            .await
+            .map_err(|_| "Timed out".to_owned())?
+    }
+}
+
+/// A request for `uri`, with the headers every request gets.
+fn builder(method: Method, uri: &Uri) -> request::Builder {
+    Request::builder()
+        .method(method)
+        .uri(uri.path_and_query().map_or("/", |p| p.as_str()))
+        .header(
+            header::HOST,
+            uri.authority()
+                .map_or(uri.host().unwrap_or_default(), |authority| {
+                    authority.as_str()
+                }),
+        )
+        .header(header::USER_AGENT, USER_AGENT)
+}
+
+async fn exchange<S, B>(stream: S, req: Request<B>) -> Result<Response<Incoming>, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Closed once the response, and its body if it's read, is dropped
+    tokio::spawn(connection);
+
+    sender.send_request(req).await.map_err(|e| e.to_string())
+}
+
+fn location<B>(res: &Response<B>) -> Option<String> {
+    res.headers()
+        .get(header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(ToOwned::to_owned)
+}
+
+/// Resolves a `Location` header against the URL that returned it.
+fn resolve(base: &Uri, location: &str) -> String {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        return location.to_owned();
+    }
+
+    let scheme = base.scheme_str().unwrap_or("http");
+    let authority = base.authority().map_or("", |authority| authority.as_str());
+
+    if location.starts_with("//") {
+        format!("{scheme}:{location}")
+    } else if location.starts_with('/') {
+        format!("{scheme}://{authority}{location}")
+    } else {
+        let directory = base
+            .path()
+            .rsplit_once('/')
+            .map_or("", |(directory, _)| directory);
+        format!("{scheme}://{authority}{directory}/{location}")
+    }
+}
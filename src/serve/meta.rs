@@ -0,0 +1,119 @@
+//! What a page tells search engines and sites showing a preview of a link to
+//! it, e.g. Facebook, as `<meta>` tags in its `<head>`. Posts carry the same
+//! fields for whatever renders them.
+
+use serde::Serialize;
+use sqlx::{prelude::FromRow, PgExecutor};
+
+use crate::{error::PhsError, validation::FieldErrors};
+
+/// Longest a description can be, as stored. Search engines cut them off well
+/// before this anyway.
+const MAX_DESCRIPTION_LENGTH: usize = 300;
+
+/// Longest a preview title can be, as stored.
+const MAX_OG_TITLE_LENGTH: usize = 200;
+
+/// Longest a preview image's URL can be, as stored.
+const MAX_OG_IMAGE_LENGTH: usize = 2048;
+
+/// Checks the fields pages and posts share, naming them as the bodies do.
+pub(crate) fn meta_errors(
+    errors: &mut FieldErrors,
+    description: Option<&str>,
+    og_title: Option<&str>,
+    og_image: Option<&str>,
+) {
+    if let Some(description) = description {
+        errors.max_chars("meta_description", description, MAX_DESCRIPTION_LENGTH);
+    }
+
+    if let Some(og_title) = og_title {
+        errors.not_blank("og_title", og_title);
+        errors.max_chars("og_title", og_title, MAX_OG_TITLE_LENGTH);
+    }
+
+    if let Some(og_image) = og_image {
+        // Sites fetching previews need somewhere they can reach on their own
+        errors.check(
+            og_image.starts_with("https://")
+                || (og_image.starts_with('/') && !og_image.starts_with("//")),
+            "og_image",
+            "Must be an https:// URL or a path on this site",
+        );
+        errors.max_chars("og_image", og_image, MAX_OG_IMAGE_LENGTH);
+    }
+}
+
+/// A page's tags, as stored with it.
+#[derive(Debug, Default, FromRow)]
+pub(super) struct PageMeta {
+    pub noindex: bool,
+    pub meta_description: Option<String>,
+    pub og_title: Option<String>,
+    pub og_image: Option<String>,
+}
+
+impl PageMeta {
+    pub async fn of(executor: impl PgExecutor<'_>, id: i32) -> Result<Self, PhsError> {
+        sqlx::query_as!(
+            Self,
+            "SELECT noindex, meta_description, og_title, og_image FROM pages WHERE id = $1",
+            id
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Adds `noindex`, and the tags as `meta`, with the title falling back to
+    /// the page's and URLs made absolute, as previews need.
+    ///
+    /// Fragments are rendered without autoescaping, so everything in `meta` is
+    /// escaped here, and templates should use it with `| safe`.
+    pub fn add_to_context(
+        &self,
+        context: &mut tera::Context,
+        title: &str,
+        url: &str,
+        site_url: &str,
+    ) {
+        let site_url = site_url.trim_end_matches('/');
+        let absolute = |url: &str| {
+            if url.starts_with('/') {
+                escape(&format!("{site_url}{url}"))
+            } else {
+                escape(url)
+            }
+        };
+
+        context.insert("noindex", &self.noindex);
+        context.insert(
+            "meta",
+            &ContextMeta {
+                description: self.meta_description.as_deref().map(escape),
+                title: escape(self.og_title.as_deref().unwrap_or(title)),
+                image: self.og_image.as_deref().map(absolute),
+                url: absolute(url),
+            },
+        );
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ContextMeta {
+    description: Option<String>,
+    title: String,
+    image: Option<String>,
+    url: String,
+}
+
+/// Escapes text for an attribute value. Unlike [`tera::escape_html`] this
+/// leaves slashes alone, so URLs stay readable.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
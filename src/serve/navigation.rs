@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgConnection, PgPool};
+use tracing::instrument;
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    db::Db,
+    error::{ErrorCode, PhsError},
+};
+
+use super::page::page_url;
+
+/// Menus deeper than this don't fit in the topbar template.
+const MAX_DEPTH: usize = 3;
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/navigation",
+            get(get_navigation).post(create_navigation_item),
+        )
+        .route(
+            "/navigation/:id",
+            put(put_navigation_item).delete(delete_navigation_item),
+        )
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "navigation_link", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum NavigationLinkType {
+    Page,
+    Post,
+    External,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromRow)]
+pub struct NavigationItem {
+    id: i32,
+    parent_id: Option<i32>,
+
+    label: String,
+    link_type: NavigationLinkType,
+    page_id: Option<i32>,
+    post_id: Option<i32>,
+    url: Option<String>,
+
+    position: i32,
+}
+
+/// A resolved menu entry, as handed to the SPA and the Tera templates.
+#[derive(Serialize, Debug)]
+pub struct NavigationNode {
+    id: i32,
+    label: String,
+    href: String,
+    children: Vec<Self>,
+}
+
+/// Loads the whole menu as a tree. Links to archived or unpublished pages, or
+/// to posts in the trash, are left out, along with anything nested under them.
+pub async fn navigation_tree(pool: &PgPool) -> Result<Vec<NavigationNode>, PhsError> {
+    let rows = sqlx::query!(
+        r#"
+        WITH RECURSIVE paths AS (
+            SELECT id, ARRAY[name]::text[] AS path FROM pages WHERE parent_id IS NULL
+            UNION ALL
+            SELECT p.id, paths.path || p.name::text FROM pages p
+            JOIN paths ON p.parent_id = paths.id
+        )
+        SELECT n.id,
+            n.parent_id,
+            n.label,
+            n.link_type as "link_type: NavigationLinkType",
+            n.post_id,
+            n.url,
+            paths.path as "page_path?"
+        FROM navigation n
+        LEFT JOIN pages p ON p.id = n.page_id
+        LEFT JOIN posts ON posts.id = n.post_id
+        LEFT JOIN paths ON paths.id = n.page_id
+        WHERE (p.modified IS NULL
+            OR (p.modified <> ALL (ARRAY['archived', 'unpublished']::page_status[])
+                AND p.visibility = 'public'::page_visibility))
+            AND posts.deleted_at IS NULL
+        ORDER BY n.position, n.id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut children: HashMap<Option<i32>, Vec<NavigationNode>> = HashMap::new();
+
+    for row in rows {
+        let href = match row.link_type {
+            NavigationLinkType::Page => row.page_path.as_deref().map(page_url),
+            NavigationLinkType::Post => row.post_id.map(|id| format!("/posts/{id}")),
+            NavigationLinkType::External => row.url,
+        };
+
+        let Some(href) = href else {
+            continue;
+        };
+
+        children
+            .entry(row.parent_id)
+            .or_default()
+            .push(NavigationNode {
+                id: row.id,
+                label: row.label,
+                href,
+                children: Vec::new(),
+            });
+    }
+
+    let mut roots = children.remove(&None).unwrap_or_default();
+    for root in &mut roots {
+        attach(root, &mut children);
+    }
+
+    Ok(roots)
+}
+
+fn attach(node: &mut NavigationNode, children: &mut HashMap<Option<i32>, Vec<NavigationNode>>) {
+    node.children = children.remove(&Some(node.id)).unwrap_or_default();
+    for child in &mut node.children {
+        attach(child, children);
+    }
+}
+
+#[instrument(skip(db))]
+async fn get_navigation(
+    Extension(db): Extension<Db>,
+) -> Result<Json<Vec<NavigationNode>>, PhsError> {
+    navigation_tree(db.read()).await.map(Json)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum NavigationTarget {
+    Page { page_id: i32 },
+    Post { post_id: i32 },
+    External { url: String },
+}
+
+/// The `link_type`, `page_id`, `post_id` and `url` columns of a navigation row.
+type TargetColumns = (NavigationLinkType, Option<i32>, Option<i32>, Option<String>);
+
+impl NavigationTarget {
+    fn into_columns(self) -> Result<TargetColumns, PhsError> {
+        Ok(match self {
+            Self::Page { page_id } => (NavigationLinkType::Page, Some(page_id), None, None),
+            Self::Post { post_id } => (NavigationLinkType::Post, None, Some(post_id), None),
+            Self::External { url } => {
+                // Rendered straight into an href, so keep out `javascript:` and friends
+                if !(url.starts_with("https://") || url.starts_with("http://")) {
+                    return Err(PhsError::client(
+                        ErrorCode::InvalidLink,
+                        "External links must be http or https URLs",
+                    ));
+                }
+
+                (NavigationLinkType::External, None, None, Some(url))
+            }
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct NavigationItemBody {
+    parent_id: Option<i32>,
+    label: String,
+    #[serde(flatten)]
+    target: NavigationTarget,
+    #[serde(default)]
+    position: i32,
+}
+
+/// Checks that placing `id` (or a new item, if `None`) under `parent_id` keeps
+/// the menu a tree no deeper than [`MAX_DEPTH`].
+async fn validate_parent(
+    conn: &mut PgConnection,
+    id: Option<i32>,
+    parent_id: Option<i32>,
+) -> Result<(), PhsError> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+
+    let ancestors = sqlx::query_scalar!(
+        r#"
+        WITH RECURSIVE ancestors AS (
+            SELECT id, parent_id FROM navigation WHERE id = $1
+            UNION ALL
+            SELECT n.id, n.parent_id FROM navigation n
+            JOIN ancestors a ON n.id = a.parent_id
+        )
+        SELECT id as "id!" FROM ancestors
+        "#,
+        parent_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if ancestors.is_empty() {
+        return Err(PhsError::client(
+            ErrorCode::ParentNotFound,
+            "No navigation item exists with this parent ID",
+        ));
+    }
+
+    let subtree_height = if let Some(id) = id {
+        if ancestors.contains(&id) {
+            return Err(PhsError::client(
+                ErrorCode::InvalidHierarchy,
+                "A navigation item can't be nested under itself",
+            ));
+        }
+
+        sqlx::query_scalar!(
+            r#"
+            WITH RECURSIVE descendants AS (
+                SELECT id, 1 AS depth FROM navigation WHERE id = $1
+                UNION ALL
+                SELECT n.id, d.depth + 1 FROM navigation n
+                JOIN descendants d ON n.parent_id = d.id
+            )
+            SELECT MAX(depth) as "depth!" FROM descendants
+            "#,
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?
+    } else {
+        1
+    };
+
+    if ancestors.len() + usize::try_from(subtree_height).unwrap_or(usize::MAX) > MAX_DEPTH {
+        return Err(PhsError::client(
+            ErrorCode::InvalidHierarchy,
+            "Navigation menus can only be nested three levels deep",
+        ));
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(pool, _auth_session))]
+async fn create_navigation_item(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Json(body): Json<NavigationItemBody>,
+) -> Result<Json<NavigationItem>, PhsError> {
+    let (link_type, page_id, post_id, url) = body.target.into_columns()?;
+
+    let mut tx = pool.begin().await?;
+
+    validate_parent(&mut tx, None, body.parent_id).await?;
+
+    let item = sqlx::query_as!(
+        NavigationItem,
+        r#"
+        INSERT INTO navigation (parent_id, label, link_type, page_id, post_id, url, position)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id,
+            parent_id,
+            label,
+            link_type as "link_type: _",
+            page_id,
+            post_id,
+            url,
+            position
+        "#,
+        body.parent_id,
+        body.label,
+        link_type as NavigationLinkType,
+        page_id,
+        post_id,
+        url,
+        body.position
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(item))
+}
+
+#[instrument(skip(pool, _auth_session))]
+async fn put_navigation_item(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Json(body): Json<NavigationItemBody>,
+) -> Result<Json<NavigationItem>, PhsError> {
+    let (link_type, page_id, post_id, url) = body.target.into_columns()?;
+
+    let mut tx = pool.begin().await?;
+
+    validate_parent(&mut tx, Some(id), body.parent_id).await?;
+
+    let item = sqlx::query_as!(
+        NavigationItem,
+        r#"
+        UPDATE navigation
+        SET parent_id = $1,
+            label = $2,
+            link_type = $3,
+            page_id = $4,
+            post_id = $5,
+            url = $6,
+            position = $7
+        WHERE id = $8
+        RETURNING id,
+            parent_id,
+            label,
+            link_type as "link_type: _",
+            page_id,
+            post_id,
+            url,
+            position
+        "#,
+        body.parent_id,
+        body.label,
+        link_type as NavigationLinkType,
+        page_id,
+        post_id,
+        url,
+        body.position,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(item))
+}
+
+/// Deletes a menu item along with everything nested under it.
+#[instrument(skip(pool, _auth_session))]
+async fn delete_navigation_item(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    sqlx::query!("DELETE FROM navigation WHERE id = $1", id)
+        .execute(&pool)
+        .await?;
+
+    Ok(())
+}
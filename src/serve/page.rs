@@ -1,238 +1,1920 @@
-use std::{path::PathBuf, sync::Arc};
+use std::sync::Arc;
 
 use axum::{
-    extract::{Path, Query},
-    routing::{post, put},
+    async_trait,
+    extract::{OriginalUri, Path, Query},
+    http::HeaderMap,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post, put},
     Extension, Json, Router,
 };
-use serde::Deserialize;
-use sqlx::{prelude::FromRow, PgPool};
-use tera::Tera;
-use tokio::{
-    fs::File,
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
-    sync::Mutex,
-};
+use futures_util::future;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgConnection, PgExecutor, PgPool};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::sync::RwLock;
 use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
 use crate::{
-    auth::{AuthSession, Permission, RequirePermission},
-    error::PhsError,
-    resources::{CursorOptions, CursorResponse, HasSqlxQueryString},
-    serve::PageStatus,
+    auth::{AuthSession, Permission, PermissionScope, ScopedPermission},
+    consent,
+    db::{Db, Tx},
+    error::{ErrorCode, PhsError},
+    events::{Notification, Notifier},
+    resources::{CursorOptions, CursorResponse, Deletable, HasSqlxQueryString, TrashKind},
+    serve::{DynamicPageElement, PageLayout, PageStatus, PageVisibility},
+    storage::{
+        precompress, precompressed_response, stored_response, SharedStorage, Storage, PRECOMPRESSED,
+    },
+    validation::{FieldErrors, Validate, Validated},
+    ServerConfig, ServerSettings,
 };
 
-use super::{render::Renderer, DynamicPageData, DynamicPageMetadata};
+use super::{
+    accessibility::{check_page, AccessibilityWarning, PageAccessibility},
+    meta::{meta_errors, PageMeta},
+    navigation::{navigation_tree, NavigationNode},
+    preview::PreviewChannels,
+    render::Renderer,
+    sitemap::generate_sitemap,
+    templates::TeraPool,
+    validation::page_issues,
+    DynamicPageData, DynamicPageMetadata,
+};
 
 use slugify::slugify;
 
+/// Longest a page's name can be, as stored.
+const MAX_NAME_LENGTH: usize = 255;
+
 pub fn router() -> Router {
     Router::new()
-        .route("/v1/pages", post(post_new_dynamic_page))
-        .route("/v1/pages/:id", put(put_dynamic_page))
-        .route("/v1/deploy", post(post_deploy_dynamic_pages))
+        .route("/pages", post(post_new_dynamic_page))
+        .route(
+            "/pages/:id",
+            get(get_dynamic_page)
+                .put(put_dynamic_page)
+                .delete(delete_dynamic_page),
+        )
+        .route("/pages/:id/draft", put(put_dynamic_page_draft))
+        .route("/pages/:id/rename", post(post_rename_dynamic_page))
+        .route("/pages/:id/parent", put(put_dynamic_page_parent))
+        .route("/pages/:id/unpublish", post(post_unpublish_dynamic_page))
+        .route("/pages/:id/restore", post(post_restore_dynamic_page))
+        .route("/pages/:id/duplicate", post(post_duplicate_dynamic_page))
+        .route("/pages/:id/layout", put(put_dynamic_page_layout))
+        .route("/pages/:id/visibility", put(put_dynamic_page_visibility))
+        .route("/pages/:id/department", put(put_dynamic_page_department))
+        .route("/pages/:id/indexing", put(put_dynamic_page_indexing))
+        .route("/pages/:id/meta", put(put_dynamic_page_meta))
+        .route("/deploy", post(post_deploy_dynamic_pages))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    post_new_dynamic_page,
+    get_dynamic_page,
+    put_dynamic_page,
+    delete_dynamic_page,
+    put_dynamic_page_draft,
+    post_rename_dynamic_page,
+    put_dynamic_page_parent,
+    post_unpublish_dynamic_page,
+    post_restore_dynamic_page,
+    post_duplicate_dynamic_page,
+    put_dynamic_page_layout,
+    put_dynamic_page_visibility,
+    put_dynamic_page_department,
+    put_dynamic_page_indexing,
+    put_dynamic_page_meta,
+    post_deploy_dynamic_pages
+))]
+struct PageApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    PageApi::openapi()
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 struct PostNewPage {
     unsafe_name: String,
+    parent_id: Option<i32>,
+    #[serde(default)]
+    layout: PageLayout,
+    #[serde(default)]
+    visibility: PageVisibility,
+    /// Needed if you only have `ManagePages` in some departments.
+    department: Option<i32>,
+    #[schema(value_type = Vec<DynamicPageElement>)]
     data: DynamicPageData,
 }
 
-#[instrument(skip(pool, _auth_session))]
+impl Validate for PostNewPage {
+    fn validate(&self) -> FieldErrors {
+        let mut errors: FieldErrors = page_issues(&self.data).into_iter().collect();
+        errors.not_blank("unsafe_name", &self.unsafe_name);
+        errors.max_chars("unsafe_name", &self.unsafe_name, MAX_NAME_LENGTH);
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/pages",
+    tag = "pages",
+    request_body = PostNewPage,
+    responses(
+        (status = 200, body = Vec<AccessibilityWarning>, description = "Created, with anything the accessibility checks found"),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has the given parent ID"),
+        (status = 422, description = "The page isn't valid"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(tx, storage, auth_session))]
 async fn post_new_dynamic_page(
-    _auth_session: AuthSession,
-    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    mut tx: Tx,
+    Extension(storage): Extension<SharedStorage>,
+    Validated(body): Validated<PostNewPage>,
+) -> Result<Json<Vec<AccessibilityWarning>>, PhsError> {
+    scope.check(body.department)?;
 
-    Extension(pool): Extension<PgPool>,
-    Json(body): Json<PostNewPage>,
-) -> Result<(), PhsError> {
     let name = slugify::slugify!(&body.unsafe_name, separator = "_");
+    let warnings = check_page(&body.data);
 
-    sqlx::query!(
-        "INSERT INTO pages (name, modified) VALUES ($1, 'new'::page_status)",
-        name
+    if let Some(parent_id) = body.parent_id {
+        ensure_page_exists(&mut tx, parent_id).await?;
+    }
+
+    let id = create_page(
+        &mut tx,
+        &*storage,
+        &name,
+        body.parent_id,
+        body.layout,
+        body.visibility,
+        body.data,
+        Some(auth_session.data().id()),
     )
-    .execute(&pool)
     .await?;
 
-    let spec_path = {
-        let mut p = PathBuf::from("pages/specs");
-        p.push(&name);
-        p.set_extension(".json");
-        p
-    };
+    if body.department.is_some() {
+        sqlx::query!(
+            "UPDATE pages SET department = $2 WHERE id = $1",
+            id,
+            body.department
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
 
-    let temp_path = {
-        let mut p = spec_path.clone();
-        p.set_extension(".json.temp");
-        p
-    };
+    Ok(Json(warnings))
+}
 
-    // Tempfile for psuedo-atomic writes
-    let mut writer = BufWriter::new(File::create_new(&temp_path).await?);
+/// Adds a page as `new`, ready to be deployed, and writes its fragment.
+/// `name` should already be a slug, and `created_by` is `None` for pages the
+/// server makes itself. The page is only kept if the fragment is written, as
+/// long as `conn` is a transaction.
+pub(crate) async fn create_page(
+    conn: &mut PgConnection,
+    storage: &dyn Storage,
+    name: &str,
+    parent_id: Option<i32>,
+    layout: PageLayout,
+    visibility: PageVisibility,
+    data: DynamicPageData,
+    created_by: Option<i32>,
+) -> Result<i32, PhsError> {
+    let id = sqlx::query_scalar!(
+        "INSERT INTO pages (name, parent_id, modified, data, layout, visibility, created_by, updated_by) VALUES ($1, $2, 'new'::page_status, $3, $4, $5, $6, $6) RETURNING id",
+        name,
+        parent_id,
+        serde_json::to_value(&data)?,
+        layout as PageLayout,
+        visibility as PageVisibility,
+        created_by
+    )
+    .fetch_one(&mut *conn)
+    .await?;
 
-    writer
-        .write_all(serde_json::ser::to_string(&body.data)?.as_bytes())
+    storage
+        .put(
+            &fragment_key(name),
+            Renderer::render_fragment(layout, data).into_bytes(),
+        )
         .await?;
 
-    writer.flush().await?;
+    Ok(id)
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct PageDraft {
+    saved_at: PrimitiveDateTime,
+    #[schema(value_type = Vec<DynamicPageElement>)]
+    data: DynamicPageData,
+}
 
-    drop(writer);
+#[derive(Serialize, Debug, ToSchema)]
+struct DynamicPage {
+    #[serde(flatten)]
+    metadata: DynamicPageMetadata,
+    #[schema(value_type = Vec<DynamicPageElement>)]
+    data: DynamicPageData,
+    /// An autosave newer than `data`, if the editor left one behind.
+    draft: Option<PageDraft>,
+}
 
-    tokio::fs::rename(temp_path, spec_path).await?;
+/// Fetches a page for the editor, along with any unsaved draft.
+#[utoipa::path(
+    get,
+    path = "/pages/{id}",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, body = DynamicPage),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, _auth_session))]
+async fn get_dynamic_page(
+    _auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
 
-    let fragment_path = {
-        let mut p = PathBuf::from("pages/fragments");
-        p.push(&name);
-        p.set_extension("html");
-        p
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<Json<DynamicPage>, PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    let row = sqlx::query!(
+        r#"
+        SELECT id, name, created_at, updated_at, created_by, updated_by, modified as "modified: PageStatus", layout as "layout: PageLayout", visibility as "visibility: PageVisibility", department, noindex, meta_description, og_title, og_image, data, draft, draft_saved_at
+        FROM pages WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let data = serde_json::from_value(
+        row.data
+            .ok_or(PhsError::bug("Page exists but its spec is missing"))?,
+    )?;
+
+    let draft = match (row.draft, row.draft_saved_at) {
+        (Some(draft), Some(saved_at)) => Some(PageDraft {
+            saved_at,
+            data: serde_json::from_str(&draft)?,
+        }),
+        _ => None,
     };
 
-    Renderer::render_fragment(fragment_path, body.data).await?;
+    Ok(Json(DynamicPage {
+        metadata: DynamicPageMetadata {
+            id: row.id,
+            name: row.name,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            created_by: row.created_by,
+            updated_by: row.updated_by,
+            modified: row.modified,
+            layout: row.layout,
+            visibility: row.visibility,
+            department: row.department,
+            noindex: row.noindex,
+            meta_description: row.meta_description,
+            og_title: row.og_title,
+            og_image: row.og_image,
+        },
+        data,
+        draft,
+    }))
+}
+
+/// Autosaves the editor's work without touching the page's spec. The draft is
+/// cleared when the page is next saved properly.
+#[utoipa::path(
+    put,
+    path = "/pages/{id}/draft",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = Vec<DynamicPageElement>,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, previews, _auth_session, data))]
+async fn put_dynamic_page_draft(
+    _auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(previews): Extension<Arc<PreviewChannels>>,
+    Path(id): Path<i32>,
+    Json(data): Json<DynamicPageData>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    let layout = sqlx::query_scalar!(
+        r#"UPDATE pages SET draft = $1, draft_saved_at = now() WHERE id = $2 AND modified <> 'archived'::page_status RETURNING layout as "layout: PageLayout""#,
+        serde_json::to_string(&data)?,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    previews.publish(pool, id, Renderer::render_fragment(layout, data));
 
     Ok(())
 }
 
 // FIXME: Past me, please don't use format! so much... Also in the other endpoints in this file
-#[instrument(skip(pool, _auth_session))]
+#[utoipa::path(
+    put,
+    path = "/pages/{id}",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = Vec<DynamicPageElement>,
+    responses(
+        (status = 200, body = Vec<AccessibilityWarning>, description = "Saved, with anything the accessibility checks found"),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+        (status = 422, description = "The page isn't valid"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(tx, pool, storage, previews, auth_session))]
 async fn put_dynamic_page(
-    _auth_session: AuthSession,
-    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
 
+    mut tx: Tx,
     Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(previews): Extension<Arc<PreviewChannels>>,
     Path(id): Path<i32>,
-    Json(data): Json<DynamicPageData>,
+    Validated(data): Validated<DynamicPageData>,
+) -> Result<Json<Vec<AccessibilityWarning>>, PhsError> {
+    check_scope(&mut *tx, &scope, id).await?;
+
+    let warnings = check_page(&data);
+
+    let page = sqlx::query!(
+        r#"UPDATE pages SET data = $2, modified = CASE WHEN modified = 'unpublished'::page_status THEN modified ELSE 'edited'::page_status END, updated_at = now(), updated_by = $3, draft = NULL, draft_saved_at = NULL WHERE id = $1 AND modified <> 'archived'::page_status RETURNING name, layout as "layout: PageLayout""#,
+        id,
+        serde_json::to_value(&data)?,
+        auth_session.data().id()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let fragment = Renderer::render_fragment(page.layout, data);
+    storage
+        .put(&fragment_key(&page.name), fragment.as_bytes().to_vec())
+        .await?;
+
+    previews.publish(pool, id, fragment);
+
+    Ok(Json(warnings))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct PageLayoutBody {
+    layout: PageLayout,
+}
+
+/// Switches the template a page is built on. The fragment is re-rendered
+/// straight away, but the live page only changes on the next deploy.
+#[utoipa::path(
+    put,
+    path = "/pages/{id}/layout",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = PageLayoutBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(tx, storage, auth_session))]
+async fn put_dynamic_page_layout(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    mut tx: Tx,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<i32>,
+    Json(body): Json<PageLayoutBody>,
 ) -> Result<(), PhsError> {
-    let name = sqlx::query_scalar!(
-        "UPDATE pages SET modified = 'edited'::page_status WHERE id = $1 RETURNING name",
+    check_scope(&mut *tx, &scope, id).await?;
+
+    let page = sqlx::query!(
+        "UPDATE pages SET layout = $2, modified = CASE WHEN modified = 'unpublished'::page_status THEN modified ELSE 'edited'::page_status END, updated_at = now(), updated_by = $3 WHERE id = $1 AND modified <> 'archived'::page_status RETURNING name, data",
+        id,
+        body.layout as PageLayout,
+        auth_session.data().id()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let data = serde_json::from_value(
+        page.data
+            .ok_or(PhsError::bug("Page exists but its spec is missing"))?,
+    )?;
+
+    storage
+        .put(
+            &fragment_key(&page.name),
+            Renderer::render_fragment(body.layout, data).into_bytes(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct PageVisibilityBody {
+    visibility: PageVisibility,
+}
+
+/// Changes who can see a page. Unlike other edits this takes effect
+/// immediately, moving any deployed copy so that a page made staff-only stops
+/// being public straight away.
+#[utoipa::path(
+    put,
+    path = "/pages/{id}/visibility",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = PageVisibilityBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, auth_session))]
+async fn put_dynamic_page_visibility(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(config): Extension<ServerConfig>,
+    Path(id): Path<i32>,
+    Json(body): Json<PageVisibilityBody>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    let mut tx = pool.begin().await?;
+
+    let old_visibility = sqlx::query_scalar!(
+        r#"SELECT visibility as "visibility: PageVisibility" FROM pages WHERE id = $1 AND modified <> 'archived'::page_status FOR UPDATE"#,
         id
     )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if old_visibility == body.visibility {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "UPDATE pages SET visibility = $1, updated_at = now(), updated_by = $3 WHERE id = $2",
+        body.visibility as PageVisibility,
+        id,
+        auth_session.data().id()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let path = page_path(&mut tx, id).await?;
+    let targets = dist_keys(old_visibility, &path)
+        .into_iter()
+        .zip(dist_keys(body.visibility, &path))
+        .collect::<Vec<_>>();
+
+    let moved = move_files(&*storage, &targets).await?;
+
+    if let Err(e) = tx.commit().await {
+        restore_files(&*storage, &moved).await;
+        return Err(e.into());
+    }
+
+    if let Err(error) = generate_sitemap(&pool, &*storage, &config).await {
+        tracing::error!(
+            ?error,
+            "Failed to regenerate sitemap after visibility change"
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct PageDepartmentBody {
+    department: Option<i32>,
+}
+
+/// Links a page to a department, or to none if `department` is `null`. Those
+/// with `ManagePages` in only some departments need it in both the old and the
+/// new one. Nothing is redeployed, as the department isn't shown on the page.
+#[utoipa::path(
+    put,
+    path = "/pages/{id}/department",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = PageDepartmentBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, auth_session))]
+async fn put_dynamic_page_department(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Json(body): Json<PageDepartmentBody>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+    scope.check(body.department)?;
+
+    sqlx::query_scalar!(
+        "UPDATE pages SET department = $2, updated_at = now(), updated_by = $3 WHERE id = $1 AND modified <> 'archived'::page_status RETURNING id",
+        id,
+        body.department,
+        auth_session.data().id()
+    )
     .fetch_one(&pool)
     .await?;
 
-    let spec_path = {
-        let mut p = PathBuf::from("pages/specs");
-        p.push(&name);
-        p.set_extension(".json");
-        p
-    };
-    let temp_path = {
-        let mut p = spec_path.clone();
-        p.set_extension(".json.temp");
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct PageIndexingBody {
+    noindex: bool,
+}
+
+/// Asks search engines to leave a page out, or stops asking. It drops out of
+/// `sitemap.xml` straight away, but its robots meta tag only changes on the
+/// next deploy.
+#[utoipa::path(
+    put,
+    path = "/pages/{id}/indexing",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = PageIndexingBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, auth_session))]
+async fn put_dynamic_page_indexing(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(config): Extension<ServerConfig>,
+    Path(id): Path<i32>,
+    Json(body): Json<PageIndexingBody>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    sqlx::query_scalar!(
+        "UPDATE pages SET noindex = $2, modified = CASE WHEN modified = ANY (ARRAY['new', 'unpublished']::page_status[]) OR noindex = $2 THEN modified ELSE 'edited'::page_status END, updated_at = now(), updated_by = $3 WHERE id = $1 AND modified <> 'archived'::page_status RETURNING id",
+        id,
+        body.noindex,
+        auth_session.data().id()
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    if let Err(error) = generate_sitemap(&pool, &*storage, &config).await {
+        tracing::error!(?error, "Failed to regenerate sitemap after indexing change");
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct PageMetaBody {
+    /// Shown under it in search results and link previews.
+    meta_description: Option<String>,
+    /// Shown in link previews instead of the page's name.
+    og_title: Option<String>,
+    /// Shown in link previews. An `https://` URL or a path on this site, e.g.
+    /// of uploaded media.
+    og_image: Option<String>,
+}
+
+impl Validate for PageMetaBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        meta_errors(
+            &mut errors,
+            self.meta_description.as_deref(),
+            self.og_title.as_deref(),
+            self.og_image.as_deref(),
+        );
+        errors
+    }
+}
+
+/// Replaces what a page tells search engines and link previews about itself.
+/// The live page only changes on the next deploy.
+#[utoipa::path(
+    put,
+    path = "/pages/{id}/meta",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = PageMetaBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+        (status = 422, description = "A field is too long, or the image isn't a usable URL"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, auth_session))]
+async fn put_dynamic_page_meta(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<PageMetaBody>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    sqlx::query_scalar!(
+        r"
+        UPDATE pages SET meta_description = $2, og_title = $3, og_image = $4,
+          modified = CASE WHEN modified = ANY (ARRAY['new', 'unpublished']::page_status[]) THEN modified ELSE 'edited'::page_status END,
+          updated_at = now(), updated_by = $5
+        WHERE id = $1 AND modified <> 'archived'::page_status
+        RETURNING id
+        ",
+        id,
+        body.meta_description,
+        body.og_title,
+        body.og_image,
+        auth_session.data().id()
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DeletePageParams {
+    #[serde(default)]
+    archive: bool,
+}
+
+/// Archives a page and moves its fragment and deployed file into
+/// `pages/archive`. Unless `?archive=true` is given, it's also put in the
+/// trash, to be removed for good once the trash expires; archived pages are
+/// kept indefinitely.
+///
+/// The files are moved aside before the transaction commits, and moved back if
+/// any step fails, so the database and the filesystem never disagree.
+#[utoipa::path(
+    delete,
+    path = "/pages/{id}",
+    tag = "pages",
+    params(("id" = i32, Path), DeletePageParams),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID"),
+        (status = 409, description = "The page has child pages"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, auth_session))]
+async fn delete_dynamic_page(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<i32>,
+    Query(params): Query<DeletePageParams>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    let mut tx = pool.begin().await?;
+
+    if sqlx::query_scalar!(
+        "SELECT id FROM pages WHERE parent_id = $1 AND ($2 OR modified <> 'archived'::page_status) LIMIT 1",
+        id,
+        !params.archive
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .is_some()
+    {
+        return Err(PhsError::client(ErrorCode::HasChildren, "This page has child pages, which must be moved or removed first"));
+    }
+
+    let path = page_path(&mut tx, id).await?;
+
+    // Pages in the trash never have children, so they can be purged
+    let name = sqlx::query_scalar!(
+        "UPDATE pages SET modified = 'archived'::page_status, updated_at = now(), updated_by = $3, deleted_at = CASE WHEN $2 THEN NULL ELSE now() END WHERE id = $1 AND modified <> 'archived'::page_status RETURNING name",
+        id,
+        params.archive,
+        auth_session.data().id()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let targets = std::iter::once(fragment_key(&name))
+        .chain(all_dist_keys(&path))
+        .map(|from| {
+            let file_name = from.rsplit('/').next().unwrap_or_default();
+            let to = format!("{}{file_name}", archive_dir(&name));
+            (from, to)
+        })
+        .collect::<Vec<_>>();
+
+    let moved = move_files(&*storage, &targets).await?;
+
+    if let Err(e) = tx.commit().await {
+        restore_files(&*storage, &moved).await;
+        return Err(e.into());
+    }
+
+    tracing::info!(page = name, archived = params.archive, "Page removed");
+
+    Ok(())
+}
+
+#[async_trait]
+impl Deletable for DynamicPageMetadata {
+    const KIND: TrashKind = TrashKind::Page;
+
+    /// Brings the page back as `new`, with its fragment rendered afresh from its
+    /// spec, so it goes live again with the next deploy. Refused if its parent
+    /// has been archived since.
+    async fn restore(
+        pool: &PgPool,
+        storage: &dyn Storage,
+        id: i32,
+        by: i32,
+    ) -> Result<(), PhsError> {
+        let mut tx = pool.begin().await?;
+
+        let page = sqlx::query!(
+            r#"UPDATE pages SET modified = 'new'::page_status, updated_at = now(), updated_by = $2, deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING name, parent_id, data, layout as "layout: PageLayout""#,
+            id,
+            by
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(parent_id) = page.parent_id {
+            ensure_page_exists(&mut tx, parent_id).await?;
+        }
+
+        let data: DynamicPageData = serde_json::from_value(
+            page.data
+                .ok_or(PhsError::bug("Page exists but its spec is missing"))?,
+        )?;
+
+        let fragment = fragment_key(&page.name);
+        storage
+            .put(
+                &fragment,
+                Renderer::render_fragment(page.layout, data).into_bytes(),
+            )
+            .await?;
+
+        if let Err(e) = tx.commit().await {
+            discard_files(storage, [&fragment].into_iter()).await;
+            return Err(e.into());
+        }
+
+        let dir = archive_dir(&page.name);
+        if let Err(error) = storage.delete_all(&dir).await {
+            tracing::warn!(?error, ?dir, "Failed to clean up archived page files");
+        }
+
+        Ok(())
+    }
+
+    async fn purge(
+        pool: &PgPool,
+        storage: &dyn Storage,
+        cutoff: OffsetDateTime,
+    ) -> Result<u64, PhsError> {
+        let names = sqlx::query_scalar!(
+            "DELETE FROM pages WHERE deleted_at < $1 RETURNING name",
+            cutoff
+        )
+        .fetch_all(pool)
+        .await?;
+
+        for name in &names {
+            let dir = archive_dir(name);
+            if let Err(error) = storage.delete_all(&dir).await {
+                tracing::warn!(?error, ?dir, "Failed to clean up archived page files");
+            }
+        }
+
+        Ok(names.len() as u64)
+    }
+}
+
+/// Takes a page back out of the trash as `new`, ready to be deployed again.
+#[utoipa::path(
+    post,
+    path = "/pages/{id}/restore",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page in the trash has this ID, or its parent is archived"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, auth_session))]
+async fn post_restore_dynamic_page(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    DynamicPageMetadata::restore(&pool, &*storage, id, auth_session.data().id()).await?;
+
+    tracing::info!(page = id, "Page restored from the trash");
+
+    Ok(())
+}
+
+/// Takes a deployed page offline whilst keeping its spec and fragment, so it
+/// can be put back up with a normal deploy.
+///
+/// If [`ServerConfig::unavailable_template`] is set, the deployed file is
+/// replaced with that template rather than just removed.
+#[utoipa::path(
+    post,
+    path = "/pages/{id}/unpublish",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No deployed page has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, tera, auth_session))]
+async fn post_unpublish_dynamic_page(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(tera): Extension<Arc<TeraPool>>,
+    Extension(config): Extension<ServerConfig>,
+    Path(id): Path<i32>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    let mut tx = pool.begin().await?;
+
+    let page = sqlx::query!(
+        r#"
+        UPDATE pages SET modified = 'unpublished'::page_status, updated_at = now(), updated_by = $2
+        WHERE id = $1 AND modified = ANY (ARRAY['unmodified', 'edited']::page_status[])
+        RETURNING name, visibility as "visibility: PageVisibility"
+        "#,
+        id,
+        auth_session.data().id()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(PhsError::client(
+        ErrorCode::NotFound,
+        "No deployed page exists with this ID",
+    ))?;
+
+    let path = page_path(&mut tx, id).await?;
+    let dist_key = dist_key(page.visibility, &path);
+
+    let targets = all_dist_keys(&path)
+        .into_iter()
+        .map(|key| {
+            let aside = with_suffix(&key, ".unpublished");
+            (key, aside)
+        })
+        .collect::<Vec<_>>();
+
+    let moved = move_files(&*storage, &targets).await?;
+
+    if let Some(ref template) = config.unavailable_template {
+        let placeholder = async {
+            let mut context = tera::Context::new();
+            context.insert("title", &page.name);
+            context.insert("navigation", &navigation_tree(&pool).await?);
+            // Nothing worth finding is on the placeholder
+            let meta = PageMeta {
+                noindex: true,
+                ..PageMeta::default()
+            };
+            meta.add_to_context(&mut context, &page.name, &page_url(&path), &config.site_url);
+            consent::add_to_context(&mut context);
+
+            let rendered = tera.render(template.clone(), context).await?;
+
+            storage.put(&dist_key, rendered.into_bytes()).await
+        };
+
+        if let Err(e) = placeholder.await {
+            restore_files(&*storage, &moved).await;
+            return Err(e);
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        restore_files(&*storage, &moved).await;
+        return Err(e.into());
+    }
+
+    discard_files(&*storage, moved.iter().map(|(_, aside)| aside)).await;
+
+    if let Err(error) = generate_sitemap(&pool, &*storage, &config).await {
+        tracing::error!(?error, "Failed to regenerate sitemap after unpublishing");
+    }
+
+    tracing::info!(page = page.name, "Page unpublished");
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct RenamePageBody {
+    unsafe_name: String,
+}
+
+impl Validate for RenamePageBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.not_blank("unsafe_name", &self.unsafe_name);
+        errors.max_chars("unsafe_name", &self.unsafe_name, MAX_NAME_LENGTH);
+        errors
+    }
+}
+
+/// Copies a page's spec and fragment under a new slug, next to the original in
+/// the hierarchy and in the same department. The copy starts out as `new`, so
+/// it isn't live until deployed.
+#[utoipa::path(
+    post,
+    path = "/pages/{id}/duplicate",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = RenamePageBody,
+    responses(
+        (status = 200, body = DynamicPageMetadata),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+        (status = 409, description = "A page with this name already exists"),
+        (status = 422, description = "The name is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, auth_session))]
+async fn post_duplicate_dynamic_page(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<RenamePageBody>,
+) -> Result<Json<DynamicPageMetadata>, PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    let new_name = slugify::slugify!(&body.unsafe_name, separator = "_");
+
+    let mut tx = pool.begin().await?;
+
+    let source = sqlx::query!(
+        r#"SELECT name, parent_id, data, layout as "layout: PageLayout", visibility as "visibility: PageVisibility", department, noindex, meta_description, og_title, og_image FROM pages WHERE id = $1 AND modified <> 'archived'::page_status"#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if sqlx::query_scalar!("SELECT id FROM pages WHERE name = $1", new_name)
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some()
+    {
+        return Err(PhsError::client(
+            ErrorCode::NameTaken,
+            "A page with this name already exists",
+        ));
+    }
+
+    let page = sqlx::query_as!(
+        DynamicPageMetadata,
+        r#"
+        INSERT INTO pages (name, parent_id, modified, data, layout, visibility, department, noindex, meta_description, og_title, og_image, created_by, updated_by) VALUES ($1, $2, 'new'::page_status, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11)
+        RETURNING id, name, created_at, updated_at, created_by, updated_by, modified as "modified: _", layout as "layout: _", visibility as "visibility: _", department, noindex, meta_description, og_title, og_image
+        "#,
+        new_name,
+        source.parent_id,
+        source.data,
+        source.layout as PageLayout,
+        source.visibility as PageVisibility,
+        source.department,
+        source.noindex,
+        source.meta_description,
+        source.og_title,
+        source.og_image,
+        auth_session.data().id()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let fragment = storage
+        .get(&fragment_key(&source.name))
+        .await?
+        .ok_or(PhsError::bug("Page exists but its fragment is missing"))?;
+
+    let copy = fragment_key(&new_name);
+    storage.put(&copy, fragment).await?;
+
+    if let Err(e) = tx.commit().await {
+        discard_files(&*storage, [&copy].into_iter()).await;
+        return Err(e.into());
+    }
+
+    tracing::info!(from = source.name, to = new_name, "Page duplicated");
+
+    Ok(Json(page))
+}
+
+/// Re-slugs a page, moves its files to the new slug and records redirects from
+/// the old URLs of it and its descendants so existing links keep working.
+#[utoipa::path(
+    post,
+    path = "/pages/{id}/rename",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = RenamePageBody,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+        (status = 409, description = "A page with this name already exists"),
+        (status = 422, description = "The name is empty or too long"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, auth_session))]
+async fn post_rename_dynamic_page(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<RenamePageBody>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    let new_name = slugify::slugify!(&body.unsafe_name, separator = "_");
+
+    let mut tx = pool.begin().await?;
+
+    let old_name = sqlx::query_scalar!(
+        "SELECT name FROM pages WHERE id = $1 AND modified <> 'archived'::page_status FOR UPDATE",
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if old_name == new_name {
+        return Ok(());
+    }
+
+    if sqlx::query_scalar!("SELECT id FROM pages WHERE name = $1", new_name)
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some()
+    {
+        return Err(PhsError::client(
+            ErrorCode::NameTaken,
+            "A page with this name already exists",
+        ));
+    }
+
+    let old_path = page_path(&mut tx, id).await?;
+    let new_path = {
+        let mut p = old_path.clone();
+        p.pop();
+        p.push(new_name.clone());
         p
     };
-    let fragment_path = {
-        let mut p = PathBuf::from("pages/fragments");
-        p.push(&name);
-        p.set_extension("html");
+
+    sqlx::query!(
+        "UPDATE pages SET name = $1, updated_at = now(), updated_by = $3 WHERE id = $2",
+        new_name,
+        id,
+        auth_session.data().id()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let mut targets = vec![(fragment_key(&old_name), fragment_key(&new_name))];
+    targets.extend(relocate_page(&mut tx, &*storage, id, &old_path, &new_path).await?);
+
+    let moved = move_files(&*storage, &targets).await?;
+
+    if let Err(e) = tx.commit().await {
+        restore_files(&*storage, &moved).await;
+        return Err(e.into());
+    }
+
+    tracing::info!(from = old_name, to = new_name, "Page renamed");
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct PutPageParentBody {
+    parent_id: Option<i32>,
+}
+
+/// Moves a page (and everything under it) beneath a new parent, or to the top
+/// level if `parent_id` is `null`.
+#[utoipa::path(
+    put,
+    path = "/pages/{id}/parent",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = PutPageParentBody,
+    responses(
+        (status = 200),
+        (status = 400, description = "The new parent is the page itself or beneath it"),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, auth_session))]
+async fn put_dynamic_page_parent(
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(id): Path<i32>,
+    Json(body): Json<PutPageParentBody>,
+) -> Result<(), PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    let mut tx = pool.begin().await?;
+
+    let old_path = page_path(&mut tx, id).await?;
+
+    let new_path = if let Some(parent_id) = body.parent_id {
+        ensure_page_exists(&mut tx, parent_id).await?;
+
+        if subtree_paths(&mut tx, id)
+            .await?
+            .iter()
+            .any(|(descendant, _)| *descendant == parent_id)
+        {
+            return Err(PhsError::client(
+                ErrorCode::InvalidHierarchy,
+                "A page can't be moved beneath itself or one of its children",
+            ));
+        }
+
+        let mut p = page_path(&mut tx, parent_id).await?;
+        p.push(old_path.last().cloned().unwrap_or_default());
         p
+    } else {
+        old_path.last().cloned().into_iter().collect()
     };
 
-    // Tempfile for psuedo-atomic writes
-    let mut writer = BufWriter::new(File::options().write(true).open(&temp_path).await?);
+    if old_path == new_path {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "UPDATE pages SET parent_id = $1, updated_at = now(), updated_by = $3 WHERE id = $2",
+        body.parent_id,
+        id,
+        auth_session.data().id()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let targets = relocate_page(&mut tx, &*storage, id, &old_path, &new_path).await?;
+    let moved = move_files(&*storage, &targets).await?;
+
+    if let Err(e) = tx.commit().await {
+        restore_files(&*storage, &moved).await;
+        return Err(e.into());
+    }
+
+    tracing::info!(
+        from = page_url(&old_path),
+        to = page_url(&new_path),
+        "Page moved"
+    );
+
+    Ok(())
+}
 
-    writer
-        .write_all(serde_json::ser::to_string(&data)?.as_bytes())
+/// Records redirects from the old URL of a page and each of its descendants,
+/// flags them all for redeployment (their breadcrumbs are now stale), and
+/// returns the deployed files that need moving.
+///
+/// The caller is expected to have already updated the page's own row.
+async fn relocate_page(
+    conn: &mut PgConnection,
+    storage: &dyn Storage,
+    id: i32,
+    old_path: &[String],
+    new_path: &[String],
+) -> Result<Vec<(String, String)>, PhsError> {
+    let subtree = subtree_paths(conn, id).await?;
+
+    for (page_id, relative) in &subtree {
+        let old_url = page_url(&[old_path, relative].concat());
+        let new_url = page_url(&[new_path, relative].concat());
+
+        // A redirect away from the new URL would now shadow the page itself
+        sqlx::query!("DELETE FROM page_redirects WHERE old_path = $1", new_url)
+            .execute(&mut *conn)
+            .await?;
+
+        // Collapse chains so older URLs go straight to the new one
+        sqlx::query!(
+            "UPDATE page_redirects SET new_path = $1 WHERE new_path = $2",
+            new_url,
+            old_url
+        )
+        .execute(&mut *conn)
         .await?;
 
-    writer.flush().await?;
+        sqlx::query!(
+            r"
+            INSERT INTO page_redirects (old_path, new_path, page_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (old_path) DO UPDATE
+            SET new_path = EXCLUDED.new_path, page_id = EXCLUDED.page_id
+            ",
+            old_url,
+            new_url,
+            page_id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    sqlx::query!(
+        r"
+        UPDATE pages
+        SET modified = 'edited'::page_status
+        WHERE id = ANY ($1) AND modified = 'unmodified'::page_status
+        ",
+        &subtree
+            .iter()
+            .map(|(page_id, _)| *page_id)
+            .collect::<Vec<_>>()
+    )
+    .execute(&mut *conn)
+    .await?;
 
-    drop(writer);
+    let mut targets = all_dist_keys(old_path)
+        .into_iter()
+        .zip(all_dist_keys(new_path))
+        .collect::<Vec<_>>();
 
-    tokio::fs::rename(temp_path, spec_path).await?;
+    // Descendants may have been deployed under either visibility
+    for visibility in VISIBILITIES {
+        let (old_dir, new_dir) = (
+            dist_dir(visibility, old_path),
+            dist_dir(visibility, new_path),
+        );
 
-    Renderer::render_fragment(fragment_path, data).await?;
+        for key in storage.list(&old_dir).await? {
+            let relative = &key[old_dir.len()..];
+            targets.push((key.clone(), format!("{new_dir}{relative}")));
+        }
+    }
 
-    Ok(())
+    Ok(targets)
 }
-#[instrument(skip(pool, _auth_session))]
+
+/// Serves files from `pages/dist`. Deployed pages are stored as `<path>.html`
+/// but linked to without the extension, and anything not found is checked
+/// against the redirects left behind by renamed or moved pages.
+pub async fn serve_deployed_page(
+    Extension(db): Extension<Db>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(config): Extension<ServerConfig>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response, PhsError> {
+    let path = percent_encoding::percent_decode_str(uri.path())
+        .decode_utf8()
+        .map_err(|_| PhsError::client(ErrorCode::NotFound, "Invalid page path"))?;
+    let key = format!("pages/dist{path}");
+
+    let pages = format!("public, max-age={}", config.cache.pages);
+    let assets = format!("public, max-age={}", config.cache.assets);
+
+    if let Some(res) =
+        precompressed_response(&*storage, &with_suffix(&key, ".html"), &headers, &pages).await?
+    {
+        return Ok(res);
+    }
+
+    if let Some(res) = stored_response(&*storage, &key, &headers, &assets).await? {
+        return Ok(res);
+    }
+
+    db.timed(
+        "find_page_redirect",
+        sqlx::query_scalar!(
+            "SELECT new_path FROM page_redirects WHERE old_path = $1",
+            uri.path()
+        )
+        .fetch_optional(db.read()),
+    )
+    .await?
+    .map(|new_path| Redirect::permanent(&new_path).into_response())
+    .ok_or(PhsError::client(
+        ErrorCode::NotFound,
+        "No page or redirect exists at this path",
+    ))
+}
+
+/// Serves staff-only pages from `pages/protected` at `/staff/<path>`, to
+/// signed-in teachers and admins only.
+pub async fn serve_protected_page(
+    auth_session: AuthSession,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(config): Extension<ServerConfig>,
+    Path(page): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, PhsError> {
+    if !auth_session.data().is_staff() {
+        return Err(PhsError::client(
+            ErrorCode::StaffOnly,
+            "Only staff can view this page",
+        ));
+    }
+
+    let key = format!("{}/{page}.html", PageVisibility::Staff.dist_root());
+
+    // Private, so the school's proxy never hands these to anyone else
+    let cache_control = format!("private, max-age={}", config.cache.pages);
+
+    precompressed_response(&*storage, &key, &headers, &cache_control)
+        .await?
+        .ok_or(PhsError::client(
+            ErrorCode::NotFound,
+            "No staff page exists at this path",
+        ))
+}
+
+#[instrument(skip(db, _auth_session))]
 async fn get_dynamic_page_metadata(
     _auth_session: AuthSession,
-    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+    _: ScopedPermission<{ Permission::ManagePages as u8 }>,
 
     Query(cursor_options): Query<CursorOptions>,
     Query(query_string): Query<<DynamicPageMetadata as HasSqlxQueryString>::QueryString>,
 
-    Extension(pool): Extension<PgPool>,
+    Extension(db): Extension<Db>,
 ) -> Result<Json<CursorResponse<DynamicPageMetadata>>, PhsError> {
     let pages = crate::resources::paginated_query_as::<DynamicPageMetadata>(
-        r"SELECT id, name, created_at, updated_at, modified FROM pages",
+        "list_pages",
+        r"SELECT id, name, created_at, updated_at, created_by, updated_by, modified, layout, visibility, department, noindex, meta_description, og_title, og_image FROM pages",
         cursor_options,
         query_string,
-        &pool,
+        &db,
     )
     .await?;
 
     Ok(Json(CursorResponse::new(pages)))
 }
 
-#[instrument(skip(pool, _auth_session))]
+/// Deploys the given pages all-or-nothing.
+///
+/// Every page is rendered in memory first; only once they have all rendered
+/// are the live files swapped in and the pages marked as unmodified, in a
+/// single transaction. If anything fails, the previous files are restored and
+/// no statuses change. Responds with the deployed pages the accessibility
+/// checks found something on.
+///
+/// With approval switched on, every page has to have been approved since it
+/// was last changed, apart from those being taken down. Those with
+/// `ManagePages` in only some departments can only deploy their pages.
+#[utoipa::path(
+    post,
+    path = "/deploy",
+    tag = "pages",
+    request_body = Vec<i32>,
+    responses(
+        (status = 200, body = Vec<PageAccessibility>),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 409, description = "Some of the pages need approving first"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, storage, tera, settings, notifier, auth_session))]
 async fn post_deploy_dynamic_pages(
-    _auth_session: AuthSession,
-    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+    auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
 
     Extension(pool): Extension<PgPool>,
-    Extension(tera): Extension<Arc<Mutex<Tera>>>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(tera): Extension<Arc<TeraPool>>,
+    Extension(config): Extension<ServerConfig>,
+    Extension(settings): Extension<Arc<RwLock<ServerSettings>>>,
+    Extension(notifier): Extension<Notifier>,
     Json(body): Json<Vec<i32>>,
-) -> Result<(), PhsError> {
-    let pages = sqlx::query_scalar!(
-        r#"UPDATE pages SET modified = 'unmodified'::page_status WHERE id = ANY ($1) AND modified = ANY (ARRAY['new', 'edited']::page_status[]) RETURNING name"#,
+) -> Result<Json<Vec<PageAccessibility>>, PhsError> {
+    let mut tx = pool.begin().await?;
+
+    let rows = sqlx::query!(
+        r#"SELECT id, visibility as "visibility: PageVisibility", data, department, COALESCE(modified = 'unpublished'::page_status OR (review = 'approved'::review_status AND reviewed_at >= updated_at), false) as "approved!" FROM pages WHERE id = ANY ($1) AND modified = ANY (ARRAY['new', 'edited', 'unpublished']::page_status[]) FOR UPDATE"#,
         &body
     )
-    .fetch_all(&pool)
+    .fetch_all(&mut *tx)
     .await?;
 
+    for row in &rows {
+        scope.check(row.department)?;
+    }
+
+    if settings.read().await.features.approval {
+        let unapproved = rows
+            .iter()
+            .filter(|row| !row.approved)
+            .map(|row| row.id.to_string())
+            .collect::<Vec<_>>();
+
+        if !unapproved.is_empty() {
+            return Err(PhsError::client(
+                ErrorCode::NotApproved,
+                format!(
+                    "These pages need approving first: {}",
+                    unapproved.join(", ")
+                ),
+            ));
+        }
+    }
+
+    let pages = rows.iter().map(|row| row.id).collect::<Vec<_>>();
+
+    let specs = rows
+        .iter()
+        .map(|row| {
+            Ok(row
+                .data
+                .clone()
+                .map(serde_json::from_value::<DynamicPageData>)
+                .transpose()?
+                .unwrap_or_default())
+        })
+        .collect::<Result<Vec<_>, PhsError>>()?;
+
+    // What gets searched is the page as deployed, not as it's being edited
+    let search_texts = specs
+        .iter()
+        .map(|data| Renderer::plain_text(data))
+        .collect::<Vec<_>>();
+
     tracing::debug!(?pages, "Pages to deploy");
 
-    for page_name in pages {
-        // FIXME: Only one endpoint can use the instance at a time...
-        deploy_page(page_name, &mut *tera.lock().await).await?;
+    let navigation = navigation_tree(&pool).await?;
+
+    let mut paths = Vec::with_capacity(rows.len());
+    let mut metas = Vec::with_capacity(rows.len());
+    for row in &rows {
+        paths.push((row.visibility, page_path(&mut tx, row.id).await?));
+        metas.push(PageMeta::of(&mut *tx, row.id).await?);
     }
 
-    Ok(())
-}
+    // Render everything before touching any live files
+    // The template pool bounds how many of these actually render at once
+    let staged = future::join_all(paths.iter().zip(&metas).map(|((visibility, path), meta)| {
+        stage_page(
+            &*storage,
+            *visibility,
+            path,
+            meta,
+            &navigation,
+            &config.site_url,
+            &tera,
+        )
+    }))
+    .await
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()?
+    .concat();
 
-async fn deploy_page(slug: String, tera: &mut Tera) -> Result<(), PhsError> {
-    let mut fragment = String::new();
-    let mut context = tera::Context::new();
-    context.insert("title", &slug);
+    // Set the currently deployed versions aside, then swap in the new ones
+    let backups = staged
+        .iter()
+        .map(|(key, _)| (key.clone(), with_suffix(key, ".backup")))
+        .collect::<Vec<_>>();
 
-    let fragment_path = {
-        let mut p = PathBuf::from("pages/fragments");
-        p.push(&slug);
-        p.set_extension("html");
-        p
-    };
+    let backed_up = move_files(&*storage, &backups).await?;
 
-    {
-        tokio::fs::File::open(fragment_path)
-            .await?
-            .read_to_string(&mut fragment)
-            .await?;
+    let mut written = Vec::with_capacity(staged.len());
+    for (key, data) in staged {
+        if let Err(e) = storage.put(&key, data).await {
+            discard_files(&*storage, written.iter()).await;
+            restore_files(&*storage, &backed_up).await;
+            return Err(e);
+        }
+
+        written.push(key);
     }
 
-    let dist_path = {
-        let mut p = PathBuf::from("pages/dist");
-        p.push(&slug);
-        p.set_extension(".html");
-        p
-    };
+    let commit = async {
+        sqlx::query!(
+            r"
+            UPDATE pages SET modified = 'unmodified'::page_status, search_text = deployed.text,
+              review = NULL, submitted_by = NULL, reviewed_at = NULL
+            FROM UNNEST($1::int[], $2::text[]) AS deployed(id, text)
+            WHERE pages.id = deployed.id
+            ",
+            &pages,
+            &search_texts
+        )
+        .execute(&mut *tx)
+        .await?;
 
-    let dist_temp_path = {
-        let mut p = dist_path.clone();
-        p.set_extension(".html.temp");
-        p
+        tx.commit().await
     };
 
-    // Tempfile for psuedo-atomic writes
-    let mut dist = tokio::fs::File::create(&dist_temp_path).await?;
+    if let Err(e) = commit.await {
+        discard_files(&*storage, written.iter()).await;
+        restore_files(&*storage, &backed_up).await;
+        return Err(e.into());
+    }
+
+    discard_files(&*storage, backed_up.iter().map(|(_, backup)| backup)).await;
+
+    // Rendered pages may show the navigation or breadcrumbs that just changed
+    tera.invalidate_cache().await;
+
+    // The pages are live at this point, so a stale sitemap isn't worth failing over
+    if let Err(error) = generate_sitemap(&pool, &*storage, &config).await {
+        tracing::error!(?error, "Failed to regenerate sitemap after deploy");
+    }
+
+    let report = paths
+        .iter()
+        .zip(&pages)
+        .zip(&specs)
+        .filter_map(|(((_, path), &id), data)| {
+            let warnings = check_page(data);
+            (!warnings.is_empty()).then(|| PageAccessibility {
+                id,
+                name: path.last().cloned().unwrap_or_default(),
+                warnings,
+            })
+        })
+        .collect();
 
-    let str = tera.render_str(&fragment, &context).unwrap();
+    notifier.notify(Notification::PagesDeployed {
+        pages,
+        by: auth_session.data().id(),
+    });
+
+    Ok(Json(report))
+}
 
-    dist.write_all(str.as_bytes()).await?;
+#[derive(Serialize, Debug)]
+struct Breadcrumb<'a> {
+    title: &'a str,
+    href: String,
+}
+
+/// What a page's fragment is rendered with: its title, its tags for search
+/// engines and link previews, the site navigation, breadcrumbs down from the
+/// top level and the visitor's cookie choices.
+pub(super) fn page_context(
+    path: &[String],
+    meta: &PageMeta,
+    navigation: &[NavigationNode],
+    site_url: &str,
+) -> tera::Context {
+    let breadcrumbs = (1..=path.len())
+        .map(|i| Breadcrumb {
+            title: &path[i - 1],
+            href: page_url(&path[..i]),
+        })
+        .collect::<Vec<_>>();
+
+    let title = path.last().map_or("", String::as_str);
+
+    let mut context = tera::Context::new();
+    context.insert("title", title);
+    meta.add_to_context(&mut context, title, &page_url(path), site_url);
+    context.insert("navigation", navigation);
+    context.insert("breadcrumbs", &breadcrumbs);
+    consent::add_to_context(&mut context);
+
+    context
+}
 
-    tokio::fs::rename(dist_temp_path, dist_path).await?;
+/// Renders a page, returning the keys and contents of its deployed file and
+/// the precompressed copies of it.
+async fn stage_page(
+    storage: &dyn Storage,
+    visibility: PageVisibility,
+    path: &[String],
+    meta: &PageMeta,
+    navigation: &[NavigationNode],
+    site_url: &str,
+    tera: &Arc<TeraPool>,
+) -> Result<Vec<(String, Vec<u8>)>, PhsError> {
+    let slug = path
+        .last()
+        .ok_or(PhsError::bug("Deploying a page with an empty path"))?;
+
+    let context = page_context(path, meta, navigation, site_url);
+
+    let fragment = storage
+        .get(&fragment_key(slug))
+        .await?
+        .ok_or(PhsError::bug("Page exists but its fragment is missing"))?;
+    let fragment = String::from_utf8(fragment)
+        .map_err(|e| PhsError::internal(e, "Page fragment isn't valid UTF-8"))?;
+
+    let rendered = tera.render_str(fragment, context).await?.into_bytes();
+    let key = dist_key(visibility, path);
+
+    tokio::task::spawn_blocking(move || {
+        let mut files = precompress(&rendered)?
+            .into_iter()
+            .map(|(suffix, data)| (with_suffix(&key, suffix), data))
+            .collect::<Vec<_>>();
+        files.push((key, rendered));
+
+        Ok(files)
+    })
+    .await?
+}
+
+/// Where specs were kept before they moved into the `pages` table. The double
+/// dot is how they were always named on disk.
+fn legacy_spec_key(slug: &str) -> String {
+    format!("pages/specs/{slug}..json")
+}
+
+/// Moves any specs still stored as files into the `pages` table, removing each
+/// file once its page has been filled in. Pages whose spec already lives in
+/// the table are left alone.
+///
+/// # Errors
+///
+/// Fails if the database or storage can't be reached, or a spec is corrupt.
+pub async fn import_legacy_specs(pool: &PgPool, storage: &dyn Storage) -> Result<(), PhsError> {
+    let pages = sqlx::query!("SELECT id, name FROM pages WHERE data IS NULL")
+        .fetch_all(pool)
+        .await?;
+
+    for page in pages {
+        let key = legacy_spec_key(&page.name);
+
+        let Some(spec) = storage.get(&key).await? else {
+            tracing::warn!(page = page.name, "Page has no spec to import");
+            continue;
+        };
+
+        // Check it still parses before it becomes the page's content
+        let data = serde_json::from_slice::<DynamicPageData>(&spec)?;
+
+        sqlx::query!(
+            "UPDATE pages SET data = $1 WHERE id = $2 AND data IS NULL",
+            serde_json::to_value(&data)?,
+            page.id
+        )
+        .execute(pool)
+        .await?;
+
+        storage.delete(&key).await?;
+
+        tracing::info!(page = page.name, "Imported page spec into the database");
+    }
 
     Ok(())
 }
+
+pub(super) fn fragment_key(slug: &str) -> String {
+    format!("pages/fragments/{slug}.html")
+}
+
+/// Where an archived page's files are moved to.
+fn archive_dir(slug: &str) -> String {
+    format!("pages/archive/{slug}/")
+}
+
+const VISIBILITIES: [PageVisibility; 2] = [PageVisibility::Public, PageVisibility::Staff];
+
+/// Where a page with the given path of slugs (see [`page_path`]) is deployed.
+fn dist_key(visibility: PageVisibility, path: &[String]) -> String {
+    format!("{}/{}.html", visibility.dist_root(), path.join("/"))
+}
+
+/// The deployed file of a page followed by its precompressed copies, which
+/// always move together.
+fn dist_keys(visibility: PageVisibility, path: &[String]) -> Vec<String> {
+    let key = dist_key(visibility, path);
+
+    PRECOMPRESSED
+        .iter()
+        .map(|(_, suffix)| with_suffix(&key, suffix))
+        .chain([key.clone()])
+        .collect()
+}
+
+/// [`dist_keys`] for every visibility, for when it doesn't matter where a page
+/// was deployed, only that all of it is found.
+fn all_dist_keys(path: &[String]) -> Vec<String> {
+    VISIBILITIES
+        .into_iter()
+        .flat_map(|visibility| dist_keys(visibility, path))
+        .collect()
+}
+
+/// The prefix of the deployed descendants of a page.
+fn dist_dir(visibility: PageVisibility, path: &[String]) -> String {
+    format!("{}/{}/", visibility.dist_root(), path.join("/"))
+}
+
+/// The public URL path a deployed page is served at.
+pub(super) fn page_url(path: &[String]) -> String {
+    format!("/{}", path.join("/"))
+}
+
+/// The slugs of a page's ancestors followed by its own, e.g. `["about", "admissions"]`.
+pub(super) async fn page_path(conn: &mut PgConnection, id: i32) -> Result<Vec<String>, PhsError> {
+    let path = sqlx::query_scalar!(
+        r#"
+        WITH RECURSIVE ancestors AS (
+            SELECT id, parent_id, name, 0 AS depth FROM pages WHERE id = $1
+            UNION ALL
+            SELECT p.id, p.parent_id, p.name, a.depth + 1 FROM pages p
+            JOIN ancestors a ON p.id = a.parent_id
+        )
+        SELECT name as "name!" FROM ancestors ORDER BY depth DESC
+        "#,
+        id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    if path.is_empty() {
+        return Err(sqlx::Error::RowNotFound.into());
+    }
+
+    Ok(path)
+}
+
+/// Every page in the subtree rooted at `id` (including itself), with its path
+/// relative to that page.
+async fn subtree_paths(
+    conn: &mut PgConnection,
+    id: i32,
+) -> Result<Vec<(i32, Vec<String>)>, PhsError> {
+    sqlx::query!(
+        r#"
+        WITH RECURSIVE descendants AS (
+            SELECT id, ARRAY[]::text[] AS path FROM pages WHERE id = $1
+            UNION ALL
+            SELECT p.id, d.path || p.name::text FROM pages p
+            JOIN descendants d ON p.parent_id = d.id
+        )
+        SELECT id as "id!", path as "path!" FROM descendants
+        "#,
+        id
+    )
+    .fetch_all(conn)
+    .await
+    .map(|rows| rows.into_iter().map(|r| (r.id, r.path)).collect())
+    .map_err(Into::into)
+}
+
+/// Fails unless `scope` covers the page with this ID, archived or not, only
+/// looking it up if `scope` is limited to some departments.
+pub(super) async fn check_scope(
+    executor: impl PgExecutor<'_>,
+    scope: &PermissionScope,
+    id: i32,
+) -> Result<(), PhsError> {
+    if *scope == PermissionScope::Everywhere {
+        return Ok(());
+    }
+
+    let department = sqlx::query_scalar!("SELECT department FROM pages WHERE id = $1", id)
+        .fetch_one(executor)
+        .await?;
+
+    scope.check(department)
+}
+
+async fn ensure_page_exists(conn: &mut PgConnection, id: i32) -> Result<(), PhsError> {
+    sqlx::query_scalar!(
+        "SELECT id FROM pages WHERE id = $1 AND modified <> 'archived'::page_status",
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .map(|_| ())
+    .ok_or(PhsError::client(
+        ErrorCode::ParentNotFound,
+        "No page exists with this parent ID",
+    ))
+}
+
+/// Renames every existing source key to its destination, skipping keys that
+/// don't exist (e.g. a page that was never deployed). If any rename fails, the
+/// keys already moved are put back before returning the error.
+async fn move_files(
+    storage: &dyn Storage,
+    targets: &[(String, String)],
+) -> Result<Vec<(String, String)>, PhsError> {
+    let mut moved = Vec::with_capacity(targets.len());
+
+    for (from, to) in targets {
+        match storage.rename(from, to).await {
+            Ok(true) => moved.push((from.clone(), to.clone())),
+            Ok(false) => {}
+            Err(e) => {
+                restore_files(storage, &moved).await;
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(moved)
+}
+
+/// Best-effort removal of files that may or may not exist.
+pub(super) async fn discard_files(storage: &dyn Storage, keys: impl Iterator<Item = &String>) {
+    for key in keys {
+        if let Err(error) = storage.delete(key).await {
+            tracing::warn!(?error, ?key, "Failed to remove temporary page file");
+        }
+    }
+}
+
+/// Appends `suffix` to a key, e.g. `about.html` to `about.html.backup`.
+fn with_suffix(key: &str, suffix: &str) -> String {
+    format!("{key}{suffix}")
+}
+
+/// Best-effort reversal of [`move_files`].
+async fn restore_files(storage: &dyn Storage, moved: &[(String, String)]) {
+    for (from, to) in moved.iter().rev() {
+        if let Err(error) = storage.rename(to, from).await {
+            tracing::error!(?error, ?from, ?to, "Failed to restore page file");
+        }
+    }
+}
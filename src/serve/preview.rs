@@ -0,0 +1,286 @@
+use std::{collections::HashMap, convert::Infallible, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, Query},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html,
+    },
+    routing::get,
+    Extension, Json, Router,
+};
+use futures_util::{stream, Stream, StreamExt};
+use parking_lot::Mutex;
+use sqlx::PgPool;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::instrument;
+use utoipa::OpenApi;
+
+use crate::{
+    auth::{AuthSession, Permission, ScopedPermission},
+    error::PhsError,
+    share::{ShareBody, ShareKey, ShareLink, ShareParams, SharedKind},
+    validation::Validated,
+    ServerConfig,
+};
+
+use super::{
+    meta::PageMeta,
+    navigation::navigation_tree,
+    page::{check_scope, page_context, page_path},
+    render::Renderer,
+    templates::TeraPool,
+    DynamicPageData, PageLayout,
+};
+
+/// Renders queued for each page before slow viewers start missing some. Only
+/// the latest matters, so a small buffer is plenty.
+const CHANNEL_CAPACITY: usize = 4;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/pages/:id/preview", get(get_page_preview))
+        .route(
+            "/pages/:id/share",
+            get(get_shared_page).post(post_share_page),
+        )
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(post_share_page, get_shared_page))]
+struct PreviewApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    PreviewApi::openapi()
+}
+
+/// A rendered preview, or `None` if rendering failed.
+type Preview = Option<Arc<str>>;
+
+/// Open preview streams, by page. Channels only exist while someone is
+/// watching, so saves to pages nobody is previewing cost nothing.
+pub struct PreviewChannels {
+    channels: Mutex<HashMap<i32, broadcast::Sender<Preview>>>,
+    tera: Arc<TeraPool>,
+    /// For the absolute URLs link previews need.
+    site_url: String,
+}
+
+impl PreviewChannels {
+    pub fn new(tera: Arc<TeraPool>, site_url: String) -> Self {
+        Self {
+            channels: Mutex::default(),
+            tera,
+            site_url,
+        }
+    }
+
+    fn subscribe(&self, id: i32) -> broadcast::Receiver<Preview> {
+        self.channels
+            .lock()
+            .entry(id)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    fn is_watched(&self, id: i32) -> bool {
+        let mut channels = self.channels.lock();
+
+        // Viewers that have gone away leave their channel behind
+        channels.retain(|_, sender| sender.receiver_count() > 0);
+        channels.contains_key(&id)
+    }
+
+    /// Renders a fragment as the page would look if deployed now.
+    async fn render(&self, pool: &PgPool, id: i32, fragment: String) -> Result<String, PhsError> {
+        let path = page_path(&mut *pool.acquire().await?, id).await?;
+        let meta = PageMeta::of(pool, id).await?;
+        let navigation = navigation_tree(pool).await?;
+
+        self.tera
+            .render_str_cached(
+                fragment,
+                page_context(&path, &meta, &navigation, &self.site_url),
+            )
+            .await
+    }
+
+    /// Re-renders a page for anyone previewing it, in the background so saving
+    /// isn't held up.
+    pub(super) fn publish(self: &Arc<Self>, pool: PgPool, id: i32, fragment: String) {
+        if !self.is_watched(id) {
+            return;
+        }
+
+        let channels = self.clone();
+        tokio::spawn(async move {
+            let preview = match channels.render(&pool, id, fragment).await {
+                Ok(html) => Some(html.into()),
+                Err(error) => {
+                    tracing::warn!(?error, id, "Failed to render page preview");
+                    None
+                }
+            };
+
+            if let Some(sender) = channels.channels.lock().get(&id) {
+                // Fails only if every viewer left while rendering, which is fine
+                let _ = sender.send(preview);
+            }
+        });
+    }
+}
+
+fn preview_event(preview: &Preview) -> Event {
+    preview.as_ref().map_or_else(
+        || {
+            Event::default()
+                .event("error")
+                .data("Preview failed to render")
+        },
+        // SSE can't carry carriage returns, and HTML doesn't need them
+        |html| {
+            Event::default()
+                .event("render")
+                .data(html.replace('\r', ""))
+        },
+    )
+}
+
+/// Streams the page's rendered HTML as a `render` event whenever its draft or
+/// spec is saved, starting with how it looks right now.
+#[instrument(skip(pool, channels, _auth_session))]
+async fn get_page_preview(
+    _auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(channels): Extension<Arc<PreviewChannels>>,
+    Path(id): Path<i32>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    let page = sqlx::query!(
+        r#"SELECT layout as "layout: PageLayout", COALESCE(draft::jsonb, data) as data FROM pages WHERE id = $1 AND modified <> 'archived'::page_status"#,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let data = page
+        .data
+        .map(serde_json::from_value::<DynamicPageData>)
+        .transpose()?
+        .unwrap_or_default();
+
+    // Subscribe first so nothing saved while the first render runs is missed
+    let receiver = channels.subscribe(id);
+    let initial = channels
+        .render(&pool, id, Renderer::render_fragment(page.layout, data))
+        .await
+        .ok()
+        .map(Into::into);
+
+    let updates = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(preview) => return Some((Ok(preview_event(&preview)), receiver)),
+                // Skipped renders are stale anyway, the next one is current
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = stream::once(async move { Ok(preview_event(&initial)) }).chain(updates);
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL)))
+}
+
+/// A link showing the page as it would look if deployed now, draft and all,
+/// to anyone who has it until it expires. See [`crate::share`].
+#[utoipa::path(
+    post,
+    path = "/pages/{id}/share",
+    tag = "pages",
+    params(("id" = i32, Path)),
+    request_body = ShareBody,
+    responses(
+        (status = 200, body = ShareLink),
+        (status = 403, description = "Missing the `ManagePages` permission, or only having it in other departments"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+        (status = 422, description = "`days` is out of range"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(pool, share_key, config, _auth_session))]
+async fn post_share_page(
+    _auth_session: AuthSession,
+    ScopedPermission(scope): ScopedPermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(pool): Extension<PgPool>,
+    Extension(share_key): Extension<ShareKey>,
+    Extension(config): Extension<ServerConfig>,
+    Path(id): Path<i32>,
+    Validated(body): Validated<ShareBody>,
+) -> Result<Json<ShareLink>, PhsError> {
+    check_scope(&pool, &scope, id).await?;
+
+    sqlx::query_scalar!(
+        "SELECT id FROM pages WHERE id = $1 AND modified <> 'archived'::page_status",
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(share_key.link(
+        &config.site_url,
+        &format!("/pages/{id}"),
+        SharedKind::Page,
+        id,
+        body.days(),
+    )))
+}
+
+/// The page as it would look if deployed now, for a link from
+/// `POST /pages/{id}/share`. Needs no login.
+#[utoipa::path(
+    get,
+    path = "/pages/{id}/share",
+    tag = "pages",
+    params(("id" = i32, Path), ShareParams),
+    responses(
+        (status = 200, content_type = "text/html"),
+        (status = 403, description = "The link isn't valid or has expired"),
+        (status = 404, description = "No page has this ID, or it's archived"),
+    )
+)]
+#[instrument(skip(pool, channels, share_key))]
+async fn get_shared_page(
+    Extension(pool): Extension<PgPool>,
+    Extension(channels): Extension<Arc<PreviewChannels>>,
+    Extension(share_key): Extension<ShareKey>,
+    Path(id): Path<i32>,
+    Query(params): Query<ShareParams>,
+) -> Result<Html<String>, PhsError> {
+    share_key.verify(SharedKind::Page, id, &params)?;
+
+    let page = sqlx::query!(
+        r#"SELECT layout as "layout: PageLayout", COALESCE(draft::jsonb, data) as data FROM pages WHERE id = $1 AND modified <> 'archived'::page_status"#,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let data = page
+        .data
+        .map(serde_json::from_value::<DynamicPageData>)
+        .transpose()?
+        .unwrap_or_default();
+
+    channels
+        .render(&pool, id, Renderer::render_fragment(page.layout, data))
+        .await
+        .map(Html)
+}
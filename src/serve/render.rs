@@ -1,13 +1,6 @@
-use std::path::PathBuf;
+use crate::serve::TextModifier;
 
-use tokio::{
-    fs::File,
-    io::{AsyncWriteExt, BufWriter},
-};
-
-use crate::{error::PhsError, serve::TextModifier};
-
-use super::{DynamicPageElement, HeaderSize, ListType, TextComponent};
+use super::{DynamicPageElement, HeaderSize, ListType, PageLayout, TextComponent};
 
 impl DynamicPageElement {
     pub fn render(self) -> String {
@@ -57,6 +50,26 @@ impl DynamicPageElement {
             + wrappers.1
     }
 
+    /// The element's text with all markup left out, for search.
+    fn plain_text(&self) -> String {
+        let components = |components: &[TextComponent]| {
+            components
+                .iter()
+                .map(|component| component.content.as_str())
+                .collect::<String>()
+        };
+
+        match self {
+            Self::Header { contents, .. } => contents.clone(),
+            Self::Text { components: c } => components(c),
+            Self::List { items, .. } => items
+                .iter()
+                .map(|item| components(item))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
     fn render_header(size: HeaderSize, contents: &str) -> String {
         let (opening, closing) = size.into_tag();
 
@@ -66,41 +79,38 @@ impl DynamicPageElement {
 
 pub struct Renderer;
 
-static FRAGMENT_HEADER: &str = r#"
-{% extends "base.html" %}
-
-{% block title %}{{ title }}{% endblock main %}
+static FRAGMENT_HEADER: &str = r"
+{% block title %}{{ title }}{% endblock title %}
 
 {% block main %}
-"#;
+";
 
 static FRAGMENT_FOOTER: &str = r"
 {% endblock main %}
 ";
 
 impl Renderer {
-    pub async fn render_fragment(
-        path: PathBuf,
-        elements: Vec<DynamicPageElement>,
-    ) -> Result<(), PhsError> {
-        let mut temp_path = path.clone();
-        temp_path.set_extension(".html.temp");
-
-        let file = File::create(&temp_path).await?;
-        let mut writer = BufWriter::new(file);
-
-        writer.write_all(FRAGMENT_HEADER.as_bytes()).await?;
+    /// Renders a page spec into a Tera template extending the page's layout,
+    /// ready to be deployed.
+    pub fn render_fragment(layout: PageLayout, elements: Vec<DynamicPageElement>) -> String {
+        let mut fragment = format!(r#"{{% extends "{}" %}}"#, layout.template());
+        fragment.push_str(FRAGMENT_HEADER);
 
         for html in elements.into_iter().map(DynamicPageElement::render) {
-            writer.write_all(html.as_bytes()).await?;
+            fragment.push_str(&html);
         }
 
-        writer.write_all(FRAGMENT_FOOTER.as_bytes()).await?;
-
-        writer.flush().await?;
+        fragment.push_str(FRAGMENT_FOOTER);
 
-        tokio::fs::rename(temp_path, path).await?;
+        fragment
+    }
 
-        Ok(())
+    /// All the text of a page spec, one element per line.
+    pub fn plain_text(elements: &[DynamicPageElement]) -> String {
+        elements
+            .iter()
+            .map(DynamicPageElement::plain_text)
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 }
@@ -0,0 +1,175 @@
+//! The site's `robots.txt`, which staff can edit without a deploy. Pages and
+//! posts can also be kept out of search engines one at a time with `noindex`.
+
+use axum::{
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    error::PhsError,
+    storage::{stored_response, SharedStorage, Storage},
+    validation::{FieldErrors, Validate, Validated},
+    ServerConfig,
+};
+
+/// Where an edited `robots.txt` is kept. It's outside `pages/dist` so that
+/// only [`serve_robots`] serves it.
+const ROBOTS_KEY: &str = "pages/robots.txt";
+
+/// Largest `robots.txt` allowed, in bytes, as the most search engines read.
+const MAX_ROBOTS_SIZE: usize = 500 * 1024;
+
+pub fn router() -> Router {
+    Router::new().route(
+        "/robots",
+        get(get_robots).put(put_robots).delete(delete_robots),
+    )
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_robots, put_robots, delete_robots))]
+struct RobotsApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    RobotsApi::openapi()
+}
+
+/// What's served when nobody has edited it: everything but staff pages and the
+/// API may be crawled, and the sitemap is listed.
+fn default_robots(config: &ServerConfig) -> String {
+    format!(
+        "User-agent: *\nDisallow: /staff/\nDisallow: /v1/\nDisallow: /v2/\n\nSitemap: {}/sitemap.xml\n",
+        config.site_url.trim_end_matches('/')
+    )
+}
+
+/// Serves `/robots.txt`, as last edited or [`default_robots`].
+pub async fn serve_robots(
+    Extension(storage): Extension<SharedStorage>,
+    Extension(config): Extension<ServerConfig>,
+    headers: HeaderMap,
+) -> Result<Response, PhsError> {
+    let cache_control = format!("public, max-age={}", config.cache.pages);
+
+    if let Some(res) = stored_response(&*storage, ROBOTS_KEY, &headers, &cache_control).await? {
+        return Ok(res);
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (header::CACHE_CONTROL, cache_control.as_str()),
+        ],
+        default_robots(&config),
+    )
+        .into_response())
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+struct RobotsTxt {
+    content: String,
+}
+
+impl Validate for RobotsTxt {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.check(
+            self.content.len() <= MAX_ROBOTS_SIZE,
+            "content",
+            "Must be at most 500 KiB",
+        );
+        errors
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct RobotsState {
+    content: String,
+    /// Whether it's been edited, rather than being the default.
+    edited: bool,
+}
+
+/// What `/robots.txt` currently serves.
+#[utoipa::path(
+    get,
+    path = "/robots",
+    tag = "robots",
+    responses(
+        (status = 200, body = RobotsState),
+        (status = 403, description = "Missing the `ManageSettings` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(storage, config, _auth_session))]
+async fn get_robots(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(storage): Extension<SharedStorage>,
+    Extension(config): Extension<ServerConfig>,
+) -> Result<Json<RobotsState>, PhsError> {
+    let state = match storage.get(ROBOTS_KEY).await? {
+        Some(content) => RobotsState {
+            content: String::from_utf8_lossy(&content).into_owned(),
+            edited: true,
+        },
+        None => RobotsState {
+            content: default_robots(&config),
+            edited: false,
+        },
+    };
+
+    Ok(Json(state))
+}
+
+/// Replaces `/robots.txt`, taking effect straight away.
+#[utoipa::path(
+    put,
+    path = "/robots",
+    tag = "robots",
+    request_body = RobotsTxt,
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageSettings` permission"),
+        (status = 422, description = "It's larger than 500 KiB"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(storage, _auth_session, body))]
+async fn put_robots(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(storage): Extension<SharedStorage>,
+    Validated(body): Validated<RobotsTxt>,
+) -> Result<(), PhsError> {
+    storage.put(ROBOTS_KEY, body.content.into_bytes()).await
+}
+
+/// Goes back to the default `/robots.txt`.
+#[utoipa::path(
+    delete,
+    path = "/robots",
+    tag = "robots",
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManageSettings` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(storage, _auth_session))]
+async fn delete_robots(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(storage): Extension<SharedStorage>,
+) -> Result<(), PhsError> {
+    storage.delete(ROBOTS_KEY).await
+}
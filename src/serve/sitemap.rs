@@ -0,0 +1,108 @@
+use std::{fmt::Write, time::Duration};
+
+use sqlx::PgPool;
+use time::format_description::well_known::Rfc3339;
+
+use crate::{
+    error::PhsError,
+    storage::{SharedStorage, Storage},
+    ServerConfig,
+};
+
+use super::page::page_url;
+
+const SITEMAP_KEY: &str = "pages/dist/sitemap.xml";
+
+/// How often the sitemap is rebuilt outside of deploys, to pick up new posts.
+const REGENERATE_INTERVAL: Duration = Duration::from_hours(1);
+
+/// Rebuilds `sitemap.xml` from deployed pages and posts, leaving out any marked
+/// `noindex`, and writes it into `pages/dist` so it's served alongside the
+/// pages themselves.
+pub async fn generate_sitemap(
+    pool: &PgPool,
+    storage: &dyn Storage,
+    config: &ServerConfig,
+) -> Result<(), PhsError> {
+    // Pages still in the `new` state have never been deployed
+    let pages = sqlx::query!(
+        r#"
+        WITH RECURSIVE paths AS (
+            SELECT id, ARRAY[name]::text[] AS path FROM pages WHERE parent_id IS NULL
+            UNION ALL
+            SELECT p.id, paths.path || p.name::text FROM pages p
+            JOIN paths ON p.parent_id = paths.id
+        )
+        SELECT paths.path as "path!", p.updated_at
+        FROM pages p
+        JOIN paths USING (id)
+        WHERE p.modified IN ('unmodified'::page_status, 'edited'::page_status)
+            AND p.visibility = 'public'::page_visibility
+            AND NOT p.noindex
+        ORDER BY p.id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let posts = sqlx::query!(
+        "SELECT id, date FROM posts WHERE deleted_at IS NULL AND published AND NOT noindex ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let site_url = config.site_url.trim_end_matches('/');
+
+    let urls = pages
+        .into_iter()
+        .map(|page| (page_url(&page.path), page.updated_at.assume_utc()))
+        .chain(
+            posts
+                .into_iter()
+                .map(|post| (format!("/posts/{}", post.id), post.date)),
+        );
+
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+"#,
+    );
+
+    for (path, lastmod) in urls {
+        let lastmod = lastmod.format(&Rfc3339).unwrap_or_default();
+
+        // Writing to a String can't fail
+        let _ = writeln!(
+            xml,
+            "  <url><loc>{}</loc><lastmod>{lastmod}</lastmod></url>",
+            escape_xml(&format!("{site_url}{path}"))
+        );
+    }
+
+    xml.push_str("</urlset>\n");
+
+    storage.put(SITEMAP_KEY, xml.into_bytes()).await
+}
+
+/// Regenerates the sitemap every [`REGENERATE_INTERVAL`] for the lifetime of
+/// the server.
+pub async fn sitemap_job(pool: PgPool, storage: SharedStorage, config: ServerConfig) {
+    let mut interval = tokio::time::interval(REGENERATE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match generate_sitemap(&pool, &*storage, &config).await {
+            Ok(()) => tracing::debug!("Regenerated sitemap"),
+            Err(error) => tracing::error!(?error, "Failed to regenerate sitemap"),
+        }
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,366 @@
+use std::{num::NonZeroUsize, sync::Arc};
+
+use axum::{
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use deadpool_redis::Pool as RedisPool;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tera::{Context, Tera};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    error::{ErrorCode, PhsError},
+    self_check::{load_templates, template_statuses},
+};
+
+/// Bumped to invalidate every cached render at once, as old entries can't be
+/// found from a new generation and are left to expire.
+const GENERATION_KEY: &str = "renders:generation";
+
+/// How long a cached render lives, in seconds, if nothing invalidates it first.
+const CACHE_TTL: u64 = 60 * 60 * 24;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/templates", get(get_templates))
+        .route("/templates/reload", post(post_reload_templates))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_templates, post_reload_templates))]
+struct TemplatesApi;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    TemplatesApi::openapi()
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct TemplateStatus {
+    /// Its path under `pages/templates`, as pages refer to it.
+    name: String,
+    /// Why it doesn't parse, or null if it does.
+    error: Option<String>,
+    /// Whether renders use it now. Templates added or fixed since the last
+    /// load aren't until they're reloaded.
+    loaded: bool,
+}
+
+/// Every template in `pages/templates`, and whether each parses. Templates
+/// that are only built in aren't listed.
+#[utoipa::path(
+    get,
+    path = "/templates",
+    tag = "templates",
+    responses(
+        (status = 200, body = Vec<TemplateStatus>),
+        (status = 403, description = "Missing the `ManagePages` permission"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(tera, _auth_session))]
+async fn get_templates(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(tera): Extension<Arc<TeraPool>>,
+) -> Result<Json<Vec<TemplateStatus>>, PhsError> {
+    let statuses = tokio::task::spawn_blocking(template_statuses)
+        .await?
+        .map_err(|e| PhsError::internal(e, "Failed to read the templates"))?;
+    let loaded = tera.template_names();
+
+    Ok(Json(
+        statuses
+            .into_iter()
+            .map(|(name, error)| TemplateStatus {
+                loaded: loaded.contains(&name),
+                name,
+                error,
+            })
+            .collect(),
+    ))
+}
+
+/// Loads the templates again from `pages/templates`, so changes to them show
+/// in previews and deploys without a restart. Deployed pages aren't
+/// re-rendered. If any template doesn't parse, the ones in use are kept.
+#[utoipa::path(
+    post,
+    path = "/templates/reload",
+    tag = "templates",
+    responses(
+        (status = 200),
+        (status = 403, description = "Missing the `ManagePages` permission"),
+        (status = 422, description = "A template doesn't parse"),
+    ),
+    security(("session" = []))
+)]
+#[instrument(skip(tera, _auth_session))]
+async fn post_reload_templates(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManagePages as u8 }>,
+
+    Extension(tera): Extension<Arc<TeraPool>>,
+) -> Result<(), PhsError> {
+    let templates = tokio::task::spawn_blocking(load_templates)
+        .await?
+        .map_err(|e| PhsError::client(ErrorCode::InvalidTemplate, e.problems().join("; ")))?;
+
+    tera.reload(templates).await;
+
+    tracing::info!("Reloaded page templates");
+
+    Ok(())
+}
+
+/// A fixed number of [`Tera`] instances shared between handlers.
+///
+/// `Tera::render_str` needs `&mut Tera`, so a single shared instance serialises
+/// every render. Each instance here is handed out to one render at a time, and
+/// the semaphore bounds how many pages render at once.
+///
+/// Each instance is tagged with the generation of templates it was cloned
+/// from, and swapped for the latest when it's next handed out after a
+/// [`Self::reload`], so renders already under way finish with what they
+/// started with.
+///
+/// Instances are handed out in a [`Checkout`], which puts them back however
+/// the render ends, so the pool never shrinks.
+pub struct TeraPool {
+    idle: parking_lot::Mutex<Vec<(u64, Tera)>>,
+    latest: parking_lot::Mutex<(u64, Tera)>,
+    available: Arc<Semaphore>,
+    cache: Option<RedisPool>,
+}
+
+impl TeraPool {
+    #[must_use]
+    pub fn new(tera: Tera, size: NonZeroUsize) -> Self {
+        let size = size.get();
+
+        Self {
+            idle: parking_lot::Mutex::new(vec![(0, tera.clone()); size]),
+            latest: parking_lot::Mutex::new((0, tera)),
+            available: Arc::new(Semaphore::new(size)),
+            cache: None,
+        }
+    }
+
+    /// Caches the output of [`Self::render_str_cached`] in Redis.
+    #[must_use]
+    pub fn with_render_cache(mut self, redis: RedisPool) -> Self {
+        self.cache = Some(redis);
+        self
+    }
+
+    /// One instance per available core, which is as parallel as rendering gets.
+    #[must_use]
+    pub fn with_available_parallelism(tera: Tera) -> Self {
+        Self::new(
+            tera,
+            std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+        )
+    }
+
+    /// Renders a one-off template on a blocking thread, waiting for a free
+    /// instance first.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the template doesn't parse or render, or the render panics.
+    pub async fn render_str(
+        self: &Arc<Self>,
+        template: String,
+        context: Context,
+    ) -> Result<String, PhsError> {
+        self.with_instance(move |tera| tera.render_str(&template, &context))
+            .await
+    }
+
+    /// As [`Self::render_str`], but reusing an earlier render of the same
+    /// template and context if there is one. Cached renders are dropped by
+    /// [`Self::invalidate_cache`], which should follow anything that changes
+    /// what a render could pull in from elsewhere. If Redis is unavailable,
+    /// this renders as normal.
+    pub(crate) async fn render_str_cached(
+        self: &Arc<Self>,
+        template: String,
+        context: Context,
+    ) -> Result<String, PhsError> {
+        let Some(redis) = &self.cache else {
+            return self.render_str(template, context).await;
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(template.as_bytes());
+        hasher.update(context.clone().into_json().to_string().as_bytes());
+        let hash = hex::encode(hasher.finalize());
+
+        let cached = async {
+            let mut conn = redis.get().await?;
+            let generation = redis::cmd("GET")
+                .arg(GENERATION_KEY)
+                .query_async::<Option<u64>>(&mut conn)
+                .await?
+                .unwrap_or_default();
+            let key = format!("renders:{generation}:{hash}");
+
+            let hit = redis::cmd("GET")
+                .arg(&key)
+                .query_async::<Option<String>>(&mut conn)
+                .await?;
+
+            Ok::<_, PhsError>((conn, key, hit))
+        }
+        .await;
+
+        let (mut conn, key) = match cached {
+            Ok((_, _, Some(hit))) => return Ok(hit),
+            Ok((conn, key, None)) => (conn, key),
+            Err(error) => {
+                tracing::warn!(?error, "Render cache unavailable");
+                return self.render_str(template, context).await;
+            }
+        };
+
+        let rendered = self.render_str(template, context).await?;
+
+        if let Err(error) = redis::cmd("SET")
+            .arg(&key)
+            .arg(&rendered)
+            .arg("EX")
+            .arg(CACHE_TTL)
+            .query_async::<()>(&mut conn)
+            .await
+        {
+            tracing::warn!(?error, "Failed to cache render");
+        }
+
+        Ok(rendered)
+    }
+
+    /// Drops every render cached by [`Self::render_str_cached`].
+    pub(crate) async fn invalidate_cache(&self) {
+        let Some(redis) = &self.cache else {
+            return;
+        };
+
+        let result = async {
+            let mut conn = redis.get().await?;
+            redis::cmd("INCR")
+                .arg(GENERATION_KEY)
+                .query_async::<u64>(&mut conn)
+                .await?;
+
+            Ok::<_, PhsError>(())
+        }
+        .await;
+
+        if let Err(error) = result {
+            tracing::error!(?error, "Failed to invalidate render cache");
+        }
+    }
+
+    /// Switches every instance over to `tera`, as each is next handed out, and
+    /// drops cached renders made with the old templates.
+    pub(crate) async fn reload(&self, tera: Tera) {
+        {
+            let mut latest = self.latest.lock();
+            *latest = (latest.0 + 1, tera);
+        }
+
+        self.invalidate_cache().await;
+    }
+
+    /// The names of the templates renders use now.
+    pub(crate) fn template_names(&self) -> Vec<String> {
+        self.latest
+            .lock()
+            .1
+            .get_template_names()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Renders one of the templates loaded from `pages/templates`.
+    pub(crate) async fn render(
+        self: &Arc<Self>,
+        template_name: String,
+        context: Context,
+    ) -> Result<String, PhsError> {
+        self.with_instance(move |tera| tera.render(&template_name, &context))
+            .await
+    }
+
+    /// Runs `f` on a blocking thread with a free instance, waiting for one
+    /// first if they're all in use.
+    ///
+    /// The instance and its permit go to the blocking thread in a
+    /// [`Checkout`], so if this future is dropped part way, e.g. by a timeout
+    /// or a client disconnecting, the render still finishes and puts them
+    /// back.
+    async fn with_instance<F>(self: &Arc<Self>, f: F) -> Result<String, PhsError>
+    where
+        F: FnOnce(&mut Tera) -> tera::Result<String> + Send + 'static,
+    {
+        let permit = self
+            .available
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| PhsError::bug("Template pool has been closed"))?;
+
+        let (mut generation, mut tera) = self.idle.lock().pop().ok_or(PhsError::bug(
+            "Template pool had a permit but no idle instance",
+        ))?;
+
+        {
+            let latest = self.latest.lock();
+            if latest.0 != generation {
+                (generation, tera) = (latest.0, latest.1.clone());
+            }
+        }
+
+        let mut checkout = Checkout {
+            pool: self.clone(),
+            instance: None,
+            _permit: permit,
+        };
+
+        let rendered = tokio::task::spawn_blocking(move || {
+            let rendered = f(&mut tera);
+            // Only reached if `f` didn't panic, leaving `tera` as it was
+            checkout.instance = Some((generation, tera));
+            rendered
+        })
+        .await?;
+
+        rendered.map_err(Into::into)
+    }
+}
+
+/// An instance out of the pool, and the permit that goes with it, put back
+/// when it's dropped. If the render panicked, the instance may have been left
+/// half way through changing, so a fresh clone of the latest goes back in its
+/// place.
+struct Checkout {
+    pool: Arc<TeraPool>,
+    instance: Option<(u64, Tera)>,
+    /// Released after the instance is back, as fields drop after `drop`.
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        let instance = self.instance.take().unwrap_or_else(|| {
+            let latest = self.pool.latest.lock();
+            (latest.0, latest.1.clone())
+        });
+        self.pool.idle.lock().push(instance);
+    }
+}
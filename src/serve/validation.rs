@@ -0,0 +1,197 @@
+use axum::http::Uri;
+
+use crate::validation::{FieldErrors, Validate};
+
+use super::{DynamicPageData, DynamicPageElement, TextComponent};
+
+/// Most elements a single page can have.
+const MAX_ELEMENTS: usize = 500;
+
+/// Most characters of text in a single element.
+const MAX_ELEMENT_LENGTH: usize = 10_000;
+
+/// Schemes links may use besides site-relative paths and `#anchors`. Anything
+/// else, notably `javascript:`, is refused.
+const ALLOWED_SCHEMES: &[&str] = &["http", "https", "mailto", "tel"];
+
+#[derive(Debug)]
+pub struct ValidationIssue {
+    /// Index of the offending page, when several are validated together.
+    page: Option<usize>,
+    /// Index of the offending element, or `None` for problems with the page
+    /// as a whole.
+    element: Option<usize>,
+    problem: String,
+}
+
+impl ValidationIssue {
+    pub(super) fn whole_page(problem: impl Into<String>) -> Self {
+        Self {
+            page: None,
+            element: None,
+            problem: problem.into(),
+        }
+    }
+
+    fn element(index: usize, problem: impl Into<String>) -> Self {
+        Self {
+            page: None,
+            element: Some(index),
+            problem: problem.into(),
+        }
+    }
+
+    pub(super) const fn in_page(mut self, index: usize) -> Self {
+        self.page = Some(index);
+        self
+    }
+}
+
+/// Each issue goes under `pages[<page>].data[<element>]`, leaving out
+/// whichever isn't known.
+impl FromIterator<ValidationIssue> for FieldErrors {
+    fn from_iter<I: IntoIterator<Item = ValidationIssue>>(iter: I) -> Self {
+        iter.into_iter()
+            .map(|issue| {
+                let page = issue.page.map(|page| format!("pages[{page}]"));
+                let element = issue.element.map(|element| format!("data[{element}]"));
+                let field = page.into_iter().chain(element).collect::<Vec<_>>();
+
+                (field.join("."), issue.problem)
+            })
+            .collect()
+    }
+}
+
+impl Validate for DynamicPageData {
+    fn validate(&self) -> FieldErrors {
+        page_issues(self).into_iter().collect()
+    }
+}
+
+/// Problems with elements kept somewhere other than a page, such as a
+/// vacancy's description, each under `<field>[<element>]`.
+pub(crate) fn element_errors(field: &str, data: &DynamicPageData) -> FieldErrors {
+    page_issues(data)
+        .into_iter()
+        .map(|issue| {
+            let path = issue
+                .element
+                .map_or_else(|| field.to_owned(), |element| format!("{field}[{element}]"));
+
+            (path, issue.problem)
+        })
+        .collect()
+}
+
+/// What's wrong with a page's elements, kept apart from [`Validate`] so a
+/// bundle can say which page each issue is in.
+pub(super) fn page_issues(data: &DynamicPageData) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+
+    if data.len() > MAX_ELEMENTS {
+        issues.push(ValidationIssue::whole_page(format!(
+            "Pages can have at most {MAX_ELEMENTS} elements"
+        )));
+    }
+
+    // Headers may go back up any number of levels, but only down one at a
+    // time, so screen readers can follow the outline
+    let mut previous_level = 1;
+
+    for (i, element) in data.iter().enumerate() {
+        let components = match element {
+            DynamicPageElement::Header { size, contents } => {
+                if contents.trim().is_empty() {
+                    issues.push(ValidationIssue::element(i, "Header is empty"));
+                }
+
+                if size.level() > previous_level + 1 {
+                    issues.push(ValidationIssue::element(
+                        i,
+                        format!(
+                            "Header skips from level {previous_level} to {}",
+                            size.level()
+                        ),
+                    ));
+                }
+                previous_level = size.level();
+
+                if contents.chars().count() > MAX_ELEMENT_LENGTH {
+                    issues.push(ValidationIssue::element(i, "Header is too long"));
+                }
+
+                continue;
+            }
+            DynamicPageElement::Text { components } => {
+                if is_blank(components) {
+                    issues.push(ValidationIssue::element(i, "Text is empty"));
+                }
+
+                components.iter().collect::<Vec<_>>()
+            }
+            DynamicPageElement::List { items, .. } => {
+                if items.is_empty() {
+                    issues.push(ValidationIssue::element(i, "List has no items"));
+                } else if items.iter().any(|item| is_blank(item)) {
+                    issues.push(ValidationIssue::element(i, "List has an empty item"));
+                }
+
+                items.iter().flatten().collect()
+            }
+        };
+
+        let length = components
+            .iter()
+            .map(|component| component.content.chars().count())
+            .sum::<usize>();
+        if length > MAX_ELEMENT_LENGTH {
+            issues.push(ValidationIssue::element(
+                i,
+                format!("Element is longer than {MAX_ELEMENT_LENGTH} characters"),
+            ));
+        }
+
+        for link in components.iter().filter_map(|c| c.link.as_deref()) {
+            if !is_valid_link(link) {
+                issues.push(ValidationIssue::element(
+                    i,
+                    format!("Link `{link}` isn't a valid URL"),
+                ));
+            }
+        }
+    }
+
+    issues
+}
+
+fn is_blank(components: &[TextComponent]) -> bool {
+    components
+        .iter()
+        .all(|component| component.content.trim().is_empty())
+}
+
+fn is_valid_link(link: &str) -> bool {
+    if link.is_empty() || link.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+
+    // Site-relative, but not protocol-relative, which could point anywhere
+    if link.starts_with('#') {
+        return true;
+    }
+    if link.starts_with('/') {
+        return !link.starts_with("//") && link.parse::<Uri>().is_ok();
+    }
+
+    let Some((scheme, rest)) = link.split_once(':') else {
+        return false;
+    };
+
+    match scheme.to_ascii_lowercase().as_str() {
+        "http" | "https" => link
+            .parse::<Uri>()
+            .is_ok_and(|uri| uri.host().is_some_and(|host| !host.is_empty())),
+        scheme => ALLOWED_SCHEMES.contains(&scheme) && !rest.is_empty(),
+    }
+}
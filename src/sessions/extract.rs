@@ -1,8 +1,4 @@
-use axum::{
-    async_trait,
-    extract::FromRequestParts,
-    http::{request::Parts, StatusCode},
-};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
 
 use crate::error::PhsError;
 
@@ -16,9 +12,7 @@ where
     type Rejection = PhsError;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        parts.extensions.get::<Self>().cloned().ok_or(PhsError(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            None,
+        parts.extensions.get::<Self>().cloned().ok_or(PhsError::bug(
             "Can't extract session. Is `SessionManagerLayer` enabled?",
         ))
     }
@@ -1,3 +1,9 @@
+//! Sessions kept in Redis, adapted from `tower-sessions` and now the only copy
+//! of it in the tree. The payload is the logged-in [`AuthUser`], stored
+//! directly rather than as a map of values, since it's all sessions hold.
+//!
+//! [`AuthUser`]: crate::auth::AuthUser
+
 #![warn(clippy::all, nonstandard_style, missing_debug_implementations)]
 #![forbid(unsafe_code)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
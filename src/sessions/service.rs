@@ -8,12 +8,15 @@ use std::{
 };
 
 use axum::http::{Request, Response, StatusCode};
-use time::OffsetDateTime;
+use time::{Duration, OffsetDateTime};
+use tokio::sync::RwLock;
 
 use tower_cookies::{cookie::SameSite, Cookie, CookieManager, Cookies, Key};
 use tower_layer::Layer;
 use tower_service::Service;
 
+use crate::settings::ServerSettings;
+
 use super::{session, Expiry, IdType, Session, SessionStore};
 
 #[doc(hidden)]
@@ -77,6 +80,12 @@ pub struct SessionConfig<'a> {
 }
 
 impl<'a> SessionConfig<'a> {
+    /// Of the session cookie.
+    #[cfg(feature = "test_support")]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     fn build_cookie(self, session_id: session::Id, expiry: Expiry) -> Cookie<'a> {
         let mut cookie_builder = Cookie::build((self.name, session_id.to_string()))
             .http_only(self.http_only)
@@ -122,6 +131,7 @@ pub struct SessionManager<S, C: CookieController> {
     session_store: Arc<SessionStore>,
     session_config: SessionConfig<'static>,
     cookie_controller: C,
+    settings: Option<Arc<RwLock<ServerSettings>>>,
 }
 
 // impl<S> SessionManager<S> {
@@ -158,6 +168,7 @@ where
         let session_store = self.session_store.clone();
         let session_config = self.session_config.clone();
         let cookie_controller = self.cookie_controller.clone();
+        let settings = self.settings.clone();
 
         // Because the inner service can panic until ready, we need to ensure we only
         // use the ready service.
@@ -188,7 +199,14 @@ where
                         .ok()
                 });
 
-                let session = Session::new(session_id, session_store, session_config.expiry);
+                let expiry = if let Some(settings) = settings {
+                    let minutes = settings.read().await.session_lifetime;
+                    Expiry::OnInactivity(Duration::minutes(minutes.into()))
+                } else {
+                    session_config.expiry
+                };
+
+                let session = Session::new(session_id, session_store, expiry);
 
                 req.extensions_mut().insert(session.clone());
 
@@ -255,6 +273,7 @@ pub struct SessionManagerLayer<C: CookieController> {
     session_store: Arc<SessionStore>,
     session_config: SessionConfig<'static>,
     cookie_controller: C,
+    settings: Option<Arc<RwLock<ServerSettings>>>,
 }
 
 impl<C: CookieController> SessionManagerLayer<C> {
@@ -278,6 +297,13 @@ impl<C: CookieController> SessionManagerLayer<C> {
         self
     }
 
+    /// Takes the lifetime of new sessions from the live settings, overriding
+    /// [`Self::with_expiry`], so admins can change it without a restart.
+    pub fn with_settings(mut self, settings: Arc<RwLock<ServerSettings>>) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
     pub const fn with_secure(mut self, secure: bool) -> Self {
         self.session_config.secure = secure;
         self
@@ -306,6 +332,7 @@ impl SessionManagerLayer<SignedCookie> {
             session_store: Arc::new(session_store),
             session_config,
             cookie_controller: SignedCookie { key },
+            settings: None,
         }
     }
 }
@@ -317,6 +344,7 @@ impl SessionManagerLayer<PlaintextCookie> {
             session_store: Arc::new(session_store),
             session_config,
             cookie_controller: PlaintextCookie,
+            settings: None,
         }
     }
 }
@@ -330,6 +358,7 @@ impl<S, C: CookieController> Layer<S> for SessionManagerLayer<C> {
             session_store: self.session_store.clone(),
             session_config: self.session_config.clone(),
             cookie_controller: self.cookie_controller.clone(),
+            settings: self.settings.clone(),
         };
 
         CookieManager::new(session_manager)
@@ -295,6 +295,20 @@ impl Session {
         Ok(())
     }
 
+    /// Deletes every session of the user with this ID but this one, as when
+    /// their password changes, returning how many there were.
+    pub async fn delete_user_sessions(&self, user_id: i32) -> Result<usize> {
+        let current = match self.id().await {
+            IdType::Id(id) | IdType::Unloaded(id) => Some(id),
+            IdType::None => None,
+        };
+
+        self.store
+            .delete_user_sessions(user_id, current.as_ref())
+            .await
+            .map_err(Error::Store)
+    }
+
     /// Flushes the session by removing all data contained in the session and
     /// then deleting it from the store.
     pub async fn flush(&self) -> Result<()> {
@@ -4,9 +4,11 @@ use rand_core::{RngCore, SeedableRng};
 use serde::{Deserialize, Serialize};
 
 use std::{
+    collections::HashMap,
     fmt::{Debug, Display},
     sync::Arc,
 };
+use time::OffsetDateTime;
 use tokio::sync::Mutex;
 
 use crate::{
@@ -17,6 +19,10 @@ use crate::{
     },
 };
 
+/// Session keys deleted by each command when clearing a user's sessions, so
+/// no one command blocks Redis for long.
+const DELETE_CHUNK: usize = 100;
+
 #[derive(thiserror::Error, Debug)]
 pub enum SessionStoreError {
     #[error("Redis client error: {0}")]
@@ -33,13 +39,23 @@ pub enum SessionStoreError {
     NotFound,
 }
 
-/// A Redis session store.
+/// A session store, in Redis or, without it, this process.
 #[derive(Clone)]
 pub struct SessionStore {
-    client: RedisPool,
+    backend: Backend,
     csprng: Arc<Mutex<ChaCha20Rng>>,
 }
 
+#[derive(Clone)]
+enum Backend {
+    Redis(RedisPool),
+    /// Sessions by hashed ID, with when each expires, for tests and running
+    /// without Redis.
+    Memory(Arc<parking_lot::Mutex<MemorySessions>>),
+}
+
+type MemorySessions = HashMap<String, (SessionStoreData, OffsetDateTime)>;
+
 enum ExistenceFlag {
     NX,
     XX,
@@ -60,8 +76,19 @@ impl Display for ExistenceFlag {
 
 impl SessionStore {
     pub fn new(client: RedisPool) -> Self {
+        Self::with_backend(Backend::Redis(client))
+    }
+
+    /// A store that keeps sessions in this process, for tests and running
+    /// without Redis. They're lost when it stops.
+    #[must_use]
+    pub fn in_memory() -> Self {
+        Self::with_backend(Backend::Memory(Arc::default()))
+    }
+
+    fn with_backend(backend: Backend) -> Self {
         Self {
-            client,
+            backend,
             // New PRNG seeded from Linux `getrandom` or equivalent
             csprng: Arc::new(Mutex::new(ChaCha20Rng::from_entropy())),
         }
@@ -71,13 +98,40 @@ impl SessionStore {
         &self,
         id: &Id,
         data: &SessionData,
-        exists: ExistenceFlag,
+        exists_flag: ExistenceFlag,
     ) -> Result<bool, SessionStoreError> {
         let session_data = SessionStoreData::from_session_data(data).unwrap();
         let key = "sessions:".to_string() + &id.hashed_id();
 
-        let mut conn = self.client.get().await?;
+        let client = match &self.backend {
+            Backend::Redis(client) => client,
+            Backend::Memory(sessions) => {
+                let mut sessions = sessions.lock();
+                // Nothing else drops expired sessions
+                if matches!(exists_flag, ExistenceFlag::NX) {
+                    sessions.retain(|_, (_, expires)| is_live(*expires));
+                }
+                let exists = sessions
+                    .get(&key)
+                    .is_some_and(|(_, expires)| is_live(*expires));
+                let save = match exists_flag {
+                    ExistenceFlag::NX => !exists,
+                    ExistenceFlag::XX => exists,
+                };
+                if save {
+                    let expires = session_data.expiry.expiry_date();
+                    sessions.insert(key, (session_data, expires));
+                }
+                return Ok(save);
+            }
+        };
+        let mut conn = client.get().await?;
+
+        let index = user_index_key(session_data.data.id());
+        let expires = session_data.expiry.expiry_date().unix_timestamp();
 
+        // The index lives as long as the user's longest-lived session: `NX`
+        // sets its expiry the first time, and `GT` only ever extends it
         #[rustfmt::skip]
         let (set_result, expireat_result) = redis::pipe()
 
@@ -85,11 +139,15 @@ impl SessionStore {
             .arg(&key)
             .arg("$")
             .arg(serde_json::to_string(&session_data).unwrap())
-            .arg(exists.to_string())
+            .arg(exists_flag.to_string())
 
             .cmd("EXPIREAT")
             .arg(&key)
-            .arg(session_data.expiry.expiry_date().unix_timestamp())
+            .arg(expires)
+
+            .cmd("SADD").arg(&index).arg(&key).ignore()
+            .cmd("EXPIREAT").arg(&index).arg(expires).arg("NX").ignore()
+            .cmd("EXPIREAT").arg(&index).arg(expires).arg("GT").ignore()
 
             .query_async(&mut conn).await?;
 
@@ -116,7 +174,24 @@ impl SessionStore {
 
     pub async fn load(&self, session_id: &Id) -> Result<Option<SessionData>, SessionStoreError> {
         let key = "sessions:".to_string() + &session_id.hashed_id();
-        let mut conn = self.client.get().await?;
+
+        let client = match &self.backend {
+            Backend::Redis(client) => client,
+            Backend::Memory(sessions) => {
+                let data = sessions
+                    .lock()
+                    .get(&key)
+                    .filter(|(_, expires)| is_live(*expires))
+                    .map(|(data, _)| data.clone())
+                    .ok_or(SessionStoreError::NotFound)?;
+                return Ok(Some(SessionData::new(
+                    *session_id,
+                    Some(data.data),
+                    data.expiry,
+                )));
+            }
+        };
+        let mut conn = client.get().await?;
 
         let query = redis::cmd("JSON.GET")
             .arg(key)
@@ -148,7 +223,15 @@ impl SessionStore {
 
     pub async fn delete(&self, session_id: &Id) -> Result<(), SessionStoreError> {
         let key = "sessions:".to_string() + &session_id.hashed_id();
-        let mut conn = self.client.get().await?;
+
+        let client = match &self.backend {
+            Backend::Redis(client) => client,
+            Backend::Memory(sessions) => {
+                sessions.lock().remove(&key);
+                return Ok(());
+            }
+        };
+        let mut conn = client.get().await?;
 
         redis::cmd("JSON.DEL")
             .arg(key)
@@ -159,6 +242,66 @@ impl SessionStore {
         Ok(())
     }
 
+    /// Deletes every session belonging to the user but `except`, returning
+    /// how many there were.
+    ///
+    /// Sessions are found through a set of their keys kept per user, which
+    /// keeps this quick however many sessions there are. Keys of sessions
+    /// that have since expired or logged out are left in it, and deleting
+    /// them is harmless. Deletes are sent in chunks in a single pipeline.
+    pub async fn delete_user_sessions(
+        &self,
+        user_id: i32,
+        except: Option<&Id>,
+    ) -> Result<usize, SessionStoreError> {
+        let except = except.map(|id| "sessions:".to_string() + &id.hashed_id());
+
+        let client = match &self.backend {
+            Backend::Redis(client) => client,
+            Backend::Memory(sessions) => {
+                let mut sessions = sessions.lock();
+                let before = sessions.len();
+                sessions.retain(|key, (data, _)| {
+                    data.data.id() != user_id || except.as_ref() == Some(key)
+                });
+                return Ok(before - sessions.len());
+            }
+        };
+        let mut conn = client.get().await?;
+
+        let index = user_index_key(user_id);
+        let keys: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(&index)
+            .query_async::<Vec<String>>(&mut conn)
+            .await?
+            .into_iter()
+            .filter(|key| except.as_ref() != Some(key))
+            .collect();
+
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let mut pipe = redis::pipe();
+        for chunk in keys.chunks(DELETE_CHUNK) {
+            pipe.cmd("UNLINK").arg(chunk).ignore();
+            pipe.cmd("SREM").arg(&index).arg(chunk).ignore();
+        }
+        pipe.query_async::<()>(&mut conn).await?;
+
+        Ok(keys.len())
+    }
+
+    /// Stores a session for `user` as logging in would, returning the ID to
+    /// put in the session cookie.
+    #[cfg(feature = "test_support")]
+    pub async fn mint(&self, user: AuthUser, expiry: Expiry) -> Result<String, SessionStoreError> {
+        let id = self
+            .create(&SessionData::new(Id::new(0), Some(user), expiry))
+            .await?;
+        Ok(id.to_string())
+    }
+
     async fn new_id(&self) -> Result<Id, SessionStoreError> {
         let mut slice = [0_u8; 16];
         self.csprng.lock().await.try_fill_bytes(&mut slice)?;
@@ -166,7 +309,7 @@ impl SessionStore {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct SessionStoreData {
     #[serde(flatten)]
     data: AuthUser,
@@ -182,3 +325,13 @@ impl SessionStoreData {
         })
     }
 }
+
+/// The set of a user's session keys.
+fn user_index_key(user_id: i32) -> String {
+    format!("user_sessions:{user_id}")
+}
+
+/// Redis drops expired keys itself, but memory has to check.
+fn is_live(expires: OffsetDateTime) -> bool {
+    expires > OffsetDateTime::now_utc()
+}
@@ -1,4 +1,248 @@
+use std::{io, path::Path, sync::Arc};
+
+use axum::{routing::get, Extension, Json, Router};
 use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::instrument;
+
+use crate::{
+    auth::{AuthSession, Permission, RequirePermission},
+    error::{ErrorCode, PhsError},
+    ConfigError, ServerConfig,
+};
+
+/// Longest an idle session can be allowed to last, in minutes: 30 days.
+const MAX_SESSION_LIFETIME: u32 = 30 * 24 * 60;
+
+/// Most calendar feeds that can be synced at once.
+const MAX_CALENDAR_FEEDS: usize = 20;
+
+/// Longest a calendar feed's name can be, as stored on its events.
+const MAX_FEED_NAME_LENGTH: usize = 255;
+
+pub fn router() -> Router {
+    Router::new().route("/settings", get(get_settings).patch(patch_settings))
+}
+
+/// Site-wide options admins can change while the server is running, kept in
+/// the file at [`ServerConfig::settings_path`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// Minutes a session lasts without being used. Changes apply to sessions
+    /// started afterwards.
+    pub session_lifetime: u32,
+    /// Shown to visitors who need to get in touch with the school.
+    pub contact_email: Option<String>,
+    pub features: FeatureToggles,
+    /// Other calendars whose events are copied into ours every hour. Events
+    /// from a feed that's taken away are deleted at the next sync.
+    pub calendar_feeds: Vec<CalendarFeed>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            session_lifetime: 2 * 60,
+            contact_email: None,
+            features: FeatureToggles::default(),
+            calendar_feeds: Vec::new(),
+        }
+    }
+}
+
+/// An iCalendar feed, such as the trust's calendar or a sports league's
+/// fixtures.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CalendarFeed {
+    /// Tags the events copied from the feed, so must be unique. Renaming a
+    /// feed deletes its events and copies them in again.
+    pub name: String,
+    pub url: String,
+    /// The department the feed's events are for, or the whole school.
+    #[serde(default)]
+    pub department: Option<i32>,
+}
+
+/// Optional parts of the site that can be switched off.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureToggles {
+    /// The public `/v1/search` endpoint.
+    pub search: bool,
+    /// The daily broken link check.
+    pub link_checker: bool,
+    /// Pages can only be deployed, and posts only go live, once someone with
+    /// the `ApproveContent` permission other than whoever changed them has
+    /// approved them. Anything still awaiting approval when this is switched
+    /// off stays hidden until it's approved.
+    pub approval: bool,
+    /// Counting views of deployed pages, for visitors who've agreed to it.
+    pub analytics: bool,
+}
+
+impl Default for FeatureToggles {
+    fn default() -> Self {
+        Self {
+            search: true,
+            link_checker: true,
+            approval: false,
+            analytics: false,
+        }
+    }
+}
+
+impl ServerSettings {
+    /// Reads settings saved by [`Self::save`], or the defaults if nothing has
+    /// been saved yet.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file exists but can't be read or parsed.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(ConfigError::Read {
+                path: path.to_owned(),
+                source,
+            }),
+        }
+    }
+
+    /// Writes the settings to a temporary file then moves it into place, so a
+    /// crash part way through can't leave them half written.
+    async fn save(&self, path: &Path) -> Result<(), PhsError> {
+        let contents = toml::to_string_pretty(self)
+            .map_err(|e| PhsError::internal(e, "Failed to serialise settings"))?;
+
+        let temporary = path.with_extension("toml.tmp");
+        tokio::fs::write(&temporary, contents).await?;
+        tokio::fs::rename(&temporary, path).await?;
+
+        Ok(())
+    }
+}
+
+#[instrument(skip(settings, _auth_session))]
+async fn get_settings(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(settings): Extension<Arc<RwLock<ServerSettings>>>,
+) -> Json<ServerSettings> {
+    Json(settings.read().await.clone())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct SettingsPatch {
+    session_lifetime: Option<u32>,
+    /// An empty string clears the address.
+    contact_email: Option<String>,
+    #[serde(default)]
+    features: FeatureTogglesPatch,
+    /// Replaces the whole list.
+    calendar_feeds: Option<Vec<CalendarFeed>>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct FeatureTogglesPatch {
+    search: Option<bool>,
+    link_checker: Option<bool>,
+    approval: Option<bool>,
+    analytics: Option<bool>,
+}
+
+/// Changes only the settings given, saving them before they take effect.
+#[instrument(skip(settings, config, _auth_session))]
+async fn patch_settings(
+    _auth_session: AuthSession,
+    _: RequirePermission<{ Permission::ManageSettings as u8 }>,
+
+    Extension(settings): Extension<Arc<RwLock<ServerSettings>>>,
+    Extension(config): Extension<ServerConfig>,
+    Json(patch): Json<SettingsPatch>,
+) -> Result<Json<ServerSettings>, PhsError> {
+    if patch
+        .session_lifetime
+        .is_some_and(|minutes| minutes == 0 || minutes > MAX_SESSION_LIFETIME)
+    {
+        return Err(PhsError::client(
+            ErrorCode::InvalidSetting,
+            "Session lifetime must be between a minute and 30 days",
+        ));
+    }
+
+    if let Some(email) = patch.contact_email.as_deref().filter(|e| !e.is_empty()) {
+        if !email.contains('@') || email.chars().any(char::is_whitespace) {
+            return Err(PhsError::client(
+                ErrorCode::InvalidSetting,
+                "Contact email isn't a valid address",
+            ));
+        }
+    }
+
+    if let Some(feeds) = &patch.calendar_feeds {
+        if let Some(problem) = calendar_feed_problem(feeds) {
+            return Err(PhsError::client(ErrorCode::InvalidSetting, problem));
+        }
+    }
+
+    // Held throughout, so concurrent patches can't save over one another
+    let mut settings = settings.write().await;
+
+    let mut updated = settings.clone();
+    if let Some(minutes) = patch.session_lifetime {
+        updated.session_lifetime = minutes;
+    }
+    if let Some(email) = patch.contact_email {
+        updated.contact_email = Some(email).filter(|e| !e.is_empty());
+    }
+    if let Some(search) = patch.features.search {
+        updated.features.search = search;
+    }
+    if let Some(link_checker) = patch.features.link_checker {
+        updated.features.link_checker = link_checker;
+    }
+    if let Some(approval) = patch.features.approval {
+        updated.features.approval = approval;
+    }
+    if let Some(analytics) = patch.features.analytics {
+        updated.features.analytics = analytics;
+    }
+    if let Some(feeds) = patch.calendar_feeds {
+        updated.calendar_feeds = feeds;
+    }
+
+    updated.save(&config.settings_path).await?;
+    tracing::info!(?updated, "Settings changed");
+
+    *settings = updated.clone();
+    drop(settings);
+
+    Ok(Json(updated))
+}
+
+/// What's wrong with a list of calendar feeds, if anything.
+fn calendar_feed_problem(feeds: &[CalendarFeed]) -> Option<&'static str> {
+    if feeds.len() > MAX_CALENDAR_FEEDS {
+        return Some("There can be at most 20 calendar feeds");
+    }
+
+    for (i, feed) in feeds.iter().enumerate() {
+        if feed.name.trim().is_empty() || feed.name.chars().count() > MAX_FEED_NAME_LENGTH {
+            return Some("Calendar feed names must be between 1 and 255 characters");
+        }
+        if feeds[..i].iter().any(|other| other.name == feed.name) {
+            return Some("Calendar feed names must be unique");
+        }
+        if !(feed.url.starts_with("http://") || feed.url.starts_with("https://")) {
+            return Some("Calendar feed URLs must start with http:// or https://");
+        }
+    }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-pub struct ServerSettings {}
+    None
+}
@@ -0,0 +1,192 @@
+//! Links that let someone without an account see a page or post before it
+//! goes live, until the link expires, such as a head approving a draft.
+//!
+//! A link says what it's for and when it expires, signed with the
+//! [`ShareKey`], so nothing about it is stored and it can't be changed to
+//! show anything else. Replacing the key file and restarting revokes every
+//! link at once.
+
+use std::{fmt, fs, io, path::Path, sync::Arc};
+
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use time::{Duration, OffsetDateTime};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::{ErrorCode, PhsError},
+    validation::{FieldErrors, Validate},
+};
+
+const KEY_LENGTH: usize = 32;
+
+/// How long a link works for if `days` isn't given.
+const DEFAULT_DAYS: u32 = 7;
+
+/// Longest a link can be made to work for.
+const MAX_DAYS: u32 = 30;
+
+/// The key share links are signed with.
+#[derive(Clone)]
+pub struct ShareKey(Arc<[u8; KEY_LENGTH]>);
+
+impl ShareKey {
+    /// Reads the key, making one if there isn't one yet, so links keep
+    /// working across restarts.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or written, or isn't a key.
+    pub fn load_or_create(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => bytes
+                .try_into()
+                .map(|key| Self(Arc::new(key)))
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Not a share key")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let key = Self::generate();
+
+                let mut options = fs::OpenOptions::new();
+                options.write(true).create_new(true);
+                // Anyone who can read it can share anything
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+                io::Write::write_all(&mut options.open(path)?, key.0.as_slice())?;
+                tracing::info!("Wrote a new share key to {}", path.display());
+
+                Ok(key)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A new random key, kept only in memory.
+    #[must_use]
+    pub fn generate() -> Self {
+        let mut key = [0; KEY_LENGTH];
+        OsRng.fill_bytes(&mut key);
+        Self(Arc::new(key))
+    }
+
+    fn mac(&self, kind: SharedKind, id: i32, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.0.as_slice())
+            .expect("HMAC accepts keys of any length");
+        mac.update(format!("{kind}:{id}:{expires}").as_bytes());
+        mac
+    }
+
+    /// A link to `{path}/share` showing the item, under the first version of
+    /// the API, that works for `days` days.
+    pub(crate) fn link(
+        &self,
+        site_url: &str,
+        path: &str,
+        kind: SharedKind,
+        id: i32,
+        days: u32,
+    ) -> ShareLink {
+        let expires_at = OffsetDateTime::now_utc() + Duration::days(days.into());
+        let expires = expires_at.unix_timestamp();
+        let signature = hex::encode(self.mac(kind, id, expires).finalize().into_bytes());
+
+        ShareLink {
+            url: format!(
+                "{}{}{path}/share?expires={expires}&signature={signature}",
+                site_url.trim_end_matches('/'),
+                crate::versions::VERSIONS[0]
+            ),
+            expires_at,
+        }
+    }
+
+    /// Checks `params` came from [`Self::link`] for this item, and that it
+    /// hasn't expired.
+    pub(crate) fn verify(
+        &self,
+        kind: SharedKind,
+        id: i32,
+        params: &ShareParams,
+    ) -> Result<(), PhsError> {
+        let valid = hex::decode(&params.signature).is_ok_and(|signature| {
+            self.mac(kind, id, params.expires)
+                .verify_slice(&signature)
+                .is_ok()
+        });
+
+        if !valid {
+            return Err(PhsError::client(
+                ErrorCode::InvalidShareLink,
+                "This link isn't valid",
+            ));
+        }
+        if params.expires < OffsetDateTime::now_utc().unix_timestamp() {
+            return Err(PhsError::client(
+                ErrorCode::InvalidShareLink,
+                "This link has expired",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// What a link can be for. Each only ever verifies as the one it was made
+/// for.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum SharedKind {
+    Page,
+    Post,
+}
+
+impl fmt::Display for SharedKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Page => "page",
+            Self::Post => "post",
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub(crate) struct ShareBody {
+    /// How many days the link works for, 7 by default and at most 30.
+    days: Option<u32>,
+}
+
+impl ShareBody {
+    pub(crate) fn days(&self) -> u32 {
+        self.days.unwrap_or(DEFAULT_DAYS)
+    }
+}
+
+impl Validate for ShareBody {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        errors.check(
+            (1..=MAX_DAYS).contains(&self.days()),
+            "days",
+            format!("Must be between 1 and {MAX_DAYS}"),
+        );
+        errors
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub(crate) struct ShareLink {
+    /// Works for anyone who has it, logged in or not.
+    url: String,
+    #[serde(with = "time::serde::iso8601")]
+    expires_at: OffsetDateTime,
+}
+
+/// The query string of a link from [`ShareKey::link`].
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ShareParams {
+    /// When the link stops working, as a Unix timestamp.
+    expires: i64,
+    signature: String,
+}
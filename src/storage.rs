@@ -0,0 +1,235 @@
+use std::{sync::Arc, time::SystemTime};
+
+use axum::{
+    async_trait,
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use httpdate::HttpDate;
+use sha2::{Digest, Sha256};
+
+use crate::{config::StorageConfig, error::PhsError, etag::if_none_match};
+
+mod compression;
+mod local;
+#[cfg(feature = "test_support")]
+mod memory;
+mod s3;
+
+use compression::accepted_encodings;
+pub use {
+    compression::{precompress, PRECOMPRESSED},
+    local::LocalStorage,
+    s3::S3Storage,
+};
+
+#[cfg(feature = "test_support")]
+pub use memory::MemoryStorage;
+
+/// An object along with what's known about when it last changed.
+pub struct StoredObject {
+    pub data: Vec<u8>,
+    pub last_modified: Option<SystemTime>,
+}
+
+/// Where page specs, fragments, deployed pages and media live.
+///
+/// Keys are `/`-separated paths relative to the root of the store, such as
+/// `pages/fragments/about.html` or `media/12/photo.jpg`, mirroring the on-disk
+/// layout used by [`LocalStorage`].
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Returns `None` if nothing is stored under `key`.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PhsError>;
+
+    /// As [`Storage::get`], but also fetching the modification time if the
+    /// backend keeps one.
+    async fn get_object(&self, key: &str) -> Result<Option<StoredObject>, PhsError> {
+        Ok(self.get(key).await?.map(|data| StoredObject {
+            data,
+            last_modified: None,
+        }))
+    }
+
+    /// Stores `data` under `key`, replacing anything already there. Readers
+    /// never see a partially written object.
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), PhsError>;
+
+    /// Removes `key`. Removing something that doesn't exist isn't an error.
+    async fn delete(&self, key: &str) -> Result<(), PhsError>;
+
+    /// Moves an object, returning `false` if there was nothing at `from`.
+    async fn rename(&self, from: &str, to: &str) -> Result<bool, PhsError>;
+
+    /// Every key starting with `prefix`, which should normally end in `/`.
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, PhsError>;
+
+    /// Removes every key starting with `prefix`.
+    async fn delete_all(&self, prefix: &str) -> Result<(), PhsError> {
+        for key in self.list(prefix).await? {
+            self.delete(&key).await?;
+        }
+
+        Ok(())
+    }
+}
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+impl StorageConfig {
+    /// Sets up the configured backend. S3 credentials are read from the
+    /// `S3_ACCESS_KEY_ID` and `S3_SECRET_ACCESS_KEY` environment variables.
+    ///
+    /// With `embedded_assets`, files missing from `pages/dist` are served from
+    /// the copy built into the binary.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the S3 endpoint is invalid or credentials are missing.
+    pub fn connect(&self) -> Result<SharedStorage, Box<dyn std::error::Error>> {
+        let storage: SharedStorage = match self {
+            Self::Local { root } => Arc::new(LocalStorage::new(root.clone())),
+            Self::S3 {
+                endpoint,
+                bucket,
+                region,
+            } => Arc::new(S3Storage::new(
+                endpoint,
+                bucket.clone(),
+                region.clone(),
+                dotenv::var("S3_ACCESS_KEY_ID").map_err(|_| "S3_ACCESS_KEY_ID not set")?,
+                dotenv::var("S3_SECRET_ACCESS_KEY").map_err(|_| "S3_SECRET_ACCESS_KEY not set")?,
+            )?),
+        };
+
+        #[cfg(feature = "embedded_assets")]
+        let storage = Arc::new(crate::embedded::EmbeddedFallback(storage));
+
+        Ok(storage)
+    }
+}
+
+/// Whether a key taken from a request is safe to look up, i.e. has no empty,
+/// hidden or `..` segments that could escape its prefix.
+pub fn is_safe_key(key: &str) -> bool {
+    key.split('/')
+        .all(|segment| !segment.is_empty() && !segment.starts_with('.'))
+}
+
+/// Builds a response serving the object at `key`, with a `Content-Type` guessed
+/// from its extension and the given `Cache-Control`. Returns `None` if there's
+/// nothing there.
+///
+/// Every response carries an `ETag`, plus `Last-Modified` where the backend
+/// knows it, and a request whose `If-None-Match` or `If-Modified-Since` shows
+/// it already has the current version gets an empty 304 instead.
+pub async fn stored_response(
+    storage: &dyn Storage,
+    key: &str,
+    request_headers: &HeaderMap,
+    cache_control: &str,
+) -> Result<Option<Response>, PhsError> {
+    if !is_safe_key(key) {
+        return Ok(None);
+    }
+
+    Ok(storage
+        .get_object(key)
+        .await?
+        .map(|object| object_response(key, object, None, request_headers, cache_control)))
+}
+
+/// As [`stored_response`], but serving a copy made by [`precompress`] instead
+/// when there is one the client accepts.
+pub async fn precompressed_response(
+    storage: &dyn Storage,
+    key: &str,
+    request_headers: &HeaderMap,
+    cache_control: &str,
+) -> Result<Option<Response>, PhsError> {
+    if !is_safe_key(key) {
+        return Ok(None);
+    }
+
+    let mut res = None;
+    for (coding, suffix) in accepted_encodings(request_headers) {
+        if let Some(object) = storage.get_object(&format!("{key}{suffix}")).await? {
+            res = Some(object_response(
+                key,
+                object,
+                Some(coding),
+                request_headers,
+                cache_control,
+            ));
+            break;
+        }
+    }
+
+    let res = match res {
+        Some(res) => Some(res),
+        None => stored_response(storage, key, request_headers, cache_control).await?,
+    };
+
+    Ok(res.map(|mut res| {
+        res.headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        res
+    }))
+}
+
+/// `key` decides the `Content-Type`, whilst `object` may be a compressed copy
+/// of it, in which case `encoding` names the coding used.
+fn object_response(
+    key: &str,
+    object: StoredObject,
+    encoding: Option<&'static str>,
+    request_headers: &HeaderMap,
+    cache_control: &str,
+) -> Response {
+    let etag = format!("\"{}\"", hex::encode(&Sha256::digest(&object.data)[..16]));
+    // HTTP dates only go down to the second, so compare at that precision
+    let last_modified = object.last_modified.map(HttpDate::from);
+
+    // If-Modified-Since is only looked at when there's no If-None-Match
+    let not_modified = if_none_match(request_headers, &etag).unwrap_or_else(|| {
+        request_headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|since| since.to_str().ok()?.parse::<HttpDate>().ok())
+            .zip(last_modified)
+            .is_some_and(|(since, modified)| modified <= since)
+    });
+
+    let mut res = if not_modified {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = StatusCode::NOT_MODIFIED;
+        res
+    } else {
+        let mime = mime_guess::from_path(key).first_or_octet_stream();
+
+        let mut res = Response::new(Body::from(object.data));
+        if let Ok(content_type) = HeaderValue::from_str(mime.as_ref()) {
+            res.headers_mut().insert(header::CONTENT_TYPE, content_type);
+        }
+        if let Some(encoding) = encoding {
+            res.headers_mut()
+                .insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        res
+    };
+
+    let headers = res.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        headers.insert(header::ETAG, etag);
+    }
+    if let Some(Ok(last_modified)) =
+        last_modified.map(|time| HeaderValue::from_str(&time.to_string()))
+    {
+        headers.insert(header::LAST_MODIFIED, last_modified);
+    }
+    if let Ok(cache_control) = HeaderValue::from_str(cache_control) {
+        headers.insert(header::CACHE_CONTROL, cache_control);
+    }
+
+    res
+}
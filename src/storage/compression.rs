@@ -0,0 +1,66 @@
+use std::io::Write;
+
+use axum::http::{header, HeaderMap};
+use flate2::{write::GzEncoder, Compression};
+
+/// Content codings that may be stored next to an object, in the order we'd
+/// rather serve them, along with the suffix each copy is stored under.
+pub const PRECOMPRESSED: &[(&str, &str)] = &[("br", ".br"), ("gzip", ".gz")];
+
+/// Compresses `data` once for each of [`PRECOMPRESSED`], returning the suffix
+/// and contents of each copy. Slow, so best kept off the async runtime.
+pub fn precompress(data: &[u8]) -> std::io::Result<Vec<(&'static str, Vec<u8>)>> {
+    let mut brotli = Vec::new();
+    {
+        let params = brotli::enc::BrotliEncoderParams {
+            quality: 11,
+            ..Default::default()
+        };
+        brotli::BrotliCompress(&mut &data[..], &mut brotli, &params)?;
+    }
+
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+    gzip.write_all(data)?;
+    let gzip = gzip.finish()?;
+
+    Ok(vec![(".br", brotli), (".gz", gzip)])
+}
+
+/// Which of [`PRECOMPRESSED`] the client accepts, in our order of preference.
+/// Codings are only excluded if missing or given `q=0`; we don't rank by
+/// q-value beyond that.
+pub fn accepted_encodings(headers: &HeaderMap) -> Vec<(&'static str, &'static str)> {
+    let Some(accept) = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|accept| accept.to_str().ok())
+    else {
+        return Vec::new();
+    };
+
+    let codings = accept
+        .split(',')
+        .filter_map(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next()?.to_ascii_lowercase();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+
+            Some((name, refused))
+        })
+        .collect::<Vec<_>>();
+
+    PRECOMPRESSED
+        .iter()
+        .copied()
+        .filter(|(coding, _)| {
+            let explicit = codings.iter().find(|(name, _)| name == coding);
+            let wildcard = codings.iter().find(|(name, _)| name == "*");
+
+            explicit.or(wildcard).is_some_and(|(_, refused)| !refused)
+        })
+        .collect()
+}
@@ -0,0 +1,134 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use axum::async_trait;
+
+use crate::error::{ErrorCode, PhsError};
+
+use super::{Storage, StoredObject};
+
+/// Stores everything as plain files under `root`.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub const fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, PhsError> {
+        if key.split('/').any(|segment| segment == "..") || key.starts_with('/') {
+            return Err(PhsError::client(
+                ErrorCode::InvalidStorageKey,
+                "Storage keys must be relative and can't contain '..'",
+            ));
+        }
+
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PhsError> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::IsADirectory) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_object(&self, key: &str) -> Result<Option<StoredObject>, PhsError> {
+        let path = self.path(key)?;
+
+        let Some(data) = self.get(key).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(StoredObject {
+            data,
+            last_modified: tokio::fs::metadata(path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+        }))
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), PhsError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Tempfile for psuedo-atomic writes
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(".temp");
+
+        tokio::fs::write(&temp_path, data).await?;
+        tokio::fs::rename(temp_path, path).await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), PhsError> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<bool, PhsError> {
+        let (from, to) = (self.path(from)?, self.path(to)?);
+
+        if !tokio::fs::try_exists(&from).await? {
+            return Ok(false);
+        }
+
+        if let Some(parent) = to.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        tokio::fs::rename(from, to).await?;
+
+        Ok(true)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, PhsError> {
+        // Only the directory part of the prefix can be walked; the rest is
+        // filtered on below
+        let dir = prefix.rsplit_once('/').map_or("", |(dir, _)| dir);
+
+        let mut keys = Vec::new();
+        let mut pending = vec![dir.to_owned()];
+
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(self.path(&dir)?).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+
+                let key = if dir.is_empty() {
+                    name
+                } else {
+                    format!("{dir}/{name}")
+                };
+
+                if entry.file_type().await?.is_dir() {
+                    pending.push(key);
+                } else if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        keys.sort_unstable();
+
+        Ok(keys)
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+
+use axum::async_trait;
+use parking_lot::Mutex;
+
+use crate::error::PhsError;
+
+use super::Storage;
+
+/// Keeps everything in this process, so tests don't touch the disk or each
+/// other's files.
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PhsError> {
+        Ok(self.objects.lock().get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), PhsError> {
+        self.objects.lock().insert(key.to_owned(), data);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), PhsError> {
+        self.objects.lock().remove(key);
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<bool, PhsError> {
+        let mut objects = self.objects.lock();
+
+        let Some(data) = objects.remove(from) else {
+            return Ok(false);
+        };
+        objects.insert(to.to_owned(), data);
+
+        Ok(true)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, PhsError> {
+        Ok(self
+            .objects
+            .lock()
+            .range(prefix.to_owned()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+}
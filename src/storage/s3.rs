@@ -0,0 +1,390 @@
+use std::{
+    fmt::{Debug, Write},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    http::{header, HeaderMap, Method, Request, StatusCode, Uri},
+};
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full};
+use hyper_util::rt::TokioIo;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use time::{macros::format_description, OffsetDateTime};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{self, pki_types::ServerName, RootCertStore},
+    TlsConnector,
+};
+
+use crate::error::PhsError;
+
+use super::{Storage, StoredObject};
+
+/// Everything except the characters `SigV4` leaves unescaped.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// As [`UNRESERVED`], but keeping `/` so keys can be used as paths.
+const PATH: &AsciiSet = &UNRESERVED.remove(b'/');
+
+/// Stores everything in an S3-compatible bucket, addressed path-style
+/// (`<endpoint>/<bucket>/<key>`) so it works with `MinIO`, R2 and friends as
+/// well as AWS itself.
+pub struct S3Storage {
+    endpoint: Uri,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    tls: TlsConnector,
+}
+
+fn storage_error(e: impl Debug + Send + 'static) -> PhsError {
+    PhsError::internal(e, "Error whilst talking to the storage backend")
+}
+
+impl S3Storage {
+    /// # Errors
+    ///
+    /// Fails if `endpoint` isn't an absolute `http` or `https` URL.
+    pub fn new(
+        endpoint: &str,
+        bucket: String,
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let endpoint = endpoint.trim_end_matches('/').parse::<Uri>()?;
+
+        if !matches!(endpoint.scheme_str(), Some("http" | "https")) || endpoint.host().is_none() {
+            return Err("S3 endpoint must be an absolute http or https URL".into());
+        }
+
+        let roots = webpki_roots::TLS_SERVER_ROOTS
+            .iter()
+            .cloned()
+            .collect::<RootCertStore>();
+        let tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        Ok(Self {
+            endpoint,
+            bucket,
+            region,
+            access_key_id,
+            secret_access_key,
+            tls: TlsConnector::from(Arc::new(tls)),
+        })
+    }
+
+    fn object_path(&self, key: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            self.bucket,
+            utf8_percent_encode(key, PATH)
+        )
+    }
+
+    /// Signs and sends a request, returning the status and body of the response.
+    /// `path` must already be percent-encoded, and `query` pairs must not be.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, String)],
+        body: Vec<u8>,
+    ) -> Result<(StatusCode, HeaderMap, Bytes), PhsError> {
+        let host = self.endpoint.host().unwrap_or_default();
+        let default_port = if self.endpoint.scheme_str() == Some("https") {
+            443
+        } else {
+            80
+        };
+        let port = self.endpoint.port_u16().unwrap_or(default_port);
+        let host_header = self
+            .endpoint
+            .authority()
+            .map_or_else(|| host.to_owned(), ToString::to_string);
+
+        let mut query = query
+            .iter()
+            .map(|(k, v)| {
+                (
+                    utf8_percent_encode(k, UNRESERVED).to_string(),
+                    utf8_percent_encode(v, UNRESERVED).to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let now = OffsetDateTime::now_utc();
+        let amz_date = now
+            .format(format_description!(
+                "[year][month][day]T[hour][minute][second]Z"
+            ))
+            .map_err(storage_error)?;
+        let date = &amz_date[..8];
+        let payload_hash = hex::encode(Sha256::digest(&body));
+
+        let mut headers = vec![
+            ("host", host_header.clone()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        headers.extend(extra_headers.iter().map(|(k, v)| (*k, v.clone())));
+        headers.sort_by(|a, b| a.0.cmp(b.0));
+
+        let canonical_headers = headers.iter().fold(String::new(), |mut acc, (k, v)| {
+            let _ = writeln!(acc, "{k}:{}", v.trim());
+            acc
+        });
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "{method}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}"
+        );
+
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = [date, &self.region, "s3", "aws4_request"].iter().fold(
+            format!("AWS4{}", self.secret_access_key).into_bytes(),
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        );
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        let uri = if query.is_empty() {
+            path.to_owned()
+        } else {
+            format!("{path}?{query}")
+        };
+
+        let mut req = Request::builder().method(method).uri(uri).header(
+            header::AUTHORIZATION,
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                self.access_key_id
+            ),
+        );
+        for (k, v) in headers {
+            req = req.header(k, v);
+        }
+        let req = req
+            .body(Full::new(Bytes::from(body)))
+            .map_err(storage_error)?;
+
+        let tcp = TcpStream::connect((host, port)).await?;
+
+        if self.endpoint.scheme_str() == Some("https") {
+            let server_name = ServerName::try_from(host.to_owned()).map_err(storage_error)?;
+            let tls = self.tls.connect(server_name, tcp).await?;
+            exchange(tls, req).await
+        } else {
+            exchange(tcp, req).await
+        }
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Sends one request over a fresh HTTP/1 connection.
+async fn exchange<S>(
+    stream: S,
+    req: Request<Full<Bytes>>,
+) -> Result<(StatusCode, HeaderMap, Bytes), PhsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(storage_error)?;
+
+    tokio::spawn(async move {
+        if let Err(error) = connection.await {
+            tracing::debug!(?error, "S3 connection closed with an error");
+        }
+    });
+
+    let res = sender.send_request(req).await.map_err(storage_error)?;
+    let status = res.status();
+    let headers = res.headers().clone();
+    let body = res
+        .into_body()
+        .collect()
+        .await
+        .map_err(storage_error)?
+        .to_bytes();
+
+    Ok((status, headers, body))
+}
+
+fn unexpected_status(status: StatusCode, body: &Bytes) -> PhsError {
+    storage_error(format!(
+        "S3 returned {status}: {}",
+        String::from_utf8_lossy(body)
+    ))
+}
+
+/// Pulls the text out of every `<tag>...</tag>` in an S3 XML response.
+fn xml_values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{tag}>"), format!("</{tag}>"));
+
+    xml.split(&open)
+        .skip(1)
+        .filter_map(|rest| rest.split_once(&close).map(|(value, _)| value))
+        .collect()
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PhsError> {
+        Ok(self.get_object(key).await?.map(|object| object.data))
+    }
+
+    async fn get_object(&self, key: &str) -> Result<Option<StoredObject>, PhsError> {
+        let (status, headers, body) = self
+            .send(Method::GET, &self.object_path(key), &[], &[], Vec::new())
+            .await?;
+
+        match status {
+            StatusCode::OK => Ok(Some(StoredObject {
+                data: body.to_vec(),
+                last_modified: headers
+                    .get(header::LAST_MODIFIED)
+                    .and_then(|time| httpdate::parse_http_date(time.to_str().ok()?).ok()),
+            })),
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(unexpected_status(status, &body)),
+        }
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), PhsError> {
+        let (status, _, body) = self
+            .send(Method::PUT, &self.object_path(key), &[], &[], data)
+            .await?;
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(unexpected_status(status, &body))
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), PhsError> {
+        let (status, _, body) = self
+            .send(Method::DELETE, &self.object_path(key), &[], &[], Vec::new())
+            .await?;
+
+        if status.is_success() || status == StatusCode::NOT_FOUND {
+            Ok(())
+        } else {
+            Err(unexpected_status(status, &body))
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<bool, PhsError> {
+        // S3 has no rename, so copy then delete the original
+        let source = format!("/{}/{}", self.bucket, utf8_percent_encode(from, PATH));
+
+        let (status, _, body) = self
+            .send(
+                Method::PUT,
+                &self.object_path(to),
+                &[],
+                &[("x-amz-copy-source", source)],
+                Vec::new(),
+            )
+            .await?;
+
+        match status {
+            // A copy can fail after the 200 has been sent, in which case the
+            // body holds an error instead of a result
+            StatusCode::OK if !String::from_utf8_lossy(&body).contains("<Error>") => {}
+            StatusCode::NOT_FOUND => return Ok(false),
+            _ => return Err(unexpected_status(status, &body)),
+        }
+
+        self.delete(from).await?;
+
+        Ok(true)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, PhsError> {
+        let path = format!(
+            "{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            self.bucket
+        );
+
+        let mut keys = Vec::new();
+        let mut continuation: Option<String> = None;
+
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(token) = continuation.as_deref() {
+                query.push(("continuation-token", token));
+            }
+
+            let (status, _, body) = self
+                .send(Method::GET, &path, &query, &[], Vec::new())
+                .await?;
+
+            if !status.is_success() {
+                return Err(unexpected_status(status, &body));
+            }
+
+            let xml = String::from_utf8_lossy(&body);
+            keys.extend(xml_values(&xml, "Key").into_iter().map(unescape_xml));
+
+            continuation = xml_values(&xml, "NextContinuationToken")
+                .first()
+                .map(|token| unescape_xml(token));
+
+            if continuation.is_none() {
+                break;
+            }
+        }
+
+        Ok(keys)
+    }
+}
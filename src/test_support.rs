@@ -0,0 +1,312 @@
+//! Helpers for handler-level integration tests, behind the `test_support`
+//! feature.
+//!
+//! Each test gets its own [`TestDb`], and a [`TestApp`] serving the full
+//! router over it as it's served without Redis, with sessions, caches and
+//! files all kept in memory.
+//!
+//! ```ignore
+//! let db = TestDb::new().await?;
+//! let app = TestApp::builder(db.pool().clone()).build()?;
+//! let cookie = app.session_for(user_id).await?;
+//!
+//! let res = app
+//!     .request(
+//!         Request::get("/v1/auth/whoami")
+//!             .header(header::COOKIE, cookie)
+//!             .body(Body::empty())?,
+//!     )
+//!     .await;
+//!
+//! db.close().await?;
+//! ```
+
+use std::{error::Error, sync::Arc, time::Duration};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{extract::Request, http::HeaderValue, response::Response, Router};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Connection, Executor, PgConnection, PgPool, Postgres, Transaction,
+};
+use tokio::sync::RwLock;
+use tower::ServiceExt;
+#[cfg(feature = "signed_cookies")]
+use tower_cookies::{cookie::CookieJar, Cookie, Key};
+
+use crate::{
+    auth,
+    error::PhsError,
+    events::Notifier,
+    self_check::load_templates,
+    sessions::{Expiry, SessionConfig, SessionManagerLayer, SessionStore},
+    share::ShareKey,
+    storage::SharedStorage,
+    Db, LiveConfig, ServerConfig, ServerSettings, TeraPool,
+};
+
+pub use crate::storage::MemoryStorage;
+
+/// A freshly migrated database of its own, so tests can run in parallel and
+/// leave nothing behind.
+///
+/// It's created alongside the one at `TEST_DATABASE_URL`, or `DATABASE_URL`
+/// if that isn't set, which the user needs to be allowed to do. Handlers
+/// commit through their own pool, so a whole test can't be wrapped in one
+/// transaction; [`TestDb::transaction`] is there for queries run directly.
+pub struct TestDb {
+    pool: PgPool,
+    name: String,
+    options: PgConnectOptions,
+}
+
+impl TestDb {
+    /// # Errors
+    ///
+    /// Fails if neither URL is set, or the database can't be created or
+    /// migrated.
+    pub async fn new() -> Result<Self, Box<dyn Error>> {
+        let url = dotenv::var("TEST_DATABASE_URL")
+            .or_else(|_| dotenv::var("DATABASE_URL"))
+            .map_err(|_| "TEST_DATABASE_URL or DATABASE_URL must be set to run database tests")?;
+        let options = url.parse::<PgConnectOptions>()?;
+
+        let mut suffix = [0_u8; 8];
+        OsRng.fill_bytes(&mut suffix);
+        let name = format!("phs_test_{}", hex::encode(suffix));
+
+        let mut conn = PgConnection::connect_with(&options).await?;
+        conn.execute(&*format!(r#"CREATE DATABASE "{name}""#))
+            .await?;
+        conn.close().await?;
+
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(options.clone().database(&name))
+            .await?;
+        sqlx::migrate!().run(&pool).await?;
+
+        Ok(Self {
+            pool,
+            name,
+            options,
+        })
+    }
+
+    #[must_use]
+    pub const fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Rolled back when dropped, unless committed.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no connection can be had.
+    pub async fn transaction(&self) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+        self.pool.begin().await
+    }
+
+    /// Drops the database. Dropping a [`TestDb`] can't do this itself, as it
+    /// has to wait on the server, so tests that skip this leave theirs behind.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the database can't be dropped.
+    pub async fn close(self) -> Result<(), sqlx::Error> {
+        self.pool.close().await;
+
+        let mut conn = PgConnection::connect_with(&self.options).await?;
+        conn.execute(&*format!(
+            r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE)"#,
+            self.name
+        ))
+        .await?;
+        conn.close().await
+    }
+}
+
+/// Adds a user with no password, named after `username`, with these
+/// permissions given directly, e.g. `&["manage_settings"]`, returning their
+/// ID.
+///
+/// # Errors
+///
+/// Fails if the username is taken or a permission doesn't exist.
+pub async fn user(pool: &PgPool, username: &str, permissions: &[&str]) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description, permissions)
+        VALUES ($1, '', $1, '', $2::text[]::permission[])
+        RETURNING id
+        ",
+    )
+    .bind(username)
+    .bind(permissions)
+    .fetch_one(pool)
+    .await
+}
+
+/// Adds a group with these permissions and nobody in it, returning its ID.
+///
+/// # Errors
+///
+/// Fails if the name is taken or a permission doesn't exist.
+pub async fn group(pool: &PgPool, name: &str, permissions: &[&str]) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar(
+        r"
+        INSERT INTO groups (group_name, permissions)
+        VALUES ($1, $2::text[]::permission[])
+        RETURNING id
+        ",
+    )
+    .bind(name)
+    .bind(permissions)
+    .fetch_one(pool)
+    .await
+}
+
+/// The whole app, as served, for sending requests to without a listener.
+pub struct TestApp {
+    router: Router,
+    db: Db,
+    sessions: SessionStore,
+    #[cfg(feature = "signed_cookies")]
+    key: Key,
+}
+
+impl TestApp {
+    #[must_use]
+    pub fn builder(pool: PgPool) -> TestAppBuilder {
+        TestAppBuilder {
+            pool,
+            config: ServerConfig::default(),
+            settings: ServerSettings::default(),
+            storage: None,
+        }
+    }
+
+    /// Runs `req` through the app. `/v1` and `/v2` are included in paths, as
+    /// a client would send them.
+    pub async fn request(&self, req: Request) -> Response {
+        self.router
+            .clone()
+            .oneshot(req)
+            .await
+            .unwrap_or_else(|never| match never {})
+    }
+
+    #[must_use]
+    pub const fn db(&self) -> &Db {
+        &self.db
+    }
+
+    /// A `Cookie` header logging requests in as the user with this ID, with
+    /// their permissions as they are now, as if they had just logged in.
+    ///
+    /// # Errors
+    ///
+    /// Fails if there's no such user.
+    pub async fn session_for(&self, user_id: i32) -> Result<HeaderValue, PhsError> {
+        let user = auth::load_auth_user(self.db.write(), user_id).await?;
+        let id = self
+            .sessions
+            .mint(user, Expiry::OnInactivity(time::Duration::hours(2)))
+            .await
+            .map_err(|e| PhsError::internal(e, "Error whilst minting a test session"))?;
+
+        let name = SessionConfig::default().name().to_owned();
+
+        #[cfg(feature = "signed_cookies")]
+        let cookie = {
+            let mut jar = CookieJar::new();
+            jar.signed_mut(&self.key).add(Cookie::new(name.clone(), id));
+            jar.get(&name)
+                .ok_or(PhsError::bug("Signed cookie missing from its jar"))?
+                .stripped()
+                .to_string()
+        };
+        #[cfg(not(feature = "signed_cookies"))]
+        let cookie = format!("{name}={id}");
+
+        HeaderValue::from_str(&cookie)
+            .map_err(|e| PhsError::internal(e, "Session cookie isn't a valid header"))
+    }
+}
+
+pub struct TestAppBuilder {
+    pool: PgPool,
+    config: ServerConfig,
+    settings: ServerSettings,
+    storage: Option<SharedStorage>,
+}
+
+impl TestAppBuilder {
+    /// Replaces the default config. TLS and the cookie and share key paths are
+    /// ignored.
+    #[must_use]
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    #[must_use]
+    pub fn settings(mut self, settings: ServerSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Replaces the empty [`MemoryStorage`], e.g. to start with files in it.
+    #[must_use]
+    pub fn storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the templates don't load.
+    pub fn build(self) -> Result<TestApp, Box<dyn Error>> {
+        let db = Db::new(self.pool, None, Duration::ZERO);
+
+        let tera = Arc::new(TeraPool::with_available_parallelism(load_templates()?));
+        let storage = self
+            .storage
+            .unwrap_or_else(|| Arc::new(MemoryStorage::new()));
+        let live = LiveConfig::new(&self.config);
+
+        let sessions = SessionStore::in_memory();
+        #[cfg(feature = "signed_cookies")]
+        let key = Key::generate();
+        #[cfg(feature = "signed_cookies")]
+        let session_manager_layer = SessionManagerLayer::new_signed(
+            sessions.clone(),
+            SessionConfig::default(),
+            key.clone(),
+        );
+        #[cfg(not(feature = "signed_cookies"))]
+        let session_manager_layer =
+            SessionManagerLayer::new(sessions.clone(), SessionConfig::default());
+
+        let router = crate::routes(
+            db.clone(),
+            None,
+            tera,
+            storage,
+            &self.config,
+            Arc::new(RwLock::new(self.settings)),
+            live,
+            Notifier::default(),
+            session_manager_layer,
+            ShareKey::generate(),
+        );
+
+        Ok(TestApp {
+            router,
+            db,
+            sessions,
+            #[cfg(feature = "signed_cookies")]
+            key,
+        })
+    }
+}
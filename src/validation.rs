@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, Request},
+    Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::{ErrorCode, PhsError};
+
+/// Problems with a request body, by the path to the field each is about, e.g.
+/// `title` or `pages[1].data[3]`. Problems with the body as a whole have an
+/// empty path.
+#[derive(Serialize, Debug, Default)]
+pub struct FieldErrors(BTreeMap<String, String>);
+
+impl FieldErrors {
+    /// Several problems with one field are joined into one message.
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        let message = message.into();
+        self.0
+            .entry(field.into())
+            .and_modify(|existing| {
+                existing.push_str("; ");
+                existing.push_str(&message);
+            })
+            .or_insert(message);
+    }
+
+    /// Adds `message` for `field` unless `ok`.
+    pub fn check(&mut self, ok: bool, field: &str, message: impl Into<String>) {
+        if !ok {
+            self.add(field, message);
+        }
+    }
+
+    pub fn not_blank(&mut self, field: &str, value: &str) {
+        self.check(!value.trim().is_empty(), field, "Can't be empty");
+    }
+
+    /// Mostly to turn away what the column would refuse anyway.
+    pub fn max_chars(&mut self, field: &str, value: &str, max: usize) {
+        self.check(
+            value.chars().count() <= max,
+            field,
+            format!("Can't be longer than {max} characters"),
+        );
+    }
+
+    pub fn min_chars(&mut self, field: &str, value: &str, min: usize) {
+        self.check(
+            value.chars().count() >= min,
+            field,
+            format!("Must be at least {min} characters"),
+        );
+    }
+
+    pub fn email(&mut self, field: &str, value: &str) {
+        self.check(is_email(value), field, "Isn't a valid email address");
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<F: Into<String>, M: Into<String>> FromIterator<(F, M)> for FieldErrors {
+    fn from_iter<I: IntoIterator<Item = (F, M)>>(iter: I) -> Self {
+        let mut errors = Self::default();
+        for (field, message) in iter {
+            errors.add(field, message);
+        }
+        errors
+    }
+}
+
+/// Only catches obvious mistakes; whether mail actually arrives is the real
+/// test.
+pub fn is_email(value: &str) -> bool {
+    value
+        .split_once('@')
+        .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+        && !value.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+pub trait Validate {
+    /// Every problem found, so they can all be fixed in one go.
+    fn validate(&self) -> FieldErrors;
+}
+
+/// A JSON body that has passed [`Validate`]. If it doesn't, the request is
+/// rejected with a 422 listing each problem by field.
+pub struct Validated<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Validated<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = PhsError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|rejection| PhsError::client(ErrorCode::BadRequest, rejection.body_text()))?;
+
+        let errors = value.validate();
+        if errors.is_empty() {
+            Ok(Self(value))
+        } else {
+            Err(PhsError::Invalid {
+                detail: "Some fields need fixing first".into(),
+                errors,
+            })
+        }
+    }
+}
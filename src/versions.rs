@@ -0,0 +1,76 @@
+use axum::{middleware, Router};
+
+use crate::{
+    admin_host, analytics, auth, comments, consent, events, export, import, media, metrics,
+    openapi, resources, retention, review, search, serve, settings,
+};
+
+/// Every version of the API still served, oldest first.
+pub const VERSIONS: &[&str] = &["/v1", "/v2"];
+
+/// Each version of the API, nested under its prefix.
+///
+/// Modules' routers use paths relative to the prefix. When an endpoint's
+/// request or response changes shape, its old handler moves from [`shared`]
+/// into [`v1`] and the new one goes in [`v2`]; everything else carries on being
+/// served by the same handler under both.
+pub fn router() -> Router {
+    let router = Router::new().nest("/v1", v1()).nest("/v2", v2());
+
+    VERSIONS.iter().fold(router, |router, prefix| {
+        router.merge(openapi::router(prefix))
+    })
+}
+
+/// Endpoints that are the same in every version.
+fn shared() -> Router {
+    let router = Router::new()
+        .merge(resources::router())
+        .merge(auth::router())
+        .merge(analytics::router())
+        .merge(media::router())
+        .merge(search::router())
+        .merge(settings::router())
+        .merge(events::router())
+        .merge(export::router())
+        .merge(import::router())
+        .merge(metrics::router())
+        .merge(review::router())
+        .merge(comments::router())
+        .merge(consent::router())
+        .merge(retention::router())
+        .merge(serve::router());
+
+    #[cfg(feature = "graphql")]
+    let router = router.merge(crate::graphql::router());
+
+    // Routes merged after the layer aren't wrapped by it
+    router
+        .route_layer(middleware::from_fn(admin_host::admin_only))
+        .merge(public_writes())
+}
+
+/// Writes visitors make from the site's pages, taken on every host rather than
+/// only the admin host.
+fn public_writes() -> Router {
+    Router::new()
+        .merge(resources::public_router())
+        .merge(analytics::public_router())
+        .merge(consent::public_router())
+}
+
+fn v1() -> Router {
+    shared()
+}
+
+fn v2() -> Router {
+    shared()
+}
+
+/// The path within the API, without the version prefix, if it's an API path.
+pub fn api_path(path: &str) -> Option<&str> {
+    VERSIONS.iter().find_map(|prefix| {
+        path.strip_prefix(prefix)
+            .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
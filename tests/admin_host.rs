@@ -0,0 +1,156 @@
+//! With an admin host set, other hosts turn away the API's staff writes but
+//! still take visitors', and the admin host takes everything.
+//!
+//! ```sh
+//! cargo test --test admin_host --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::{
+    test_support::{self, TestApp, TestDb},
+    ServerConfig,
+};
+use serde_json::json;
+
+const ADMIN_HOST: &str = "admin.example.sch.uk";
+const PUBLIC_HOST: &str = "www.example.sch.uk";
+
+#[tokio::test]
+async fn admin_host() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let admin = test_support::user(pool, "admin", &["manage_settings"]).await?;
+    let student = test_support::user(pool, "student", &[]).await?;
+
+    let app = TestApp::builder(pool.clone())
+        .config(ServerConfig {
+            admin_host: Some(ADMIN_HOST.into()),
+            ..ServerConfig::default()
+        })
+        .build()?;
+    let admin = app.session_for(admin).await?;
+    let student = app.session_for(student).await?;
+
+    let put_robots = |host: &'static str| {
+        Request::put("/v1/robots")
+            .header(header::HOST, host)
+            .header(header::COOKIE, &admin)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "content": "User-agent: *\n" }).to_string(),
+            ))
+    };
+
+    let res = app.request(put_robots(PUBLIC_HOST)?).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // The port doesn't matter
+    let res = app.request(put_robots("admin.example.sch.uk:5000")?).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = app
+        .request(
+            Request::get("/v1/robots")
+                .header(header::HOST, PUBLIC_HOST)
+                .header(header::COOKIE, &admin)
+                .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let poll: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO polls (question, options, opens_at)
+        VALUES ('Lunch?', '{Pasta,Curry}', now() - interval '1 hour')
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+    let event: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO events (title, starts_at, ends_at, registration)
+        VALUES ('Open evening', now() + interval '1 day', now() + interval '1 day', true)
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+    let form: i32 = sqlx::query_scalar("INSERT INTO forms (title) VALUES ('Trip') RETURNING id")
+        .fetch_one(pool)
+        .await?;
+
+    // Everything visitors send from the site's pages
+    let visitor_writes = [
+        (
+            "PUT",
+            "/v1/consent".to_owned(),
+            json!({ "analytics": true }),
+        ),
+        (
+            "POST",
+            "/v1/analytics/hit".to_owned(),
+            json!({ "path": "/" }),
+        ),
+        (
+            "POST",
+            format!("/v1/polls/{poll}/vote"),
+            json!({ "choice": 0 }),
+        ),
+        (
+            "POST",
+            format!("/v1/events/{event}/register"),
+            json!({ "name": "Parent", "email": "parent@example.com" }),
+        ),
+        ("POST", format!("/v1/forms/{form}/submissions"), json!({})),
+        (
+            "POST",
+            "/v1/admissions/enquiries".to_owned(),
+            json!({
+                "child_name": "Child",
+                "year_group": "Year 7",
+                "parent_name": "Parent",
+                "email": "parent@example.com",
+                "consent": true,
+            }),
+        ),
+    ];
+    for (method, path, body) in visitor_writes {
+        let res = app
+            .request(
+                Request::builder()
+                    .method(method)
+                    .uri(&path)
+                    .header(header::HOST, PUBLIC_HOST)
+                    .header(header::COOKIE, &student)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))?,
+            )
+            .await;
+        assert!(
+            res.status().is_success(),
+            "{method} {path} gave {}",
+            res.status()
+        );
+    }
+
+    let res = app
+        .request(
+            Request::get("/robots.txt")
+                .header(header::HOST, PUBLIC_HOST)
+                .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    db.close().await?;
+
+    Ok(())
+}
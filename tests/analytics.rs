@@ -0,0 +1,85 @@
+//! Views are only counted for visitors who've agreed to analytics, and the
+//! same visitor only counts once a day.
+//!
+//! ```sh
+//! cargo test --test analytics --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::{
+    test_support::{TestApp, TestDb},
+    FeatureToggles, ServerSettings,
+};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn analytics() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let admin: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description, permissions)
+        VALUES ('admin', '', 'admin', '', '{manage_settings}')
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let app = TestApp::builder(pool.clone())
+        .settings(ServerSettings {
+            features: FeatureToggles {
+                analytics: true,
+                ..FeatureToggles::default()
+            },
+            ..ServerSettings::default()
+        })
+        .build()?;
+    let admin = app.session_for(admin).await?;
+
+    for consent in [
+        None,
+        Some("phs_consent=none"),
+        Some("phs_consent=analytics"),
+        Some("phs_consent=analytics"),
+    ] {
+        let mut req = Request::post("/v1/analytics/hit")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::USER_AGENT, "test");
+        if let Some(consent) = consent {
+            req = req.header(header::COOKIE, consent);
+        }
+
+        let res = app
+            .request(req.body(Body::from(
+                json!({ "path": "/about/?tab=staff" }).to_string(),
+            ))?)
+            .await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+
+    let res = app
+        .request(
+            Request::get("/v1/analytics")
+                .header(header::COOKIE, &admin)
+                .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let summary: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+    assert_eq!(
+        summary["paths"],
+        json!([{ "path": "/about", "views": 2, "visitors": 1 }])
+    );
+
+    db.close().await?;
+
+    Ok(())
+}
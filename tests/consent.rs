@@ -0,0 +1,62 @@
+//! Visitors' cookie choices round-trip through their own cookie, without
+//! logging in.
+//!
+//! ```sh
+//! cargo test --test consent --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::test_support::{TestApp, TestDb};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn consent() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let app = TestApp::builder(db.pool().clone()).build()?;
+
+    let res = app
+        .request(Request::get("/v1/consent").body(Body::empty())?)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let undecided: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+    assert_eq!(undecided, Value::Null);
+
+    let res = app
+        .request(
+            Request::put("/v1/consent")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "analytics": true }).to_string()))?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let cookie = res
+        .headers()
+        .get(header::SET_COOKIE)
+        .ok_or("No cookie set")?
+        .to_str()?
+        .split(';')
+        .next()
+        .ok_or("Empty cookie")?
+        .to_owned();
+    assert_eq!(cookie, "phs_consent=analytics");
+
+    let res = app
+        .request(
+            Request::get("/v1/consent")
+                .header(header::COOKIE, &cookie)
+                .body(Body::empty())?,
+        )
+        .await;
+    let decided: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+    assert_eq!(decided, json!({ "analytics": true }));
+
+    db.close().await?;
+
+    Ok(())
+}
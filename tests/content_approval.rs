@@ -0,0 +1,125 @@
+//! With approval on, a new post stays hidden until someone other than its
+//! author approves it, and each step is kept in its history.
+//!
+//! ```sh
+//! cargo test --test content_approval --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, HeaderValue, Method, StatusCode},
+};
+use phs_backend::{
+    test_support::{user, TestApp, TestDb},
+    FeatureToggles, ServerSettings,
+};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn content_approval() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let author = user(
+        pool,
+        "author",
+        &["create_posts", "edit_posts", "approve_content"],
+    )
+    .await?;
+    let approver = user(pool, "approver", &["approve_content"]).await?;
+
+    let app = TestApp::builder(pool.clone())
+        .settings(ServerSettings {
+            features: FeatureToggles {
+                approval: true,
+                ..FeatureToggles::default()
+            },
+            ..ServerSettings::default()
+        })
+        .build()?;
+    let author = app.session_for(author).await?;
+    let approver = app.session_for(approver).await?;
+
+    let send = |method: Method, uri: String, cookie: Option<&HeaderValue>, body: Option<Value>| {
+        let app = &app;
+        let cookie = cookie.cloned();
+        async move {
+            let mut req = Request::builder().method(method).uri(uri);
+            if let Some(cookie) = cookie {
+                req = req.header(header::COOKIE, cookie);
+            }
+            let req = match body {
+                Some(body) => req
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))?,
+                None => req.body(Body::empty())?,
+            };
+
+            let res = app.request(req).await;
+            let status = res.status();
+            let body = to_bytes(res.into_body(), usize::MAX).await?;
+            Ok::<(StatusCode, Value), Box<dyn Error>>((
+                status,
+                serde_json::from_slice(&body).unwrap_or(Value::Null),
+            ))
+        }
+    };
+
+    let (status, post) = send(
+        Method::POST,
+        "/v1/posts".into(),
+        Some(&author),
+        Some(json!({ "title": "News", "content": "Soon", "pinned": false, "share": false })),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    let id = post["id"].as_i64().ok_or("No post ID")?;
+
+    let (status, _) = send(Method::GET, format!("/v1/posts/{id}"), None, None).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Being able to approve doesn't let anyone approve their own post
+    let (status, _) = send(
+        Method::POST,
+        format!("/v1/reviews/post/{id}/approve"),
+        Some(&author),
+        Some(json!({})),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(
+        Method::POST,
+        format!("/v1/reviews/post/{id}/approve"),
+        Some(&approver),
+        Some(json!({ "comment": "Looks good" })),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(Method::GET, format!("/v1/posts/{id}"), None, None).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, history) = send(
+        Method::GET,
+        format!("/v1/reviews/post/{id}"),
+        Some(&approver),
+        None,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    let actions = history
+        .as_array()
+        .ok_or("No history")?
+        .iter()
+        .map(|entry| entry["action"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(actions, [json!("submitted"), json!("approved")]);
+
+    db.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! `EditPosts` given over one department covers that department's posts and
+//! nothing else, not even posts in no department, and `/auth/can` says so.
+//!
+//! ```sh
+//! cargo test --test department_permissions --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::test_support::{user, TestApp, TestDb};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn department_permissions() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let (pe, maths): (i32, i32) = sqlx::query_as(
+        r"
+        WITH inserted AS (
+          INSERT INTO departments (department) VALUES ('PE'), ('Maths') RETURNING id
+        )
+        SELECT min(id), max(id) FROM inserted
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+    let head: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description)
+        VALUES ('head_of_pe', '', 'Head of PE', '')
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO department_permissions (user_id, department_id, permission) VALUES ($1, $2, 'edit_posts')",
+    )
+    .bind(head)
+    .bind(pe)
+    .execute(pool)
+    .await?;
+
+    let mut posts = Vec::new();
+    for department in [Some(pe), Some(maths), None] {
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO posts (title, content, pinned, department) VALUES ('News', '', false, $1) RETURNING id",
+        )
+        .bind(department)
+        .fetch_one(pool)
+        .await?;
+        posts.push(id);
+    }
+
+    let admin = user(pool, "admin", &["manage_permissions"]).await?;
+
+    let app = TestApp::builder(pool.clone()).build()?;
+    let cookie = app.session_for(head).await?;
+
+    let mut statuses = Vec::new();
+    for &post in &posts {
+        let res = app
+            .request(
+                Request::post(format!("/v1/posts/{post}/share"))
+                    .header(header::COOKIE, cookie.clone())
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(json!({}).to_string()))?,
+            )
+            .await;
+        statuses.push(res.status());
+    }
+    assert_eq!(
+        statuses,
+        [StatusCode::OK, StatusCode::FORBIDDEN, StatusCode::FORBIDDEN]
+    );
+
+    // Still missing it across the site
+    let res = app
+        .request(
+            Request::get("/v1/reviews")
+                .header(header::COOKIE, cookie)
+                .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let admin = app.session_for(admin).await?;
+    let can = |resource: String| {
+        let admin = admin.clone();
+        let app = &app;
+        async move {
+            let res = app
+                .request(
+                    Request::get(format!(
+                        "/v1/auth/can?user={head}&permission=EditPosts{resource}"
+                    ))
+                    .header(header::COOKIE, admin)
+                    .body(Body::empty())?,
+                )
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let body = to_bytes(res.into_body(), usize::MAX).await?;
+            Ok::<Value, Box<dyn Error>>(serde_json::from_slice(&body)?)
+        }
+    };
+
+    let body = can(String::new()).await?;
+    assert_eq!(body["allowed"], true);
+    assert_eq!(body["departments"], json!([pe]));
+
+    let mut allowed = Vec::new();
+    for post in &posts {
+        allowed.push(can(format!("&resource=post:{post}")).await?["allowed"].clone());
+    }
+    assert_eq!(allowed, [true, false, false]);
+
+    db.close().await?;
+
+    Ok(())
+}
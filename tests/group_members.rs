@@ -0,0 +1,154 @@
+//! Group memberships can be changed in bulk, from the group's side or the
+//! user's, and a bulk change naming anyone who doesn't exist changes nothing.
+//! Either side can be listed.
+//!
+//! ```sh
+//! cargo test --test group_members --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, Method, StatusCode},
+};
+use phs_backend::test_support::{group, user, TestApp, TestDb};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+#[tokio::test]
+async fn group_members() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let admin = user(pool, "admin", &["manage_permissions"]).await?;
+    let ann = user(pool, "ann", &[]).await?;
+    let bob = user(pool, "bob", &[]).await?;
+    let cat = user(pool, "cat", &[]).await?;
+
+    let staff = group(pool, "staff", &["create_posts"]).await?;
+    let editors = group(pool, "editors", &["edit_posts"]).await?;
+
+    let app = TestApp::builder(pool.clone()).build()?;
+    let cookie = app.session_for(admin).await?;
+
+    let send = |method: Method, uri: String, body: Value| {
+        let cookie = cookie.clone();
+        let app = &app;
+        async move {
+            let res = app
+                .request(
+                    Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .header(header::COOKIE, cookie)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(body.to_string()))?,
+                )
+                .await;
+            Ok::<StatusCode, Box<dyn Error>>(res.status())
+        }
+    };
+    let get = |uri: String| {
+        let cookie = cookie.clone();
+        let app = &app;
+        async move {
+            let res = app
+                .request(
+                    Request::get(uri)
+                        .header(header::COOKIE, cookie)
+                        .body(Body::empty())?,
+                )
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let body = to_bytes(res.into_body(), usize::MAX).await?;
+            Ok::<Value, Box<dyn Error>>(serde_json::from_slice(&body)?)
+        }
+    };
+    let members = format!("/v1/auth/groups/{staff}/members");
+
+    // Bob being added twice is fine
+    let status = send(
+        Method::POST,
+        members.clone(),
+        json!({ "users": [ann, bob] }),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    let status = send(
+        Method::POST,
+        members.clone(),
+        json!({ "users": [bob, cat] }),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(groups_of(pool, ann).await?, [staff]);
+    assert_eq!(groups_of(pool, bob).await?, [staff]);
+    assert_eq!(groups_of(pool, cat).await?, [staff]);
+
+    let listed = get(format!(
+        "/v1/auth/group/{staff}/members?sort_by=username.desc"
+    ))
+    .await?;
+    assert_eq!(
+        listed["data"],
+        json!([
+            { "id": cat, "username": "cat", "name": "cat" },
+            { "id": bob, "username": "bob", "name": "bob" },
+            { "id": ann, "username": "ann", "name": "ann" },
+        ])
+    );
+
+    let status = send(
+        Method::POST,
+        format!("/v1/auth/groups/{editors}/members"),
+        json!({ "users": [ann, -1] }),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(groups_of(pool, ann).await?, [staff]);
+
+    let status = send(Method::DELETE, members, json!({ "users": [ann, cat] })).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(groups_of(pool, ann).await?.is_empty());
+    assert_eq!(groups_of(pool, bob).await?, [staff]);
+    assert!(groups_of(pool, cat).await?.is_empty());
+
+    let groups = format!("/v1/auth/users/{bob}/groups");
+
+    let status = send(Method::PUT, groups.clone(), json!({ "groups": [editors] })).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(groups_of(pool, bob).await?, [editors]);
+
+    let listed = get(format!("/v1/users/{bob}/groups")).await?;
+    assert_eq!(
+        listed,
+        json!([{ "id": editors, "group_name": "editors", "permissions": ["EditPosts"] }])
+    );
+
+    let status = send(
+        Method::PUT,
+        groups.clone(),
+        json!({ "groups": [staff, -1] }),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(groups_of(pool, bob).await?, [editors]);
+
+    let status = send(Method::PUT, groups, json!({ "groups": [] })).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(groups_of(pool, bob).await?.is_empty());
+
+    db.close().await?;
+
+    Ok(())
+}
+
+async fn groups_of(pool: &PgPool, user: i32) -> Result<Vec<i32>, sqlx::Error> {
+    sqlx::query_scalar("SELECT group_id FROM users_groups WHERE user_id = $1 ORDER BY group_id")
+        .bind(user)
+        .fetch_all(pool)
+        .await
+}
@@ -0,0 +1,116 @@
+//! Uploaded photos are published without their EXIF or comments, but still
+//! the right way up.
+//!
+//! ```sh
+//! cargo test --test image_metadata --features test_support
+//! ```
+
+use std::{error::Error, io::Cursor};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use image::{codecs::jpeg::JpegEncoder, ImageDecoder, ImageReader, RgbImage};
+use phs_backend::test_support::{TestApp, TestDb};
+use serde_json::Value;
+
+const BOUNDARY: &str = "phs-test-boundary";
+
+/// Somewhere a photo shouldn't give away.
+const LOCATION: &[u8] = b"51.5007N 0.1246W";
+
+/// A small JPEG with EXIF saying it's on its side and where it was taken, and
+/// a comment.
+fn photo() -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut jpeg = Vec::new();
+    JpegEncoder::new(&mut jpeg).encode_image(&RgbImage::new(8, 4))?;
+
+    // Big-endian TIFF whose only entry is orientation 6, then the location
+    let mut tiff = b"MM\0\x2A\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0".to_vec();
+    tiff.extend_from_slice(LOCATION);
+
+    let mut exif = vec![0xFF, 0xE1];
+    exif.extend_from_slice(&u16::try_from(tiff.len() + 8)?.to_be_bytes());
+    exif.extend_from_slice(b"Exif\0\0");
+    exif.extend_from_slice(&tiff);
+
+    let mut comment = vec![0xFF, 0xFE];
+    comment.extend_from_slice(&u16::try_from(LOCATION.len() + 2)?.to_be_bytes());
+    comment.extend_from_slice(LOCATION);
+
+    jpeg.splice(2..2, exif.into_iter().chain(comment));
+
+    Ok(jpeg)
+}
+
+#[tokio::test]
+async fn image_metadata() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let librarian: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description, permissions)
+        VALUES ('librarian', '', 'librarian', '', '{manage_media}')
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let app = TestApp::builder(pool.clone()).build()?;
+    let cookie = app.session_for(librarian).await?;
+
+    let mut body = format!(
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"trip.jpg\"\r\nContent-Type: image/jpeg\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(&photo()?);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+    let res = app
+        .request(
+            Request::post("/v1/media")
+                .header(header::COOKIE, &cookie)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={BOUNDARY}"),
+                )
+                .body(Body::from(body))?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let media: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+
+    let res = app
+        .request(
+            Request::get(format!(
+                "/media/{}/{}",
+                media["id"],
+                media["filename"].as_str().ok_or("No filename")?
+            ))
+            .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let published = to_bytes(res.into_body(), usize::MAX).await?;
+
+    assert!(!published
+        .windows(LOCATION.len())
+        .any(|window| window == LOCATION));
+    assert_eq!(i64::try_from(published.len())?, media["size"]);
+
+    let mut decoder = ImageReader::new(Cursor::new(published))
+        .with_guessed_format()?
+        .into_decoder()?;
+    assert_eq!(
+        decoder.orientation()?,
+        image::metadata::Orientation::Rotate90
+    );
+
+    db.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! A page's description and preview image end up in its `<meta>` tags when
+//! it's deployed, escaped, with the image's URL made absolute.
+//!
+//! ```sh
+//! cargo test --test link_previews --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::test_support::{TestApp, TestDb};
+use serde_json::json;
+
+#[tokio::test]
+async fn link_previews() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let editor: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description, permissions)
+        VALUES ('editor', '', 'editor', '', '{manage_pages}')
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let app = TestApp::builder(pool.clone()).build()?;
+    let editor = app.session_for(editor).await?;
+
+    let json_request = |method: &str, uri: &str, body: serde_json::Value| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, &editor)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+    };
+
+    let res = app
+        .request(json_request(
+            "POST",
+            "/v1/pages",
+            json!({
+                "unsafe_name": "fees",
+                "data": [{ "type": "header", "size": "h1", "contents": "Fees" }],
+            }),
+        )?)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let id: i32 = sqlx::query_scalar("SELECT id FROM pages WHERE name = 'fees'")
+        .fetch_one(pool)
+        .await?;
+
+    let res = app
+        .request(json_request(
+            "PUT",
+            &format!("/v1/pages/{id}/meta"),
+            json!({
+                "meta_description": "Trips & \"extras\"",
+                "og_image": "/media/1/fees.jpg",
+            }),
+        )?)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = app
+        .request(json_request("POST", "/v1/deploy", json!([id]))?)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = app
+        .request(Request::get("/fees").body(Body::empty())?)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let html = String::from_utf8(to_bytes(res.into_body(), usize::MAX).await?.to_vec())?;
+    assert!(html.contains(r#"<meta name="description" content="Trips &amp; &quot;extras&quot;">"#));
+    assert!(html.contains(r#"<meta property="og:title" content="fees">"#));
+    assert!(
+        html.contains(r#"<meta property="og:image" content="https://localhost/media/1/fees.jpg">"#)
+    );
+
+    db.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! `robots.txt` starts out as the default, can be replaced, and goes back to
+//! the default once the replacement is deleted.
+//!
+//! ```sh
+//! cargo test --test robots --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::test_support::{TestApp, TestDb};
+use serde_json::json;
+
+#[tokio::test]
+async fn robots() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let admin: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description, permissions)
+        VALUES ('admin', '', 'admin', '', '{manage_settings}')
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let app = TestApp::builder(pool.clone()).build()?;
+    let admin = app.session_for(admin).await?;
+
+    let robots = || async {
+        let res = app
+            .request(Request::get("/robots.txt").body(Body::empty())?)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = to_bytes(res.into_body(), usize::MAX).await?;
+        Ok::<_, Box<dyn Error>>(String::from_utf8(body.to_vec())?)
+    };
+
+    let default = robots().await?;
+    assert!(default.contains("Disallow: /staff/"));
+    assert!(default.contains("/sitemap.xml"));
+
+    let res = app
+        .request(
+            Request::put("/v1/robots")
+                .header(header::COOKIE, &admin)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({ "content": "User-agent: *\nDisallow: /\n" }).to_string(),
+                ))?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(robots().await?, "User-agent: *\nDisallow: /\n");
+
+    let res = app
+        .request(
+            Request::delete("/v1/robots")
+                .header(header::COOKIE, &admin)
+                .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(robots().await?, default);
+
+    db.close().await?;
+
+    Ok(())
+}
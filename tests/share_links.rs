@@ -0,0 +1,83 @@
+//! A share link shows its post to anyone, but only that post, and not once
+//! it's been changed.
+//!
+//! ```sh
+//! cargo test --test share_links --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::test_support::{TestApp, TestDb};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn share_links() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let editor: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description, permissions)
+        VALUES ('editor', '', 'editor', '', '{edit_posts}')
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+    let (post, other): (i32, i32) = sqlx::query_as(
+        r"
+        WITH inserted AS (
+          INSERT INTO posts (title, content, pinned)
+          VALUES ('Draft', 'Not yet', false), ('Other', 'Also not yet', false)
+          RETURNING id
+        )
+        SELECT min(id), max(id) FROM inserted
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let app = TestApp::builder(pool.clone()).build()?;
+    let cookie = app.session_for(editor).await?;
+
+    let res = app
+        .request(
+            Request::post(format!("/v1/posts/{post}/share"))
+                .header(header::COOKIE, cookie)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "days": 1 }).to_string()))?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let link: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+    let url = link["url"].as_str().ok_or("No URL")?;
+    let share = &url[url.find("/v1/").ok_or("Not an API URL")?..];
+
+    let status = |uri: String| {
+        let app = &app;
+        async move {
+            let res = app.request(Request::get(uri).body(Body::empty())?).await;
+            Ok::<StatusCode, Box<dyn Error>>(res.status())
+        }
+    };
+
+    assert_eq!(status(share.to_owned()).await?, StatusCode::OK);
+    assert_eq!(
+        status(share.replace(&format!("/posts/{post}/"), &format!("/posts/{other}/"))).await?,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        status(share.replace("expires=", "expires=9")).await?,
+        StatusCode::FORBIDDEN
+    );
+
+    db.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! The shipped templates all parse and are in use, and reloading them works.
+//! Renders that are cancelled or panic don't use up the pool.
+//!
+//! ```sh
+//! cargo test --test templates --features test_support
+//! ```
+
+use std::{collections::HashMap, error::Error, num::NonZeroUsize, sync::Arc, time::Duration};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::{
+    test_support::{TestApp, TestDb},
+    TeraPool,
+};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn templates() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let editor: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description, permissions)
+        VALUES ('editor', '', 'editor', '', '{manage_pages}')
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let app = TestApp::builder(pool.clone()).build()?;
+    let editor = app.session_for(editor).await?;
+
+    let res = app
+        .request(
+            Request::post("/v1/templates/reload")
+                .header(header::COOKIE, &editor)
+                .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = app
+        .request(
+            Request::get("/v1/templates")
+                .header(header::COOKIE, &editor)
+                .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let templates: Vec<Value> =
+        serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+    assert!(templates.contains(&json!({
+        "name": "base.html",
+        "error": null,
+        "loaded": true,
+    })));
+    assert!(templates.iter().all(|template| template["error"].is_null()));
+
+    db.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn abandoned_renders() -> Result<(), Box<dyn Error>> {
+    let mut tera = tera::Tera::default();
+    tera.register_function(
+        "boom",
+        |_: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+            panic!("Rendering went wrong")
+        },
+    );
+    tera.register_function(
+        "slow",
+        |_: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+            std::thread::sleep(Duration::from_millis(100));
+            Ok(tera::Value::Null)
+        },
+    );
+    let pool = Arc::new(TeraPool::new(tera, NonZeroUsize::MIN));
+    let render = |template: &str| pool.render_str(template.into(), tera::Context::new());
+
+    // Dropped while the render is under way
+    let cancelled = tokio::time::timeout(Duration::ZERO, render("{{ slow() }}")).await;
+    assert!(cancelled.is_err());
+
+    assert!(render("{{ boom() }}").await.is_err());
+
+    // More renders than there are instances, each of which would fail or hang
+    // if the pool had lost one
+    for _ in 0..3 {
+        let rendered = tokio::time::timeout(Duration::from_secs(5), render("{{ 1 + 1 }}")).await?;
+        assert_eq!(rendered?, "2");
+    }
+
+    Ok(())
+}
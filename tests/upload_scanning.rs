@@ -0,0 +1,92 @@
+//! With a scanner set up, flagged uploads are refused and quarantined, and
+//! clean ones are stored as usual.
+//!
+//! ```sh
+//! cargo test --test upload_scanning --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+};
+use phs_backend::{
+    test_support::{TestApp, TestDb},
+    ScannerConfig, ServerConfig,
+};
+use serde_json::Value;
+
+const BOUNDARY: &str = "phs-test-boundary";
+
+fn upload(cookie: &HeaderValue, filename: &str, contents: &str) -> Result<Request, Box<dyn Error>> {
+    let body = format!(
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: text/plain\r\n\r\n{contents}\r\n--{BOUNDARY}--\r\n"
+    );
+
+    Ok(Request::post("/v1/media")
+        .header(header::COOKIE, cookie)
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(Body::from(body))?)
+}
+
+#[tokio::test]
+async fn upload_scanning() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let librarian: i32 = sqlx::query_scalar(
+        r"
+        INSERT INTO users (username, hash, name, description, permissions)
+        VALUES ('librarian', '', 'librarian', '', '{manage_media}')
+        RETURNING id
+        ",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    // Flags anything mentioning EICAR, as `clamdscan -` would
+    let app = TestApp::builder(pool.clone())
+        .config(ServerConfig {
+            scanner: Some(ScannerConfig::Command {
+                program: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    "if grep -q EICAR; then echo 'stdin: Test-Signature FOUND'; exit 1; fi".into(),
+                ],
+            }),
+            ..ServerConfig::default()
+        })
+        .build()?;
+    let cookie = app.session_for(librarian).await?;
+
+    let res = app.request(upload(&cookie, "bad.txt", "EICAR")?).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let res = app.request(upload(&cookie, "good.txt", "Hello")?).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let media: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+    assert_eq!(media["scanned"], true);
+
+    let res = app
+        .request(
+            Request::get("/v1/quarantine")
+                .header(header::COOKIE, &cookie)
+                .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let quarantine: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+    let quarantine = quarantine.as_array().ok_or("Not a list")?;
+    assert_eq!(quarantine.len(), 1);
+    assert_eq!(quarantine[0]["filename"], "bad.txt");
+    assert_eq!(quarantine[0]["threat"], "Test-Signature");
+
+    db.close().await?;
+
+    Ok(())
+}
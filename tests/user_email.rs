@@ -0,0 +1,55 @@
+//! Anyone can look a user up, but their email address is only shown to those
+//! who manage users.
+//!
+//! ```sh
+//! cargo test --test user_email --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::test_support::{user, TestApp, TestDb};
+use serde_json::Value;
+
+#[tokio::test]
+async fn user_email() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let admin = user(pool, "admin", &["manage_users"]).await?;
+    let teacher = user(pool, "teacher", &[]).await?;
+    sqlx::query("UPDATE users SET email = 'teacher@example.sch.uk' WHERE id = $1")
+        .bind(teacher)
+        .execute(pool)
+        .await?;
+
+    let app = TestApp::builder(pool.clone()).build()?;
+    let cookie = app.session_for(admin).await?;
+
+    let res = app
+        .request(Request::get(format!("/v1/users/{teacher}")).body(Body::empty())?)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+    assert_eq!(body["username"], "teacher");
+    assert!(body.get("email").is_none());
+
+    let res = app
+        .request(
+            Request::get("/v1/users?username=teacher")
+                .header(header::COOKIE, cookie)
+                .body(Body::empty())?,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+    assert_eq!(body["data"][0]["email"], "teacher@example.sch.uk");
+
+    db.close().await?;
+
+    Ok(())
+}
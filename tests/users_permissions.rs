@@ -0,0 +1,98 @@
+//! `/auth/users/permissions` gives each user their own permissions together
+//! with their groups', and no one else's.
+//!
+//! ```sh
+//! cargo test --test users_permissions --features test_support
+//! ```
+
+use std::error::Error;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, StatusCode},
+};
+use phs_backend::test_support::{group, user, TestApp, TestDb};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn users_permissions() -> Result<(), Box<dyn Error>> {
+    let db = TestDb::new().await?;
+    let pool = db.pool();
+
+    let admin = user(pool, "admin", &["manage_permissions"]).await?;
+    let member = user(pool, "member", &["create_posts"]).await?;
+    let loner = user(pool, "loner", &[]).await?;
+
+    let editors = group(pool, "editors", &["edit_posts", "manage_pages"]).await?;
+    let media = group(pool, "media", &["edit_posts", "manage_media"]).await?;
+    // Nobody's in it, so none of these should turn up
+    group(pool, "unused", &["manage_users", "manage_settings"]).await?;
+
+    for group in [editors, media] {
+        sqlx::query("INSERT INTO users_groups (user_id, group_id) VALUES ($1, $2)")
+            .bind(member)
+            .bind(group)
+            .execute(pool)
+            .await?;
+    }
+
+    let app = TestApp::builder(pool.clone()).build()?;
+    let cookie = app.session_for(admin).await?;
+
+    let get = |uri: String| {
+        let cookie = cookie.clone();
+        let app = &app;
+        async move {
+            let res = app
+                .request(
+                    Request::get(uri)
+                        .header(header::COOKIE, cookie)
+                        .body(Body::empty())?,
+                )
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let body = to_bytes(res.into_body(), usize::MAX).await?;
+            Ok::<Value, Box<dyn Error>>(serde_json::from_slice(&body)?)
+        }
+    };
+
+    let listed = get("/v1/auth/users/permissions".into()).await?;
+    assert_eq!(
+        listed["data"],
+        json!([
+            {
+                "id": admin,
+                "username": "admin",
+                "name": "admin",
+                "permissions": ["ManagePermissions"],
+                "group_ids": [],
+            },
+            {
+                "id": member,
+                "username": "member",
+                "name": "member",
+                "permissions": ["CreatePosts", "EditPosts", "ManagePages", "ManageMedia"],
+                "group_ids": [editors, media],
+            },
+            {
+                "id": loner,
+                "username": "loner",
+                "name": "loner",
+                "permissions": [],
+                "group_ids": [],
+            },
+        ])
+    );
+
+    // The same, one user at a time
+    for user in listed["data"].as_array().ok_or("`data` isn't an array")? {
+        let one = get(format!("/v1/auth/users/permissions/{}", user["id"])).await?;
+        assert_eq!(&one, user);
+    }
+
+    db.close().await?;
+
+    Ok(())
+}